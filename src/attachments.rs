@@ -0,0 +1,375 @@
+//! Encrypted, content-addressed attachment chunks.
+//!
+//! Attachment content is split into fixed-size chunks stored as individual files in a
+//! `<store>.blobs/` directory next to the keystore. Each chunk file is named after an
+//! HMAC of its plaintext, so identical content attached to several entries is stored
+//! once without the file name revealing a plain content hash, and each chunk is
+//! encrypted on its own so content can be read back chunk by chunk.
+//!
+//! Chunk file layout:
+//! ```text
+//! MAGIC "KNCH" (4) | ALGORITHM (1) | NONCE | CIPHERTEXT
+//! ```
+
+use anyhow::{Context, Result, anyhow, bail};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+use crate::crypto::KEY_LEN;
+use crate::crypto::algorithm::Algorithm;
+use crate::storage::Storage;
+use crate::store::Attachment;
+
+/// Size of a plaintext chunk (1 MiB).
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+const CHUNK_MAGIC: &[u8; 4] = b"KNCH";
+const ID_LABEL: &[u8] = b"keynest attachment chunk id";
+const ENC_LABEL: &[u8] = b"keynest attachment chunk encryption";
+
+/// Directory of encrypted attachment chunks belonging to a keystore.
+pub(crate) struct BlobStore {
+    dir: PathBuf,
+    algorithm: Algorithm,
+    id_key: Zeroizing<[u8; KEY_LEN]>,
+    enc_key: Zeroizing<[u8; KEY_LEN]>,
+}
+
+impl BlobStore {
+    /// Opens the chunk directory of `storage` using the store's attachment key; new
+    /// chunks are encrypted with `algorithm`, the cipher of the store.
+    pub(crate) fn new(storage: &Storage, attachment_key: &[u8], algorithm: Algorithm) -> Self {
+        Self {
+            dir: blob_dir(storage),
+            algorithm,
+            id_key: subkey(attachment_key, ID_LABEL),
+            enc_key: subkey(attachment_key, ENC_LABEL),
+        }
+    }
+
+    /// Splits `reader` into chunks, writes every chunk that is not stored yet, and
+    /// returns the resulting attachment metadata.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading, encrypting, or writing a chunk fails.
+    pub(crate) fn write(&self, mut reader: impl Read) -> Result<Attachment> {
+        let mut buf = Zeroizing::new(vec![0u8; CHUNK_SIZE]);
+        let mut chunks = Vec::new();
+        let mut size = 0u64;
+
+        loop {
+            let n = read_full(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+
+            let plaintext = &buf[..n];
+            let id = self.chunk_id(plaintext);
+            let path = self.dir.join(&id);
+
+            if !path.exists() {
+                let aad = chunk_aad(self.algorithm, &id);
+                let (ciphertext, nonce) =
+                    self.algorithm.encrypt(&*self.enc_key, plaintext, &aad)?;
+
+                let mut file = Vec::with_capacity(aad.len() + nonce.len() + ciphertext.len());
+                file.extend_from_slice(CHUNK_MAGIC);
+                file.push(self.algorithm.into());
+                file.extend_from_slice(&nonce);
+                file.extend_from_slice(&ciphertext);
+
+                Storage::new(path).save(&file)?;
+            }
+
+            chunks.push(id);
+            size += n as u64;
+
+            if n < CHUNK_SIZE {
+                break;
+            }
+        }
+
+        Ok(Attachment::new(size, chunks))
+    }
+
+    /// Reads and decrypts a single chunk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the chunk is missing, malformed, or fails authentication.
+    pub(crate) fn read_chunk(&self, id: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let data = fs::read(self.dir.join(id))
+            .with_context(|| format!("attachment chunk {id} is missing"))?;
+
+        if data.len() < CHUNK_MAGIC.len() + 1 || &data[..CHUNK_MAGIC.len()] != CHUNK_MAGIC {
+            bail!("attachment chunk {id} is malformed");
+        }
+
+        let algorithm = Algorithm::try_from(data[CHUNK_MAGIC.len()])?;
+        let rest = &data[CHUNK_MAGIC.len() + 1..];
+        if rest.len() < algorithm.nonce_len() {
+            bail!("attachment chunk {id} is truncated");
+        }
+        let (nonce, ciphertext) = rest.split_at(algorithm.nonce_len());
+
+        algorithm
+            .decrypt(&*self.enc_key, nonce, ciphertext, &chunk_aad(algorithm, id))
+            .map_err(|_| anyhow!("attachment chunk {id} failed authentication"))
+    }
+
+    /// Reads a whole attachment into memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any chunk cannot be read.
+    pub(crate) fn read(&self, attachment: &Attachment) -> Result<Zeroizing<Vec<u8>>> {
+        let mut out = Zeroizing::new(Vec::with_capacity(attachment.size() as usize));
+        for id in attachment.chunks() {
+            out.extend_from_slice(&self.read_chunk(id)?);
+        }
+        Ok(out)
+    }
+
+    /// Returns a reader that decrypts the attachment one chunk at a time.
+    pub(crate) fn reader(self, attachment: &Attachment) -> AttachmentReader {
+        AttachmentReader {
+            blobs: self,
+            chunks: attachment.chunks().to_vec().into_iter(),
+            current: Zeroizing::new(Vec::new()),
+            pos: 0,
+        }
+    }
+
+    /// Deletes chunk files that are no longer referenced and returns how many were removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the chunk directory cannot be read or a file cannot be removed.
+    pub(crate) fn purge(&self, is_referenced: impl Fn(&str) -> bool) -> Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else { continue };
+
+            // Leave foreign files and in-flight temp files alone.
+            if !is_chunk_id(name) || is_referenced(name) {
+                continue;
+            }
+
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    fn chunk_id(&self, plaintext: &[u8]) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&*self.id_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(plaintext);
+        to_hex(&mac.finalize().into_bytes())
+    }
+}
+
+/// Streaming reader over an attachment's plaintext.
+///
+/// Returned by [`Keynest::attachment_reader`](crate::Keynest::attachment_reader). Only one
+/// decrypted chunk (at most 1 MiB) is held in memory at a time, and it is zeroized when
+/// the next chunk is loaded or the reader is dropped. A chunk that is missing or fails
+/// authentication surfaces as an [`std::io::Error`] from `read`.
+pub struct AttachmentReader {
+    blobs: BlobStore,
+    chunks: std::vec::IntoIter<String>,
+    current: Zeroizing<Vec<u8>>,
+    pos: usize,
+}
+
+impl Read for AttachmentReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.current.len() {
+            match self.chunks.next() {
+                Some(id) => {
+                    self.current = self
+                        .blobs
+                        .read_chunk(&id)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Returns the chunk directory belonging to `storage` (`<store file>.blobs`).
+pub(crate) fn blob_dir(storage: &Storage) -> PathBuf {
+    let mut name = storage
+        .path()
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(".blobs");
+    storage.path().with_file_name(name)
+}
+
+/// Derives a purpose-specific key from the attachment key.
+fn subkey(key: &[u8], label: &[u8]) -> Zeroizing<[u8; KEY_LEN]> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(label);
+
+    let mut out = Zeroizing::new([0u8; KEY_LEN]);
+    out.copy_from_slice(&mac.finalize().into_bytes());
+    out
+}
+
+/// Authenticated data binding a chunk to its id and algorithm.
+fn chunk_aad(algorithm: Algorithm, id: &str) -> Vec<u8> {
+    let mut aad = CHUNK_MAGIC.to_vec();
+    aad.push(algorithm.into());
+    aad.extend_from_slice(id.as_bytes());
+    aad
+}
+
+fn is_chunk_id(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Fills `buf` from `reader`, returning fewer bytes only at end of input.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        bail!("invalid hex string");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| anyhow!("invalid hex string")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn blob_store(dir: &std::path::Path) -> BlobStore {
+        BlobStore::new(
+            &Storage::new(dir.join("store.db")),
+            &[7u8; 32],
+            Algorithm::XChaCha20Poly1305,
+        )
+    }
+
+    #[test]
+    fn write_read_roundtrip_across_chunks() {
+        let dir = tempdir().unwrap();
+        let blobs = blob_store(dir.path());
+
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let attachment = blobs.write(&data[..]).unwrap();
+
+        assert_eq!(attachment.size(), data.len() as u64);
+        assert_eq!(attachment.chunks().len(), 3);
+        assert_eq!(*blobs.read(&attachment).unwrap(), data);
+    }
+
+    #[test]
+    fn reader_streams_in_small_reads() {
+        let dir = tempdir().unwrap();
+        let blobs = blob_store(dir.path());
+
+        let data: Vec<u8> = (0..CHUNK_SIZE + 3).map(|i| (i % 251) as u8).collect();
+        let attachment = blobs.write(&data[..]).unwrap();
+
+        let mut reader = blob_store(dir.path()).reader(&attachment);
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn identical_content_is_stored_once() {
+        let dir = tempdir().unwrap();
+        let blobs = blob_store(dir.path());
+
+        let a = blobs.write(&b"-----BEGIN CERTIFICATE-----"[..]).unwrap();
+        let b = blobs.write(&b"-----BEGIN CERTIFICATE-----"[..]).unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(fs::read_dir(&blobs.dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn tampered_chunk_fails_authentication() {
+        let dir = tempdir().unwrap();
+        let blobs = blob_store(dir.path());
+
+        let attachment = blobs.write(&b"bundle"[..]).unwrap();
+        let path = blobs.dir.join(&attachment.chunks()[0]);
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        assert!(blobs.read(&attachment).is_err());
+    }
+
+    #[test]
+    fn purge_removes_only_unreferenced_chunks() {
+        let dir = tempdir().unwrap();
+        let blobs = blob_store(dir.path());
+
+        let keep = blobs.write(&b"keep"[..]).unwrap();
+        let drop = blobs.write(&b"drop"[..]).unwrap();
+
+        let removed = blobs.purge(|id| id == keep.chunks()[0]).unwrap();
+        assert_eq!(removed, 1);
+        assert!(blobs.read(&keep).is_ok());
+        assert!(blobs.read(&drop).is_err());
+    }
+
+    #[test]
+    fn hex_roundtrip() {
+        let bytes = [0u8, 1, 0xab, 0xff];
+        assert_eq!(from_hex(&to_hex(&bytes)).unwrap(), bytes);
+        assert!(from_hex("abc").is_err());
+    }
+}
@@ -0,0 +1,380 @@
+//! Encrypted, append-only audit log of the changes and reads of a store.
+//!
+//! With the audit log on (see [`crate::Keynest::set_audit_log`]), every save appends
+//! the entries added, changed or removed since the previous save to `<store>.audit`,
+//! along with the reads counted by [`crate::Keynest::record_get`], rekeys and
+//! promotions (see [`crate::Keynest::promote`]), each with its time and the reader name
+//! of the handle (see [`crate::Keynest::set_reader`]). The log is encrypted under a
+//! random key kept inside the store, so it survives rekeys and anyone who can open the
+//! store can read it.
+//!
+//! File layout:
+//! ```text
+//! MAGIC "KNAL" (4) | VERSION (1) | ALGORITHM (1) | FRAME...
+//! FRAME = NONCE | CIPHERTEXT_LEN (4, LE) | CIPHERTEXT
+//! ```
+//!
+//! Each frame holds the events of one save as a JSON array. Its AAD is the file header,
+//! the frame number and the tag of the previous frame, so a frame cannot be changed,
+//! removed or moved without the frames after it failing to authenticate. The store
+//! records the number of frames on every save, so cutting frames off the end is
+//! detected too, except for reads logged since the last save. A last frame torn by a
+//! crash is ignored and overwritten by the next append.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+use crate::crypto::algorithm::Algorithm;
+
+const MAGIC: &[u8; 4] = b"KNAL";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2;
+const LEN_LEN: usize = 4;
+/// Length of the authentication tag at the end of every ciphertext.
+const TAG_LEN: usize = 16;
+
+/// What an audit event records.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    /// An entry was added.
+    Set,
+    /// An entry was read.
+    Get,
+    /// An entry was changed.
+    Update,
+    /// An entry was removed.
+    Remove,
+    /// The password or KDF parameters were changed.
+    Rekey,
+    /// An entry was copied from another namespace by [`crate::Keynest::promote`].
+    Promote,
+}
+
+impl AuditAction {
+    /// Returns the name of the action, e.g. `update`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Set => "set",
+            Self::Get => "get",
+            Self::Update => "update",
+            Self::Remove => "remove",
+            Self::Rekey => "rekey",
+            Self::Promote => "promote",
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One event of the audit log; see [`crate::Keynest::audit_events`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    time: String,
+    action: AuditAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    actor: String,
+}
+
+impl AuditEvent {
+    pub(crate) fn new(
+        time: DateTime<Utc>,
+        action: AuditAction,
+        key: Option<&str>,
+        actor: &str,
+    ) -> Self {
+        Self {
+            time: crate::store::format_timestamp(time),
+            action,
+            key: key.map(str::to_string),
+            from: None,
+            actor: actor.to_string(),
+        }
+    }
+
+    /// Records the entry a promoted entry was copied from.
+    pub(crate) fn with_from(mut self, from: &str) -> Self {
+        self.from = Some(from.to_string());
+        self
+    }
+
+    /// Returns when the event happened (RFC 3339, UTC). Changes are logged when they
+    /// are saved.
+    pub fn time(&self) -> &str {
+        &self.time
+    }
+
+    /// Returns what happened.
+    pub fn action(&self) -> AuditAction {
+        self.action
+    }
+
+    /// Returns the key of the entry, or `None` for events of the whole store.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// Returns the entry a promoted entry was copied from, or `None` for other events.
+    pub fn from(&self) -> Option<&str> {
+        self.from.as_deref()
+    }
+
+    /// Returns who did it, as set with [`crate::Keynest::set_reader`].
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+}
+
+/// The key of the audit log and its length as of the last save, kept in the store
+/// while the log is on (see [`crate::settings::AuditLog`]).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditLogState {
+    /// Hex-encoded random key the frames are encrypted with.
+    key: String,
+    /// Number of frames in the log as of the last save.
+    #[serde(default)]
+    frames: u64,
+}
+
+impl AuditLogState {
+    pub(crate) fn new(key: &[u8]) -> Self {
+        Self {
+            key: crate::attachments::to_hex(key),
+            frames: 0,
+        }
+    }
+
+    pub(crate) fn key(&self) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(crate::attachments::from_hex(&self.key)?))
+    }
+
+    /// Returns the number of frames in the log as of the last save.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub(crate) fn set_frames(&mut self, frames: u64) {
+        self.frames = frames;
+    }
+}
+
+/// A frame of the log as laid out on disk.
+struct Frame<'a> {
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+/// The frames of a log file, up to the first torn one, and the length they cover.
+fn frames(data: &[u8], algorithm: Algorithm) -> (Vec<Frame<'_>>, usize) {
+    let nonce_len = algorithm.nonce_len();
+    let mut frames = Vec::new();
+    let mut pos = HEADER_LEN;
+    while let Some(nonce) = data.get(pos..pos + nonce_len) {
+        let Some(len) = data.get(pos + nonce_len..pos + nonce_len + LEN_LEN) else {
+            break;
+        };
+        let start = pos + nonce_len + LEN_LEN;
+        let len = u32::from_le_bytes(len.try_into().expect("slice of LEN_LEN bytes"));
+        let Some(ciphertext) = data.get(start..start + len as usize) else {
+            break;
+        };
+        frames.push(Frame { nonce, ciphertext });
+        pos = start + len as usize;
+    }
+    (frames, pos)
+}
+
+fn parse_header(data: &[u8]) -> Result<Algorithm> {
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+        bail!("not a keynest audit log");
+    }
+    if data[MAGIC.len()] != VERSION {
+        bail!("unsupported audit log version {}", data[MAGIC.len()]);
+    }
+    Algorithm::try_from(data[MAGIC.len() + 1])
+}
+
+fn frame_aad(header: &[u8], frame: u64, previous: Option<&Frame>) -> Vec<u8> {
+    let mut aad = header.to_vec();
+    aad.extend_from_slice(&frame.to_le_bytes());
+    if let Some(previous) = previous {
+        let ciphertext = previous.ciphertext;
+        aad.extend_from_slice(&ciphertext[ciphertext.len().saturating_sub(TAG_LEN)..]);
+    }
+    aad
+}
+
+/// Appends `events` as one frame to the log at `path`, creating it for `algorithm` if
+/// it does not exist. Returns the number of frames in the log.
+///
+/// # Errors
+///
+/// Returns an error if the log cannot be read or written, or is not an audit log.
+pub(crate) fn append(
+    path: &Path,
+    key: &[u8],
+    algorithm: Algorithm,
+    events: &[AuditEvent],
+) -> Result<u64> {
+    let existing = match fs::read(path) {
+        Ok(data) => Some(data),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let (header, frames, end) = match &existing {
+        Some(data) => {
+            let algorithm = parse_header(data)?;
+            let (frames, end) = frames(data, algorithm);
+            (data[..HEADER_LEN].to_vec(), frames, end)
+        }
+        None => {
+            let mut header = MAGIC.to_vec();
+            header.push(VERSION);
+            header.push(algorithm.into());
+            (header, Vec::new(), 0)
+        }
+    };
+    let algorithm = Algorithm::try_from(header[MAGIC.len() + 1])?;
+
+    let plaintext = Zeroizing::new(serde_json::to_vec(events)?);
+    let aad = frame_aad(&header, frames.len() as u64, frames.last());
+    let (ciphertext, nonce) = algorithm.encrypt(key, &plaintext, &aad)?;
+    let mut frame = if existing.is_none() {
+        header
+    } else {
+        Vec::new()
+    };
+    frame.extend_from_slice(&nonce);
+    frame.extend_from_slice(&u32::try_from(ciphertext.len())?.to_le_bytes());
+    frame.extend_from_slice(&ciphertext);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    // Drop a torn last frame, which would break the chain.
+    if existing.is_some() {
+        file.set_len(end as u64)?;
+    }
+    std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(end as u64))?;
+    file.write_all(&frame)?;
+    file.sync_all()?;
+    Ok(frames.len() as u64 + 1)
+}
+
+/// Decrypts the log at `path` and returns its events, oldest first. A missing log has
+/// none.
+///
+/// # Errors
+///
+/// Returns an error if the log cannot be read, a frame does not authenticate, or it
+/// holds fewer than `expected_frames` frames.
+pub(crate) fn read(path: &Path, key: &[u8], expected_frames: u64) -> Result<Vec<AuditEvent>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound && expected_frames == 0 => {
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let algorithm = parse_header(&data)?;
+    let header = &data[..HEADER_LEN];
+    let (frames, _) = frames(&data, algorithm);
+    if (frames.len() as u64) < expected_frames {
+        bail!(
+            "the audit log {} was truncated: it has {} of at least {expected_frames} frames",
+            path.display(),
+            frames.len()
+        );
+    }
+
+    let mut events = Vec::new();
+    for (i, frame) in frames.iter().enumerate() {
+        let aad = frame_aad(header, i as u64, i.checked_sub(1).map(|p| &frames[p]));
+        let plaintext = algorithm
+            .decrypt(key, frame.nonce, frame.ciphertext, &aad)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "frame {i} of the audit log {} does not authenticate; it was changed \
+                     or frames were removed",
+                    path.display()
+                )
+            })?;
+        let frame_events: Vec<AuditEvent> =
+            serde_json::from_slice(&plaintext).context("malformed audit log frame")?;
+        events.extend(frame_events);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn event(key: &str) -> AuditEvent {
+        AuditEvent::new(Utc::now(), AuditAction::Set, Some(key), "alice@laptop")
+    }
+
+    #[test]
+    fn frames_are_chained() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("store.db.audit");
+        let key = [3u8; 32];
+        assert!(read(&path, &key, 0).unwrap().is_empty());
+
+        for name in ["a", "b", "c"] {
+            append(&path, &key, Algorithm::XChaCha20Poly1305, &[event(name)]).unwrap();
+        }
+        let keys: Vec<_> = read(&path, &key, 3)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key().unwrap().to_string())
+            .collect();
+        assert_eq!(keys, ["a", "b", "c"]);
+        assert!(read(&path, &key, 4).is_err(), "truncation is detected");
+        assert!(read(&path, &[4u8; 32], 0).is_err());
+
+        // Removing the middle frame breaks the chain.
+        let data = fs::read(&path).unwrap();
+        let (frames, _) = frames(&data, Algorithm::XChaCha20Poly1305);
+        let frame_len = |f: &Frame| f.nonce.len() + LEN_LEN + f.ciphertext.len();
+        let second = HEADER_LEN + frame_len(&frames[0]);
+        let third = second + frame_len(&frames[1]);
+        let mut cut = data[..second].to_vec();
+        cut.extend_from_slice(&data[third..]);
+        fs::write(&path, &cut).unwrap();
+        assert!(read(&path, &key, 0).is_err());
+
+        // A torn last frame is dropped by the next append.
+        let mut torn = data.clone();
+        torn.truncate(data.len() - 5);
+        fs::write(&path, &torn).unwrap();
+        assert_eq!(read(&path, &key, 2).unwrap().len(), 2);
+        assert_eq!(
+            append(&path, &key, Algorithm::XChaCha20Poly1305, &[event("d")]).unwrap(),
+            3
+        );
+        assert_eq!(read(&path, &key, 3).unwrap()[2].key(), Some("d"));
+    }
+}
@@ -1,22 +1,214 @@
 //! Password input handling.
 //!
-//! Supports multiple input methods: environment variable, stdin, and interactive prompt.
+//! Supports multiple input methods: a file descriptor, environment variable, stdin,
+//! and interactive prompt, and with `--use-keychain` the OS credential store (see
+//! [`unlock`]). Also holds the keyfile given with `--keyfile` and checks the strength of
+//! new passwords (see [`check_new_password`]).
 
-use anyhow::{Result, bail};
-use std::io::{self, BufRead, IsTerminal};
+use anyhow::{Context, Result, bail};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use zeroize::Zeroizing;
 
+use crate::commands::keychain;
+
+/// Reader for `--password-fd`; each password read consumes one line.
+static PASSWORD_FD: Mutex<Option<(u32, BufReader<File>)>> = Mutex::new(None);
+
+/// Keyfile set with `--keyfile` or `KEYNEST_KEYFILE`.
+static KEYFILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Set once stdin has been consumed for something other than the password.
+static STDIN_CONSUMED: AtomicBool = AtomicBool::new(false);
+
+/// Set with `--use-keychain`.
+static USE_KEYCHAIN: AtomicBool = AtomicBool::new(false);
+
+/// Score new passwords must reach: `--min-password-score`, the profile's, or 3.
+static MIN_PASSWORD_SCORE: AtomicU8 = AtomicU8::new(3);
+
+/// Set with `--allow-weak`.
+static ALLOW_WEAK: AtomicBool = AtomicBool::new(false);
+
+/// Reads passwords from file descriptor `fd` instead of the environment, stdin, or a
+/// prompt.
+///
+/// Every password the command needs is read as one line from `fd`, in order (for
+/// `rekey`: the current password, then the new one).
+///
+/// # Errors
+///
+/// Returns an error if `fd` is not open or not readable.
+pub fn set_password_fd(fd: u32) -> Result<()> {
+    let file = open_fd(fd)?;
+    *PASSWORD_FD.lock().unwrap_or_else(|e| e.into_inner()) = Some((fd, BufReader::new(file)));
+    Ok(())
+}
+
+/// Returns the file descriptor set with [`set_password_fd`], if any.
+pub fn password_fd() -> Option<u32> {
+    PASSWORD_FD
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(|(fd, _)| *fd)
+}
+
+/// Uses the keyfile at `path` besides the password to open the store, and for `init`.
+pub fn set_keyfile(path: PathBuf) {
+    *KEYFILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(path);
+}
+
+/// Returns the path set with [`set_keyfile`], if any.
+pub fn keyfile_path() -> Option<PathBuf> {
+    KEYFILE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Reads the keyfile set with [`set_keyfile`], if any.
+///
+/// # Errors
+///
+/// Returns an error if the keyfile cannot be read or is empty.
+pub fn read_keyfile() -> Result<Option<keynest::Keyfile>> {
+    keyfile_path()
+        .as_deref()
+        .map(keynest::Keyfile::read)
+        .transpose()
+}
+
+/// Takes the master password from the OS credential store, where `keynest keychain
+/// enable` put it, before reading it as [`read_password`] does.
+pub fn set_use_keychain() {
+    USE_KEYCHAIN.store(true, Ordering::Relaxed);
+}
+
+/// Returns `true` if [`set_use_keychain`] was called.
+pub fn uses_keychain() -> bool {
+    USE_KEYCHAIN.load(Ordering::Relaxed)
+}
+
+/// The master password to unlock a store with, and where it came from.
+pub enum Unlock {
+    /// Stored in the OS credential store.
+    Keychain(Zeroizing<String>),
+    /// Read with [`read_password`].
+    Input(Zeroizing<String>),
+}
+
+impl Unlock {
+    /// Returns `true` if the password came from the OS credential store.
+    pub fn is_keychain(&self) -> bool {
+        matches!(self, Self::Keychain(_))
+    }
+
+    /// Returns the password.
+    pub fn into_password(self) -> Zeroizing<String> {
+        match self {
+            Self::Keychain(pw) | Self::Input(pw) => pw,
+        }
+    }
+}
+
+/// Returns the master password for the store at `path`.
+///
+/// With `--use-keychain`, the password stored in the OS credential store for that store
+/// is used; if there is none or the credential store cannot be read, a warning is
+/// printed and the password is read with [`read_password`].
+///
+/// # Errors
+///
+/// Returns an error if no password is provided.
+pub fn unlock(path: &Path) -> Result<Unlock> {
+    if uses_keychain() {
+        match keychain::stored_password(path) {
+            Ok(Some(pw)) => return Ok(Unlock::Keychain(pw)),
+            Ok(None) => eprintln!(
+                "warning: the OS keychain holds no password for {}; run `keynest keychain enable`",
+                path.display()
+            ),
+            Err(e) => eprintln!("warning: {e:#}"),
+        }
+    }
+    read_password().map(Unlock::Input)
+}
+
+/// Reads everything from file descriptor `fd`, e.g. a secret value passed by automation.
+///
+/// # Errors
+///
+/// Returns an error if `fd` cannot be read or does not contain valid UTF-8.
+pub fn read_fd_to_string(fd: u32) -> Result<Zeroizing<String>> {
+    let mut buf = Zeroizing::new(String::new());
+    open_fd(fd)?
+        .read_to_string(&mut buf)
+        .with_context(|| format!("failed to read from file descriptor {fd}"))?;
+    Ok(buf)
+}
+
+/// Reads everything from stdin and marks it as consumed, so the password is not
+/// expected there as well.
+///
+/// # Errors
+///
+/// Returns an error if stdin cannot be read or does not contain valid UTF-8.
+pub fn read_stdin_to_string() -> Result<Zeroizing<String>> {
+    STDIN_CONSUMED.store(true, Ordering::Relaxed);
+    let mut buf = Zeroizing::new(String::new());
+    io::stdin().read_to_string(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(unix)]
+fn open_fd(fd: u32) -> Result<File> {
+    // Reopening through /dev/fd avoids taking ownership of a raw descriptor and fails
+    // cleanly if it is not open.
+    File::open(format!("/dev/fd/{fd}"))
+        .with_context(|| format!("cannot read from file descriptor {fd}"))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: u32) -> Result<File> {
+    bail!("reading from a file descriptor is only supported on Unix")
+}
+
+/// Reads one line from the `--password-fd` descriptor, if one was set.
+fn read_password_fd() -> Result<Option<Zeroizing<String>>> {
+    let mut guard = PASSWORD_FD.lock().unwrap_or_else(|e| e.into_inner());
+    let Some((fd, reader)) = guard.as_mut() else {
+        return Ok(None);
+    };
+
+    let mut pw = Zeroizing::new(String::new());
+    reader
+        .read_line(&mut pw)
+        .with_context(|| format!("failed to read password from file descriptor {fd}"))?;
+    trim_newline(&mut pw);
+
+    if pw.is_empty() {
+        bail!("No password provided on file descriptor {fd}");
+    }
+    Ok(Some(pw))
+}
+
 /// Reads the master password from the user.
 ///
 /// Checks in order:
-/// 1. `KEYNEST_PASSWORD` environment variable
-/// 2. stdin (non-interactive)
-/// 3. Terminal prompt (interactive)
+/// 1. `--password-fd` file descriptor
+/// 2. `KEYNEST_PASSWORD` environment variable
+/// 3. stdin (non-interactive, unless stdin carries the secret value)
+/// 4. Terminal prompt (interactive)
 ///
 /// # Errors
 ///
 /// Returns an error if no password is provided.
 pub fn read_password() -> Result<Zeroizing<String>> {
+    if let Some(pw) = read_password_fd()? {
+        return Ok(pw);
+    }
+
     //  Environment Variable
     //  KEYNEST_PASSWORD="supersecret" keynest get github_token
     if let Ok(pw) = std::env::var("KEYNEST_PASSWORD") {
@@ -28,6 +220,12 @@ pub fn read_password() -> Result<Zeroizing<String>> {
     //  stdin (Pipeline)
     //  echo "supersecret" | keynest get github_token
     //  printf "%s" "$KEYNEST_PASSWORD" | keynest get github_token
+    if STDIN_CONSUMED.load(Ordering::Relaxed) {
+        bail!(
+            "stdin is already used for the secret value; pass the password with \
+             KEYNEST_PASSWORD or --password-fd"
+        );
+    }
     if !io::stdin().is_terminal() {
         let mut buf = String::new();
         io::stdin().read_line(&mut buf)?;
@@ -49,15 +247,73 @@ pub fn read_password() -> Result<Zeroizing<String>> {
     bail!("No password provided")
 }
 
+/// Sets the score from 0 to 4 new passwords must reach (see [`check_new_password`]).
+pub fn set_min_password_score(score: u8) {
+    MIN_PASSWORD_SCORE.store(score, Ordering::Relaxed);
+}
+
+/// Accepts new passwords below the minimum score with a warning instead of refusing them.
+pub fn set_allow_weak() {
+    ALLOW_WEAK.store(true, Ordering::Relaxed);
+}
+
+/// Checks that `password`, about to protect a keystore, reaches the minimum score of
+/// [`keynest::estimate_strength`]; below it, warns with `--allow-weak` or fails.
+///
+/// # Errors
+///
+/// Returns an error if the password is too weak and `--allow-weak` was not given.
+pub fn check_new_password(password: &str) -> Result<()> {
+    let min_score = MIN_PASSWORD_SCORE.load(Ordering::Relaxed);
+    let strength = keynest::estimate_strength(password);
+    if strength.score() >= min_score {
+        return Ok(());
+    }
+    let weaknesses: Vec<String> = strength
+        .weaknesses()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let problem = format!(
+        "the new password is weak: score {} of 4, {} needed{}",
+        strength.score(),
+        min_score,
+        if weaknesses.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", weaknesses.join(", "))
+        }
+    );
+    if ALLOW_WEAK.load(Ordering::Relaxed) {
+        eprintln!("warning: {problem}");
+        return Ok(());
+    }
+    bail!(
+        "{problem}; choose a longer one, e.g. from `keynest generate --words 6`, or pass \
+         --allow-weak to use it anyway"
+    )
+}
+
 /// Reads a new password with confirmation.
 ///
 /// Used when creating or rekeying a keystore. Prompts for password twice
-/// and ensures they match.
+/// and ensures they match. With `--password-fd`, a single line is read instead.
+/// The password must pass [`check_new_password`].
 ///
 /// # Errors
 ///
-/// Returns an error if passwords don't match or are empty.
+/// Returns an error if passwords don't match, are empty or are too weak.
 pub fn read_new_password_with_confirmation() -> Result<Zeroizing<String>> {
+    let password = read_new_password()?;
+    check_new_password(&password)?;
+    Ok(password)
+}
+
+fn read_new_password() -> Result<Zeroizing<String>> {
+    if let Some(pw) = read_password_fd()? {
+        return Ok(pw);
+    }
+
     if !io::stdin().is_terminal() {
         let stdin = io::stdin();
         let mut handle = stdin.lock();
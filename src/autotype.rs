@@ -0,0 +1,134 @@
+//! Autotype sequences: what `keynest type` types for a login.
+//!
+//! A login is a namespace whose entries are its fields, e.g. `bank/username` and
+//! `bank/password`. A sequence such as `{USERNAME}{TAB}{PASSWORD}{ENTER}` mixes literal
+//! text, field placeholders and special keys, using KeePass syntax:
+//!
+//! - `{NAME}`: the value of field `name` (`<login>/name`, case-insensitive)
+//! - `{TAB}`, `{ENTER}`: press the key
+//! - `{DELAY n}`: wait `n` milliseconds
+//! - `{{}`, `{}}`: a literal brace
+
+use anyhow::{Result, bail};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Sequence used for logins without a configured one.
+pub const DEFAULT_SEQUENCE: &str = "{USERNAME}{TAB}{PASSWORD}{ENTER}";
+
+/// A special key in a sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKey {
+    Tab,
+    Enter,
+}
+
+/// One step of a parsed sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Text(String),
+    Field(String),
+    Key(SpecialKey),
+    Delay(Duration),
+}
+
+/// One step of a resolved sequence, ready to be performed.
+#[derive(Debug)]
+pub enum Action {
+    /// Type this text (literal text or a field value).
+    Text(Zeroizing<String>),
+    /// Press this key.
+    Key(SpecialKey),
+    /// Wait before the next step.
+    Delay(Duration),
+}
+
+/// Parses `sequence` into tokens.
+///
+/// # Errors
+///
+/// Returns an error for an unclosed `{`, a stray `}`, an empty placeholder, or an
+/// invalid `{DELAY n}`.
+pub(crate) fn parse(sequence: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut rest = sequence;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{}") || rest.starts_with("{}}") {
+            text.push(rest.as_bytes()[1] as char);
+            rest = &rest[3..];
+            continue;
+        }
+        match c {
+            '{' => {
+                let Some(end) = rest.find('}') else {
+                    bail!("unclosed '{{' in autotype sequence '{sequence}'");
+                };
+                let placeholder = &rest[1..end];
+                rest = &rest[end + 1..];
+
+                if !text.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut text)));
+                }
+                tokens.push(parse_placeholder(placeholder, sequence)?);
+            }
+            '}' => bail!(
+                "unexpected '}}' in autotype sequence '{sequence}' (use {{}}}} for a literal brace)"
+            ),
+            _ => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    Ok(tokens)
+}
+
+fn parse_placeholder(placeholder: &str, sequence: &str) -> Result<Token> {
+    let upper = placeholder.trim().to_uppercase();
+    if let Some(ms) = upper.strip_prefix("DELAY ") {
+        let Ok(ms) = ms.trim().parse() else {
+            bail!("invalid {{{placeholder}}} in autotype sequence '{sequence}'");
+        };
+        return Ok(Token::Delay(Duration::from_millis(ms)));
+    }
+    match upper.as_str() {
+        "TAB" => Ok(Token::Key(SpecialKey::Tab)),
+        "ENTER" => Ok(Token::Key(SpecialKey::Enter)),
+        "" => bail!("empty placeholder in autotype sequence '{sequence}'"),
+        _ if upper.contains(char::is_whitespace) || upper.contains('/') => {
+            bail!("invalid field {{{placeholder}}} in autotype sequence '{sequence}'")
+        }
+        _ => Ok(Token::Field(upper.to_lowercase())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keepass_syntax() {
+        let tokens = parse("{USERNAME}{TAB}pin:{{}{Password}{}}{DELAY 250}{ENTER}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Token::Field("username".into()),
+                Token::Key(SpecialKey::Tab),
+                Token::Text("pin:{".into()),
+                Token::Field("password".into()),
+                Token::Text("}".into()),
+                Token::Delay(Duration::from_millis(250)),
+                Token::Key(SpecialKey::Enter),
+            ]
+        );
+
+        for bad in ["{USERNAME", "a}b", "{}", "{DELAY x}", "{two words}"] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Offline checks of passwords against a list of breached passwords, such as the Pwned
+//! Passwords list of Have I Been Pwned, downloaded beforehand.
+//!
+//! Passwords are looked up by their SHA-1 hash, so nothing leaves the machine. Three
+//! layouts of the list are read:
+//!
+//! - a text file of `HASH:COUNT` lines sorted by hash, as written by the Pwned Passwords
+//!   downloader (`COUNT` may be left out);
+//! - a directory of range files `00000.txt` to `FFFFF.txt`, each holding the
+//!   `SUFFIX:COUNT` lines of the hashes starting with its name, as served by the
+//!   k-anonymity range API;
+//! - a binary file of the sorted 20-byte hashes, without counts, e.g. converted from the
+//!   text file with `cut -d: -f1 pwnedpasswords.txt | xxd -r -p > pwned.bin`.
+//!
+//! Files are mapped into memory and binary searched, so multi-gigabyte lists are not read
+//! in full.
+
+use anyhow::{Context, Result, bail};
+use memmap2::Mmap;
+use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use crate::attachments::to_hex;
+
+/// Length of a SHA-1 hash.
+const HASH_LEN: usize = 20;
+/// Length of a SHA-1 hash in hex.
+const HEX_LEN: usize = 2 * HASH_LEN;
+/// Length of the hash prefix naming a range file.
+const PREFIX_LEN: usize = 5;
+
+/// A list of breached passwords to check passwords against; see [`BreachList::open`]
+/// for the layouts read.
+pub struct BreachList {
+    path: PathBuf,
+    source: Source,
+}
+
+enum Source {
+    Ranges,
+    Text(Mmap),
+    Hashes(Mmap),
+    Empty,
+}
+
+/// A secret whose value is in a [`BreachList`], found by [`crate::Keynest::audit_breach`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breached {
+    key: String,
+    count: Option<u64>,
+}
+
+impl Breached {
+    pub(crate) fn new(key: &str, count: Option<u64>) -> Self {
+        Self {
+            key: key.to_string(),
+            count,
+        }
+    }
+
+    /// Returns the key of the secret.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns how often the value was seen in breaches, if the list records it.
+    pub fn count(&self) -> Option<u64> {
+        self.count
+    }
+}
+
+impl BreachList {
+    /// Opens the list at `path`: a text file of `HASH:COUNT` lines sorted by hash, a
+    /// directory of range files `00000.txt` to `FFFFF.txt` holding `SUFFIX:COUNT` lines,
+    /// or a binary file of sorted 20-byte SHA-1 hashes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` cannot be read or is none of these.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if path.is_dir() {
+            return Ok(Self {
+                path,
+                source: Source::Ranges,
+            });
+        }
+        let file =
+            File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+        if file.metadata()?.len() == 0 {
+            return Ok(Self {
+                path,
+                source: Source::Empty,
+            });
+        }
+        // SAFETY: the list is only read; a list changed by another program while it is
+        // mapped gives wrong answers or errors, not memory unsafety beyond what memmap2
+        // documents.
+        let map = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("failed to map {}", path.display()))?;
+        let source = if map.len() > HEX_LEN
+            && map[..HEX_LEN].iter().all(u8::is_ascii_hexdigit)
+            && matches!(map[HEX_LEN], b':' | b'\r' | b'\n')
+        {
+            Source::Text(map)
+        } else if map.len() % HASH_LEN == 0 {
+            Source::Hashes(map)
+        } else {
+            bail!(
+                "{} is not a breach list: expected SHA-1 hashes, one per line or 20 bytes each",
+                path.display()
+            );
+        };
+        Ok(Self { path, source })
+    }
+
+    /// Returns the path of the list.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Looks up `password`: returns `None` if it is not in the list, or else how often it
+    /// was seen, if the list records it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the list is malformed or, for a directory, its range file is
+    /// missing.
+    pub fn lookup(&self, password: &str) -> Result<Option<Option<u64>>> {
+        let digest: [u8; HASH_LEN] = Sha1::digest(password.as_bytes()).into();
+        let mut hex = Zeroizing::new(to_hex(&digest));
+        hex.make_ascii_uppercase();
+        match &self.source {
+            Source::Empty => Ok(None),
+            Source::Hashes(map) => Ok(search_hashes(map, &digest).then_some(None)),
+            Source::Text(map) => self.search_text(map, hex.as_bytes()),
+            Source::Ranges => self.search_range(&hex),
+        }
+    }
+
+    fn search_text(&self, data: &[u8], hex: &[u8]) -> Result<Option<Option<u64>>> {
+        let (mut lo, mut hi) = (0, data.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let start = data[..mid]
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |i| i + 1);
+            let end = data[mid..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(data.len(), |i| mid + i);
+            let line = &data[start..end];
+            if line.trim_ascii().is_empty() {
+                // Blank lines only appear at the end of a valid list.
+                hi = start;
+                continue;
+            }
+            let Some(line_hex) = line.get(..HEX_LEN) else {
+                bail!("malformed line in {}", self.path.display());
+            };
+            match compare_hex(line_hex, hex) {
+                Ordering::Equal => return self.count(&line[HEX_LEN..]).map(Some),
+                Ordering::Less => lo = end + 1,
+                Ordering::Greater => hi = start,
+            }
+        }
+        Ok(None)
+    }
+
+    fn search_range(&self, hex: &str) -> Result<Option<Option<u64>>> {
+        let (prefix, suffix) = hex.split_at(PREFIX_LEN);
+        let path = self.path.join(format!("{prefix}.txt"));
+        let data = std::fs::read(&path)
+            .with_context(|| format!("failed to read range file {}", path.display()))?;
+        for line in data.split(|b| *b == b'\n') {
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let Some(line_suffix) = line.get(..suffix.len()) else {
+                bail!("malformed line in {}", path.display());
+            };
+            if compare_hex(line_suffix, suffix.as_bytes()) == Ordering::Equal {
+                return self.count(&line[suffix.len()..]).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parses the `:COUNT` after a hash, if there is one.
+    fn count(&self, rest: &[u8]) -> Result<Option<u64>> {
+        let rest = rest.trim_ascii();
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let count = rest
+            .strip_prefix(b":")
+            .and_then(|count| std::str::from_utf8(count).ok())
+            .and_then(|count| count.parse().ok())
+            .with_context(|| format!("malformed count in {}", self.path.display()))?;
+        Ok(Some(count))
+    }
+}
+
+/// Compares hex digits case-insensitively, `upper` being uppercase already.
+fn compare_hex(hex: &[u8], upper: &[u8]) -> Ordering {
+    hex.iter()
+        .map(u8::to_ascii_uppercase)
+        .cmp(upper.iter().copied())
+}
+
+fn search_hashes(data: &[u8], digest: &[u8; HASH_LEN]) -> bool {
+    let (mut lo, mut hi) = (0, data.len() / HASH_LEN);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match data[mid * HASH_LEN..(mid + 1) * HASH_LEN].cmp(digest) {
+            Ordering::Equal => return true,
+            Ordering::Less => lo = mid + 1,
+            Ordering::Greater => hi = mid,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha1_hex(password: &str) -> String {
+        to_hex(&Sha1::digest(password.as_bytes())).to_ascii_uppercase()
+    }
+
+    #[test]
+    fn lookup_reads_all_list_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let breached = ["password", "123456", "hunter2", "letmein"];
+        let mut hashes: Vec<String> = breached.iter().map(|p| sha1_hex(p)).collect();
+        hashes.sort();
+
+        let text = dir.path().join("pwned.txt");
+        let lines: Vec<String> = hashes
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| format!("{hash}:{}\r\n", i + 1))
+            .collect();
+        std::fs::write(&text, lines.concat()).unwrap();
+
+        let bin = dir.path().join("pwned.bin");
+        let bytes: Vec<u8> = hashes
+            .iter()
+            .flat_map(|hash| crate::attachments::from_hex(hash).unwrap())
+            .collect();
+        std::fs::write(&bin, bytes).unwrap();
+
+        let ranges = dir.path().join("ranges");
+        std::fs::create_dir(&ranges).unwrap();
+        for password in breached.iter().chain(&["correct horse"]) {
+            let hash = sha1_hex(password);
+            let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+            let path = ranges.join(format!("{prefix}.txt"));
+            if breached.contains(password) {
+                std::fs::write(path, format!("{}:7\n", suffix.to_ascii_lowercase())).unwrap();
+            } else {
+                std::fs::write(path, "0000000000000000000000000000000000A:1\n").unwrap();
+            }
+        }
+
+        let text = BreachList::open(&text).unwrap();
+        let bin = BreachList::open(&bin).unwrap();
+        let ranges = BreachList::open(&ranges).unwrap();
+        for password in breached {
+            let rank = hashes
+                .iter()
+                .position(|h| *h == sha1_hex(password))
+                .unwrap();
+            assert_eq!(text.lookup(password).unwrap(), Some(Some(rank as u64 + 1)));
+            assert_eq!(bin.lookup(password).unwrap(), Some(None));
+            assert_eq!(ranges.lookup(password).unwrap(), Some(Some(7)));
+        }
+        for list in [&text, &bin, &ranges] {
+            assert_eq!(list.lookup("correct horse").unwrap(), None);
+        }
+        assert!(ranges.lookup("not downloaded").is_err());
+
+        let junk = dir.path().join("junk");
+        std::fs::write(&junk, "not a list").unwrap();
+        assert!(BreachList::open(&junk).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Encrypted bundles of entries, for moving secrets between machines.
+//!
+//! A bundle is a standalone file, encrypted under its own passphrase with its own salt,
+//! KDF parameters and nonce, so it can travel over untrusted channels without revealing
+//! anything about the store it came from. It holds the entries with their kind, fields,
+//! tags, expiry and access policy; attachments stay behind.
+//!
+//! File layout:
+//! ```text
+//! MAGIC "KNBU" (4) | VERSION (1) | ALGORITHM (1) | KDF_LEN (1) | KDF | SALT (16)
+//!   | NONCE_LEN (1) | NONCE | CIPHERTEXT
+//! ```
+//!
+//! The KDF parameters are encoded as in keystore headers. Everything before the nonce
+//! is authenticated as AAD. The plaintext is the JSON document `{"entries": [...]}`,
+//! each entry as stored in the keystore payload.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, KdfParams, SALT_LEN, algorithm::Algorithm};
+use crate::store::SecretEntry;
+
+const MAGIC: &[u8; 4] = b"KNBU";
+const VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Contents {
+    entries: Vec<SecretEntry>,
+}
+
+/// Encrypts `entries` into a bundle under `passphrase`.
+///
+/// # Errors
+///
+/// Returns an error if the KDF parameters are invalid or encryption fails.
+pub(crate) fn seal(entries: Vec<SecretEntry>, passphrase: &str, kdf: KdfParams) -> Result<Vec<u8>> {
+    let algorithm = Algorithm::XChaCha20Poly1305;
+    let salt = crypto::generate_salt()?;
+    let key = Zeroizing::new(
+        crypto::derive_key(passphrase, &salt, kdf).context("failed to derive the bundle key")?,
+    );
+
+    let mut encoded_kdf = Vec::new();
+    kdf.encode(&mut encoded_kdf);
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.push(algorithm.into());
+    out.push(u8::try_from(encoded_kdf.len())?);
+    out.extend_from_slice(&encoded_kdf);
+    out.extend_from_slice(&salt);
+
+    let plaintext = Zeroizing::new(serde_json::to_vec(&Contents { entries })?);
+    let (ciphertext, nonce) = algorithm.encrypt(&*key, &plaintext, &out)?;
+    out.push(u8::try_from(nonce.len())?);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypts a bundle written by [`seal`] and returns its entries.
+///
+/// # Errors
+///
+/// Returns an error if `data` is not a bundle, uses an unknown version, or does not
+/// decrypt under `passphrase`.
+pub(crate) fn open(data: &[u8], passphrase: &str) -> Result<Vec<SecretEntry>> {
+    let mut reader = Reader(data);
+    if reader.take(MAGIC.len())? != MAGIC {
+        bail!("not a keynest bundle");
+    }
+    let version = reader.take(1)?[0];
+    if version != VERSION {
+        bail!("unsupported bundle version {version}");
+    }
+    let algorithm = Algorithm::try_from(reader.take(1)?[0])?;
+    let kdf_len = usize::from(reader.take(1)?[0]);
+    let kdf = KdfParams::decode(reader.take(kdf_len)?)?;
+    let salt = reader.take(SALT_LEN)?;
+    let aad = &data[..data.len() - reader.0.len()];
+    let nonce_len = usize::from(reader.take(1)?[0]);
+    if nonce_len != algorithm.nonce_len() {
+        bail!("invalid nonce length in the bundle");
+    }
+    let nonce = reader.take(nonce_len)?;
+
+    let key = Zeroizing::new(
+        crypto::derive_key(passphrase, salt, kdf).context("failed to derive the bundle key")?,
+    );
+    let plaintext = algorithm
+        .decrypt(&*key, nonce, reader.0, aad)
+        .map_err(|_| anyhow::anyhow!("wrong passphrase, or the bundle was changed"))?;
+    let contents: Contents =
+        serde_json::from_slice(&plaintext).context("malformed bundle contents")?;
+    Ok(contents.entries)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("truncated bundle");
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+}
@@ -0,0 +1,236 @@
+//! Detecting rollbacks of a store with the hash chain over its saves.
+//!
+//! Every save of a v3 file records a [`ChainLink`] in its header: the generation,
+//! counted up by every save, and the SHA-256 hash of the file it replaced. The link is
+//! authenticated with the index record, so it cannot be changed without the key, but
+//! someone with write access to the file can still put back an older copy, which is
+//! valid, only stale. A [`ChainWitness`] remembers the newest generation and file hash
+//! seen on this machine, so [`crate::Keynest::verify_chain`] tells such a copy apart
+//! from a newer one.
+
+use anyhow::{Context, Result, bail};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::attachments::{from_hex, to_hex};
+use crate::format::{ChainLink, FILE_HASH_LEN, file_hash};
+use crate::storage::Storage;
+
+/// Record of the newest save of a store seen on this machine, kept outside the store so
+/// that putting back an older copy of the store does not put back an older record.
+///
+/// Once a witness exists, every save through a handle it is set on advances it (see
+/// [`crate::Keynest::set_chain_witness`]), unless the save builds on a stale copy.
+#[derive(Debug, Clone)]
+pub struct ChainWitness {
+    path: PathBuf,
+}
+
+/// A generation with the hash of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChainHead {
+    pub(crate) generation: u64,
+    pub(crate) hash: [u8; FILE_HASH_LEN],
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredHead {
+    generation: u64,
+    hash: String,
+}
+
+impl ChainWitness {
+    /// Creates a witness kept in the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Creates the witness of the store at `store` in `dir`, named after the hash of the
+    /// canonical path of the store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path of the store cannot be resolved.
+    pub fn in_dir(dir: &Path, store: &Path) -> Result<Self> {
+        let store = std::fs::canonicalize(store)
+            .with_context(|| format!("failed to resolve {}", store.display()))?;
+        let name = to_hex(&file_hash(store.as_os_str().as_encoded_bytes()));
+        Ok(Self::new(dir.join(name)))
+    }
+
+    /// Creates the witness of the store at `store` in the platform data directory, e.g.
+    /// `~/.local/share/keynest/chain/` on Linux.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the platform directories cannot be determined or the path of
+    /// the store cannot be resolved.
+    pub fn default_for(store: &Path) -> Result<Self> {
+        let project_dirs = ProjectDirs::from("", "", "keynest")
+            .context("could not determine platform directories")?;
+        Self::in_dir(&project_dirs.data_local_dir().join("chain"), store)
+    }
+
+    /// Returns the path of the witness file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns `true` if a save was recorded in the witness.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Returns the recorded save, or `None` if there is none yet.
+    pub(crate) fn head(&self) -> Result<Option<ChainHead>> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", self.path.display()));
+            }
+        };
+        let stored: StoredHead = serde_json::from_slice(&data)
+            .with_context(|| format!("malformed chain witness {}", self.path.display()))?;
+        let Ok(hash) = from_hex(&stored.hash)?.try_into() else {
+            bail!("malformed chain witness {}", self.path.display());
+        };
+        Ok(Some(ChainHead {
+            generation: stored.generation,
+            hash,
+        }))
+    }
+
+    /// Records `head` as the newest save seen.
+    pub(crate) fn record(&self, head: ChainHead) -> Result<()> {
+        let stored = StoredHead {
+            generation: head.generation,
+            hash: to_hex(&head.hash),
+        };
+        Storage::new(self.path.clone())
+            .save(&serde_json::to_vec(&stored)?)
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+
+    /// Records the save of the file hashed as `hash` with link `link`, if it follows the
+    /// recorded save or a later one. A save that builds on an older or a different file
+    /// than the recorded one is left for [`crate::Keynest::verify_chain`] to report.
+    pub(crate) fn advance(&self, link: &ChainLink, hash: [u8; FILE_HASH_LEN]) -> Result<()> {
+        let parent = link.generation() - 1;
+        let follows = match self.head()? {
+            None => true,
+            Some(head) => {
+                head.generation < parent
+                    || (head.generation == parent && head.hash == *link.previous())
+            }
+        };
+        if follows {
+            self.record(ChainHead {
+                generation: link.generation(),
+                hash,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of [`crate::Keynest::verify_chain`]: how the store compares to the newest save
+/// recorded in the witness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainStatus {
+    /// Nothing was recorded yet; the store is recorded from now on.
+    Untracked {
+        /// Generation of the store.
+        generation: u64,
+    },
+    /// The store is the save recorded.
+    Current {
+        /// Generation of the store.
+        generation: u64,
+    },
+    /// The store was saved again since, e.g. on another machine.
+    Ahead {
+        /// Generation of the store.
+        generation: u64,
+        /// Number of saves since the recorded one.
+        saves: u64,
+        /// Whether the links back to the recorded save were checked; saves in between
+        /// are only checked when they are still kept as backups.
+        linked: bool,
+    },
+    /// The store is older than the save recorded: it was put back to a stale copy.
+    RolledBack {
+        /// Generation of the store.
+        generation: u64,
+        /// Generation recorded.
+        seen: u64,
+    },
+    /// The store does not descend from the save recorded, e.g. a copy of it was changed
+    /// and put in its place.
+    Forked {
+        /// Generation of the store.
+        generation: u64,
+        /// Generation recorded.
+        seen: u64,
+    },
+}
+
+impl ChainStatus {
+    /// Returns `true` unless the store was rolled back or forked.
+    pub fn is_ok(&self) -> bool {
+        !matches!(self, Self::RolledBack { .. } | Self::Forked { .. })
+    }
+}
+
+/// Compares `link`, the link of the file hashed as `hash`, to the save recorded in
+/// `witness`, following the links back through `earlier` (files by hash, e.g. the
+/// backups) when the store is more than one save ahead.
+pub(crate) fn verify(
+    link: &ChainLink,
+    hash: [u8; FILE_HASH_LEN],
+    witness: &ChainWitness,
+    earlier: impl Fn(&[u8; FILE_HASH_LEN]) -> Option<ChainLink>,
+) -> Result<ChainStatus> {
+    let generation = link.generation();
+    let Some(head) = witness.head()? else {
+        return Ok(ChainStatus::Untracked { generation });
+    };
+    let seen = head.generation;
+    if generation < seen {
+        return Ok(ChainStatus::RolledBack { generation, seen });
+    }
+    if generation == seen {
+        return Ok(if hash == head.hash {
+            ChainStatus::Current { generation }
+        } else {
+            ChainStatus::Forked { generation, seen }
+        });
+    }
+
+    let saves = generation - seen;
+    let mut link = *link;
+    loop {
+        if link.generation() - 1 == seen {
+            return Ok(if *link.previous() == head.hash {
+                ChainStatus::Ahead {
+                    generation,
+                    saves,
+                    linked: true,
+                }
+            } else {
+                ChainStatus::Forked { generation, seen }
+            });
+        }
+        match earlier(link.previous()) {
+            Some(parent) if parent.generation() == link.generation() - 1 => link = parent,
+            _ => {
+                return Ok(ChainStatus::Ahead {
+                    generation,
+                    saves,
+                    linked: false,
+                });
+            }
+        }
+    }
+}
@@ -1,9 +1,24 @@
 use clap::{Parser, Subcommand};
+use std::ffi::OsString;
 
 use crate::commands::{
-    Command, exec::ExecCommand, export::ExportCommand, get::GetCommand, import::ImportCommand,
-    info::InfoCommand, init::InitCommand, list::ListCommand, rekey::RekeyCommand,
-    remove::RemoveCommand, set::SetCommand, update::UpdateCommand,
+    Command, agent::AgentCommand, api::ApiCommand, attach::AttachCommand, audit::AuditCommand,
+    audit_age::AuditAgeCommand, audit_breach::AuditBreachCommand,
+    audit_strength::AuditStrengthCommand, autotype::AutotypeCommand, backup::BackupCommand,
+    compact::CompactCommand, compat::CompatCommand, completions, completions::CompleteKeysCommand,
+    completions::CompletionsCommand, convert::ConvertCommand, counter::CounterCommand,
+    cp::CpCommand, crypt::CryptCommand, deps::DepsCommand, derive::DeriveCommand, dev::DevCommand,
+    duress::DuressCommand, edit::EditCommand, exec::ExecCommand, export::ExportCommand,
+    generate::GenerateCommand, get::GetCommand, gpg_preset::GpgPresetCommand,
+    help_topics::HelpTopicsCommand, import::ImportCommand, info::InfoCommand, init::InitCommand,
+    key_index::KeyIndexCommand, keychain::KeychainCommand, keyslot::KeyslotCommand,
+    lease::LeaseCommand, list::ListCommand, lookup::LookupCommand, merge::MergeCommand,
+    migrate::MigrateCommand, mv::MvCommand, new::NewCommand, nuke::NukeCommand, pin::PinCommand,
+    pin::UnpinCommand, plan::PlanCommand, plugin, plugin::PluginsCommand, promote::PromoteCommand,
+    quota::QuotaCommand, rekey::RekeyCommand, remove::RemoveCommand, repair::RepairCommand,
+    resolve::ResolveCommand, search::SearchCommand, set::SetCommand, snapshot::SnapshotCommand,
+    ssh::SshCommand, stats::StatsCommand, template::TemplateCommand, totp::TotpCommand,
+    typing::TypeCommand, update::UpdateCommand, verify_chain::VerifyChainCommand,
 };
 
 #[derive(Parser)]
@@ -17,6 +32,26 @@ pub struct Cli {
     #[arg(long, global = true, value_name = "PATH", env = "KEYNEST_PATH")]
     pub store: Option<std::path::PathBuf>,
 
+    /// Use this profile of the config file (its store and defaults for `init`)
+    #[arg(long, global = true, value_name = "NAME", env = "KEYNEST_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Read the master password from this file descriptor (one line per password)
+    #[arg(long = "password-fd", global = true, value_name = "FD")]
+    pub password_fd: Option<u32>,
+
+    /// Keyfile needed besides the password to open the store (with `init`: the keyfile to require)
+    #[arg(long, global = true, value_name = "PATH", env = "KEYNEST_KEYFILE")]
+    pub keyfile: Option<std::path::PathBuf>,
+
+    /// Get the key from `keynest agent` at the default socket ($KEYNEST_AGENT_SOCK implies this)
+    #[arg(long = "use-agent", global = true)]
+    pub use_agent: bool,
+
+    /// Take the master password from the OS keychain (see `keynest keychain enable`)
+    #[arg(long = "use-keychain", global = true)]
+    pub use_keychain: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -26,14 +61,72 @@ pub enum Commands {
     Init(InitCommand),
     Get(GetCommand),
     Set(SetCommand),
+    Generate(GenerateCommand),
+    New(NewCommand),
+    Derive(DeriveCommand),
     Update(UpdateCommand),
+    Edit(EditCommand),
     List(ListCommand),
+    Search(SearchCommand),
+    Lookup(LookupCommand),
     Remove(RemoveCommand),
+    #[command(visible_alias = "rename")]
+    Mv(MvCommand),
+    Cp(CpCommand),
+    Pin(PinCommand),
+    Unpin(UnpinCommand),
+    Lease(LeaseCommand),
     Info(InfoCommand),
     Rekey(RekeyCommand),
+    Keyslot(KeyslotCommand),
+    Duress(DuressCommand),
+    Repair(RepairCommand),
+    Nuke(NukeCommand),
+    Backup(BackupCommand),
+    #[command(visible_alias = "run")]
     Exec(ExecCommand),
     Import(ImportCommand),
     Export(ExportCommand),
+    Snapshot(SnapshotCommand),
+    Crypt(CryptCommand),
+    Deps(DepsCommand),
+    Promote(PromoteCommand),
+    Merge(MergeCommand),
+    Resolve(ResolveCommand),
+    Attach(AttachCommand),
+    Template(TemplateCommand),
+    Plan(PlanCommand),
+    Quota(QuotaCommand),
+    Stats(StatsCommand),
+    Audit(AuditCommand),
+    AuditAge(AuditAgeCommand),
+    AuditBreach(AuditBreachCommand),
+    AuditStrength(AuditStrengthCommand),
+    VerifyChain(VerifyChainCommand),
+    Compat(CompatCommand),
+    Compact(CompactCommand),
+    Convert(ConvertCommand),
+    Migrate(MigrateCommand),
+    Totp(TotpCommand),
+    Counter(CounterCommand),
+    Type(TypeCommand),
+    Autotype(AutotypeCommand),
+    Ssh(SshCommand),
+    GpgPreset(GpgPresetCommand),
+    HelpTopics(HelpTopicsCommand),
+    Plugins(PluginsCommand),
+    KeyIndex(KeyIndexCommand),
+    Keychain(KeychainCommand),
+    Api(ApiCommand),
+    Agent(AgentCommand),
+    Completions(CompletionsCommand),
+    #[command(name = completions::COMPLETE_KEYS, hide = true)]
+    CompleteKeys(CompleteKeysCommand),
+    #[command(hide = true)]
+    Dev(DevCommand),
+    /// Runs the `keynest-<name>` plugin found on PATH
+    #[command(external_subcommand)]
+    Plugin(Vec<OsString>),
 }
 
 impl Command for Commands {
@@ -42,14 +135,66 @@ impl Command for Commands {
             Commands::Init(cmd) => cmd.run(store),
             Commands::Get(cmd) => cmd.run(store),
             Commands::Set(cmd) => cmd.run(store),
+            Commands::New(cmd) => cmd.run(store),
+            Commands::Generate(cmd) => cmd.run(store),
+            Commands::Derive(cmd) => cmd.run(store),
             Commands::Update(cmd) => cmd.run(store),
+            Commands::Edit(cmd) => cmd.run(store),
             Commands::List(cmd) => cmd.run(store),
+            Commands::Search(cmd) => cmd.run(store),
+            Commands::Lookup(cmd) => cmd.run(store),
             Commands::Remove(cmd) => cmd.run(store),
+            Commands::Mv(cmd) => cmd.run(store),
+            Commands::Cp(cmd) => cmd.run(store),
+            Commands::Pin(cmd) => cmd.run(store),
+            Commands::Unpin(cmd) => cmd.run(store),
+            Commands::Lease(cmd) => cmd.run(store),
             Commands::Info(cmd) => cmd.run(store),
             Commands::Rekey(cmd) => cmd.run(store),
+            Commands::Keyslot(cmd) => cmd.run(store),
+            Commands::Duress(cmd) => cmd.run(store),
+            Commands::Repair(cmd) => cmd.run(store),
+            Commands::Nuke(cmd) => cmd.run(store),
+            Commands::Backup(cmd) => cmd.run(store),
             Commands::Exec(cmd) => cmd.run(store),
             Commands::Import(cmd) => cmd.run(store),
             Commands::Export(cmd) => cmd.run(store),
+            Commands::Snapshot(cmd) => cmd.run(store),
+            Commands::Crypt(cmd) => cmd.run(store),
+            Commands::Deps(cmd) => cmd.run(store),
+            Commands::Promote(cmd) => cmd.run(store),
+            Commands::Merge(cmd) => cmd.run(store),
+            Commands::Resolve(cmd) => cmd.run(store),
+            Commands::Attach(cmd) => cmd.run(store),
+            Commands::Template(cmd) => cmd.run(store),
+            Commands::Plan(cmd) => cmd.run(store),
+            Commands::Quota(cmd) => cmd.run(store),
+            Commands::Stats(cmd) => cmd.run(store),
+            Commands::Audit(cmd) => cmd.run(store),
+            Commands::AuditAge(cmd) => cmd.run(store),
+            Commands::AuditBreach(cmd) => cmd.run(store),
+            Commands::AuditStrength(cmd) => cmd.run(store),
+            Commands::VerifyChain(cmd) => cmd.run(store),
+            Commands::Compat(cmd) => cmd.run(store),
+            Commands::Compact(cmd) => cmd.run(store),
+            Commands::Convert(cmd) => cmd.run(store),
+            Commands::Migrate(cmd) => cmd.run(store),
+            Commands::Totp(cmd) => cmd.run(store),
+            Commands::Counter(cmd) => cmd.run(store),
+            Commands::Type(cmd) => cmd.run(store),
+            Commands::Autotype(cmd) => cmd.run(store),
+            Commands::Ssh(cmd) => cmd.run(store),
+            Commands::GpgPreset(cmd) => cmd.run(store),
+            Commands::HelpTopics(cmd) => cmd.run(store),
+            Commands::Plugins(cmd) => cmd.run(store),
+            Commands::KeyIndex(cmd) => cmd.run(store),
+            Commands::Keychain(cmd) => cmd.run(store),
+            Commands::Api(cmd) => cmd.run(store),
+            Commands::Agent(cmd) => cmd.run(store),
+            Commands::Completions(cmd) => cmd.run(store),
+            Commands::CompleteKeys(cmd) => cmd.run(store),
+            Commands::Dev(cmd) => cmd.run(store),
+            Commands::Plugin(args) => plugin::run(args, store),
         }
     }
 }
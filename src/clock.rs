@@ -0,0 +1,109 @@
+//! Source of the current time for timestamps and expiry checks.
+//!
+//! Every `updated`/`creation_date` timestamp and every expiry check of a store reads
+//! the time from its [`Clock`], [`SystemClock`] unless one is set with
+//! [`crate::Keynest::set_clock`] or [`crate::InitOptions::with_clock`]. Tests pin the
+//! time with [`FixedClock`]; [`OffsetClock`] simulates a machine whose clock is off,
+//! e.g. to exercise sync between two stores.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that stands still until it is moved with [`FixedClock::set`] or
+/// [`FixedClock::advance`].
+#[derive(Debug)]
+pub struct FixedClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl FixedClock {
+    /// Creates a clock showing `now`.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Sets the time.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+    /// Moves the time forward by `delta` (backward if negative).
+    pub fn advance(&self, delta: TimeDelta) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) += delta;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A clock running `offset` ahead of another one (behind if negative).
+#[derive(Debug)]
+pub struct OffsetClock {
+    inner: Arc<dyn Clock>,
+    offset: TimeDelta,
+}
+
+impl OffsetClock {
+    /// Creates a clock showing the time of `inner` plus `offset`.
+    pub fn new(inner: Arc<dyn Clock>, offset: TimeDelta) -> Self {
+        Self { inner, offset }
+    }
+}
+
+impl Clock for OffsetClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.inner.now() + self.offset
+    }
+}
+
+/// The clock of a store; [`SystemClock`] by default.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedClock(pub(crate) Arc<dyn Clock>);
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self(Arc::new(SystemClock))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_and_offset_clocks() {
+        let start = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let fixed = Arc::new(FixedClock::new(start));
+        let skewed = OffsetClock::new(fixed.clone(), TimeDelta::minutes(-5));
+        assert_eq!(fixed.now(), start);
+        assert_eq!(skewed.now(), start - TimeDelta::minutes(5));
+
+        fixed.advance(TimeDelta::hours(1));
+        assert_eq!(fixed.now(), start + TimeDelta::hours(1));
+        assert_eq!(skewed.now(), start + TimeDelta::minutes(55));
+    }
+}
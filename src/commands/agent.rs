@@ -0,0 +1,1108 @@
+//! `keynest agent`: holds the key of one keystore so other invocations skip the KDF.
+//!
+//! The agent derives the key once (reading the master password as usual) and keeps it
+//! in locked memory, listening on a Unix domain socket (a named pipe on Windows).
+//! Commands ask it for the key when `--use-agent` is given or `KEYNEST_AGENT_SOCK` is
+//! set; if no agent answers or it holds the key of another keystore, they fall back to
+//! the master password. The key is forgotten and the agent exits once it has gone unused
+//! for the timeout, or on `keynest agent --stop`.
+//!
+//! Each connection carries one JSON request line and one JSON response line:
+//!
+//! Request:  `{"version": 1, "token": "<hex>", "op": "key", "store": "/path/keynest.db"}`
+//! Success:  `{"version": 1, "ok": true, "key": "<base64>"}`
+//! Failure:  `{"version": 1, "ok": false, "error": {"code": "wrong_store", "message": "..."}}`
+//!
+//! The other operations are `status` and `stop`. Every request must carry the random
+//! token the agent writes to an owner-only file at start ([`token_path`]), so only
+//! processes of the same user are served; on Unix the agent also checks the peer's uid.
+//!
+//! The agent speaks versions 1 and 2 of the protocol and answers in the version of the
+//! request. Fields are only ever added within a version. Version 2 adds `hello`, which
+//! is answered in any version so clients can negotiate:
+//!
+//! Request:  `{"version": 2, "token": "<hex>", "op": "hello", "capabilities": ["events"]}`
+//! Success:  `{"version": 2, "ok": true, "versions": [1, 2], "capabilities": ["events"], ...}`
+//!
+//! `capabilities` in the response are those of the request the agent offers, or all it
+//! offers if the request lists none. With `--service` the agent offers the operations of
+//! [`service`]; with `--session` it also persists the key for new shells that do not
+//! know about it (see [`session`]).
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use clap::Args;
+use keynest::{Storage, UnlockKey};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{open_keystore, resolve_existing_storage};
+use crate::commands::service;
+use crate::commands::session::{self, SessionStore};
+
+/// Version of the request/response schema.
+const PROTOCOL_VERSION: u64 = 2;
+
+/// Oldest version of the schema the agent still answers, and the one clients send for
+/// the operations it defines.
+const MIN_PROTOCOL_VERSION: u64 = 1;
+
+/// Capabilities of every agent.
+const CAPABILITIES: &[&str] = &["key", "status", "stop"];
+
+/// Capabilities an agent started with `--service` adds: `lock` (`lock`, `unlock`),
+/// `entries` (paginated `list`, `get`), `metrics` and `events` (`subscribe`).
+const SERVICE_CAPABILITIES: &[&str] = &["lock", "entries", "metrics", "events"];
+
+/// How often the agent checks whether the key has gone unused for the timeout.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Environment variable naming the agent's socket (or pipe) for other commands.
+pub const SOCKET_ENV: &str = "KEYNEST_AGENT_SOCK";
+
+/// How long a client waits for the agent to answer.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  eval \"$(keynest agent)\"                 Start an agent and point this shell at it
+  keynest agent --timeout 2h              Keep the key for 2 hours after its last use
+  keynest --use-agent get db/password     Use the agent at the default socket
+  keynest agent --status                  Show which keystore the agent unlocks
+  keynest agent --stop                    Forget the key and stop the agent
+  keynest agent --session 8h              Also let new shells skip the password for 8 hours
+  keynest agent --end-session             End the session before it expires
+  keynest agent --service --timeout 1h    Run as the service layer of a GUI or status bar
+
+The agent asks for the master password once, keeps the derived key in locked memory
+and detaches, printing shell commands that set KEYNEST_AGENT_SOCK. Commands run with
+KEYNEST_AGENT_SOCK set, or with --use-agent, get the key from the agent instead of
+running Argon2 and asking for the password; they fall back to the password if the
+agent is gone or holds the key of another keystore.
+The key is forgotten and the agent exits after --timeout without use. Only processes
+of the same user can use the agent: requests must carry a token from an owner-only
+file next to the socket (under the local app data directory on Windows).
+
+--session is opt-in: it writes the key, wrapped with a random session key, to an
+owner-only file in the per-user runtime directory, so commands in new terminals open
+the store without the password or Argon2 until the session expires, even after the
+agent is gone. Anyone who can read the file (and, with --session-store keychain, your
+OS credential store) can open the store until then. Logging out or rebooting clears
+the runtime directory on most systems.
+
+--service keeps the agent running after --timeout, locked instead of stopped, and lets
+clients of the agent protocol (version 2) lock and unlock it, page through entries,
+read values, fetch metrics and subscribe to change events; see the README.")]
+pub struct AgentCommand {
+    /// Forget the key and exit after this long without use (e.g. 90s, 15m, 2h)
+    #[arg(long, value_name = "DURATION", default_value = "15m", value_parser = parse_timeout)]
+    pub timeout: Duration,
+
+    /// Socket to listen on, or a named pipe on Windows [default: $KEYNEST_AGENT_SOCK or a per-user path]
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<String>,
+
+    /// Stay in the foreground instead of detaching
+    #[arg(long)]
+    pub foreground: bool,
+
+    /// Show whether an agent is running and which keystore it unlocks
+    #[arg(long, conflicts_with_all = ["stop", "foreground"])]
+    pub status: bool,
+
+    /// Forget the key and stop the agent
+    #[arg(long, conflicts_with = "foreground")]
+    pub stop: bool,
+
+    /// Also persist the key for new shells for this long (e.g. 30m, 8h); opt-in
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, conflicts_with_all = ["status", "stop"])]
+    pub session: Option<Duration>,
+
+    /// Where to keep the key that unwraps the session
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STORE",
+        default_value = "file",
+        requires = "session"
+    )]
+    pub session_store: SessionStore,
+
+    /// End the session of the keystore
+    #[arg(long, conflicts_with_all = ["status", "stop", "foreground", "session"])]
+    pub end_session: bool,
+
+    /// Serve entries, metrics and change events to frontends, and lock instead of exiting after --timeout
+    #[arg(long, conflicts_with_all = ["status", "stop", "end_session"])]
+    pub service: bool,
+
+    /// Read the key from stdin instead of deriving it (used when detaching)
+    #[arg(long, hide = true, requires = "foreground")]
+    pub key_stdin: bool,
+}
+
+impl Command for AgentCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let address = match &self.socket {
+            Some(socket) => socket.clone(),
+            None => std::env::var(SOCKET_ENV).or_else(|_| default_address())?,
+        };
+        if self.status {
+            return status(&address, store);
+        }
+        if self.end_session {
+            let storage = resolve_existing_storage(store)?;
+            if !session::end(&canonical(&storage)?)? {
+                eprintln!("no session for {}", storage.path().display());
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("ended the session of {}", storage.path().display());
+            return Ok(ExitCode::SUCCESS);
+        }
+        if self.stop {
+            return match request(&address, json!({"op": "stop"})) {
+                Ok(_) => {
+                    println!("Agent stopped");
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("No agent running at {address}: {e:#}");
+                    Ok(ExitCode::FAILURE)
+                }
+            };
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let store_path = canonical(&storage)?;
+        if request(&address, json!({"op": "status"})).is_ok() {
+            bail!("an agent is already running at {address}; stop it with `keynest agent --stop`");
+        }
+
+        let key = if self.key_stdin {
+            let mut bytes = Zeroizing::new([0u8; 32]);
+            std::io::stdin()
+                .read_exact(&mut *bytes)
+                .context("failed to read the key from stdin")?;
+            UnlockKey::from_bytes(*bytes)
+        } else {
+            let password = auth::unlock(storage.path())?.into_password();
+            open_keystore(password, storage)?.unlock_key()?
+        };
+        if let Some(lifetime) = self.session {
+            let path = session::start(&store_path, &key, lifetime, self.session_store)?;
+            eprintln!(
+                "Session written to {}; it expires in {}",
+                path.display(),
+                format_duration(lifetime)
+            );
+        }
+
+        if self.foreground {
+            let listener = transport::bind(&address)?;
+            let token = write_token(&address)?;
+            if self.key_stdin {
+                // Tells the detaching parent that the agent is ready.
+                println!("ready");
+            } else {
+                print_environment(&address, std::process::id());
+            }
+            std::io::stdout().flush()?;
+            let config = Config {
+                address,
+                token,
+                store: store_path,
+                timeout: self.timeout,
+                service: self.service,
+            };
+            serve(listener, config, key)
+        } else {
+            detach(&address, &store_path, &key, self.timeout, self.service)
+        }
+    }
+}
+
+/// Parses `--timeout`: a number of seconds, or a number followed by `s`, `m` or `h`.
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid timeout '{s}' (use e.g. 90s, 15m or 2h)");
+    let (count, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => Some(count),
+        "m" => count.checked_mul(60),
+        "h" => count.checked_mul(3600),
+        _ => None,
+    };
+    match seconds {
+        Some(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Prints shell commands that point other commands at the agent, like `ssh-agent`.
+fn print_environment(address: &str, pid: u32) {
+    println!("{SOCKET_ENV}={address}; export {SOCKET_ENV};");
+    println!("echo Agent pid {pid};");
+}
+
+fn status(address: &str, store: Option<PathBuf>) -> Result<ExitCode> {
+    let session = crate::commands::common::resolve_storage(store)
+        .ok()
+        .and_then(|storage| Some((canonical(&storage).ok()?, storage)))
+        .and_then(|(store, storage)| Some((session::remaining(&store)?, storage)));
+    if let Some((left, storage)) = &session {
+        println!(
+            "Session of {} expires in {}",
+            storage.path().display(),
+            format_duration(*left)
+        );
+    }
+
+    match request(address, json!({"op": "status"})) {
+        Ok(response) => {
+            let store = response["store"].as_str().unwrap_or_default();
+            let expires_in = response["expires_in"].as_u64().unwrap_or_default();
+            let kind = if response["service"] == true {
+                "Service"
+            } else {
+                "Agent"
+            };
+            println!("{kind} at {address} unlocks {store}");
+            if response["locked"] == true {
+                println!("Locked; unlock it through the agent protocol");
+            } else {
+                println!(
+                    "Key expires in {}m {}s without use",
+                    expires_in / 60,
+                    expires_in % 60
+                );
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(e) => {
+            println!("No agent running at {address} ({e:#})");
+            Ok(if session.is_some() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
+        }
+    }
+}
+
+/// Formats `duration` as hours and minutes, like `7h 59m`.
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs().div_ceil(60);
+    match minutes / 60 {
+        0 => format!("{minutes}m"),
+        hours => format!("{hours}h {}m", minutes % 60),
+    }
+}
+
+/// Runs the agent as a detached child that gets the key on its stdin, then prints the
+/// environment once it listens.
+fn detach(
+    address: &str,
+    store: &Path,
+    key: &UnlockKey,
+    timeout: Duration,
+    service: bool,
+) -> Result<ExitCode> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .arg("--store")
+        .arg(store)
+        .args(["agent", "--foreground", "--key-stdin", "--socket", address])
+        .arg("--timeout")
+        .arg(format!("{}s", timeout.as_secs()));
+    if service {
+        command.arg("--service");
+    }
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Keep terminal signals such as Ctrl-C from reaching the agent.
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let mut child = command.spawn().context("failed to start the agent")?;
+    let mut stdin = child.stdin.take().context("agent stdin unavailable")?;
+    stdin.write_all(key.as_bytes())?;
+    drop(stdin);
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().context("agent stdout unavailable")?)
+        .read_line(&mut line)?;
+    if line.trim() != "ready" {
+        let _ = child.kill();
+        bail!("the agent failed to start");
+    }
+    print_environment(address, child.id());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Returns the absolute, canonical path of the keystore, which identifies it to the agent.
+fn canonical(storage: &Storage) -> Result<PathBuf> {
+    std::fs::canonicalize(storage.path())
+        .with_context(|| format!("cannot resolve {}", storage.path().display()))
+}
+
+/// The key held by the agent, in memory that is kept out of swap where the OS allows it.
+struct LockedKey(Box<UnlockKey>);
+
+impl LockedKey {
+    fn new(key: UnlockKey) -> Self {
+        let key = Box::new(key);
+        memory::lock(key.as_bytes());
+        Self(key)
+    }
+}
+
+impl Drop for LockedKey {
+    fn drop(&mut self) {
+        memory::unlock(self.0.as_bytes());
+    }
+}
+
+/// What the agent serves, shared with its expiry watchdog and subscriptions.
+struct State {
+    key: Option<LockedKey>,
+    deadline: Instant,
+}
+
+/// How the agent was started.
+struct Config {
+    address: String,
+    token: Zeroizing<String>,
+    store: PathBuf,
+    timeout: Duration,
+    service: bool,
+}
+
+impl Config {
+    fn capabilities(&self) -> impl Iterator<Item = &'static str> {
+        let service = if self.service {
+            SERVICE_CAPABILITIES
+        } else {
+            &[]
+        };
+        CAPABILITIES.iter().chain(service).copied()
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    version: u64,
+    token: String,
+    #[serde(flatten)]
+    op: Op,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Op {
+    Hello {
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+    Key {
+        store: PathBuf,
+    },
+    Status,
+    Stop,
+    Lock,
+    Unlock {
+        password: String,
+    },
+    List {
+        prefix: Option<String>,
+        cursor: Option<String>,
+        limit: Option<usize>,
+    },
+    Get {
+        key: String,
+        #[serde(default = "default_true")]
+        resolve: bool,
+    },
+    Metrics,
+    Subscribe,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn lock_state(state: &Mutex<State>) -> std::sync::MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn serve(listener: transport::Listener, config: Config, key: UnlockKey) -> Result<ExitCode> {
+    memory::harden();
+    let state = Arc::new(Mutex::new(State {
+        key: Some(LockedKey::new(key)),
+        deadline: Instant::now() + config.timeout,
+    }));
+
+    let watchdog = Arc::clone(&state);
+    let watched = config.address.clone();
+    let service = config.service;
+    std::thread::spawn(move || {
+        loop {
+            let expired = {
+                let state = lock_state(&watchdog);
+                state.key.is_some() && Instant::now() >= state.deadline
+            };
+            if expired {
+                if !service {
+                    shut_down(&watchdog, &watched);
+                }
+                lock_state(&watchdog).key = None;
+            }
+            std::thread::sleep(WATCHDOG_INTERVAL);
+        }
+    });
+
+    loop {
+        let Ok(mut stream) = listener.accept() else {
+            continue;
+        };
+        let (version, op) = match read_request(&mut stream, &config.token) {
+            Ok(request) => request,
+            Err(e) => {
+                let _ = send(
+                    &mut stream,
+                    &error(PROTOCOL_VERSION, "unauthorized", format!("{e:#}")),
+                );
+                continue;
+            }
+        };
+        let response = match op {
+            Op::Hello { capabilities } => {
+                let offered: Vec<_> = config
+                    .capabilities()
+                    .filter(|c| capabilities.is_empty() || capabilities.iter().any(|r| r == c))
+                    .collect();
+                json!({
+                    "version": version,
+                    "ok": true,
+                    "versions": (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).collect::<Vec<_>>(),
+                    "capabilities": offered,
+                    "keynest": env!("CARGO_PKG_VERSION"),
+                    "store": config.store.display().to_string(),
+                })
+            }
+            Op::Key { store: requested } => {
+                let mut state = lock_state(&state);
+                if requested != config.store {
+                    error(
+                        version,
+                        "wrong_store",
+                        format!("the agent unlocks {}", config.store.display()),
+                    )
+                } else if let Some(key) = &state.key {
+                    let encoded = Zeroizing::new(STANDARD.encode(key.0.as_bytes()));
+                    state.deadline = Instant::now() + config.timeout;
+                    json!({"version": version, "ok": true, "key": *encoded})
+                } else {
+                    error(version, "locked", "the agent no longer holds the key")
+                }
+            }
+            Op::Status => {
+                let state = lock_state(&state);
+                let left = state.deadline.saturating_duration_since(Instant::now());
+                json!({
+                    "version": version,
+                    "ok": true,
+                    "store": config.store.display().to_string(),
+                    "expires_in": left.as_secs(),
+                    "locked": state.key.is_none(),
+                    "service": config.service,
+                })
+            }
+            Op::Stop => {
+                let _ = send(&mut stream, &json!({"version": version, "ok": true}));
+                shut_down(&state, &config.address);
+            }
+            _ if !config.service => error(
+                version,
+                "unsupported",
+                "the agent was not started with --service",
+            ),
+            Op::Lock => {
+                lock_state(&state).key = None;
+                json!({"version": version, "ok": true})
+            }
+            Op::Unlock { mut password } => {
+                let result = open_keystore(
+                    Zeroizing::new(std::mem::take(&mut password)),
+                    Storage::new(config.store.clone()),
+                )
+                .and_then(|kn| kn.unlock_key());
+                match result {
+                    Ok(key) => {
+                        let mut state = lock_state(&state);
+                        state.key = Some(LockedKey::new(key));
+                        state.deadline = Instant::now() + config.timeout;
+                        json!({"version": version, "ok": true})
+                    }
+                    Err(e) => error(version, service::error_code(&e), format!("{e:#}")),
+                }
+            }
+            Op::Subscribe => {
+                if send(&mut stream, &json!({"version": version, "ok": true})).is_ok() {
+                    let state = Arc::clone(&state);
+                    let store = config.store.clone();
+                    std::thread::spawn(move || {
+                        service::subscribe(stream, store, version, || {
+                            lock_state(&state).key.is_none()
+                        });
+                    });
+                }
+                continue;
+            }
+            op => {
+                let mut state = lock_state(&state);
+                let Some(key) = &state.key else {
+                    let _ = send(
+                        &mut stream,
+                        &error(version, "locked", "the agent is locked"),
+                    );
+                    continue;
+                };
+                let result = service::open(&config.store, &key.0).and_then(|mut kn| match op {
+                    Op::List {
+                        prefix,
+                        cursor,
+                        limit,
+                    } => service::list(
+                        &kn,
+                        prefix.as_deref(),
+                        cursor.as_deref(),
+                        limit.unwrap_or(service::DEFAULT_PAGE),
+                    ),
+                    Op::Get { key, resolve } => service::get(&mut kn, &key, resolve),
+                    Op::Metrics => Ok(serde_json::to_value(kn.metrics()?)?),
+                    _ => unreachable!("handled above"),
+                });
+                state.deadline = Instant::now() + config.timeout;
+                match result {
+                    Ok(result) => json!({"version": version, "ok": true, "result": result}),
+                    Err(e) => error(version, service::error_code(&e), format!("{e:#}")),
+                }
+            }
+        };
+        let _ = send(&mut stream, &response);
+    }
+}
+
+fn error(version: u64, code: &str, message: impl Into<String>) -> Value {
+    json!({
+        "version": version,
+        "ok": false,
+        "error": {"code": code, "message": message.into()},
+    })
+}
+
+/// Reads and authenticates one request, returning its version and operation.
+fn read_request(stream: &mut transport::Stream, token: &str) -> Result<(u64, Op)> {
+    if !transport::same_user(stream) {
+        bail!("the client runs as another user");
+    }
+    let mut line = Zeroizing::new(String::new());
+    BufReader::new(&mut *stream)
+        .take(64 * 1024)
+        .read_line(&mut line)?;
+    let mut request: Request = serde_json::from_str(&line).context("malformed request")?;
+    let authorized = constant_time_eq(request.token.as_bytes(), token.as_bytes());
+    request.token.zeroize();
+    if !authorized {
+        bail!("invalid token");
+    }
+    match request.op {
+        // Answered in any version, so clients can find one both sides speak.
+        Op::Hello { .. } => Ok((
+            request
+                .version
+                .clamp(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION),
+            request.op,
+        )),
+        _ if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&request.version) => {
+            Ok((request.version, request.op))
+        }
+        _ => bail!("unsupported protocol version {}", request.version),
+    }
+}
+
+fn send(stream: &mut transport::Stream, response: &Value) -> Result<()> {
+    let mut line = Zeroizing::new(serde_json::to_string(response)?);
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Forgets the key, removes the socket and token files, and exits.
+fn shut_down(state: &Mutex<State>, address: &str) -> ! {
+    state.lock().unwrap_or_else(|e| e.into_inner()).key = None;
+    if let Ok(path) = token_path(address) {
+        let _ = std::fs::remove_file(path);
+    }
+    transport::remove(address);
+    std::process::exit(0)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Creates a new random token and writes it to the owner-only [`token_path`].
+fn write_token(address: &str) -> Result<Zeroizing<String>> {
+    let mut bytes = Zeroizing::new([0u8; 32]);
+    getrandom::fill(&mut *bytes).map_err(|_| anyhow::anyhow!("OS random generator unavailable"))?;
+    let token: Zeroizing<String> =
+        Zeroizing::new(bytes.iter().map(|b| format!("{b:02x}")).collect());
+    let path = token_path(address)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    crate::commands::common::write_file_secure(&path, token.as_bytes())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(token)
+}
+
+/// Returns the file holding the token of the agent at `address`: next to the socket on
+/// Unix, under the local app data directory on Windows (pipes have no directory).
+fn token_path(address: &str) -> Result<PathBuf> {
+    if cfg!(windows) {
+        let name = address.rsplit(['\\', '/']).next().unwrap_or(address);
+        Ok(runtime_dir()?.join(format!("{name}.token")))
+    } else {
+        Ok(PathBuf::from(format!("{address}.token")))
+    }
+}
+
+/// Returns the default agent address: a socket in a private per-user directory, or a
+/// per-user pipe name on Windows.
+fn default_address() -> Result<String> {
+    #[cfg(windows)]
+    {
+        let user = std::env::var("USERNAME").unwrap_or_else(|_| "default".to_string());
+        Ok(format!(r"\\.\pipe\keynest-agent-{user}"))
+    }
+    #[cfg(not(windows))]
+    {
+        let socket = runtime_dir()?.join("agent.sock");
+        socket
+            .into_os_string()
+            .into_string()
+            .map_err(|_| anyhow::anyhow!("agent socket path is not valid UTF-8"))
+    }
+}
+
+/// Returns the private per-user directory of the agent's socket and sessions:
+/// `$XDG_RUNTIME_DIR/keynest` (or a per-user temporary directory) on Unix, the local
+/// app data directory on Windows.
+///
+/// # Errors
+///
+/// Returns an error if the platform directories cannot be determined.
+pub fn runtime_dir() -> Result<PathBuf> {
+    #[cfg(windows)]
+    {
+        let dirs = directories::ProjectDirs::from("", "", "keynest")
+            .context("could not determine platform directories")?;
+        Ok(dirs.data_local_dir().join("agent"))
+    }
+    #[cfg(not(windows))]
+    {
+        Ok(match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(runtime) => PathBuf::from(runtime).join("keynest"),
+            None => std::env::temp_dir().join(format!("keynest-{}", transport::uid())),
+        })
+    }
+}
+
+/// Agent address selected for this invocation, if the agent should be used.
+static SELECTED: OnceLock<Option<(String, bool)>> = OnceLock::new();
+
+/// Selects the agent for this invocation: `$KEYNEST_AGENT_SOCK` if set, else the
+/// default address with `--use-agent`.
+///
+/// # Errors
+///
+/// Returns an error if `--use-agent` is given and the default address cannot be
+/// determined.
+pub fn select(use_agent: bool) -> Result<()> {
+    let address = match std::env::var(SOCKET_ENV) {
+        Ok(address) if !address.is_empty() => Some((address, use_agent)),
+        _ if use_agent => Some((default_address()?, true)),
+        _ => None,
+    };
+    let _ = SELECTED.set(address);
+    Ok(())
+}
+
+/// Asks the selected agent for the key of `storage`, falling back to its session (see
+/// [`session`]). Returns `None` if neither can provide the key; with `--use-agent` the
+/// reason the agent could not is printed.
+pub fn key_for(storage: &Storage) -> Option<UnlockKey> {
+    from_agent(storage).or_else(|| session::key_for(storage))
+}
+
+fn from_agent(storage: &Storage) -> Option<UnlockKey> {
+    let (address, explicit) = SELECTED.get()?.as_ref()?;
+    let result = canonical(storage)
+        .and_then(|store| request(address, json!({"op": "key", "store": store})))
+        .and_then(|mut response| {
+            let mut encoded = response["key"].take();
+            let decoded = encoded
+                .as_str()
+                .map(|s| Zeroizing::new(STANDARD.decode(s).unwrap_or_default()));
+            if let Value::String(s) = &mut encoded {
+                s.zeroize();
+            }
+            let bytes: [u8; 32] = decoded
+                .as_deref()
+                .and_then(|bytes| bytes.as_slice().try_into().ok())
+                .context("malformed key from the agent")?;
+            Ok(UnlockKey::from_bytes(bytes))
+        });
+    match result {
+        Ok(key) => Some(key),
+        Err(e) => {
+            if *explicit {
+                eprintln!("Warning: cannot use the agent at {address}: {e:#}");
+            }
+            None
+        }
+    }
+}
+
+/// Sends one request to the agent at `address` and returns its successful response.
+fn request(address: &str, mut request: Value) -> Result<Value> {
+    let token = Zeroizing::new(
+        std::fs::read_to_string(token_path(address)?).context("no agent token found")?,
+    );
+    // The client only uses operations of version 1, which agents of every version know.
+    request["version"] = json!(MIN_PROTOCOL_VERSION);
+    request["token"] = json!(token.trim());
+    let mut line = Zeroizing::new(serde_json::to_string(&request)?);
+    line.push('\n');
+    if let Value::String(s) = &mut request["token"] {
+        s.zeroize();
+    }
+
+    let mut stream = transport::connect(address, CLIENT_TIMEOUT)?;
+    stream.write_all(line.as_bytes())?;
+    stream.flush()?;
+    let mut response = Zeroizing::new(String::new());
+    BufReader::new(stream).read_line(&mut response)?;
+    let response: Value = serde_json::from_str(&response).context("malformed response")?;
+    if response["ok"].as_bool() != Some(true) {
+        let message = response["error"]["message"]
+            .as_str()
+            .unwrap_or("request failed");
+        bail!("{message}");
+    }
+    Ok(response)
+}
+
+#[cfg(unix)]
+mod transport {
+    use anyhow::{Context, Result, bail};
+    use std::io::ErrorKind;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub(super) type Stream = UnixStream;
+
+    pub(super) struct Listener(UnixListener);
+
+    impl Listener {
+        pub(super) fn accept(&self) -> std::io::Result<Stream> {
+            let (stream, _) = self.0.accept()?;
+            // A client that stops mid-request must not block the agent.
+            stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+            Ok(stream)
+        }
+    }
+
+    pub(super) fn uid() -> u32 {
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        unsafe { libc::geteuid() }
+    }
+
+    /// Listens at `address`, creating its directory owner-only and replacing a stale
+    /// socket left by an agent that did not shut down.
+    pub(super) fn bind(address: &str) -> Result<Listener> {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+        let path = Path::new(address);
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            if !dir.exists() {
+                std::fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)?;
+            }
+            let meta = std::fs::metadata(dir)?;
+            if meta.uid() != uid() || meta.permissions().mode() & 0o022 != 0 {
+                bail!(
+                    "{} must be owned by the current user and not writable by others",
+                    dir.display()
+                );
+            }
+        }
+        match UnixStream::connect(path) {
+            Ok(_) => bail!("an agent is already listening at {address}"),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+            Err(_) => {}
+        }
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("cannot listen at {address}"))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(Listener(listener))
+    }
+
+    pub(super) fn connect(address: &str, timeout: Duration) -> Result<Stream> {
+        let stream =
+            UnixStream::connect(address).with_context(|| format!("cannot connect to {address}"))?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(stream)
+    }
+
+    pub(super) fn remove(address: &str) {
+        let _ = std::fs::remove_file(address);
+    }
+
+    /// Returns `true` if the peer of `stream` runs as the current user.
+    pub(super) fn same_user(stream: &Stream) -> bool {
+        peer_uid(stream) == Some(uid())
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn peer_uid(stream: &Stream) -> Option<u32> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `cred` and `len` are valid for writes of the sizes given.
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        (rc == 0).then_some(cred.uid)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn peer_uid(stream: &Stream) -> Option<u32> {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: `uid` and `gid` are valid for writes.
+        let rc = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+        (rc == 0).then_some(uid)
+    }
+}
+
+#[cfg(windows)]
+mod transport {
+    use anyhow::{Context, Result, bail};
+    use std::fs::File;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::FromRawHandle;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use windows_sys::Win32::Foundation::{
+        ERROR_PIPE_CONNECTED, GetLastError, INVALID_HANDLE_VALUE,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+    };
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    pub(super) type Stream = File;
+
+    pub(super) struct Listener {
+        name: Vec<u16>,
+        first: AtomicBool,
+    }
+
+    impl Listener {
+        /// Creates a pipe instance and waits for a client to connect to it.
+        pub(super) fn accept(&self) -> std::io::Result<Stream> {
+            let first = self.first.swap(false, Ordering::SeqCst);
+            let open_mode = PIPE_ACCESS_DUPLEX
+                | if first {
+                    FILE_FLAG_FIRST_PIPE_INSTANCE
+                } else {
+                    0
+                };
+            // SAFETY: `name` is a NUL-terminated UTF-16 string that outlives the call.
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    self.name.as_ptr(),
+                    open_mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    4096,
+                    4096,
+                    0,
+                    std::ptr::null(),
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(std::io::Error::last_os_error());
+            }
+            // SAFETY: the handle was just created and is owned by the returned file.
+            let file = unsafe { File::from_raw_handle(handle as _) };
+            // SAFETY: the handle is a valid pipe instance; no overlapped I/O is used.
+            let connected = unsafe { ConnectNamedPipe(handle, std::ptr::null_mut()) };
+            // SAFETY: GetLastError has no preconditions.
+            if connected == 0 && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(file)
+        }
+    }
+
+    pub(super) fn bind(address: &str) -> Result<Listener> {
+        if !address.starts_with(r"\\.\pipe\") {
+            bail!(r"the agent address must be a pipe name like \\.\pipe\keynest-agent");
+        }
+        Ok(Listener {
+            name: std::ffi::OsStr::new(address)
+                .encode_wide()
+                .chain(Some(0))
+                .collect(),
+            first: AtomicBool::new(true),
+        })
+    }
+
+    pub(super) fn connect(address: &str, _timeout: Duration) -> Result<Stream> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(address)
+            .with_context(|| format!("cannot connect to {address}"))
+    }
+
+    pub(super) fn remove(_address: &str) {}
+
+    /// Remote clients are rejected by the pipe; local ones are checked by the token.
+    pub(super) fn same_user(_stream: &Stream) -> bool {
+        true
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod transport {
+    use anyhow::{Result, bail};
+    use std::time::Duration;
+
+    pub(super) type Stream = std::fs::File;
+
+    pub(super) struct Listener;
+
+    impl Listener {
+        pub(super) fn accept(&self) -> std::io::Result<Stream> {
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    pub(super) fn bind(_address: &str) -> Result<Listener> {
+        bail!("keynest agent is only available on Unix and Windows")
+    }
+
+    pub(super) fn connect(_address: &str, _timeout: Duration) -> Result<Stream> {
+        bail!("keynest agent is only available on Unix and Windows")
+    }
+
+    pub(super) fn remove(_address: &str) {}
+
+    pub(super) fn same_user(_stream: &Stream) -> bool {
+        false
+    }
+}
+
+/// Keeps the key out of swap and core dumps where the OS allows it; failures are
+/// ignored since the key stays protected by the process boundary either way.
+mod memory {
+    #[cfg(unix)]
+    pub(super) fn lock(bytes: &[u8]) {
+        // SAFETY: the range is a live allocation for as long as it stays locked.
+        unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) };
+    }
+
+    #[cfg(unix)]
+    pub(super) fn unlock(bytes: &[u8]) {
+        // SAFETY: unlocking a locked range of a live allocation.
+        unsafe { libc::munlock(bytes.as_ptr().cast(), bytes.len()) };
+    }
+
+    #[cfg(windows)]
+    pub(super) fn lock(bytes: &[u8]) {
+        // SAFETY: the range is a live allocation for as long as it stays locked.
+        unsafe {
+            windows_sys::Win32::System::Memory::VirtualLock(bytes.as_ptr().cast(), bytes.len())
+        };
+    }
+
+    #[cfg(windows)]
+    pub(super) fn unlock(bytes: &[u8]) {
+        // SAFETY: unlocking a locked range of a live allocation.
+        unsafe {
+            windows_sys::Win32::System::Memory::VirtualUnlock(bytes.as_ptr().cast(), bytes.len())
+        };
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub(super) fn lock(_bytes: &[u8]) {}
+
+    #[cfg(not(any(unix, windows)))]
+    pub(super) fn unlock(_bytes: &[u8]) {}
+
+    /// Keeps other processes of the user from attaching to the agent or reading its
+    /// memory through a core dump.
+    pub(super) fn harden() {
+        #[cfg(target_os = "linux")]
+        // SAFETY: PR_SET_DUMPABLE only changes a flag of the calling process.
+        unsafe {
+            libc::prctl(libc::PR_SET_DUMPABLE, 0);
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! `keynest api`: one JSON request in, one JSON response out.
+//!
+//! Wrappers in other languages target this instead of the human-oriented output of the
+//! other commands. Requests and responses carry the schema version ([`API_VERSION`]);
+//! fields and operations are only ever added within a version.
+//!
+//! The API covers reading and editing entries, not every command: store administration
+//! (rekey, keyslots, duress, backups, compat, migrate, convert, journal, chain), history,
+//! attachments, leases, sharing and the external integrations stay CLI-only for now.
+//! Clients check the `operations` of `{"op": "version"}` before relying on one.
+//!
+//! Request:  `{"version": 1, "op": "get", "key": "db/password"}`
+//! Success:  `{"version": 1, "ok": true, "result": ...}`
+//! Failure:  `{"version": 1, "ok": false, "error": {"code": "not_found", "message": "..."}}`
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Args;
+use keynest::{
+    Cancelled, Corrupted, ExportFormat, ImportPolicy, KdfParams, Keynest, NoMatchingKeyslot,
+    StoreError, WrongPassword,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{
+    parse_expiry, resolve_existing_storage, resolve_storage, unlock_keystore,
+};
+
+/// Version of the request/response schema.
+const API_VERSION: u64 = 1;
+
+/// Operations understood by this version, as reported by `{"op": "version"}`.
+const OPERATIONS: &[&str] = &[
+    "version",
+    "init",
+    "info",
+    "metrics",
+    "list",
+    "get",
+    "set",
+    "update",
+    "remove",
+    "rename",
+    "copy",
+    "tag",
+    "set_field",
+    "remove_field",
+    "expire",
+    "totp",
+    "import",
+    "export",
+];
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = r#"Examples:
+  keynest api '{"version":1,"op":"version"}'                         Show the API version and operations
+  keynest api '{"version":1,"op":"get","key":"db/password"}'         Read a secret
+  keynest api '{"version":1,"op":"set","key":"token","value":"s3"}'  Store a secret
+  echo '{"version":1,"op":"list"}' | keynest api -                   Read the request from stdin
+
+Operations: version, init, info, metrics, list [prefix], get key [resolve], set key value,
+update key value, remove key, rename from to [overwrite], copy from to [overwrite],
+tag key [add] [remove], set_field key name value, remove_field key name,
+expire key expires, totp key, import entries [overwrite], export format [prefix].
+Other commands (rekey, keyslot, backup, attach, history, ...) have no operation yet.
+The response is a single JSON line; the exit status is 0 if "ok" is true, 1 otherwise.
+The password is read as for every other command (KEYNEST_PASSWORD, --password-fd, prompt)."#
+)]
+pub struct ApiCommand {
+    /// JSON request, or '-' to read it from stdin
+    pub request: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Version,
+    Init,
+    Info,
+    Metrics,
+    List {
+        prefix: Option<String>,
+    },
+    Get {
+        key: String,
+        #[serde(default = "default_true")]
+        resolve: bool,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    Update {
+        key: String,
+        value: String,
+    },
+    Remove {
+        key: String,
+    },
+    Rename {
+        from: String,
+        to: String,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    Copy {
+        from: String,
+        to: String,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    Tag {
+        key: String,
+        #[serde(default)]
+        add: Vec<String>,
+        #[serde(default)]
+        remove: Vec<String>,
+    },
+    SetField {
+        key: String,
+        name: String,
+        value: String,
+    },
+    RemoveField {
+        key: String,
+        name: String,
+    },
+    Expire {
+        key: String,
+        expires: Option<String>,
+    },
+    Totp {
+        key: String,
+    },
+    Import {
+        entries: BTreeMap<String, String>,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    Export {
+        format: String,
+        prefix: Option<String>,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A failed request: a stable machine-readable code plus a human-readable message.
+struct ApiError {
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        let code = if e.is::<Cancelled>() {
+            "cancelled"
+        } else if e.is::<WrongPassword>() || e.is::<NoMatchingKeyslot>() {
+            "wrong_password"
+        } else if e.is::<Corrupted>() {
+            "corrupted"
+        } else {
+            match e.downcast_ref::<StoreError>() {
+                Some(StoreError::KeyNotFound(_)) => "not_found",
+                Some(StoreError::KeyAlreadyExists(_)) => "already_exists",
+                Some(StoreError::ReservedKey(_)) => "reserved_key",
+                Some(StoreError::InvalidKey { .. }) => "invalid_key",
+                Some(StoreError::ReferenceCycle(_) | StoreError::BrokenReference { .. }) => {
+                    "invalid_reference"
+                }
+                Some(StoreError::QuotaExceeded(_)) => "quota_exceeded",
+                _ => "failed",
+            }
+        };
+        Self::new(code, format!("{e:#}"))
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+impl Command for ApiCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let response = match handle(&self.request, store) {
+            Ok(result) => json!({"version": API_VERSION, "ok": true, "result": result}),
+            Err(e) => json!({
+                "version": API_VERSION,
+                "ok": false,
+                "error": {"code": e.code, "message": e.message},
+            }),
+        };
+        let failed = response["ok"] == false;
+        println!("{response}");
+
+        Ok(if failed {
+            ExitCode::from(1)
+        } else {
+            ExitCode::SUCCESS
+        })
+    }
+}
+
+fn handle(request: &str, store: Option<PathBuf>) -> Result<Value, ApiError> {
+    let request = if request == "-" {
+        auth::read_stdin_to_string()?
+    } else {
+        Zeroizing::new(request.to_string())
+    };
+    let request: Value = serde_json::from_str(&request)
+        .map_err(|e| ApiError::new("bad_request", format!("invalid JSON: {e}")))?;
+
+    match request.get("version").and_then(Value::as_u64) {
+        Some(API_VERSION) => {}
+        Some(v) => {
+            return Err(ApiError::new(
+                "unsupported_version",
+                format!("API version {v} is not supported (this keynest speaks {API_VERSION})"),
+            ));
+        }
+        None => return Err(ApiError::new("bad_request", "missing \"version\"")),
+    }
+    let request =
+        Request::deserialize(&request).map_err(|e| ApiError::new("bad_request", e.to_string()))?;
+
+    match request {
+        Request::Version => Ok(json!({
+            "api": API_VERSION,
+            "keynest": env!("CARGO_PKG_VERSION"),
+            "operations": OPERATIONS,
+        })),
+        Request::Init => {
+            let storage = resolve_storage(store)?;
+            if storage.exists() {
+                return Err(ApiError::new(
+                    "already_exists",
+                    format!("keystore already exists: {}", storage.path().display()),
+                ));
+            }
+            let password = auth::read_password()?;
+            Keynest::init_with_storage_and_kdf(password, storage, KdfParams::default())?;
+            Ok(Value::Null)
+        }
+        Request::Info => {
+            Ok(serde_json::to_value(open(store)?.info()?).map_err(anyhow::Error::from)?)
+        }
+        Request::Metrics => {
+            Ok(serde_json::to_value(open(store)?.metrics()?).map_err(anyhow::Error::from)?)
+        }
+        Request::List { prefix } => {
+            let kn = open(store)?;
+            let entries: Vec<_> = kn
+                .list_all()?
+                .iter()
+                .filter(|e| prefix.as_ref().is_none_or(|p| e.key().starts_with(p)))
+                .map(|e| {
+                    json!({
+                        "key": e.key(),
+                        "kind": e.kind().to_string(),
+                        "updated": e.updated(),
+                        "tags": e.tags(),
+                        "expires": e.expires().map(format_time),
+                    })
+                })
+                .collect();
+            Ok(Value::Array(entries))
+        }
+        Request::Get { key, resolve } => {
+            let mut kn = open(store)?;
+            check_unrestricted(&kn, &key)?;
+            let value = if resolve {
+                kn.resolve(&key)?
+            } else {
+                kn.get(&key)?
+            };
+            let Some(value) = value else {
+                return Err(StoreError::KeyNotFound(key).into());
+            };
+            let result = json!({
+                "key": key,
+                "value": value,
+                "kind": kn.kind(&key)?.map(|k| k.to_string()),
+                "tags": kn.tags(&key)?,
+                "fields": kn.fields(&key)?,
+                "expires": kn.expires(&key)?.map(format_time),
+            });
+            if kn.records_reads()? {
+                kn.record_get(&key)?;
+                kn.save_usage()?;
+            }
+            Ok(result)
+        }
+        Request::Set { key, value } => {
+            let mut kn = open(store)?;
+            kn.set(&key, &value)?;
+            kn.save()?;
+            Ok(Value::Null)
+        }
+        Request::Update { key, value } => {
+            let mut kn = open(store)?;
+            kn.update(&key, &value)?;
+            kn.save()?;
+            Ok(Value::Null)
+        }
+        Request::Remove { key } => {
+            let mut kn = open(store)?;
+            kn.remove(&key)?;
+            kn.save()?;
+            kn.purge_attachments()?;
+            Ok(Value::Null)
+        }
+        Request::Rename {
+            from,
+            to,
+            overwrite,
+        } => {
+            let mut kn = open(store)?;
+            let summary = kn.rename(&from, &to, overwrite)?;
+            kn.save()?;
+            let renamed: Vec<_> = summary
+                .renamed()
+                .iter()
+                .map(|(from, to)| json!({"from": from, "to": to}))
+                .collect();
+            Ok(json!({ "renamed": renamed, "replaced": summary.replaced() }))
+        }
+        Request::Copy {
+            from,
+            to,
+            overwrite,
+        } => {
+            let mut kn = open(store)?;
+            kn.copy(&from, &to, overwrite)?;
+            kn.save()?;
+            Ok(Value::Null)
+        }
+        Request::Tag { key, add, remove } => {
+            let mut kn = open(store)?;
+            for tag in &add {
+                kn.add_tag(&key, tag)?;
+            }
+            for tag in &remove {
+                kn.remove_tag(&key, tag)?;
+            }
+            kn.save()?;
+            Ok(json!({ "tags": kn.tags(&key)? }))
+        }
+        Request::SetField { key, name, value } => {
+            let mut kn = open(store)?;
+            kn.set_field(&key, &name, &value)?;
+            kn.save()?;
+            Ok(Value::Null)
+        }
+        Request::RemoveField { key, name } => {
+            let mut kn = open(store)?;
+            let removed = kn.remove_field(&key, &name)?;
+            kn.save()?;
+            Ok(json!({ "removed": removed }))
+        }
+        Request::Expire { key, expires } => {
+            let expires = expires
+                .as_deref()
+                .map(parse_expiry)
+                .transpose()
+                .map_err(|e| ApiError::new("bad_request", format!("{e:#}")))?;
+            let mut kn = open(store)?;
+            kn.set_expiry(&key, expires)?;
+            kn.save()?;
+            Ok(json!({ "expires": expires.map(format_time) }))
+        }
+        Request::Totp { key } => {
+            let kn = open(store)?;
+            check_unrestricted(&kn, &key)?;
+            let Some(code) = kn.totp(&key)? else {
+                return Err(StoreError::KeyNotFound(key).into());
+            };
+            Ok(json!({
+                "key": key,
+                "code": code.code(),
+                "remaining_secs": code.remaining_secs(),
+            }))
+        }
+        Request::Import { entries, overwrite } => {
+            let policy = if overwrite {
+                ImportPolicy::Overwrite
+            } else {
+                ImportPolicy::SkipExisting
+            };
+            let mut kn = open(store)?;
+            let summary = kn.import_entries(entries.iter(), policy)?;
+            kn.save()?;
+            Ok(json!({
+                "created": summary.created(),
+                "updated": summary.updated(),
+                "skipped": summary.skipped(),
+            }))
+        }
+        Request::Export { format, prefix } => {
+            let format = ExportFormat::from_extension(&format).ok_or_else(|| {
+                ApiError::new("bad_request", format!("unknown export format '{format}'"))
+            })?;
+            let kn = open(store)?;
+            for key in kn.list() {
+                if prefix.as_ref().is_none_or(|p| key.starts_with(p)) {
+                    check_unrestricted(&kn, key)?;
+                }
+            }
+            let output = kn.export(format, prefix.as_deref())?;
+            Ok(json!({ "output": output.as_str() }))
+        }
+    }
+}
+
+/// Refuses entries with an access policy: the response goes to stdout and stdin carries
+/// the request, so there is no way to honour either restriction.
+fn check_unrestricted(kn: &Keynest, key: &str) -> Result<(), ApiError> {
+    if kn
+        .effective_policy(key)?
+        .is_some_and(|policy| !policy.is_unrestricted())
+    {
+        return Err(ApiError::new(
+            "restricted",
+            format!("'{key}' has an access policy and cannot be read through the API"),
+        ));
+    }
+    Ok(())
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn open(store: Option<PathBuf>) -> Result<Keynest, ApiError> {
+    let storage =
+        resolve_existing_storage(store).map_err(|e| ApiError::new("no_store", format!("{e:#}")))?;
+    Ok(unlock_keystore(storage)?)
+}
@@ -0,0 +1,125 @@
+use anyhow::{Result, bail};
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    create_file_secure, print_json, resolve_existing_storage, unlock_keystore,
+};
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "\
+Examples:
+  keynest attach add prod/tls ca.pem                 Attach a file to a secret (stored encrypted, deduplicated)
+  keynest attach add prod/tls bundle.pem --name ca   Attach under a different name
+  keynest attach list prod/tls                       List the attachments of a secret
+  keynest attach get prod/tls ca.pem -o ca.pem       Write an attachment to a file (stdout by default)
+  keynest attach remove prod/tls ca.pem              Detach a file and purge chunks no longer used
+  keynest attach purge                               Delete unreferenced chunks left behind by interrupted runs"
+)]
+pub struct AttachCommand {
+    #[command(subcommand)]
+    pub action: AttachAction,
+}
+
+#[derive(Subcommand)]
+pub enum AttachAction {
+    /// Attach a file to a secret
+    Add {
+        key: String,
+        file: PathBuf,
+
+        /// Attachment name (default: the file name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Write an attachment to a file or stdout
+    Get {
+        key: String,
+        name: String,
+
+        /// Output file (default: stdout)
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+    },
+    /// List the attachments of a secret
+    List {
+        key: String,
+
+        /// Output as JSON
+        #[arg(long, short = 'j')]
+        json: bool,
+    },
+    /// Detach a file from a secret
+    Remove { key: String, name: String },
+    /// Delete attachment chunks that are no longer referenced
+    Purge,
+}
+
+impl Command for AttachCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        match self.action {
+            AttachAction::Add { key, file, name } => {
+                let name = match name {
+                    Some(name) => name,
+                    None => match file.file_name().and_then(|n| n.to_str()) {
+                        Some(name) => name.to_string(),
+                        None => bail!("cannot derive attachment name from path; use --name"),
+                    },
+                };
+
+                let reader = std::fs::File::open(&file)?;
+                kn.attach(&key, &name, reader)?;
+                kn.save()?;
+                println!("attached '{name}' to '{key}'");
+            }
+            AttachAction::Get { key, name, output } => {
+                let mut reader = kn.attachment_reader(&key, &name)?;
+                match output {
+                    Some(path) => {
+                        std::io::copy(&mut reader, &mut create_file_secure(&path)?)?;
+                    }
+                    None => {
+                        std::io::copy(&mut reader, &mut std::io::stdout().lock())?;
+                    }
+                }
+            }
+            AttachAction::List { key, json } => {
+                let Some(attachments) = kn.attachments(&key)? else {
+                    eprintln!("key not found: {key}");
+                    return Ok(ExitCode::from(1));
+                };
+
+                if json {
+                    let list: Vec<_> = attachments
+                        .iter()
+                        .map(|(name, a)| serde_json::json!({"name": name, "size": a.size()}))
+                        .collect();
+                    print_json(&list)?;
+                } else {
+                    for (name, a) in attachments {
+                        println!("{name}  ({} bytes)", a.size());
+                    }
+                }
+            }
+            AttachAction::Remove { key, name } => {
+                kn.detach(&key, &name)?;
+                kn.save()?;
+                kn.purge_attachments()?;
+                println!("removed attachment '{name}' from '{key}'");
+            }
+            AttachAction::Purge => {
+                let removed = kn.purge_attachments()?;
+                println!("Purged {removed} unreferenced chunk(s)");
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
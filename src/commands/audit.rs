@@ -0,0 +1,180 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    print_json, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
+use keynest::settings::ReadReceipts;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest audit enable --log                     Start the audit log
+  keynest audit                                  Show what changed and was read, and when
+  keynest audit --since 7d                       Only the events of the last week
+  keynest audit enable                           Start recording who reads each entry
+  keynest audit --reads                          Show who last read each entry, and when
+  KEYNEST_READER=ci keynest get deploy/token     Read as 'ci' instead of user@host
+  keynest audit disable                          Stop recording and delete the receipts
+
+The audit log, <store>.audit, is encrypted with a key kept inside the store and only
+appended to. Each save logs the entries added (set), changed (update) and removed
+(remove) since the previous one; reads (get) and password changes (rekey) are logged as
+they happen. Frames are chained, so changing or removing logged events is detected.
+'disable --log' deletes the log.
+
+Read receipts are stored inside the encrypted store, so everyone sharing it can see
+them and no server is involved. Reads by 'get', 'exec'/'run', 'lookup' and 'type' are
+recorded as user@host (or $KEYNEST_READER), also in the audit log; only the latest read
+of each entry is kept. While receipts or the audit log are on, these commands also
+write the store or the log.")]
+pub struct AuditCommand {
+    #[command(subcommand)]
+    pub action: Option<AuditAction>,
+
+    /// Show the latest read of each entry instead of the audit log
+    #[arg(long)]
+    pub reads: bool,
+
+    /// Only show audit log events since this long ago (30m, 12h, 7d, 2w) or this date
+    #[arg(long, value_name = "WHEN", value_parser = parse_since, conflicts_with = "reads")]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+#[derive(Subcommand)]
+pub enum AuditAction {
+    /// Start recording read receipts
+    Enable {
+        /// Start the audit log instead
+        #[arg(long)]
+        log: bool,
+    },
+    /// Stop recording read receipts and delete them
+    Disable {
+        /// Stop the audit log and delete it instead
+        #[arg(long)]
+        log: bool,
+    },
+}
+
+impl Command for AuditCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        if let Some(action) = self.action {
+            let mut kn = unlock_keystore(storage)?;
+            let (enable, log) = match action {
+                AuditAction::Enable { log } => (true, log),
+                AuditAction::Disable { log } => (false, log),
+            };
+            if log {
+                kn.set_audit_log(enable)?;
+            } else {
+                kn.set_read_receipts(enable)?;
+            }
+            kn.save()?;
+            println!(
+                "{} {}",
+                if log { "audit log" } else { "read receipts" },
+                if enable { "enabled" } else { "disabled" }
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let kn = unlock_indexed(storage)?;
+        if !self.reads {
+            let Some(events) = kn.audit_events()? else {
+                eprintln!("the audit log is off (enable it with: keynest audit enable --log)");
+                return Ok(ExitCode::from(1));
+            };
+            let events = events.iter().filter(|event| {
+                self.since.is_none_or(|since| {
+                    DateTime::parse_from_rfc3339(event.time()).is_ok_and(|time| time >= since)
+                })
+            });
+            if self.json {
+                let events: Vec<_> = events
+                    .map(|event| {
+                        serde_json::json!({
+                            "time": event.time(),
+                            "action": event.action().as_str(),
+                            "key": event.key(),
+                            "from": event.from(),
+                            "actor": event.actor(),
+                        })
+                    })
+                    .collect();
+                return print_json(&events).map(|()| ExitCode::SUCCESS);
+            }
+            for event in events {
+                let key = event.key().unwrap_or("-");
+                let key = match event.from() {
+                    Some(from) => format!("{from} -> {key}"),
+                    None => key.to_string(),
+                };
+                println!(
+                    "{}\t{}\t{key}\t{}",
+                    event.time(),
+                    event.action(),
+                    event.actor()
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let Some(receipts) = kn.setting::<ReadReceipts>()? else {
+            eprintln!("read receipts are off (enable them with: keynest audit enable)");
+            return Ok(ExitCode::from(1));
+        };
+
+        if self.json {
+            let receipts: Vec<_> = receipts
+                .iter()
+                .map(|(key, receipt)| {
+                    serde_json::json!({
+                        "key": key,
+                        "reader": receipt.reader(),
+                        "read": receipt.read(),
+                    })
+                })
+                .collect();
+            return print_json(&receipts).map(|()| ExitCode::SUCCESS);
+        }
+        for (key, receipt) in &receipts {
+            println!("{key}\t{}\t{}", receipt.reader(), receipt.read());
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Parses `--since`: a duration back from now (`30m`, `12h`, `7d`, `2w`), a date
+/// (`2026-12-31`, midnight UTC) or an RFC 3339 timestamp.
+fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    let invalid = || format!("invalid time '{s}' (use e.g. 7d, 12h, 2w or 2026-12-31)");
+    let (count, unit) = s.split_at(s.len().saturating_sub(1));
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "m" => TimeDelta::try_minutes(count),
+        "h" => TimeDelta::try_hours(count),
+        "d" => TimeDelta::try_days(count),
+        "w" => TimeDelta::try_weeks(count),
+        _ => None,
+    };
+    duration
+        .filter(|d| *d >= TimeDelta::zero())
+        .and_then(|d| Utc::now().checked_sub_signed(d))
+        .ok_or_else(invalid)
+}
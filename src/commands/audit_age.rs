@@ -0,0 +1,96 @@
+use anyhow::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest audit-age                             Secrets not rotated in the last 90 days
+  keynest audit-age --max-age 30d prod/         Stricter policy for production
+  keynest audit-age --max-age 12w --json        Machine-readable report, e.g. for CI
+
+Every secret remembers when its value last changed; adding tags, fields or an expiry
+does not count. Secrets whose value is older than --max-age are listed with that time
+and their age in days, and the command exits with 1 if there are any, so a CI job fails
+when credentials get stale. Secrets stored before keynest tracked this use the time
+they were last updated. Notes, TOTP seeds, recipes, counters and references are not
+checked. Nothing is written.")]
+pub struct AuditAgeCommand {
+    /// Only check secrets whose key starts with this, e.g. prod/
+    pub prefix: Option<String>,
+
+    /// Longest time a value may go unchanged (12h, 90d, 13w)
+    #[arg(long, value_name = "DURATION", default_value = "90d", value_parser = parse_max_age)]
+    pub max_age: TimeDelta,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+impl Command for AuditAgeCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let kn = unlock_keystore(storage)?;
+        let now = kn.clock().now();
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        let stale: Vec<_> = kn
+            .stale(self.max_age)?
+            .into_iter()
+            .filter(|entry| entry.key().starts_with(prefix))
+            .collect();
+        let age_days = |rotated: Option<DateTime<Utc>>| rotated.map(|r| (now - r).num_days());
+
+        if self.json {
+            let stale: Vec<_> = stale
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "key": entry.key(),
+                        "rotated": entry.rotated(),
+                        "age_days": age_days(entry.rotated_at()),
+                    })
+                })
+                .collect();
+            print_json(&stale)?;
+        } else {
+            for entry in &stale {
+                match age_days(entry.rotated_at()) {
+                    Some(days) => println!("{}\t{}\t{days} days", entry.key(), entry.rotated()),
+                    None => println!("{}\t{}\t?", entry.key(), entry.rotated()),
+                }
+            }
+            if stale.is_empty() {
+                println!("no secret needs rotating");
+            } else {
+                eprintln!("{} secrets need rotating", stale.len());
+            }
+        }
+        Ok(if stale.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(1)
+        })
+    }
+}
+
+/// Parses `--max-age`: a positive number followed by `h`, `d` or `w`.
+fn parse_max_age(s: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("invalid duration '{s}' (use e.g. 12h, 90d or 13w)");
+    let (count, unit) = s.split_at(s.len().saturating_sub(1));
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let max_age = match unit {
+        "h" => TimeDelta::try_hours(count),
+        "d" => TimeDelta::try_days(count),
+        "w" => TimeDelta::try_weeks(count),
+        _ => None,
+    };
+    max_age
+        .filter(|max_age| *max_age > TimeDelta::zero())
+        .ok_or_else(invalid)
+}
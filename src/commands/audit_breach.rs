@@ -0,0 +1,69 @@
+use anyhow::Result;
+use clap::Args;
+use keynest::BreachList;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest audit-breach --hashes pwnedpasswords.txt     Check against the downloaded list
+  keynest audit-breach --hashes ~/pwned/ --json        Range files, machine-readable
+
+Each secret is hashed with SHA-1 and looked up in a list of breached passwords on disk,
+such as the Pwned Passwords list of Have I Been Pwned; nothing is sent anywhere. The
+list is either a text file of HASH:COUNT lines sorted by hash, as written by the Pwned
+Passwords downloader, a directory of the range files 00000.txt to FFFFF.txt of the
+k-anonymity API, or a binary file of the sorted 20-byte hashes (e.g. made with
+'cut -d: -f1 pwnedpasswords.txt | xxd -r -p > pwned.bin'). The secrets found are listed
+with how often they were seen, and the command exits with 1 if there are any. Notes,
+TOTP seeds, recipes, counters and references are not checked. Nothing is written.")]
+pub struct AuditBreachCommand {
+    /// List of breached password hashes: sorted text file, range directory or binary file
+    #[arg(long, value_name = "PATH")]
+    pub hashes: PathBuf,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+impl Command for AuditBreachCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let list = BreachList::open(&self.hashes)?;
+        let kn = unlock_keystore(storage)?;
+        let breached = kn.audit_breach(&list)?;
+
+        if self.json {
+            let breached: Vec<_> = breached
+                .iter()
+                .map(|found| serde_json::json!({"key": found.key(), "count": found.count()}))
+                .collect();
+            print_json(&breached)?;
+        } else {
+            for found in &breached {
+                match found.count() {
+                    Some(count) => println!("{}\tseen {count} times", found.key()),
+                    None => println!("{}\tbreached", found.key()),
+                }
+            }
+            if breached.is_empty() {
+                println!("no secret is in {}", list.path().display());
+            } else {
+                eprintln!(
+                    "{} secrets are in the breach list and need rotating",
+                    breached.len()
+                );
+            }
+        }
+        Ok(if breached.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(1)
+        })
+    }
+}
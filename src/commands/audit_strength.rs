@@ -0,0 +1,104 @@
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest audit-strength                  List the secrets that need rotating
+  keynest audit-strength --all            Score every secret
+  keynest audit-strength --min-score 4    Only accept secrets out of reach
+  keynest audit-strength --json           Machine-readable report, e.g. for a cron job
+
+Each secret is scored from 0 (guessed at once) to 4 (out of reach) by estimating how
+many guesses an attacker needs who tries common passwords, dictionary words, sequences
+like abc, keyboard runs like qwerty and repeated characters first; score 3 takes 60
+bits. Secrets scoring below --min-score and values shared by several secrets are
+reported, and the command exits with 1 if there are any. Notes, TOTP seeds, recipes,
+counters and references are not scored. Nothing is written.")]
+pub struct AuditStrengthCommand {
+    /// Report secrets scoring below this (0-4)
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..=4))]
+    pub min_score: u8,
+
+    /// List every secret, not only those that need rotating
+    #[arg(long, short = 'a')]
+    pub all: bool,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+impl Command for AuditStrengthCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let kn = unlock_keystore(storage)?;
+        let findings = kn.audit()?;
+        let rotate = findings
+            .iter()
+            .filter(|finding| finding.needs_rotation(self.min_score))
+            .count();
+        let shown = findings
+            .iter()
+            .filter(|finding| self.all || finding.needs_rotation(self.min_score));
+
+        if self.json {
+            let shown: Vec<_> = shown
+                .map(|finding| {
+                    let strength = finding.strength();
+                    serde_json::json!({
+                        "key": finding.key(),
+                        "score": strength.score(),
+                        "bits": (strength.bits() * 10.0).round() / 10.0,
+                        "weaknesses": strength
+                            .weaknesses()
+                            .iter()
+                            .map(|weakness| weakness.as_str())
+                            .collect::<Vec<_>>(),
+                        "reused_by": finding.reused_by(),
+                        "rotate": finding.needs_rotation(self.min_score),
+                    })
+                })
+                .collect();
+            print_json(&shown)?;
+        } else {
+            for finding in shown {
+                let strength = finding.strength();
+                let mut problems: Vec<String> = strength
+                    .weaknesses()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                if !finding.reused_by().is_empty() {
+                    problems.push(format!("same value as {}", finding.reused_by().join(", ")));
+                }
+                println!(
+                    "{}\t{}/4\t{:.0} bits\t{}",
+                    finding.key(),
+                    strength.score(),
+                    strength.bits(),
+                    if problems.is_empty() {
+                        "-".to_string()
+                    } else {
+                        problems.join("; ")
+                    }
+                );
+            }
+            if rotate == 0 {
+                println!("{} secrets checked, none need rotating", findings.len());
+            } else {
+                eprintln!("{} of {} secrets need rotating", rotate, findings.len());
+            }
+        }
+        Ok(if rotate == 0 {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(1)
+        })
+    }
+}
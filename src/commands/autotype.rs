@@ -0,0 +1,73 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+use keynest::autotype::DEFAULT_SEQUENCE;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest autotype                               Show the configured sequences
+  keynest autotype set bank '{USERNAME}{TAB}{DELAY 500}{PASSWORD}{ENTER}'
+                                                 Type bank/username, Tab, wait, bank/password, Enter
+  keynest autotype unset bank                    Use the default sequence for bank again
+
+A login is an entry with a password field, or a namespace whose entries are its fields:
+{USERNAME} is the field username (or user) of bank, else bank/username, {PASSWORD}
+bank/password, {OTP} bank/otp, and so on. Special keys are {TAB} and
+{ENTER}, {DELAY n} waits n ms, {{} and {}} type literal braces. The default sequence
+is {USERNAME}{TAB}{PASSWORD}{ENTER}. 'keynest type <login>' types the sequence.")]
+pub struct AutotypeCommand {
+    #[command(subcommand)]
+    pub action: Option<AutotypeAction>,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+#[derive(Subcommand)]
+pub enum AutotypeAction {
+    /// Set the sequence of a login
+    Set { login: String, sequence: String },
+    /// Revert a login to the default sequence
+    Unset { login: String },
+}
+
+impl Command for AutotypeCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        match self.action {
+            Some(AutotypeAction::Set { login, sequence }) => {
+                kn.set_autotype_sequence(&login, Some(&sequence))?;
+                kn.save()?;
+                println!("autotype sequence of '{login}' set");
+            }
+            Some(AutotypeAction::Unset { login }) => {
+                kn.set_autotype_sequence(&login, None)?;
+                kn.save()?;
+                println!("'{login}' uses the default autotype sequence");
+            }
+            None => {
+                let sequences = kn.autotype_sequences()?;
+                if self.json {
+                    print_json(&serde_json::json!({
+                        "default": DEFAULT_SEQUENCE,
+                        "logins": sequences,
+                    }))?;
+                } else {
+                    println!("default  {DEFAULT_SEQUENCE}");
+                    for (login, sequence) in &sequences {
+                        println!("{login}  {sequence}");
+                    }
+                }
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
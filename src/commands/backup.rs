@@ -0,0 +1,150 @@
+use anyhow::{Result, bail};
+use chrono::{SecondsFormat, Utc};
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    confirm, print_json, resolve_existing_storage, resolve_storage, unlock_keystore,
+};
+use keynest::{Backup, Storage};
+
+/// Backups kept by `backup enable` without --keep.
+const DEFAULT_KEEP: u32 = 5;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest backup enable                          Keep the last 5 versions of the store
+  keynest backup enable --keep 20                Keep the last 20 versions
+  keynest backup list                            Show the backups, newest first
+  keynest backup restore 20260718T093012.345Z    Put a backup back in place of the store
+  keynest backup restore 20260718T09             ... or any unique beginning of its name
+  keynest backup disable                         Stop taking backups (existing ones are kept)
+
+While backups are enabled, every save first copies the store to
+<store>.bak.<time> (UTC) and deletes all but the newest backups. Backups are encrypted
+like the store itself. Restoring keeps the replaced store as a backup too.")]
+pub struct BackupCommand {
+    #[command(subcommand)]
+    pub action: BackupAction,
+}
+
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// Keep previous versions of the store on every save
+    Enable {
+        /// Number of backups to keep
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_KEEP,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        keep: u32,
+    },
+    /// Stop keeping previous versions of the store
+    Disable,
+    /// List the backups of the store, newest first
+    List {
+        /// Output as JSON
+        #[arg(long, short = 'j')]
+        json: bool,
+    },
+    /// Replace the store with one of its backups
+    Restore {
+        /// Backup to restore: its time as shown by `backup list`, or a unique prefix
+        id: String,
+
+        /// Do not ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+}
+
+impl Command for BackupCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        match self.action {
+            BackupAction::Enable { keep } => {
+                let mut kn = unlock_keystore(resolve_existing_storage(store)?)?;
+                kn.set_backups(keep)?;
+                kn.save()?;
+                println!("keeping the last {keep} backup(s)");
+            }
+            BackupAction::Disable => {
+                let mut kn = unlock_keystore(resolve_existing_storage(store)?)?;
+                kn.set_backups(0)?;
+                kn.save()?;
+                println!("backups disabled; existing backups were kept");
+            }
+            BackupAction::List { json } => list(&resolve_storage(store)?.backups()?, json)?,
+            BackupAction::Restore { id, yes } => return restore(resolve_storage(store)?, &id, yes),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn list(backups: &[Backup], json: bool) -> Result<()> {
+    if json {
+        let backups: Vec<_> = backups
+            .iter()
+            .map(|backup| {
+                serde_json::json!({
+                    "id": backup.id(),
+                    "created": backup.created().map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true)),
+                    "path": backup.path(),
+                })
+            })
+            .collect();
+        return print_json(&backups);
+    }
+    if backups.is_empty() {
+        eprintln!("no backups (enable them with: keynest backup enable)");
+    }
+    for backup in backups {
+        println!("{}\t{}", backup.id(), backup.path().display());
+    }
+    Ok(())
+}
+
+fn restore(storage: Storage, id: &str, yes: bool) -> Result<ExitCode> {
+    let backups = storage.backups()?;
+    let backup = match backups.iter().find(|backup| backup.id() == id) {
+        Some(backup) => backup,
+        None => {
+            let matches: Vec<_> = backups
+                .iter()
+                .filter(|backup| backup.id().starts_with(id))
+                .collect();
+            match matches.as_slice() {
+                [backup] => *backup,
+                [] => {
+                    eprintln!("backup not found: {id}");
+                    return Ok(ExitCode::from(1));
+                }
+                _ => bail!(
+                    "'{id}' matches {} backups; use more of the name, as shown by `keynest backup list`",
+                    matches.len()
+                ),
+            }
+        }
+    };
+
+    // Check that the backup decrypts before anything is replaced.
+    let source = Storage::new(backup.path().to_path_buf());
+    let entries = unlock_keystore(source.clone())?.list().len();
+    let question = format!(
+        "Replace {} with backup {} ({entries} entries)?",
+        storage.path().display(),
+        backup.id()
+    );
+    if !yes && !confirm(&question)? {
+        println!("Aborted");
+        return Ok(ExitCode::from(1));
+    }
+
+    let kept = storage.backup(Utc::now())?;
+    storage.save(&source.load()?)?;
+    println!("restored backup {} ({entries} entries)", backup.id());
+    if let Some(kept) = kept {
+        println!("the replaced store was kept as backup {}", kept.id());
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,815 @@
+//! Reading the passwords saved by web browsers, for `import --browser`.
+//!
+//! - Chromium-based browsers (Chrome, Chromium, Brave, Edge) keep logins in the SQLite
+//!   database `Login Data` of a profile, encrypted with a key held by the OS: the
+//!   `<Browser> Safe Storage` item of the login keychain on macOS, a DPAPI-protected key
+//!   in `Local State` on Windows, and on Linux the Secret Service (through
+//!   `secret-tool`) or Chromium's fixed fallback key. Values with Chrome's app-bound
+//!   encryption (`v20`, Windows) can only be read by the browser itself and are skipped.
+//! - Firefox keeps logins in `logins.json`, encrypted with a key from `key4.db` that is
+//!   protected by the primary password (empty unless the user set one).
+//!
+//! The databases are opened read-only and nothing is written next to them; decrypted
+//! passwords only live in zeroized memory until they are imported.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+use crate::commands::os_keychain;
+
+/// A browser to read saved passwords from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Browser {
+    Chrome,
+    Chromium,
+    Brave,
+    Edge,
+    Firefox,
+}
+
+/// A login saved by a browser.
+pub struct Login {
+    /// Page or origin the login belongs to, e.g. `https://github.com/login`.
+    pub url: String,
+    /// User name; may be empty.
+    pub username: String,
+    pub password: Zeroizing<String>,
+}
+
+impl Login {
+    /// Returns the keynest key for the login: `<host>/<username>`, like credentials of
+    /// the OS credential store.
+    pub fn key(&self) -> String {
+        os_keychain::credential_key(&site(&self.url), &self.username)
+    }
+}
+
+/// The logins of a browser profile.
+#[derive(Default)]
+pub struct Logins {
+    pub logins: Vec<Login>,
+    /// Number of saved passwords that could not be decrypted.
+    pub undecryptable: usize,
+}
+
+/// Reads the saved logins of `browser` from `profile`, or from the default profile of
+/// the current user.
+///
+/// # Errors
+///
+/// Returns an error if the profile has no password database, the database cannot be
+/// read, or the Firefox primary password is wrong.
+pub fn read(browser: Browser, profile: Option<&Path>) -> Result<Logins> {
+    imp::read(browser, profile)
+}
+
+/// Returns the host (with port, if any) of `url`, lowercased; `url` itself if it has no
+/// scheme.
+fn site(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    host.to_lowercase()
+}
+
+#[cfg(feature = "browser")]
+mod imp {
+    use anyhow::{Context, Result};
+    use rusqlite::{Connection, OpenFlags};
+    use std::fmt::Write;
+    use std::path::Path;
+
+    use super::{Browser, Logins};
+
+    pub(super) fn read(browser: Browser, profile: Option<&Path>) -> Result<Logins> {
+        match browser {
+            Browser::Firefox => firefox::read(profile),
+            _ => chromium::read(browser, profile),
+        }
+    }
+
+    /// Opens the SQLite database at `path` read-only, without taking locks or looking
+    /// at journals, so it can be read while the browser keeps it open.
+    fn open_read_only(path: &Path) -> Result<Connection> {
+        let path = std::path::absolute(path)?;
+        let path = path.to_str().context("profile path is not valid UTF-8")?;
+        let mut uri = String::from("file://");
+        if cfg!(windows) {
+            uri.push('/');
+        }
+        for byte in path.bytes() {
+            match byte {
+                b'\\' if cfg!(windows) => uri.push('/'),
+                b'/' | b':' | b'-' | b'.' | b'_' | b'~' => uri.push(byte as char),
+                _ if byte.is_ascii_alphanumeric() => uri.push(byte as char),
+                _ => write!(uri, "%{byte:02X}")?,
+            }
+        }
+        uri.push_str("?immutable=1");
+        Connection::open_with_flags(
+            uri,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+        )
+        .with_context(|| format!("cannot open {}", path))
+    }
+
+    mod chromium {
+        use anyhow::{Context, Result, bail};
+        use directories::BaseDirs;
+        use std::path::{Path, PathBuf};
+
+        use super::super::{Browser, Login, Logins};
+        use super::open_read_only;
+
+        pub(in super::super) fn read(browser: Browser, profile: Option<&Path>) -> Result<Logins> {
+            let profile = match profile {
+                Some(path) => path.to_path_buf(),
+                None => user_data_dir(browser)?.join("Default"),
+            };
+            let db = profile.join("Login Data");
+            if !db.is_file() {
+                bail!("no saved passwords found: {} does not exist", db.display());
+            }
+
+            let conn = open_read_only(&db)?;
+            // Newest first, so the most recent login wins when several map to one key.
+            let mut stmt = conn.prepare(
+                "SELECT origin_url, username_value, password_value FROM logins \
+                 WHERE blacklisted_by_user = 0 ORDER BY date_created DESC",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                    row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    row.get::<_, Option<Vec<u8>>>(2)?.unwrap_or_default(),
+                ))
+            })?;
+
+            let mut keys = os::Keys::new(browser, &profile);
+            let mut logins = Logins::default();
+            for row in rows {
+                let (url, username, encrypted) = row?;
+                if encrypted.is_empty() {
+                    continue;
+                }
+                match keys.decrypt(&encrypted) {
+                    Some(password) => logins.logins.push(Login {
+                        url,
+                        username,
+                        password,
+                    }),
+                    None => logins.undecryptable += 1,
+                }
+            }
+            Ok(logins)
+        }
+
+        /// Returns the "User Data" directory of `browser`, which holds its profiles.
+        fn user_data_dir(browser: Browser) -> Result<PathBuf> {
+            let dirs = BaseDirs::new().context("could not determine platform directories")?;
+            #[cfg(target_os = "macos")]
+            let (base, relative) = (
+                dirs.data_dir(),
+                match browser {
+                    Browser::Chrome => "Google/Chrome",
+                    Browser::Chromium => "Chromium",
+                    Browser::Brave => "BraveSoftware/Brave-Browser",
+                    Browser::Edge => "Microsoft Edge",
+                    Browser::Firefox => unreachable!("Firefox is not Chromium-based"),
+                },
+            );
+            #[cfg(windows)]
+            let (base, relative) = (
+                dirs.data_local_dir(),
+                match browser {
+                    Browser::Chrome => "Google/Chrome/User Data",
+                    Browser::Chromium => "Chromium/User Data",
+                    Browser::Brave => "BraveSoftware/Brave-Browser/User Data",
+                    Browser::Edge => "Microsoft/Edge/User Data",
+                    Browser::Firefox => unreachable!("Firefox is not Chromium-based"),
+                },
+            );
+            #[cfg(not(any(target_os = "macos", windows)))]
+            let (base, relative) = (
+                dirs.config_dir(),
+                match browser {
+                    Browser::Chrome => "google-chrome",
+                    Browser::Chromium => "chromium",
+                    Browser::Brave => "BraveSoftware/Brave-Browser",
+                    Browser::Edge => "microsoft-edge",
+                    Browser::Firefox => unreachable!("Firefox is not Chromium-based"),
+                },
+            );
+            Ok(base.join(relative))
+        }
+
+        #[cfg(not(windows))]
+        mod safe_storage {
+            use aes::Aes128;
+            use aes::cipher::{BlockDecryptMut, KeyIvInit, block_padding::Pkcs7};
+            use zeroize::Zeroizing;
+
+            /// Derives the AES-128 key from the OS-held password, as Chromium does.
+            pub(super) fn derive(password: &[u8], iterations: u32) -> Zeroizing<[u8; 16]> {
+                let mut key = Zeroizing::new([0u8; 16]);
+                pbkdf2::pbkdf2_hmac::<sha1::Sha1>(password, b"saltysalt", iterations, &mut *key);
+                key
+            }
+
+            /// Decrypts a value after its version prefix: AES-128-CBC with an IV of spaces.
+            pub(super) fn decrypt(key: &[u8; 16], data: &[u8]) -> Option<Zeroizing<String>> {
+                let plain = Zeroizing::new(
+                    cbc::Decryptor::<Aes128>::new(key.into(), &[b' '; 16].into())
+                        .decrypt_padded_vec_mut::<Pkcs7>(data)
+                        .ok()?,
+                );
+                String::from_utf8(plain.to_vec()).ok().map(Zeroizing::new)
+            }
+        }
+
+        #[cfg(target_os = "macos")]
+        mod os {
+            use std::path::Path;
+            use std::process::{Command, Stdio};
+            use zeroize::Zeroizing;
+
+            use super::super::super::Browser;
+            use super::safe_storage;
+
+            /// The key from the `<Browser> Safe Storage` keychain item, read on first use
+            /// since reading it may show a keychain access prompt.
+            pub(super) struct Keys {
+                browser: Browser,
+                key: Option<Option<Zeroizing<[u8; 16]>>>,
+            }
+
+            impl Keys {
+                pub(super) fn new(browser: Browser, _profile: &Path) -> Self {
+                    Self { browser, key: None }
+                }
+
+                pub(super) fn decrypt(&mut self, value: &[u8]) -> Option<Zeroizing<String>> {
+                    let data = value.strip_prefix(b"v10")?;
+                    let browser = self.browser;
+                    let key = self
+                        .key
+                        .get_or_insert_with(|| {
+                            safe_storage_password(browser)
+                                .map(|pw| safe_storage::derive(pw.as_bytes(), 1003))
+                        })
+                        .as_ref()?;
+                    safe_storage::decrypt(key, data)
+                }
+            }
+
+            fn safe_storage_password(browser: Browser) -> Option<Zeroizing<String>> {
+                let service = match browser {
+                    Browser::Chrome => "Chrome Safe Storage",
+                    Browser::Chromium => "Chromium Safe Storage",
+                    Browser::Brave => "Brave Safe Storage",
+                    Browser::Edge => "Microsoft Edge Safe Storage",
+                    Browser::Firefox => unreachable!("Firefox is not Chromium-based"),
+                };
+                let output = Command::new("security")
+                    .args(["find-generic-password", "-w", "-s", service])
+                    .stderr(Stdio::null())
+                    .output()
+                    .ok()?;
+                let stdout = Zeroizing::new(output.stdout);
+                if !output.status.success() {
+                    return None;
+                }
+                let password = std::str::from_utf8(&stdout).ok()?.trim_end_matches('\n');
+                Some(Zeroizing::new(password.to_string()))
+            }
+        }
+
+        #[cfg(windows)]
+        mod os {
+            use aes_gcm::aead::{Aead, KeyInit};
+            use aes_gcm::{Aes256Gcm, Nonce};
+            use base64::Engine;
+            use base64::engine::general_purpose::STANDARD;
+            use std::path::Path;
+            use windows_sys::Win32::Foundation::LocalFree;
+            use windows_sys::Win32::Security::Cryptography::{
+                CRYPT_INTEGER_BLOB, CRYPTPROTECT_UI_FORBIDDEN, CryptUnprotectData,
+            };
+            use zeroize::Zeroizing;
+
+            use super::super::super::Browser;
+
+            /// The AES-256-GCM key from `Local State`, next to the profile directory.
+            pub(super) struct Keys {
+                key: Option<Zeroizing<Vec<u8>>>,
+            }
+
+            impl Keys {
+                pub(super) fn new(_browser: Browser, profile: &Path) -> Self {
+                    let local_state = profile.parent().map(|dir| dir.join("Local State"));
+                    Self {
+                        key: local_state.and_then(|path| master_key(&path)),
+                    }
+                }
+
+                pub(super) fn decrypt(&mut self, value: &[u8]) -> Option<Zeroizing<String>> {
+                    let plain = match value.get(..3) {
+                        Some(b"v10" | b"v11") => {
+                            let key = self.key.as_ref()?;
+                            let (nonce, data) = value[3..].split_at_checked(12)?;
+                            let cipher = Aes256Gcm::new_from_slice(key).ok()?;
+                            Zeroizing::new(cipher.decrypt(Nonce::from_slice(nonce), data).ok()?)
+                        }
+                        // App-bound: only the browser's elevation service can decrypt.
+                        Some(b"v20") => return None,
+                        _ => unprotect(value)?,
+                    };
+                    String::from_utf8(plain.to_vec()).ok().map(Zeroizing::new)
+                }
+            }
+
+            fn master_key(local_state: &Path) -> Option<Zeroizing<Vec<u8>>> {
+                let state: serde_json::Value =
+                    serde_json::from_slice(&std::fs::read(local_state).ok()?).ok()?;
+                let encoded = state["os_crypt"]["encrypted_key"].as_str()?;
+                let encrypted = STANDARD.decode(encoded).ok()?;
+                unprotect(encrypted.strip_prefix(b"DPAPI")?)
+            }
+
+            /// Decrypts `data` with the DPAPI key of the current user.
+            fn unprotect(data: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+                let input = CRYPT_INTEGER_BLOB {
+                    cbData: u32::try_from(data.len()).ok()?,
+                    pbData: data.as_ptr().cast_mut(),
+                };
+                let mut out = CRYPT_INTEGER_BLOB {
+                    cbData: 0,
+                    pbData: std::ptr::null_mut(),
+                };
+                // SAFETY:
+                // - The input blob points to a live buffer for the duration of the call
+                // - DPAPI only reads it and allocates the output itself
+                let ok = unsafe {
+                    CryptUnprotectData(
+                        &input,
+                        std::ptr::null_mut(),
+                        std::ptr::null(),
+                        std::ptr::null(),
+                        std::ptr::null(),
+                        CRYPTPROTECT_UI_FORBIDDEN,
+                        &mut out,
+                    )
+                };
+                if ok == 0 {
+                    return None;
+                }
+                // SAFETY: DPAPI returned `cbData` bytes at `pbData`, allocated with LocalAlloc.
+                let plain = unsafe { std::slice::from_raw_parts(out.pbData, out.cbData as usize) };
+                let copy = Zeroizing::new(plain.to_vec());
+                // SAFETY: the buffer is owned by us and not used after this.
+                unsafe { LocalFree(out.pbData.cast()) };
+                Some(copy)
+            }
+        }
+
+        #[cfg(not(any(target_os = "macos", windows)))]
+        mod os {
+            use std::path::Path;
+            use std::process::{Command, Stdio};
+            use zeroize::Zeroizing;
+
+            use super::super::super::Browser;
+            use super::safe_storage;
+
+            /// `v10` values use a fixed key; `v11` values a key from the Secret Service,
+            /// read on first use.
+            pub(super) struct Keys {
+                browser: Browser,
+                v10: Zeroizing<[u8; 16]>,
+                v11: Option<Option<Zeroizing<[u8; 16]>>>,
+            }
+
+            impl Keys {
+                pub(super) fn new(browser: Browser, _profile: &Path) -> Self {
+                    Self {
+                        browser,
+                        v10: safe_storage::derive(b"peanuts", 1),
+                        v11: None,
+                    }
+                }
+
+                pub(super) fn decrypt(&mut self, value: &[u8]) -> Option<Zeroizing<String>> {
+                    if let Some(data) = value.strip_prefix(b"v10") {
+                        return safe_storage::decrypt(&self.v10, data);
+                    }
+                    let data = value.strip_prefix(b"v11")?;
+                    let browser = self.browser;
+                    let key = self
+                        .v11
+                        .get_or_insert_with(|| {
+                            keyring_password(browser)
+                                .map(|pw| safe_storage::derive(pw.as_bytes(), 1))
+                        })
+                        .as_ref()?;
+                    safe_storage::decrypt(key, data)
+                }
+            }
+
+            /// Reads the "<Browser> Safe Storage" secret with `secret-tool`; KWallet is not
+            /// supported.
+            fn keyring_password(browser: Browser) -> Option<Zeroizing<String>> {
+                let application = match browser {
+                    Browser::Chrome => "chrome",
+                    Browser::Chromium => "chromium",
+                    Browser::Brave => "brave",
+                    Browser::Edge => "microsoft-edge",
+                    Browser::Firefox => unreachable!("Firefox is not Chromium-based"),
+                };
+                let output = Command::new("secret-tool")
+                    .args(["lookup", "application", application])
+                    .stderr(Stdio::null())
+                    .output()
+                    .ok()?;
+                let stdout = Zeroizing::new(output.stdout);
+                if !output.status.success() || stdout.is_empty() {
+                    return None;
+                }
+                let password = std::str::from_utf8(&stdout).ok()?.trim_end_matches('\n');
+                Some(Zeroizing::new(password.to_string()))
+            }
+        }
+    }
+
+    mod firefox {
+        use aes::Aes256;
+        use aes::cipher::{BlockDecryptMut, KeyIvInit, block_padding::Pkcs7};
+        use anyhow::{Context, Result, bail};
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD;
+        use des::TdesEde3;
+        use directories::BaseDirs;
+        use hmac::{Hmac, Mac};
+        use sha1::{Digest, Sha1};
+        use sha2::Sha256;
+        use std::collections::HashMap;
+        use std::io::IsTerminal;
+        use std::path::{Path, PathBuf};
+        use zeroize::Zeroizing;
+
+        use super::super::{Login, Logins};
+        use super::open_read_only;
+
+        const PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+        const PBE_SHA1_3DES: &[u8] = &[
+            0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x05, 0x01, 0x03,
+        ];
+        const HMAC_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x09];
+        const DES_EDE3_CBC: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x03, 0x07];
+        const AES256_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a];
+
+        /// Decryption keys of `key4.db`, by key id.
+        type Keys = HashMap<Vec<u8>, Zeroizing<Vec<u8>>>;
+
+        pub(in super::super) fn read(profile: Option<&Path>) -> Result<Logins> {
+            let profile = match profile {
+                Some(path) => path.to_path_buf(),
+                None => default_profile()?,
+            };
+            let logins_json = profile.join("logins.json");
+            if !logins_json.is_file() {
+                bail!(
+                    "no saved passwords found: {} does not exist",
+                    logins_json.display()
+                );
+            }
+            let keys = unlock(&profile.join("key4.db"))?;
+
+            let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&logins_json)?)
+                .with_context(|| format!("cannot parse {}", logins_json.display()))?;
+            let mut logins = Logins::default();
+            for login in json["logins"].as_array().into_iter().flatten() {
+                let url = login["hostname"].as_str().unwrap_or_default().to_string();
+                let field = |name: &str| {
+                    login[name]
+                        .as_str()
+                        .and_then(|value| decrypt_field(&keys, value))
+                };
+                match (field("encryptedUsername"), field("encryptedPassword")) {
+                    (Some(username), Some(password)) => logins.logins.push(Login {
+                        url,
+                        username: username.to_string(),
+                        password,
+                    }),
+                    _ => logins.undecryptable += 1,
+                }
+            }
+            Ok(logins)
+        }
+
+        /// Returns the default profile named in `profiles.ini`.
+        fn default_profile() -> Result<PathBuf> {
+            let dirs = BaseDirs::new().context("could not determine platform directories")?;
+            #[cfg(target_os = "macos")]
+            let roots = [dirs.data_dir().join("Firefox")];
+            #[cfg(windows)]
+            let roots = [dirs.data_dir().join("Mozilla/Firefox")];
+            #[cfg(not(any(target_os = "macos", windows)))]
+            let roots = [
+                dirs.home_dir().join(".mozilla/firefox"),
+                dirs.config_dir().join("mozilla/firefox"),
+            ];
+            let Some(root) = roots
+                .iter()
+                .find(|root| root.join("profiles.ini").is_file())
+            else {
+                bail!("no Firefox profile found; use --browser-profile");
+            };
+            let ini = std::fs::read_to_string(root.join("profiles.ini"))?;
+
+            // The profile of the current installation, else the one marked as default.
+            let mut install = None;
+            let mut marked = None;
+            let mut section = String::new();
+            let (mut path, mut relative, mut default) = (None, true, false);
+            for line in ini.lines().map(str::trim).chain(["[end]"]) {
+                if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                    if section.starts_with("Profile") && default && marked.is_none() {
+                        marked = path
+                            .take()
+                            .map(|p: String| if relative { root.join(p) } else { p.into() });
+                    }
+                    section = name.to_string();
+                    (path, relative, default) = (None, true, false);
+                    continue;
+                }
+                let Some((key, value)) = line.split_once('=') else {
+                    continue;
+                };
+                match key.trim() {
+                    "Default" if section.starts_with("Install") && install.is_none() => {
+                        install = Some(root.join(value.trim()));
+                    }
+                    "Default" => default = value.trim() == "1",
+                    "Path" => path = Some(value.trim().to_string()),
+                    "IsRelative" => relative = value.trim() == "1",
+                    _ => {}
+                }
+            }
+            install
+                .or(marked)
+                .context("no default Firefox profile found; use --browser-profile")
+        }
+
+        /// Reads the decryption keys of `key4.db`, asking for the primary password if
+        /// one is set.
+        fn unlock(db: &Path) -> Result<Keys> {
+            if !db.is_file() {
+                bail!("{} does not exist", db.display());
+            }
+            let conn = open_read_only(db)?;
+            let (global_salt, check): (Vec<u8>, Vec<u8>) = conn
+                .query_row(
+                    "SELECT item1, item2 FROM metaData WHERE id = 'password'",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .context("key4.db has no password check")?;
+
+            let checks = |primary: &str| -> Result<bool> {
+                Ok(decrypt_pbe(&global_salt, primary.as_bytes(), &check)?
+                    .is_some_and(|plain| plain.starts_with(b"password-check")))
+            };
+            let mut primary = Zeroizing::new(String::new());
+            if !checks(&primary)? {
+                if !std::io::stdin().is_terminal() {
+                    bail!(
+                        "the Firefox profile is protected by a primary password; run interactively to enter it"
+                    );
+                }
+                primary = Zeroizing::new(rpassword::prompt_password("Firefox primary password: ")?);
+                if !checks(&primary)? {
+                    bail!("wrong Firefox primary password");
+                }
+            }
+
+            let mut keys = Keys::new();
+            let mut stmt = conn.prepare("SELECT a11, a102 FROM nssPrivate")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?;
+            for row in rows {
+                let (encrypted, id) = row?;
+                if let Some(key) = decrypt_pbe(&global_salt, primary.as_bytes(), &encrypted)? {
+                    keys.insert(id, key);
+                }
+            }
+            Ok(keys)
+        }
+
+        /// Decrypts an `EncryptedPrivateKeyInfo`-like item of `key4.db`. Returns `None`
+        /// if the padding is wrong, i.e. the primary password is.
+        fn decrypt_pbe(
+            global_salt: &[u8],
+            primary: &[u8],
+            item: &[u8],
+        ) -> Result<Option<Zeroizing<Vec<u8>>>> {
+            let [algorithm, ciphertext] = sequence(item)?;
+            let [oid, params] = algorithm.fields()?;
+            let ciphertext = ciphertext.expect(OCTET_STRING)?;
+            let hashed = Sha1::new()
+                .chain_update(global_salt)
+                .chain_update(primary)
+                .finalize();
+
+            match oid.expect(OID)? {
+                PBES2 => {
+                    let [kdf, cipher] = params.fields()?;
+                    let [_, kdf_params] = kdf.fields()?;
+                    let kdf_params = children(kdf_params.expect(SEQUENCE)?)?;
+                    let (Some(salt), Some(iterations)) = (kdf_params.first(), kdf_params.get(1))
+                    else {
+                        bail!("malformed key derivation parameters in key4.db");
+                    };
+                    if let Some(prf) = kdf_params.iter().find(|p| p.tag == SEQUENCE) {
+                        let [prf, ..] = children(prf.body)?[..] else {
+                            bail!("malformed key derivation parameters in key4.db");
+                        };
+                        if prf.expect(OID)? != HMAC_SHA256 {
+                            bail!("unsupported key derivation in key4.db");
+                        }
+                    }
+                    let iterations = u32::try_from(integer(iterations)?)?;
+                    let mut key = Zeroizing::new([0u8; 32]);
+                    pbkdf2::pbkdf2_hmac::<Sha256>(
+                        &hashed,
+                        salt.expect(OCTET_STRING)?,
+                        iterations,
+                        &mut *key,
+                    );
+                    let [cipher_oid, iv] = cipher.fields()?;
+                    if cipher_oid.expect(OID)? != AES256_CBC {
+                        bail!("unsupported cipher in key4.db");
+                    }
+                    // NSS stores the IV without the DER header of its OCTET STRING.
+                    let iv = [&[0x04, 0x0e][..], iv.expect(OCTET_STRING)?].concat();
+                    Ok(aes_cbc(&*key, &iv, ciphertext))
+                }
+                PBE_SHA1_3DES => {
+                    let [entry_salt, _] = params.fields()?;
+                    let entry_salt = entry_salt.expect(OCTET_STRING)?;
+                    let (key, iv) = legacy_key(&hashed, entry_salt)?;
+                    Ok(des3_cbc(&key, &iv, ciphertext))
+                }
+                _ => bail!("unsupported encryption in key4.db"),
+            }
+        }
+
+        /// Derives the 3DES key and IV of NSS's legacy PBE with SHA-1.
+        fn legacy_key(hashed: &[u8], entry_salt: &[u8]) -> Result<(Zeroizing<Vec<u8>>, Vec<u8>)> {
+            let hmac = |data: &[&[u8]]| -> Result<Vec<u8>> {
+                let chp = Sha1::new()
+                    .chain_update(hashed)
+                    .chain_update(entry_salt)
+                    .finalize();
+                let mut mac = Hmac::<Sha1>::new_from_slice(&chp)?;
+                for part in data {
+                    mac.update(part);
+                }
+                Ok(mac.finalize().into_bytes().to_vec())
+            };
+            let mut padded_salt = entry_salt.to_vec();
+            padded_salt.resize(padded_salt.len().max(20), 0);
+            let k1 = hmac(&[&padded_salt, entry_salt])?;
+            let tk = hmac(&[&padded_salt])?;
+            let k2 = hmac(&[&tk, entry_salt])?;
+            let k = Zeroizing::new([k1, k2].concat());
+            Ok((Zeroizing::new(k[..24].to_vec()), k[k.len() - 8..].to_vec()))
+        }
+
+        /// Decrypts `encryptedUsername` or `encryptedPassword` of `logins.json`.
+        fn decrypt_field(keys: &Keys, value: &str) -> Option<Zeroizing<String>> {
+            let der = STANDARD.decode(value).ok()?;
+            let [key_id, algorithm, ciphertext] = sequence(&der).ok()?;
+            let key = keys.get(key_id.expect(OCTET_STRING).ok()?)?;
+            let [oid, iv] = algorithm.fields().ok()?;
+            let iv = iv.expect(OCTET_STRING).ok()?;
+            let ciphertext = ciphertext.expect(OCTET_STRING).ok()?;
+            let plain = match oid.expect(OID).ok()? {
+                DES_EDE3_CBC => des3_cbc(key.get(..24)?, iv, ciphertext)?,
+                AES256_CBC => aes_cbc(key.get(..32)?, iv, ciphertext)?,
+                _ => return None,
+            };
+            String::from_utf8(plain.to_vec()).ok().map(Zeroizing::new)
+        }
+
+        fn aes_cbc(key: &[u8], iv: &[u8], data: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+            cbc::Decryptor::<Aes256>::new_from_slices(key, iv)
+                .ok()?
+                .decrypt_padded_vec_mut::<Pkcs7>(data)
+                .ok()
+                .map(Zeroizing::new)
+        }
+
+        fn des3_cbc(key: &[u8], iv: &[u8], data: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+            cbc::Decryptor::<TdesEde3>::new_from_slices(key, iv)
+                .ok()?
+                .decrypt_padded_vec_mut::<Pkcs7>(data)
+                .ok()
+                .map(Zeroizing::new)
+        }
+
+        const INTEGER: u8 = 0x02;
+        const OCTET_STRING: u8 = 0x04;
+        const OID: u8 = 0x06;
+        const SEQUENCE: u8 = 0x30;
+
+        /// A DER element: its tag and contents.
+        #[derive(Clone, Copy)]
+        struct Der<'a> {
+            tag: u8,
+            body: &'a [u8],
+        }
+
+        impl<'a> Der<'a> {
+            /// Returns the contents if the element has tag `tag`.
+            fn expect(self, tag: u8) -> Result<&'a [u8]> {
+                if self.tag != tag {
+                    bail!("malformed DER in Firefox profile");
+                }
+                Ok(self.body)
+            }
+
+            /// Returns the `N` elements of a SEQUENCE.
+            fn fields<const N: usize>(self) -> Result<[Der<'a>; N]> {
+                children(self.expect(SEQUENCE)?)?
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("malformed DER in Firefox profile"))
+            }
+        }
+
+        /// Splits the first DER element off `input`.
+        fn element(input: &[u8]) -> Result<(Der<'_>, &[u8])> {
+            let malformed = || anyhow::anyhow!("malformed DER in Firefox profile");
+            let (&tag, rest) = input.split_first().ok_or_else(malformed)?;
+            let (&first, rest) = rest.split_first().ok_or_else(malformed)?;
+            let (len, rest) = if first < 0x80 {
+                (usize::from(first), rest)
+            } else {
+                let count = usize::from(first & 0x7f);
+                if count == 0 || count > 4 {
+                    return Err(malformed());
+                }
+                let (bytes, rest) = rest.split_at_checked(count).ok_or_else(malformed)?;
+                let len = bytes
+                    .iter()
+                    .fold(0usize, |len, &b| len << 8 | usize::from(b));
+                (len, rest)
+            };
+            let (body, rest) = rest.split_at_checked(len).ok_or_else(malformed)?;
+            Ok((Der { tag, body }, rest))
+        }
+
+        /// Returns the elements of the contents of a SEQUENCE.
+        fn children(mut body: &[u8]) -> Result<Vec<Der<'_>>> {
+            let mut out = Vec::new();
+            while !body.is_empty() {
+                let (der, rest) = element(body)?;
+                out.push(der);
+                body = rest;
+            }
+            Ok(out)
+        }
+
+        /// Parses `input` as a SEQUENCE of `N` elements.
+        fn sequence<const N: usize>(input: &[u8]) -> Result<[Der<'_>; N]> {
+            match element(input)? {
+                (der, []) => der.fields(),
+                _ => bail!("malformed DER in Firefox profile"),
+            }
+        }
+
+        fn integer(der: &Der<'_>) -> Result<u64> {
+            let body = der.expect(INTEGER)?;
+            if body.len() > 8 {
+                bail!("malformed DER in Firefox profile");
+            }
+            Ok(body.iter().fold(0u64, |n, &b| n << 8 | u64::from(b)))
+        }
+    }
+}
+
+#[cfg(not(feature = "browser"))]
+mod imp {
+    use anyhow::{Result, bail};
+    use std::path::Path;
+
+    use super::{Browser, Logins};
+
+    pub(super) fn read(_browser: Browser, _profile: Option<&Path>) -> Result<Logins> {
+        bail!("this keynest was built without browser import; rebuild with `--features browser`")
+    }
+}
@@ -1,19 +1,25 @@
 use anyhow::{Result, bail};
-use clap::Args;
-use keynest::{KdfParams, Storage, default_storage};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use clap::{Args, ValueEnum};
+use keynest::{
+    AccessPolicy, Argon2Params, CancelToken, ChainWitness, EntropySource, EntropyUse,
+    IndexedKeynest, KdfParams, Keyfile, Keynest, MissingKeyfile, OsEntropy, Storage,
+    default_storage, format, repair,
+};
 use serde::Serialize;
-use std::fmt::Display;
-use std::path::PathBuf;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, Once};
+use zeroize::Zeroizing;
+
+use super::super::auth;
+use crate::commands::agent;
 
 pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
     println!("{}", serde_json::to_string_pretty(value)?);
     Ok(())
 }
 
-pub fn print_plain<T: Display>(value: &T) {
-    println!("{value}");
-}
-
 pub fn resolve_storage(path: Option<PathBuf>) -> Result<Storage> {
     match path {
         Some(p) => Ok(Storage::new(p)),
@@ -28,6 +34,12 @@ pub fn resolve_storage(path: Option<PathBuf>) -> Result<Storage> {
 pub fn resolve_existing_storage(path: Option<PathBuf>) -> Result<Storage> {
     let storage = resolve_storage(path)?;
     if !storage.exists() {
+        if !repair::find_candidates(&storage)?.is_empty() {
+            bail!(
+                "keystore does not exist: {}\nA temporary file or backup of it was found; run `keynest repair` to restore it.",
+                storage.path().display()
+            );
+        }
         bail!(
             "keystore does not exist: {}\nRun `keynest init` first.",
             storage.path().display()
@@ -36,6 +48,164 @@ pub fn resolve_existing_storage(path: Option<PathBuf>) -> Result<Storage> {
     Ok(storage)
 }
 
+/// Writes `data` to `path`, restricting the file to owner-only (0600) on Unix so
+/// exported plaintext secrets are not world/group readable (mirrors the keystore's
+/// permission hardening in `storage.rs`).
+pub fn write_file_secure(path: &Path, data: &[u8]) -> Result<()> {
+    use std::io::Write as _;
+
+    create_file_secure(path)?.write_all(data)?;
+    Ok(())
+}
+
+/// Creates (or truncates) `path` for writing plaintext, owner-only (0600) on Unix.
+#[cfg(unix)]
+pub fn create_file_secure(path: &Path) -> Result<std::fs::File> {
+    use std::fs::{OpenOptions, Permissions};
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // Tighten before writing, in case the file already existed with looser modes.
+    file.set_permissions(Permissions::from_mode(0o600))?;
+
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+pub fn create_file_secure(path: &Path) -> Result<std::fs::File> {
+    Ok(std::fs::File::create(path)?)
+}
+
+/// Size of the keyfiles created by `init` and `rekey`.
+const KEYFILE_LEN: usize = 64;
+
+/// Reads the keyfile at `path`, first creating it with random contents (owner-only) if it
+/// does not exist yet.
+pub fn read_or_create_keyfile(path: &Path) -> Result<Keyfile> {
+    if !path.exists() {
+        let mut contents = Zeroizing::new([0u8; KEYFILE_LEN]);
+        OsEntropy.fill(EntropyUse::Key, &mut *contents)?;
+        write_file_secure(path, &*contents)?;
+        eprintln!(
+            "created keyfile {}; keep a copy of it: the store cannot be opened without it",
+            path.display()
+        );
+    }
+    Keyfile::read(path)
+}
+
+/// Reads a passphrase (of a snapshot or bundle) from the environment variable `var`.
+pub fn passphrase_from_env(var: &str) -> Result<Zeroizing<String>> {
+    let passphrase =
+        Zeroizing::new(std::env::var(var).map_err(|_| anyhow::anyhow!("{var} is not set"))?);
+    if passphrase.is_empty() {
+        bail!("{var} is empty");
+    }
+    Ok(passphrase)
+}
+
+/// Parses a file descriptor for handing secrets to another process; 0-2 are rejected
+/// since they are stdin/stdout/stderr.
+pub fn parse_fd(s: &str) -> Result<u32> {
+    let fd: u32 = s
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid file descriptor '{s}'"))?;
+    if fd < 3 {
+        bail!("file descriptor must be 3 or higher (0-2 are stdin/stdout/stderr)");
+    }
+    if fd > i32::MAX as u32 {
+        bail!("invalid file descriptor '{s}'");
+    }
+    Ok(fd)
+}
+
+/// Parses an expiry: a duration from now (`30m`, `12h`, `90d`, `2w`), a date
+/// (`2026-12-31`, midnight UTC) or an RFC 3339 timestamp.
+pub fn parse_expiry(s: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    let invalid = || anyhow::anyhow!("invalid expiry '{s}' (use e.g. 90d, 12h, 2w or 2026-12-31)");
+    let (count, unit) = s.split_at(s.len().saturating_sub(1));
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "m" => TimeDelta::try_minutes(count),
+        "h" => TimeDelta::try_hours(count),
+        "d" => TimeDelta::try_days(count),
+        "w" => TimeDelta::try_weeks(count),
+        _ => None,
+    };
+    duration
+        .filter(|d| *d > TimeDelta::zero())
+        .and_then(|d| Utc::now().checked_add_signed(d))
+        .ok_or_else(invalid)
+}
+
+/// Parses a `--field` argument: `NAME=VALUE`, or just `NAME` to prompt for the value.
+pub fn parse_field(s: &str) -> Result<(String, Option<String>)> {
+    let (name, value) = match s.split_once('=') {
+        Some((name, value)) => (name, Some(value.to_string())),
+        None => (s, None),
+    };
+    if name.is_empty() {
+        bail!("expected NAME=VALUE or NAME, got '{s}'");
+    }
+    Ok((name.to_string(), value))
+}
+
+/// Returns the values of parsed `--field` arguments, prompting for those given without
+/// one.
+pub fn read_fields(
+    fields: Vec<(String, Option<String>)>,
+) -> Result<Vec<(String, Zeroizing<String>)>> {
+    fields
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Some(value) => Zeroizing::new(value),
+                None => Zeroizing::new(rpassword::prompt_password(format!("{name}: "))?),
+            };
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Writes `data` to the inherited file descriptor `fd` and closes it.
+///
+/// The descriptor is used as-is rather than reopened, so `3>>file` appends and pipes
+/// see a single writer.
+#[cfg(unix)]
+pub fn write_to_fd(fd: u32, data: &[u8]) -> Result<()> {
+    use std::io::Write as _;
+    use std::os::fd::FromRawFd;
+
+    let fd = fd as i32;
+    // SAFETY: F_GETFD only queries the descriptor flags.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        bail!("file descriptor {fd} is not open");
+    }
+    // SAFETY: the descriptor is open, was inherited for this purpose, and nothing else
+    // in the process uses it; taking ownership closes it once written.
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(data)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn write_to_fd(_fd: u32, _data: &[u8]) -> Result<()> {
+    bail!("writing to a file descriptor is only supported on Unix")
+}
+
 #[derive(Debug, Args)]
 pub struct Argon2Args {
     /// Argon2 memory cost in KiB (default: 65536)
@@ -52,20 +222,292 @@ pub struct Argon2Args {
 }
 
 impl Argon2Args {
-    pub fn to_kdf_params(&self) -> anyhow::Result<KdfParams> {
-        let default = KdfParams::default();
+    pub fn to_argon2_params(&self) -> anyhow::Result<Argon2Params> {
+        self.apply_to(Argon2Params::default())
+    }
 
-        KdfParams::new(
-            self.mem_cost_kib.unwrap_or(default.mem_cost_kib()),
-            self.time_cost.unwrap_or(default.time_cost()),
-            self.parallelism.unwrap_or(default.parallelism()),
+    /// Returns `base` with the parameters given on the command line replaced.
+    pub fn apply_to(&self, base: Argon2Params) -> anyhow::Result<Argon2Params> {
+        Argon2Params::new(
+            self.mem_cost_kib.unwrap_or(base.mem_cost_kib()),
+            self.time_cost.unwrap_or(base.time_cost()),
+            self.parallelism.unwrap_or(base.parallelism()),
         )
     }
+
+    fn is_empty(&self) -> bool {
+        self.mem_cost_kib.is_none() && self.time_cost.is_none() && self.parallelism.is_none()
+    }
+}
+
+/// Choice of key derivation function: Argon2id with the `--argon-*` options, or scrypt
+/// with the `--scrypt-*` options.
+#[derive(Debug, Args)]
+pub struct KdfArgs {
+    /// Key derivation function: argon2id (default) or scrypt
+    #[arg(long, value_name = "KDF", value_parser = ["argon2id", "scrypt"])]
+    pub kdf: Option<String>,
+
+    #[command(flatten)]
+    pub argon2: Argon2Args,
+
+    /// scrypt cost N, a power of two (default: 131072)
+    #[arg(long = "scrypt-n", value_name = "N")]
+    pub scrypt_n: Option<u64>,
+
+    /// scrypt block size r (default: 8)
+    #[arg(long = "scrypt-r", value_name = "R")]
+    pub scrypt_r: Option<u32>,
+
+    /// scrypt parallelism p (default: 1)
+    #[arg(long = "scrypt-p", value_name = "P")]
+    pub scrypt_p: Option<u32>,
+}
+
+impl KdfArgs {
+    pub fn to_kdf_params(&self) -> anyhow::Result<KdfParams> {
+        self.apply_to(KdfParams::default())
+    }
+
+    /// Returns `true` if no KDF option was given.
+    pub fn is_empty(&self) -> bool {
+        self.kdf.is_none()
+            && self.argon2.is_empty()
+            && self.scrypt_n.is_none()
+            && self.scrypt_r.is_none()
+            && self.scrypt_p.is_none()
+    }
+
+    /// Returns `base` with the KDF and parameters given on the command line replaced;
+    /// `--kdf` starts from the defaults of that KDF.
+    pub fn apply_to(&self, base: KdfParams) -> anyhow::Result<KdfParams> {
+        let base = match &self.kdf {
+            Some(name) if name != base.name() => KdfParams::default_for(name)
+                .ok_or_else(|| anyhow::anyhow!("unknown KDF '{name}'"))?,
+            _ => base,
+        };
+        let scrypt_given =
+            self.scrypt_n.is_some() || self.scrypt_r.is_some() || self.scrypt_p.is_some();
+        match base {
+            KdfParams::Argon2id(base) => {
+                if scrypt_given {
+                    anyhow::bail!("--scrypt-* options need --kdf scrypt");
+                }
+                self.argon2.apply_to(base).map(KdfParams::Argon2id)
+            }
+            KdfParams::Scrypt(base) => {
+                if !self.argon2.is_empty() {
+                    anyhow::bail!("--argon-* options do not apply to scrypt");
+                }
+                let log_n = match self.scrypt_n {
+                    Some(n) if n < 2 || !n.is_power_of_two() => {
+                        anyhow::bail!("--scrypt-n must be a power of two, e.g. 131072")
+                    }
+                    Some(n) => u8::try_from(n.trailing_zeros())?,
+                    None => base.log_n(),
+                };
+                KdfParams::scrypt(
+                    log_n,
+                    self.scrypt_r.unwrap_or(base.r()),
+                    self.scrypt_p.unwrap_or(base.p()),
+                )
+            }
+        }
+    }
+}
+
+/// Strength requirements for the new password or passphrase a command sets.
+#[derive(Debug, Args)]
+pub struct StrengthArgs {
+    /// Score from 0 to 4 the new password must reach (default: the profile's, or 3)
+    #[arg(long, value_name = "SCORE", env = "KEYNEST_MIN_PASSWORD_SCORE",
+          value_parser = clap::value_parser!(u8).range(0..=4))]
+    pub min_password_score: Option<u8>,
+
+    /// Only warn about a weak new password instead of refusing it
+    #[arg(long = "allow-weak")]
+    pub allow_weak: bool,
+}
+
+impl StrengthArgs {
+    /// Makes [`auth::check_new_password`] use the options given on the command line.
+    pub fn apply(&self) {
+        if let Some(score) = self.min_password_score {
+            auth::set_min_password_score(score);
+        }
+        if self.allow_weak {
+            auth::set_allow_weak();
+        }
+    }
+}
+
+/// Token of the operation currently waiting for Ctrl-C, if any.
+static INTERRUPT: Mutex<Option<CancelToken>> = Mutex::new(None);
+
+fn interrupt_slot() -> MutexGuard<'static, Option<CancelToken>> {
+    INTERRUPT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Runs `f` with a token that is cancelled when the user presses Ctrl-C.
+///
+/// A single process-wide handler is installed on first use. Outside of `interruptible`,
+/// Ctrl-C still terminates the process right away (exit status 130).
+pub fn interruptible<T>(f: impl FnOnce(&CancelToken) -> T) -> T {
+    static HANDLER: Once = Once::new();
+    HANDLER.call_once(|| {
+        let _ = ctrlc::set_handler(|| match interrupt_slot().as_ref() {
+            Some(token) => token.cancel(),
+            None => std::process::exit(130),
+        });
+    });
+
+    let token = CancelToken::new();
+    *interrupt_slot() = Some(token.clone());
+    let result = f(&token);
+    *interrupt_slot() = None;
+    result
+}
+
+/// Opens the keystore, with the keyfile given with `--keyfile` if any; Ctrl-C aborts the
+/// key derivation immediately.
+pub fn open_keystore(password: Zeroizing<String>, storage: Storage) -> Result<Keynest> {
+    let keyfile = auth::read_keyfile()?;
+    let result = interruptible(|cancel| match &keyfile {
+        Some(keyfile) => {
+            Keynest::open_with_keyfile_cancellable(password, keyfile, storage.clone(), cancel)
+        }
+        None => Keynest::open_with_storage_cancellable(password, storage.clone(), cancel),
+    });
+    let mut kn = result.map_err(|e| with_repair_hint(e, &storage))?;
+    kn.set_reader(&reader_name());
+    track_chain(&mut kn, storage.path());
+    Ok(kn)
+}
+
+/// Opens the keystore index only (see [`IndexedKeynest`]); Ctrl-C aborts the key
+/// derivation immediately.
+pub fn open_indexed(password: Zeroizing<String>, storage: Storage) -> Result<IndexedKeynest> {
+    let keyfile = auth::read_keyfile()?;
+    let result = interruptible(|cancel| match &keyfile {
+        Some(keyfile) => IndexedKeynest::open_with_keyfile_cancellable(
+            password,
+            keyfile,
+            storage.clone(),
+            cancel,
+        ),
+        None => IndexedKeynest::open_with_storage_cancellable(password, storage.clone(), cancel),
+    });
+    result.map_err(|e| with_repair_hint(e, &storage))
+}
+
+/// Opens the keystore with the key held by `keynest agent` if one is in use, or else
+/// with the master password (see [`open_keystore`]).
+pub fn unlock_keystore(storage: Storage) -> Result<Keynest> {
+    if let Some(Ok(mut kn)) =
+        agent::key_for(&storage).map(|key| Keynest::open_with_key(&key, storage.clone()))
+    {
+        kn.set_reader(&reader_name());
+        track_chain(&mut kn, storage.path());
+        return Ok(kn);
+    }
+    let unlock = auth::unlock(storage.path())?;
+    let from_keychain = unlock.is_keychain();
+    open_keystore(unlock.into_password(), storage)
+        .map_err(|e| stale_keychain_hint(e, from_keychain))
+}
+
+/// Returns the chain witness of the store at `store`: in `$KEYNEST_CHAIN_DIR` if set,
+/// or else in the platform data directory.
+pub fn chain_witness(store: &Path) -> Result<ChainWitness> {
+    match std::env::var_os("KEYNEST_CHAIN_DIR") {
+        Some(dir) => ChainWitness::in_dir(Path::new(&dir), store),
+        None => ChainWitness::default_for(store),
+    }
+}
+
+/// Has every save of `kn`, opened from `store`, advance the chain witness of the store
+/// once `keynest verify-chain` has started tracking it on this machine.
+pub fn track_chain(kn: &mut Keynest, store: &Path) {
+    if let Ok(witness) = chain_witness(store) {
+        if witness.exists() {
+            kn.set_chain_witness(Some(witness));
+        }
+    }
+}
+
+/// Name recorded in read receipts: `KEYNEST_READER`, or else `user@host`.
+pub fn reader_name() -> String {
+    if let Some(reader) = std::env::var("KEYNEST_READER")
+        .ok()
+        .filter(|r| !r.is_empty())
+    {
+        return reader;
+    }
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    match hostname() {
+        Some(host) => format!("{user}@{host}"),
+        None => user,
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let host = String::from_utf8_lossy(&buf[..len]).into_owned();
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Opens the keystore index with the key held by `keynest agent` if one is in use, or
+/// else with the master password (see [`open_indexed`]).
+pub fn unlock_indexed(storage: Storage) -> Result<IndexedKeynest> {
+    if let Some(Ok(kn)) =
+        agent::key_for(&storage).map(|key| IndexedKeynest::open_with_key(&key, storage.clone()))
+    {
+        return Ok(kn);
+    }
+    let unlock = auth::unlock(storage.path())?;
+    let from_keychain = unlock.is_keychain();
+    open_indexed(unlock.into_password(), storage).map_err(|e| stale_keychain_hint(e, from_keychain))
+}
+
+/// Points at `keynest keychain enable` when the password from the OS keychain failed to
+/// open the store, e.g. after a rekey without `--use-keychain`.
+fn stale_keychain_hint(e: anyhow::Error, from_keychain: bool) -> anyhow::Error {
+    if from_keychain {
+        e.context("the password in the OS keychain did not open the store; run `keynest keychain enable` to replace it")
+    } else {
+        e
+    }
+}
+
+/// Points at `keynest repair` when opening failed because the file is damaged, and at
+/// `--keyfile` when the keyfile is missing.
+fn with_repair_hint(e: anyhow::Error, storage: &Storage) -> anyhow::Error {
+    if e.is::<MissingKeyfile>() {
+        return anyhow::anyhow!("{e}; pass it with --keyfile or $KEYNEST_KEYFILE");
+    }
+    let damaged = std::fs::read(storage.path()).is_ok_and(|data| format::parse(&data).is_err());
+    if damaged {
+        e.context("the keystore file is damaged; run `keynest repair` to restore it from a temporary file or backup")
+    } else {
+        e
+    }
 }
 
 pub fn copy_to_clipboard(secret: &str, timeout: u64) -> anyhow::Result<()> {
     use arboard::Clipboard;
-    use std::{thread::sleep, time::Duration};
+    use std::time::{Duration, Instant};
 
     let mut cb = Clipboard::new()?;
     let old = cb.get_text().ok();
@@ -75,18 +517,14 @@ pub fn copy_to_clipboard(secret: &str, timeout: u64) -> anyhow::Result<()> {
     eprintln!("Secret copied to clipboard for {timeout}s");
     eprintln!("Press Ctrl+C to clear early");
 
-    let old_clip = old.clone();
-    ctrlc::set_handler(move || {
-        if let Ok(mut cb) = Clipboard::new() {
-            let _ = cb.set_text(old_clip.clone().unwrap_or_default());
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    interruptible(|cancel| {
+        while Instant::now() < deadline && !cancel.is_cancelled() {
+            std::thread::sleep(Duration::from_millis(100));
         }
-        sleep(Duration::from_millis(100));
-        std::process::exit(0);
-    })?;
+    });
 
     let had_old = old.is_some();
-    sleep(Duration::from_secs(timeout));
-
     let mut cb = Clipboard::new()?;
     cb.set_text(old.unwrap_or_default())?;
 
@@ -94,3 +532,100 @@ pub fn copy_to_clipboard(secret: &str, timeout: u64) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Strips a single trailing newline that editors and `echo` commonly append, so the
+/// stored secret does not carry a stray "\n" (handles "\n" and "\r\n").
+pub fn strip_trailing_newline(mut content: Zeroizing<String>) -> Zeroizing<String> {
+    if content.ends_with('\n') {
+        content.pop();
+        if content.ends_with('\r') {
+            content.pop();
+        }
+    }
+    content
+}
+
+/// Asks a yes/no question on the terminal; defaults to "no".
+///
+/// Without a terminal there is nobody to ask, so `--yes` is required instead.
+pub fn confirm(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!("confirmation required; re-run with --yes to proceed non-interactively");
+    }
+    ask(question)
+}
+
+/// Compression of records, as given to `--compress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompressionMethod {
+    /// Zstandard, the default
+    Zstd,
+    /// Raw DEFLATE
+    Deflate,
+}
+
+impl From<CompressionMethod> for format::Compression {
+    fn from(method: CompressionMethod) -> Self {
+        match method {
+            CompressionMethod::Zstd => Self::Zstd,
+            CompressionMethod::Deflate => Self::Deflate,
+        }
+    }
+}
+
+/// A restriction of an entry's access policy, as given to `--restrict`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Restriction {
+    /// Never print the value to stdout (clipboard, --fd and files only)
+    NoPrint,
+    /// Ask for confirmation on every access
+    Confirm,
+}
+
+/// Returns `policy` with each of `restrictions` turned on or, with `on == false`, off.
+pub fn with_restrictions(
+    mut policy: AccessPolicy,
+    restrictions: &[Restriction],
+    on: bool,
+) -> AccessPolicy {
+    for restriction in restrictions {
+        policy = match restriction {
+            Restriction::NoPrint => policy.with_no_print(on),
+            Restriction::Confirm => policy.with_confirm(on),
+        };
+    }
+    policy
+}
+
+/// Enforces the access policy of `key` before its value is handed out; `to_stdout`
+/// tells whether the value (or anything revealing it) goes to stdout.
+///
+/// `policy` is `None` for a missing key, which the caller reports. Confirmation
+/// cannot be skipped with `--yes`: without a terminal, access is refused.
+pub fn check_access(key: &str, policy: Option<AccessPolicy>, to_stdout: bool) -> Result<()> {
+    let Some(policy) = policy else {
+        return Ok(());
+    };
+    if policy.no_print() && to_stdout {
+        bail!("'{key}' must not be printed to stdout; use --clip, --fd or a file instead");
+    }
+    if policy.confirm() {
+        if !io::stdin().is_terminal() {
+            bail!("'{key}' requires confirmation on access, but stdin is not a terminal");
+        }
+        if !ask(&format!("Reveal '{key}'?"))? {
+            bail!("access to '{key}' was not confirmed");
+        }
+    }
+    Ok(())
+}
+
+fn ask(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
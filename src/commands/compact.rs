@@ -0,0 +1,51 @@
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest compact --journal                      Append saves to <store>.journal from now on
+  keynest compact                                Fold the journal into the keystore file
+  keynest compact --no-journal                   Fold the journal in and rewrite on every save
+
+With the journal on, a save appends only the changed entries, encrypted, instead of
+rewriting the whole file, which keeps saves of large stores fast. The file is rewritten
+automatically once the journal reaches half its size or 1024 saves; backups are only
+made when it is.")]
+pub struct CompactCommand {
+    /// Append saves to a journal instead of rewriting the keystore file
+    #[arg(long, overrides_with = "no_journal")]
+    pub journal: bool,
+
+    /// Rewrite the keystore file on every save (the default)
+    #[arg(long = "no-journal")]
+    pub no_journal: bool,
+}
+
+impl Command for CompactCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        if self.journal || self.no_journal {
+            kn.set_journal(self.journal)?;
+        }
+        let folded = kn.compact()?;
+        match folded {
+            0 => println!("keystore file is up to date"),
+            1 => println!("folded 1 save from the journal into the keystore file"),
+            n => println!("folded {n} saves from the journal into the keystore file"),
+        }
+        if self.journal {
+            println!("journal enabled");
+        } else if self.no_journal {
+            println!("journal disabled");
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
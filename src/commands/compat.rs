@@ -0,0 +1,91 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+use keynest::format::CURRENT_VERSION;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest compat                     Show the file format written on save and the copy for older versions
+  keynest compat set 2               Also write a v2 copy to <store>.v2 on every save
+  keynest compat clear               Stop writing the copy and delete it
+
+The store itself stays in the current format. The copy opens with the same password in
+keynest versions that cannot read it, but is replaced on every save, so changes made to
+it are lost. Stores with a keyfile, keyslots, DPAPI binding, scrypt or AES-256-GCM
+cannot have a v2 copy, and saves fail once the store outgrows v2's 64 KiB payload.")]
+pub struct CompatCommand {
+    #[command(subcommand)]
+    pub action: Option<CompatAction>,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+#[derive(Subcommand)]
+pub enum CompatAction {
+    /// Also write the store in this format version beside it on every save (only 2)
+    Set {
+        /// Format version
+        version: u8,
+    },
+    /// Stop writing the copy and delete it
+    Clear,
+}
+
+impl Command for CompatCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage.clone())?;
+
+        match self.action {
+            None => {
+                let version = kn.write_format()?;
+                let copy = kn.compat_copy()?;
+                if self.json {
+                    print_json(&serde_json::json!({
+                        "write_format": version,
+                        "current_format": CURRENT_VERSION,
+                        "compat_copy": copy.map(|copy| serde_json::json!({
+                            "version": copy,
+                            "path": storage.compat_path(copy),
+                        })),
+                    }))?;
+                } else {
+                    if version == CURRENT_VERSION {
+                        println!("Write format:      v{version} (current)");
+                    } else {
+                        println!("Write format:      v{version} (migrated)");
+                    }
+                    match copy {
+                        Some(copy) => println!(
+                            "Copy:              v{copy} at {}",
+                            storage.compat_path(copy).display()
+                        ),
+                        None => println!("Copy:              none"),
+                    }
+                }
+            }
+            Some(CompatAction::Set { version }) => {
+                kn.set_compat_copy(Some(version))?;
+                kn.save()?;
+                println!(
+                    "keystore saved, with a v{version} copy at {}",
+                    storage.compat_path(version).display()
+                );
+            }
+            Some(CompatAction::Clear) => {
+                kn.set_compat_copy(None)?;
+                kn.save()?;
+                println!("keystore saved without a copy for older versions");
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
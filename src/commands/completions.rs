@@ -0,0 +1,287 @@
+//! Shell completion scripts, and the hidden helper they call to complete key names.
+//!
+//! The scripts are the ones generated by clap_complete from the CLI definition, with the
+//! generated completer renamed and wrapped: before falling back to it, the wrapper runs
+//! `keynest __complete-keys --cword N -- WORDS...` with the words of the command line.
+//! The helper decides from the CLI definition whether the cursor is on the key of `get`,
+//! `update` or `remove`; if so it prints the matching keys, otherwise it fails without
+//! output and the generated completer takes over. The store is only opened (and the
+//! password only asked for) when a key is completed.
+
+use anyhow::{Result, bail};
+use clap::{Args, CommandFactory, ValueEnum};
+use clap_complete::Shell;
+use keynest::config::Config;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::cli::Cli;
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_indexed};
+use crate::commands::profile;
+
+/// Name of the hidden helper command that lists keys.
+pub const COMPLETE_KEYS: &str = "__complete-keys";
+
+/// Commands whose first argument is the key of an existing secret.
+const KEY_COMMANDS: &[&str] = &["get", "update", "remove"];
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest completions bash > ~/.local/share/bash-completion/completions/keynest
+  keynest completions zsh > ~/.zfunc/_keynest     (with fpath+=~/.zfunc before compinit)
+  keynest completions fish > ~/.config/fish/completions/keynest.fish
+  keynest completions powershell | Out-String | Invoke-Expression
+
+Besides commands and options, the scripts complete the key names of get, update and
+remove. Listing the keys needs the master password: it is read like for any other
+command (--password-fd, KEYNEST_PASSWORD, or a prompt), only when a key is completed.")]
+pub struct CompletionsCommand {
+    /// Shell to generate the completion script for
+    pub shell: CompletionShell,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl From<CompletionShell> for Shell {
+    fn from(shell: CompletionShell) -> Self {
+        match shell {
+            CompletionShell::Bash => Shell::Bash,
+            CompletionShell::Zsh => Shell::Zsh,
+            CompletionShell::Fish => Shell::Fish,
+            CompletionShell::Powershell => Shell::PowerShell,
+        }
+    }
+}
+
+impl Command for CompletionsCommand {
+    fn run(self, _store: Option<PathBuf>) -> Result<ExitCode> {
+        print!("{}", script(self.shell.into())?);
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Lists the keys for the word under the cursor; used by the completion scripts.
+#[derive(Args)]
+pub struct CompleteKeysCommand {
+    /// Index of the word under the cursor in WORDS (default: the last word)
+    #[arg(long)]
+    pub cword: Option<usize>,
+
+    /// The words of the command line after the program name
+    #[arg(last = true, allow_hyphen_values = true)]
+    pub words: Vec<String>,
+}
+
+impl Command for CompleteKeysCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let cword = self.cword.unwrap_or(self.words.len().saturating_sub(1));
+        let Some(target) = key_target(&self.words, cword) else {
+            return Ok(ExitCode::FAILURE);
+        };
+
+        let store = match (target.store, target.profile) {
+            (Some(path), _) => Some(path),
+            (None, Some(name)) => Config::load(&profile::config_path()?)?
+                .profile(&name)?
+                .store()
+                .map(PathBuf::from),
+            (None, None) => store,
+        };
+        let storage = resolve_existing_storage(store)?;
+        let kn = unlock_indexed(storage)?;
+        for key in kn.list_prefix(&target.prefix) {
+            println!("{key}");
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// A key being completed, and the store named on the command line.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct KeyTarget {
+    prefix: String,
+    store: Option<PathBuf>,
+    profile: Option<String>,
+}
+
+/// Returns what to complete if word `cword` of `words` is the key argument of one of
+/// [`KEY_COMMANDS`], or `None` for anything else (commands, options, option values and
+/// further arguments).
+fn key_target(words: &[String], cword: usize) -> Option<KeyTarget> {
+    let prefix = words.get(cword).map_or("", String::as_str);
+    if prefix.starts_with('-') {
+        return None;
+    }
+    let before = &words[..cword.min(words.len())];
+
+    let mut cli = Cli::command();
+    cli.build();
+    let mut target = KeyTarget {
+        prefix: prefix.to_string(),
+        ..KeyTarget::default()
+    };
+    let mut command: Option<&clap::Command> = None;
+    let mut iter = before.iter();
+    while let Some(word) = iter.next() {
+        if word == "--" {
+            return None;
+        }
+        if word.starts_with('-') {
+            let (name, inline) = match word.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (word.as_str(), None),
+            };
+            let Some(arg) = find_option(command.unwrap_or(&cli), name) else {
+                continue;
+            };
+            if !arg.get_action().takes_values() {
+                continue;
+            }
+            // Without a value yet, the word under the cursor is the value: not a key.
+            let value = match inline {
+                Some(value) => value,
+                None => iter.next()?,
+            };
+            match arg.get_id().as_str() {
+                "store" => target.store = Some(PathBuf::from(value)),
+                "profile" => target.profile = Some(value.to_string()),
+                _ => {}
+            }
+        } else if command.is_none() {
+            let found = cli.find_subcommand(word)?;
+            if !KEY_COMMANDS.contains(&found.get_name()) {
+                return None;
+            }
+            command = Some(found);
+        } else {
+            // The key was already given.
+            return None;
+        }
+    }
+    command.map(|_| target)
+}
+
+/// Finds the option `--long` or `-s` of `command`. Clustered short flags such as `-cj`
+/// are not looked up: they cannot take a value.
+fn find_option<'a>(command: &'a clap::Command, name: &str) -> Option<&'a clap::Arg> {
+    match name.strip_prefix("--") {
+        Some(long) => command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long)),
+        None => {
+            let mut chars = name.strip_prefix('-')?.chars();
+            let short = chars.next().filter(|_| chars.next().is_none())?;
+            command
+                .get_arguments()
+                .find(|arg| arg.get_short() == Some(short))
+        }
+    }
+}
+
+/// Returns the completion script for `shell`: the generated one, wrapped to complete
+/// key names in bash, zsh, fish and PowerShell.
+///
+/// # Errors
+///
+/// Returns an error if the generated script does not have the expected layout.
+pub fn script(shell: Shell) -> Result<String> {
+    // The generators join subcommand names with `__`, which the helper's name breaks.
+    let mut cli = Cli::command().mut_subcommand(COMPLETE_KEYS, |c| c.name("complete-keys"));
+    let mut out = Vec::new();
+    clap_complete::generate(shell, &mut cli, "keynest", &mut out);
+    let script = String::from_utf8(out)?;
+    Ok(match shell {
+        Shell::Bash => {
+            let script = replace_once(&script, "_keynest() {\n", "_keynest_static() {\n")?;
+            script + BASH_KEYS
+        }
+        Shell::Zsh => {
+            // The wrapper must be defined before the generated script calls `_keynest`.
+            let script = replace_once(&script, "\n_keynest() {\n", "\n_keynest_static() {\n")?;
+            let end = "\nif [ \"$funcstack[1]\" = \"_keynest\" ]; then\n";
+            replace_once(&script, end, &format!("{ZSH_KEYS}{end}"))?
+        }
+        Shell::Fish => script + FISH_KEYS,
+        Shell::PowerShell => {
+            let script = replace_once(
+                &script,
+                "Register-ArgumentCompleter -Native -CommandName 'keynest' -ScriptBlock {\n",
+                "$global:__KeynestStaticCompleter = {\n",
+            )?;
+            script + POWERSHELL_KEYS
+        }
+        _ => script,
+    })
+}
+
+/// Replaces `from`, which must occur exactly once in the generated `script`.
+fn replace_once(script: &str, from: &str, to: &str) -> Result<String> {
+    if script.matches(from).count() != 1 {
+        bail!("unexpected layout of the generated completion script");
+    }
+    Ok(script.replacen(from, to, 1))
+}
+
+const BASH_KEYS: &str = r#"
+_keynest() {
+    local keys
+    if keys="$("$1" __complete-keys --cword "$((COMP_CWORD - 1))" -- "${COMP_WORDS[@]:1}" 2>/dev/null)"; then
+        local IFS=$'\n'
+        COMPREPLY=($(compgen -W "$keys" -- "${COMP_WORDS[COMP_CWORD]}"))
+        return 0
+    fi
+    _keynest_static "$@"
+}
+"#;
+
+const ZSH_KEYS: &str = r#"
+_keynest() {
+    local output
+    local -a keys
+    if output="$($words[1] __complete-keys --cword $((CURRENT - 2)) -- "${(@)words[2,-1]}" 2>/dev/null)"; then
+        keys=(${(f)output})
+        compadd -a keys
+        return
+    fi
+    _keynest_static "$@"
+}
+"#;
+
+const FISH_KEYS: &str = r#"
+function __fish_keynest_complete_keys
+    set -l words (commandline -opc)
+    set -l program $words[1]
+    set -e words[1]
+    set -g __fish_keynest_keys ($program __complete-keys --cword (count $words) -- $words (commandline -ct) 2>/dev/null)
+end
+
+complete -c keynest -f -n __fish_keynest_complete_keys -a '$__fish_keynest_keys'
+"#;
+
+const POWERSHELL_KEYS: &str = r#"
+Register-ArgumentCompleter -Native -CommandName 'keynest' -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+
+    $program = $commandAst.CommandElements[0].ToString()
+    $words = @($commandAst.CommandElements |
+        Select-Object -Skip 1 |
+        Where-Object { $_.Extent.EndOffset -lt $cursorPosition } |
+        ForEach-Object { $_.ToString() })
+    $keys = @(& $program __complete-keys --cword $words.Count -- @words $wordToComplete 2>$null)
+    if ($LASTEXITCODE -eq 0) {
+        $keys | Where-Object { $_ -like "$wordToComplete*" } | ForEach-Object {
+            [CompletionResult]::new($_, $_, [CompletionResultType]::ParameterValue, $_)
+        }
+        return
+    }
+    & $global:__KeynestStaticCompleter $wordToComplete $commandAst $cursorPosition
+}
+"#;
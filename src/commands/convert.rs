@@ -0,0 +1,190 @@
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    CompressionMethod, print_json, resolve_existing_storage, unlock_keystore,
+};
+use keynest::format::{Compression, Padding, PayloadEncoding, Serialization};
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Encoding {
+    Json,
+    Msgpack,
+}
+
+impl From<Encoding> for Serialization {
+    fn from(e: Encoding) -> Self {
+        match e {
+            Encoding::Json => Serialization::Json,
+            Encoding::Msgpack => Serialization::MessagePack,
+        }
+    }
+}
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest convert                                Show the current payload encoding
+  keynest convert --encoding msgpack --compress  Store records as zstd-compressed MessagePack
+  keynest convert --compress=deflate             Compress records with DEFLATE instead
+  keynest convert --pad                          Pad records to hide their exact size
+  keynest convert --per-entry                    Encrypt every entry as a record of its own
+  keynest convert --deterministic --per-entry    Keep unchanged records byte-identical for git
+  keynest convert --encoding json --no-compress --no-pad   Back to plain JSON
+
+With --per-entry, every entry is encrypted with a key of its own, wrapped with the store
+key, and `keynest get` and the other read-only commands decrypt only the entries they
+read, instead of the section of up to 256 entries holding them.
+With --deterministic, records are encrypted under nonces derived from their contents, so
+a save rewrites only the records of changed entries and the index, and git or Syncthing
+see small changes; anyone holding two versions learns which records stayed the same.")]
+pub struct ConvertCommand {
+    /// Serialization of the encrypted records
+    #[arg(long, value_enum)]
+    pub encoding: Option<Encoding>,
+
+    /// Compress records before encryption, with zstd (the default) or deflate
+    #[arg(
+        long,
+        value_enum,
+        value_name = "METHOD",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "zstd",
+        overrides_with = "no_compress"
+    )]
+    pub compress: Option<CompressionMethod>,
+
+    /// Store records uncompressed
+    #[arg(long = "no-compress")]
+    pub no_compress: bool,
+
+    /// Pad records to the next power of two before encryption
+    #[arg(long, overrides_with = "no_pad")]
+    pub pad: bool,
+
+    /// Store records unpadded
+    #[arg(long = "no-pad")]
+    pub no_pad: bool,
+
+    /// Encrypt every entry as a record of its own
+    #[arg(long, overrides_with = "grouped")]
+    pub per_entry: bool,
+
+    /// Encrypt entries in sections of up to 256 (the default)
+    #[arg(long)]
+    pub grouped: bool,
+
+    /// Write unchanged records as the same bytes on every save
+    #[arg(long, overrides_with = "no_deterministic")]
+    pub deterministic: bool,
+
+    /// Encrypt records under random nonces (the default)
+    #[arg(long = "no-deterministic")]
+    pub no_deterministic: bool,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+impl Command for ConvertCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let current = kn.payload_encoding();
+        let size_before = kn.info()?.file_size();
+        let per_entry = kn.per_entry_records()?;
+        let deterministic = kn.deterministic_records()?;
+
+        if self.encoding.is_none()
+            && self.compress.is_none()
+            && !(self.no_compress
+                || self.pad
+                || self.no_pad
+                || self.per_entry
+                || self.grouped
+                || self.deterministic
+                || self.no_deterministic)
+        {
+            if self.json {
+                print_json(&serde_json::json!({
+                    "payload_encoding": current.to_string(),
+                    "per_entry_records": per_entry,
+                    "deterministic_records": deterministic,
+                    "file_size": size_before,
+                }))?;
+            } else {
+                println!("Payload encoding:  {current}");
+                println!(
+                    "Records:           {}",
+                    if per_entry {
+                        "one per entry"
+                    } else {
+                        "grouped"
+                    }
+                );
+                println!(
+                    "Nonces:            {}",
+                    if deterministic {
+                        "deterministic"
+                    } else {
+                        "random"
+                    }
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if self.per_entry || self.grouped {
+            kn.set_per_entry_records(self.per_entry)?;
+        }
+        if self.deterministic || self.no_deterministic {
+            kn.set_deterministic_records(self.deterministic)?;
+        }
+
+        let compression = if let Some(method) = self.compress {
+            method.into()
+        } else if self.no_compress {
+            Compression::None
+        } else {
+            current.compression()
+        };
+        let padding = if self.pad {
+            Padding::PowerOfTwo
+        } else if self.no_pad {
+            Padding::None
+        } else {
+            current.padding()
+        };
+        let target = PayloadEncoding::new(
+            self.encoding.map_or(current.serialization(), Into::into),
+            compression,
+            padding,
+        );
+
+        kn.convert(target)?;
+        let size_after = kn.info()?.file_size();
+
+        if self.json {
+            print_json(&serde_json::json!({
+                "from": current.to_string(),
+                "to": target.to_string(),
+                "per_entry_records": kn.per_entry_records()?,
+                "deterministic_records": kn.deterministic_records()?,
+                "size_before": size_before,
+                "size_after": size_after,
+            }))?;
+        } else {
+            println!(
+                "keystore converted from {current} to {target} ({size_before} -> {size_after} bytes)"
+            );
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
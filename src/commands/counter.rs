@@ -0,0 +1,58 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest counter add ca/serial --start 1000     Store a counter; the first `next` prints 1001
+  keynest counter next ca/serial                 Increment the counter, save and print the value
+  keynest get ca/serial                          Print the current value without incrementing
+
+`counter next` saves the store before printing, so a value is never handed out twice. It
+can be skipped if the save fails. Use counters for HOTP counters, serial numbers of an
+offline CA or one-time IDs. `keynest update` can move a counter forward, never back.")]
+pub struct CounterCommand {
+    #[command(subcommand)]
+    pub action: CounterAction,
+}
+
+#[derive(Subcommand)]
+pub enum CounterAction {
+    /// Store a counter entry
+    Add {
+        key: String,
+
+        /// Current value; the first `counter next` returns the value after it
+        #[arg(long, default_value_t = 0)]
+        start: u64,
+    },
+    /// Increment a counter, save the store and print the new value
+    Next { key: String },
+}
+
+impl Command for CounterCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+        match self.action {
+            CounterAction::Add { key, start } => {
+                kn.set_counter(&key, start)?;
+                kn.save()?;
+                println!("stored counter '{key}' at {start}");
+            }
+            CounterAction::Next { key } => {
+                if kn.kind(&key)?.is_none() {
+                    eprintln!("key not found: {key}");
+                    return Ok(ExitCode::from(1));
+                }
+                println!("{}", kn.next_counter(&key)?);
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
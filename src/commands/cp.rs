@@ -0,0 +1,45 @@
+use anyhow::{Result, bail};
+use clap::Args;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "\
+Examples:
+  keynest cp prod/db/password staging/db/password        Copy a secret
+  keynest cp prod/db/password staging/db/password -f     Replace an existing secret
+
+The copy keeps the kind, fields, tags, expiry and attachments of the original.
+Attachments are shared, not duplicated on disk."
+)]
+pub struct CpCommand {
+    /// Key of the secret to copy
+    pub src: String,
+
+    /// Key of the copy
+    pub dst: String,
+
+    /// Replace the destination if it exists
+    #[arg(long, short = 'f')]
+    pub force: bool,
+}
+
+impl Command for CpCommand {
+    fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        if self.src == self.dst {
+            bail!("the source and destination must be different");
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+        kn.copy(&self.src, &self.dst, self.force)?;
+        kn.save()?;
+        println!("Copied '{}' to '{}'", self.src, self.dst);
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
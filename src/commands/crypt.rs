@@ -0,0 +1,534 @@
+//! `keynest crypt`: encrypts files under a passphrase kept in the keystore.
+//!
+//! Files are written in a self-describing format, so decryption needs nothing but the
+//! passphrase: the header names the algorithm and the KDF with its parameters, and is
+//! authenticated along with every chunk.
+//!
+//! ```text
+//! MAGIC "KNEF" (4) | VERSION (1) | ALGORITHM (1) | KDF (1) | MEM_KIB (4) | TIME (4)
+//!   | PARALLELISM (4) | SALT_LEN (1) | SALT | NONCE_LEN (1) | NONCE_PREFIX | CHUNK_LEN (4)
+//! CHUNK*  each CHUNK_LEN bytes of plaintext (the last one shorter, possibly empty)
+//!         encrypted with XChaCha20-Poly1305 under NONCE_PREFIX | COUNTER (4, BE) | LAST (1)
+//! ```
+//!
+//! Integers are little-endian unless noted. The key is Argon2id of the passphrase. The
+//! counter and the last-chunk flag in the nonce make reordered, dropped or truncated
+//! chunks fail authentication.
+//!
+//! With `--openssl` the file is instead what `openssl enc -aes-256-cbc -pbkdf2` writes
+//! (`Salted__`, an 8-byte salt, AES-256-CBC with PKCS#7 padding; key and IV from
+//! PBKDF2-HMAC-SHA256 with 10000 iterations), for exchange with machines without keynest.
+//! That format is not authenticated.
+
+use anyhow::{Context, Result, bail};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use clap::{Args, Subcommand};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    Argon2Args, check_access, create_file_secure, interruptible, resolve_existing_storage,
+    unlock_indexed,
+};
+use keynest::{Argon2Params, KdfParams, derive_key_cancellable};
+
+const MAGIC: &[u8; 4] = b"KNEF";
+const VERSION: u8 = 1;
+const ALGORITHM_XCHACHA20_POLY1305: u8 = 1;
+const KDF_ARGON2ID: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 19;
+const TAG_LEN: usize = 16;
+/// Plaintext bytes per chunk.
+const CHUNK_LEN: usize = 64 * 1024;
+const MAX_CHUNK_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest generate backups/key --length 40        Create a passphrase for the files
+  keynest crypt encrypt db.dump --key-entry backups/key
+                                                 Write db.dump.enc
+  keynest crypt decrypt db.dump.enc --key-entry backups/key
+                                                 Write db.dump
+  tar c photos | keynest crypt encrypt - --key-entry backups/key -o photos.tar.enc
+  keynest crypt decrypt photos.tar.enc --key-entry backups/key -o - | tar x
+  keynest crypt encrypt notes.txt --key-entry backups/key --openssl
+                                                 Decrypt elsewhere with: openssl enc -d -aes-256-cbc -pbkdf2 -in notes.txt.enc
+
+The value of the key entry is the passphrase. Encrypted files name their algorithm and
+key derivation in a header, so 'crypt decrypt' needs only the key entry; it also reads
+files written by 'openssl enc -aes-256-cbc -pbkdf2' with the default iteration count.
+Decrypted files are owner-only and appear only once fully decrypted and authenticated.")]
+pub struct CryptCommand {
+    #[command(subcommand)]
+    pub action: CryptAction,
+}
+
+#[derive(Subcommand)]
+pub enum CryptAction {
+    /// Encrypt a file with the passphrase stored in a key entry
+    Encrypt {
+        /// File to encrypt, or - for standard input
+        file: PathBuf,
+
+        #[command(flatten)]
+        target: Target,
+
+        /// Write the format of 'openssl enc -aes-256-cbc -pbkdf2' instead (not authenticated)
+        #[arg(long, conflicts_with_all = ["mem_cost_kib", "time_cost", "parallelism"])]
+        openssl: bool,
+
+        #[command(flatten)]
+        argon: Argon2Args,
+    },
+    /// Decrypt a file written by 'crypt encrypt' or 'openssl enc'
+    Decrypt {
+        /// File to decrypt, or - for standard input
+        file: PathBuf,
+
+        #[command(flatten)]
+        target: Target,
+    },
+}
+
+#[derive(Args)]
+pub struct Target {
+    /// Entry whose value is the passphrase
+    #[arg(long, value_name = "KEY")]
+    key_entry: String,
+
+    /// Output file, or - for standard output [default: FILE.enc when encrypting, FILE
+    /// without .enc when decrypting]
+    #[arg(long, short = 'o', value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Replace the output file if it exists
+    #[arg(long)]
+    force: bool,
+}
+
+impl Command for CryptCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let (file, target) = match &self.action {
+            CryptAction::Encrypt { file, target, .. } | CryptAction::Decrypt { file, target } => {
+                (file, target)
+            }
+        };
+        let encrypt = matches!(self.action, CryptAction::Encrypt { .. });
+        let output = output_path(file, target.output.as_deref(), encrypt)?;
+        if let Some(path) = output.as_ref().filter(|p| !target.force && p.exists()) {
+            bail!(
+                "{} already exists; use --force to replace it",
+                path.display()
+            );
+        }
+        let input: Box<dyn Read> = if is_stdio(file) {
+            Box::new(io::stdin().lock())
+        } else {
+            let f =
+                File::open(file).with_context(|| format!("failed to open {}", file.display()))?;
+            Box::new(f)
+        };
+
+        let storage = resolve_existing_storage(store)?;
+        let passphrase = {
+            let mut kn = unlock_indexed(storage)?;
+            check_access(
+                &target.key_entry,
+                kn.effective_policy(&target.key_entry)?,
+                false,
+            )?;
+            let Some(value) = kn.resolve(&target.key_entry)? else {
+                eprintln!("key not found: {}", target.key_entry);
+                return Ok(ExitCode::from(1));
+            };
+            Zeroizing::new(value.to_string())
+        };
+
+        let mut input = BufReader::new(input);
+        let result = write_output(output.as_deref(), |out| match &self.action {
+            CryptAction::Encrypt { openssl: true, .. } => {
+                openssl::encrypt(&passphrase, &mut input, out)
+            }
+            CryptAction::Encrypt { argon, .. } => {
+                encrypt_file(&passphrase, argon.to_argon2_params()?, &mut input, out)
+            }
+            CryptAction::Decrypt { .. } => decrypt_file(&passphrase, &mut input, out),
+        });
+        result?;
+
+        if let Some(path) = output {
+            let verb = if encrypt { "encrypted" } else { "decrypted" };
+            println!("{verb} {} to {}", file.display(), path.display());
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Returns the file to write, or `None` for standard output.
+fn output_path(input: &Path, output: Option<&Path>, encrypt: bool) -> Result<Option<PathBuf>> {
+    if let Some(output) = output {
+        return Ok((!is_stdio(output)).then(|| output.to_path_buf()));
+    }
+    if is_stdio(input) {
+        return Ok(None);
+    }
+    if encrypt {
+        let mut name = input.as_os_str().to_owned();
+        name.push(".enc");
+        return Ok(Some(name.into()));
+    }
+    match input.extension() {
+        Some(ext) if ext == "enc" => Ok(Some(input.with_extension(""))),
+        _ => bail!(
+            "cannot derive the output name from {}; use --output",
+            input.display()
+        ),
+    }
+}
+
+/// Runs `f` on the output: standard output, or a temporary owner-only file that
+/// replaces `path` only if `f` succeeds.
+fn write_output(path: Option<&Path>, f: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let Some(path) = path else {
+        let mut out = io::stdout().lock();
+        f(&mut out)?;
+        return Ok(out.flush()?);
+    };
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let result = create_file_secure(&partial)
+        .with_context(|| format!("failed to create {}", partial.display()))
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            f(&mut out)?;
+            out.into_inner()
+                .map_err(io::IntoInnerError::into_error)?
+                .sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| {
+            fs::rename(&partial, path)
+                .with_context(|| format!("failed to write {}", path.display()))
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+/// Reads until `buf` is full or the input ends; returns the number of bytes read.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match input.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Calls `f` with each chunk of up to `chunk_len` bytes of `input` and whether it is the
+/// last one; an empty input is a single empty last chunk.
+fn for_each_chunk(
+    input: &mut impl Read,
+    chunk_len: usize,
+    mut f: impl FnMut(&mut [u8], bool) -> Result<()>,
+) -> Result<()> {
+    let mut current = Zeroizing::new(vec![0u8; chunk_len]);
+    let mut next = Zeroizing::new(vec![0u8; chunk_len]);
+    let mut len = read_full(input, &mut current)?;
+    loop {
+        // A full chunk is the last one only if nothing follows it.
+        let next_len = if len < chunk_len {
+            0
+        } else {
+            read_full(input, &mut next)?
+        };
+        let last = len < chunk_len || next_len == 0;
+        f(&mut current[..len], last)?;
+        if last {
+            return Ok(());
+        }
+        std::mem::swap(&mut current, &mut next);
+        len = next_len;
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], kdf: KdfParams) -> Result<Zeroizing<[u8; 32]>> {
+    let key = interruptible(|cancel| derive_key_cancellable(passphrase, salt, kdf, cancel))?;
+    Ok(Zeroizing::new(key))
+}
+
+fn random<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes).map_err(|_| anyhow::anyhow!("OS random generator unavailable"))?;
+    Ok(bytes)
+}
+
+/// Header of a keynest encrypted file.
+struct Header {
+    argon2: Argon2Params,
+    salt: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    chunk_len: usize,
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&[VERSION, ALGORITHM_XCHACHA20_POLY1305, KDF_ARGON2ID]);
+        out.extend_from_slice(&self.argon2.mem_cost_kib().to_le_bytes());
+        out.extend_from_slice(&self.argon2.time_cost().to_le_bytes());
+        out.extend_from_slice(&self.argon2.parallelism().to_le_bytes());
+        out.push(self.salt.len() as u8);
+        out.extend_from_slice(&self.salt);
+        out.push(NONCE_PREFIX_LEN as u8);
+        out.extend_from_slice(&self.nonce_prefix);
+        out.extend_from_slice(&(self.chunk_len as u32).to_le_bytes());
+        out
+    }
+
+    /// Reads the header after the magic; returns it with its bytes, the AAD of every
+    /// chunk.
+    fn read(input: &mut impl Read) -> Result<(Self, Vec<u8>)> {
+        let mut bytes = MAGIC.to_vec();
+        let mut take = |n: usize| -> Result<Vec<u8>> {
+            let mut buf = vec![0u8; n];
+            input.read_exact(&mut buf).context("truncated header")?;
+            bytes.extend_from_slice(&buf);
+            Ok(buf)
+        };
+        let u32_at = |b: &[u8], i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+
+        let fixed = take(15)?;
+        if fixed[0] != VERSION {
+            bail!("unsupported file version {}", fixed[0]);
+        }
+        if fixed[1] != ALGORITHM_XCHACHA20_POLY1305 {
+            bail!("unsupported algorithm id {}", fixed[1]);
+        }
+        if fixed[2] != KDF_ARGON2ID {
+            bail!("unsupported key derivation id {}", fixed[2]);
+        }
+        let argon2 = Argon2Params::new(u32_at(&fixed, 3), u32_at(&fixed, 7), u32_at(&fixed, 11))
+            .context("invalid key derivation parameters")?;
+        let salt_len = take(1)?[0] as usize;
+        if !(8..=64).contains(&salt_len) {
+            bail!("invalid salt length {salt_len}");
+        }
+        let salt = take(salt_len)?;
+        if take(1)?[0] as usize != NONCE_PREFIX_LEN {
+            bail!("invalid nonce length");
+        }
+        let nonce_prefix = take(NONCE_PREFIX_LEN)?.try_into().unwrap();
+        let chunk_len = u32_at(&take(4)?, 0) as usize;
+        if !(1..=MAX_CHUNK_LEN).contains(&chunk_len) {
+            bail!("invalid chunk length {chunk_len}");
+        }
+
+        let header = Self {
+            argon2,
+            salt,
+            nonce_prefix,
+            chunk_len,
+        };
+        Ok((header, bytes))
+    }
+
+    fn nonce(&self, counter: u32, last: bool) -> XNonce {
+        let mut nonce = [0u8; 24];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..23].copy_from_slice(&counter.to_be_bytes());
+        nonce[23] = last as u8;
+        nonce.into()
+    }
+}
+
+fn encrypt_file(
+    passphrase: &str,
+    argon2: Argon2Params,
+    input: &mut impl Read,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let kdf = KdfParams::Argon2id(argon2);
+    let header = Header {
+        argon2,
+        salt: random::<SALT_LEN>()?.to_vec(),
+        nonce_prefix: random()?,
+        chunk_len: CHUNK_LEN,
+    };
+    let aad = header.to_bytes();
+    let cipher = XChaCha20Poly1305::new((&*derive_key(passphrase, &header.salt, kdf)?).into());
+    out.write_all(&aad)?;
+
+    let mut counter = 0u32;
+    for_each_chunk(input, header.chunk_len, |chunk, last| {
+        let payload = Payload {
+            msg: chunk,
+            aad: &aad,
+        };
+        let ciphertext = cipher
+            .encrypt(&header.nonce(counter, last), payload)
+            .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+        out.write_all(&ciphertext)?;
+        counter = counter.checked_add(1).context("file too large")?;
+        Ok(())
+    })
+}
+
+fn decrypt_file(passphrase: &str, input: &mut impl Read, out: &mut dyn Write) -> Result<()> {
+    let mut magic = [0u8; 8];
+    let len = read_full(input, &mut magic[..4])?;
+    if len == 4 && &magic[..4] == MAGIC {
+        return decrypt_keynest(passphrase, input, out);
+    }
+    read_full(input, &mut magic[4..])?;
+    if &magic == openssl::MAGIC {
+        return openssl::decrypt(passphrase, input, out);
+    }
+    bail!("not a file encrypted by keynest crypt or openssl enc");
+}
+
+fn decrypt_keynest(passphrase: &str, input: &mut impl Read, out: &mut dyn Write) -> Result<()> {
+    let (header, aad) = Header::read(input)?;
+    let cipher = XChaCha20Poly1305::new(
+        (&*derive_key(passphrase, &header.salt, KdfParams::Argon2id(header.argon2))?).into(),
+    );
+
+    let mut counter = 0u32;
+    for_each_chunk(input, header.chunk_len + TAG_LEN, |chunk, last| {
+        let payload = Payload {
+            msg: chunk,
+            aad: &aad,
+        };
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(&header.nonce(counter, last), payload)
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "decryption failed: wrong key entry, or the file is corrupted or truncated"
+                    )
+                })?,
+        );
+        out.write_all(&plaintext)?;
+        counter = counter.checked_add(1).context("file too large")?;
+        Ok(())
+    })
+}
+
+#[cfg(feature = "openssl-enc")]
+mod openssl {
+    use aes::Aes256;
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+    use anyhow::{Result, anyhow, bail};
+    use sha2::Sha256;
+    use std::io::{Read, Write};
+    use zeroize::Zeroizing;
+
+    use super::{CHUNK_LEN, for_each_chunk, random};
+
+    pub const MAGIC: &[u8; 8] = b"Salted__";
+    /// Default iteration count of `openssl enc -pbkdf2`.
+    const ITERATIONS: u32 = 10_000;
+    const BLOCK_LEN: usize = 16;
+
+    fn key_iv(passphrase: &str, salt: &[u8]) -> Zeroizing<[u8; 48]> {
+        let mut key_iv = Zeroizing::new([0u8; 48]);
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, ITERATIONS, &mut *key_iv);
+        key_iv
+    }
+
+    pub fn encrypt(passphrase: &str, input: &mut impl Read, out: &mut dyn Write) -> Result<()> {
+        let salt = random::<8>()?;
+        let key_iv = key_iv(passphrase, &salt);
+        let mut cipher = cbc::Encryptor::<Aes256>::new_from_slices(&key_iv[..32], &key_iv[32..])
+            .map_err(|_| anyhow!("invalid key length"))?;
+        out.write_all(MAGIC)?;
+        out.write_all(&salt)?;
+
+        // One spare block for the padding of the last chunk.
+        let mut buf = Zeroizing::new(vec![0u8; CHUNK_LEN + BLOCK_LEN]);
+        for_each_chunk(input, CHUNK_LEN, |chunk, last| {
+            let len = chunk.len();
+            buf[..len].copy_from_slice(chunk);
+            if last {
+                let padded = cipher
+                    .clone()
+                    .encrypt_padded_mut::<Pkcs7>(&mut buf, len)
+                    .map_err(|_| anyhow!("encryption failed"))?;
+                out.write_all(padded)?;
+            } else {
+                for block in buf[..len].chunks_exact_mut(BLOCK_LEN) {
+                    cipher.encrypt_block_mut(GenericArray::from_mut_slice(block));
+                }
+                out.write_all(&buf[..len])?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn decrypt(passphrase: &str, input: &mut impl Read, out: &mut dyn Write) -> Result<()> {
+        let mut salt = [0u8; 8];
+        input.read_exact(&mut salt)?;
+        let key_iv = key_iv(passphrase, &salt);
+        let mut cipher = cbc::Decryptor::<Aes256>::new_from_slices(&key_iv[..32], &key_iv[32..])
+            .map_err(|_| anyhow!("invalid key length"))?;
+
+        for_each_chunk(input, CHUNK_LEN, |chunk, last| {
+            if chunk.len() % BLOCK_LEN != 0 || (last && chunk.is_empty()) {
+                bail!("not a valid openssl enc file (truncated ciphertext)");
+            }
+            if last {
+                let plaintext = cipher
+                    .clone()
+                    .decrypt_padded_mut::<Pkcs7>(chunk)
+                    .map_err(|_| anyhow!("bad decrypt: wrong key entry or corrupted file"))?;
+                out.write_all(plaintext)?;
+            } else {
+                for block in chunk.chunks_exact_mut(BLOCK_LEN) {
+                    cipher.decrypt_block_mut(GenericArray::from_mut_slice(block));
+                }
+                out.write_all(chunk)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(not(feature = "openssl-enc"))]
+mod openssl {
+    use anyhow::{Result, bail};
+    use std::io::{Read, Write};
+
+    pub const MAGIC: &[u8; 8] = b"Salted__";
+
+    pub fn encrypt(_passphrase: &str, _input: &mut impl Read, _out: &mut dyn Write) -> Result<()> {
+        bail!(
+            "this keynest was built without OpenSSL file support; rebuild with `--features openssl-enc`"
+        )
+    }
+
+    pub fn decrypt(_passphrase: &str, _input: &mut impl Read, _out: &mut dyn Write) -> Result<()> {
+        bail!(
+            "this keynest was built without OpenSSL file support; rebuild with `--features openssl-enc`"
+        )
+    }
+}
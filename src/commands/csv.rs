@@ -0,0 +1,441 @@
+//! Reading CSV exports of other tools for `import --csv`.
+//!
+//! Password managers and spreadsheets all lay out their CSV exports differently, so the
+//! columns are mapped explicitly: `--map 'name=1,value=3,note=4'` takes the entry name
+//! from column 1, the secret from column 3 and keeps column 4 as the field `note`.
+//! Columns are numbered from 1 or named after the header row. Without `--map`, a header
+//! with well-known names (`name`/`title`, `password`/`value`, ...) is mapped by itself,
+//! and on a terminal the mapping can be picked interactively.
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::collections::HashSet;
+use std::io::{self, BufRead, IsTerminal, Write};
+use zeroize::Zeroizing;
+
+use crate::commands::common::confirm;
+
+/// Header names recognized as the entry name, in order of preference.
+const NAME_HEADERS: [&str; 4] = ["name", "key", "title", "account"];
+/// Header names recognized as the secret, in order of preference.
+const VALUE_HEADERS: [&str; 4] = ["value", "password", "secret", "pass"];
+
+/// One row of a CSV file, with the line it starts on.
+pub struct Record {
+    pub line: usize,
+    pub cells: Vec<Zeroizing<String>>,
+}
+
+/// Splits `text` into records. Cells may be quoted with `"` (doubled inside quotes) and
+/// then contain the delimiter and line breaks; blank lines are skipped.
+///
+/// # Errors
+///
+/// Returns an error if a quoted cell is not closed.
+pub fn parse(text: &str, delimiter: char) -> Result<Vec<Record>> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut records = Vec::new();
+    let mut cells = Vec::new();
+    let mut cell = Zeroizing::new(String::new());
+    let mut line = 1;
+    let mut start = 1;
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    cell.push('"');
+                }
+                '"' => quoted = false,
+                _ => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    cell.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if cell.is_empty() => quoted = true,
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                cells.push(std::mem::take(&mut cell));
+                push_record(&mut records, start, std::mem::take(&mut cells));
+                line += 1;
+                start = line;
+            }
+            _ if c == delimiter => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    if quoted {
+        bail!("line {start}: quoted cell is not closed");
+    }
+    cells.push(cell);
+    push_record(&mut records, start, cells);
+    Ok(records)
+}
+
+fn push_record(records: &mut Vec<Record>, line: usize, cells: Vec<Zeroizing<String>>) {
+    if cells.len() > 1 || !cells[0].trim().is_empty() {
+        records.push(Record { line, cells });
+    }
+}
+
+/// A column, by number (from 0) or by header name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl Column {
+    fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        match s.parse::<usize>() {
+            Ok(0) => bail!("columns are numbered from 1"),
+            Ok(n) => Ok(Column::Index(n - 1)),
+            Err(_) if s.is_empty() => bail!("missing column"),
+            Err(_) => Ok(Column::Name(s.to_string())),
+        }
+    }
+
+    fn resolve(&self, header: Option<&Record>) -> Result<usize> {
+        match self {
+            Column::Index(i) => Ok(*i),
+            Column::Name(name) => header
+                .and_then(|h| {
+                    h.cells
+                        .iter()
+                        .position(|cell| cell.trim().eq_ignore_ascii_case(name))
+                })
+                .with_context(|| format!("no column named '{name}' in the header row")),
+        }
+    }
+}
+
+/// Which columns hold the name, value, tags and fields of the entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    name: Column,
+    value: Column,
+    tags: Option<Column>,
+    fields: Vec<(String, Column)>,
+}
+
+impl Mapping {
+    /// Parses a `--map` value: comma-separated `target=column` pairs where the target is
+    /// `name`, `value`, `tags` or the name of a field, and the column a number from 1 or
+    /// a header name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a pair is malformed, a target is given twice, a field name
+    /// is invalid, or `name` or `value` is missing.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut name = None;
+        let mut value = None;
+        let mut tags = None;
+        let mut fields: Vec<(String, Column)> = Vec::new();
+        let mut seen = HashSet::new();
+        for pair in spec.split(',').filter(|p| !p.trim().is_empty()) {
+            let Some((target, column)) = pair.split_once('=') else {
+                bail!("invalid mapping '{pair}' (expected target=column, e.g. name=1)");
+            };
+            let target = target.trim();
+            let column =
+                Column::parse(column).with_context(|| format!("invalid mapping '{pair}'"))?;
+            if !seen.insert(target.to_string()) {
+                bail!("'{target}' is mapped twice");
+            }
+            match target {
+                "name" => name = Some(column),
+                "value" => value = Some(column),
+                "tags" => tags = Some(column),
+                _ if valid_field_name(target) => fields.push((target.to_string(), column)),
+                _ => bail!("invalid field name '{target}' in mapping"),
+            }
+        }
+        let (Some(name), Some(value)) = (name, value) else {
+            bail!("the mapping needs a name and a value column, e.g. 'name=1,value=2'");
+        };
+        Ok(Self {
+            name,
+            value,
+            tags,
+            fields,
+        })
+    }
+
+    /// Maps a header of well-known column names: the name, value and tags columns, and
+    /// every other named column as a field. Returns `None` if no name or value column is
+    /// recognized.
+    pub fn detect(header: &Record) -> Option<Self> {
+        let names: Vec<String> = header
+            .cells
+            .iter()
+            .map(|cell| cell.trim().to_lowercase())
+            .collect();
+        let find = |candidates: &[&str]| {
+            candidates
+                .iter()
+                .find_map(|c| names.iter().position(|n| n == c))
+        };
+        let name = find(&NAME_HEADERS)?;
+        let value = find(&VALUE_HEADERS)?;
+        let tags = find(&["tags", "tag"]);
+
+        let mut fields = Vec::new();
+        for (i, header) in names.iter().enumerate() {
+            let field = header.split_whitespace().collect::<Vec<_>>().join("-");
+            if [Some(name), Some(value), tags].contains(&Some(i))
+                || !valid_field_name(&field)
+                || fields.iter().any(|(f, _)| *f == field)
+            {
+                continue;
+            }
+            fields.push((field, Column::Index(i)));
+        }
+        Some(Self {
+            name: Column::Index(name),
+            value: Column::Index(value),
+            tags: tags.map(Column::Index),
+            fields,
+        })
+    }
+
+    /// Returns `true` if a column is given by its header name.
+    pub fn uses_names(&self) -> bool {
+        [Some(&self.name), Some(&self.value), self.tags.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(self.fields.iter().map(|(_, c)| c))
+            .any(|c| matches!(c, Column::Name(_)))
+    }
+
+    /// Returns the mapping as a `--map` value.
+    pub fn to_spec(&self) -> String {
+        let column = |c: &Column| match c {
+            Column::Index(i) => (i + 1).to_string(),
+            Column::Name(name) => name.clone(),
+        };
+        let mut pairs = vec![
+            format!("name={}", column(&self.name)),
+            format!("value={}", column(&self.value)),
+        ];
+        if let Some(tags) = &self.tags {
+            pairs.push(format!("tags={}", column(tags)));
+        }
+        for (field, c) in &self.fields {
+            pairs.push(format!("{field}={}", column(c)));
+        }
+        pairs.join(",")
+    }
+
+    /// Applies the mapping to `records`, reading column names from `header`. Rows with
+    /// an empty name or value are left out and counted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a named column is not in the header, or a row is too short
+    /// for a mapped column.
+    pub fn rows(&self, header: Option<&Record>, records: &[Record]) -> Result<(Vec<Row>, usize)> {
+        let name = self.name.resolve(header)?;
+        let value = self.value.resolve(header)?;
+        let tags = self.tags.as_ref().map(|c| c.resolve(header)).transpose()?;
+        let fields = self
+            .fields
+            .iter()
+            .map(|(field, c)| Ok((field.as_str(), c.resolve(header)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut rows = Vec::new();
+        let mut incomplete = 0;
+        for record in records {
+            let cell = |i: usize| {
+                record.cells.get(i).map(|c| c.as_str()).with_context(|| {
+                    format!(
+                        "line {}: no column {} (the row has {})",
+                        record.line,
+                        i + 1,
+                        record.cells.len()
+                    )
+                })
+            };
+            let row = Row {
+                name: cell(name)?.trim().to_string(),
+                value: Zeroizing::new(cell(value)?.to_string()),
+                tags: match tags {
+                    Some(i) => cell(i)?
+                        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+                        .filter(|t| !t.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    None => Vec::new(),
+                },
+                fields: fields
+                    .iter()
+                    .map(|(field, i)| Ok((field.to_string(), cell(*i)?.to_string())))
+                    .filter(|f| f.as_ref().map_or(true, |(_, v)| !v.is_empty()))
+                    .collect::<Result<_>>()?,
+            };
+            if row.name.is_empty() || row.value.is_empty() {
+                incomplete += 1;
+            } else {
+                rows.push(row);
+            }
+        }
+        Ok((rows, incomplete))
+    }
+}
+
+fn valid_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '=')
+}
+
+/// An entry read from a CSV row.
+pub struct Row {
+    pub name: String,
+    pub value: Zeroizing<String>,
+    pub tags: Vec<String>,
+    pub fields: Vec<(String, String)>,
+}
+
+/// What to do with rows whose name appeared in an earlier row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Duplicates {
+    /// Keep the first row of each name
+    First,
+    /// Keep the last row of each name
+    Last,
+    /// Keep all rows, numbering the later ones: name-2, name-3, ...
+    Number,
+}
+
+/// Resolves rows with the same name; returns how many rows were dropped or renamed.
+pub fn dedup(rows: &mut Vec<Row>, duplicates: Duplicates) -> usize {
+    let mut seen = HashSet::new();
+    match duplicates {
+        Duplicates::First | Duplicates::Last => {
+            if duplicates == Duplicates::Last {
+                rows.reverse();
+            }
+            let before = rows.len();
+            rows.retain(|row| seen.insert(row.name.clone()));
+            if duplicates == Duplicates::Last {
+                rows.reverse();
+            }
+            before - rows.len()
+        }
+        Duplicates::Number => {
+            let taken: HashSet<String> = rows.iter().map(|row| row.name.clone()).collect();
+            let mut renamed = 0;
+            for row in rows.iter_mut() {
+                if seen.insert(row.name.clone()) {
+                    continue;
+                }
+                let name = (2..)
+                    .map(|n| format!("{}-{n}", row.name))
+                    .find(|name| !taken.contains(name) && !seen.contains(name))
+                    .unwrap_or_default();
+                seen.insert(name.clone());
+                row.name = name;
+                renamed += 1;
+            }
+            renamed
+        }
+    }
+}
+
+/// Returns `true` if the mapping can be asked for interactively.
+pub fn can_ask() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Asks which columns to import, showing the first row of the file. Returns the
+/// mapping and whether the first row is a header.
+///
+/// # Errors
+///
+/// Returns an error if `records` is empty, the terminal cannot be read, or the answers
+/// do not form a valid mapping.
+pub fn ask_mapping(records: &[Record], header: bool) -> Result<(Mapping, bool)> {
+    let Some(first) = records.first() else {
+        bail!("the CSV file has no rows");
+    };
+    let header = header || confirm(&format!("Is the first row a header ({})?", summary(first)))?;
+    let sample = if header { records.get(1) } else { Some(first) };
+
+    eprintln!("Columns:");
+    for i in 0..first.cells.len() {
+        let name = if header { first.cells[i].trim() } else { "" };
+        let example = sample
+            .and_then(|r| r.cells.get(i))
+            .map_or(String::new(), |c| shorten(c));
+        eprintln!("  {:>2}  {name:<20} {example}", i + 1);
+    }
+
+    let name = ask("Column of the entry names")?;
+    let value = ask("Column of the secret values")?;
+    let tags = ask("Column of tags (empty for none)")?;
+    let fields = ask("Other columns to keep as fields, as field=column (e.g. user=3,url=2)")?;
+    let mut spec = format!("name={name},value={value}");
+    if !tags.is_empty() {
+        spec.push_str(&format!(",tags={tags}"));
+    }
+    if !fields.is_empty() {
+        spec.push(',');
+        spec.push_str(&fields);
+    }
+    Ok((Mapping::parse(&spec)?, header))
+}
+
+fn ask(question: &str) -> Result<String> {
+    eprint!("{question}: ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// Returns the cells of `record` joined for a one-line summary.
+fn summary(record: &Record) -> String {
+    record
+        .cells
+        .iter()
+        .map(|c| shorten(c))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn shorten(cell: &str) -> String {
+    let cell = cell.trim().replace(['\r', '\n'], " ");
+    if cell.chars().count() > 24 {
+        format!("{}...", cell.chars().take(21).collect::<String>())
+    } else {
+        cell
+    }
+}
+
+/// Parses `--delimiter`: a single character, or `tab`.
+pub fn parse_delimiter(s: &str) -> Result<char, String> {
+    match s {
+        "tab" | "\\t" => Ok('\t'),
+        _ => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c != '"' && c != '\n' && c != '\r' => Ok(c),
+                _ => Err(format!(
+                    "invalid delimiter '{s}' (use one character, or 'tab')"
+                )),
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use clap::Args;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "\
+Examples:
+  keynest deps staging/db/password               Show what a secret references and what references it
+  keynest deps staging/db/password --json        Output the reference graph as JSON"
+)]
+pub struct DepsCommand {
+    pub key: String,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+impl Command for DepsCommand {
+    fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let kn = unlock_keystore(storage)?;
+
+        if kn.get(&self.key)?.is_none() {
+            eprintln!("key not found: {}", self.key);
+            return Ok(ExitCode::from(1));
+        }
+
+        let chain = kn.references(&self.key)?;
+        let dependents = kn.dependents(&self.key)?;
+
+        if self.json {
+            print_json(&serde_json::json!({
+                "key": self.key,
+                "references": &chain[1..],
+                "referenced_by": dependents,
+            }))?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        println!("{}", self.key);
+        for (depth, key) in chain.iter().skip(1).enumerate() {
+            println!("{}└── ref:{key}", "    ".repeat(depth));
+        }
+
+        if !dependents.is_empty() {
+            println!();
+            println!("Referenced by:");
+            for key in dependents {
+                println!("  {key}");
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
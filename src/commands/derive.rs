@@ -0,0 +1,156 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    check_access, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
+use keynest::generator::Recipe;
+
+/// Namespace of the saved recipes and default key of the master secret.
+const NAMESPACE: &str = "derive";
+const DEFAULT_MASTER: &str = "derive/master";
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest generate derive/master --length 32     Create the master secret once, then copy it to each device
+  keynest derive example.com                     Print the password of example.com
+  keynest derive example.com --length 16 --symbols --save
+                                                 Remember the site's rules in the recipe derive/example.com
+  keynest derive example.com --rotate            Bump the counter of the recipe and print the new password
+
+Passwords are computed from the master secret, the site name and a counter with
+HKDF-SHA256 and are never stored. A site without a saved recipe gets a 24-character
+password of letters and digits with counter 1, so it needs nothing but the master secret;
+--save stores the recipe (site, counter, length and character classes), not the password.")]
+pub struct DeriveCommand {
+    /// Site whose password to compute, e.g. example.com (case-insensitive)
+    pub site: String,
+
+    /// Entry holding the master secret
+    #[arg(long, value_name = "KEY", default_value = DEFAULT_MASTER)]
+    pub master: String,
+
+    /// Counter of the password [default: the saved recipe's, or 1]
+    #[arg(long, value_name = "N")]
+    pub counter: Option<u32>,
+
+    /// Number of characters [default: the saved recipe's, or 24]
+    #[arg(long, short = 'l')]
+    pub length: Option<usize>,
+
+    /// Include symbols (ASCII punctuation)
+    #[arg(long, short = 's')]
+    pub symbols: bool,
+
+    /// Leave out easily confused characters (0 O 1 l I | ` ' ")
+    #[arg(long = "no-ambiguous")]
+    pub no_ambiguous: bool,
+
+    /// Leave out lowercase letters
+    #[arg(long = "no-lowercase")]
+    pub no_lowercase: bool,
+
+    /// Leave out uppercase letters
+    #[arg(long = "no-uppercase")]
+    pub no_uppercase: bool,
+
+    /// Leave out digits
+    #[arg(long = "no-digits")]
+    pub no_digits: bool,
+
+    /// Store the recipe under derive/<site>, replacing a saved one
+    #[arg(long)]
+    pub save: bool,
+
+    /// Increment the counter and save the recipe, to change the site's password
+    #[arg(long, conflicts_with = "counter")]
+    pub rotate: bool,
+}
+
+impl DeriveCommand {
+    /// Applies the options given on the command line to `recipe`.
+    fn apply_to(&self, recipe: Recipe) -> Result<Recipe> {
+        let mut options = *recipe.options();
+        if let Some(length) = self.length {
+            options = options.with_length(length);
+        }
+        // Any class flag replaces the saved classes as a whole, like in `generate`.
+        if self.symbols
+            || self.no_ambiguous
+            || self.no_lowercase
+            || self.no_uppercase
+            || self.no_digits
+        {
+            options = options
+                .with_lowercase(!self.no_lowercase)
+                .with_uppercase(!self.no_uppercase)
+                .with_digits(!self.no_digits)
+                .with_symbols(self.symbols)
+                .with_exclude_ambiguous(self.no_ambiguous);
+        }
+        let counter = match self.counter {
+            Some(counter) => counter,
+            None if self.rotate => recipe
+                .counter()
+                .checked_add(1)
+                .context("the counter cannot be incremented further")?,
+            None => recipe.counter(),
+        };
+        recipe.with_options(options).with_counter(counter)
+    }
+}
+
+impl Command for DeriveCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let site = Recipe::new(&self.site)?;
+        let key = format!("{NAMESPACE}/{}", site.site());
+        if key == self.master {
+            bail!("'{key}' holds the master secret; pick another site name or --master");
+        }
+        let parse = |value: &str| {
+            Recipe::parse(value).with_context(|| format!("'{key}' is not a valid recipe"))
+        };
+
+        let storage = resolve_existing_storage(store)?;
+        if !self.save && !self.rotate {
+            let mut kn = unlock_indexed(storage)?;
+            let saved = kn.resolve(&key)?.map(parse).transpose()?;
+            let recipe = self.apply_to(saved.unwrap_or(site))?;
+            check_access(&self.master, kn.effective_policy(&self.master)?, false)?;
+            let Some(master) = kn.resolve(&self.master)? else {
+                return Ok(master_not_found(&self.master));
+            };
+            println!("{}", recipe.derive(master)?.as_str());
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let mut kn = unlock_keystore(storage)?;
+        let saved = kn.resolve(&key)?.map(parse).transpose()?;
+        let recipe = self.apply_to(saved.clone().unwrap_or(site))?;
+        check_access(&self.master, kn.effective_policy(&self.master)?, false)?;
+        let Some(master) = kn.resolve(&self.master)? else {
+            return Ok(master_not_found(&self.master));
+        };
+        let password = recipe.derive(master)?;
+        match saved {
+            None => kn.set_recipe(&key, &recipe)?,
+            Some(saved) if saved != recipe => kn.update(&key, &recipe.to_uri())?,
+            Some(_) => {}
+        }
+        kn.save()?;
+
+        println!("{}", password.as_str());
+        eprintln!("saved recipe '{key}' (counter {})", recipe.counter());
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn master_not_found(master: &str) -> ExitCode {
+    eprintln!("key not found: {master}");
+    eprintln!("create the master secret with: keynest generate {master} --length 32");
+    ExitCode::from(1)
+}
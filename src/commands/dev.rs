@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use clap::{Args, CommandFactory, Subcommand, ValueEnum};
+use clap_complete::{Generator, Shell};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::cli::Cli;
+use crate::commands::Command;
+use crate::commands::completions;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest dev mangen --out-dir target/assets     Write man pages and shell completions for packaging")]
+pub struct DevCommand {
+    #[command(subcommand)]
+    pub action: DevAction,
+}
+
+#[derive(Subcommand)]
+pub enum DevAction {
+    /// Write man pages (man/) and shell completions (completions/) generated from the CLI definition
+    Mangen {
+        /// Output directory (created if missing)
+        #[arg(long = "out-dir", value_name = "DIR")]
+        out_dir: PathBuf,
+    },
+}
+
+impl Command for DevCommand {
+    fn run(self, _store: Option<PathBuf>) -> Result<ExitCode> {
+        match self.action {
+            DevAction::Mangen { out_dir } => {
+                let man_dir = out_dir.join("man");
+                std::fs::create_dir_all(&man_dir)
+                    .with_context(|| format!("failed to create {}", man_dir.display()))?;
+                clap_mangen::generate_to(Cli::command(), &man_dir)
+                    .context("failed to write man pages")?;
+                println!("man pages written to {}", man_dir.display());
+
+                let completions_dir = out_dir.join("completions");
+                std::fs::create_dir_all(&completions_dir)
+                    .with_context(|| format!("failed to create {}", completions_dir.display()))?;
+                for shell in Shell::value_variants() {
+                    let path = completions_dir.join(shell.file_name("keynest"));
+                    std::fs::write(&path, completions::script(*shell)?)
+                        .with_context(|| format!("failed to write {shell} completions"))?;
+                }
+                println!("shell completions written to {}", completions_dir.display());
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,92 @@
+//! `keynest duress`: a second password that opens a decoy store instead of the real one.
+//!
+//! The duress password lives in a keyslot of the real store wrapping the key of the
+//! decoy, which is kept in the keystore header; see [`keynest::Keynest::set_duress`].
+
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{
+    KdfArgs, StrengthArgs, confirm, resolve_existing_storage, unlock_keystore,
+};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest duress set                      Add a duress password, asking for both passwords
+  keynest duress set --slot backup        Name its keyslot 'backup' instead of 'recovery'
+  keynest duress remove                   Remove the duress password and the decoy
+
+Unlocking the store with the duress password opens an empty decoy store instead: secret
+commands work as usual but read and write the decoy, so fill it with a few plausible
+secrets. The decoy is kept inside the store file, in space every store with keyslots
+reserves for it, so neither the file nor `keynest info` shows whether there is one. It
+holds up to 16 KiB of encoded secrets, without attachments, and cannot change the
+password, keyslots, format or side files of the store; `keynest nuke` destroys the
+whole store. The duress password is a keyslot like any other (see `keynest keyslot`),
+so pick an innocuous name for it.")]
+pub struct DuressCommand {
+    #[command(subcommand)]
+    pub action: DuressAction,
+}
+
+#[derive(Subcommand)]
+pub enum DuressAction {
+    /// Add a duress password, asking for the current password and then for it
+    Set {
+        /// Name of the keyslot holding the duress password
+        #[arg(long, default_value = "recovery")]
+        slot: String,
+
+        #[command(flatten)]
+        kdf: KdfArgs,
+
+        #[command(flatten)]
+        strength: StrengthArgs,
+    },
+    /// Remove the duress password and the decoy store
+    Remove {
+        /// Do not ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+}
+
+impl Command for DuressCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        match self.action {
+            DuressAction::Set {
+                slot,
+                kdf,
+                strength,
+            } => {
+                strength.apply();
+                let kdf = kdf.to_kdf_params()?;
+                let mut kn = unlock_keystore(storage)?;
+                let password = auth::read_new_password_with_confirmation()?;
+                kn.set_duress(&slot, password, kdf)?;
+                println!("added duress keyslot '{slot}'; its password opens an empty decoy store");
+            }
+            DuressAction::Remove { yes } => {
+                let mut kn = unlock_keystore(storage)?;
+                let Some(slot) = kn.duress_slot()? else {
+                    eprintln!("the keystore has no duress password");
+                    return Ok(ExitCode::from(1));
+                };
+                let question = format!("Remove duress keyslot '{slot}' and its decoy store?");
+                if !yes && !confirm(&question)? {
+                    println!("Aborted");
+                    return Ok(ExitCode::from(1));
+                }
+                kn.remove_duress()?;
+                println!("removed duress keyslot '{slot}'");
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    check_access, resolve_existing_storage, strip_trailing_newline, unlock_keystore,
+};
+use crate::commands::secret_dir::SecretDir;
+use keynest::EntryKind;
+
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest edit recovery/bank                     Edit a note (created if it does not exist)
+  keynest edit api_key                           Edit an existing secret in the editor
+  EDITOR='code --wait' keynest edit notes/server Use a different editor
+
+The editor is taken from $VISUAL, then $EDITOR (default: vi, notepad on Windows). The
+value is written to a private, memory-backed temporary file that is overwritten and
+removed when the editor exits.")]
+pub struct EditCommand {
+    pub key: String,
+}
+
+impl Command for EditCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let kind = kn.kind(&self.key)?;
+        check_access(&self.key, kn.effective_policy(&self.key)?, false)?;
+        let original = Zeroizing::new(kn.get(&self.key)?.unwrap_or_default().to_string());
+
+        let dir = SecretDir::create()?;
+        let file_name = match kind {
+            Some(EntryKind::Secret | EntryKind::Totp | EntryKind::Recipe | EntryKind::Counter) => {
+                "secret.txt"
+            }
+            _ => "note.md",
+        };
+        let path = dir.write(file_name, original.as_bytes())?;
+        run_editor(&path)?;
+        let edited = strip_trailing_newline(Zeroizing::new(
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+        ));
+        drop(dir);
+
+        if *edited == *original {
+            println!("no changes to '{}'", self.key);
+            return Ok(ExitCode::SUCCESS);
+        }
+        if edited.trim().is_empty() {
+            bail!("value cannot be empty; nothing saved");
+        }
+
+        match kind {
+            Some(kind) => {
+                kn.update(&self.key, &edited)?;
+                kn.save()?;
+                println!("updated {kind} '{}'", self.key);
+            }
+            None => {
+                kn.set_note(&self.key, &edited)?;
+                kn.save()?;
+                println!("stored note '{}'", self.key);
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Opens `path` in the user's editor and waits for it to exit.
+fn run_editor(path: &std::path::Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+
+    // Allow editors with arguments, e.g. `code --wait`.
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or(DEFAULT_EDITOR);
+    let status = std::process::Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .with_context(|| format!("failed to run editor '{program}'"))?;
+    if !status.success() {
+        bail!("editor '{program}' exited with {status}; nothing saved");
+    }
+    Ok(())
+}
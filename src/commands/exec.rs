@@ -2,8 +2,10 @@ use anyhow::Result;
 use clap::Args;
 use std::process::ExitCode;
 
-use super::super::auth;
-use crate::commands::common::resolve_existing_storage;
+use crate::commands::common::{
+    check_access, interruptible, parse_fd, resolve_existing_storage, unlock_keystore,
+};
+use crate::commands::secret_dir::SecretDir;
 use keynest::Keynest;
 
 fn to_env_name(key: &str) -> String {
@@ -25,14 +27,28 @@ fn apply_prefix(prefix: Option<&str>, mut key: String) -> String {
     key
 }
 
+/// Splits `--prefix` into a key namespace (`prod/`, ends with `/`) or an env var prefix.
+fn split_prefix(prefix: Option<&str>) -> (Option<&str>, Option<&str>) {
+    match prefix {
+        Some(p) if p.ends_with('/') => (Some(p), None),
+        p => (None, p),
+    }
+}
+
+/// Returns the env var name of `key`: without the namespace, with the env var prefix.
+fn env_name(key: &str, namespace: Option<&str>, prefix: Option<&str>) -> String {
+    let relative = namespace.and_then(|ns| key.strip_prefix(ns)).unwrap_or(key);
+    apply_prefix(prefix, to_env_name(relative))
+}
+
 /// Warns (non-fatally, to stderr) when two distinct keys map to the same environment
 /// variable name, since only the last one would survive as an env var.
-fn warn_on_env_name_collisions(keys: &[String], prefix: Option<&str>) {
+fn warn_on_env_name_collisions(keys: &[String], namespace: Option<&str>, prefix: Option<&str>) {
     use std::collections::HashMap;
 
     let mut seen: HashMap<String, String> = HashMap::new();
     for key in keys {
-        let env_key = apply_prefix(prefix, to_env_name(key));
+        let env_key = env_name(key, namespace, prefix);
         if let Some(prev) = seen.insert(env_key.clone(), key.clone()) {
             if &prev != key {
                 eprintln!(
@@ -51,19 +67,44 @@ fn shell_escape(value: &str) -> String {
     }
 }
 
+/// Parses a `FD=KEY` mapping for `--to-fd`.
+fn parse_fd_mapping(s: &str) -> Result<(u32, String)> {
+    let (fd, key) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected FD=KEY, got '{s}'"))?;
+    if key.is_empty() {
+        anyhow::bail!("expected FD=KEY, got '{s}'");
+    }
+    Ok((parse_fd(fd)?, key.to_string()))
+}
+
+/// Parses a `NAME=KEY` mapping for `--tmpfile`.
+fn parse_env_mapping(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((name, key)) if !name.is_empty() && !key.is_empty() && !name.contains('/') => {
+            Ok((name.to_string(), key.to_string()))
+        }
+        _ => anyhow::bail!("expected NAME=KEY, got '{s}'"),
+    }
+}
+
 #[derive(Args)]
 #[command(after_help = "\
 Examples:
   keynest exec -- docker compose up                Run command with all secrets as env vars
   keynest exec --only API_KEY -- curl api.example.com  Run command with specific secret
   keynest exec --prefix MY_ -- env                 Show env vars with prefix
-  keynest exec --print                             Preview environment variables")]
+  keynest exec --prefix prod/ -- my-app --flag     Only secrets under prod/; prod/db/password -> DB_PASSWORD
+  keynest exec --print                             Preview environment variables
+  keynest run --to-fd 3=db/password -- psql-wrapper   Pass a secret on fd 3 instead of the env
+  keynest run --tmpfile DB_CA=prod/ca.pem -- app      Pass a secret as a file; DB_CA holds its path")]
 pub struct ExecCommand {
     /// Only export specific keys
     #[arg(long, value_delimiter = ',')]
     pub only: Option<Vec<String>>,
 
-    /// Prefix environment variables
+    /// Prefix environment variables; a prefix ending in '/' (e.g. prod/) instead selects
+    /// the secrets under that namespace and leaves it out of the variable names
     #[arg(long)]
     pub prefix: Option<String>,
 
@@ -71,6 +112,16 @@ pub struct ExecCommand {
     #[arg(long)]
     pub print: bool,
 
+    /// Pass a secret to the command on an inherited file descriptor (FD=KEY, FD >= 3);
+    /// unless --only is given, no secrets are exported as env vars
+    #[arg(long = "to-fd", value_name = "FD=KEY", value_parser = parse_fd_mapping, conflicts_with = "print")]
+    pub to_fd: Vec<(u32, String)>,
+
+    /// Write a secret to a file in a private tmpfs directory and set env var NAME to its
+    /// path (NAME=KEY); the files are overwritten and removed when the command exits
+    #[arg(long = "tmpfile", value_name = "NAME=KEY", value_parser = parse_env_mapping, conflicts_with = "print")]
+    pub tmpfile: Vec<(String, String)>,
+
     /// Command to run
     #[arg(trailing_var_arg = true)]
     pub cmd: Vec<String>,
@@ -79,27 +130,42 @@ pub struct ExecCommand {
 impl crate::commands::Command for ExecCommand {
     fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
         let storage = resolve_existing_storage(store)?;
-        let password = auth::read_password()?;
-        let kn = Keynest::open_with_storage(password, storage)?;
+        let mut kn = unlock_keystore(storage)?;
 
+        let (namespace, prefix) = split_prefix(self.prefix.as_deref());
         let keys: Vec<String> = if let Some(ref only) = self.only {
-            only.clone()
+            only.iter()
+                .map(|key| match namespace {
+                    Some(ns) if !key.starts_with(ns) => format!("{ns}{key}"),
+                    _ => key.clone(),
+                })
+                .collect()
+        } else if !self.to_fd.is_empty() || !self.tmpfile.is_empty() {
+            Vec::new()
+        } else if let Some(ns) = namespace {
+            let keys: Vec<String> = kn.list_prefix(ns).into_iter().cloned().collect();
+            if keys.is_empty() {
+                anyhow::bail!("no secrets under '{ns}'");
+            }
+            keys
         } else {
             kn.list().iter().map(|s| s.to_string()).collect()
         };
 
-        warn_on_env_name_collisions(&keys, self.prefix.as_deref());
+        warn_on_env_name_collisions(&keys, namespace, prefix);
 
         if self.print {
             for key in &keys {
+                check_access(key, kn.effective_policy(key)?, true)?;
                 let secret = kn
-                    .get(key)
+                    .resolve(key)?
                     .ok_or_else(|| anyhow::anyhow!("key not found: {key}"))?;
 
-                let env_key = apply_prefix(self.prefix.as_deref(), to_env_name(key));
+                let env_key = env_name(key, namespace, prefix);
 
                 println!("{}={}", env_key, shell_escape(secret));
             }
+            record_usage(&mut kn, &keys)?;
             return Ok(ExitCode::SUCCESS);
         }
 
@@ -111,17 +177,192 @@ impl crate::commands::Command for ExecCommand {
         cmd.args(&self.cmd[1..]);
 
         for key in &keys {
+            check_access(key, kn.effective_policy(key)?, false)?;
             let secret = kn
-                .get(key)
+                .resolve(key)?
                 .ok_or_else(|| anyhow::anyhow!("key not found: {key}"))?;
 
-            let env_key = apply_prefix(self.prefix.as_deref(), to_env_name(key));
+            let env_key = env_name(key, namespace, prefix);
 
             cmd.env(env_key, secret);
         }
 
-        let status = cmd.status()?;
+        let mut fd_values = Vec::with_capacity(self.to_fd.len());
+        for (fd, key) in &self.to_fd {
+            if fd_values.iter().any(|(f, _)| f == fd) {
+                anyhow::bail!("file descriptor {fd} is given more than once");
+            }
+            check_access(key, kn.effective_policy(key)?, false)?;
+            let secret = kn
+                .resolve(key)?
+                .ok_or_else(|| anyhow::anyhow!("key not found: {key}"))?;
+            fd_values.push((*fd, zeroize::Zeroizing::new(secret.as_bytes().to_vec())));
+        }
+
+        // Dropped after the command exits, which shreds and removes the files.
+        let secret_dir = if self.tmpfile.is_empty() {
+            None
+        } else {
+            Some(SecretDir::create()?)
+        };
+        if let Some(dir) = &secret_dir {
+            for (name, key) in &self.tmpfile {
+                if cmd.get_envs().any(|(k, _)| k == name.as_str()) {
+                    anyhow::bail!("environment variable {name} is set more than once");
+                }
+                check_access(key, kn.effective_policy(key)?, false)?;
+                let secret = kn
+                    .resolve(key)?
+                    .ok_or_else(|| anyhow::anyhow!("key not found: {key}"))?;
+                cmd.env(name, dir.write(name, secret.as_bytes())?);
+            }
+        }
+
+        let used: Vec<String> = keys
+            .iter()
+            .chain(self.to_fd.iter().map(|(_, key)| key))
+            .chain(self.tmpfile.iter().map(|(_, key)| key))
+            .cloned()
+            .collect();
+        record_usage(&mut kn, &used)?;
+
+        let handoff = fd_handoff::FdHandoff::new(&mut cmd, fd_values)?;
+        let mut child = cmd.spawn()?;
+        handoff.start();
+        // Ctrl-C reaches the command directly; keep running until it exits so the
+        // secret files are cleaned up.
+        let status = interruptible(|_| child.wait())?;
+        drop(secret_dir);
 
         Ok(ExitCode::from(status.code().unwrap_or(1) as u8))
     }
 }
+
+/// Counts reads of `keys` while usage tracking or read receipts are on.
+fn record_usage(kn: &mut Keynest, keys: &[String]) -> Result<()> {
+    if !kn.records_reads()? {
+        return Ok(());
+    }
+    for key in keys {
+        kn.record_get(key)?;
+    }
+    kn.save_usage()
+}
+
+/// Hands secrets to the child process through pipes on chosen descriptor numbers.
+#[cfg(unix)]
+mod fd_handoff {
+    use anyhow::Result;
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use zeroize::Zeroizing;
+
+    pub struct FdHandoff {
+        /// Read ends, kept open until the child has been spawned.
+        readers: Vec<OwnedFd>,
+        writers: Vec<(File, Zeroizing<Vec<u8>>)>,
+    }
+
+    impl FdHandoff {
+        /// Creates a pipe per value and arranges for its read end to appear on the
+        /// requested descriptor in the child.
+        pub fn new(cmd: &mut Command, values: Vec<(u32, Zeroizing<Vec<u8>>)>) -> Result<Self> {
+            let min = values
+                .iter()
+                .map(|(fd, _)| *fd as RawFd + 1)
+                .max()
+                .unwrap_or(3);
+
+            let mut readers = Vec::with_capacity(values.len());
+            let mut writers = Vec::with_capacity(values.len());
+            let mut moves = Vec::with_capacity(values.len());
+            for (target, value) in values {
+                let (read, write) = pipe_above(min)?;
+                moves.push((read.as_raw_fd(), target as RawFd));
+                readers.push(read);
+                writers.push((File::from(write), value));
+            }
+
+            if !moves.is_empty() {
+                // SAFETY: the hook only calls dup2, which is async-signal-safe, and
+                // iterating the captured Vec does not allocate.
+                unsafe {
+                    cmd.pre_exec(move || {
+                        for &(src, target) in &moves {
+                            if libc::dup2(src, target) == -1 {
+                                return Err(io::Error::last_os_error());
+                            }
+                        }
+                        Ok(())
+                    });
+                }
+            }
+
+            Ok(Self { readers, writers })
+        }
+
+        /// Closes the parent's read ends and writes the values once the child is running.
+        ///
+        /// Writes happen on background threads so values larger than the pipe buffer do
+        /// not block the parent; a child that exits without reading just breaks the pipe.
+        pub fn start(self) {
+            drop(self.readers);
+            for (mut writer, value) in self.writers {
+                std::thread::spawn(move || {
+                    if let Err(e) = writer.write_all(&value) {
+                        if e.kind() != io::ErrorKind::BrokenPipe {
+                            eprintln!("Warning: failed to pass secret to the command: {e}");
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    /// Creates a close-on-exec pipe whose read end is numbered `min` or higher, so
+    /// moving one read end onto its target in the child cannot clobber another.
+    fn pipe_above(min: RawFd) -> io::Result<(OwnedFd, OwnedFd)> {
+        let mut fds = [0 as RawFd; 2];
+        // SAFETY: `fds` has room for the two descriptors pipe() writes.
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: pipe() succeeded, so both descriptors are open and owned by us.
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+        // SAFETY: F_SETFD on a descriptor we own.
+        if unsafe { libc::fcntl(write.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: F_DUPFD_CLOEXEC duplicates a descriptor we own into a new one.
+        let high = unsafe { libc::fcntl(read.as_raw_fd(), libc::F_DUPFD_CLOEXEC, min) };
+        if high == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fcntl returned a new open descriptor that nothing else owns.
+        Ok((unsafe { OwnedFd::from_raw_fd(high) }, write))
+    }
+}
+
+#[cfg(not(unix))]
+mod fd_handoff {
+    use anyhow::{Result, bail};
+    use std::process::Command;
+    use zeroize::Zeroizing;
+
+    pub struct FdHandoff;
+
+    impl FdHandoff {
+        pub fn new(_cmd: &mut Command, values: Vec<(u32, Zeroizing<Vec<u8>>)>) -> Result<Self> {
+            if !values.is_empty() {
+                bail!("--to-fd is only supported on Unix");
+            }
+            Ok(Self)
+        }
+
+        pub fn start(self) {}
+    }
+}
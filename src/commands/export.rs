@@ -1,27 +1,54 @@
-use anyhow::Result;
-use clap::{Args, ValueEnum};
-use std::collections::BTreeMap;
-use std::fmt::Write;
+use anyhow::{Result, bail};
+use clap::{ArgGroup, Args, ValueEnum};
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
 use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::resolve_existing_storage;
-use keynest::Keynest;
+use crate::commands::common::{
+    KdfArgs, StrengthArgs, check_access, passphrase_from_env, resolve_existing_storage,
+    unlock_keystore, write_file_secure,
+};
+use crate::commands::os_keychain;
+use crate::commands::wifi;
+use keynest::ExportFormat as Format;
+use keynest::Redaction;
 
 #[derive(Debug, Clone, ValueEnum)]
 pub enum ExportFormat {
     Env,
     Json,
+    Csv,
+    Yaml,
+    Toml,
 }
 
-impl ExportFormat {
-    pub fn from_extension(ext: &str) -> Option<Self> {
-        match ext.to_lowercase().as_str() {
-            "env" => Some(ExportFormat::Env),
-            "json" => Some(ExportFormat::Json),
-            _ => None,
+impl From<ExportFormat> for Format {
+    fn from(f: ExportFormat) -> Self {
+        match f {
+            ExportFormat::Env => Format::Env,
+            ExportFormat::Json => Format::Json,
+            ExportFormat::Csv => Format::Csv,
+            ExportFormat::Yaml => Format::Yaml,
+            ExportFormat::Toml => Format::Toml,
+        }
+    }
+}
+
+/// How `--redact` replaces values.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum RedactMode {
+    /// A keyed hash per value; equal values share a hash within one export
+    Values,
+    /// The first 3 characters of long values followed by a fixed mask
+    Partial,
+}
+
+impl From<RedactMode> for Redaction {
+    fn from(mode: RedactMode) -> Self {
+        match mode {
+            RedactMode::Values => Redaction::Values,
+            RedactMode::Partial => Redaction::Partial,
         }
     }
 }
@@ -29,135 +56,262 @@ impl ExportFormat {
 #[derive(Args)]
 #[command(
     arg_required_else_help = false,
+    group = ArgGroup::new("target").args(["os_keychain", "wifi"]),
     after_help = "\
  Examples:
    keynest export                         Export all secrets to stdout (JSON)
    keynest export .env                    Export as .env file (format from extension)
    keynest export --format env            Export as env format to stdout
-   keynest export --format json file.json Export as JSON to file"
+   keynest export --format json file.json Export as JSON to file
+   keynest export --format csv --output secrets.csv --prefix prod/
+                                          Export the prod/ secrets as CSV (key,value)
+   keynest export secrets.yaml            Export as YAML (prod/db/password nests as prod: db: password:)
+   keynest export --format toml           Export as TOML to stdout (namespaces become tables)
+   keynest export --redact values         Share the layout of the store for a bug report
+   keynest export --bundle laptop.knb --prefix work/
+                                          Write the work/ secrets to an encrypted bundle
+   keynest export --os-keychain --prefix wifi/ --dry-run
+                                          Show where the wifi/ secrets would be stored
+   keynest export --os-keychain --prefix wifi/
+                                          Store them in the OS credential store
+   sudo keynest --store ~/vault.db export --wifi
+                                          Write the wifi/ passwords back to NetworkManager
+
+ --os-keychain writes each secret to the macOS keychain (as a generic password) or the
+ Windows Credential Manager (as a generic credential), with the namespace as service and
+ the last name as account: wifi/home becomes account 'home' of service 'wifi'. Fields
+ named service, account, user or username take precedence, as for 'keynest lookup'. Existing
+ credentials are replaced. References are stored with the value they point to.
+
+ --wifi writes each wifi/<ssid> secret as the passphrase of the NetworkManager connection
+ for that SSID (the ssid field takes precedence), in /etc/NetworkManager/system-connections
+ or --nm-dir. Existing connections are updated; other SSIDs get a new WPA personal
+ connection. Run 'nmcli connection reload' afterwards.
+
+ --redact values replaces each value with 'redacted:' and a hash that is keyed per
+ export, so equal values can be spotted without revealing them; --redact partial keeps
+ the first 3 characters of values of 12 or more characters (none for entries with an
+ access policy) and masks the rest. Keys, empty values and ref: links are kept.
+
+ --bundle writes an encrypted bundle for 'keynest import --bundle' on another machine:
+ a standalone file under its own passphrase (prompted for, or read from
+ --passphrase-env) with its own salt and KDF parameters (--kdf, --argon-*, --scrypt-*),
+ so it can be sent over untrusted channels. Entries keep their kind, fields, tags,
+ expiry and access policy; attachments are not included."
 )]
 pub struct ExportCommand {
     /// Output file (format auto-detected from extension, or use --format)
     pub file: Option<PathBuf>,
 
-    /// Export format (env or json)
+    /// Output file; same as the positional FILE
+    #[arg(long, short = 'o', value_name = "FILE", conflicts_with = "file")]
+    pub output: Option<PathBuf>,
+
+    /// Export format (env, json, csv, yaml or toml)
     #[arg(long = "format", value_enum)]
     pub format: Option<ExportFormat>,
 
+    /// Export to the OS credential store (macOS keychain, Windows Credential Manager)
+    #[arg(long, conflicts_with_all = ["file", "output", "format"])]
+    pub os_keychain: bool,
+
+    /// Write the wifi/ secrets as NetworkManager Wi-Fi connections
+    #[arg(long, conflicts_with_all = ["file", "output", "format", "os_keychain"])]
+    pub wifi: bool,
+
+    /// With --wifi, the directory of the NetworkManager connections
+    #[arg(long, value_name = "DIR", requires = "wifi")]
+    pub nm_dir: Option<PathBuf>,
+
+    /// With --os-keychain or --wifi, only show where each secret would be stored
+    #[arg(long, requires = "target")]
+    pub dry_run: bool,
+
     /// Only export secrets with this prefix
     #[arg(long = "prefix")]
     pub prefix: Option<String>,
+
+    /// Replace the values, keeping keys and `ref:` links: `values` with a hash,
+    /// `partial` with a fixed mask after the first 3 characters
+    #[arg(long, value_name = "MODE", value_enum, conflicts_with = "target")]
+    pub redact: Option<RedactMode>,
+
+    /// Write an encrypted bundle for `keynest import --bundle` to this file
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["file", "output", "format", "target", "redact"]
+    )]
+    pub bundle: Option<PathBuf>,
+
+    /// With --bundle, read its passphrase from this environment variable
+    #[arg(long = "passphrase-env", value_name = "VAR", requires = "bundle")]
+    pub passphrase_env: Option<String>,
+
+    #[command(flatten)]
+    pub kdf: KdfArgs,
+
+    #[command(flatten)]
+    pub strength: StrengthArgs,
 }
 
 impl Command for ExportCommand {
     fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        if self.bundle.is_none() && !self.kdf.is_empty() {
+            anyhow::bail!("--kdf, --argon-* and --scrypt-* only apply to --bundle");
+        }
         let storage = resolve_existing_storage(store)?;
-        let password = auth::read_password()?;
-        let kn = Keynest::open_with_storage(password, storage)?;
+        let kn = unlock_keystore(storage)?;
 
-        let keys: Vec<&String> = kn.list();
-
-        let filtered_keys: Vec<&String> = if let Some(ref prefix) = self.prefix {
-            keys.into_iter().filter(|k| k.starts_with(prefix)).collect()
-        } else {
-            keys
-        };
-
-        if filtered_keys.is_empty() {
+        let prefix = self.prefix.as_deref();
+        if !kn
+            .list()
+            .iter()
+            .any(|k| prefix.is_none_or(|p| k.starts_with(p)))
+        {
             println!("No secrets to export");
             return Ok(ExitCode::SUCCESS);
         }
 
+        if self.os_keychain {
+            if !self.dry_run && !cfg!(any(target_os = "macos", windows)) {
+                bail!(
+                    "exporting to the OS credential store is only available on macOS and Windows"
+                );
+            }
+            let mut count = 0;
+            let entries = kn.list_all()?.into_iter();
+            for entry in entries.filter(|e| prefix.is_none_or(|p| e.key().starts_with(p))) {
+                let key = entry.key();
+                let service = entry.attribute("service").unwrap_or(key);
+                let account = entry.attribute("account").unwrap_or_default();
+                if self.dry_run {
+                    println!("{key}  (service '{service}', account '{account}')");
+                    continue;
+                }
+                check_access(key, kn.effective_policy(key)?, false)?;
+                let value = kn.resolve(key)?.unwrap_or_default();
+                os_keychain::store(service, account, value)?;
+                count += 1;
+            }
+            if !self.dry_run {
+                println!("Exported {count} secret(s) to the OS credential store");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if self.wifi {
+            return self.export_wifi(&kn);
+        }
+        if let Some(path) = &self.bundle {
+            return self.export_bundle(&kn, path);
+        }
+
+        let file = self.output.or(self.file);
         let format = self
             .format
+            .map(Format::from)
             .or_else(|| {
-                self.file
-                    .as_ref()
+                file.as_ref()
                     .and_then(|p| p.extension()?.to_str())
-                    .and_then(ExportFormat::from_extension)
+                    .and_then(Format::from_extension)
             })
-            .unwrap_or(ExportFormat::Json);
-
-        match format {
-            ExportFormat::Env => {
-                let output = format_as_env(&kn, &filtered_keys)?;
-                if let Some(ref path) = self.file {
-                    write_file_secure(path, output.as_bytes())?;
-                } else {
-                    println!("{output}");
-                }
-            }
-            ExportFormat::Json => {
-                let output = format_as_json(&kn, &filtered_keys)?;
-                if let Some(ref path) = self.file {
-                    write_file_secure(path, output.as_bytes())?;
-                } else {
-                    println!("{output}");
-                }
+            .unwrap_or(Format::Json);
+
+        let output = if let Some(mode) = self.redact {
+            kn.export_redacted(format, prefix, mode.into())?
+        } else {
+            for key in kn
+                .list()
+                .iter()
+                .filter(|k| prefix.is_none_or(|p| k.starts_with(p)))
+            {
+                check_access(key, kn.effective_policy(key)?, file.is_none())?;
             }
+            kn.export(format, prefix)?
+        };
+
+        if let Some(ref path) = file {
+            write_file_secure(path, output.as_bytes())?;
+        } else {
+            println!("{}", *output);
         }
 
         Ok(ExitCode::SUCCESS)
     }
 }
 
-/// Writes `data` to `path`, restricting the file to owner-only (0600) on Unix so
-/// exported plaintext secrets are not world/group readable (mirrors the keystore's
-/// permission hardening in `storage.rs`).
-#[cfg(unix)]
-fn write_file_secure(path: &Path, data: &[u8]) -> Result<()> {
-    use std::fs::{OpenOptions, Permissions};
-    use std::io::Write as _;
-    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .mode(0o600)
-        .open(path)?;
-
-    // Tighten before writing, in case the file already existed with looser modes.
-    file.set_permissions(Permissions::from_mode(0o600))?;
-    file.write_all(data)?;
-
-    Ok(())
-}
+impl ExportCommand {
+    fn export_bundle(&self, kn: &keynest::Keynest, path: &Path) -> Result<ExitCode> {
+        let kdf = self.kdf.to_kdf_params()?;
+        self.strength.apply();
+        let prefix = self.prefix.as_deref();
+        let keys: Vec<_> = kn
+            .list()
+            .into_iter()
+            .filter(|k| prefix.is_none_or(|p| k.starts_with(p)))
+            .collect();
+        for key in &keys {
+            check_access(key, kn.effective_policy(key)?, false)?;
+        }
+        let passphrase = match &self.passphrase_env {
+            Some(var) => passphrase_from_env(var)?,
+            None => auth::read_new_password_with_confirmation()?,
+        };
+        let bundle = kn.export_bundle(prefix, passphrase, kdf)?;
+        write_file_secure(path, &bundle)?;
+        println!(
+            "Exported {} secret(s) to the bundle {}",
+            keys.len(),
+            path.display()
+        );
+        Ok(ExitCode::SUCCESS)
+    }
 
-#[cfg(not(unix))]
-fn write_file_secure(path: &Path, data: &[u8]) -> Result<()> {
-    std::fs::write(path, data)?;
-    Ok(())
-}
+    fn export_wifi(&self, kn: &keynest::Keynest) -> Result<ExitCode> {
+        let dir = self.nm_dir.as_deref().unwrap_or(wifi::DEFAULT_DIR.as_ref());
+        let namespace = format!("{}/", wifi::NAMESPACE);
+        let prefix = self.prefix.as_deref();
+        let entries: Vec<_> = kn
+            .list_all()?
+            .into_iter()
+            .filter(|e| {
+                e.key().starts_with(&namespace) && prefix.is_none_or(|p| e.key().starts_with(p))
+            })
+            .collect();
+        if entries.is_empty() {
+            println!("No wifi/ secrets to export");
+            return Ok(ExitCode::SUCCESS);
+        }
 
-fn format_as_env(kn: &Keynest, keys: &[&String]) -> Result<String> {
-    let mut output = String::new();
-    for key in keys {
-        if let Some(value) = kn.get(key) {
-            writeln!(&mut output, "{}={}", key, escape_env_value(value))?;
+        let mut count = 0;
+        for entry in entries {
+            let key = entry.key();
+            let ssid = entry
+                .attribute("ssid")
+                .unwrap_or_else(|| &key[namespace.len()..]);
+            if self.dry_run {
+                match wifi::plan(dir, ssid)? {
+                    wifi::Change::Created(path) => {
+                        println!("{key}  (ssid '{ssid}', new {})", path.display())
+                    }
+                    wifi::Change::Updated(path) => {
+                        println!("{key}  (ssid '{ssid}', updates {})", path.display())
+                    }
+                }
+                continue;
+            }
+            check_access(key, kn.effective_policy(key)?, false)?;
+            let value = kn.resolve(key)?.unwrap_or_default();
+            wifi::write(dir, ssid, value)
+                .map_err(|e| e.context(format!("cannot export '{key}'")))?;
+            count += 1;
+        }
+        if !self.dry_run {
+            println!("Exported {count} Wi-Fi password(s) to {}", dir.display());
+            println!("Run 'nmcli connection reload' to apply them");
         }
-    }
-    Ok(output)
-}
 
-fn escape_env_value(value: &str) -> String {
-    if value.contains(' ')
-        || value.contains('"')
-        || value.contains('\'')
-        || value.contains('\n')
-        || value.contains('\\')
-    {
-        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
-    } else {
-        value.to_string()
+        Ok(ExitCode::SUCCESS)
     }
 }
-
-fn format_as_json(kn: &Keynest, keys: &[&String]) -> Result<String> {
-    // BTreeMap keeps the JSON output sorted by key (deterministic across runs).
-    let map: BTreeMap<&str, &str> = keys
-        .iter()
-        .filter_map(|k| kn.get(k).map(|v| (k.as_str(), v)))
-        .collect();
-    Ok(serde_json::to_string_pretty(&map)?)
-}
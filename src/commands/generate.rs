@@ -0,0 +1,117 @@
+use anyhow::{Result, bail};
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+use keynest::generator::{PassphraseOptions, PasswordOptions};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest generate                               Print a 24-character password of letters and digits
+  keynest generate --length 32 --symbols --no-ambiguous
+                                                 32 characters including symbols, without 0/O, 1/l/I, ...
+  keynest generate db/password --symbols         Store a new password under db/password without printing it
+  keynest generate db/password --force --show    Replace the stored password and print the new one
+  keynest generate --words 6                     A passphrase of six words, e.g. 'lunar-cactus-...'
+  keynest generate --words 5 --separator ' ' --capitalize
+
+Passwords contain at least one character of every enabled class. Passphrase words come
+from a built-in list of 2048 words (11 bits per word).")]
+pub struct GenerateCommand {
+    /// Store the generated value under this key instead of printing it
+    pub key: Option<String>,
+
+    /// Number of characters
+    #[arg(long, short = 'l', default_value_t = 24)]
+    pub length: usize,
+
+    /// Include symbols (ASCII punctuation)
+    #[arg(long, short = 's')]
+    pub symbols: bool,
+
+    /// Leave out easily confused characters (0 O 1 l I | ` ' ")
+    #[arg(long = "no-ambiguous")]
+    pub no_ambiguous: bool,
+
+    /// Leave out lowercase letters
+    #[arg(long = "no-lowercase")]
+    pub no_lowercase: bool,
+
+    /// Leave out uppercase letters
+    #[arg(long = "no-uppercase")]
+    pub no_uppercase: bool,
+
+    /// Leave out digits
+    #[arg(long = "no-digits")]
+    pub no_digits: bool,
+
+    /// Generate a passphrase of this many words instead of a password
+    #[arg(long, short = 'w', value_name = "N", conflicts_with_all = [
+        "length", "symbols", "no_ambiguous", "no_lowercase", "no_uppercase", "no_digits"
+    ])]
+    pub words: Option<usize>,
+
+    /// Text between passphrase words
+    #[arg(long, default_value = "-", requires = "words")]
+    pub separator: String,
+
+    /// Capitalize passphrase words
+    #[arg(long, requires = "words")]
+    pub capitalize: bool,
+
+    /// Replace the value if KEY already exists
+    #[arg(long, short = 'f', requires = "key")]
+    pub force: bool,
+
+    /// Also print the value when storing it under KEY
+    #[arg(long, requires = "key")]
+    pub show: bool,
+}
+
+impl Command for GenerateCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let (value, bits) = match self.words {
+            Some(words) => {
+                let options = PassphraseOptions::new(words)
+                    .with_separator(&self.separator)
+                    .with_capitalize(self.capitalize);
+                (options.generate()?, options.entropy_bits())
+            }
+            None => {
+                let options = PasswordOptions::new(self.length)
+                    .with_lowercase(!self.no_lowercase)
+                    .with_uppercase(!self.no_uppercase)
+                    .with_digits(!self.no_digits)
+                    .with_symbols(self.symbols)
+                    .with_exclude_ambiguous(self.no_ambiguous);
+                (options.generate()?, options.entropy_bits())
+            }
+        };
+
+        let Some(key) = self.key else {
+            println!("{}", value.as_str());
+            return Ok(ExitCode::SUCCESS);
+        };
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+        if kn.get(&key)?.is_none() {
+            kn.set(&key, &value)?;
+        } else if self.force {
+            kn.update(&key, &value)?;
+        } else {
+            bail!("secret '{key}' already exists; use --force to replace it");
+        }
+        kn.save()?;
+
+        if self.show {
+            println!("{}", value.as_str());
+        }
+        eprintln!("stored generated secret '{key}' ({bits:.0} bits)");
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
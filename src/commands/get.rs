@@ -1,13 +1,21 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, SecondsFormat, Utc};
 use clap::Args;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
 use std::process::ExitCode;
+use zeroize::Zeroizing;
 
-use super::super::auth;
 use crate::commands::Command;
 use crate::commands::common::{
-    copy_to_clipboard, print_json, print_plain, resolve_existing_storage,
+    check_access, copy_to_clipboard, parse_fd, print_json, reader_name, resolve_existing_storage,
+    track_chain, unlock_indexed, write_to_fd,
 };
-use keynest::Keynest;
+use crate::commands::markdown;
+use keynest::detect::{self, ValueKind};
+use keynest::{EntryKind, OtpAuth, SshCertificateInfo};
 
 #[derive(Args)]
 #[command(
@@ -17,7 +25,16 @@ Examples:
   keynest get api_key                              Display the secret value on stdout
   keynest get api_key --clip                       Copy the secret to clipboard (auto-clears after 15 seconds)
   keynest get api_key -c --timeout 30              Copy the secret to clipboard with custom timeout
-  keynest get api_key --json                       Output the secret as JSON (includes key and value)"
+  keynest get api_key --json                       Output the secret as JSON (includes key and value)
+  keynest get api_key --no-resolve                 Show a `ref:<key>` value itself instead of following it
+  keynest get api_key -n > token.txt               Write the value without a trailing newline
+  keynest get tls/key --base64                     Print the value base64-encoded
+  keynest get tls/der --raw > key.der              Decode a base64-stored binary secret and write the raw bytes
+  keynest get db/password --fd 3 3>&1 >/dev/null   Write the value to an inherited file descriptor
+  keynest get api/token --pretty                   Show decoded JWT claims, a certificate summary, formatted JSON, ...
+  keynest get recovery/bank --pretty               Render a note's markdown
+  keynest get api_key --strict                     Fail instead of warning if the secret has expired
+  keynest get github --field user                  Display a field of a multi-field entry"
 )]
 pub struct GetCommand {
     pub key: String,
@@ -33,6 +50,38 @@ pub struct GetCommand {
     /// Output as JSON
     #[arg(long, short = 'j')]
     pub json: bool,
+
+    /// Do not follow `ref:<key>` references
+    #[arg(long = "no-resolve")]
+    pub no_resolve: bool,
+
+    /// Do not print a trailing newline
+    #[arg(long = "no-newline", short = 'n', conflicts_with_all = ["clip", "json"])]
+    pub no_newline: bool,
+
+    /// Print the value base64-encoded
+    #[arg(long, conflicts_with_all = ["clip", "json", "raw"])]
+    pub base64: bool,
+
+    /// Decode a base64-encoded value and write the raw bytes (no trailing newline)
+    #[arg(long, conflicts_with_all = ["clip", "json", "no_newline"])]
+    pub raw: bool,
+
+    /// Write the value to this inherited file descriptor (3 or higher) instead of stdout
+    #[arg(long, value_name = "FD", value_parser = parse_fd, conflicts_with_all = ["clip", "json"])]
+    pub fd: Option<u32>,
+
+    /// Detect the value format and display it accordingly (decoded JWT claims, certificate summary, formatted JSON)
+    #[arg(long, conflicts_with_all = ["clip", "json", "base64", "raw", "fd"])]
+    pub pretty: bool,
+
+    /// Fail if the secret has expired instead of printing a warning
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Display this field of the entry instead of its value
+    #[arg(long, value_name = "NAME", conflicts_with = "no_resolve")]
+    pub field: Option<String>,
 }
 
 impl Command for GetCommand {
@@ -42,17 +91,99 @@ impl Command for GetCommand {
         }
 
         let storage = resolve_existing_storage(store)?;
-        let password = auth::read_password()?;
-        let kn = Keynest::open_with_storage(password, storage)?;
+        let path = storage.path().to_path_buf();
+        let mut kn = unlock_indexed(storage)?;
+
+        let entry = kn.entry(&self.key)?;
+        let is_note = self.pretty && entry.is_some_and(|e| e.kind() == EntryKind::Note);
+        let expired = entry
+            .and_then(|e| e.expires())
+            .filter(|expires| *expires <= Utc::now());
+        let field_names: Vec<&str> = entry
+            .map(|e| e.fields().keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        let fields_json = if self.json && self.field.is_none() && !field_names.is_empty() {
+            entry.map(|e| serde_json::json!(e.fields()))
+        } else {
+            None
+        };
+        if let Some(entry) = entry {
+            if let Some(name) = &self.field {
+                if entry.field(name).is_none() {
+                    anyhow::bail!(
+                        "secret '{}' has no field '{name}' (fields: {})",
+                        self.key,
+                        fields_or_none(&field_names)
+                    );
+                }
+            } else if entry.value().is_empty() && !field_names.is_empty() && !self.json {
+                anyhow::bail!(
+                    "secret '{}' only has fields ({}); use --field NAME",
+                    self.key,
+                    field_names.join(", ")
+                );
+            }
+        }
+        let policy = if self.no_resolve || self.field.is_some() {
+            kn.entry(&self.key)?.map(|e| e.policy())
+        } else {
+            kn.effective_policy(&self.key)?
+        };
+        check_access(&self.key, policy, !self.clip && self.fd.is_none())?;
+
+        let secret = if let Some(name) = &self.field {
+            kn.entry(&self.key)?.and_then(|e| e.field(name))
+        } else if self.no_resolve {
+            kn.get(&self.key)?
+        } else {
+            kn.resolve(&self.key)?
+        };
 
-        match kn.get(&self.key) {
+        match secret {
             Some(secret) => {
+                if let Some(expires) = expired {
+                    let expires = expires.to_rfc3339_opts(SecondsFormat::Secs, true);
+                    if self.strict {
+                        eprintln!("secret '{}' expired at {expires}", self.key);
+                        return Ok(ExitCode::from(1));
+                    }
+                    eprintln!("warning: secret '{}' expired at {expires}", self.key);
+                }
                 if self.clip {
                     copy_to_clipboard(secret, self.timeout)?;
+                } else if is_note {
+                    let color = std::io::stdout().is_terminal();
+                    let rendered = Zeroizing::new(markdown::render(secret, color));
+                    write_stdout(rendered.as_bytes())?;
+                } else if self.pretty {
+                    print_pretty(secret)?;
                 } else if self.json {
-                    print_json(&serde_json::json!({"key": self.key, "value": secret}))?;
+                    let mut json = serde_json::json!({"key": self.key, "value": secret});
+                    if let Some(name) = &self.field {
+                        json["field"] = serde_json::json!(name);
+                    }
+                    if let Some(fields) = fields_json {
+                        json["fields"] = fields;
+                    }
+                    print_json(&json)?;
                 } else {
-                    print_plain(&secret);
+                    let mut output = if self.raw {
+                        Zeroizing::new(BASE64.decode(secret.trim()).with_context(|| {
+                            format!("value of '{}' is not valid base64", self.key)
+                        })?)
+                    } else if self.base64 {
+                        Zeroizing::new(BASE64.encode(secret).into_bytes())
+                    } else {
+                        Zeroizing::new(secret.as_bytes().to_vec())
+                    };
+                    if !self.raw && !self.no_newline {
+                        output.push(b'\n');
+                    }
+
+                    match self.fd {
+                        Some(fd) => write_to_fd(fd, &output)?,
+                        None => write_stdout(&output)?,
+                    }
                 }
             }
             None => {
@@ -61,6 +192,140 @@ impl Command for GetCommand {
             }
         }
 
+        if kn.records_reads()? {
+            let mut kn = kn.into_keynest()?;
+            kn.set_reader(&reader_name());
+            track_chain(&mut kn, &path);
+            kn.record_get(&self.key)?;
+            kn.save_usage()?;
+        }
+
         Ok(ExitCode::SUCCESS)
     }
 }
+
+fn fields_or_none(names: &[&str]) -> String {
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// Prints `value` according to its detected format, preceded by a `Type:` line.
+fn print_pretty(value: &str) -> Result<()> {
+    let kind = detect::detect(value);
+    let mut out = Zeroizing::new(String::new());
+
+    match kind {
+        ValueKind::Jwt => {
+            let jwt = detect::decode_jwt(value)?;
+            writeln!(
+                out,
+                "Type: JWT ({}, signature not verified)",
+                jwt.algorithm().unwrap_or("unknown algorithm")
+            )?;
+            for (name, label) in [("iat", "Issued"), ("nbf", "Not before"), ("exp", "Expires")] {
+                if let Some(time) = jwt.time_claim(name) {
+                    let expired = if name == "exp" && time < Utc::now() {
+                        " (expired)"
+                    } else {
+                        ""
+                    };
+                    writeln!(out, "{label}: {}{expired}", time.to_rfc3339())?;
+                }
+            }
+            writeln!(
+                out,
+                "Header:\n{}",
+                serde_json::to_string_pretty(jwt.header())?
+            )?;
+            writeln!(
+                out,
+                "Claims:\n{}",
+                serde_json::to_string_pretty(jwt.claims())?
+            )?;
+        }
+        ValueKind::Pem => {
+            let blocks = detect::parse_pem(value)?;
+            writeln!(out, "Type: PEM ({} block(s))", blocks.len())?;
+            for block in &blocks {
+                match detect::certificate_summary(block.der()) {
+                    Ok(cert) if block.is_certificate() => {
+                        let status = if cert.is_expired_at(Utc::now()) {
+                            " (not valid now)"
+                        } else {
+                            ""
+                        };
+                        writeln!(out, "\nCertificate")?;
+                        writeln!(out, "  Subject:    {}", cert.subject())?;
+                        writeln!(out, "  Issuer:     {}", cert.issuer())?;
+                        writeln!(out, "  Serial:     {}", cert.serial())?;
+                        writeln!(out, "  Not before: {}", cert.not_before().to_rfc3339())?;
+                        writeln!(
+                            out,
+                            "  Not after:  {}{status}",
+                            cert.not_after().to_rfc3339()
+                        )?;
+                    }
+                    _ => writeln!(out, "\n{} ({} bytes)", block.label(), block.der().len())?,
+                }
+            }
+        }
+        ValueKind::Json => {
+            let json: serde_json::Value = serde_json::from_str(value)?;
+            writeln!(out, "Type: JSON")?;
+            writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+        }
+        ValueKind::OtpAuth => match OtpAuth::parse(value) {
+            Ok(otp) => {
+                writeln!(out, "Type: OTP ({})", otp.kind())?;
+                writeln!(out, "Label:  {}", otp.label())?;
+                if let Some(issuer) = otp.issuer() {
+                    writeln!(out, "Issuer: {issuer}")?;
+                }
+                writeln!(out, "{}", otp.uri())?;
+            }
+            Err(e) => writeln!(out, "Type: otpauth URI (invalid: {e})\n{value}")?,
+        },
+        ValueKind::SshCertificate => match SshCertificateInfo::parse(value) {
+            Ok(cert) => {
+                let format_time = |secs: u64| {
+                    i64::try_from(secs)
+                        .ok()
+                        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                        .map_or_else(|| "forever".to_string(), |t| t.to_rfc3339())
+                };
+                writeln!(out, "Type: SSH {} certificate", cert.kind())?;
+                writeln!(out, "  Key ID:     {}", cert.key_id())?;
+                writeln!(out, "  Serial:     {}", cert.serial())?;
+                writeln!(out, "  Principals: {}", cert.principals().join(", "))?;
+                writeln!(out, "  Valid from: {}", format_time(cert.valid_after()))?;
+                writeln!(out, "  Valid to:   {}", format_time(cert.valid_before()))?;
+                writeln!(out, "  Signed by:  {}", cert.ca_fingerprint())?;
+            }
+            Err(e) => writeln!(out, "Type: SSH certificate (invalid: {e})\n{value}")?,
+        },
+        ValueKind::Base64 => {
+            let decoded = Zeroizing::new(BASE64.decode(value.trim())?);
+            writeln!(out, "Type: base64 ({} bytes decoded)", decoded.len())?;
+            match std::str::from_utf8(&decoded) {
+                Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+                    writeln!(out, "Decoded: {text}")?;
+                }
+                _ => writeln!(out, "{value}")?,
+            }
+        }
+        ValueKind::Uuid => writeln!(out, "Type: UUID\n{value}")?,
+        ValueKind::Text => writeln!(out, "Type: text\n{value}")?,
+    }
+
+    write_stdout(out.as_bytes())
+}
+
+fn write_stdout(bytes: &[u8]) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(bytes)?;
+    stdout.flush()?;
+    Ok(())
+}
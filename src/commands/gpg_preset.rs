@@ -0,0 +1,133 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{ExitCode, Stdio};
+use zeroize::Zeroizing;
+
+use crate::commands::Command;
+use crate::commands::common::{check_access, resolve_existing_storage, unlock_indexed};
+
+/// Assuan client shipped with GnuPG; the passphrase is sent on its stdin, never in argv.
+const CONNECT_AGENT: &str = "gpg-connect-agent";
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest gpg-preset gpg/work --keygrip 0123456789ABCDEF0123456789ABCDEF01234567
+                                                 Cache the passphrase stored in gpg/work for a key
+  keynest gpg-preset gpg/work --keygrip GRIP1 --keygrip GRIP2
+                                                 Same passphrase for the primary key and a subkey
+  keynest gpg-preset gpg/work --keygrip GRIP --forget
+                                                 Remove the cached passphrase again
+
+Keygrips are listed by: gpg --list-secret-keys --with-keygrip
+gpg-agent only accepts preset passphrases with 'allow-preset-passphrase' in
+gpg-agent.conf (reload with: gpgconf --reload gpg-agent). Preset passphrases stay
+cached until the agent restarts or --forget is used.")]
+pub struct GpgPresetCommand {
+    /// Key of the secret holding the GPG passphrase
+    pub key: String,
+
+    /// Keygrip of a GPG key to preset (40 hex digits); may be repeated
+    #[arg(long = "keygrip", value_name = "GRIP", required = true)]
+    pub keygrips: Vec<String>,
+
+    /// Clear the cached passphrase instead of presetting it
+    #[arg(long)]
+    pub forget: bool,
+}
+
+impl Command for GpgPresetCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        for grip in &self.keygrips {
+            if grip.len() != 40 || !grip.bytes().all(|b| b.is_ascii_hexdigit()) {
+                bail!("invalid keygrip '{grip}' (expected 40 hex digits)");
+            }
+        }
+
+        let mut script = Zeroizing::new(String::new());
+        if self.forget {
+            for grip in &self.keygrips {
+                writeln!(script, "CLEAR_PASSPHRASE --mode=normal {grip}")?;
+            }
+        } else {
+            let storage = resolve_existing_storage(store)?;
+            let mut kn = unlock_indexed(storage)?;
+            check_access(&self.key, kn.effective_policy(&self.key)?, false)?;
+            let Some(passphrase) = kn.resolve(&self.key)? else {
+                eprintln!("key not found: {}", self.key);
+                return Ok(ExitCode::from(1));
+            };
+            let hex = Zeroizing::new(
+                passphrase
+                    .bytes()
+                    .map(|b| format!("{b:02X}"))
+                    .collect::<String>(),
+            );
+            for grip in &self.keygrips {
+                writeln!(script, "PRESET_PASSPHRASE {grip} -1 {}", *hex)?;
+            }
+        }
+        script.push_str("/bye\n");
+
+        let responses = run_agent_script(&script)?;
+        if responses.len() != self.keygrips.len() {
+            bail!("unexpected response from {CONNECT_AGENT}: {responses:?}");
+        }
+
+        let mut failed = false;
+        for (grip, response) in self.keygrips.iter().zip(&responses) {
+            if response.starts_with("OK") {
+                if self.forget {
+                    println!("cleared cached passphrase for {grip}");
+                } else {
+                    println!("preset passphrase from '{}' for {grip}", self.key);
+                }
+            } else {
+                eprintln!("gpg-agent refused {grip}: {response}");
+                if response.contains("Not supported") || response.contains("Forbidden") {
+                    eprintln!("hint: add 'allow-preset-passphrase' to gpg-agent.conf");
+                }
+                failed = true;
+            }
+        }
+
+        Ok(if failed {
+            ExitCode::from(1)
+        } else {
+            ExitCode::SUCCESS
+        })
+    }
+}
+
+/// Runs `script` through `gpg-connect-agent` and returns its `OK`/`ERR` lines, one per
+/// command.
+fn run_agent_script(script: &str) -> Result<Vec<String>> {
+    let mut child = std::process::Command::new(CONNECT_AGENT)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("failed to run {CONNECT_AGENT} (is GnuPG installed?)"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(script.as_bytes())
+            .with_context(|| format!("failed to write to {CONNECT_AGENT}"))?;
+    }
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("failed to wait for {CONNECT_AGENT}"))?;
+    if !output.status.success() {
+        bail!("{CONNECT_AGENT} exited with {}", output.status);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| *line == "OK" || line.starts_with("OK ") || line.starts_with("ERR "))
+        .map(str::to_string)
+        .collect())
+}
@@ -0,0 +1,375 @@
+//! Long-form help on advanced topics, embedded in the binary.
+//!
+//! Each topic is structured data (a summary and titled sections of markdown) rendered
+//! with the note renderer and shown in a pager, so the documentation is available on
+//! machines without network access and always matches the installed version.
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use std::io::{self, IsTerminal, Write};
+use std::process::{ExitCode, Stdio};
+
+use crate::commands::Command;
+use crate::commands::markdown;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest help-topics                           List the topics
+  keynest help-topics crypto                    Read about the cryptography in a pager
+  keynest help-topics format --no-pager         Print the file format description
+
+The pager is $PAGER, or 'less -R'; output that is not a terminal is printed as is.")]
+pub struct HelpTopicsCommand {
+    /// Topic to show; lists the topics if omitted
+    pub topic: Option<TopicName>,
+
+    /// Print the topic instead of opening a pager
+    #[arg(long)]
+    pub no_pager: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TopicName {
+    Crypto,
+    Format,
+    Sync,
+}
+
+impl Command for HelpTopicsCommand {
+    fn run(self, _store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        let Some(name) = self.topic else {
+            for topic in TOPICS {
+                println!("{:<8}  {}", topic.name.as_str(), topic.summary);
+            }
+            return Ok(ExitCode::SUCCESS);
+        };
+
+        let topic = TOPICS
+            .iter()
+            .find(|t| t.name == name)
+            .expect("every topic name has a topic");
+        let interactive = io::stdout().is_terminal();
+        let text = markdown::render(&topic.to_markdown(), interactive);
+        if self.no_pager || !interactive || !page(&text)? {
+            io::stdout().write_all(text.as_bytes())?;
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl TopicName {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Crypto => "crypto",
+            Self::Format => "format",
+            Self::Sync => "sync",
+        }
+    }
+}
+
+/// A help topic: a one-line summary and titled sections of markdown.
+struct Topic {
+    name: TopicName,
+    title: &'static str,
+    summary: &'static str,
+    sections: &'static [(&'static str, &'static str)],
+}
+
+impl Topic {
+    fn to_markdown(&self) -> String {
+        let mut text = format!("# {}\n\n{}\n", self.title, self.summary);
+        for (heading, body) in self.sections {
+            text.push_str(&format!("\n## {heading}\n\n{}\n", body.trim()));
+        }
+        text
+    }
+}
+
+/// Shows `text` in `$PAGER` (default `less -R`). Returns `false` if no pager could be
+/// started, so the caller prints the text itself.
+fn page(text: &str) -> Result<bool> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+    let mut words = pager.split_whitespace();
+    let Some(program) = words.next() else {
+        return Ok(false);
+    };
+    let Ok(mut child) = std::process::Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        return Ok(false);
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit the pager before reading everything.
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    child.wait()?;
+    Ok(true)
+}
+
+const TOPICS: &[Topic] = &[
+    Topic {
+        name: TopicName::Crypto,
+        title: "Cryptography",
+        summary: "How the master password protects the keystore: key derivation, \
+                  encryption, authentication and what is kept in memory.",
+        sections: &[
+            (
+                "Overview",
+                "
+1. The master password is stretched into a 256-bit key with Argon2id.
+2. The entries are serialized and encrypted with XChaCha20-Poly1305 (or AES-256-GCM).
+3. The header (KDF parameters, cipher, salt, encoding) is authenticated as additional
+   data, so it cannot be changed without the password.
+
+Only well-reviewed primitives are used; nothing is written to disk unencrypted.
+",
+            ),
+            (
+                "Key derivation",
+                "
+Argon2id (v0x13) with a random 16-byte salt. The defaults follow the OWASP password
+storage recommendations: 64 MiB of memory, 3 iterations, 1 lane.
+
+The parameters are stored in the header, so each store keeps the cost it was created
+with. Raise them with `keynest init --argon-mem 131072 --argon-time 5`, or later with
+`keynest rekey`, which also changes the password. A profile of the config file can set
+the defaults of new stores.
+
+Where scrypt is mandated, `keynest init --kdf scrypt` derives the key with scrypt
+instead (N = 2^17, r = 8, p = 1 unless --scrypt-n, --scrypt-r or --scrypt-p is given).
+
+Unlocking can be interrupted with Ctrl-C while the KDF runs; keynest then exits with
+status 130.
+",
+            ),
+            (
+                "Encryption",
+                "
+XChaCha20-Poly1305 with a fresh random 24-byte nonce for every record on every save.
+Stores created with `keynest init --cipher aes256-gcm` use AES-256-GCM with a random
+12-byte nonce instead, for environments that mandate AES.
+Tampering with the ciphertext, the header, or using a wrong password makes decryption
+fail; keynest never returns partially decrypted data.
+
+In the current format the index and every section of entries are separate records.
+Each record's additional data is the header followed by its record number, so records
+cannot be reordered or moved between files, and the index lists the nonces of its
+sections so sections from an older save are rejected.
+",
+            ),
+            (
+                "Attachments",
+                "
+Attached files are split into 1 MiB chunks stored in `<store>.blobs/`. They are
+encrypted with a random attachment key kept inside the encrypted payload, so `rekey`
+does not have to re-encrypt them. Chunk names are keyed HMACs of their content:
+identical files are stored once without revealing a plain content hash.
+",
+            ),
+            (
+                "Windows account binding",
+                "
+`keynest init --dpapi` mixes a secret protected with DPAPI for the current Windows user
+into the key. A copy of the file on another machine or account cannot be opened, even
+with the right password. Losing the Windows profile loses the store: keep an export or
+an unbound copy somewhere safe.
+",
+            ),
+            (
+                "Keyfiles",
+                "
+`keynest init --keyfile PATH` mixes the SHA-256 digest of a file into the key, so the
+store opens only with the password and the keyfile (`--keyfile` or $KEYNEST_KEYFILE on
+every command). Keep the keyfile apart from the store, e.g. on a USB stick, and keep a
+backup of it: without it the store is lost. `keynest rekey --new-keyfile` and
+`--remove-keyfile` change it.
+",
+            ),
+            (
+                "Keyslots",
+                "
+`keynest keyslot add NAME` lets another password open the store, e.g. one per team
+member. The records are encrypted with a random data key, and each keyslot holds that
+key encrypted under one password. `keynest rekey` changes only the password you opened
+the store with, and `keynest keyslot remove NAME` stops a password from opening it. A
+removed person who kept a copy of the file can still open that copy: re-create the
+store (e.g. init a new one and import an export) to lock them out entirely.
+",
+            ),
+            (
+                "Memory",
+                "
+Keys, passwords and decrypted values are held in buffers that are zeroized when they
+are dropped. Secrets passed to commands are best handed over with `run --to-fd` or
+`run --tmpfile` rather than environment variables, which other processes of the same
+user may be able to read.
+",
+            ),
+            (
+                "Threat model",
+                "
+Protected against: theft of the keystore file or its backups, offline guessing (slowed
+by Argon2id), and tampering with the file.
+
+Not protected against: malware running as your user while the store is unlocked, a
+keylogger capturing the master password, or a weak master password.
+",
+            ),
+        ],
+    },
+    Topic {
+        name: TopicName::Format,
+        title: "File format",
+        summary: "What keynest writes to disk: the keystore file, its header and records, \
+                  and the files kept next to it.",
+        sections: &[
+            (
+                "Files",
+                "
+- `<store>`: the keystore itself (default `.keynest.db` in the data directory, or
+  `--store`, `KEYNEST_PATH`, or the store of a profile)
+- `<store>.bak.<time>`: previous versions, when `keynest backup enable` or a profile
+  sets a backup count
+- `<store>.blobs/`: encrypted attachment chunks
+- `<store>.keyindex`: the optional Bloom filter of key names (`keynest key-index`)
+- `<store>.journal`: the changes saved since the store was last rewritten, while the
+  journal is on (`keynest compact --journal`)
+
+Saves write a temporary file, sync it, and rename it over the store, so a crash leaves
+either the old or the new version. `keynest repair` recovers from leftover temporary
+files and backups. With the journal on, saves append the changed entries as one
+encrypted frame instead, and a crash can only cut the last frame short.
+",
+            ),
+            (
+                "Layout",
+                "
+```
+MAGIC \"KNST\" (4) | VERSION (1) | HEADER_LEN (4) | HEADER TLVs | RECORD_COUNT (4) | RECORD...
+RECORD = NONCE (24) | CIPHERTEXT_LEN (4) | CIPHERTEXT
+```
+
+Lengths are little-endian. Record 0 is the index: store metadata and settings, every key
+with the number of its section, and the nonces of the sections. The following records
+hold up to 256 entries each (fewer above about 1 MiB), in key order. Reading one secret
+decrypts the index and a single section. After `keynest convert --per-entry` every
+entry is a section of its own, encrypted with an entry key that the index holds wrapped
+with the store key, so reading one decrypts no other value.
+",
+            ),
+            (
+                "Header fields",
+                "
+The header is a list of TYPE (1) | LENGTH (2) | VALUE fields:
+
+- 1 KDF: Argon2 memory, time and parallelism, or 2 and the scrypt log2(N), r and p
+- 2 Salt
+- 5 Algorithm: 1 = XChaCha20-Poly1305, 2 = AES-256-GCM
+- 6 Encoding: serialization, compression and padding of the records
+- 7 DPAPI blob: the protected secret of a store bound to a Windows account
+- 8 Keyfile: present if the key also depends on a keyfile
+- 9 Keyslot: the data key wrapped for one password, one per keyslot
+- 10 Key check: tells a wrong password (exit status 3) from corrupted data (status 4)
+
+`keynest info --no-decrypt` shows the header without the password.
+",
+            ),
+            (
+                "Payload encoding",
+                "
+Records are MessagePack. Stores written as JSON by older versions move to MessagePack on
+their next save, unless `keynest convert --encoding json` asked for JSON.
+`keynest convert --compress --pad` adds zstd compression (`--compress=deflate` for
+DEFLATE) and padding to a power of two so record sizes only reveal a size class. The payload carries a schema version; stores written by
+older versions are migrated when opened.
+",
+            ),
+            (
+                "Older versions",
+                "
+Format 2 stores the whole payload as one ciphertext. Keynest reads it and writes the
+current format on the next save. `keynest compat set 2` also writes a v2 copy beside the
+store on every save, which older keynest versions can open (without the features that
+need format 3). `keynest migrate` rewrites it in the current format right away and keeps
+the original file as a backup.
+",
+            ),
+        ],
+    },
+    Topic {
+        name: TopicName::Sync,
+        title: "Using a store on several machines",
+        summary: "Keynest works offline and has no sync service; how to carry a store \
+                  between machines and share parts of it safely.",
+        sections: &[
+            (
+                "Copying the store",
+                "
+A keystore is a single encrypted file, safe to put on a USB stick, a sync folder or a
+git repository. Copy `<store>.blobs/` along with it if you use attachments. Point
+keynest at the copy with `--store`, `KEYNEST_PATH` or a profile of the config file.
+After `keynest convert --deterministic --per-entry`, a save rewrites only the records of
+the changed entries, so git and Syncthing carry small changes instead of the whole file.
+",
+            ),
+            (
+                "Concurrent changes",
+                "
+Every save replaces the whole file, so the last writer wins: changes made to two copies
+of a store are not merged on save. Edit on one machine at a time and let the copy reach
+the others before changing it there. If a sync tool leaves a conflicting copy,
+`keynest merge <file>` adds its new entries and keeps the newer side of each entry
+changed in both (`--prefer ours|theirs`, or `--interactive` to decide each one).
+Entries deleted in one copy come back from the other; delete them again after merging.
+`keynest resolve` does this for every `*.sync-conflict*` or \"conflicted copy\" file next
+to the store and removes them afterwards. In git, register `keynest --store %A merge %B`
+as merge driver for the store.
+",
+            ),
+            (
+                "Backups",
+                "
+Run `keynest backup enable --keep 5` (or set `backups = 5` in a profile) to copy the
+store to `<store>.bak.<time>` before every save and keep the newest 5 copies.
+`keynest backup list` shows them and `keynest backup restore <time>` puts one back.
+Backups are encrypted like the store; `keynest repair` picks the newest one that
+decrypts if the store is damaged.
+",
+            ),
+            (
+                "Detecting rollbacks",
+                "
+Anyone who can write to a synced folder can put back an older copy of the store: it
+still decrypts, it only lacks the latest changes. Run `keynest verify-chain` once on
+each machine; it remembers the generation of the store there, later saves move it
+forward, and later runs report a store that went back or was replaced by a changed
+copy. After restoring a backup on purpose, or merging a conflict copy on another
+machine, confirm the store with `keynest verify-chain --accept`.
+",
+            ),
+            (
+                "Sharing part of a store",
+                "
+`keynest snapshot --keys prod/ --out prod.db` writes a read-only store with only the
+selected keys, under its own passphrase, to hand to a colleague or a server. Within one
+store, `keynest promote --from staging/ --to prod/` copies a namespace.
+",
+            ),
+            (
+                "Moving between tools",
+                "
+`keynest export --format json` and `keynest import` move secrets in and out as env,
+JSON, YAML or TOML files. Exports are plain text: write them to an encrypted volume or a
+tmpfs and delete them afterwards. An import is saved in one step, all or nothing.
+",
+            ),
+        ],
+    },
+];
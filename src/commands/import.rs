@@ -1,20 +1,29 @@
-use anyhow::Result;
-use clap::{Args, ValueEnum};
-use std::collections::HashMap;
-use std::io::Cursor;
-use std::path::PathBuf;
+use anyhow::{Context, Result, bail};
+use clap::{ArgGroup, Args, ValueEnum};
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
-use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::resolve_existing_storage;
+use crate::commands::browser::{self, Browser};
+use crate::commands::common::{
+    confirm, passphrase_from_env, resolve_existing_storage, unlock_keystore,
+};
+use crate::commands::csv::{self, Duplicates, Mapping};
+use crate::commands::os_keychain;
+use crate::commands::wifi;
 use dotenvy::from_read_iter as parse_env_dotenv;
-use keynest::Keynest;
+use keynest::{ImportPolicy, ImportSummary};
+use zeroize::Zeroizing;
 
 #[derive(Debug, Clone, ValueEnum)]
 pub enum ImportFormat {
     Env,
     Json,
+    Yaml,
+    Toml,
+    Csv,
 }
 
 impl ImportFormat {
@@ -22,6 +31,9 @@ impl ImportFormat {
         match ext.to_lowercase().as_str() {
             "env" => Some(ImportFormat::Env),
             "json" => Some(ImportFormat::Json),
+            "yaml" | "yml" => Some(ImportFormat::Yaml),
+            "toml" => Some(ImportFormat::Toml),
+            "csv" => Some(ImportFormat::Csv),
             _ => None,
         }
     }
@@ -30,38 +42,165 @@ impl ImportFormat {
 #[derive(Args)]
 #[command(
     arg_required_else_help = true,
+    group = ArgGroup::new("source").args(["os_keychain", "browser", "wifi"]),
     after_help = "\
  Examples:
    keynest import .env                     Import from .env file
    keynest import secrets.json             Import from JSON file
+   keynest import secrets.yaml             Import from YAML (nested maps become prod/db/password keys)
+   keynest import secrets.toml             Import from TOML (tables become namespaces)
    keynest import --format env file.txt     Import from file with explicit format
+   keynest import --csv export.csv --map 'name=1,value=3,note=4'
+                                           Import a CSV file: names from column 1, values
+                                           from column 3, column 4 as the field 'note'
+   keynest import --csv export.csv --header --map 'name=title,value=password,user=username'
+                                           Map the columns by their header names
+   keynest import --csv export.csv --header --preview
+                                           Show what would be imported, without values
    keynest import --overwrite .env          Overwrite existing secrets
-   keynest import --prefix API_ .env        Only import secrets with this prefix"
+   keynest import --skip-existing .env      Keep existing secrets (the default)
+   keynest import --prefix API_ .env        Only import secrets with this prefix
+   keynest import --os-keychain --list      List the credentials of the OS credential store
+   keynest import --os-keychain --prefix github.com/
+                                           Copy the selected credentials into keynest
+   keynest import --browser firefox        Import the passwords saved by Firefox
+   keynest import --browser chrome --list  List the logins saved by Chrome
+   keynest import --browser firefox --browser-profile ~/old-firefox-profile
+                                           Import from a specific profile directory
+   keynest import --wifi                   Import the Wi-Fi passwords of NetworkManager
+   keynest import --bundle laptop.knb      Import a bundle written by 'keynest export --bundle'
+
+ All secrets are applied in a single save; if one is rejected, none are imported.
+
+ CSV files need a column mapping (--map): name and value, optionally tags (separated
+ by spaces, commas or semicolons), and any number of fields. Without --map, a --header
+ with well-known names (name/title, password/value, tags) is mapped automatically; on a
+ terminal, keynest asks for the columns and shows a preview instead. Rows without a
+ name or value are skipped; --duplicates decides about rows repeating a name.
+
+ --os-keychain reads the macOS keychain (generic and internet passwords) or the
+ Windows Credential Manager (generic credentials) and imports each credential as
+ <service>/<account>. macOS may ask for permission for each password read.
+
+ --browser reads the passwords saved in a browser profile (the default profile
+ unless --browser-profile is given) and imports each login as <host>/<username>,
+ with url and user fields. Close the browser first so its latest changes are on
+ disk. Chromium-based browsers need the OS key: the Secret Service on Linux
+ (through secret-tool), the keychain on macOS, DPAPI on Windows; passwords that
+ cannot be decrypted are counted and skipped. Firefox asks for the primary
+ password if one is set.
+
+ --wifi reads the NetworkManager connections in /etc/NetworkManager/system-connections
+ (or --nm-dir) and imports the passphrase of each WPA personal network as
+ wifi/<ssid>, with an ssid field. Without access to that directory (it is only
+ readable by root), the connections are read through nmcli, which shows the
+ secrets to the logged-in user. 'keynest export --wifi' writes them back.
+
+ --bundle reads an encrypted bundle written by 'keynest export --bundle', asking for its
+ passphrase (or reading it from --passphrase-env). Entries keep their kind, fields,
+ tags, expiry and access policy; an overwritten entry keeps its attachments."
 )]
 pub struct ImportCommand {
     /// File to import (format auto-detected from extension)
-    pub file: PathBuf,
+    #[arg(required_unless_present = "source")]
+    pub file: Option<PathBuf>,
 
-    /// Import format (env or json)
+    /// Import format (env, json, yaml, toml or csv)
     #[arg(long = "format", value_enum)]
     pub format: Option<ImportFormat>,
 
+    /// Import a CSV file (same as --format csv)
+    #[arg(long, conflicts_with_all = ["format", "source"])]
+    pub csv: bool,
+
+    /// With CSV, the columns to import: name=N,value=N[,tags=N][,FIELD=N]..., numbered
+    /// from 1 or named after the header row
+    #[arg(long, value_name = "MAPPING")]
+    pub map: Option<String>,
+
+    /// With CSV, the first row names the columns and is not imported
+    #[arg(long)]
+    pub header: bool,
+
+    /// With CSV, the character between cells (or 'tab')
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = csv::parse_delimiter)]
+    pub delimiter: char,
+
+    /// With CSV, what to do with rows whose name appeared in an earlier row
+    #[arg(long, value_enum, value_name = "MODE", default_value = "first")]
+    pub duplicates: Duplicates,
+
+    /// With CSV, list the entries that would be imported (without values) and stop
+    #[arg(long)]
+    pub preview: bool,
+
+    /// Import from the OS credential store (macOS keychain, Windows Credential Manager)
+    #[arg(long, conflicts_with_all = ["file", "format"])]
+    pub os_keychain: bool,
+
+    /// Import the passwords saved by a browser
+    #[arg(long, value_enum, value_name = "BROWSER", conflicts_with_all = ["file", "format", "os_keychain"])]
+    pub browser: Option<Browser>,
+
+    /// With --browser, the profile directory to read instead of the default profile
+    #[arg(long, value_name = "DIR", requires = "browser")]
+    pub browser_profile: Option<PathBuf>,
+
+    /// Import the Wi-Fi passphrases of NetworkManager connections
+    #[arg(long, conflicts_with_all = ["file", "format", "os_keychain", "browser"])]
+    pub wifi: bool,
+
+    /// With --wifi, the directory of the NetworkManager connections
+    #[arg(long, value_name = "DIR", requires = "wifi")]
+    pub nm_dir: Option<PathBuf>,
+
+    /// With --os-keychain, --browser or --wifi, only list the keys found
+    #[arg(long, requires = "source")]
+    pub list: bool,
+
     /// Overwrite existing secrets
-    #[arg(long = "overwrite")]
+    #[arg(long = "overwrite", conflicts_with = "skip_existing")]
     pub overwrite: bool,
 
+    /// Keep existing secrets unchanged (the default)
+    #[arg(long = "skip-existing")]
+    pub skip_existing: bool,
+
     /// Only import secrets with this prefix
     #[arg(long = "prefix")]
     pub prefix: Option<String>,
+
+    /// FILE is an encrypted bundle written by `keynest export --bundle`
+    #[arg(long, requires = "file", conflicts_with_all = ["format", "csv", "source", "prefix"])]
+    pub bundle: bool,
+
+    /// With --bundle, read its passphrase from this environment variable
+    #[arg(long = "passphrase-env", value_name = "VAR", requires = "bundle")]
+    pub passphrase_env: Option<String>,
 }
 
 impl Command for ImportCommand {
     fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        if self.os_keychain {
+            return self.import_os_keychain(store);
+        }
+        if let Some(browser) = self.browser {
+            return self.import_browser(browser, store);
+        }
+        if self.wifi {
+            return self.import_wifi(store);
+        }
+        let file = self.file.clone().unwrap_or_default();
+        if self.bundle {
+            return self.import_bundle(&file, store);
+        }
+
         let format = self
             .format
+            .clone()
+            .or(self.csv.then_some(ImportFormat::Csv))
             .or_else(|| {
-                self.file
-                    .extension()
+                file.extension()
                     .and_then(|ext| ext.to_str())
                     .and_then(ImportFormat::from_extension)
             })
@@ -69,7 +208,20 @@ impl Command for ImportCommand {
                 anyhow::anyhow!("cannot detect format from file extension; use --format")
             })?;
 
-        let content = std::fs::read_to_string(&self.file)?;
+        let content = std::fs::read_to_string(&file)?;
+        if let ImportFormat::Csv = format {
+            return self.import_csv(&content, store);
+        }
+        if self.map.is_some()
+            || self.header
+            || self.delimiter != ','
+            || self.duplicates != Duplicates::First
+            || self.preview
+        {
+            anyhow::bail!(
+                "--map, --header, --delimiter, --duplicates and --preview only apply to CSV files"
+            );
+        }
 
         let secrets: HashMap<String, String> = match format {
             ImportFormat::Env => {
@@ -77,7 +229,10 @@ impl Command for ImportCommand {
                 let iter = parse_env_dotenv(cursor);
                 iter.collect::<Result<Vec<_>, _>>()?.into_iter().collect()
             }
-            ImportFormat::Json => serde_json::from_str(&content)?,
+            ImportFormat::Json => flatten_document(serde_json::from_str(&content)?)?,
+            ImportFormat::Yaml => flatten_document(serde_yaml::from_str(&content)?)?,
+            ImportFormat::Toml => flatten_document(toml::from_str(&content)?)?,
+            ImportFormat::Csv => unreachable!("CSV files are imported by import_csv"),
         };
 
         if secrets.is_empty() {
@@ -86,44 +241,373 @@ impl Command for ImportCommand {
         }
 
         let storage = resolve_existing_storage(store)?;
-        let password = auth::read_password()?;
-        let mut kn = Keynest::open_with_storage(password, storage)?;
-
-        let mut imported = 0;
-        let mut skipped = 0;
-        let mut filtered = 0;
-
-        for (key, value) in secrets {
-            if let Some(ref prefix) = self.prefix {
-                if !key.starts_with(prefix) {
-                    filtered += 1;
-                    continue;
+        let mut kn = unlock_keystore(storage)?;
+
+        let total = secrets.len();
+        let mut secrets: Vec<(String, String)> = secrets
+            .into_iter()
+            .filter(|(key, _)| self.selected(key))
+            .collect();
+        secrets.sort();
+        let filtered = total - secrets.len();
+
+        let summary = kn.import_entries(secrets, self.policy())?;
+        kn.save()?;
+        self.report(summary, filtered);
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl ImportCommand {
+    fn import_bundle(&self, file: &Path, store: Option<PathBuf>) -> Result<ExitCode> {
+        let bundle =
+            std::fs::read(file).with_context(|| format!("failed to read {}", file.display()))?;
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let passphrase = match &self.passphrase_env {
+            Some(var) => passphrase_from_env(var)?,
+            None if std::io::stdin().is_terminal() => {
+                Zeroizing::new(rpassword::prompt_password("Bundle passphrase: ")?)
+            }
+            None => bail!("no bundle passphrase; pass it with --passphrase-env"),
+        };
+        let summary = kn.import_bundle(&bundle, passphrase, self.policy())?;
+        kn.save()?;
+        self.report(summary, 0);
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn import_csv(&self, content: &str, store: Option<PathBuf>) -> Result<ExitCode> {
+        let records = csv::parse(content, self.delimiter)?;
+        if records.is_empty() {
+            println!("No secrets found in file");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let interactive = self.map.is_none() && csv::can_ask();
+        let (mapping, header) = match &self.map {
+            Some(spec) => {
+                let mapping = Mapping::parse(spec)?;
+                let header = self.header || mapping.uses_names();
+                (mapping, header)
+            }
+            None if interactive => csv::ask_mapping(&records, self.header)?,
+            None => match Mapping::detect(&records[0]).filter(|_| self.header) {
+                Some(mapping) => (mapping, true),
+                None => anyhow::bail!(
+                    "no column mapping for the CSV file; use --map, e.g. --map 'name=1,value=2'"
+                ),
+            },
+        };
+        let (header_row, data) = if header {
+            (Some(&records[0]), &records[1..])
+        } else {
+            (None, &records[..])
+        };
+
+        let (mut rows, incomplete) = mapping.rows(header_row, data)?;
+        let total = rows.len();
+        rows.retain(|row| self.selected(&row.name));
+        let filtered = total - rows.len();
+        let duplicates = csv::dedup(&mut rows, self.duplicates);
+        rows.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if self.preview || interactive {
+            for row in &rows {
+                let mut line = row.name.clone();
+                for (field, value) in &row.fields {
+                    line.push_str(&format!("\t{field}={value}"));
                 }
+                if !row.tags.is_empty() {
+                    line.push_str(&format!("\ttags={}", row.tags.join(",")));
+                }
+                println!("{line}");
+            }
+            eprintln!(
+                "{} entries; same as --map '{}'{}",
+                rows.len(),
+                mapping.to_spec(),
+                if header { " --header" } else { "" }
+            );
+            if self.preview {
+                return Ok(ExitCode::SUCCESS);
             }
+            if !confirm(&format!("Import {} secret(s)?", rows.len()))? {
+                println!("Aborted");
+                return Ok(ExitCode::from(1));
+            }
+        }
+        if rows.is_empty() {
+            println!("No secrets found in file");
+            return Ok(ExitCode::SUCCESS);
+        }
 
-            if kn.get(&key).is_some() {
-                if self.overwrite {
-                    kn.update(&key, &value)?;
-                    imported += 1;
-                } else {
-                    skipped += 1;
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let mut written = Vec::new();
+        for row in &rows {
+            if self.overwrite || kn.get(&row.name)?.is_none() {
+                written.push(row);
+            }
+        }
+        let secrets = rows
+            .iter()
+            .map(|row| (row.name.as_str(), row.value.as_str()));
+        let summary = kn.import_entries(secrets, self.policy())?;
+        for row in written {
+            for (field, value) in &row.fields {
+                kn.set_field(&row.name, field, value)?;
+            }
+            for tag in &row.tags {
+                kn.add_tag(&row.name, tag)?;
+            }
+        }
+        kn.save()?;
+        self.report(summary, filtered);
+        if incomplete > 0 {
+            println!("Skipped {incomplete} row(s) without a name or value");
+        }
+        if duplicates > 0 {
+            match self.duplicates {
+                Duplicates::Number => {
+                    println!("Numbered {duplicates} row(s) repeating an earlier name")
                 }
-            } else {
-                kn.set(&key, &value)?;
-                imported += 1;
+                _ => println!("Skipped {duplicates} row(s) repeating another row's name"),
             }
         }
 
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn import_os_keychain(&self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let credentials = os_keychain::list()?;
+        let total = credentials.len();
+        let mut credentials: Vec<_> = credentials
+            .into_iter()
+            .filter(|c| !c.key().is_empty() && self.selected(&c.key()))
+            .collect();
+        credentials.sort_by_key(|c| c.key());
+        let filtered = total - credentials.len();
+
+        if self.list {
+            for credential in &credentials {
+                println!("{}", credential.key());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        if credentials.is_empty() {
+            println!("No credentials found in the OS credential store");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let mut secrets = Vec::with_capacity(credentials.len());
+        for credential in &credentials {
+            secrets.push((credential.key(), credential.secret()?));
+        }
+        let secrets = secrets
+            .iter()
+            .map(|(key, secret)| (key.as_str(), secret.as_str()));
+        let summary = kn.import_entries(secrets, self.policy())?;
+        kn.save()?;
+        self.report(summary, filtered);
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn import_browser(&self, browser: Browser, store: Option<PathBuf>) -> Result<ExitCode> {
+        let found = browser::read(browser, self.browser_profile.as_deref())?;
+        let total = found.logins.len();
+        let mut seen = HashSet::new();
+        // Logins come newest first; keep the first of several mapping to one key.
+        let mut logins: Vec<_> = found
+            .logins
+            .into_iter()
+            .filter(|l| !l.key().is_empty() && self.selected(&l.key()) && seen.insert(l.key()))
+            .collect();
+        logins.sort_by_key(|l| l.key());
+        let filtered = total - seen.len();
+        let duplicates = seen.len() - logins.len();
+        if found.undecryptable > 0 {
+            eprintln!(
+                "warning: {} saved password(s) could not be decrypted",
+                found.undecryptable
+            );
+        }
+
+        if self.list {
+            for login in &logins {
+                println!("{}", login.key());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        if logins.is_empty() {
+            println!("No saved passwords found in the browser profile");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let mut written = Vec::new();
+        for l in &logins {
+            if self.overwrite || kn.get(&l.key())?.is_none() {
+                written.push(l);
+            }
+        }
+        let secrets = logins.iter().map(|l| (l.key(), l.password.as_str()));
+        let summary = kn.import_entries(secrets, self.policy())?;
+        for login in written {
+            kn.set_field(&login.key(), "url", &login.url)?;
+            if !login.username.is_empty() {
+                kn.set_field(&login.key(), "user", &login.username)?;
+            }
+        }
+        kn.save()?;
+        self.report(summary, filtered);
+        if duplicates > 0 {
+            println!("Skipped {duplicates} older login(s) with the same host and user");
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn import_wifi(&self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let found = wifi::read(self.nm_dir.as_deref())?;
+        let total = found.networks.len();
+        let mut seen = HashSet::new();
+        // Several connections can share an SSID; keep the first by file name.
+        let mut networks: Vec<_> = found
+            .networks
+            .into_iter()
+            .filter(|n| self.selected(&n.key()) && seen.insert(n.key()))
+            .collect();
+        networks.sort_by_key(|n| n.key());
+        let filtered = total - seen.len();
+        let duplicates = seen.len() - networks.len();
+
+        if self.list {
+            for network in &networks {
+                println!("{}", network.key());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        if networks.is_empty() {
+            println!("No Wi-Fi passwords found in the NetworkManager connections");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let mut written = Vec::new();
+        for n in &networks {
+            if self.overwrite || kn.get(&n.key())?.is_none() {
+                written.push(n);
+            }
+        }
+        let secrets = networks.iter().map(|n| (n.key(), n.psk.as_str()));
+        let summary = kn.import_entries(secrets, self.policy())?;
+        for network in written {
+            kn.set_field(&network.key(), "ssid", &network.ssid)?;
+        }
         kn.save()?;
+        self.report(summary, filtered);
+        if duplicates > 0 {
+            println!("Skipped {duplicates} other connection(s) for the same SSID");
+        }
+        if found.without_psk > 0 {
+            println!(
+                "Skipped {} Wi-Fi connection(s) without a stored password",
+                found.without_psk
+            );
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Returns `true` if `key` passes the `--prefix` filter.
+    fn selected(&self, key: &str) -> bool {
+        self.prefix.as_ref().is_none_or(|p| key.starts_with(p))
+    }
 
-        println!("Imported {imported} secret(s)");
-        if skipped > 0 {
-            println!("Skipped {skipped} existing secret(s) (use --overwrite to replace)");
+    fn policy(&self) -> ImportPolicy {
+        if self.overwrite {
+            ImportPolicy::Overwrite
+        } else {
+            ImportPolicy::SkipExisting
+        }
+    }
+
+    fn report(&self, summary: ImportSummary, filtered: usize) {
+        println!(
+            "Imported {} secret(s)",
+            summary.created() + summary.updated()
+        );
+        if summary.skipped() > 0 {
+            if self.skip_existing {
+                println!("Skipped {} existing secret(s)", summary.skipped());
+            } else {
+                println!(
+                    "Skipped {} existing secret(s) (use --overwrite to replace)",
+                    summary.skipped()
+                );
+            }
         }
         if filtered > 0 {
             println!("Filtered {filtered} secret(s) by prefix");
         }
+    }
+}
 
-        Ok(ExitCode::SUCCESS)
+/// Flattens a parsed JSON/YAML/TOML document into `key -> value` pairs.
+///
+/// Nested maps are mapped to namespaced keys joined with `/`, so
+/// `{prod: {db: {password: x}}}` becomes `prod/db/password = x`. Numbers and
+/// booleans are stored as their textual form; arrays and nulls are rejected.
+fn flatten_document(doc: serde_json::Value) -> Result<HashMap<String, String>> {
+    let mut out = HashMap::new();
+    match doc {
+        serde_json::Value::Object(map) => flatten_into(None, map, &mut out)?,
+        _ => anyhow::bail!("expected a map of secrets at the top level"),
+    }
+    Ok(out)
+}
+
+fn flatten_into(
+    prefix: Option<&str>,
+    map: serde_json::Map<String, serde_json::Value>,
+    out: &mut HashMap<String, String>,
+) -> Result<()> {
+    use serde_json::Value;
+
+    for (name, value) in map {
+        let key = match prefix {
+            Some(p) => format!("{p}/{name}"),
+            None => name,
+        };
+
+        let value = match value {
+            Value::Object(inner) => {
+                flatten_into(Some(&key), inner, out)?;
+                continue;
+            }
+            Value::String(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Array(_) => anyhow::bail!("unsupported list value for '{key}'"),
+            Value::Null => anyhow::bail!("missing value for '{key}'"),
+        };
+        if out.contains_key(&key) {
+            anyhow::bail!("'{key}' is given twice (nested maps join their keys with '/')");
+        }
+        out.insert(key, value);
     }
+
+    Ok(())
 }
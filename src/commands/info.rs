@@ -2,9 +2,9 @@ use anyhow::Result;
 use clap::Args;
 use std::process::ExitCode;
 
-use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::{print_json, resolve_existing_storage};
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+use crate::commands::profile;
 use keynest::Keynest;
 
 #[derive(Args)]
@@ -37,14 +37,20 @@ impl Command for InfoCommand {
             return Ok(ExitCode::SUCCESS);
         }
 
-        let password = auth::read_password()?;
-        let kn = Keynest::open_with_storage(password, storage)?;
+        let kn = unlock_keystore(storage)?;
         let info = kn.info()?;
+        let profile = profile::active().map(|(name, _)| name);
 
         if self.json {
-            print_json(&info)?;
+            let mut json = serde_json::to_value(&info)?;
+            json["profile"] = serde_json::json!(profile);
+            print_json(&json)?;
         } else {
-            println!("{info}");
+            print!("{info}");
+            if let Some(name) = profile {
+                println!("  Profile:           {name}");
+            }
+            println!();
         }
 
         Ok(ExitCode::SUCCESS)
@@ -1,32 +1,146 @@
-use anyhow::Result;
-use clap::Args;
+use anyhow::{Result, bail};
+use clap::{Args, ValueEnum};
 use std::process::ExitCode;
 
 use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::{Argon2Args, resolve_storage};
-use keynest::Keynest;
+use crate::commands::common::{
+    CompressionMethod, KdfArgs, StrengthArgs, read_or_create_keyfile, resolve_storage,
+};
+use crate::commands::profile;
+use keynest::format::{Padding, PayloadEncoding};
+use keynest::{Algorithm, Keynest};
 
 #[derive(Args)]
 #[command(after_help = "\
 Examples:
   keynest init                                      Initialize a new keystore with default settings
   keynest init --argon-mem 131072                 Initialize with higher memory cost (128 MiB)
-  keynest init --argon-time 5 --argon-mem 65536   Initialize with custom Argon2 parameters")]
+  keynest init --argon-time 5 --argon-mem 65536   Initialize with custom Argon2 parameters
+  keynest init --kdf scrypt --scrypt-n 131072    Derive the key with scrypt instead of Argon2id
+  keynest init --cipher aes256-gcm                Encrypt with AES-256-GCM instead of XChaCha20-Poly1305
+  keynest init --compress                         Compress records with zstd before encryption
+  keynest init --padding bucket                   Pad records so their size only reveals a size class
+  keynest init --dpapi                            Bind the keystore to this Windows account
+  keynest init --keyfile /media/usb/vault.key     Also require a keyfile (created if missing)
+  keynest --profile vault init                    Create the store of profile 'vault' with its defaults
+
+A profile of the config file ($KEYNEST_CONFIG, or config.toml in the keynest config
+directory) can set the KDF parameters, cipher, padding, backup count and DPAPI binding
+of new stores; --kdf, --argon-*, --scrypt-* and --cipher override the profile's settings.
+
+The master password must score at least 3 of 4 for how hard it is to guess (see
+`keynest audit-strength`); --min-password-score, $KEYNEST_MIN_PASSWORD_SCORE or the
+profile's min-password-score change the bar, and --allow-weak only warns. The same
+applies to `rekey`, `keyslot add` and `duress set`.
+
+--compress pays off for large, repetitive values such as PEM certificates and keys:
+records are compressed with zstd (or DEFLATE with --compress=deflate) before they are
+encrypted. `keynest convert --compress` turns it on for an existing store.
+
+--padding bucket pads every record to the next power of two (at least 512 bytes)
+before it is encrypted, so the file size does not reveal how many or how large the
+secrets are, or whether a save added one, beyond the size class. `none` overrides a
+profile that turns padding on; `keynest convert --pad/--no-pad` changes it later.
+
+With --keyfile, the store can only be opened with the password and the keyfile: pass
+--keyfile (or set $KEYNEST_KEYFILE) on every later command. Any file can serve as the
+keyfile as long as it never changes; a missing one is created with random contents.")]
 pub struct InitCommand {
     #[command(flatten)]
-    pub argon2: Argon2Args,
+    pub kdf: KdfArgs,
+
+    #[command(flatten)]
+    pub strength: StrengthArgs,
+
+    /// AEAD cipher encrypting the store: xchacha20 (XChaCha20-Poly1305, the default)
+    /// or aes256-gcm
+    #[arg(long, value_name = "CIPHER", value_parser = parse_cipher)]
+    pub cipher: Option<Algorithm>,
+
+    /// Also bind the key to the current Windows user account with DPAPI: a copy of the
+    /// file on another machine or account cannot be opened, even with the password
+    #[arg(long)]
+    pub dpapi: bool,
+
+    /// Compress records before encrypting them, with zstd (the default) or deflate
+    #[arg(
+        long,
+        value_enum,
+        value_name = "METHOD",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "zstd"
+    )]
+    pub compress: Option<CompressionMethod>,
+
+    /// Pad records before encrypting them: none, or bucket (to the next power of two)
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub padding: Option<PaddingMode>,
+}
+
+/// Padding of records, as given to `--padding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PaddingMode {
+    /// Records are as long as their contents
+    None,
+    /// Records are padded to the next power of two, at least 512 bytes
+    Bucket,
 }
 
 impl Command for InitCommand {
     fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
-        let kdf = self.argon2.to_kdf_params()?;
+        let options = profile::init_options()?;
+        let kdf = self.kdf.apply_to(*options.kdf())?;
+        let dpapi = self.dpapi || options.dpapi();
+        if dpapi && !cfg!(windows) {
+            bail!("DPAPI binding is only available on Windows");
+        }
         let storage = resolve_storage(store)?;
+        let keyfile = auth::keyfile_path()
+            .map(|path| read_or_create_keyfile(&path))
+            .transpose()?;
+        let mut options = options
+            .with_kdf(kdf)
+            .with_dpapi(dpapi)
+            .with_keyfile(keyfile);
+        if let Some(cipher) = self.cipher {
+            options = options.with_algorithm(cipher);
+        }
+        if let Some(method) = self.compress {
+            let encoding = options.encoding();
+            options = options.with_encoding(PayloadEncoding::new(
+                encoding.serialization(),
+                method.into(),
+                encoding.padding(),
+            ));
+        }
+        if let Some(mode) = self.padding {
+            let encoding = options.encoding();
+            options = options.with_encoding(PayloadEncoding::new(
+                encoding.serialization(),
+                encoding.compression(),
+                match mode {
+                    PaddingMode::None => Padding::None,
+                    PaddingMode::Bucket => Padding::PowerOfTwo,
+                },
+            ));
+        }
         let password = auth::read_password()?;
+        self.strength.apply();
+        auth::check_new_password(&password)?;
 
-        Keynest::init_with_storage_and_kdf(password, storage, kdf)?;
-        println!("keystore initialized");
+        Keynest::init_with_options(password, storage, options)?;
+        match profile::active() {
+            Some((name, _)) => println!("keystore initialized (profile '{name}')"),
+            None => println!("keystore initialized"),
+        }
 
         Ok(ExitCode::SUCCESS)
     }
 }
+
+fn parse_cipher(s: &str) -> Result<Algorithm, String> {
+    Algorithm::from_name(s)
+        .ok_or_else(|| format!("unknown cipher '{s}' (expected xchacha20 or aes256-gcm)"))
+}
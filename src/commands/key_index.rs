@@ -0,0 +1,110 @@
+use anyhow::{Result, bail};
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+use keynest::key_index::{KeyIndex, index_path};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest key-index enable                       Maintain <store>.keyindex on every save
+  keynest key-index check github/token           Test for a key without the master password
+  keynest key-index verify                       Check the index against the keystore
+  keynest key-index disable                      Stop maintaining the index and delete it
+
+The key index is a Bloom filter of salted key-name hashes. It never lists names, but
+anyone who can read it can test guessed names: enabling it reveals which keys exist.
+'check' answers 'absent' (definitely not stored) or 'maybe' (stored, or a rare false
+positive) and exits with status 1 if any key is absent.")]
+pub struct KeyIndexCommand {
+    #[command(subcommand)]
+    pub action: KeyIndexAction,
+}
+
+#[derive(Subcommand)]
+pub enum KeyIndexAction {
+    /// Start maintaining the key index (leaks which keys exist)
+    Enable,
+    /// Stop maintaining the key index and delete it
+    Disable,
+    /// Rewrite the key index from the keystore
+    Rebuild,
+    /// Check that the key index covers every key
+    Verify,
+    /// Test keys against the index; does not need the master password
+    Check {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
+
+impl Command for KeyIndexCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+
+        if let KeyIndexAction::Check { keys } = &self.action {
+            let Some(index) = KeyIndex::load(&storage)? else {
+                bail!(
+                    "no key index at {} (enable it with: keynest key-index enable)",
+                    index_path(&storage).display()
+                );
+            };
+            let mut absent = false;
+            for key in keys {
+                if index.might_contain(key) {
+                    println!("{key}  maybe");
+                } else {
+                    println!("{key}  absent");
+                    absent = true;
+                }
+            }
+            return Ok(if absent {
+                ExitCode::from(1)
+            } else {
+                ExitCode::SUCCESS
+            });
+        }
+
+        let mut kn = unlock_keystore(storage.clone())?;
+
+        match self.action {
+            KeyIndexAction::Enable => {
+                kn.set_key_index(true)?;
+                kn.save()?;
+                eprintln!(
+                    "warning: the key index reveals which keys exist to anyone who can read it"
+                );
+                println!("key index written to {}", index_path(&storage).display());
+            }
+            KeyIndexAction::Disable => {
+                kn.set_key_index(false)?;
+                kn.save()?;
+                println!("key index disabled");
+            }
+            KeyIndexAction::Rebuild => {
+                if !kn.key_index_enabled()? {
+                    bail!("the key index is disabled (enable it with: keynest key-index enable)");
+                }
+                kn.rebuild_key_index()?;
+                println!("key index rebuilt");
+            }
+            KeyIndexAction::Verify => {
+                let missing = kn.verify_key_index()?;
+                if !missing.is_empty() {
+                    for key in &missing {
+                        eprintln!("missing from key index: {key}");
+                    }
+                    eprintln!("hint: run 'keynest key-index rebuild'");
+                    return Ok(ExitCode::from(1));
+                }
+                println!("key index is up to date");
+            }
+            KeyIndexAction::Check { .. } => unreachable!("handled without the password"),
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
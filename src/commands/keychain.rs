@@ -0,0 +1,104 @@
+//! `keynest keychain`: keeps the master password in the OS credential store, so
+//! `--use-keychain` unlocks without asking for it.
+//!
+//! The password is stored as the credential of account [`ACCOUNT`] at service
+//! `keynest:<canonical store path>` (see [`os_keychain`] for the credential store of
+//! each platform), so every store has its own entry.
+
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{open_keystore, resolve_existing_storage};
+use crate::commands::os_keychain;
+
+/// Account of the credential holding the master password.
+pub const ACCOUNT: &str = "master-password";
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest keychain enable                        Store the master password in the OS keychain
+  keynest get db/password --use-keychain         Unlock with it instead of asking
+  keynest keychain disable                       Remove it from the OS keychain again
+
+The password is kept in the macOS keychain, the Windows Credential Manager, or the Secret
+Service (GNOME Keyring, KWallet) through secret-tool. Anyone who can read your OS
+credential store can then open the keystore: it is as safe as your login session.
+`keynest rekey --use-keychain` updates the stored password.")]
+pub struct KeychainCommand {
+    #[command(subcommand)]
+    pub action: KeychainAction,
+}
+
+#[derive(Subcommand)]
+pub enum KeychainAction {
+    /// Store the master password in the OS keychain (after checking it opens the store)
+    Enable,
+    /// Remove the master password from the OS keychain
+    Disable,
+}
+
+impl Command for KeychainCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        match self.action {
+            KeychainAction::Enable => {
+                // Always asked for: the point is to put a password in the keychain.
+                let password = auth::read_password()?;
+                open_keystore(password.clone(), storage.clone())?;
+                store_password(storage.path(), &password)?;
+                println!(
+                    "stored the master password of {} in the OS keychain; unlock with --use-keychain",
+                    storage.path().display()
+                );
+            }
+            KeychainAction::Disable => {
+                let service = service(storage.path())?;
+                if !os_keychain::delete(&service, ACCOUNT)? {
+                    eprintln!(
+                        "the OS keychain holds no password for {}",
+                        storage.path().display()
+                    );
+                    return Ok(ExitCode::from(1));
+                }
+                println!(
+                    "removed the master password of {} from the OS keychain",
+                    storage.path().display()
+                );
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Returns the master password stored for the store at `path`, if any.
+///
+/// # Errors
+///
+/// Returns an error if the path cannot be resolved or the credential store cannot be
+/// read.
+pub fn stored_password(path: &Path) -> Result<Option<Zeroizing<String>>> {
+    os_keychain::lookup(&service(path)?, ACCOUNT)
+}
+
+/// Stores `password` as the master password of the store at `path`, replacing the
+/// stored one.
+///
+/// # Errors
+///
+/// Returns an error if the path cannot be resolved or the credential store refuses the
+/// write.
+pub fn store_password(path: &Path, password: &str) -> Result<()> {
+    os_keychain::store(&service(path)?, ACCOUNT, password)
+}
+
+fn service(path: &Path) -> Result<String> {
+    let path = std::fs::canonicalize(path)
+        .with_context(|| format!("cannot resolve {}", path.display()))?;
+    Ok(format!("keynest:{}", path.display()))
+}
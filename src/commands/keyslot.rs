@@ -0,0 +1,134 @@
+//! `keynest keyslot`: several passwords opening one store.
+//!
+//! The first `keyslot add` moves the store to a random data key wrapped in a `default`
+//! keyslot for the current password; see [`keynest::format::Keyslot`].
+
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{
+    KdfArgs, StrengthArgs, confirm, print_json, resolve_existing_storage, unlock_keystore,
+};
+use keynest::KdfParams;
+use keynest::format::{self, DEFAULT_KEYSLOT};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest keyslot add alice                      Let a second password open the store
+  keynest keyslot list                           Show the keyslots (no password needed)
+  keynest keyslot remove alice                   Stop the password of 'alice' from opening it
+
+A store without keyslots gets a random data key on the first `keyslot add`: the current
+password is kept in a keyslot named 'default' and the new one is added next to it. Any
+keyslot's password opens the store, and `keynest rekey` then only changes the password of
+the keyslot you opened it with. Removing a keyslot does not change the data key, so
+re-create the store to lock out someone who kept a copy of it.")]
+pub struct KeyslotCommand {
+    #[command(subcommand)]
+    pub action: KeyslotAction,
+}
+
+#[derive(Subcommand)]
+pub enum KeyslotAction {
+    /// Add a keyslot, asking for the current password and then for its password
+    Add {
+        /// Name of the keyslot, e.g. the person whose password it holds
+        name: String,
+
+        #[command(flatten)]
+        kdf: KdfArgs,
+
+        #[command(flatten)]
+        strength: StrengthArgs,
+    },
+    /// List the keyslots and their KDF parameters
+    List {
+        /// Output as JSON
+        #[arg(long, short = 'j')]
+        json: bool,
+    },
+    /// Remove a keyslot
+    Remove {
+        name: String,
+
+        /// Do not ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct Listed<'a> {
+    name: &'a str,
+    kdf: &'a KdfParams,
+}
+
+impl Command for KeyslotCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        match self.action {
+            KeyslotAction::Add {
+                name,
+                kdf,
+                strength,
+            } => {
+                strength.apply();
+                let kdf = kdf.to_kdf_params()?;
+                let mut kn = unlock_keystore(storage)?;
+                let converting = kn.keyslots().is_empty();
+                let password = auth::read_new_password_with_confirmation()?;
+                kn.add_keyslot(&name, password, kdf)?;
+                if converting {
+                    println!(
+                        "moved the current password to keyslot '{DEFAULT_KEYSLOT}' and added keyslot '{name}'"
+                    );
+                } else {
+                    println!("added keyslot '{name}'");
+                }
+            }
+            KeyslotAction::List { json } => {
+                let file = format::parse(&storage.load()?)?;
+                let listed: Vec<_> = file
+                    .keyslots()
+                    .iter()
+                    .map(|slot| Listed {
+                        name: slot.name(),
+                        kdf: slot.kdf(),
+                    })
+                    .collect();
+                if json {
+                    print_json(&listed)?;
+                } else if listed.is_empty() {
+                    println!("No keyslots; the store has a single password");
+                } else {
+                    let width = listed.iter().map(|s| s.name.len()).max().unwrap_or(0);
+                    for slot in &listed {
+                        println!("{:width$}  {}", slot.name, slot.kdf);
+                    }
+                }
+            }
+            KeyslotAction::Remove { name, yes } => {
+                let mut kn = unlock_keystore(storage)?;
+                if !kn.keyslots().iter().any(|slot| slot.name() == name) {
+                    eprintln!("no keyslot named '{name}'");
+                    return Ok(ExitCode::from(1));
+                }
+                let question =
+                    format!("Remove keyslot '{name}'? Its password will no longer open the store.");
+                if !yes && !confirm(&question)? {
+                    println!("Aborted");
+                    return Ok(ExitCode::from(1));
+                }
+                kn.remove_keyslot(&name)?;
+                println!("removed keyslot '{name}'");
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
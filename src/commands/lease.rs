@@ -0,0 +1,137 @@
+use anyhow::Result;
+use chrono::{SecondsFormat, TimeDelta};
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    print_json, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
+use keynest::Lease;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest lease db/admin                        Take db/admin for an hour, warning if others have it
+  keynest lease db/admin --ttl 30m --exclusive  Keep others from leasing it for 30 minutes
+  keynest lease db/admin --release              Give it back
+  keynest lease db/admin --release --force      Break the leases of everyone
+  keynest lease                                 List the active leases
+
+Leases are advisory: they record who is using a shared credential and until when, and
+never keep anyone from reading it. Leasing an entry that others lease prints a warning;
+while someone holds an exclusive lease, other lease attempts fail.
+Taking a lease again renews it. The holder defaults to $USER; set --holder or
+KEYNEST_LEASE_HOLDER to tell people apart who share an account.")]
+pub struct LeaseCommand {
+    /// Entry to lease; lists the active leases if omitted
+    pub key: Option<String>,
+
+    /// How long the lease lasts: a number followed by m, h or d
+    #[arg(long, value_name = "DURATION", default_value = "1h", value_parser = parse_ttl)]
+    pub ttl: TimeDelta,
+
+    /// Make other lease attempts fail until this lease is released or expires
+    #[arg(long, requires = "key", conflicts_with = "release")]
+    pub exclusive: bool,
+
+    /// Release the lease instead of taking it
+    #[arg(long, requires = "key")]
+    pub release: bool,
+
+    /// With --release, release the leases of all holders
+    #[arg(long, requires = "release")]
+    pub force: bool,
+
+    /// Name recorded as the holder of the lease
+    #[arg(long, env = "KEYNEST_LEASE_HOLDER")]
+    pub holder: Option<String>,
+
+    /// Output as JSON (when listing)
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+impl Command for LeaseCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let Some(key) = &self.key else {
+            let kn = unlock_indexed(storage)?;
+            list(&kn.leases()?, self.json)?;
+            return Ok(ExitCode::SUCCESS);
+        };
+        let holder = self.holder.clone().unwrap_or_else(default_holder);
+
+        let mut kn = unlock_keystore(storage)?;
+        if self.release {
+            let released = kn.release_lease(key, (!self.force).then_some(holder.as_str()))?;
+            if released == 0 {
+                eprintln!("'{key}' is not leased by {holder}");
+                return Ok(ExitCode::from(1));
+            }
+            kn.save()?;
+            println!("released {released} lease(s) of '{key}'");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let others = kn.lease(key, &holder, self.ttl, self.exclusive)?;
+        kn.save()?;
+        for lease in &others {
+            eprintln!("warning: '{key}' is also leased by {lease}");
+        }
+        let held = kn.leases()?.remove(key).unwrap_or_default();
+        if let Some(lease) = held.iter().find(|lease| lease.holder() == holder) {
+            println!("leased '{key}' to {lease}");
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn list(leases: &BTreeMap<String, Vec<Lease>>, json: bool) -> Result<()> {
+    if json {
+        let leases: Vec<_> = leases
+            .iter()
+            .flat_map(|(key, held)| held.iter().map(move |lease| (key, lease)))
+            .map(|(key, lease)| {
+                serde_json::json!({
+                    "key": key,
+                    "holder": lease.holder(),
+                    "acquired": lease.acquired().map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                    "expires": lease.expires().map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                    "exclusive": lease.is_exclusive(),
+                })
+            })
+            .collect();
+        return print_json(&leases);
+    }
+    for (key, held) in leases {
+        for lease in held {
+            println!("{key}\t{lease}");
+        }
+    }
+    Ok(())
+}
+
+/// Returns the login name of the current user.
+fn default_holder() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Parses `--ttl`: a positive number followed by `m`, `h` or `d`.
+fn parse_ttl(s: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("invalid duration '{s}' (use e.g. 30m, 1h or 2d)");
+    let (count, unit) = s.split_at(s.len().saturating_sub(1));
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let ttl = match unit {
+        "m" => TimeDelta::try_minutes(count),
+        "h" => TimeDelta::try_hours(count),
+        "d" => TimeDelta::try_days(count),
+        _ => None,
+    };
+    ttl.filter(|ttl| *ttl > TimeDelta::zero())
+        .ok_or_else(invalid)
+}
@@ -1,11 +1,13 @@
 use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
 use clap::Args;
+use std::collections::BTreeSet;
 use std::process::ExitCode;
 
-use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::{print_json, resolve_existing_storage};
-use keynest::Keynest;
+use crate::commands::common::{
+    print_json, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
 
 #[derive(Args)]
 #[command(
@@ -15,9 +17,20 @@ Examples:
   keynest list                                   List all secret keys
   keynest list --all                            List all secrets with timestamps
   keynest list --json                           List all keys as JSON array
-  keynest list --all --json                    List all secrets with timestamps as JSON"
+  keynest list --all --json                    List all secrets with timestamps as JSON
+  keynest list prod/                            List the keys in the prod namespace
+  keynest list --tree                           Show keys as a tree of namespaces
+  keynest list --tag billing --tag prod         List the keys tagged with both billing and prod
+  keynest list --expired                        List the secrets whose expiry has passed
+
+Keys are paths of '/'-separated names, e.g. prod/db/password. Entries pinned with
+'keynest pin' are listed first, except in the tree. Secrets whose expiry has passed
+are marked '(expired)', except in the JSON output."
 )]
 pub struct ListCommand {
+    /// Only list keys starting with this prefix (e.g. `prod/`)
+    pub prefix: Option<String>,
+
     /// Show keys with their last-updated timestamps
     #[arg(required = false, short, long, default_value_t = false)]
     pub all: bool,
@@ -25,34 +38,77 @@ pub struct ListCommand {
     /// Output as JSON
     #[arg(long, short = 'j')]
     pub json: bool,
+
+    /// Show keys as a tree of namespaces
+    #[arg(long, short = 't', conflicts_with_all = ["all", "json"])]
+    pub tree: bool,
+
+    /// Only list entries with this tag; may be repeated to require several tags
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
+    /// Only list secrets whose expiry has passed
+    #[arg(long)]
+    pub expired: bool,
 }
 
 impl Command for ListCommand {
     fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
         let storage = resolve_existing_storage(store)?;
-        let password = auth::read_password()?;
-        let kn = Keynest::open_with_storage(password, storage)?;
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+
+        if !self.all && self.tags.is_empty() && !self.expired {
+            // Keys only: the index is enough, no section needs to be decrypted.
+            let mut kn = unlock_indexed(storage)?;
+            let expired: BTreeSet<String> = kn.expired_keys()?.into_iter().collect();
+            let mut keys: Vec<&str> = kn.list_prefix(prefix).iter().map(|s| s.as_str()).collect();
+            self.pinned_first(&mut keys, |key| key, &kn.pinned()?);
+            self.print_keys(&keys, prefix, |key| expired.contains(key))?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let kn = unlock_keystore(storage)?;
+        let now = Utc::now();
+        let pinned = kn.pinned()?;
+        let mut entries: Vec<_> = kn
+            .list_all()?
+            .into_iter()
+            .filter(|e| {
+                e.key().starts_with(prefix)
+                    && self.tags.iter().all(|t| e.has_tag(t))
+                    && (!self.expired || e.is_expired_at(now))
+            })
+            .collect();
+        self.pinned_first(&mut entries, |e| e.key(), &pinned);
+
+        if !self.all {
+            let keys: Vec<&str> = entries.iter().map(|e| e.key()).collect();
+            let expired: BTreeSet<&str> = entries
+                .iter()
+                .filter(|e| !self.expired && e.is_expired_at(now))
+                .map(|e| e.key())
+                .collect();
+            self.print_keys(&keys, prefix, |key| expired.contains(key))?;
+            return Ok(ExitCode::SUCCESS);
+        }
 
         if self.json {
-            if self.all {
-                let entries: Vec<_> = kn
-                    .list_all()
-                    .iter()
-                    .map(|e| {
-                        serde_json::json!({
-                            "key": e.key(),
-                            "updated": e.updated()
-                        })
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|e| {
+                    serde_json::json!({
+                        "key": e.key(),
+                        "kind": e.kind().to_string(),
+                        "updated": e.updated(),
+                        "tags": e.tags(),
+                        "expires": e.expires().map(format_time),
+                        "expired": e.is_expired_at(now),
+                        "pinned": pinned.contains(e.key())
                     })
-                    .collect();
-                print_json(&entries)?;
-            } else {
-                let keys: Vec<&str> = kn.list().iter().map(|s| s.as_str()).collect();
-                print_json(&keys)?;
-            }
-        } else if self.all {
-            let entries = kn.list_all();
-
+                })
+                .collect();
+            print_json(&entries)?;
+        } else {
             if entries.is_empty() {
                 println!("No secrets stored.");
                 return Ok(ExitCode::SUCCESS);
@@ -72,18 +128,124 @@ impl Command for ListCommand {
                 .max()
                 .unwrap();
 
-            println!("{:<key_width$}  {:<updated_width$}", "Key", "Updated");
-            println!("{:-<key_width$}  {:-<updated_width$}", "", "");
+            // The expiry and tags columns are only shown once they are in use.
+            let show_expires = entries.iter().any(|e| e.expires().is_some());
+            let show_tags = entries.iter().any(|e| !e.tags().is_empty());
+            let expires: Vec<String> = entries
+                .iter()
+                .map(|e| match e.expires() {
+                    Some(t) if t <= now => {
+                        format!("{} (expired)", format_time(t))
+                    }
+                    Some(t) => format_time(t),
+                    None => String::new(),
+                })
+                .collect();
+            let expires_width = expires
+                .iter()
+                .map(String::len)
+                .chain(std::iter::once("Expires".len()))
+                .max()
+                .unwrap();
 
-            for e in entries {
-                println!("{:<key_width$}  {:<updated_width$}", e.key(), e.updated());
+            let row = |key: &str, updated: &str, expires: &str, tags: &str| {
+                let mut line = format!("{key:<key_width$}  {updated:<updated_width$}");
+                if show_expires {
+                    line.push_str(&format!("  {expires:<expires_width$}"));
+                }
+                if show_tags {
+                    line.push_str(&format!("  {tags}"));
+                }
+                println!("{}", line.trim_end());
+            };
+            row("Key", "Updated", "Expires", "Tags");
+            row(
+                &"-".repeat(key_width),
+                &"-".repeat(updated_width),
+                &"-".repeat(expires_width),
+                "----",
+            );
+            for (e, expires) in entries.iter().zip(&expires) {
+                row(e.key(), e.updated(), expires, &e.tags().join(", "));
             }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl ListCommand {
+    /// Moves the pinned items first, keeping the order within both groups. The tree
+    /// needs the keys sorted, so it ignores pins.
+    fn pinned_first<T>(
+        &self,
+        items: &mut [T],
+        key: impl Fn(&T) -> &str,
+        pinned: &BTreeSet<String>,
+    ) {
+        if !self.tree && !pinned.is_empty() {
+            items.sort_by_key(|item| !pinned.contains(key(item)));
+        }
+    }
+
+    /// Prints `keys`, marking those `is_expired` accepts in the text output; the JSON
+    /// output stays a list of keys.
+    fn print_keys(
+        &self,
+        keys: &[&str],
+        prefix: &str,
+        is_expired: impl Fn(&str) -> bool,
+    ) -> Result<()> {
+        if self.json {
+            print_json(&keys)?;
+        } else if self.tree {
+            print_tree(keys, prefix, is_expired);
         } else {
-            for secret_key in kn.list() {
-                println!("{secret_key}");
+            for key in keys {
+                if is_expired(key) {
+                    println!("{key}  (expired)");
+                } else {
+                    println!("{key}");
+                }
             }
         }
+        Ok(())
+    }
+}
 
-        Ok(ExitCode::SUCCESS)
+/// Prints sorted `keys` as a tree, one namespace or entry per line, indented by depth.
+///
+/// Names are shown relative to the namespace part of `prefix` (up to its last `/`),
+/// which is printed as the root. Entries `is_expired` accepts are marked.
+fn print_tree(keys: &[&str], prefix: &str, is_expired: impl Fn(&str) -> bool) {
+    let base = prefix.rfind('/').map_or("", |i| &prefix[..=i]);
+    let root = usize::from(!base.is_empty());
+    if !base.is_empty() && !keys.is_empty() {
+        println!("{base}");
     }
+
+    // Namespaces of the previous key; sorted keys of a namespace are contiguous.
+    let mut open: Vec<&str> = Vec::new();
+    for key in keys {
+        let mut names: Vec<&str> = key[base.len()..].split('/').collect();
+        let leaf = names.pop().unwrap_or_default();
+
+        let common = open.iter().zip(&names).take_while(|(a, b)| a == b).count();
+        open.truncate(common);
+        for name in &names[common..] {
+            println!("{:indent$}{name}/", "", indent = 2 * (root + open.len()));
+            open.push(name);
+        }
+        let mark = if is_expired(key) { "  (expired)" } else { "" };
+        println!(
+            "{:indent$}{leaf}{mark}",
+            "",
+            indent = 2 * (root + open.len())
+        );
+    }
+}
+
+/// Formats `time` like the `updated` timestamps, e.g. `2026-01-31T12:00:00Z`.
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
 }
@@ -0,0 +1,67 @@
+use anyhow::{Result, bail};
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{check_access, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest lookup service wifi account home       Print the secret stored as wifi/home
+  keynest lookup host github.com user alice      Find a login by the host of its url field
+  keynest lookup --all service mail              List the keys of all matching entries
+
+Attributes follow libsecret (secret-tool lookup/search), so scripts written for the
+Secret Service find keynest entries. A field of the same name always matches;
+otherwise 'service' is the namespace of the key, 'account' (or 'user', 'username')
+the user or username field or the last name of the key, and 'host' (or 'server')
+the host of the url field.
+Prints the secret of the first match in key order; exits with status 1 if nothing
+matches.")]
+pub struct LookupCommand {
+    /// Pairs of attribute name and value
+    #[arg(value_name = "ATTRIBUTE VALUE", required = true, num_args = 2..)]
+    pub attributes: Vec<String>,
+
+    /// List the keys of all matching entries instead of printing a secret
+    #[arg(long)]
+    pub all: bool,
+}
+
+impl Command for LookupCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        if self.attributes.len() % 2 != 0 {
+            bail!("attributes must be given as ATTRIBUTE VALUE pairs");
+        }
+        let attributes: Vec<(&str, &str)> = self
+            .attributes
+            .chunks_exact(2)
+            .map(|pair| (pair[0].as_str(), pair[1].as_str()))
+            .collect();
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+        let keys: Vec<String> = kn
+            .lookup(&attributes)?
+            .map(|e| e.key().to_string())
+            .collect();
+
+        if self.all {
+            for key in &keys {
+                println!("{key}");
+            }
+        } else if let Some(key) = keys.first() {
+            check_access(key, kn.effective_policy(key)?, true)?;
+            println!("{}", kn.resolve(key)?.unwrap_or_default());
+            kn.record_get(key)?;
+            kn.save_usage()?;
+        }
+        Ok(if keys.is_empty() {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        })
+    }
+}
@@ -0,0 +1,159 @@
+//! Minimal markdown rendering for notes in the terminal.
+//!
+//! Covers what notes typically use: headings, lists, quotes, code, emphasis, links and
+//! rules. Styling uses ANSI escapes only when `color` is set; otherwise the markup is
+//! replaced by plain-text equivalents so the output stays readable when piped.
+
+const BOLD: &str = "\x1b[1m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const DIM: &str = "\x1b[2m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Width of rendered horizontal rules.
+const RULE_WIDTH: usize = 40;
+
+/// Renders `text` for display, one output line per input line (fence lines excepted).
+pub fn render(text: &str, color: bool) -> String {
+    let style = |code: &str, s: &str| {
+        if color {
+            format!("{code}{s}{RESET}")
+        } else {
+            s.to_string()
+        }
+    };
+
+    let mut out = String::with_capacity(text.len());
+    let mut in_code_block = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            out.push_str(&format!("    {}\n", style(CYAN, line)));
+            continue;
+        }
+
+        if let Some((level, heading)) = heading(trimmed) {
+            let heading = render_inline(heading, false);
+            out.push_str(&style(&format!("{BOLD}{UNDERLINE}"), &heading));
+            out.push('\n');
+            if !color && level <= 2 {
+                let underline = if level == 1 { "=" } else { "-" };
+                out.push_str(&underline.repeat(heading.chars().count()));
+                out.push('\n');
+            }
+        } else if is_rule(trimmed) {
+            out.push_str(&style(DIM, &"─".repeat(RULE_WIDTH)));
+            out.push('\n');
+        } else if let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|marker| trimmed.strip_prefix(marker))
+        {
+            let item = match item.strip_prefix("[ ] ") {
+                Some(rest) => format!("☐ {rest}"),
+                None => match item
+                    .strip_prefix("[x] ")
+                    .or_else(|| item.strip_prefix("[X] "))
+                {
+                    Some(rest) => format!("☑ {rest}"),
+                    None => item.to_string(),
+                },
+            };
+            out.push_str(&format!("{indent}• {}\n", render_inline(&item, color)));
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            let quote = render_inline(quote.trim_start(), color);
+            out.push_str(&format!("{indent}{} {quote}\n", style(DIM, "│")));
+        } else {
+            out.push_str(&format!("{indent}{}\n", render_inline(trimmed, color)));
+        }
+    }
+    out
+}
+
+/// Returns the level and text of an ATX heading (`## Text`).
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.bytes().take_while(|&b| b == b'#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    (rest.is_empty() || rest.starts_with(' '))
+        .then(|| (level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// `---`, `***` or `___` (three or more, spaces allowed).
+fn is_rule(line: &str) -> bool {
+    let chars: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    chars.len() >= 3 && matches!(chars[0], '-' | '*' | '_') && chars.iter().all(|&c| c == chars[0])
+}
+
+/// Renders `**bold**`, `*italic*`, `` `code` `` and `[text](url)` spans.
+fn render_inline(text: &str, color: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(['*', '`', '[']) {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let span = if let Some(inner) = rest.strip_prefix("**") {
+            inner
+                .find("**")
+                .map(|end| (BOLD, &inner[..end], end + 4, None))
+        } else if let Some(inner) = rest.strip_prefix('*') {
+            inner
+                .find('*')
+                .filter(|&end| end > 0)
+                .map(|end| (ITALIC, &inner[..end], end + 2, None))
+        } else if let Some(inner) = rest.strip_prefix('`') {
+            inner
+                .find('`')
+                .map(|end| (CYAN, &inner[..end], end + 2, None))
+        } else {
+            link(rest).map(|(label, url, len)| (UNDERLINE, label, len, Some(url)))
+        };
+
+        match span {
+            Some((code, inner, len, url)) => {
+                if color {
+                    out.push_str(&format!("{code}{inner}{RESET}"));
+                } else {
+                    out.push_str(inner);
+                }
+                if let Some(url) = url {
+                    out.push_str(&format!(" <{url}>"));
+                }
+                rest = &rest[len..];
+            }
+            None => {
+                // Not a span: keep the character as-is.
+                let ch = rest.chars().next().unwrap_or_default();
+                out.push(ch);
+                rest = &rest[ch.len_utf8()..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parses `[label](url)` at the start of `text`, returning label, url and length.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let close = text.find("](")?;
+    let label = &text[1..close];
+    if label.contains(['[', ']']) {
+        return None;
+    }
+    let url_start = close + 2;
+    let url_len = text[url_start..].find(')')?;
+    Some((
+        label,
+        &text[url_start..url_start + url_len],
+        url_start + url_len + 1,
+    ))
+}
@@ -0,0 +1,132 @@
+use anyhow::{Result, bail};
+use clap::{Args, ValueEnum};
+use keynest::{Conflict, MergePolicy, MergeSummary, Side, Storage};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+
+/// Which side of a conflict to keep.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Prefer {
+    /// The entry of this store
+    Ours,
+    /// The entry of the other store
+    Theirs,
+    /// The entry updated last
+    Newer,
+}
+
+impl From<Prefer> for MergePolicy {
+    fn from(prefer: Prefer) -> Self {
+        match prefer {
+            Prefer::Ours => MergePolicy::Ours,
+            Prefer::Theirs => MergePolicy::Theirs,
+            Prefer::Newer => MergePolicy::Newer,
+        }
+    }
+}
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest merge ~/sync/laptop.db                 Merge the copy of the other laptop, newer entries win
+  keynest merge ~/sync/laptop.db --prefer ours   Keep this store's entry on every conflict
+  keynest merge ~/sync/laptop.db --interactive   Ask for each conflict
+
+Entries only the other store holds are added; entries both hold with a different value,
+kind, fields, tags, expiry or policy are conflicts. Values are never shown: --interactive
+lists what differs and when each side was updated. There is no history, so an entry
+removed from one copy comes back from the other; remove it again after merging. Settings
+are not merged. The other store is unlocked like this one (on a terminal, its password
+is asked for) and is not changed; copy this store over it to bring both in line.")]
+pub struct MergeCommand {
+    /// The other store
+    pub other: PathBuf,
+
+    /// Which side of a conflict to keep
+    #[arg(
+        long,
+        value_enum,
+        default_value = "newer",
+        conflicts_with = "interactive"
+    )]
+    pub prefer: Prefer,
+
+    /// Ask which side to keep for each conflict
+    #[arg(long, short = 'i')]
+    pub interactive: bool,
+}
+
+impl Command for MergeCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let other = Storage::new(self.other.clone());
+        if !other.exists() {
+            bail!("keystore not found: {}", self.other.display());
+        }
+        if std::fs::canonicalize(storage.path())? == std::fs::canonicalize(&self.other)? {
+            bail!("cannot merge a store with itself");
+        }
+        let mut kn = unlock_keystore(storage)?;
+        let other = unlock_keystore(other)?;
+
+        let summary = if self.interactive {
+            let mut stdin = io::stdin().lock();
+            kn.merge_with(&other, |conflict| ask(conflict, &mut stdin))?
+        } else {
+            kn.merge(&other, self.prefer.into())?
+        };
+        kn.save()?;
+        print_summary(&summary);
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Prints the entries a merge added or decided, then the counts.
+pub(crate) fn print_summary(summary: &MergeSummary) {
+    for key in summary.added() {
+        println!("added   {key}");
+    }
+    for key in summary.taken() {
+        println!("theirs  {key}");
+    }
+    for key in summary.kept() {
+        println!("ours    {key}");
+    }
+    println!(
+        "Merged: {} added, {} taken from the other store, {} kept, {} unchanged",
+        summary.added().len(),
+        summary.taken().len(),
+        summary.kept().len(),
+        summary.unchanged()
+    );
+}
+
+/// Asks on stderr which side of `conflict` to keep, reading the answer from `input`.
+pub(crate) fn ask(conflict: &Conflict, input: &mut impl BufRead) -> Result<Side> {
+    let mut stderr = io::stderr();
+    writeln!(
+        stderr,
+        "{}: {} differ",
+        conflict.key(),
+        conflict.differences().join(", ")
+    )?;
+    writeln!(stderr, "  ours:   updated {}", conflict.ours().updated())?;
+    writeln!(stderr, "  theirs: updated {}", conflict.theirs().updated())?;
+    loop {
+        write!(stderr, "Keep [o]urs or [t]heirs? ")?;
+        stderr.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            bail!("no answer for the conflict of '{}'", conflict.key());
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "o" | "ours" => return Ok(Side::Ours),
+            "t" | "theirs" => return Ok(Side::Theirs),
+            _ => {}
+        }
+    }
+}
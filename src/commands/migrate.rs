@@ -0,0 +1,41 @@
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+use keynest::format::CURRENT_VERSION;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest migrate                    Rewrite the store in the current file format
+  keynest migrate --to 2             Rewrite it in format v2 for older keynest versions
+
+The original file is kept as <store>.bak.<time>; `keynest backup restore` brings it back.
+Migrating keeps the password, keyfile, keyslots, cipher and KDF of the store.")]
+pub struct MigrateCommand {
+    /// Format version to write (2 or the current version)
+    #[arg(long, value_name = "VERSION", default_value_t = CURRENT_VERSION)]
+    pub to: u8,
+}
+
+impl Command for MigrateCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let from = kn.info()?.version();
+        let backup = kn.migrate_to(self.to)?;
+        if from == self.to {
+            println!("rewrote the keystore in format v{}", self.to);
+        } else {
+            println!("migrated the keystore from format v{from} to v{}", self.to);
+        }
+        if let Some(backup) = backup {
+            println!("original kept as {}", backup.path().display());
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
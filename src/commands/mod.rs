@@ -6,15 +6,73 @@ pub trait Command {
     fn run(self, store: Option<PathBuf>) -> Result<ExitCode>;
 }
 
+pub mod agent;
+pub mod api;
+pub mod attach;
+pub mod audit;
+pub mod audit_age;
+pub mod audit_breach;
+pub mod audit_strength;
+pub mod autotype;
+pub mod backup;
+pub mod browser;
 pub mod common;
+pub mod compact;
+pub mod compat;
+pub mod completions;
+pub mod convert;
+pub mod counter;
+pub mod cp;
+pub mod crypt;
+pub mod csv;
+pub mod deps;
+pub mod derive;
+pub mod dev;
+pub mod duress;
+pub mod edit;
 pub mod exec;
 pub mod export;
+pub mod generate;
 pub mod get;
+pub mod gpg_preset;
+pub mod help_topics;
 pub mod import;
 pub mod info;
 pub mod init;
+pub mod key_index;
+pub mod keychain;
+pub mod keyslot;
+pub mod lease;
 pub mod list;
+pub mod lookup;
+pub mod markdown;
+pub mod merge;
+pub mod migrate;
+pub mod mv;
+pub mod new;
+pub mod nuke;
+pub mod os_keychain;
+pub mod pin;
+pub mod plan;
+pub mod plugin;
+pub mod profile;
+pub mod promote;
+pub mod quota;
 pub mod rekey;
 pub mod remove;
+pub mod repair;
+pub mod resolve;
+pub mod search;
+pub mod secret_dir;
+pub mod service;
+pub mod session;
 pub mod set;
+pub mod snapshot;
+pub mod ssh;
+pub mod stats;
+pub mod template;
+pub mod totp;
+pub mod typing;
 pub mod update;
+pub mod verify_chain;
+pub mod wifi;
@@ -0,0 +1,104 @@
+use anyhow::{Result, bail};
+use clap::Args;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{confirm, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "\
+Examples:
+  keynest mv api_key github/token                   Rename one secret
+  keynest mv --prefix old-app/ new-app/             Move a whole namespace
+  keynest mv --prefix old-app/ new-app/ --dry-run   Preview without changing anything
+  keynest mv --prefix staging/ prod/ --yes          Skip the confirmation prompt
+  keynest mv db/password-new db/password --force    Replace an existing secret
+
+Entries keep their fields, tags, attachments and timestamps; 'ref:' values pointing at
+a renamed key are rewritten, and pins follow. Either every key is renamed or none is;
+renaming onto an existing key fails unless --force is given."
+)]
+pub struct MvCommand {
+    /// Key to rename, or with --prefix the prefix to replace
+    pub from: String,
+
+    /// New key, or with --prefix the new prefix
+    pub to: String,
+
+    /// Rename every key starting with FROM
+    #[arg(long)]
+    pub prefix: bool,
+
+    /// Replace existing secrets with the renamed ones
+    #[arg(long, short = 'f')]
+    pub force: bool,
+
+    /// Show what would be renamed without changing the store
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Do not ask for confirmation
+    #[arg(long, short = 'y')]
+    pub yes: bool,
+}
+
+impl Command for MvCommand {
+    fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        if self.from == self.to {
+            bail!("the old and new names must be different");
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        // Renamed in memory only; nothing is written before the confirmation.
+        let summary = if self.prefix {
+            kn.rename_prefix(&self.from, &self.to, self.force)?
+        } else {
+            kn.rename(&self.from, &self.to, self.force)?
+        };
+        let replaced = summary.replaced();
+        let (renamed, rewritten) = (summary.renamed(), summary.rewritten());
+
+        if renamed.is_empty() {
+            println!("No secrets to rename");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if self.prefix || self.dry_run {
+            for (old, new) in renamed {
+                if replaced.contains(new) {
+                    println!("  replace  {old} -> {new}");
+                } else {
+                    println!("  rename   {old} -> {new}");
+                }
+            }
+            for key in rewritten {
+                println!("  rewrite  {key} -> {}", kn.get(key)?.unwrap_or_default());
+            }
+        }
+
+        if self.dry_run {
+            println!("Dry run: {} secret(s) would be renamed", renamed.len());
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if self.prefix && !self.yes && !confirm(&format!("Rename {} secret(s)?", renamed.len()))? {
+            println!("Aborted");
+            return Ok(ExitCode::from(1));
+        }
+
+        kn.save()?;
+        match rewritten.len() {
+            0 => println!("Renamed {} secret(s)", renamed.len()),
+            n => println!(
+                "Renamed {} secret(s) and updated {n} reference(s)",
+                renamed.len()
+            ),
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
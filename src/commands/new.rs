@@ -0,0 +1,163 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use crate::commands::Command;
+use crate::commands::common::{parse_expiry, resolve_existing_storage, unlock_keystore};
+use crate::commands::profile;
+use keynest::template::{Rule, Template};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest new --template postgres prod/db2       Create prod/db2 from the postgres template,
+                                                 asking for the fields it asks for
+  keynest new -t postgres prod/db2 --field host=db2.internal --field user=app
+                                                 Fill in fields instead of asking for them
+  keynest new -t api-key stripe/live --value sk_live_...
+                                                 Use a given value instead of the template's
+  keynest template list                          Show the available templates
+
+The template is looked up in the keystore first (keynest template add), then in the
+[templates] tables of the config file. Generated values are not printed unless --show is
+given; asked-for values must be passed with --field when stdin is not a terminal.")]
+pub struct NewCommand {
+    /// Key of the new entry
+    pub key: String,
+
+    /// Template to create the entry from
+    #[arg(long, short = 't', value_name = "NAME")]
+    pub template: String,
+
+    /// Fill in a field of the template (NAME=VALUE); repeatable
+    #[arg(long = "field", value_name = "NAME=VALUE", value_parser = parse_filled_field)]
+    pub fields: Vec<(String, String)>,
+
+    /// Use this value instead of the one the template generates or asks for
+    #[arg(long)]
+    pub value: Option<String>,
+
+    /// Also print the value when it was generated
+    #[arg(long)]
+    pub show: bool,
+}
+
+impl Command for NewCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+        if kn.kind(&self.key)?.is_some() {
+            bail!("'{}' already exists", self.key);
+        }
+
+        let Some((template, _)) = all_templates(kn.templates()?).remove(&self.template) else {
+            bail!(
+                "no template named '{}'; see keynest template list",
+                self.template
+            );
+        };
+        let expires = template
+            .expires()
+            .map(parse_expiry)
+            .transpose()
+            .with_context(|| format!("invalid expiry in template '{}'", self.template))?;
+
+        let mut given: BTreeMap<String, String> = self.fields.into_iter().collect();
+        let value_rule = template.value();
+        let generated = self.value.is_none() && value_rule.generates();
+        let value = fill("value", &value_rule, self.value, "--value VALUE")?;
+        let mut fields = Vec::new();
+        for (name, rule) in template.fields() {
+            let hint = format!("--field {name}=VALUE");
+            fields.push((name, fill(name, rule, given.remove(name), &hint)?));
+        }
+        if let Some(name) = given.keys().next() {
+            bail!("template '{}' has no field '{name}'", self.template);
+        }
+
+        kn.set(&self.key, &value)?;
+        for tag in template.tags() {
+            kn.add_tag(&self.key, tag)?;
+        }
+        for (name, value) in &fields {
+            kn.set_field(&self.key, name, value)?;
+        }
+        if expires.is_some() {
+            kn.set_expiry(&self.key, expires)?;
+        }
+        kn.save()?;
+
+        if self.show && generated {
+            println!("{}", value.as_str());
+        }
+        eprintln!("created '{}' from template '{}'", self.key, self.template);
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn parse_filled_field(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => bail!("expected NAME=VALUE, got '{s}'"),
+    }
+}
+
+/// Fills in `what` following `rule`, unless a value was `given`.
+fn fill(what: &str, rule: &Rule, given: Option<String>, hint: &str) -> Result<Zeroizing<String>> {
+    if let Some(value) = given {
+        return Ok(Zeroizing::new(value));
+    }
+    if let Some(value) = rule.generate()? {
+        return Ok(value);
+    }
+    if !rule.asks() {
+        return Ok(Zeroizing::new(
+            rule.default_value().unwrap_or_default().to_string(),
+        ));
+    }
+    if !io::stdin().is_terminal() {
+        match rule.default_value() {
+            Some(default) => return Ok(Zeroizing::new(default.to_string())),
+            None => bail!("{what} is asked for; pass {hint} when stdin is not a terminal"),
+        }
+    }
+
+    let question = match rule.default_value() {
+        Some(default) if !rule.is_secret() => format!("{what} [{default}]: "),
+        _ => format!("{what}: "),
+    };
+    let answer = if rule.is_secret() {
+        Zeroizing::new(rpassword::prompt_password(&question)?)
+    } else {
+        eprint!("{question}");
+        io::stderr().flush()?;
+        let mut answer = Zeroizing::new(String::new());
+        io::stdin().lock().read_line(&mut answer)?;
+        Zeroizing::new(answer.trim().to_string())
+    };
+    match rule.default_value() {
+        Some(default) if answer.is_empty() => Ok(Zeroizing::new(default.to_string())),
+        _ if answer.is_empty() => bail!("{what} cannot be empty"),
+        _ => Ok(answer),
+    }
+}
+
+/// Returns the templates of the keystore and the config file, the keystore's taking
+/// precedence, with where each comes from.
+pub fn all_templates(
+    in_store: BTreeMap<String, Template>,
+) -> BTreeMap<String, (Template, &'static str)> {
+    let mut all: BTreeMap<_, _> = profile::config()
+        .templates()
+        .iter()
+        .map(|(name, template)| (name.clone(), (template.clone(), "config")))
+        .collect();
+    for (name, template) in in_store {
+        all.insert(name, (template, "store"));
+    }
+    all
+}
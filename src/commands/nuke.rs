@@ -0,0 +1,62 @@
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{chain_witness, resolve_existing_storage, unlock_keystore};
+use crate::commands::session;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest nuke --confirm                  Destroy the default store for good
+  keynest --store work.db nuke --confirm  Destroy another store
+
+Asks for the master password, then overwrites the store file with random data, flushes
+it to disk and deletes it, together with its backups, leftover temporary files, journal,
+audit log, key index, attachment chunks and sync conflict copies.
+It also ends an agent session of the store and deletes its chain witness, so a new
+store created at the same path starts afresh.
+There is no undo: exports and copies elsewhere are the only way back.
+
+Overwriting only helps where the file system writes in place. Copy-on-write file
+systems (btrfs, ZFS, APFS), snapshots and the wear leveling of SSDs can keep the old
+blocks; full-disk encryption is the way to make those unreadable.")]
+pub struct NukeCommand {
+    /// Really destroy the store; nothing is touched without it
+    #[arg(long)]
+    pub confirm: bool,
+}
+
+impl Command for NukeCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        if !self.confirm {
+            eprintln!(
+                "refusing to destroy {} without --confirm",
+                storage.path().display()
+            );
+            return Ok(ExitCode::from(1));
+        }
+        // The session and the chain witness are named after the path of the store: a
+        // store created there later must start without them.
+        let canonical = std::fs::canonicalize(storage.path())?;
+        let witness = chain_witness(&canonical).ok();
+
+        let kn = unlock_keystore(storage)?;
+        let files = kn.destroy()?;
+        for file in &files {
+            println!("wiped {}", file.display());
+        }
+        if session::end(&canonical)? {
+            println!("ended the session of {}", canonical.display());
+        }
+        if let Some(witness) = witness.filter(|w| w.exists()) {
+            std::fs::remove_file(witness.path())?;
+            println!("removed the chain witness {}", witness.path().display());
+        }
+        println!("destroyed the keystore ({} files)", files.len());
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,595 @@
+//! Reading and writing credentials of the operating system's credential store, for
+//! `import --os-keychain`, `export --os-keychain` and `keynest keychain`.
+//!
+//! - macOS: generic and internet passwords of the default keychain search list, through
+//!   the `security` tool. Listing only reads attributes; reading a password may show a
+//!   keychain access prompt. Exports are written as generic passwords.
+//! - Windows: generic credentials of the current user in Credential Manager. Domain
+//!   credentials are skipped, their passwords cannot be read back.
+//! - Other Unix systems: the Secret Service (GNOME Keyring, KWallet) through the
+//!   `secret-tool` of libsecret, with `service` and `account` attributes, for the master
+//!   password of `keynest keychain` only. It cannot be listed, so importing is not
+//!   supported, and exporting stays limited to macOS and Windows.
+//!
+//! Other platforms have no supported credential store.
+
+use anyhow::Result;
+use zeroize::Zeroizing;
+
+/// A credential found in the OS store.
+pub struct Credential {
+    /// Service, server or target name.
+    pub service: String,
+    /// Account or user name; may be empty.
+    pub account: String,
+    source: imp::Source,
+}
+
+impl Credential {
+    /// Returns the keynest key for the credential: `<service>/<account>`, with empty,
+    /// `.` and `..` names dropped so any service name gives a valid key.
+    pub fn key(&self) -> String {
+        credential_key(&self.service, &self.account)
+    }
+
+    /// Reads the secret of the credential.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the OS store refuses access or the secret is not text.
+    pub fn secret(&self) -> Result<Zeroizing<String>> {
+        imp::secret(self)
+    }
+}
+
+/// Returns the keynest key for the credential of `account` at `service`:
+/// `<service>/<account>`, with empty, `.` and `..` names dropped.
+pub fn credential_key(service: &str, account: &str) -> String {
+    service
+        .split('/')
+        .chain(account.split('/'))
+        .map(|name| name.trim().replace(char::is_control, ""))
+        .filter(|name| !name.is_empty() && name != "." && name != "..")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Lists the credentials of the current user, without their secrets where the platform
+/// allows it.
+///
+/// # Errors
+///
+/// Returns an error if the OS store cannot be enumerated, or on platforms without one.
+pub fn list() -> Result<Vec<Credential>> {
+    imp::list()
+}
+
+/// Stores `secret` as the credential of `account` at `service`, replacing an existing
+/// one.
+///
+/// # Errors
+///
+/// Returns an error if the OS store refuses the write, or on platforms without one.
+pub fn store(service: &str, account: &str, secret: &str) -> Result<()> {
+    imp::store(service, account, secret)
+}
+
+/// Returns the secret of the credential of `account` at `service`, or `None` if there
+/// is no such credential.
+///
+/// # Errors
+///
+/// Returns an error if the OS store refuses access, or on platforms without one.
+pub fn lookup(service: &str, account: &str) -> Result<Option<Zeroizing<String>>> {
+    imp::lookup(service, account)
+}
+
+/// Deletes the credential of `account` at `service`. Returns `false` if there was none.
+///
+/// # Errors
+///
+/// Returns an error if the OS store refuses the deletion, or on platforms without one.
+pub fn delete(service: &str, account: &str) -> Result<bool> {
+    imp::delete(service, account)
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use anyhow::{Context, Result, bail};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use zeroize::Zeroizing;
+
+    use super::Credential;
+
+    /// Keychain item class.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub(super) enum Source {
+        Generic,
+        Internet,
+    }
+
+    pub(super) fn list() -> Result<Vec<Credential>> {
+        let output = Command::new("security")
+            .arg("dump-keychain")
+            .stderr(Stdio::inherit())
+            .output()
+            .context("failed to run 'security'")?;
+        if !output.status.success() {
+            bail!("'security dump-keychain' failed with {}", output.status);
+        }
+        Ok(parse_dump(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    pub(super) fn secret(credential: &Credential) -> Result<Zeroizing<String>> {
+        let command = match credential.source {
+            Source::Generic => "find-generic-password",
+            Source::Internet => "find-internet-password",
+        };
+        let output = Command::new("security")
+            .args([command, "-s", &credential.service])
+            .args(["-a", &credential.account, "-w"])
+            .stderr(Stdio::null())
+            .output()
+            .context("failed to run 'security'")?;
+        let stdout = Zeroizing::new(output.stdout);
+        if !output.status.success() {
+            bail!(
+                "keychain refused access to '{}' ({})",
+                credential.key(),
+                output.status
+            );
+        }
+        let secret = std::str::from_utf8(&stdout)
+            .with_context(|| format!("password of '{}' is not text", credential.key()))?;
+        Ok(Zeroizing::new(
+            secret.strip_suffix('\n').unwrap_or(secret).to_string(),
+        ))
+    }
+
+    pub(super) fn store(service: &str, account: &str, secret: &str) -> Result<()> {
+        // `security -i` reads the command from stdin, so the password (hex encoded for
+        // -X) never shows up in the arguments of a process.
+        let hex: Zeroizing<String> =
+            Zeroizing::new(secret.bytes().map(|b| format!("{b:02x}")).collect());
+        let line = Zeroizing::new(format!(
+            "add-generic-password -U -s {} -a {} -X {}\n",
+            quote(service),
+            quote(account),
+            *hex
+        ));
+        let mut child = Command::new("security")
+            .arg("-i")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run 'security'")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(line.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        // Interactive mode reports failed commands on stderr, not in its exit status.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() || !stderr.trim().is_empty() {
+            bail!(
+                "keychain refused to store service '{service}', account '{account}': {}",
+                stderr.trim()
+            );
+        }
+        Ok(())
+    }
+
+    pub(super) fn lookup(service: &str, account: &str) -> Result<Option<Zeroizing<String>>> {
+        let output = Command::new("security")
+            .args(["find-generic-password", "-s", service, "-a", account, "-w"])
+            .stderr(Stdio::null())
+            .output()
+            .context("failed to run 'security'")?;
+        let stdout = Zeroizing::new(output.stdout);
+        match output.status.code() {
+            Some(0) => {}
+            Some(ERR_ITEM_NOT_FOUND) => return Ok(None),
+            _ => bail!(
+                "keychain refused access to service '{service}', account '{account}' ({})",
+                output.status
+            ),
+        }
+        let secret = std::str::from_utf8(&stdout)
+            .with_context(|| format!("password of service '{service}' is not text"))?;
+        Ok(Some(Zeroizing::new(
+            secret.strip_suffix('\n').unwrap_or(secret).to_string(),
+        )))
+    }
+
+    pub(super) fn delete(service: &str, account: &str) -> Result<bool> {
+        let status = Command::new("security")
+            .args(["delete-generic-password", "-s", service, "-a", account])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("failed to run 'security'")?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(ERR_ITEM_NOT_FOUND) => Ok(false),
+            _ => bail!(
+                "keychain refused to delete service '{service}', account '{account}' ({status})"
+            ),
+        }
+    }
+
+    /// Exit status of `security` for errSecItemNotFound.
+    const ERR_ITEM_NOT_FOUND: i32 = 44;
+
+    /// Quotes an argument for the command line of `security -i`.
+    fn quote(arg: &str) -> String {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    /// Parses the item attributes printed by `security dump-keychain`.
+    ///
+    /// Items start with a `keychain:` line, followed by `class: "genp"` or `"inet"` and
+    /// attribute lines such as `    "svce"<blob>="github.com"`.
+    fn parse_dump(dump: &str) -> Vec<Credential> {
+        let mut credentials = Vec::new();
+        let mut item: Option<(Source, Option<String>, Option<String>)> = None;
+        let mut flush = |item: Option<(Source, Option<String>, Option<String>)>| {
+            if let Some((source, Some(service), account)) = item {
+                credentials.push(Credential {
+                    service,
+                    account: account.unwrap_or_default(),
+                    source,
+                });
+            }
+        };
+
+        for line in dump.lines() {
+            let line = line.trim();
+            if line.starts_with("keychain:") {
+                flush(item.take());
+            } else if let Some(class) = line.strip_prefix("class: ") {
+                item = match class {
+                    "\"genp\"" => Some((Source::Generic, None, None)),
+                    "\"inet\"" => Some((Source::Internet, None, None)),
+                    _ => None,
+                };
+            } else if let Some((source, service, account)) = item.as_mut() {
+                let Some((name, value)) = attribute(line) else {
+                    continue;
+                };
+                match (name, *source) {
+                    ("svce", Source::Generic) | ("srvr", Source::Internet) => {
+                        *service = Some(value);
+                    }
+                    ("acct", _) => *account = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        flush(item);
+        credentials
+    }
+
+    /// Parses `"name"<type>=value`, where value is `"text"`, `0x<hex>  "text"` or
+    /// `<NULL>`.
+    fn attribute(line: &str) -> Option<(&str, String)> {
+        let rest = line.strip_prefix('"')?;
+        let (name, rest) = rest.split_once('"')?;
+        let (_, value) = rest.split_once('=')?;
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => {
+                let hex = hex.split_whitespace().next()?;
+                let bytes: Option<Vec<u8>> = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect();
+                String::from_utf8(bytes?).ok()?
+            }
+            None => value.strip_prefix('"')?.strip_suffix('"')?.to_string(),
+        };
+        Some((name, value))
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use anyhow::{Result, bail};
+    use windows_sys::Win32::Security::Credentials::{
+        CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC, CREDENTIALW, CredDeleteW, CredEnumerateW,
+        CredFree, CredReadW, CredWriteW,
+    };
+    use zeroize::Zeroizing;
+
+    use super::Credential;
+
+    /// The secret, read while enumerating: Credential Manager returns it with the item.
+    pub(super) struct Source(Zeroizing<String>);
+
+    pub(super) fn list() -> Result<Vec<Credential>> {
+        let mut count = 0u32;
+        let mut items: *mut *mut CREDENTIALW = std::ptr::null_mut();
+        // SAFETY: a null filter enumerates all credentials of the user; on success the
+        // array and the items it points to are owned by us until CredFree.
+        let ok = unsafe { CredEnumerateW(std::ptr::null(), 0, &mut count, &mut items) };
+        if ok == 0 {
+            let error = std::io::Error::last_os_error();
+            // The user has no credentials.
+            if error.raw_os_error() == Some(ERROR_NOT_FOUND) {
+                return Ok(Vec::new());
+            }
+            bail!("failed to enumerate Credential Manager: {error}");
+        }
+
+        let mut credentials = Vec::new();
+        for i in 0..count as usize {
+            // SAFETY: `items` holds `count` valid pointers to CREDENTIALW.
+            let item = unsafe { &**items.add(i) };
+            if item.Type != CRED_TYPE_GENERIC {
+                continue;
+            }
+            // SAFETY: the blob pointer is valid for `CredentialBlobSize` bytes.
+            let blob = unsafe {
+                std::slice::from_raw_parts(item.CredentialBlob, item.CredentialBlobSize as usize)
+            };
+            let Some(secret) = decode_blob(blob) else {
+                continue;
+            };
+            credentials.push(Credential {
+                // SAFETY: both are null or null-terminated strings owned by the item.
+                service: unsafe { wide_to_string(item.TargetName) },
+                account: unsafe { wide_to_string(item.UserName) },
+                source: Source(secret),
+            });
+        }
+        // SAFETY: `items` was allocated by CredEnumerateW and is not used after this.
+        unsafe { CredFree(items as *const _) };
+        Ok(credentials)
+    }
+
+    pub(super) fn secret(credential: &Credential) -> Result<Zeroizing<String>> {
+        Ok(credential.source.0.clone())
+    }
+
+    pub(super) fn store(service: &str, account: &str, secret: &str) -> Result<()> {
+        // CRED_MAX_CREDENTIAL_BLOB_SIZE
+        const MAX_BLOB: usize = 5 * 512;
+
+        let mut target = wide(service);
+        let mut user = wide(account);
+        let mut blob: Zeroizing<Vec<u8>> =
+            Zeroizing::new(secret.encode_utf16().flat_map(u16::to_le_bytes).collect());
+        if blob.len() > MAX_BLOB {
+            bail!(
+                "'{service}/{account}' is too long for Credential Manager ({MAX_BLOB} bytes at most)"
+            );
+        }
+
+        // SAFETY: an all-zero CREDENTIALW is valid: null pointers and no attributes.
+        let mut credential: CREDENTIALW = unsafe { std::mem::zeroed() };
+        credential.Type = CRED_TYPE_GENERIC;
+        credential.TargetName = target.as_mut_ptr();
+        if !account.is_empty() {
+            credential.UserName = user.as_mut_ptr();
+        }
+        credential.CredentialBlobSize = blob.len() as u32;
+        credential.CredentialBlob = blob.as_mut_ptr();
+        credential.Persist = CRED_PERSIST_LOCAL_MACHINE;
+        // SAFETY: the strings and the blob the credential points to outlive the call.
+        if unsafe { CredWriteW(&credential, 0) } == 0 {
+            bail!(
+                "failed to store '{service}' in Credential Manager: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    pub(super) fn lookup(service: &str, _account: &str) -> Result<Option<Zeroizing<String>>> {
+        let target = wide(service);
+        let mut item: *mut CREDENTIALW = std::ptr::null_mut();
+        // SAFETY: `target` is null-terminated; on success the item is owned by us until
+        // CredFree.
+        if unsafe { CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut item) } == 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_NOT_FOUND) {
+                return Ok(None);
+            }
+            bail!("failed to read '{service}' from Credential Manager: {error}");
+        }
+        // SAFETY: the blob pointer of the item is valid for `CredentialBlobSize` bytes.
+        let secret = unsafe {
+            let item = &*item;
+            decode_blob(std::slice::from_raw_parts(
+                item.CredentialBlob,
+                item.CredentialBlobSize as usize,
+            ))
+        };
+        // SAFETY: `item` was allocated by CredReadW and is not used after this.
+        unsafe { CredFree(item as *const _) };
+        match secret {
+            Some(secret) => Ok(Some(secret)),
+            None => bail!("the secret of '{service}' in Credential Manager is not text"),
+        }
+    }
+
+    pub(super) fn delete(service: &str, _account: &str) -> Result<bool> {
+        let target = wide(service);
+        // SAFETY: `target` is null-terminated.
+        if unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_NOT_FOUND) {
+                return Ok(false);
+            }
+            bail!("failed to delete '{service}' from Credential Manager: {error}");
+        }
+        Ok(true)
+    }
+
+    /// ERROR_NOT_FOUND: no such credential.
+    const ERROR_NOT_FOUND: i32 = 1168;
+
+    /// Converts to a null-terminated UTF-16 string.
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    /// Decodes a credential blob: UTF-16LE as written by Credential Manager, or UTF-8 as
+    /// written by some applications. Returns `None` for binary blobs.
+    fn decode_blob(blob: &[u8]) -> Option<Zeroizing<String>> {
+        if blob.len() % 2 == 0 {
+            let wide: Zeroizing<Vec<u16>> = Zeroizing::new(
+                blob.chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect(),
+            );
+            if let Some(text) = String::from_utf16(&wide)
+                .ok()
+                .filter(|text| !text.contains('\0'))
+            {
+                return Some(Zeroizing::new(text));
+            }
+        }
+        std::str::from_utf8(blob)
+            .ok()
+            .filter(|text| !text.contains('\0'))
+            .map(|text| Zeroizing::new(text.to_string()))
+    }
+
+    /// Converts a null-terminated UTF-16 string.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to a null-terminated UTF-16 string.
+    unsafe fn wide_to_string(ptr: *const u16) -> String {
+        if ptr.is_null() {
+            return String::new();
+        }
+        let mut len = 0;
+        // SAFETY: the string is null-terminated.
+        while unsafe { *ptr.add(len) } != 0 {
+            len += 1;
+        }
+        // SAFETY: `len` elements before the terminator are readable.
+        String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(ptr, len) })
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use anyhow::{Context, Result, bail};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use zeroize::Zeroizing;
+
+    use super::Credential;
+
+    pub(super) enum Source {}
+
+    pub(super) fn list() -> Result<Vec<Credential>> {
+        bail!("importing from the OS credential store is only available on macOS and Windows")
+    }
+
+    pub(super) fn secret(credential: &Credential) -> Result<Zeroizing<String>> {
+        match credential.source {}
+    }
+
+    pub(super) fn store(service: &str, account: &str, secret: &str) -> Result<()> {
+        // secret-tool reads the secret from stdin when it is not a terminal.
+        let mut child = secret_tool()
+            .arg("store")
+            .arg(format!("--label={service} ({account})"))
+            .args(["service", service, "account", account])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run 'secret-tool' (libsecret)")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(secret.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "Secret Service refused to store service '{service}', account '{account}': {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    pub(super) fn lookup(service: &str, account: &str) -> Result<Option<Zeroizing<String>>> {
+        let output = secret_tool()
+            .args(["lookup", "service", service, "account", account])
+            .stdin(Stdio::null())
+            .output()
+            .context("failed to run 'secret-tool' (libsecret)")?;
+        let stdout = Zeroizing::new(output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            // A missing item fails without a message.
+            if stderr.trim().is_empty() {
+                return Ok(None);
+            }
+            bail!(
+                "Secret Service refused access to service '{service}', account '{account}': {}",
+                stderr.trim()
+            );
+        }
+        let secret = std::str::from_utf8(&stdout)
+            .with_context(|| format!("secret of service '{service}' is not text"))?;
+        Ok(Some(Zeroizing::new(secret.to_string())))
+    }
+
+    pub(super) fn delete(service: &str, account: &str) -> Result<bool> {
+        // `secret-tool clear` succeeds whether or not there was an item.
+        if lookup(service, account)?.is_none() {
+            return Ok(false);
+        }
+        let output = secret_tool()
+            .args(["clear", "service", service, "account", account])
+            .stdin(Stdio::null())
+            .output()
+            .context("failed to run 'secret-tool' (libsecret)")?;
+        if !output.status.success() {
+            bail!(
+                "Secret Service refused to delete service '{service}', account '{account}': {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(true)
+    }
+
+    fn secret_tool() -> Command {
+        Command::new("secret-tool")
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use anyhow::{Result, bail};
+    use zeroize::Zeroizing;
+
+    use super::Credential;
+
+    pub(super) enum Source {}
+
+    pub(super) fn list() -> Result<Vec<Credential>> {
+        bail!("importing from the OS credential store is only available on macOS and Windows")
+    }
+
+    pub(super) fn secret(credential: &Credential) -> Result<Zeroizing<String>> {
+        match credential.source {}
+    }
+
+    pub(super) fn store(_service: &str, _account: &str, _secret: &str) -> Result<()> {
+        bail!("this platform has no supported OS credential store")
+    }
+
+    pub(super) fn lookup(_service: &str, _account: &str) -> Result<Option<Zeroizing<String>>> {
+        bail!("this platform has no supported OS credential store")
+    }
+
+    pub(super) fn delete(_service: &str, _account: &str) -> Result<bool> {
+        bail!("this platform has no supported OS credential store")
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use clap::Args;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_indexed, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest pin prod/db/password                  Pin an entry as a favorite
+  keynest pin api_key github/token              Pin several entries
+  keynest pin                                   List the pinned entries
+
+Pinned entries are listed first by 'keynest list'. Removing an entry unpins it.")]
+pub struct PinCommand {
+    /// Keys of the entries to pin; lists the pinned entries if omitted
+    pub keys: Vec<String>,
+}
+
+impl Command for PinCommand {
+    fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        if self.keys.is_empty() {
+            let kn = unlock_indexed(storage)?;
+            for key in kn.pinned()? {
+                println!("{key}");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let mut kn = unlock_keystore(storage)?;
+        let mut changed = Vec::new();
+        for key in &self.keys {
+            if kn.pin(key)? {
+                changed.push(key);
+            } else {
+                println!("'{key}' is already pinned");
+            }
+        }
+        if !changed.is_empty() {
+            kn.save()?;
+        }
+        for key in changed {
+            println!("pinned '{key}'");
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "\
+Examples:
+  keynest unpin prod/db/password                Stop listing an entry first"
+)]
+pub struct UnpinCommand {
+    /// Keys of the entries to unpin
+    #[arg(required = true)]
+    pub keys: Vec<String>,
+}
+
+impl Command for UnpinCommand {
+    fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let mut changed = Vec::new();
+        for key in &self.keys {
+            if kn.unpin(key)? {
+                changed.push(key);
+            } else {
+                println!("'{key}' is not pinned");
+            }
+        }
+        if !changed.is_empty() {
+            kn.save()?;
+        }
+        for key in changed {
+            println!("unpinned '{key}'");
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
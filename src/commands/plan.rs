@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+use keynest::plan::{Plan, StepOutcome};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest plan apply plan.yaml                   Create the entries of a plan that do not exist yet
+  keynest plan apply plan.yaml --dry-run         Show the order the steps would run in
+
+A plan is a YAML file with a list of steps, each creating one entry:
+
+  steps:
+    - key: ssh/ca
+      ssh-ca: { comment: prod-ca }          # Ed25519 CA key (public key in field public-key)
+    - key: ssh/web1/key
+      ssh-key: { comment: web1 }            # Ed25519 key pair
+    - key: ssh/web1/cert
+      ssh-cert: { ca: ssh/ca, subject: ssh/web1/key, identity: web1,
+                  principals: [web1.lan], host: true, days: 365 }
+    - key: db/password
+      generate: { length: 32, symbols: true }   # or { words: 6 }
+      tags: [db]
+      fields: { user: app }
+    - key: app/db-password
+      ref: db/password                      # a ref: entry pointing at db/password
+    - key: app/name
+      value: billing
+
+Steps run after the steps creating the entries they use, whatever their order in the
+file. Entries that already exist are kept. All steps are applied in one transaction: if
+one fails, nothing is saved.")]
+pub struct PlanCommand {
+    #[command(subcommand)]
+    pub action: PlanAction,
+}
+
+#[derive(Subcommand)]
+pub enum PlanAction {
+    /// Create the entries of a plan
+    Apply {
+        /// YAML file with the plan
+        file: PathBuf,
+
+        /// Only check the plan and show the order the steps would run in
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+impl Command for PlanCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let PlanAction::Apply { file, dry_run } = self.action;
+        let text = std::fs::read_to_string(&file)
+            .with_context(|| format!("failed to read {}", file.display()))?;
+        let plan =
+            Plan::from_yaml(&text).with_context(|| format!("invalid plan {}", file.display()))?;
+
+        if dry_run {
+            for (i, step) in plan.order()?.iter().enumerate() {
+                println!("{:>3}. {}  ({})", i + 1, step.key(), step.describe());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+        let outcomes = kn.apply_plan(&plan)?;
+        let mut created = 0;
+        for (key, outcome) in &outcomes {
+            match outcome {
+                StepOutcome::Created => {
+                    created += 1;
+                    println!("created {key}");
+                }
+                StepOutcome::Kept => println!("kept    {key} (exists)"),
+            }
+        }
+        println!(
+            "plan applied: {created} created, {} kept",
+            outcomes.len() - created
+        );
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,149 @@
+//! External subcommands: `keynest <name> ...` runs `keynest-<name>` from `PATH`.
+//!
+//! Plugins inherit stdin, stdout and stderr and receive their arguments unchanged.
+//! The contract is the environment below, versioned by `KEYNEST_PLUGIN_API`:
+//!
+//! - `KEYNEST_PLUGIN_API`: contract version (currently 1)
+//! - `KEYNEST_VERSION`: version of the calling keynest
+//! - `KEYNEST_BIN`: path of the calling keynest binary, for calling back into it
+//! - `KEYNEST_PATH`: path of the store selected by `--store`/`KEYNEST_PATH` or the default
+//! - `KEYNEST_PASSWORD_FD`: the `--password-fd` descriptor, if one was given (inherited)
+//!
+//! The exit status of the plugin becomes the exit status of keynest.
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{interruptible, print_json, resolve_storage};
+
+/// Version of the environment contract passed to plugins.
+const PLUGIN_API: u32 = 1;
+/// Prefix of plugin executables.
+const PREFIX: &str = "keynest-";
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest plugins                                List plugins found on PATH
+  keynest bitwarden-import export.json           Run keynest-bitwarden-import with its arguments
+
+Any executable named keynest-<name> on PATH can be run as 'keynest <name>'. It gets
+KEYNEST_PLUGIN_API, KEYNEST_VERSION, KEYNEST_BIN, KEYNEST_PATH and, with
+--password-fd, KEYNEST_PASSWORD_FD in its environment; see the README.")]
+pub struct PluginsCommand {
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+impl Command for PluginsCommand {
+    fn run(self, _store: Option<PathBuf>) -> Result<ExitCode> {
+        let plugins = discover();
+        if self.json {
+            let plugins: Vec<_> = plugins
+                .iter()
+                .map(|(name, path)| serde_json::json!({"name": name, "path": path}))
+                .collect();
+            print_json(&plugins)?;
+        } else if plugins.is_empty() {
+            println!("No plugins found (executables named {PREFIX}<name> on PATH)");
+        } else {
+            for (name, path) in &plugins {
+                println!("{name}  {}", path.display());
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Runs the plugin named by `args[0]` with the remaining arguments.
+pub fn run(args: Vec<OsString>, store: Option<PathBuf>) -> Result<ExitCode> {
+    let Some((name, args)) = args.split_first() else {
+        bail!("missing plugin name");
+    };
+    let name = name.to_string_lossy();
+    let Some(path) = find(&name) else {
+        eprintln!("error: unrecognized subcommand '{name}'");
+        eprintln!("  (no plugin '{PREFIX}{name}' found on PATH; see 'keynest plugins')");
+        return Ok(ExitCode::from(2));
+    };
+
+    let storage = resolve_storage(store)?;
+    let mut cmd = std::process::Command::new(&path);
+    cmd.args(args)
+        .env("KEYNEST_PLUGIN_API", PLUGIN_API.to_string())
+        .env("KEYNEST_VERSION", env!("CARGO_PKG_VERSION"))
+        .env("KEYNEST_PATH", storage.path());
+    if let Ok(exe) = std::env::current_exe() {
+        cmd.env("KEYNEST_BIN", exe);
+    }
+    if let Some(fd) = auth::password_fd() {
+        cmd.env("KEYNEST_PASSWORD_FD", fd.to_string());
+    }
+    if let Some(keyfile) = auth::keyfile_path() {
+        cmd.env("KEYNEST_KEYFILE", keyfile);
+    }
+
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("failed to run plugin {}", path.display()))?;
+    // Ctrl-C reaches the plugin directly; wait for it to exit.
+    let status = interruptible(|_| child.wait())?;
+    Ok(ExitCode::from(status.code().unwrap_or(1) as u8))
+}
+
+/// Returns the first `keynest-<name>` executable on `PATH`.
+fn find(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return None;
+    }
+    let file_name = format!("{PREFIX}{name}{}", std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_executable(path))
+}
+
+/// Lists plugins on `PATH` by name; earlier `PATH` entries win.
+fn discover() -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    let Some(path) = std::env::var_os("PATH") else {
+        return plugins;
+    };
+    for dir in std::env::split_paths(&path) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name
+                .to_str()
+                .and_then(|n| n.strip_prefix(PREFIX))
+                .and_then(|n| n.strip_suffix(std::env::consts::EXE_SUFFIX))
+            else {
+                continue;
+            };
+            if !name.is_empty() && is_executable(&entry.path()) {
+                plugins.entry(name.to_string()).or_insert(entry.path());
+            }
+        }
+    }
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,62 @@
+//! The profile selected with `--profile` or `default-profile` in the config file.
+
+use anyhow::Result;
+use keynest::InitOptions;
+use keynest::config::{Config, Profile, default_config_path};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static ACTIVE: OnceLock<Option<(String, Profile)>> = OnceLock::new();
+
+/// Returns the path of the config file: `$KEYNEST_CONFIG` or the platform default.
+pub fn config_path() -> Result<PathBuf> {
+    match std::env::var_os("KEYNEST_CONFIG") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => default_config_path(),
+    }
+}
+
+/// Loads the config file and selects profile `name`, or else its default profile.
+pub fn select(name: Option<&str>) -> Result<()> {
+    let config = Config::load(&config_path()?)?;
+    let active = match name.or(config.default_profile()) {
+        Some(name) => Some((name.to_string(), config.profile(name)?.clone())),
+        None => None,
+    };
+    let _ = ACTIVE.set(active);
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+/// Returns the loaded config file; empty before [`select`] has run.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Returns the name and settings of the selected profile, if any.
+pub fn active() -> Option<(&'static str, &'static Profile)> {
+    ACTIVE
+        .get()?
+        .as_ref()
+        .map(|(name, profile)| (name.as_str(), profile))
+}
+
+/// Returns the keystore path of the selected profile, if it names one.
+pub fn store() -> Option<PathBuf> {
+    active()?.1.store().map(PathBuf::from)
+}
+
+/// Returns the score new master passwords must reach in the selected profile, if it
+/// sets one.
+pub fn min_password_score() -> Option<u8> {
+    active()?.1.min_password_score()
+}
+
+/// Returns the parameters for a new keystore: the selected profile's, or the defaults.
+pub fn init_options() -> Result<InitOptions> {
+    match active() {
+        Some((_, profile)) => profile.init_options(),
+        None => Ok(InitOptions::default()),
+    }
+}
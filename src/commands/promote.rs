@@ -0,0 +1,104 @@
+use anyhow::{Result, bail};
+use clap::Args;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{confirm, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest promote --from staging/ --to prod/                               Promote every secret under staging/
+  keynest promote --from staging/ --to prod/ --keys db/password,api/key    Promote selected secrets
+  keynest promote --from dev/ --to staging/ --dry-run                      Preview without changing anything
+  keynest promote --from staging/ --to prod/ --yes                         Skip the confirmation prompt
+
+Promoted secrets keep their kind, fields, tags, expiry, access restrictions and
+attachments. A promoted reference into --from (staging/db = ref:staging/shared) is
+rewritten to point into --to (prod/db = ref:prod/shared). With the audit log on (see
+`keynest audit`), every promoted secret is logged with the key it was copied from.")]
+pub struct PromoteCommand {
+    /// Source namespace (e.g. staging/)
+    #[arg(long)]
+    pub from: String,
+
+    /// Destination namespace (e.g. prod/)
+    #[arg(long)]
+    pub to: String,
+
+    /// Keys to promote, relative to the namespaces (default: all under --from)
+    #[arg(long, value_delimiter = ',')]
+    pub keys: Option<Vec<String>>,
+
+    /// Show what would be promoted without changing the store
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Do not ask for confirmation
+    #[arg(long, short = 'y')]
+    pub yes: bool,
+}
+
+impl Command for PromoteCommand {
+    fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        if self.from == self.to {
+            bail!("--from and --to must be different namespaces");
+        }
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let names: Vec<String> = match self.keys {
+            Some(keys) => keys,
+            None => kn
+                .list()
+                .into_iter()
+                .filter_map(|k| k.strip_prefix(&self.from).map(str::to_string))
+                .collect(),
+        };
+
+        if names.is_empty() {
+            println!("No secrets to promote");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        // (source, destination, destination already exists)
+        let mut plan = Vec::with_capacity(names.len());
+        for name in &names {
+            let src = format!("{}{name}", self.from);
+            let dst = format!("{}{name}", self.to);
+            if kn.get(&src)?.is_none() {
+                bail!("secret '{src}' not found");
+            }
+            let exists = kn.get(&dst)?.is_some();
+            plan.push((src, dst, exists));
+        }
+
+        for (src, dst, exists) in &plan {
+            let action = if *exists { "update" } else { "create" };
+            println!("  {action:<6}  {src} -> {dst}");
+        }
+
+        if self.dry_run {
+            println!("Dry run: {} secret(s) would be promoted", plan.len());
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if !self.yes && !confirm(&format!("Promote {} secret(s)?", plan.len()))? {
+            println!("Aborted");
+            return Ok(ExitCode::from(1));
+        }
+
+        kn.promote(&self.from, &self.to, &names)?;
+        kn.save()?;
+
+        println!(
+            "Promoted {} secret(s) from '{}' to '{}'",
+            plan.len(),
+            self.from,
+            self.to
+        );
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use clap::{Args, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+use keynest::{QuotaEnforcement, Quotas};
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Enforcement {
+    Warn,
+    Error,
+}
+
+impl From<Enforcement> for QuotaEnforcement {
+    fn from(e: Enforcement) -> Self {
+        match e {
+            Enforcement::Warn => QuotaEnforcement::Warn,
+            Enforcement::Error => QuotaEnforcement::Error,
+        }
+    }
+}
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest quota                                          Show the configured quotas
+  keynest quota set --max-value-len 65536 --enforce error   Reject values larger than 64 KiB
+  keynest quota set --max-entries 500 --max-store-size 1048576  Warn above 500 secrets or 1 MiB
+  keynest quota clear                                    Remove all quotas")]
+pub struct QuotaCommand {
+    #[command(subcommand)]
+    pub action: Option<QuotaAction>,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+#[derive(Subcommand)]
+pub enum QuotaAction {
+    /// Configure quotas (unspecified limits keep their current value)
+    Set {
+        /// Maximum number of secrets
+        #[arg(long = "max-entries")]
+        max_entries: Option<u64>,
+
+        /// Maximum length of a single value in bytes
+        #[arg(long = "max-value-len")]
+        max_value_len: Option<u64>,
+
+        /// Maximum size of the serialized store in bytes
+        #[arg(long = "max-store-size")]
+        max_store_size: Option<u64>,
+
+        /// What to do when a quota is exceeded (default: warn)
+        #[arg(long = "enforce", value_enum)]
+        enforce: Option<Enforcement>,
+    },
+    /// Remove all quotas
+    Clear,
+}
+
+impl Command for QuotaCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        match self.action {
+            None => {
+                let quotas = kn.quotas();
+                if self.json {
+                    print_json(quotas)?;
+                } else {
+                    print_quotas(quotas);
+                }
+            }
+            Some(QuotaAction::Set {
+                max_entries,
+                max_value_len,
+                max_store_size,
+                enforce,
+            }) => {
+                let current = *kn.quotas();
+                let quotas = Quotas::new(
+                    max_entries.or(current.max_entries()),
+                    max_value_len.or(current.max_value_len()),
+                    max_store_size.or(current.max_store_size()),
+                    enforce.map_or(current.enforcement(), Into::into),
+                );
+                kn.set_quotas(quotas)?;
+                kn.save()?;
+                println!("quotas updated");
+            }
+            Some(QuotaAction::Clear) => {
+                kn.set_quotas(Quotas::default())?;
+                kn.save()?;
+                println!("quotas cleared");
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn print_quotas(quotas: &Quotas) {
+    fn limit(value: Option<u64>, unit: &str) -> String {
+        value.map_or_else(|| "unlimited".to_string(), |v| format!("{v}{unit}"))
+    }
+
+    println!("Max entries:       {}", limit(quotas.max_entries(), ""));
+    println!(
+        "Max value length:  {}",
+        limit(quotas.max_value_len(), " bytes")
+    );
+    println!(
+        "Max store size:    {}",
+        limit(quotas.max_store_size(), " bytes")
+    );
+    println!(
+        "Enforcement:       {}",
+        match quotas.enforcement() {
+            QuotaEnforcement::Warn => "warn",
+            QuotaEnforcement::Error => "error",
+        }
+    );
+}
@@ -1,33 +1,82 @@
 use anyhow::Result;
 use clap::Args;
+use std::path::PathBuf;
 use std::process::ExitCode;
 
 use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::{Argon2Args, resolve_existing_storage};
-use keynest::Keynest;
+use crate::commands::common::{
+    KdfArgs, StrengthArgs, open_keystore, read_or_create_keyfile, resolve_existing_storage,
+};
+use crate::commands::keychain;
 
 #[derive(Args)]
 #[command(after_help = "\
 Examples:
   keynest rekey                                  Change the keystore password
-  keynest rekey --argon-mem 131072              Change password and upgrade memory cost")]
+  keynest rekey --argon-mem 131072              Change password and upgrade memory cost
+  keynest rekey --kdf scrypt                     Change password and keep deriving the key with scrypt
+  keynest rekey --new-keyfile ~/vault.key        Also require a keyfile from now on
+  keynest rekey --keyfile old.key --new-keyfile new.key
+                                                 Replace the keyfile
+  keynest rekey --keyfile ~/vault.key --remove-keyfile
+                                                 Stop requiring the keyfile
+  keynest rekey --use-keychain                   Also update the password kept by `keynest keychain`
+
+The new key is derived with Argon2id unless --kdf scrypt is given.
+A store that requires a keyfile keeps requiring the same one unless --new-keyfile or
+--remove-keyfile is given; a missing --new-keyfile is created with random contents.
+In a store with keyslots (keynest keyslot) only the password of the keyslot you open it
+with changes, and its keyfile cannot be changed.")]
 pub struct RekeyCommand {
     #[command(flatten)]
-    pub argon2: Argon2Args,
+    pub kdf: KdfArgs,
+
+    #[command(flatten)]
+    pub strength: StrengthArgs,
+
+    /// Require this keyfile besides the new password from now on
+    #[arg(long, value_name = "PATH")]
+    pub new_keyfile: Option<PathBuf>,
+
+    /// Stop requiring a keyfile
+    #[arg(long, conflicts_with = "new_keyfile")]
+    pub remove_keyfile: bool,
 }
 
 impl Command for RekeyCommand {
     fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
-        let kdf = self.argon2.to_kdf_params()?;
+        let kdf = self.kdf.to_kdf_params()?;
+        self.strength.apply();
         let storage = resolve_existing_storage(store)?;
+        // The current password is always asked for, even with an agent running.
         let password = auth::read_password()?;
-        let mut kn = Keynest::open_with_storage(password, storage)?;
+        let mut kn = open_keystore(password, storage.clone())?;
 
+        let new_keyfile = self
+            .new_keyfile
+            .as_deref()
+            .map(read_or_create_keyfile)
+            .transpose()?;
         let new_password = auth::read_new_password_with_confirmation()?;
-        kn.rekey(new_password, kdf)?;
-
-        println!("store successfully rekeyed");
+        let keychain_password = auth::uses_keychain().then(|| new_password.clone());
+        if let Some(keyfile) = new_keyfile {
+            kn.rekey_with_keyfile(new_password, kdf, Some(keyfile))?;
+            println!("store successfully rekeyed; it now requires the keyfile");
+        } else if self.remove_keyfile {
+            kn.rekey_with_keyfile(new_password, kdf, None)?;
+            println!("store successfully rekeyed; it no longer requires a keyfile");
+        } else if let Some(name) = kn.opened_keyslot().map(str::to_string) {
+            kn.rekey(new_password, kdf)?;
+            println!("changed the password of keyslot '{name}'");
+        } else {
+            kn.rekey(new_password, kdf)?;
+            println!("store successfully rekeyed");
+        }
+        if let Some(password) = keychain_password {
+            keychain::store_password(storage.path(), &password)?;
+            println!("updated the master password in the OS keychain");
+        }
 
         Ok(ExitCode::SUCCESS)
     }
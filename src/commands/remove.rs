@@ -2,10 +2,8 @@ use anyhow::Result;
 use clap::Args;
 use std::process::ExitCode;
 
-use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::resolve_existing_storage;
-use keynest::Keynest;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
 
 #[derive(Args)]
 #[command(
@@ -21,10 +19,10 @@ pub struct RemoveCommand {
 impl Command for RemoveCommand {
     fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
         let storage = resolve_existing_storage(store)?;
-        let password = auth::read_password()?;
-        let mut kn = Keynest::open_with_storage(password, storage)?;
+        let mut kn = unlock_keystore(storage)?;
         kn.remove(&self.key)?;
         kn.save()?;
+        kn.purge_attachments()?;
         println!("Removed '{}'", self.key);
 
         Ok(ExitCode::SUCCESS)
@@ -0,0 +1,90 @@
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{confirm, resolve_storage};
+use keynest::repair::{self, Health};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest repair                                 Check the store and restore it if it is damaged
+  keynest repair --dry-run                       Only report what would be restored
+  keynest repair --candidate /mnt/usb/keynest.db Also consider a copy on another disk
+
+Candidates are leftover temporary files from interrupted saves (<store>.tmp.*),
+backups next to the store (<store>.bak*), and --candidate paths. Each is fully
+decrypted with the master password; the newest one that decrypts replaces the store.
+The damaged file is kept as <store>.corrupt.<time>.")]
+pub struct RepairCommand {
+    /// Additional copy of the store to consider (e.g. a mirror); may be repeated
+    #[arg(long = "candidate", value_name = "PATH")]
+    pub candidates: Vec<PathBuf>,
+
+    /// Show the candidates and the selection without changing anything
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Do not ask for confirmation
+    #[arg(long, short = 'y')]
+    pub yes: bool,
+}
+
+impl Command for RepairCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_storage(store)?;
+        let keyfile = auth::read_keyfile()?;
+        let password = auth::read_password()?;
+        let plan =
+            repair::plan_with_keyfile(&storage, &password, keyfile.as_ref(), &self.candidates)?;
+        drop(password);
+
+        println!("{}: {}", storage.path().display(), plan.health());
+        if *plan.health() == Health::Healthy {
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if plan.candidates().is_empty() {
+            println!("no temporary files or backups found");
+        }
+        for candidate in plan.candidates() {
+            let status = match candidate.entries() {
+                Some(n) => format!("valid, {n} entries"),
+                None => candidate.error().unwrap_or_default().to_string(),
+            };
+            println!(
+                "  {} ({}): {status}",
+                candidate.path().display(),
+                candidate.source()
+            );
+        }
+
+        let Some(selected) = plan.selected() else {
+            eprintln!("no candidate decrypts with this password; nothing to restore");
+            return Ok(ExitCode::from(1));
+        };
+        println!("newest valid copy: {}", selected.path().display());
+        if *plan.health() == Health::Undecryptable {
+            println!(
+                "(the store parses but does not decrypt with this password, while this copy does)"
+            );
+        }
+
+        if self.dry_run {
+            return Ok(ExitCode::SUCCESS);
+        }
+        if !self.yes && !confirm("Replace the store with this copy?")? {
+            println!("Aborted");
+            return Ok(ExitCode::from(1));
+        }
+
+        if let Some(moved) = repair::promote(&storage, selected)? {
+            println!("damaged store kept as {}", moved.display());
+        }
+        println!("restored {}", storage.path().display());
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use keynest::Storage;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{resolve_existing_storage, unlock_keystore};
+use crate::commands::merge::{Prefer, ask, print_summary};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest resolve                      Merge and remove the conflict copies next to the store
+  keynest resolve --list               Only list them
+  keynest resolve --interactive        Ask for each entry changed in both copies
+  keynest resolve theirs.db --keep     Merge a copy of your choice and keep it
+
+Without files, resolve looks next to the store for the conflict copies of Syncthing
+(<stem>.sync-conflict-*.<ext>) and of Dropbox or Nextcloud (<stem> (... conflicted
+copy ...).<ext>). Each copy is merged like `keynest merge`, the store is saved, and
+then the copies are removed. For a store kept in git, register keynest as merge driver:
+  git config merge.keynest.driver 'keynest --store %A merge %B'
+  echo 'keynest.db merge=keynest' >> .gitattributes")]
+pub struct ResolveCommand {
+    /// Conflict copies to merge (default: those found next to the store)
+    pub copies: Vec<PathBuf>,
+
+    /// Which side of a conflict to keep
+    #[arg(
+        long,
+        value_enum,
+        default_value = "newer",
+        conflicts_with = "interactive"
+    )]
+    pub prefer: Prefer,
+
+    /// Ask which side to keep for each conflict
+    #[arg(long, short = 'i')]
+    pub interactive: bool,
+
+    /// Keep the copies after merging them
+    #[arg(long)]
+    pub keep: bool,
+
+    /// Only list the conflict copies
+    #[arg(long, conflicts_with_all = ["copies", "interactive", "keep"])]
+    pub list: bool,
+}
+
+impl Command for ResolveCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let copies = if self.copies.is_empty() {
+            storage.conflict_copies()?
+        } else {
+            self.copies
+        };
+
+        if self.list {
+            for copy in &copies {
+                println!("{}", copy.display());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        if copies.is_empty() {
+            println!("No conflict copies of {}", storage.path().display());
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let this = std::fs::canonicalize(storage.path())?;
+        for copy in &copies {
+            if !copy.is_file() {
+                bail!("keystore not found: {}", copy.display());
+            }
+            if std::fs::canonicalize(copy)? == this {
+                bail!("cannot merge a store with itself");
+            }
+        }
+
+        let mut kn = unlock_keystore(storage)?;
+        let mut stdin = io::stdin().lock();
+        for copy in &copies {
+            let other = unlock_keystore(Storage::new(copy.clone()))?;
+            let summary = if self.interactive {
+                kn.merge_with(&other, |conflict| ask(conflict, &mut stdin))?
+            } else {
+                kn.merge(&other, self.prefer.into())?
+            };
+            println!("{}:", copy.display());
+            print_summary(&summary);
+        }
+        kn.save()?;
+
+        if !self.keep {
+            for copy in &copies {
+                std::fs::remove_file(copy)
+                    .with_context(|| format!("failed to remove {}", copy.display()))?;
+                println!("removed {}", copy.display());
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
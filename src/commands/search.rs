@@ -0,0 +1,192 @@
+use anyhow::Result;
+use clap::Args;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    print_json, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
+use keynest::{EntryKind, MatchMode, Matcher};
+
+/// Characters of context shown on each side of a match in a value.
+const CONTEXT_CHARS: usize = 12;
+/// Replaces each character of context unless `--reveal` is given.
+const MASK: char = '*';
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest search github                          Find keys containing 'github'
+  keynest search 'prod/*/db_*'                   Find keys matching a glob
+  keynest search --regex '^(dev|test)/.*token$'  Find keys matching a regular expression
+  keynest search --values hunter2                Find entries whose value or note contains 'hunter2'
+  keynest search --values -i 'account no'        Case-insensitive search in values and notes
+  keynest search --values iban --reveal          Show the text around each match
+
+A pattern with '*', '?' or '[' is a glob matching whole keys (or whole lines of a
+value): '*' and '?' stay within one namespace, '**' spans several. Other patterns
+match anywhere in the key, as does --regex.
+
+Matches inside values print the key, the line, and the matched text with the
+surrounding characters masked; --reveal prints the context as stored.
+Exits with status 1 if nothing matches.")]
+pub struct SearchCommand {
+    /// Text or glob to search for, or a regular expression with --regex
+    pub pattern: String,
+
+    /// Interpret the pattern as a regular expression
+    #[arg(long, short = 'r')]
+    pub regex: bool,
+
+    /// Also search inside values and notes (decrypts every entry)
+    #[arg(long)]
+    pub values: bool,
+
+    /// Show the text around matches in values instead of masking it
+    #[arg(long, requires = "values")]
+    pub reveal: bool,
+
+    /// Ignore case when matching
+    #[arg(long, short = 'i')]
+    pub ignore_case: bool,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+struct Match {
+    key: String,
+    location: &'static str,
+    line: Option<usize>,
+    context: Option<Zeroizing<String>>,
+}
+
+impl Command for SearchCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let matcher = if self.regex {
+            Matcher::new(&self.pattern, MatchMode::Regex, self.ignore_case)?
+        } else {
+            Matcher::auto(&self.pattern, self.ignore_case)?
+        }
+        .with_values(self.values);
+
+        let storage = resolve_existing_storage(store)?;
+        let mut matches = Vec::new();
+
+        if self.values {
+            let kn = unlock_keystore(storage)?;
+            for entry in kn.find(&matcher)? {
+                if matcher.is_match(entry.key()) {
+                    matches.push(Match {
+                        key: entry.key().to_string(),
+                        location: "key",
+                        line: None,
+                        context: None,
+                    });
+                }
+                // A match would reveal part of the value, so restricted ones are skipped.
+                if !entry.policy().is_unrestricted() {
+                    continue;
+                }
+                let multiline = entry.value().contains('\n');
+                for (n, line) in entry.value().lines().enumerate() {
+                    if let Some(range) = matcher.find(line) {
+                        matches.push(Match {
+                            key: entry.key().to_string(),
+                            location: if entry.kind() == EntryKind::Note {
+                                "note"
+                            } else {
+                                "value"
+                            },
+                            line: multiline.then_some(n + 1),
+                            context: Some(self.context(line, range)),
+                        });
+                    }
+                }
+            }
+        } else {
+            // Keys only: the index is enough, no section needs to be decrypted.
+            let kn = unlock_indexed(storage)?;
+            for key in kn.list() {
+                if matcher.is_match(key) {
+                    matches.push(Match {
+                        key: key.clone(),
+                        location: "key",
+                        line: None,
+                        context: None,
+                    });
+                }
+            }
+        }
+
+        if self.json {
+            let matches: Vec<_> = matches
+                .iter()
+                .map(|m| {
+                    let mut json = serde_json::json!({"key": m.key, "in": m.location});
+                    if let Some(line) = m.line {
+                        json["line"] = line.into();
+                    }
+                    if let Some(context) = &m.context {
+                        json["context"] = context.as_str().into();
+                    }
+                    json
+                })
+                .collect();
+            print_json(&matches)?;
+        } else {
+            for m in &matches {
+                match (&m.context, m.line) {
+                    (Some(context), Some(line)) => {
+                        println!("{}  {} line {line}: {}", m.key, m.location, **context);
+                    }
+                    (Some(context), None) => println!("{}  {}: {}", m.key, m.location, **context),
+                    _ => println!("{}", m.key),
+                }
+            }
+        }
+
+        Ok(if matches.is_empty() {
+            ExitCode::from(1)
+        } else {
+            ExitCode::SUCCESS
+        })
+    }
+}
+
+impl SearchCommand {
+    /// Returns the match at `range` in `line` with up to [`CONTEXT_CHARS`] characters of
+    /// context on each side, masked unless `--reveal` is given.
+    fn context(&self, line: &str, range: Range<usize>) -> Zeroizing<String> {
+        let Range { start, end } = range;
+        let from = line[..start]
+            .char_indices()
+            .rev()
+            .nth(CONTEXT_CHARS - 1)
+            .map_or(0, |(i, _)| i);
+        let to = line[end..]
+            .char_indices()
+            .nth(CONTEXT_CHARS)
+            .map_or(line.len(), |(i, _)| end + i);
+
+        let mut out = Zeroizing::new(String::new());
+        if from > 0 {
+            out.push('…');
+        }
+        out.extend(line[from..start].chars().map(|c| self.mask(c)));
+        out.push_str(&line[start..end]);
+        out.extend(line[end..to].chars().map(|c| self.mask(c)));
+        if to < line.len() {
+            out.push('…');
+        }
+        out
+    }
+
+    fn mask(&self, c: char) -> char {
+        if self.reveal { c } else { MASK }
+    }
+}
@@ -0,0 +1,116 @@
+//! Private, memory-backed directory for handing secrets to a child process as files.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::commands::common::create_file_secure;
+
+/// A private directory holding secret files for the lifetime of a child process.
+///
+/// The directory is created owner-only (0700) under a tmpfs where one is available, so
+/// the files never reach a disk. On drop every file is overwritten with zeros before the
+/// directory is removed.
+pub struct SecretDir {
+    path: PathBuf,
+}
+
+impl SecretDir {
+    /// Creates a fresh directory with a random name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn create() -> Result<Self> {
+        let (base, memory_backed) = base_dir();
+        if !memory_backed {
+            eprintln!(
+                "Warning: no memory-backed directory available; secret files are written to {}",
+                base.display()
+            );
+        }
+
+        let mut suffix = [0u8; 8];
+        getrandom::fill(&mut suffix)
+            .map_err(|_| anyhow::anyhow!("OS random generator unavailable"))?;
+        let name: String = suffix.iter().map(|b| format!("{b:02x}")).collect();
+        let path = base.join(format!("keynest-{name}"));
+
+        create_private_dir(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Self { path })
+    }
+
+    /// Writes `value` to a file named `name` and returns its path.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn write(&self, name: &str, value: &[u8]) -> Result<PathBuf> {
+        let path = self.path.join(name);
+        create_file_secure(&path)?.write_all(value)?;
+        Ok(path)
+    }
+}
+
+impl Drop for SecretDir {
+    fn drop(&mut self) {
+        if let Ok(entries) = fs::read_dir(&self.path) {
+            for entry in entries.flatten() {
+                shred(&entry.path());
+            }
+        }
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            eprintln!(
+                "Warning: failed to remove secret directory {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Overwrites a regular file with zeros and flushes it to storage before it is removed.
+fn shred(path: &Path) {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return;
+    };
+    if !meta.is_file() {
+        return;
+    }
+    if let Ok(mut file) = fs::OpenOptions::new().write(true).open(path) {
+        let zeros = vec![0u8; meta.len() as usize];
+        let _ = file.write_all(&zeros).and_then(|()| file.sync_all());
+    }
+}
+
+/// Picks the directory to create secret directories in, preferring per-user and shared
+/// tmpfs mounts. Returns whether the directory is known to be memory-backed.
+fn base_dir() -> (PathBuf, bool) {
+    #[cfg(target_os = "linux")]
+    {
+        let candidates = [
+            std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from),
+            Some(PathBuf::from("/dev/shm")),
+        ];
+        for dir in candidates.into_iter().flatten() {
+            if dir.is_dir() {
+                return (dir, true);
+            }
+        }
+    }
+
+    (std::env::temp_dir(), false)
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    fs::create_dir(path)
+}
@@ -0,0 +1,187 @@
+//! Service mode: `keynest agent --service` keeps running as keynest's service layer, so
+//! frontends (a GUI, a status bar, editor plugins) can be built on the agent protocol
+//! without linking Rust.
+//!
+//! On top of the `key`, `status` and `stop` operations of every agent, a service serves
+//! `lock`/`unlock`, paginated `list`, `get`, `metrics` and `subscribe`. After
+//! `--timeout` without use it forgets the key but keeps running, locked, until a client
+//! unlocks it again with the master password. Entries with an access policy are refused
+//! like in `keynest api`, since the service cannot ask on a terminal.
+//!
+//! `subscribe` keeps its connection open: after the response, the service writes one
+//! JSON line per event, `{"version": 2, "event": "changed" | "locked" | "unlocked"}`,
+//! until the client disconnects. `changed` means the keystore file or its journal was
+//! written, by this or any other process. The connection closes when the service stops.
+
+use anyhow::{Result, bail};
+use chrono::SecondsFormat;
+use keynest::{Keynest, StoreError, UnlockKey, WrongPassword};
+use serde_json::{Value, json};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::commands::common::{reader_name, track_chain};
+
+/// Number of entries `list` returns when the request does not say.
+pub const DEFAULT_PAGE: usize = 100;
+
+/// Largest page `list` returns.
+pub const MAX_PAGE: usize = 1000;
+
+/// How often subscriptions look for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An entry that has an access policy, which the service cannot honour.
+#[derive(Debug)]
+pub struct Restricted(pub String);
+
+impl std::fmt::Display for Restricted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' has an access policy and cannot be read through the agent",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for Restricted {}
+
+/// Returns the error code of the agent protocol for `e`.
+pub fn error_code(e: &anyhow::Error) -> &'static str {
+    if e.is::<WrongPassword>() {
+        "wrong_password"
+    } else if e.is::<Restricted>() {
+        "restricted"
+    } else if let Some(StoreError::KeyNotFound(_)) = e.downcast_ref() {
+        "not_found"
+    } else {
+        "failed"
+    }
+}
+
+/// Opens the keystore at `store` with the key held by the agent.
+///
+/// # Errors
+///
+/// Returns an error if the key no longer opens the keystore (e.g. after a rekey).
+pub fn open(store: &Path, key: &UnlockKey) -> Result<Keynest> {
+    let mut kn = Keynest::open_with_key(key, keynest::Storage::new(store.to_path_buf()))?;
+    kn.set_reader(&reader_name());
+    track_chain(&mut kn, store);
+    Ok(kn)
+}
+
+/// Returns up to `limit` entries (without values) in key order, starting after the key
+/// `cursor`, and the cursor of the next page, or `null` on the last page.
+///
+/// # Errors
+///
+/// Returns an error if the entries cannot be decrypted.
+pub fn list(
+    kn: &Keynest,
+    prefix: Option<&str>,
+    cursor: Option<&str>,
+    limit: usize,
+) -> Result<Value> {
+    let limit = limit.clamp(1, MAX_PAGE);
+    let mut entries = kn
+        .list_all()?
+        .into_iter()
+        .filter(|e| prefix.is_none_or(|p| e.key().starts_with(p)))
+        .filter(|e| cursor.is_none_or(|c| e.key() > c))
+        .peekable();
+    let page: Vec<_> = entries.by_ref().take(limit).collect();
+    let next = match (page.last(), entries.peek()) {
+        (Some(last), Some(_)) => Some(last.key()),
+        _ => None,
+    };
+    let page: Vec<_> = page
+        .iter()
+        .map(|e| {
+            json!({
+                "key": e.key(),
+                "kind": e.kind().to_string(),
+                "updated": e.updated(),
+                "tags": e.tags(),
+                "expires": e.expires().map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            })
+        })
+        .collect();
+    Ok(json!({"entries": page, "next": next}))
+}
+
+/// Reads entry `key`, following references if `resolve` is set, and records the read
+/// if read receipts are on.
+///
+/// # Errors
+///
+/// Returns an error if the entry does not exist or has an access policy.
+pub fn get(kn: &mut Keynest, key: &str, resolve: bool) -> Result<Value> {
+    if kn
+        .effective_policy(key)?
+        .is_some_and(|policy| !policy.is_unrestricted())
+    {
+        bail!(Restricted(key.to_string()));
+    }
+    let value = if resolve {
+        kn.resolve(key)?
+    } else {
+        kn.get(key)?
+    };
+    let Some(value) = value else {
+        bail!(StoreError::KeyNotFound(key.to_string()));
+    };
+    let result = json!({
+        "key": key,
+        "value": value,
+        "kind": kn.kind(key)?.map(|k| k.to_string()),
+    });
+    if kn.records_reads()? {
+        kn.record_get(key)?;
+        kn.save_usage()?;
+    }
+    Ok(result)
+}
+
+/// Writes an event line to `stream` whenever the keystore at `store` changes on disk or
+/// `locked` changes, until the client disconnects.
+pub fn subscribe(mut stream: impl Write, store: PathBuf, version: u64, locked: impl Fn() -> bool) {
+    let mut written = fingerprint(&store);
+    let mut was_locked = locked();
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let mut events = Vec::new();
+        let now = fingerprint(&store);
+        if now != written {
+            written = now;
+            events.push("changed");
+        }
+        let is_locked = locked();
+        if is_locked != was_locked {
+            was_locked = is_locked;
+            events.push(if is_locked { "locked" } else { "unlocked" });
+        }
+        for event in events {
+            let line = json!({"version": version, "event": event});
+            if writeln!(stream, "{line}")
+                .and_then(|()| stream.flush())
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Length and modification time of the keystore file and its journal.
+type Fingerprint = [Option<(u64, SystemTime)>; 2];
+
+fn fingerprint(store: &Path) -> Fingerprint {
+    let journal = keynest::Storage::new(store.to_path_buf()).journal_path();
+    [store, journal.as_path()].map(|path| {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((metadata.len(), metadata.modified().ok()?))
+    })
+}
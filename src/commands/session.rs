@@ -0,0 +1,261 @@
+//! Quick-unlock sessions: `keynest agent --session` persists the key of a keystore so
+//! new shells can open it without the master password or the KDF until the session
+//! expires, even after the agent is gone.
+//!
+//! The key is wrapped (XChaCha20-Poly1305) with a random session key, with the canonical
+//! store path and the expiry as AAD, and written to an owner-only file in the per-user
+//! runtime directory, which the OS clears on logout or reboot:
+//!
+//! `{"version": 1, "store": "/path/keynest.db", "expires": 1767225600, "nonce": "<base64>", "key": "<base64>", "session_key": "<base64>"}`
+//!
+//! With `--session-store keychain` the session key is kept in the OS credential store
+//! (service `keynest-session:<store path>`, account [`ACCOUNT`]) instead of the file, so
+//! neither the file nor the credential alone unlocks the store. Sessions are opt-in and
+//! end at their expiry, on `keynest agent --end-session`, or when the store is rekeyed
+//! (the wrapped key no longer opens it).
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use clap::ValueEnum;
+use keynest::{Storage, UnlockKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::commands::agent;
+use crate::commands::common::write_file_secure;
+use crate::commands::os_keychain;
+
+/// Version of the session file.
+const VERSION: u64 = 1;
+
+/// Account of the credential holding the session key.
+pub const ACCOUNT: &str = "session-key";
+
+/// Where the session key is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SessionStore {
+    /// In the session file, next to the wrapped key
+    File,
+    /// In the OS credential store
+    Keychain,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    version: u64,
+    store: PathBuf,
+    expires: u64,
+    nonce: String,
+    key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session_key: Option<String>,
+}
+
+/// Persists `key` as the session of the keystore at `store` (a canonical path) for
+/// `lifetime`. Returns the path of the session file.
+///
+/// # Errors
+///
+/// Returns an error if the file or the credential cannot be written.
+pub fn start(
+    store: &Path,
+    key: &UnlockKey,
+    lifetime: Duration,
+    session_store: SessionStore,
+) -> Result<PathBuf> {
+    let mut session_key = Zeroizing::new([0u8; 32]);
+    let mut nonce = [0u8; 24];
+    getrandom::fill(&mut *session_key)
+        .and_then(|()| getrandom::fill(&mut nonce))
+        .map_err(|_| anyhow::anyhow!("OS random generator unavailable"))?;
+    let expires = now() + lifetime.as_secs();
+
+    let wrapped = XChaCha20Poly1305::new(session_key.as_ref().into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: key.as_bytes(),
+                aad: &aad(store, expires),
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("failed to wrap the session key"))?;
+
+    let encoded_key = Zeroizing::new(STANDARD.encode(*session_key));
+    let session_key = match session_store {
+        SessionStore::File => Some(encoded_key.to_string()),
+        SessionStore::Keychain => {
+            os_keychain::store(&service(store), ACCOUNT, &encoded_key)?;
+            None
+        }
+    };
+    let mut session = SessionFile {
+        version: VERSION,
+        store: store.to_path_buf(),
+        expires,
+        nonce: STANDARD.encode(nonce),
+        key: STANDARD.encode(wrapped),
+        session_key,
+    };
+    let file = Zeroizing::new(serde_json::to_string(&session)?);
+    session.session_key.zeroize();
+
+    let path = path(store)?;
+    if let Some(dir) = path.parent() {
+        create_private_dir(dir)?;
+    }
+    write_file_secure(&path, file.as_bytes())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Returns the key of the session of `storage`, if there is one that has not expired.
+/// An expired session is removed.
+pub fn key_for(storage: &Storage) -> Option<UnlockKey> {
+    let store = std::fs::canonicalize(storage.path()).ok()?;
+    let path = path(&store).ok()?;
+    if !path.exists() {
+        return None;
+    }
+    let result = unwrap(&store, &path);
+    if let Err(e) = &result {
+        eprintln!(
+            "Warning: cannot use the session of {}: {e:#}",
+            store.display()
+        );
+    }
+    result.ok().flatten()
+}
+
+fn unwrap(store: &Path, path: &Path) -> Result<Option<UnlockKey>> {
+    check_private(path)?;
+    let session = read(path)?;
+    if session.version != VERSION || session.store != store {
+        bail!("the session file does not belong to this keystore");
+    }
+    if session.expires <= now() {
+        end(store)?;
+        return Ok(None);
+    }
+
+    let session_key = match session.session_key {
+        Some(session_key) => Zeroizing::new(session_key),
+        None => os_keychain::lookup(&service(store), ACCOUNT)?
+            .context("the session key is missing from the OS keychain")?,
+    };
+    let session_key = Zeroizing::new(STANDARD.decode(session_key.as_bytes())?);
+    let nonce = STANDARD.decode(&session.nonce)?;
+    let wrapped = STANDARD.decode(&session.key)?;
+    if session_key.len() != 32 || nonce.len() != 24 {
+        bail!("malformed session file");
+    }
+    let key = Zeroizing::new(
+        XChaCha20Poly1305::new(session_key.as_slice().into())
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &wrapped,
+                    aad: &aad(store, session.expires),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("the session does not authenticate"))?,
+    );
+    let bytes: [u8; 32] = key
+        .as_slice()
+        .try_into()
+        .context("malformed session file")?;
+    Ok(Some(UnlockKey::from_bytes(bytes)))
+}
+
+/// Returns how long the session of `store` (a canonical path) has left, if there is
+/// one that has not expired.
+pub fn remaining(store: &Path) -> Option<Duration> {
+    let session = read(&path(store).ok()?).ok()?;
+    session
+        .expires
+        .checked_sub(now())
+        .filter(|&left| left > 0)
+        .map(Duration::from_secs)
+}
+
+fn read(path: &Path) -> Result<SessionFile> {
+    let data = Zeroizing::new(std::fs::read_to_string(path)?);
+    serde_json::from_str(&data).context("malformed session file")
+}
+
+/// Removes the session of `store` (a canonical path). Returns `true` if there was one.
+///
+/// # Errors
+///
+/// Returns an error if the file or the credential cannot be removed.
+pub fn end(store: &Path) -> Result<bool> {
+    let path = path(store)?;
+    let in_keychain = match read(&path) {
+        Ok(session) => session.session_key.is_none(),
+        Err(_) if !path.exists() => return Ok(false),
+        Err(_) => false,
+    };
+    std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    if in_keychain {
+        os_keychain::delete(&service(store), ACCOUNT)?;
+    }
+    Ok(true)
+}
+
+/// Returns the session file of `store`: named after its path, in the runtime directory
+/// of the agent.
+fn path(store: &Path) -> Result<PathBuf> {
+    let digest = Sha256::digest(store.as_os_str().as_encoded_bytes());
+    let name: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    Ok(agent::runtime_dir()?.join(format!("session-{name}.json")))
+}
+
+fn service(store: &Path) -> String {
+    format!("keynest-session:{}", store.display())
+}
+
+fn aad(store: &Path, expires: u64) -> Vec<u8> {
+    let mut aad = b"keynest session v1".to_vec();
+    aad.extend_from_slice(&expires.to_le_bytes());
+    aad.extend_from_slice(store.as_os_str().as_encoded_bytes());
+    aad
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Refuses session files that other users could have read or written.
+fn check_private(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let metadata = std::fs::metadata(path)?;
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        if metadata.uid() != unsafe { libc::geteuid() }
+            || metadata.permissions().mode() & 0o077 != 0
+        {
+            bail!("{} is not private to this user", path.display());
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
@@ -1,12 +1,17 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use clap::Args;
 use std::path::PathBuf;
 use std::process::ExitCode;
+use zeroize::Zeroizing;
 
 use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::resolve_existing_storage;
-use keynest::Keynest;
+use crate::commands::common::{
+    Restriction, parse_expiry, parse_field, read_fields, resolve_existing_storage,
+    strip_trailing_newline, unlock_keystore, with_restrictions,
+};
+use keynest::AccessPolicy;
 
 #[derive(Args)]
 #[command(
@@ -15,7 +20,19 @@ use keynest::Keynest;
 Examples:
   keynest set api_key \"secret123\"              Store a secret from command line argument
   keynest set api_key --file secret.txt         Store a secret from a file
-  keynest set api_key --prompt                   Store a secret from interactive prompt"
+  keynest set api_key --prompt                   Store a secret from interactive prompt
+  keynest set api_key - < secret.txt              Store a secret read from stdin
+  keynest set recovery/bank --note --file recovery.md
+                                                 Store a markdown file as a note
+  keynest set stripe/key \"sk_...\" --tag billing,prod
+                                                 Store a secret with tags
+  keynest set api_key \"secret123\" --expires 90d Store a secret that is due for rotation in 90 days
+  keynest set github --field user=alice --field password --field url=https://github.com
+                                                 Store a login as fields (prompts for the password)
+  keynest set prod/db --prompt --restrict no-print,confirm
+                                                 Never print the secret; confirm each access
+  keynest --password-fd 3 set api_key --value-fd 4 3<pw.txt 4<secret.txt
+                                                 Read password and secret from separate fds"
 )]
 pub struct SetCommand {
     pub key: String,
@@ -28,6 +45,36 @@ pub struct SetCommand {
     /// Read secret from file
     #[arg(long = "file")]
     pub file: Option<PathBuf>,
+
+    /// Read secret from this file descriptor
+    #[arg(long = "value-fd", value_name = "FD", conflicts_with_all = ["value", "prompt", "file"])]
+    pub value_fd: Option<u32>,
+
+    /// Store the value as a note (free-form markdown text) instead of a secret
+    #[arg(long)]
+    pub note: bool,
+
+    /// Tag the entry; comma-separated or repeated
+    #[arg(long = "tag", value_name = "TAG", value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    /// Mark the secret as expired after this time: 90d, 12h, 2w, 30m, a date or RFC 3339
+    #[arg(long, value_name = "WHEN", value_parser = parse_expiry)]
+    pub expires: Option<DateTime<Utc>>,
+
+    /// Store a named field (NAME=VALUE, or NAME to prompt for the value); repeatable.
+    /// With fields, the value itself is optional
+    #[arg(long = "field", value_name = "NAME=VALUE", value_parser = parse_field)]
+    pub fields: Vec<(String, Option<String>)>,
+
+    /// Restrict access to the value: no-print, confirm; comma-separated or repeated
+    #[arg(
+        long = "restrict",
+        value_name = "RULE",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub restrict: Vec<Restriction>,
 }
 
 impl Command for SetCommand {
@@ -39,34 +86,64 @@ impl Command for SetCommand {
         // Fail fast if the keystore is missing before prompting for the secret/password.
         let storage = resolve_existing_storage(store)?;
 
+        let from_stdin = self.value.as_deref() == Some("-");
+        let value_fd = self.value_fd.or(from_stdin.then_some(0));
+        if value_fd.is_some() && value_fd == auth::password_fd() {
+            anyhow::bail!(
+                "the secret value and the password must come from different file descriptors"
+            );
+        }
+
         let secret = if self.prompt {
-            rpassword::prompt_password("Secret: ")?
+            Zeroizing::new(rpassword::prompt_password("Secret: ")?)
         } else if let Some(path) = self.file {
-            let mut content = std::fs::read_to_string(&path)?;
-            // Strip a single trailing newline that editors commonly append,
-            // so the stored secret does not carry a stray "\n" (handles "\n" and "\r\n").
-            if content.ends_with('\n') {
-                content.pop();
-                if content.ends_with('\r') {
-                    content.pop();
-                }
-            }
-            content
+            strip_trailing_newline(Zeroizing::new(std::fs::read_to_string(&path)?))
+        } else if let Some(fd) = self.value_fd {
+            strip_trailing_newline(auth::read_fd_to_string(fd)?)
+        } else if from_stdin {
+            strip_trailing_newline(auth::read_stdin_to_string()?)
+        } else if self.value.is_none() && !self.fields.is_empty() {
+            Zeroizing::new(String::new())
         } else {
-            self.value.ok_or_else(|| {
-                anyhow::anyhow!("secret value required: provide as argument, --prompt, or --file")
-            })?
+            Zeroizing::new(self.value.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "secret value required: provide as argument, -, --prompt, --file, --value-fd, or --field"
+                )
+            })?)
         };
 
-        if secret.trim().is_empty() {
+        if secret.trim().is_empty() && self.fields.is_empty() {
             anyhow::bail!("secret value cannot be empty");
         }
+        let fields = read_fields(self.fields)?;
 
-        let password = auth::read_password()?;
-        let mut kn = Keynest::open_with_storage(password, storage)?;
-        kn.set(&self.key, &secret)?;
+        let mut kn = unlock_keystore(storage)?;
+        if self.note {
+            kn.set_note(&self.key, &secret)?;
+        } else {
+            kn.set(&self.key, &secret)?;
+        }
+        for tag in &self.tags {
+            kn.add_tag(&self.key, tag)?;
+        }
+        for (name, value) in &fields {
+            kn.set_field(&self.key, name, value)?;
+        }
+        if self.expires.is_some() {
+            kn.set_expiry(&self.key, self.expires)?;
+        }
+        if !self.restrict.is_empty() {
+            kn.set_policy(
+                &self.key,
+                with_restrictions(AccessPolicy::default(), &self.restrict, true),
+            )?;
+        }
         kn.save()?;
-        println!("stored secret '{}'", self.key);
+        println!(
+            "stored {} '{}'",
+            if self.note { "note" } else { "secret" },
+            self.key
+        );
 
         Ok(ExitCode::SUCCESS)
     }
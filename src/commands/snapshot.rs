@@ -0,0 +1,96 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use super::super::auth;
+use crate::commands::Command;
+use crate::commands::common::{
+    KdfArgs, StrengthArgs, passphrase_from_env, resolve_existing_storage, unlock_keystore,
+};
+use keynest::Storage;
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "\
+Examples:
+  keynest snapshot --keys db/url,api_token --out ci.db                Copy two secrets into ci.db
+  keynest snapshot --keys deploy/ --out ci.db                         Copy the deploy namespace
+  keynest snapshot --keys deploy/ --out ci.db --passphrase-env CI_PW  Take the passphrase from $CI_PW
+
+The snapshot is a separate store holding only the selected entries, encrypted under its
+own passphrase (prompted for, or read from --passphrase-env). References are resolved.
+It is read-only: keynest refuses to change it; create a new snapshot instead.
+In the pipeline: KEYNEST_PASSWORD=$CI_PW keynest --store ci.db get db/url"
+)]
+pub struct SnapshotCommand {
+    /// Keys to include, comma-separated or repeated; `prefix/` selects a namespace
+    #[arg(long, short = 'k', value_delimiter = ',', required = true)]
+    pub keys: Vec<String>,
+
+    /// Path of the snapshot to create
+    #[arg(long, short = 'o', value_name = "PATH")]
+    pub out: PathBuf,
+
+    /// Read the snapshot passphrase from this environment variable
+    #[arg(long = "passphrase-env", value_name = "VAR")]
+    pub passphrase_env: Option<String>,
+
+    /// Replace an existing file at the output path
+    #[arg(long, short = 'f')]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub kdf: KdfArgs,
+
+    #[command(flatten)]
+    pub strength: StrengthArgs,
+}
+
+impl Command for SnapshotCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let kdf = self.kdf.to_kdf_params()?;
+        self.strength.apply();
+        let storage = resolve_existing_storage(store)?;
+        if self.out.exists() {
+            if !self.force {
+                bail!(
+                    "{} already exists; use --force to replace it",
+                    self.out.display()
+                );
+            }
+            if std::fs::canonicalize(&self.out)? == std::fs::canonicalize(storage.path())? {
+                bail!("the snapshot cannot replace the store it is taken from");
+            }
+        }
+
+        let kn = unlock_keystore(storage)?;
+
+        let passphrase = match &self.passphrase_env {
+            Some(var) => passphrase_from_env(var)?,
+            None => auth::read_new_password_with_confirmation()?,
+        };
+
+        // Replace an existing file only once the new snapshot is complete.
+        let target = if self.out.exists() {
+            let mut name = self.out.file_name().unwrap_or_default().to_os_string();
+            name.push(".new");
+            self.out.with_file_name(name)
+        } else {
+            self.out.clone()
+        };
+        let snapshot = kn.snapshot(&self.keys, passphrase, Storage::new(target.clone()), kdf)?;
+        if target != self.out {
+            std::fs::rename(&target, &self.out)
+                .with_context(|| format!("failed to replace {}", self.out.display()))?;
+        }
+
+        println!(
+            "Wrote read-only snapshot with {} secret(s) to {}",
+            snapshot.list().len(),
+            self.out.display()
+        );
+        Ok(ExitCode::SUCCESS)
+    }
+}
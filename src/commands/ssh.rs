@@ -0,0 +1,191 @@
+use anyhow::{Context, Result, bail};
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commands::Command;
+use crate::commands::common::{
+    check_access, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
+use keynest::{CertKind, CertificateRequest, SshCa};
+
+/// Certificates are backdated by this many seconds to tolerate clock skew.
+const BACKDATE_SECS: u64 = 5 * 60;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest ssh ca init ssh/ca                     Generate an Ed25519 CA key and print its public key
+  keynest ssh ca pubkey ssh/ca >> /etc/ssh/trusted_user_ca_keys
+  keynest ssh ca pubkey ssh/ca --known-hosts '*.lan' >> ~/.ssh/known_hosts
+  keynest ssh ca sign ssh/ca --pubkey /etc/ssh/ssh_host_ed25519_key.pub --host \\
+      --identity nas --principals nas.lan,10.0.0.2 --out /etc/ssh/ssh_host_ed25519_key-cert.pub
+  keynest ssh ca sign ssh/ca --pubkey ~/.ssh/id_ed25519.pub --identity alice \\
+      --principals alice --days 30 --save ssh/certs/alice
+
+SSH CA entries are secrets whose value is an unencrypted OpenSSH Ed25519 private key;
+an existing key can be stored with: keynest set ssh/ca --file ca_key")]
+pub struct SshCommand {
+    #[command(subcommand)]
+    pub action: SshAction,
+}
+
+#[derive(Subcommand)]
+pub enum SshAction {
+    /// Manage SSH certificate authority keys
+    Ca {
+        #[command(subcommand)]
+        action: CaAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CaAction {
+    /// Generate a new Ed25519 CA key and store it under KEY
+    Init {
+        key: String,
+
+        /// Comment for the public key
+        #[arg(long, default_value = "keynest-ca")]
+        comment: String,
+    },
+    /// Print the CA public key
+    Pubkey {
+        key: String,
+
+        /// Print a known_hosts `@cert-authority` line for hosts matching PATTERN
+        #[arg(long = "known-hosts", value_name = "PATTERN")]
+        known_hosts: Option<String>,
+    },
+    /// Sign a host or user public key with the CA stored under KEY
+    Sign {
+        key: String,
+
+        /// Public key to sign (.pub file)
+        #[arg(long, value_name = "FILE")]
+        pubkey: PathBuf,
+
+        /// Key identifier recorded in the certificate and in server logs
+        #[arg(long, value_name = "ID")]
+        identity: String,
+
+        /// Comma-separated user names (or host names with --host)
+        #[arg(long, value_name = "NAMES", value_delimiter = ',', required = true)]
+        principals: Vec<String>,
+
+        /// Issue a host certificate instead of a user certificate
+        #[arg(long)]
+        host: bool,
+
+        /// Validity in days, starting now
+        #[arg(long, default_value_t = 365, value_parser = clap::value_parser!(u64).range(1..))]
+        days: u64,
+
+        /// Serial number
+        #[arg(long, default_value_t = 0)]
+        serial: u64,
+
+        /// Write the certificate to FILE instead of stdout
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+
+        /// Also store the certificate as the secret ENTRY
+        #[arg(long, value_name = "ENTRY")]
+        save: Option<String>,
+    },
+}
+
+impl Command for SshCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let SshAction::Ca { action } = self.action;
+        let storage = resolve_existing_storage(store)?;
+
+        match action {
+            CaAction::Init { key, comment } => {
+                let mut kn = unlock_keystore(storage)?;
+                let ca = SshCa::generate(&comment)?;
+                kn.set(&key, &ca.to_openssh()?)?;
+                kn.save()?;
+                println!("created SSH CA '{key}' ({})", ca.fingerprint());
+                println!("{}", ca.public_key());
+            }
+            CaAction::Pubkey { key, known_hosts } => {
+                let mut kn = unlock_indexed(storage)?;
+                let Some(value) = kn.resolve(&key)? else {
+                    eprintln!("key not found: {key}");
+                    return Ok(ExitCode::from(1));
+                };
+                let ca = load_ca(&key, value)?;
+                match known_hosts {
+                    Some(pattern) => println!("@cert-authority {pattern} {}", ca.public_key()),
+                    None => println!("{}", ca.public_key()),
+                }
+            }
+            CaAction::Sign {
+                key,
+                pubkey,
+                identity,
+                principals,
+                host,
+                days,
+                serial,
+                out,
+                save,
+            } => {
+                let public_key = std::fs::read_to_string(&pubkey)
+                    .with_context(|| format!("failed to read {}", pubkey.display()))?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .context("system clock is before 1970")?
+                    .as_secs();
+                let kind = if host { CertKind::Host } else { CertKind::User };
+                let request = CertificateRequest::new(
+                    kind,
+                    &identity,
+                    principals,
+                    now.saturating_sub(BACKDATE_SECS),
+                    now.saturating_add(days.saturating_mul(24 * 60 * 60)),
+                )
+                .with_serial(serial);
+
+                let mut kn = unlock_keystore(storage)?;
+                check_access(&key, kn.effective_policy(&key)?, false)?;
+                let Some(value) = kn.resolve(&key)? else {
+                    eprintln!("key not found: {key}");
+                    return Ok(ExitCode::from(1));
+                };
+                let certificate = load_ca(&key, value)?.sign(&public_key, &request)?;
+
+                if let Some(entry) = &save {
+                    kn.set(entry, &certificate)?;
+                    kn.save()?;
+                }
+                match &out {
+                    Some(path) => {
+                        std::fs::write(path, format!("{certificate}\n"))
+                            .with_context(|| format!("failed to write {}", path.display()))?;
+                        println!(
+                            "{kind} certificate '{identity}' written to {}",
+                            path.display()
+                        );
+                    }
+                    None if save.is_none() => println!("{certificate}"),
+                    None => {}
+                }
+                if let Some(entry) = save {
+                    println!("stored {kind} certificate '{identity}' as '{entry}'");
+                }
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn load_ca(key: &str, value: &str) -> Result<SshCa> {
+    if !SshCa::is_private_key(value) {
+        bail!("'{key}' is not an SSH CA entry (its value is not an OpenSSH private key)");
+    }
+    SshCa::from_openssh(value).with_context(|| format!("invalid SSH CA entry '{key}'"))
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{print_json, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest stats enable                           Start counting unlocks, saves and reads
+  keynest stats                                  Show the counters, most used entries first
+  keynest stats --unused                         List entries never read since tracking began
+  keynest stats reset                            Start counting from zero
+  keynest stats disable                          Stop counting and delete the counters
+
+Counters are stored inside the encrypted store and never leave it. Reads are counted
+by 'get' and 'exec'/'run'; while tracking is on, these commands also write the store.")]
+pub struct StatsCommand {
+    #[command(subcommand)]
+    pub action: Option<StatsAction>,
+
+    /// Only list entries that were never read
+    #[arg(long)]
+    pub unused: bool,
+
+    /// Output as JSON
+    #[arg(long, short = 'j')]
+    pub json: bool,
+}
+
+#[derive(Subcommand)]
+pub enum StatsAction {
+    /// Start tracking usage
+    Enable,
+    /// Stop tracking usage and delete the counters
+    Disable,
+    /// Reset the counters to zero
+    Reset,
+}
+
+impl Command for StatsCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        match self.action {
+            Some(StatsAction::Enable) => {
+                kn.set_usage_tracking(true)?;
+                kn.save_usage()?;
+                println!("usage tracking enabled");
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(StatsAction::Disable) => {
+                kn.set_usage_tracking(false)?;
+                kn.save()?;
+                println!("usage tracking disabled");
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(StatsAction::Reset) => {
+                kn.reset_usage()?;
+                kn.save_usage()?;
+                println!("usage counters reset");
+                return Ok(ExitCode::SUCCESS);
+            }
+            None => {}
+        }
+
+        let Some(usage) = kn.usage()? else {
+            eprintln!("usage tracking is off (enable it with: keynest stats enable)");
+            return Ok(ExitCode::from(1));
+        };
+
+        let mut rows: Vec<(&str, u64, Option<&str>)> = kn
+            .list()
+            .into_iter()
+            .map(|key| match usage.entry(key) {
+                Some(e) => (key.as_str(), e.gets(), Some(e.last_used())),
+                None => (key.as_str(), 0, None),
+            })
+            .filter(|(_, gets, _)| !self.unused || *gets == 0)
+            .collect();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        if self.json {
+            let entries: Vec<_> = rows
+                .iter()
+                .map(|(key, gets, last_used)| {
+                    serde_json::json!({"key": key, "gets": gets, "last_used": last_used})
+                })
+                .collect();
+            print_json(&serde_json::json!({
+                "since": usage.since(),
+                "opens": usage.opens(),
+                "saves": usage.saves(),
+                "entries": entries,
+            }))?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if !self.unused {
+            println!("Tracking since: {}", usage.since());
+            println!("Unlocks:        {}", usage.opens());
+            println!("Saves:          {}", usage.saves());
+            if !rows.is_empty() {
+                println!();
+            }
+        }
+        let width = rows.iter().map(|(key, ..)| key.len()).max().unwrap_or(0);
+        for (key, gets, last_used) in &rows {
+            match last_used {
+                Some(last_used) => println!("{key:<width$}  {gets:>6}  last used {last_used}"),
+                None => println!("{key:<width$}  {gets:>6}  never used"),
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
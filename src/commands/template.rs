@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    print_json, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
+use crate::commands::new::all_templates;
+use keynest::template::Template;
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest template list                          Show the templates of the keystore and config file
+  keynest template show postgres                 Print a template as TOML
+  keynest template add postgres --file postgres.toml
+                                                 Store a template in the keystore for everyone sharing it
+  keynest template remove postgres               Remove a template from the keystore
+
+A template file holds the contents of a [templates.NAME] table of the config file:
+
+  description = \"PostgreSQL login\"
+  value = { length = 32, symbols = true }
+  tags = [\"db\"]
+  expires = \"90d\"
+  fields.host = { ask = true }
+  fields.port = { default = \"5432\" }
+
+Create entries from templates with keynest new --template NAME KEY.")]
+pub struct TemplateCommand {
+    #[command(subcommand)]
+    pub action: TemplateAction,
+}
+
+#[derive(Subcommand)]
+pub enum TemplateAction {
+    /// List the templates of the keystore and the config file
+    List {
+        /// Output as JSON
+        #[arg(long, short = 'j')]
+        json: bool,
+    },
+    /// Print a template as TOML
+    Show { name: String },
+    /// Store a template in the keystore, replacing one of that name
+    Add {
+        name: String,
+
+        /// TOML file with the template
+        #[arg(long)]
+        file: PathBuf,
+    },
+    /// Remove a template from the keystore
+    Remove { name: String },
+}
+
+#[derive(Serialize)]
+struct Listed<'a> {
+    name: &'a str,
+    source: &'a str,
+    description: Option<&'a str>,
+}
+
+impl Command for TemplateCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        match self.action {
+            TemplateAction::List { json } => {
+                let kn = unlock_indexed(storage)?;
+                let templates = all_templates(kn.templates()?);
+                let listed: Vec<_> = templates
+                    .iter()
+                    .map(|(name, (template, source))| Listed {
+                        name,
+                        source,
+                        description: template.description(),
+                    })
+                    .collect();
+                if json {
+                    print_json(&listed)?;
+                } else if listed.is_empty() {
+                    println!("No templates");
+                } else {
+                    let width = listed.iter().map(|t| t.name.len()).max().unwrap_or(0);
+                    for t in &listed {
+                        println!(
+                            "{:width$}  {:6}  {}",
+                            t.name,
+                            t.source,
+                            t.description.unwrap_or("")
+                        );
+                    }
+                }
+            }
+            TemplateAction::Show { name } => {
+                let kn = unlock_indexed(storage)?;
+                let Some((template, _)) = all_templates(kn.templates()?).remove(&name) else {
+                    eprintln!("no template named '{name}'");
+                    return Ok(ExitCode::from(1));
+                };
+                print!("{}", template.to_toml()?);
+            }
+            TemplateAction::Add { name, file } => {
+                let text = std::fs::read_to_string(&file)
+                    .with_context(|| format!("failed to read {}", file.display()))?;
+                let template = Template::from_toml(&text)
+                    .with_context(|| format!("invalid template in {}", file.display()))?;
+                let mut kn = unlock_keystore(storage)?;
+                kn.set_template(&name, Some(&template))?;
+                kn.save()?;
+                println!("stored template '{name}'");
+            }
+            TemplateAction::Remove { name } => {
+                let mut kn = unlock_keystore(storage)?;
+                if !kn.set_template(&name, None)? {
+                    eprintln!("no template named '{name}' in the keystore");
+                    return Ok(ExitCode::from(1));
+                }
+                kn.save()?;
+                println!("removed template '{name}'");
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
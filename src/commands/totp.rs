@@ -0,0 +1,263 @@
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use clap::{Args, Subcommand};
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    check_access, create_file_secure, resolve_existing_storage, unlock_indexed, unlock_keystore,
+};
+use keynest::{OtpAuth, Totp, TotpAlgorithm};
+
+/// Pixels per QR module in PNG output.
+const PNG_SCALE: usize = 8;
+/// Width of the blank border around the code, in modules.
+const PNG_QUIET_ZONE: usize = 4;
+
+#[derive(Args)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = "\
+Examples:
+  keynest totp add github JBSWY3DPEHPK3PXP      Store a TOTP seed (prompts for it if omitted)
+  keynest totp add github --issuer GitHub --digits 8
+                                                 Store a seed with non-default parameters
+  keynest totp github                            Print the current code and how long it is valid
+  keynest totp export github                     Print the otpauth:// URI of an OTP entry
+  keynest totp export github --qr                Show it as a QR code in the terminal to enroll a new authenticator
+  keynest totp export github --qr --invert       QR code for terminals with a light background
+  keynest totp export github --png qr.png        Write the QR code to a PNG file (owner-only permissions)
+
+OTP entries are entries whose value is an otpauth:// URI: those added with `totp add`, or
+secrets set to such a URI, e.g.
+  keynest set github/otp 'otpauth://totp/GitHub:alice?secret=JBSWY3DPEHPK3PXP&issuer=GitHub'"
+)]
+pub struct TotpCommand {
+    #[command(subcommand)]
+    pub action: Option<TotpAction>,
+
+    /// OTP entry to print the current code of
+    #[arg(required = true)]
+    pub key: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum TotpAction {
+    /// Store a TOTP seed as an OTP entry
+    Add {
+        key: String,
+
+        /// Base32 seed shown by the service (spaces allowed), or an otpauth:// URI;
+        /// prompted for if omitted, which keeps it out of the shell history
+        seed: Option<String>,
+
+        /// Service name shown by authenticators
+        #[arg(long)]
+        issuer: Option<String>,
+
+        /// Digits per code
+        #[arg(long, default_value_t = 6)]
+        digits: u32,
+
+        /// Seconds each code is valid for
+        #[arg(long, default_value_t = 30)]
+        period: u64,
+
+        /// HMAC algorithm: sha1, sha256 or sha512
+        #[arg(long, default_value = "sha1", value_parser = parse_algorithm)]
+        algorithm: TotpAlgorithm,
+    },
+
+    /// Export the otpauth:// URI of an OTP entry, optionally as a QR code
+    Export {
+        key: String,
+
+        /// Render the URI as a QR code in the terminal
+        #[arg(long)]
+        qr: bool,
+
+        /// Swap dark and light modules (for terminals with a light background)
+        #[arg(long, requires = "qr")]
+        invert: bool,
+
+        /// Write the QR code to a PNG file instead
+        #[arg(long, value_name = "FILE", conflicts_with = "qr")]
+        png: Option<PathBuf>,
+    },
+}
+
+impl Command for TotpCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let (key, qr, invert, png) = match self.action {
+            None => return code(self.key.unwrap_or_default(), store),
+            Some(TotpAction::Add {
+                key,
+                seed,
+                issuer,
+                digits,
+                period,
+                algorithm,
+            }) => {
+                let options = AddOptions {
+                    issuer,
+                    digits,
+                    period,
+                    algorithm,
+                };
+                return add(key, seed, options, store);
+            }
+            Some(TotpAction::Export {
+                key,
+                qr,
+                invert,
+                png,
+            }) => (key, qr, invert, png),
+        };
+
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_indexed(storage)?;
+
+        check_access(&key, kn.effective_policy(&key)?, png.is_none())?;
+        let Some(value) = kn.resolve(&key)? else {
+            eprintln!("key not found: {key}");
+            return Ok(ExitCode::from(1));
+        };
+        if !OtpAuth::is_otp_uri(value) {
+            bail!("'{key}' is not an OTP entry (its value is not an otpauth:// URI)");
+        }
+        let otp = OtpAuth::parse(value).with_context(|| format!("invalid OTP entry '{key}'"))?;
+
+        if !qr && png.is_none() {
+            println!("{}", otp.uri());
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let code = QrCode::new(otp.uri().as_bytes()).context("failed to encode QR code")?;
+        if let Some(path) = png {
+            write_png(&code, &path)?;
+            println!("QR code for {} written to {}", otp.label(), path.display());
+        } else {
+            let (dark, light) = if invert {
+                (Dense1x2::Dark, Dense1x2::Light)
+            } else {
+                (Dense1x2::Light, Dense1x2::Dark)
+            };
+            let image = Zeroizing::new(
+                code.render::<Dense1x2>()
+                    .dark_color(dark)
+                    .light_color(light)
+                    .build(),
+            );
+            println!("{}", *image);
+            println!("{} ({})", otp.label(), otp.kind());
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn parse_algorithm(s: &str) -> Result<TotpAlgorithm, String> {
+    TotpAlgorithm::from_name(s)
+        .ok_or_else(|| format!("unknown algorithm '{s}' (expected sha1, sha256 or sha512)"))
+}
+
+/// Parameters of `totp add` besides the seed.
+struct AddOptions {
+    issuer: Option<String>,
+    digits: u32,
+    period: u64,
+    algorithm: TotpAlgorithm,
+}
+
+fn add(
+    key: String,
+    seed: Option<String>,
+    options: AddOptions,
+    store: Option<PathBuf>,
+) -> Result<ExitCode> {
+    let storage = resolve_existing_storage(store)?;
+    let seed = match seed {
+        Some(seed) => Zeroizing::new(seed),
+        None => Zeroizing::new(rpassword::prompt_password("TOTP seed: ")?),
+    };
+
+    let otp = if OtpAuth::is_otp_uri(&seed) {
+        OtpAuth::parse(&seed)?
+    } else {
+        let totp = Totp::new(&seed)?
+            .with_algorithm(options.algorithm)
+            .with_digits(options.digits)?
+            .with_period(options.period)?;
+        let label = match &options.issuer {
+            Some(issuer) => format!("{issuer}:{key}"),
+            None => key.clone(),
+        };
+        OtpAuth::from_totp(&label, options.issuer.as_deref(), &totp)
+    };
+
+    let mut kn = unlock_keystore(storage)?;
+    kn.set_totp(&key, &otp)?;
+    kn.save()?;
+    println!("stored totp '{key}'");
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn code(key: String, store: Option<PathBuf>) -> Result<ExitCode> {
+    let storage = resolve_existing_storage(store)?;
+    let mut kn = unlock_indexed(storage)?;
+
+    // The code is derived from the seed, so only confirmation applies.
+    check_access(&key, kn.effective_policy(&key)?, false)?;
+    let Some(value) = kn.resolve(&key)? else {
+        eprintln!("key not found: {key}");
+        return Ok(ExitCode::from(1));
+    };
+    if !OtpAuth::is_otp_uri(value) {
+        bail!("'{key}' is not an OTP entry (its value is not an otpauth:// URI)");
+    }
+    let otp = OtpAuth::parse(value).with_context(|| format!("invalid OTP entry '{key}'"))?;
+    let Some(totp) = otp.totp() else {
+        bail!("'{key}' is a HOTP entry; only TOTP codes can be generated");
+    };
+
+    let code = totp.code_at(Utc::now());
+    println!("{}", code.code());
+    eprintln!("valid for {}s", code.remaining_secs());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn write_png(code: &QrCode, path: &Path) -> Result<()> {
+    let width = code.width();
+    let size = (width + 2 * PNG_QUIET_ZONE) * PNG_SCALE;
+
+    let mut pixels = Zeroizing::new(vec![u8::MAX; size * size]);
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color != qrcode::Color::Dark {
+            continue;
+        }
+        let x = (i % width + PNG_QUIET_ZONE) * PNG_SCALE;
+        let y = (i / width + PNG_QUIET_ZONE) * PNG_SCALE;
+        for row in y..y + PNG_SCALE {
+            pixels[row * size + x..row * size + x + PNG_SCALE].fill(0);
+        }
+    }
+
+    let mut out = BufWriter::new(create_file_secure(path)?);
+    let mut encoder = png::Encoder::new(&mut out, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .context("failed to write PNG")?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use anyhow::Result;
+use clap::Args;
+use std::io::Write;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use crate::commands::Command;
+use crate::commands::common::{
+    check_access, interruptible, resolve_existing_storage, unlock_keystore,
+};
+use keynest::autotype::{Action, SpecialKey};
+use zeroize::Zeroizing;
+
+#[derive(Args)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "\
+Examples:
+  keynest type bank/password                     Focus the password field, then wait for it to be typed
+  keynest type bank/password --delay 5 --enter   Wait 5 seconds, type the secret and press Enter
+  keynest type bank                              Type the login bank: bank/username, Tab, bank/password, Enter
+  keynest type bank --sequence '{PASSWORD}{ENTER}'
+                                                 Use this sequence instead of the configured one
+
+The secret is typed as keystrokes into whatever window has focus when the countdown
+ends, for sites and apps that block pasting. Ctrl-C during the countdown aborts.
+If KEY is a namespace rather than an entry, or an entry with a password field, it is
+typed as a login using its autotype sequence (see 'keynest autotype'); a placeholder
+such as {USERNAME} takes the field of that name, else the entry KEY/username.
+Requires a build with the 'type' feature. Linux needs an X11 session (or XWayland
+windows); macOS asks to grant the terminal Accessibility access on first use."
+)]
+pub struct TypeCommand {
+    pub key: String,
+
+    /// Seconds to wait before typing, to focus the target window
+    #[arg(long, short = 'd', default_value_t = 3)]
+    pub delay: u64,
+
+    /// Press Enter after typing the secret
+    #[arg(long)]
+    pub enter: bool,
+
+    /// Do not follow `ref:<key>` references
+    #[arg(long = "no-resolve", conflicts_with = "sequence")]
+    pub no_resolve: bool,
+
+    /// Type KEY as a login with this autotype sequence, e.g. '{USERNAME}{TAB}{PASSWORD}'
+    #[arg(long, short = 's')]
+    pub sequence: Option<String>,
+}
+
+impl Command for TypeCommand {
+    fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let mut kn = unlock_keystore(storage)?;
+
+        let is_login = self.sequence.is_some()
+            || match kn.fields(&self.key)? {
+                Some(fields) => fields.contains_key("password"),
+                None => !kn.list_prefix(&format!("{}/", self.key)).is_empty(),
+            };
+        let (mut actions, keys) = if is_login {
+            kn.autotype(&self.key, self.sequence.as_deref())?
+        } else {
+            let secret = if self.no_resolve {
+                kn.get(&self.key)?
+            } else {
+                kn.resolve(&self.key)?
+            };
+            let Some(secret) = secret else {
+                eprintln!("key not found: {}", self.key);
+                return Ok(ExitCode::from(1));
+            };
+            (
+                vec![Action::Text(Zeroizing::new(secret.to_string()))],
+                vec![self.key.clone()],
+            )
+        };
+        for key in &keys {
+            check_access(key, kn.effective_policy(key)?, false)?;
+        }
+        if self.enter {
+            actions.push(Action::Key(SpecialKey::Enter));
+        }
+
+        keyboard::ensure_available()?;
+        if !countdown(self.delay)? {
+            eprintln!("Aborted");
+            return Ok(ExitCode::from(130));
+        }
+        keyboard::perform(&actions)?;
+
+        if kn.records_reads()? {
+            for key in &keys {
+                kn.record_get(key)?;
+            }
+            kn.save_usage()?;
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Counts down `secs` seconds on stderr; returns `false` if Ctrl-C was pressed.
+fn countdown(secs: u64) -> Result<bool> {
+    let mut stderr = std::io::stderr();
+    let completed = interruptible(|cancel| {
+        for remaining in (1..=secs).rev() {
+            eprint!("\rTyping in {remaining}s... (focus the target window, Ctrl-C to abort) ");
+            let _ = stderr.flush();
+            for _ in 0..10 {
+                if cancel.is_cancelled() {
+                    return false;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+        !cancel.is_cancelled()
+    });
+    if secs > 0 {
+        eprintln!();
+    }
+    Ok(completed)
+}
+
+#[cfg(feature = "type")]
+mod keyboard {
+    use anyhow::{Result, anyhow};
+    use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+    use keynest::autotype::{Action, SpecialKey};
+
+    pub fn ensure_available() -> Result<()> {
+        Ok(())
+    }
+
+    /// Performs `actions` in the focused window.
+    pub fn perform(actions: &[Action]) -> Result<()> {
+        let mut enigo = Enigo::new(&Settings::default())
+            .map_err(|e| anyhow!("cannot access the keyboard input API: {e}"))?;
+        for action in actions {
+            match action {
+                Action::Text(text) => enigo
+                    .text(text)
+                    .map_err(|e| anyhow!("failed to type the secret: {e}"))?,
+                Action::Key(key) => {
+                    let key = match key {
+                        SpecialKey::Tab => Key::Tab,
+                        SpecialKey::Enter => Key::Return,
+                    };
+                    enigo
+                        .key(key, Direction::Click)
+                        .map_err(|e| anyhow!("failed to press {key:?}: {e}"))?
+                }
+                Action::Delay(delay) => std::thread::sleep(*delay),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "type"))]
+mod keyboard {
+    use anyhow::{Result, bail};
+    use keynest::autotype::Action;
+
+    pub fn ensure_available() -> Result<()> {
+        bail!("this keynest was built without keyboard typing; rebuild with `--features type`")
+    }
+
+    pub fn perform(_actions: &[Action]) -> Result<()> {
+        ensure_available()
+    }
+}
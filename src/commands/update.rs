@@ -1,30 +1,111 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use clap::Args;
 use std::process::ExitCode;
 
-use super::super::auth;
 use crate::commands::Command;
-use crate::commands::common::resolve_existing_storage;
-use keynest::Keynest;
+use crate::commands::common::{
+    Restriction, parse_expiry, parse_field, read_fields, resolve_existing_storage, unlock_keystore,
+    with_restrictions,
+};
 
 #[derive(Args)]
 #[command(
     arg_required_else_help = true,
     after_help = "\
 Examples:
-  keynest update api_key \"new_secret\"          Update an existing secret value"
+  keynest update api_key \"new_secret\"          Update an existing secret value
+  keynest update api_key --add-tag prod          Add a tag without changing the value
+  keynest update api_key --remove-tag staging    Remove a tag
+  keynest update api_key \"rotated\" --expires 90d Rotate the secret and set a new expiry
+  keynest update api_key --no-expiry             Remove the expiry
+  keynest update github --field password         Change the password field (prompts for it)
+  keynest update github --field url=https://github.com/login --remove-field otp
+                                                 Set one field and remove another
+  keynest update prod/db --restrict confirm      Ask for confirmation whenever prod/db is read
+  keynest update prod/db --unrestrict no-print   Allow printing prod/db to stdout again"
 )]
 pub struct UpdateCommand {
     pub key: String,
-    pub new_value: String,
+    #[arg(required_unless_present_any = [
+        "add_tags", "remove_tags", "expires", "no_expiry", "fields", "remove_fields",
+        "restrict", "unrestrict"
+    ])]
+    pub new_value: Option<String>,
+
+    /// Add a tag; comma-separated or repeated
+    #[arg(long = "add-tag", value_name = "TAG", value_delimiter = ',')]
+    pub add_tags: Vec<String>,
+
+    /// Remove a tag; comma-separated or repeated
+    #[arg(long = "remove-tag", value_name = "TAG", value_delimiter = ',')]
+    pub remove_tags: Vec<String>,
+
+    /// Mark the secret as expired after this time: 90d, 12h, 2w, 30m, a date or RFC 3339
+    #[arg(long, value_name = "WHEN", value_parser = parse_expiry)]
+    pub expires: Option<DateTime<Utc>>,
+
+    /// Remove the expiry
+    #[arg(long = "no-expiry", conflicts_with = "expires")]
+    pub no_expiry: bool,
+
+    /// Set a named field (NAME=VALUE, or NAME to prompt for the value); repeatable
+    #[arg(long = "field", value_name = "NAME=VALUE", value_parser = parse_field)]
+    pub fields: Vec<(String, Option<String>)>,
+
+    /// Remove a field; comma-separated or repeated
+    #[arg(long = "remove-field", value_name = "NAME", value_delimiter = ',')]
+    pub remove_fields: Vec<String>,
+
+    /// Restrict access to the value: no-print, confirm; comma-separated or repeated
+    #[arg(
+        long = "restrict",
+        value_name = "RULE",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub restrict: Vec<Restriction>,
+
+    /// Lift an access restriction; comma-separated or repeated
+    #[arg(
+        long = "unrestrict",
+        value_name = "RULE",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub unrestrict: Vec<Restriction>,
 }
 
 impl Command for UpdateCommand {
     fn run(self, store: Option<std::path::PathBuf>) -> Result<ExitCode> {
         let storage = resolve_existing_storage(store)?;
-        let password = auth::read_password()?;
-        let mut kn = Keynest::open_with_storage(password, storage)?;
-        kn.update(&self.key, &self.new_value)?;
+        let fields = read_fields(self.fields)?;
+        let mut kn = unlock_keystore(storage)?;
+        if let Some(new_value) = &self.new_value {
+            kn.update(&self.key, new_value)?;
+        }
+        for tag in &self.add_tags {
+            kn.add_tag(&self.key, tag)?;
+        }
+        for tag in &self.remove_tags {
+            kn.remove_tag(&self.key, tag)?;
+        }
+        for (name, value) in &fields {
+            kn.set_field(&self.key, name, value)?;
+        }
+        for name in &self.remove_fields {
+            if !kn.remove_field(&self.key, name)? {
+                anyhow::bail!("secret '{}' has no field '{name}'", self.key);
+            }
+        }
+        if self.expires.is_some() || self.no_expiry {
+            kn.set_expiry(&self.key, self.expires)?;
+        }
+        if !self.restrict.is_empty() || !self.unrestrict.is_empty() {
+            let policy = kn.policy(&self.key)?.unwrap_or_default();
+            let policy = with_restrictions(policy, &self.unrestrict, false);
+            kn.set_policy(&self.key, with_restrictions(policy, &self.restrict, true))?;
+        }
         kn.save()?;
         println!("secret '{}' updated.", self.key);
 
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use clap::Args;
+use keynest::ChainStatus;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::commands::Command;
+use crate::commands::common::{chain_witness, resolve_existing_storage, unlock_keystore};
+
+#[derive(Args)]
+#[command(after_help = "\
+Examples:
+  keynest verify-chain                 Check the store against the newest save seen here
+  keynest verify-chain --accept        Trust the store as it is, e.g. after restoring a backup
+
+Every save counts the generation of the store up and records the hash of the file it
+replaced, authenticated with the key. The first run remembers the generation and hash
+of the store on this machine (in $KEYNEST_CHAIN_DIR, or else in the keynest data
+directory), and every later save from this machine moves them forward. A store that
+was put back to an older copy since, by anyone with write access to the file, is
+reported as rolled back and the command exits with 1; so is a copy that was changed
+and put in its place. Saves made elsewhere in between are checked against the backups
+that are kept. Changes appended to the journal are not covered until it is folded in.")]
+pub struct VerifyChainCommand {
+    /// Record the store as the newest save seen, even if it is older
+    #[arg(long)]
+    pub accept: bool,
+}
+
+impl Command for VerifyChainCommand {
+    fn run(self, store: Option<PathBuf>) -> Result<ExitCode> {
+        let storage = resolve_existing_storage(store)?;
+        let witness = chain_witness(storage.path())?;
+        let kn = unlock_keystore(storage)?;
+
+        if self.accept {
+            kn.accept_chain(&witness)?;
+            if let Some(link) = kn.chain() {
+                println!(
+                    "generation {} recorded as the newest save",
+                    link.generation()
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        match kn.verify_chain(&witness)? {
+            ChainStatus::Untracked { generation } => {
+                println!("generation {generation} recorded; later saves are checked against it");
+            }
+            ChainStatus::Current { generation } => {
+                println!("ok: generation {generation}, the newest save seen here");
+            }
+            ChainStatus::Ahead {
+                generation,
+                saves,
+                linked,
+            } => {
+                let saves = match saves {
+                    1 => "1 save".to_string(),
+                    n => format!("{n} saves"),
+                };
+                if linked {
+                    println!("ok: generation {generation}, {saves} after the newest seen here");
+                } else {
+                    println!(
+                        "ok: generation {generation}, {saves} after the newest seen here \
+                         (not all of them are kept as backups, so the links in between were \
+                         not checked)"
+                    );
+                }
+            }
+            ChainStatus::RolledBack { generation, seen } => {
+                eprintln!(
+                    "ROLLED BACK: the store is at generation {generation}, but generation \
+                     {seen} was seen here"
+                );
+                eprintln!(
+                    "if you restored a backup on purpose, run: keynest verify-chain --accept"
+                );
+                return Ok(ExitCode::from(1));
+            }
+            ChainStatus::Forked { generation, seen } => {
+                eprintln!(
+                    "FORKED: the store at generation {generation} does not descend from \
+                     generation {seen} seen here"
+                );
+                eprintln!(
+                    "if you replaced the store on purpose, run: keynest verify-chain --accept"
+                );
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,534 @@
+//! Reading and writing the Wi-Fi passphrases of NetworkManager connection profiles, for
+//! `import --wifi` and `export --wifi`.
+//!
+//! NetworkManager keeps system connections as keyfiles (`.ini`-like, one per connection)
+//! in `/etc/NetworkManager/system-connections`, readable by root only. A WPA or WPA3
+//! personal network stores its passphrase as `psk` in the `wifi-security` group unless
+//! the secret is kept by a user's secret agent instead; such connections, open networks
+//! and enterprise (802.1X) networks carry no passphrase and are skipped.
+//!
+//! Exports update the passphrase of an existing connection with the same SSID, or create
+//! a new connection with automatic IP configuration. NetworkManager picks up changed
+//! files after `nmcli connection reload`.
+
+use anyhow::{Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use crate::commands::common::write_file_secure;
+use crate::commands::os_keychain::credential_key;
+
+/// Where NetworkManager stores its system connections.
+pub const DEFAULT_DIR: &str = "/etc/NetworkManager/system-connections";
+
+/// The namespace Wi-Fi passphrases are stored under.
+pub const NAMESPACE: &str = "wifi";
+
+const WIFI: [&str; 2] = ["wifi", "802-11-wireless"];
+const SECURITY: [&str; 2] = ["wifi-security", "802-11-wireless-security"];
+
+/// A Wi-Fi connection with a stored passphrase.
+pub struct Network {
+    pub ssid: String,
+    pub psk: Zeroizing<String>,
+}
+
+impl Network {
+    /// Returns the keynest key for the network: `wifi/<ssid>`.
+    pub fn key(&self) -> String {
+        credential_key(NAMESPACE, &self.ssid)
+    }
+}
+
+/// The Wi-Fi connections found in a directory.
+pub struct Networks {
+    pub networks: Vec<Network>,
+    /// Wi-Fi connections without a stored passphrase.
+    pub without_psk: usize,
+}
+
+impl Networks {
+    fn add(&mut self, ssid: String, psk: Option<Zeroizing<String>>) {
+        match psk.filter(|psk| !psk.is_empty()) {
+            Some(psk) if !credential_key("", &ssid).is_empty() => {
+                self.networks.push(Network { ssid, psk });
+            }
+            // An SSID of dots or control characters has no usable key.
+            Some(_) => {}
+            None => self.without_psk += 1,
+        }
+    }
+}
+
+/// Reads the Wi-Fi connections of the keyfiles in `dir`, or of the system connections.
+///
+/// The system connections are only readable by root; other users get them from
+/// NetworkManager through `nmcli`, which shows the secrets to the logged-in user.
+///
+/// # Errors
+///
+/// Returns an error if the directory or one of its files cannot be read.
+pub fn read(dir: Option<&Path>) -> Result<Networks> {
+    if let Some(dir) = dir {
+        return read_keyfiles(dir);
+    }
+    let error = match read_keyfiles(DEFAULT_DIR.as_ref()) {
+        Err(e) if permission_denied(&e) => e,
+        found => return found,
+    };
+    match nmcli::read() {
+        Err(e)
+            if e.downcast_ref::<std::io::Error>().map(std::io::Error::kind)
+                == Some(std::io::ErrorKind::NotFound) =>
+        {
+            Err(error)
+        }
+        found => found,
+    }
+}
+
+fn read_keyfiles(dir: &Path) -> Result<Networks> {
+    let mut found = Networks {
+        networks: Vec::new(),
+        without_psk: 0,
+    };
+    for path in keyfiles(dir)? {
+        let text = fs::read_to_string(&path).map_err(|e| unreadable(&path, e))?;
+        let file = Keyfile::parse(&text);
+        if !file.is_wifi() {
+            continue;
+        }
+        let Some(ssid) = file.ssid() else { continue };
+        let psk = file
+            .get(&SECURITY, "psk")
+            .filter(|_| file.uses_psk())
+            .map(Zeroizing::new);
+        found.add(ssid, psk);
+    }
+    Ok(found)
+}
+
+/// What [`write`] did, or would do, with a connection.
+pub enum Change {
+    Created(PathBuf),
+    Updated(PathBuf),
+}
+
+/// Returns the change storing the passphrase of `ssid` in `dir` would make, without
+/// writing anything.
+///
+/// # Errors
+///
+/// Returns an error if the directory or one of its files cannot be read.
+pub fn plan(dir: &Path, ssid: &str) -> Result<Change> {
+    Ok(match find(dir, ssid)? {
+        Some((path, _)) => Change::Updated(path),
+        None => Change::Created(new_path(dir, ssid)),
+    })
+}
+
+/// Stores `psk` as the passphrase of the connection for `ssid` in `dir`, creating a
+/// WPA personal connection if there is none.
+///
+/// # Errors
+///
+/// Returns an error if the passphrase is not valid for WPA, or a file cannot be read or
+/// written.
+pub fn write(dir: &Path, ssid: &str, psk: &str) -> Result<Change> {
+    if let Some((path, mut file)) = find(dir, ssid)? {
+        // WPA3 (SAE) passwords have no length limits.
+        if file.get(&SECURITY, "key-mgmt").as_deref() != Some("sae") {
+            check_passphrase(psk)?;
+        }
+        if !file.uses_psk() {
+            file.set(&SECURITY, "key-mgmt", "wpa-psk");
+        }
+        file.set(&SECURITY, "psk", &escape(psk));
+        file.remove(&SECURITY, "psk-flags");
+        write_file_secure(&path, Zeroizing::new(file.to_string()).as_bytes())?;
+        return Ok(Change::Updated(path));
+    }
+
+    check_passphrase(psk)?;
+    let path = new_path(dir, ssid);
+    let text = Zeroizing::new(format!(
+        "[connection]\nid={id}\nuuid={uuid}\ntype=wifi\n\n\
+         [wifi]\nmode=infrastructure\nssid={ssid}\n\n\
+         [wifi-security]\nkey-mgmt=wpa-psk\npsk={psk}\n\n\
+         [ipv4]\nmethod=auto\n\n[ipv6]\naddr-gen-mode=default\nmethod=auto\n",
+        id = escape(ssid),
+        uuid = uuid()?,
+        ssid = encode_ssid(ssid),
+        psk = escape(psk),
+    ));
+    write_file_secure(&path, text.as_bytes())?;
+    Ok(Change::Created(path))
+}
+
+/// Checks that `psk` is a WPA passphrase (8 to 63 printable ASCII characters) or a raw
+/// 256-bit key (64 hex digits).
+fn check_passphrase(psk: &str) -> Result<()> {
+    let passphrase =
+        (8..=63).contains(&psk.len()) && psk.bytes().all(|b| (b' '..=b'~').contains(&b));
+    let raw = psk.len() == 64 && psk.bytes().all(|b| b.is_ascii_hexdigit());
+    if !passphrase && !raw {
+        bail!("not a valid WPA passphrase (8 to 63 ASCII characters, or 64 hex digits)");
+    }
+    Ok(())
+}
+
+/// Finds the Wi-Fi connection for `ssid` in `dir`.
+fn find(dir: &Path, ssid: &str) -> Result<Option<(PathBuf, Keyfile)>> {
+    if !dir.exists() {
+        return Ok(None);
+    }
+    for path in keyfiles(dir)? {
+        let text = fs::read_to_string(&path).map_err(|e| unreadable(&path, e))?;
+        let file = Keyfile::parse(&text);
+        if file.is_wifi() && file.ssid().as_deref() == Some(ssid) {
+            return Ok(Some((path, file)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the files of `dir` NetworkManager would load, sorted by name.
+fn keyfiles(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).map_err(|e| unreadable(dir, e))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        // Hidden files, editor backups and package manager leftovers are ignored.
+        let ignored = name.starts_with('.')
+            || name.ends_with('~')
+            || [
+                ".swp",
+                ".bak",
+                ".tmp",
+                ".rpmnew",
+                ".rpmsave",
+                ".dpkg-old",
+                ".dpkg-dist",
+            ]
+            .iter()
+            .any(|suffix| name.ends_with(suffix));
+        if !ignored && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn permission_denied(e: &anyhow::Error) -> bool {
+    e.root_cause()
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+fn unreadable(path: &Path, e: std::io::Error) -> anyhow::Error {
+    let hint = if e.kind() == std::io::ErrorKind::PermissionDenied {
+        " (NetworkManager connections are only readable by root; try sudo)"
+    } else {
+        ""
+    };
+    anyhow::Error::new(e).context(format!("cannot read {}{hint}", path.display()))
+}
+
+/// Returns a free path for a new connection named after `ssid`.
+fn new_path(dir: &Path, ssid: &str) -> PathBuf {
+    let name: String = ssid
+        .chars()
+        .map(|c| if c == '/' || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim_start_matches('.');
+    let name = if name.is_empty() { "wifi" } else { name };
+    let mut path = dir.join(format!("{name}.nmconnection"));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{name}-{n}.nmconnection"));
+    }
+    path
+}
+
+/// Returns a random (version 4) UUID.
+fn uuid() -> Result<String> {
+    let mut b = [0u8; 16];
+    getrandom::fill(&mut b).map_err(|_| anyhow::anyhow!("OS random generator unavailable"))?;
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|b| format!("{b:02x}")).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+mod nmcli {
+    use anyhow::{Result, bail};
+    use std::process::{Command, Stdio};
+    use zeroize::Zeroizing;
+
+    use super::Networks;
+
+    /// Reads the Wi-Fi connections NetworkManager knows about, with their secrets.
+    ///
+    /// Fails with the `io::Error` of spawning `nmcli` if it is not installed.
+    pub fn read() -> Result<Networks> {
+        let mut found = Networks {
+            networks: Vec::new(),
+            without_psk: 0,
+        };
+        let list = run(&["--terse", "--fields", "UUID,TYPE", "connection", "show"])?;
+        for line in list.lines() {
+            let Some((uuid, kind)) = line.split_once(':') else {
+                continue;
+            };
+            if kind != "802-11-wireless" {
+                continue;
+            }
+            let ssid = field(uuid, "802-11-wireless.ssid")?;
+            if ssid.is_empty() {
+                continue;
+            }
+            let key_mgmt = field(uuid, "802-11-wireless-security.key-mgmt")?;
+            let psk = if *key_mgmt == "wpa-psk" || *key_mgmt == "sae" {
+                Some(field(uuid, "802-11-wireless-security.psk")?)
+            } else {
+                None
+            };
+            found.add(ssid.to_string(), psk);
+        }
+        Ok(found)
+    }
+
+    /// Returns one property of a connection, with secrets shown.
+    fn field(uuid: &str, name: &str) -> Result<Zeroizing<String>> {
+        let value = run(&[
+            "--show-secrets",
+            "--get-values",
+            name,
+            "connection",
+            "show",
+            "uuid",
+            uuid,
+        ])?;
+        Ok(Zeroizing::new(unescape(value.trim_end_matches('\n'))))
+    }
+
+    fn run(args: &[&str]) -> Result<Zeroizing<String>> {
+        let output = Command::new("nmcli")
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()?;
+        let stdout = Zeroizing::new(output.stdout);
+        if !output.status.success() {
+            bail!(
+                "nmcli failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(Zeroizing::new(
+            String::from_utf8_lossy(&stdout).into_owned(),
+        ))
+    }
+
+    /// Undoes the escaping of terse output (`\:` and `\\`).
+    fn unescape(value: &str) -> String {
+        let mut out = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => out.extend(chars.next()),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+}
+
+/// A NetworkManager keyfile, kept as lines so rewriting it preserves everything else.
+struct Keyfile {
+    lines: Vec<String>,
+}
+
+impl Keyfile {
+    fn parse(text: &str) -> Self {
+        Self {
+            lines: text.lines().map(str::to_owned).collect(),
+        }
+    }
+
+    fn is_wifi(&self) -> bool {
+        self.get(&["connection"], "type")
+            .is_some_and(|t| WIFI.contains(&t.as_str()))
+    }
+
+    fn uses_psk(&self) -> bool {
+        self.get(&SECURITY, "key-mgmt")
+            .is_some_and(|m| m == "wpa-psk" || m == "sae")
+    }
+
+    fn ssid(&self) -> Option<String> {
+        let raw = self.raw(&WIFI, "ssid")?;
+        Some(decode_ssid(raw))
+    }
+
+    /// Returns the index of the `key` line in one of the `groups`, or of the last line of
+    /// the group if it has no such key.
+    fn find(&self, groups: &[&str], key: &str) -> Option<Result<usize, usize>> {
+        let mut group = None;
+        let mut end = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                group = Some(name);
+                continue;
+            }
+            if !group.is_some_and(|g| groups.contains(&g)) {
+                continue;
+            }
+            end = Some(i);
+            if line.split_once('=').is_some_and(|(k, _)| k.trim() == key) {
+                return Some(Ok(i));
+            }
+        }
+        if end.is_none() {
+            let header = self.lines.iter().position(|l| {
+                l.trim()
+                    .strip_prefix('[')
+                    .and_then(|l| l.strip_suffix(']'))
+                    .is_some_and(|g| groups.contains(&g))
+            })?;
+            return Some(Err(header));
+        }
+        end.map(Err)
+    }
+
+    fn raw(&self, groups: &[&str], key: &str) -> Option<&str> {
+        let i = self.find(groups, key)?.ok()?;
+        let (_, value) = self.lines[i].split_once('=')?;
+        Some(value.trim_start())
+    }
+
+    fn get(&self, groups: &[&str], key: &str) -> Option<String> {
+        self.raw(groups, key).map(unescape)
+    }
+
+    /// Sets `key` to the already escaped `value`, adding the group (named after the first
+    /// of `groups`) if needed.
+    fn set(&mut self, groups: &[&str], key: &str, value: &str) {
+        let line = format!("{key}={value}");
+        match self.find(groups, key) {
+            Some(Ok(i)) => self.lines[i] = line,
+            Some(Err(last)) => {
+                // Keep blank lines that separate the group from the next one.
+                let mut at = last + 1;
+                while at > 1 && self.lines[at - 1].trim().is_empty() {
+                    at -= 1;
+                }
+                self.lines.insert(at, line);
+            }
+            None => {
+                if self.lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                    self.lines.push(String::new());
+                }
+                self.lines.push(format!("[{}]", groups[0]));
+                self.lines.push(line);
+            }
+        }
+    }
+
+    fn remove(&mut self, groups: &[&str], key: &str) {
+        if let Some(Ok(i)) = self.find(groups, key) {
+            self.lines.remove(i);
+        }
+    }
+}
+
+impl std::fmt::Display for Keyfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Keyfile {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+        self.lines.zeroize();
+    }
+}
+
+/// Decodes an SSID, written either as a string or as a list of bytes (`72;111;109;101;`).
+fn decode_ssid(raw: &str) -> String {
+    let bytes: Option<Vec<u8>> = raw
+        .strip_suffix(';')
+        .filter(|list| !list.is_empty())
+        .and_then(|list| list.split(';').map(|b| b.trim().parse().ok()).collect());
+    match bytes {
+        Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        None => unescape(raw),
+    }
+}
+
+/// Encodes an SSID as NetworkManager would: as a string, unless it could be misread as a
+/// list of bytes or holds characters a keyfile string cannot.
+fn encode_ssid(ssid: &str) -> String {
+    let ambiguous = ssid.contains(';') || ssid.chars().any(char::is_control);
+    if ambiguous {
+        ssid.bytes().map(|b| format!("{b};")).collect()
+    } else {
+        escape(ssid)
+    }
+}
+
+/// Unescapes a keyfile string value (`\s`, `\n`, `\t`, `\r` and `\\`).
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Escapes a keyfile string value; leading spaces would otherwise be dropped.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut leading = true;
+    for c in value.chars() {
+        match c {
+            ' ' if leading => out.push_str("\\s"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+        leading &= c == ' ';
+    }
+    out
+}
@@ -0,0 +1,353 @@
+//! The keynest config file: named profiles with defaults for new keystores, and entry
+//! templates.
+//!
+//! A profile names a keystore and the parameters `keynest init` creates it with, so a
+//! high-value vault can use a heavier KDF than a throwaway dev store:
+//!
+//! ```toml
+//! default-profile = "vault"
+//!
+//! [profiles.vault]
+//! store = "/home/alice/vault.db"
+//! cipher = "xchacha20"
+//! compress = true
+//! padding = true
+//! backups = 5
+//! dpapi = true
+//! min-password-score = 4
+//! kdf = { memory-kib = 262144, time-cost = 6, parallelism = 4 }
+//!
+//! [profiles.dev]
+//! store = "/home/alice/dev.db"
+//! kdf = { memory-kib = 19456, time-cost = 2 }
+//!
+//! [profiles.fips]
+//! kdf = { algorithm = "scrypt", n = 131072, r = 8, p = 1 }
+//! ```
+//!
+//! Everything is optional; unset values fall back to the built-in defaults. The
+//! defaults only apply when a keystore is created; afterwards the keystore itself
+//! records its parameters (see [`crate::Keynest::info`]).
+//!
+//! `[templates.NAME]` tables define entry templates for every keystore (see
+//! [`crate::template`]).
+
+use anyhow::{Context, Result, bail};
+use directories::ProjectDirs;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::format::{Compression, Padding, PayloadEncoding};
+use crate::template::Template;
+use crate::{Algorithm, InitOptions, KdfParams};
+
+/// The parsed config file.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    templates: BTreeMap<String, Template>,
+}
+
+/// Defaults for one keystore.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    store: Option<PathBuf>,
+    #[serde(default)]
+    kdf: KdfDefaults,
+    cipher: Option<String>,
+    compress: Option<bool>,
+    padding: Option<bool>,
+    backups: Option<u32>,
+    dpapi: Option<bool>,
+    min_password_score: Option<u8>,
+}
+
+/// KDF parameters of a profile: `memory-kib`, `time-cost` and `parallelism` for Argon2id,
+/// or `n`, `r` and `p` with `algorithm = "scrypt"`; unset ones use the KDF's defaults.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct KdfDefaults {
+    algorithm: Option<String>,
+    memory_kib: Option<u32>,
+    time_cost: Option<u32>,
+    parallelism: Option<u32>,
+    n: Option<u64>,
+    r: Option<u32>,
+    p: Option<u32>,
+}
+
+impl Config {
+    /// Parses the TOML text of a config file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for invalid TOML, unknown fields, or a `default-profile` that
+    /// is not defined.
+    pub fn parse(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text)?;
+        if let Some(name) = &config.default_profile {
+            config.profile(name)?;
+        }
+        for (name, profile) in &config.profiles {
+            if profile.min_password_score.is_some_and(|score| score > 4) {
+                bail!("invalid min-password-score in profile '{name}' (expected 0 to 4)");
+            }
+        }
+        for (name, template) in &config.templates {
+            template
+                .validate()
+                .with_context(|| format!("invalid template '{name}'"))?;
+        }
+        Ok(config)
+    }
+
+    /// Reads the config file at `path`; a missing file is an empty config.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        Self::parse(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Returns the profile used when none is selected explicitly, if configured.
+    pub fn default_profile(&self) -> Option<&str> {
+        self.default_profile.as_deref()
+    }
+
+    /// Returns the profile called `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no such profile.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).with_context(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("unknown profile '{name}': no profiles are configured")
+            } else {
+                format!(
+                    "unknown profile '{name}' (configured: {})",
+                    known.join(", ")
+                )
+            }
+        })
+    }
+
+    /// Returns the names of the configured profiles, sorted.
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    /// Returns the entry templates defined in the config file, by name.
+    pub fn templates(&self) -> &BTreeMap<String, Template> {
+        &self.templates
+    }
+}
+
+impl Profile {
+    /// Returns the path of the profile's keystore, if it names one.
+    pub fn store(&self) -> Option<&Path> {
+        self.store.as_deref()
+    }
+
+    /// Returns the score from 0 to 4 new master passwords must reach (see
+    /// [`crate::estimate_strength`]), if the profile sets one.
+    pub fn min_password_score(&self) -> Option<u8> {
+        self.min_password_score
+    }
+
+    /// Returns the KDF parameters of new keystores.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configured parameters are out of range.
+    pub fn kdf_params(&self) -> Result<KdfParams> {
+        let kdf = &self.kdf;
+        let algorithm = kdf.algorithm.as_deref().unwrap_or("argon2id");
+        match KdfParams::default_for(algorithm) {
+            Some(KdfParams::Argon2id(default)) => {
+                if kdf.n.is_some() || kdf.r.is_some() || kdf.p.is_some() {
+                    bail!("n, r and p need algorithm = \"scrypt\"");
+                }
+                KdfParams::new(
+                    kdf.memory_kib.unwrap_or(default.mem_cost_kib()),
+                    kdf.time_cost.unwrap_or(default.time_cost()),
+                    kdf.parallelism.unwrap_or(default.parallelism()),
+                )
+            }
+            Some(KdfParams::Scrypt(default)) => {
+                if kdf.memory_kib.is_some() || kdf.time_cost.is_some() || kdf.parallelism.is_some()
+                {
+                    bail!("memory-kib, time-cost and parallelism do not apply to scrypt");
+                }
+                let log_n = match kdf.n {
+                    Some(n) if n < 2 || !n.is_power_of_two() => {
+                        bail!("scrypt n must be a power of two, e.g. 131072")
+                    }
+                    Some(n) => u8::try_from(n.trailing_zeros())?,
+                    None => default.log_n(),
+                };
+                KdfParams::scrypt(
+                    log_n,
+                    kdf.r.unwrap_or(default.r()),
+                    kdf.p.unwrap_or(default.p()),
+                )
+            }
+            None => bail!("unknown KDF '{algorithm}' (expected argon2id or scrypt)"),
+        }
+    }
+
+    /// Returns the parameters `keynest init` creates a keystore with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the KDF parameters are out of range or the cipher is unknown.
+    pub fn init_options(&self) -> Result<InitOptions> {
+        let mut options = InitOptions::new(self.kdf_params()?);
+        if let Some(cipher) = &self.cipher {
+            let Some(algorithm) = Algorithm::from_name(cipher) else {
+                bail!("unknown cipher '{cipher}' (expected xchacha20 or aes256-gcm)");
+            };
+            options = options.with_algorithm(algorithm);
+        }
+        if self.compress == Some(true) {
+            let encoding = options.encoding();
+            options = options.with_encoding(PayloadEncoding::new(
+                encoding.serialization(),
+                Compression::Zstd,
+                encoding.padding(),
+            ));
+        }
+        if self.padding == Some(true) {
+            let encoding = options.encoding();
+            options = options.with_encoding(PayloadEncoding::new(
+                encoding.serialization(),
+                encoding.compression(),
+                Padding::PowerOfTwo,
+            ));
+        }
+        Ok(options
+            .with_backups(self.backups.unwrap_or(0))
+            .with_dpapi(self.dpapi.unwrap_or(false)))
+    }
+}
+
+/// Returns the path of the config file: `config.toml` in the platform config directory
+/// (e.g. `~/.config/keynest/config.toml` on Linux).
+///
+/// # Errors
+///
+/// Returns an error if the platform-specific directories cannot be determined.
+pub fn default_config_path() -> Result<PathBuf> {
+    let project_dirs =
+        ProjectDirs::from("", "", "keynest").context("could not determine platform directories")?;
+    Ok(project_dirs.config_dir().join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_build_init_options() {
+        let config = Config::parse(
+            r#"
+            default-profile = "vault"
+
+            [profiles.vault]
+            store = "/tmp/vault.db"
+            cipher = "XChaCha20-Poly1305"
+            compress = true
+            padding = true
+            backups = 3
+            dpapi = true
+            min-password-score = 4
+            kdf = { memory-kib = 131072, time-cost = 5 }
+
+            [profiles.fips]
+            kdf = { algorithm = "scrypt", n = 32768 }
+
+            [profiles.dev]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.default_profile(), Some("vault"));
+        assert_eq!(
+            config.profile_names().collect::<Vec<_>>(),
+            ["dev", "fips", "vault"]
+        );
+
+        let vault = config.profile("vault").unwrap();
+        assert_eq!(vault.store(), Some(Path::new("/tmp/vault.db")));
+        let options = vault.init_options().unwrap();
+        assert_eq!(*options.kdf(), KdfParams::new(131072, 5, 1).unwrap());
+        assert_eq!(options.encoding().compression(), Compression::Zstd);
+        assert_eq!(options.encoding().padding(), Padding::PowerOfTwo);
+        assert_eq!(options.backups(), 3);
+        assert!(options.dpapi());
+        assert_eq!(vault.min_password_score(), Some(4));
+
+        let fips = config.profile("fips").unwrap();
+        assert_eq!(
+            fips.kdf_params().unwrap(),
+            KdfParams::scrypt(15, 8, 1).unwrap()
+        );
+
+        let dev = config.profile("dev").unwrap().init_options().unwrap();
+        assert_eq!(dev.encoding(), PayloadEncoding::compact());
+        assert_eq!(dev.backups(), 0);
+        assert!(!dev.dpapi());
+        assert_eq!(config.profile("dev").unwrap().min_password_score(), None);
+
+        let err = config.profile("prod").unwrap_err().to_string();
+        assert!(err.contains("configured: dev, fips, vault"), "{err}");
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        assert!(Config::parse("default-profile = \"missing\"").is_err());
+        assert!(Config::parse("[profiles.a]\nunknown = 1").is_err());
+        let bad_cipher = Config::parse("[profiles.a]\ncipher = \"rot13\"").unwrap();
+        assert!(bad_cipher.profile("a").unwrap().init_options().is_err());
+        let bad_kdf = Config::parse("[profiles.a]\nkdf = { time-cost = 0 }").unwrap();
+        assert!(bad_kdf.profile("a").unwrap().kdf_params().is_err());
+        for kdf in [
+            "{ algorithm = \"bcrypt\" }",
+            "{ algorithm = \"scrypt\", n = 1000 }",
+            "{ algorithm = \"scrypt\", time-cost = 2 }",
+            "{ n = 1024 }",
+        ] {
+            let bad_kdf = Config::parse(&format!("[profiles.a]\nkdf = {kdf}")).unwrap();
+            assert!(bad_kdf.profile("a").unwrap().kdf_params().is_err(), "{kdf}");
+        }
+        assert!(Config::parse("[templates.a]\nfields.b = {}").is_err());
+        assert!(Config::parse("[profiles.a]\nmin-password-score = 5").is_err());
+    }
+
+    #[test]
+    fn templates_are_read_from_the_config() {
+        let config = Config::parse(
+            r#"
+            [templates.postgres]
+            tags = ["db"]
+            fields.port = { default = "5432" }
+            "#,
+        )
+        .unwrap();
+        let postgres = &config.templates()["postgres"];
+        assert_eq!(postgres.tags(), ["db"]);
+        assert_eq!(postgres.fields()["port"].default_value(), Some("5432"));
+    }
+}
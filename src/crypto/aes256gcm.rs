@@ -0,0 +1,100 @@
+//! Authenticated encryption using AES-256-GCM.
+//!
+//! Offered for environments that mandate AES. Nonces are random (or synthetic, for
+//! deterministic records) and only 96 bits long, so a key should not encrypt more than
+//! about 2^32 messages; keynest derives a new key from a fresh salt on every rekey and
+//! stays far below that.
+
+use crate::crypto::KEY_LEN;
+
+use super::random::{self, EntropyUse};
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use anyhow::{Result, anyhow};
+use zeroize::Zeroizing;
+
+/// Length of the nonce (12 bytes for AES-256-GCM).
+pub const NONCE_LEN: usize = 12;
+
+/// Encrypts plaintext using AES-256-GCM.
+///
+/// # Errors
+///
+/// Returns an error if random number generation fails.
+pub fn encrypt(key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut nonce = vec![0u8; NONCE_LEN];
+    random::fill(EntropyUse::Nonce, &mut nonce)?;
+    let ciphertext = encrypt_with_nonce(key, &nonce, plaintext, aad)?;
+    Ok((ciphertext, nonce))
+}
+
+/// Encrypts plaintext using AES-256-GCM under `nonce`.
+///
+/// A nonce must never encrypt two different plaintexts under the same key; [`encrypt`]
+/// draws a random one.
+///
+/// # Errors
+///
+/// Returns an error if the key or nonce length is wrong.
+pub fn encrypt_with_nonce(
+    key: &[u8],
+    nonce: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    if key.len() != KEY_LEN {
+        return Err(anyhow!("invalid key length"));
+    }
+    if nonce.len() != NONCE_LEN {
+        return Err(anyhow!("invalid nonce length"));
+    }
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .encrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("encryption failed"))
+}
+
+/// Decrypts ciphertext using AES-256-GCM.
+///
+/// Returns the plaintext wrapped in `Zeroizing` for secure memory handling.
+///
+/// # Errors
+///
+/// Returns an error if the key is incorrect, or the ciphertext has been tampered with or
+/// is corrupted.
+pub fn decrypt(
+    key: &[u8],
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Zeroizing<Vec<u8>>> {
+    if key.len() != KEY_LEN {
+        return Err(anyhow!("invalid key length"));
+    }
+
+    if nonce.len() != NONCE_LEN {
+        return Err(anyhow!("invalid nonce length"));
+    }
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| super::DecryptionFailed)?;
+    Ok(Zeroizing::new(plaintext))
+}
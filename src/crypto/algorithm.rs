@@ -1,7 +1,7 @@
 use anyhow::Result;
 use zeroize::Zeroizing;
 
-use crate::crypto::chacha20poly1305;
+use crate::crypto::{aes256gcm, chacha20poly1305, synthetic_nonce};
 
 /// Encryption algorithm used for the keystore.
 #[repr(u8)]
@@ -9,6 +9,8 @@ use crate::crypto::chacha20poly1305;
 pub enum Algorithm {
     /// XChaCha20-Poly1305 authenticated encryption.
     XChaCha20Poly1305 = 1,
+    /// AES-256-GCM authenticated encryption, for environments that mandate AES.
+    Aes256Gcm = 2,
 }
 
 impl TryFrom<u8> for Algorithm {
@@ -17,6 +19,7 @@ impl TryFrom<u8> for Algorithm {
     fn try_from(value: u8) -> anyhow::Result<Self> {
         match value {
             1 => Ok(Self::XChaCha20Poly1305),
+            2 => Ok(Self::Aes256Gcm),
             _ => anyhow::bail!("unsupported algorithm id {}", value),
         }
     }
@@ -37,9 +40,33 @@ impl Algorithm {
     pub fn encrypt(self, key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
         match self {
             Algorithm::XChaCha20Poly1305 => chacha20poly1305::encrypt(key, plaintext, aad),
+            Algorithm::Aes256Gcm => aes256gcm::encrypt(key, plaintext, aad),
         }
     }
 
+    /// Encrypts plaintext under a synthetic nonce derived from `key`, `aad` and
+    /// `plaintext` (see [`synthetic_nonce`]), so encrypting the same plaintext again
+    /// gives the same `(ciphertext, nonce)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encryption fails.
+    pub(crate) fn encrypt_synthetic(
+        self,
+        key: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let nonce = synthetic_nonce::derive(key, aad, plaintext, self.nonce_len());
+        let ciphertext = match self {
+            Algorithm::XChaCha20Poly1305 => {
+                chacha20poly1305::encrypt_with_nonce(key, &nonce, plaintext, aad)?
+            }
+            Algorithm::Aes256Gcm => aes256gcm::encrypt_with_nonce(key, &nonce, plaintext, aad)?,
+        };
+        Ok((ciphertext, nonce))
+    }
+
     /// Decrypts ciphertext using the specified algorithm.
     ///
     /// # Errors
@@ -54,6 +81,7 @@ impl Algorithm {
     ) -> Result<Zeroizing<Vec<u8>>> {
         match self {
             Algorithm::XChaCha20Poly1305 => chacha20poly1305::decrypt(key, nonce, ciphertext, aad),
+            Algorithm::Aes256Gcm => aes256gcm::decrypt(key, nonce, ciphertext, aad),
         }
     }
 
@@ -61,6 +89,7 @@ impl Algorithm {
     pub fn nonce_len(self) -> usize {
         match self {
             Algorithm::XChaCha20Poly1305 => chacha20poly1305::NONCE_LEN,
+            Algorithm::Aes256Gcm => aes256gcm::NONCE_LEN,
         }
     }
 
@@ -68,6 +97,25 @@ impl Algorithm {
     pub fn name(&self) -> &'static str {
         match self {
             Self::XChaCha20Poly1305 => "XChaCha20-Poly1305",
+            Self::Aes256Gcm => "AES-256-GCM",
         }
     }
+
+    /// Returns the short name `init --cipher` takes for this algorithm.
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Self::XChaCha20Poly1305 => "xchacha20",
+            Self::Aes256Gcm => "aes256-gcm",
+        }
+    }
+
+    /// Looks up an algorithm by its name or short name, ignoring case (e.g.
+    /// `xchacha20-poly1305` or `aes256-gcm`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::XChaCha20Poly1305, Self::Aes256Gcm]
+            .into_iter()
+            .find(|a| {
+                a.name().eq_ignore_ascii_case(name) || a.short_name().eq_ignore_ascii_case(name)
+            })
+    }
 }
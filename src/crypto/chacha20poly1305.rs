@@ -3,26 +3,21 @@
 use crate::crypto::KEY_LEN;
 
 use super::SALT_LEN;
+use super::random::{self, EntropyUse};
 use anyhow::{Result, anyhow};
 use chacha20poly1305::{
     Key, XChaCha20Poly1305, XNonce,
     aead::{Aead, KeyInit, Payload},
 };
-use getrandom::fill;
 use zeroize::Zeroizing;
 
 /// Length of the nonce (24 bytes for XChaCha20-Poly1305).
 pub const NONCE_LEN: usize = 24;
 
-/// Fills buffer with cryptographically secure random bytes.
-fn secure_random(buf: &mut [u8]) -> Result<()> {
-    fill(buf).map_err(|_| anyhow!("OS random generator unavailable"))
-}
-
 /// Generates a random salt for key derivation.
 pub fn generate_salt() -> Result<[u8; SALT_LEN]> {
     let mut salt = [0u8; SALT_LEN];
-    secure_random(&mut salt)?;
+    random::fill(EntropyUse::Salt, &mut salt)?;
     Ok(salt)
 }
 
@@ -32,26 +27,43 @@ pub fn generate_salt() -> Result<[u8; SALT_LEN]> {
 ///
 /// Returns an error if random number generation fails.
 pub fn encrypt(key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut nonce = vec![0u8; NONCE_LEN];
+    random::fill(EntropyUse::Nonce, &mut nonce)?;
+    let ciphertext = encrypt_with_nonce(key, &nonce, plaintext, aad)?;
+    Ok((ciphertext, nonce))
+}
+
+/// Encrypts plaintext using XChaCha20-Poly1305 under `nonce`.
+///
+/// A nonce must never encrypt two different plaintexts under the same key; [`encrypt`]
+/// draws a random one.
+///
+/// # Errors
+///
+/// Returns an error if the key or nonce length is wrong.
+pub fn encrypt_with_nonce(
+    key: &[u8],
+    nonce: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
     if key.len() != KEY_LEN {
         return Err(anyhow!("invalid key length"));
     }
+    if nonce.len() != NONCE_LEN {
+        return Err(anyhow!("invalid nonce length"));
+    }
 
     let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
-
-    let mut nonce = vec![0u8; NONCE_LEN];
-    secure_random(&mut nonce)?;
-
-    let ciphertext = cipher
+    cipher
         .encrypt(
-            XNonce::from_slice(&nonce),
+            XNonce::from_slice(nonce),
             Payload {
                 msg: plaintext,
                 aad,
             },
         )
-        .map_err(|_| anyhow!("encryption failed"))?;
-
-    Ok((ciphertext, nonce))
+        .map_err(|_| anyhow!("encryption failed"))
 }
 
 /// Decrypts ciphertext using XChaCha20-Poly1305.
@@ -88,6 +100,6 @@ pub fn decrypt(
                 aad,
             },
         )
-        .map_err(|_| anyhow!("Invalid password or corrupted data"))?;
+        .map_err(|_| super::DecryptionFailed)?;
     Ok(Zeroizing::new(plaintext))
 }
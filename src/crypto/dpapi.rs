@@ -0,0 +1,188 @@
+//! Binding of the keystore key to a Windows user account with DPAPI.
+//!
+//! A bound keystore keeps a random secret in its header, protected with
+//! `CryptProtectData` for the current user. The file key is derived from the password as
+//! usual and then mixed with that secret, so opening the file needs the password *and*
+//! the Windows account (and machine) that created it. A copy of the file on another
+//! machine or under another user cannot be opened even with the right password.
+//!
+//! DPAPI exists only on Windows; elsewhere binding and opening a bound keystore fail.
+
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::KEY_LEN;
+use super::random::{self, EntropyUse};
+
+/// Length of the secret protected with DPAPI.
+const SECRET_LEN: usize = 32;
+/// Domain separation for the key binding and the DPAPI optional entropy.
+const CONTEXT: &[u8] = b"keynest dpapi v1";
+
+/// Creates a new binding: returns the secret and its DPAPI-protected blob, which is
+/// stored in the header.
+///
+/// # Errors
+///
+/// Returns an error if DPAPI is unavailable or fails.
+pub(crate) fn new_binding() -> Result<(Zeroizing<Vec<u8>>, Vec<u8>)> {
+    let mut secret = Zeroizing::new(vec![0u8; SECRET_LEN]);
+    random::fill(EntropyUse::Key, &mut secret)?;
+    let blob = imp::protect(&secret)?;
+    Ok((secret, blob))
+}
+
+/// Recovers the secret from the blob stored in the header.
+///
+/// # Errors
+///
+/// Returns an error if DPAPI is unavailable or the blob was protected for another
+/// account or machine.
+pub(crate) fn unprotect(blob: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let secret = imp::unprotect(blob)?;
+    if secret.len() != SECRET_LEN {
+        anyhow::bail!("invalid DPAPI secret length");
+    }
+    Ok(secret)
+}
+
+/// Mixes the password-derived `key` with the DPAPI secret.
+pub(crate) fn bind_key(key: &[u8; KEY_LEN], secret: &[u8]) -> [u8; KEY_LEN] {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(CONTEXT);
+    mac.update(secret);
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(windows)]
+mod imp {
+    use anyhow::{Result, bail};
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Cryptography::{
+        CRYPT_INTEGER_BLOB, CRYPTPROTECT_UI_FORBIDDEN, CryptProtectData, CryptUnprotectData,
+    };
+    use zeroize::Zeroizing;
+
+    use super::CONTEXT;
+
+    fn blob(data: &[u8]) -> Result<CRYPT_INTEGER_BLOB> {
+        Ok(CRYPT_INTEGER_BLOB {
+            cbData: u32::try_from(data.len())?,
+            pbData: data.as_ptr().cast_mut(),
+        })
+    }
+
+    /// Copies the output of a DPAPI call and frees it.
+    fn take(out: CRYPT_INTEGER_BLOB) -> Zeroizing<Vec<u8>> {
+        // SAFETY: DPAPI returned `cbData` bytes at `pbData`, allocated with LocalAlloc.
+        let data = unsafe { std::slice::from_raw_parts(out.pbData, out.cbData as usize) };
+        let copy = Zeroizing::new(data.to_vec());
+        // SAFETY: the buffer is owned by us and not used after this.
+        unsafe { LocalFree(out.pbData.cast()) };
+        copy
+    }
+
+    pub(super) fn protect(secret: &[u8]) -> Result<Vec<u8>> {
+        let input = blob(secret)?;
+        let entropy = blob(CONTEXT)?;
+        let mut out = CRYPT_INTEGER_BLOB {
+            cbData: 0,
+            pbData: std::ptr::null_mut(),
+        };
+        // SAFETY:
+        // - Input blobs point to live buffers for the duration of the call
+        // - DPAPI only reads them and allocates the output itself
+        let ok = unsafe {
+            CryptProtectData(
+                &input,
+                std::ptr::null(),
+                &entropy,
+                std::ptr::null(),
+                std::ptr::null(),
+                CRYPTPROTECT_UI_FORBIDDEN,
+                &mut out,
+            )
+        };
+        if ok == 0 {
+            bail!(
+                "DPAPI failed to protect the key: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(take(out).to_vec())
+    }
+
+    pub(super) fn unprotect(protected: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let input = blob(protected)?;
+        let entropy = blob(CONTEXT)?;
+        let mut out = CRYPT_INTEGER_BLOB {
+            cbData: 0,
+            pbData: std::ptr::null_mut(),
+        };
+        // SAFETY: see `protect`.
+        let ok = unsafe {
+            CryptUnprotectData(
+                &input,
+                std::ptr::null_mut(),
+                &entropy,
+                std::ptr::null(),
+                std::ptr::null(),
+                CRYPTPROTECT_UI_FORBIDDEN,
+                &mut out,
+            )
+        };
+        if ok == 0 {
+            bail!(
+                "keystore is bound to another Windows account or machine: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(take(out))
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use anyhow::{Result, bail};
+    use zeroize::Zeroizing;
+
+    pub(super) fn protect(_secret: &[u8]) -> Result<Vec<u8>> {
+        bail!("binding a keystore with DPAPI is only available on Windows")
+    }
+
+    pub(super) fn unprotect(_protected: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        bail!("keystore is bound to a Windows account with DPAPI and can only be opened there")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_depends_on_secret() {
+        let key = [1u8; KEY_LEN];
+        let bound = bind_key(&key, &[2u8; SECRET_LEN]);
+        assert_ne!(bound, key);
+        assert_eq!(bound, bind_key(&key, &[2u8; SECRET_LEN]));
+        assert_ne!(bound, bind_key(&key, &[3u8; SECRET_LEN]));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn protect_roundtrip() {
+        let (secret, blob) = new_binding().unwrap();
+        assert_eq!(unprotect(&blob).unwrap(), secret);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn unavailable_off_windows() {
+        let err = new_binding().unwrap_err().to_string();
+        assert!(err.contains("only available on Windows"), "{err}");
+        assert!(unprotect(&[1u8; 40]).is_err());
+    }
+}
@@ -1,20 +1,58 @@
-//! Key derivation using Argon2.
+//! Key derivation using Argon2id or scrypt.
 
-use anyhow::{Context, Result};
-use argon2::{Algorithm, Argon2, Params, Version};
+use anyhow::{Context, Result, bail};
+use argon2::{Argon2, Params, Version};
 use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use zeroize::Zeroizing;
 
 use super::KEY_LEN;
 
+/// How often [`derive_key_cancellable`] checks its cancel token.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Identifier of scrypt in encoded parameters; Argon2id parameters carry no identifier.
+const KDF_SCRYPT: u8 = 2;
+/// Length of encoded Argon2id parameters.
+const ARGON2_ENCODED_LEN: usize = 12;
+/// Largest scrypt cost exponent: N = 2^30 needs 128 GiB of memory with r = 1.
+pub const MAX_SCRYPT_LOG_N: u8 = 30;
+
+/// Key derivation function and its parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum KdfParams {
+    /// Argon2id, the default.
+    Argon2id(Argon2Params),
+    /// scrypt, for environments that mandate it.
+    Scrypt(ScryptParams),
+}
+
 /// Parameters for Argon2id key derivation.
-#[derive(Debug, Clone, Copy, Serialize)]
-pub struct KdfParams {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Argon2Params {
     mem_cost_kib: u32,
     time_cost: u32,
     parallelism: u32,
 }
 
+/// Parameters for scrypt key derivation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScryptParams {
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
 impl Default for KdfParams {
+    fn default() -> Self {
+        Self::Argon2id(Argon2Params::default())
+    }
+}
+
+impl Default for Argon2Params {
     fn default() -> Self {
         Self {
             // default memory cost
@@ -27,8 +65,136 @@ impl Default for KdfParams {
     }
 }
 
+impl Default for ScryptParams {
+    /// N = 2^17, r = 8, p = 1 (128 MiB), as recommended by OWASP.
+    fn default() -> Self {
+        Self {
+            log_n: 17,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
 impl KdfParams {
-    /// Creates new KDF parameters with validation.
+    /// Creates new Argon2id parameters with validation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parameters don't meet minimum requirements.
+    pub fn new(mem_cost_kib: u32, time_cost: u32, parallelism: u32) -> anyhow::Result<Self> {
+        Argon2Params::new(mem_cost_kib, time_cost, parallelism).map(Self::Argon2id)
+    }
+
+    /// Creates new scrypt parameters with validation; the cost is N = 2^`log_n`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parameters are out of range.
+    pub fn scrypt(log_n: u8, r: u32, p: u32) -> anyhow::Result<Self> {
+        ScryptParams::new(log_n, r, p).map(Self::Scrypt)
+    }
+
+    /// Returns the default parameters of the KDF called `name` (`argon2id` or `scrypt`,
+    /// ignoring case), or `None` for an unknown name.
+    pub fn default_for(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("argon2id") {
+            Some(Self::Argon2id(Argon2Params::default()))
+        } else if name.eq_ignore_ascii_case("scrypt") {
+            Some(Self::Scrypt(ScryptParams::default()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the name of the KDF: `argon2id` or `scrypt`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Argon2id(_) => "argon2id",
+            Self::Scrypt(_) => "scrypt",
+        }
+    }
+
+    /// Returns the memory one derivation needs, in KiB: the memory cost of Argon2id, or
+    /// 128 * r * N bytes for scrypt.
+    pub fn memory_kib(&self) -> u64 {
+        match self {
+            Self::Argon2id(params) => u64::from(params.mem_cost_kib),
+            Self::Scrypt(params) => 128 * u64::from(params.r) * params.n() / 1024,
+        }
+    }
+
+    /// Validates the parameters meet the requirements of the KDF.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first parameter out of range.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Argon2id(params) => params.validate(),
+            Self::Scrypt(params) => params.validate(),
+        }
+    }
+
+    /// Encodes the parameters as stored in keystore headers and keyslots: the 12 bytes
+    /// of Argon2id parameters, or a KDF identifier followed by the parameters of others.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Argon2id(params) => {
+                out.extend_from_slice(&params.mem_cost_kib.to_le_bytes());
+                out.extend_from_slice(&params.time_cost.to_le_bytes());
+                out.extend_from_slice(&params.parallelism.to_le_bytes());
+            }
+            Self::Scrypt(params) => {
+                out.push(KDF_SCRYPT);
+                out.extend_from_slice(&u32::from(params.log_n).to_le_bytes());
+                out.extend_from_slice(&params.r.to_le_bytes());
+                out.extend_from_slice(&params.p.to_le_bytes());
+            }
+        }
+    }
+
+    /// Decodes parameters written by [`KdfParams::encode`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an unknown KDF, a wrong length, or invalid parameters.
+    pub(crate) fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        match bytes.len() {
+            ARGON2_ENCODED_LEN => Self::new(u32_at(0), u32_at(4), u32_at(8)),
+            len if len == ARGON2_ENCODED_LEN + 1 && bytes[0] == KDF_SCRYPT => {
+                let Ok(log_n) = u8::try_from(u32_at(1)) else {
+                    bail!("scrypt cost exponent out of range");
+                };
+                Self::scrypt(log_n, u32_at(5), u32_at(9))
+            }
+            len if len == ARGON2_ENCODED_LEN + 1 => bail!("unsupported KDF id {}", bytes[0]),
+            _ => bail!("invalid kdf length"),
+        }
+    }
+}
+
+impl std::fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Argon2id(params) => write!(
+                f,
+                "argon2id m={} KiB, t={}, p={}",
+                params.mem_cost_kib, params.time_cost, params.parallelism
+            ),
+            Self::Scrypt(params) => write!(
+                f,
+                "scrypt N=2^{}, r={}, p={}",
+                params.log_n, params.r, params.p
+            ),
+        }
+    }
+}
+
+impl Argon2Params {
+    /// Creates new Argon2id parameters with validation.
     ///
     /// # Errors
     ///
@@ -84,34 +250,196 @@ impl KdfParams {
     }
 }
 
-/// Derives a 256-bit key from a password using Argon2id.
+impl ScryptParams {
+    /// Creates new scrypt parameters with validation; the cost is N = 2^`log_n`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parameters are out of range.
+    pub fn new(log_n: u8, r: u32, p: u32) -> anyhow::Result<Self> {
+        let params = Self { log_n, r, p };
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Returns the base-2 logarithm of the cost N.
+    pub fn log_n(&self) -> u8 {
+        self.log_n
+    }
+
+    /// Returns the cost N.
+    pub fn n(&self) -> u64 {
+        1 << self.log_n
+    }
+
+    /// Returns the block size r.
+    pub fn r(&self) -> u32 {
+        self.r
+    }
+
+    /// Returns the parallelism p.
+    pub fn p(&self) -> u32 {
+        self.p
+    }
+
+    /// Validates the parameters are in range.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - log2(N) is not between 1 and [`MAX_SCRYPT_LOG_N`]
+    /// - r or p is 0, or r * p >= 2^30
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(1..=MAX_SCRYPT_LOG_N).contains(&self.log_n) {
+            bail!("scrypt N must be a power of two between 2 and 2^{MAX_SCRYPT_LOG_N}");
+        }
+        self.to_scrypt()?;
+        Ok(())
+    }
+
+    fn to_scrypt(self) -> anyhow::Result<scrypt::Params> {
+        if self.r < 1 || self.p < 1 {
+            bail!("scrypt r and p must be >= 1");
+        }
+        scrypt::Params::new(self.log_n, self.r, self.p, KEY_LEN)
+            .map_err(|_| anyhow::anyhow!("scrypt r * p must be below 2^30"))
+    }
+}
+
+/// Derives a 256-bit key from a password with the KDF of `kdf`.
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - KDF parameters are invalid
-/// - Argon2 fails to derive the key
+/// - The KDF fails to derive the key
 pub fn derive_key(password: &str, salt: &[u8], kdf: KdfParams) -> Result<[u8; KEY_LEN]> {
-    kdf.validate().context("invalid Argon2 parameters")?;
-
-    let params = Params::new(
-        kdf.mem_cost_kib,
-        kdf.time_cost,
-        kdf.parallelism,
-        Some(KEY_LEN),
-    )
-    .map_err(|e| anyhow::anyhow!("failed to construct Argon2 params: {e}"))?;
-
-    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    kdf.validate().context("invalid KDF parameters")?;
 
     let mut key = [0u8; KEY_LEN];
-    argon2
-        .hash_password_into(password.as_bytes(), salt, &mut key)
-        .map_err(|e| anyhow::anyhow!("argon2 key derivation failed: {e}"))?;
+    match kdf {
+        KdfParams::Argon2id(kdf) => {
+            let params = Params::new(
+                kdf.mem_cost_kib,
+                kdf.time_cost,
+                kdf.parallelism,
+                Some(KEY_LEN),
+            )
+            .map_err(|e| anyhow::anyhow!("failed to construct Argon2 params: {e}"))?;
+
+            let argon2 = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params);
+            argon2
+                .hash_password_into(password.as_bytes(), salt, &mut key)
+                .map_err(|e| anyhow::anyhow!("argon2 key derivation failed: {e}"))?;
+        }
+        KdfParams::Scrypt(kdf) => {
+            scrypt::scrypt(password.as_bytes(), salt, &kdf.to_scrypt()?, &mut key)
+                .map_err(|e| anyhow::anyhow!("scrypt key derivation failed: {e}"))?;
+        }
+    }
 
     Ok(key)
 }
 
+/// A cloneable flag used to cancel a running key derivation.
+///
+/// All clones share the same state, so a token can be handed to a signal handler or
+/// another thread and cancelled from there.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a new, not yet cancelled token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every operation waiting on this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The key of a keystore, as derived from its master password.
+///
+/// Lets a long-running process unlock the same keystore again without re-running the
+/// KDF, e.g. an agent serving other processes. Zeroized on drop.
+pub struct UnlockKey(Zeroizing<[u8; KEY_LEN]>);
+
+impl UnlockKey {
+    /// Wraps the raw bytes of a key.
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    /// Returns the raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+}
+
+/// Error returned when an operation is aborted through a [`CancelToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "key derivation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Derives a key like `derive_key`, but runs the KDF on a worker thread so the caller
+/// can give up early.
+///
+/// Returns a [`Cancelled`] error as soon as `cancel` is triggered. The KDF itself cannot
+/// be interrupted: the worker finishes in the background and its result is zeroized
+/// and discarded.
+///
+/// # Errors
+///
+/// Returns an error if the derivation is cancelled, the KDF parameters are invalid, or
+/// the KDF fails.
+pub fn derive_key_cancellable(
+    password: &str,
+    salt: &[u8],
+    kdf: KdfParams,
+    cancel: &CancelToken,
+) -> Result<[u8; KEY_LEN]> {
+    if cancel.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+
+    let password = Zeroizing::new(password.to_string());
+    let salt = salt.to_vec();
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::Builder::new()
+        .name("keynest-kdf".to_string())
+        .spawn(move || {
+            let result = derive_key(&password, &salt, kdf).map(Zeroizing::new);
+            let _ = tx.send(result);
+        })
+        .context("failed to spawn key derivation thread")?;
+
+    loop {
+        if cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        match rx.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(result) => return result.map(|key| *key),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("key derivation thread panicked"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,17 +461,8 @@ mod tests {
 
         let salt = [7u8; 16];
 
-        let kdf1 = KdfParams {
-            mem_cost_kib: 32768,
-            time_cost: 2,
-            parallelism: 1,
-        };
-
-        let kdf2 = KdfParams {
-            mem_cost_kib: 65536,
-            time_cost: 2,
-            parallelism: 1,
-        };
+        let kdf1 = KdfParams::new(32768, 2, 1).unwrap();
+        let kdf2 = KdfParams::new(65536, 2, 1).unwrap();
 
         let k1 = derive_key("pw", &salt, kdf1).unwrap();
         let k2 = derive_key("pw", &salt, kdf2).unwrap();
@@ -155,5 +474,72 @@ mod tests {
     fn kdf_invalid_params_fail_gracefully() {
         use crate::crypto::KdfParams;
         assert!(KdfParams::new(0, 0, 0).is_err());
+        assert!(KdfParams::scrypt(0, 8, 1).is_err());
+        assert!(KdfParams::scrypt(MAX_SCRYPT_LOG_N + 1, 8, 1).is_err());
+        assert!(KdfParams::scrypt(4, 0, 1).is_err());
+    }
+
+    #[test]
+    fn scrypt_params_roundtrip_and_derive_a_different_key() {
+        let salt = [5u8; 16];
+        let argon2 = KdfParams::new(8, 1, 1).unwrap();
+        let scrypt = KdfParams::scrypt(4, 8, 1).unwrap();
+
+        for kdf in [argon2, scrypt] {
+            let mut encoded = Vec::new();
+            kdf.encode(&mut encoded);
+            assert_eq!(KdfParams::decode(&encoded).unwrap(), kdf);
+        }
+        let mut encoded = Vec::new();
+        scrypt.encode(&mut encoded);
+        assert_eq!(encoded.len(), 13);
+        encoded[0] = 99;
+        assert!(KdfParams::decode(&encoded).is_err());
+
+        assert_eq!(scrypt.to_string(), "scrypt N=2^4, r=8, p=1");
+        assert_ne!(
+            derive_key("pw", &salt, argon2).unwrap(),
+            derive_key("pw", &salt, scrypt).unwrap()
+        );
+        assert_eq!(
+            derive_key("pw", &salt, scrypt).unwrap(),
+            derive_key("pw", &salt, scrypt).unwrap()
+        );
+    }
+
+    #[test]
+    fn cancellable_matches_derive_key() {
+        let salt = [3u8; 16];
+        let kdf = KdfParams::default();
+
+        let key = derive_key_cancellable("pw", &salt, kdf, &CancelToken::new()).unwrap();
+        assert_eq!(key, derive_key("pw", &salt, kdf).unwrap());
+    }
+
+    #[test]
+    fn cancelled_token_aborts_derivation() {
+        let salt = [3u8; 16];
+        let token = CancelToken::new();
+
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+
+        // Takes far longer than the 20ms before cancellation.
+        let kdf = KdfParams::new(16 * 1024, 10, 1).unwrap();
+        let err = derive_key_cancellable("pw", &salt, kdf, &token).unwrap_err();
+        assert!(err.is::<Cancelled>());
+    }
+
+    #[test]
+    fn already_cancelled_token_fails_immediately() {
+        let token = CancelToken::new();
+        token.cancel();
+
+        let err =
+            derive_key_cancellable("pw", &[0u8; 16], KdfParams::default(), &token).unwrap_err();
+        assert_eq!(err.to_string(), "key derivation cancelled");
     }
 }
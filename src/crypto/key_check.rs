@@ -0,0 +1,46 @@
+//! Key-check values: a short MAC of the key kept in the header, so opening a keystore
+//! can tell a wrong password apart from damaged data.
+//!
+//! The value is `HMAC-SHA256(key, "keynest key check v1")` truncated to 16 bytes. Testing
+//! a password guess against it costs a key derivation, exactly like decrypting the
+//! index, so it gives an attacker nothing the ciphertext does not.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Length of a key-check value.
+pub(crate) const KEY_CHECK_LEN: usize = 16;
+
+/// Domain separation for the key check.
+const CONTEXT: &[u8] = b"keynest key check v1";
+
+/// Computes the key-check value of `key`.
+pub(crate) fn compute(key: &[u8]) -> Vec<u8> {
+    mac(key).finalize().into_bytes()[..KEY_CHECK_LEN].to_vec()
+}
+
+/// Returns `true` if `check` is the key-check value of `key`, in constant time.
+pub(crate) fn verify(key: &[u8], check: &[u8]) -> bool {
+    check.len() == KEY_CHECK_LEN && mac(key).verify_truncated_left(check).is_ok()
+}
+
+fn mac(key: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(CONTEXT);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_matches_only_its_key() {
+        let check = compute(&[1u8; 32]);
+        assert_eq!(check.len(), KEY_CHECK_LEN);
+        assert!(verify(&[1u8; 32], &check));
+        assert!(!verify(&[2u8; 32], &check));
+        assert!(!verify(&[1u8; 32], &check[..8]));
+    }
+}
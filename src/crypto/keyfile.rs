@@ -0,0 +1,87 @@
+//! Keyfiles: a file the key depends on besides the password.
+//!
+//! A keystore created with a keyfile records that in its header. Its key is derived
+//! from the password as usual and then mixed with the SHA-256 digest of the keyfile, so
+//! opening the file needs the password *and* the keyfile. Any file works as a keyfile;
+//! its contents must never change.
+
+use anyhow::{Context, Result, bail};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+use super::KEY_LEN;
+
+/// Domain separation for the key binding.
+const CONTEXT: &[u8] = b"keynest keyfile v1";
+
+/// The contents of a keyfile, reduced to their digest. Zeroized on drop.
+#[derive(Clone)]
+pub struct Keyfile(Zeroizing<[u8; 32]>);
+
+impl Keyfile {
+    /// Reads the keyfile at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is empty.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = Zeroizing::new(
+            std::fs::read(path)
+                .with_context(|| format!("failed to read keyfile {}", path.display()))?,
+        );
+        if contents.is_empty() {
+            bail!("keyfile {} is empty", path.display());
+        }
+        Ok(Self::from_bytes(&contents))
+    }
+
+    /// Creates a keyfile from its contents.
+    pub fn from_bytes(contents: &[u8]) -> Self {
+        Self(Zeroizing::new(Sha256::digest(contents).into()))
+    }
+
+    /// Mixes the password-derived `key` with the keyfile.
+    pub(crate) fn bind_key(&self, key: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(CONTEXT);
+        mac.update(&*self.0);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+impl std::fmt::Debug for Keyfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Keyfile(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_depends_on_contents() {
+        let key = [1u8; KEY_LEN];
+        let bound = Keyfile::from_bytes(b"one").bind_key(&key);
+        assert_ne!(bound, key);
+        assert_eq!(bound, Keyfile::from_bytes(b"one").bind_key(&key));
+        assert_ne!(bound, Keyfile::from_bytes(b"two").bind_key(&key));
+    }
+
+    #[test]
+    fn empty_keyfiles_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        std::fs::write(&path, b"").unwrap();
+        assert!(
+            Keyfile::read(&path)
+                .unwrap_err()
+                .to_string()
+                .contains("empty")
+        );
+        assert!(Keyfile::read(&dir.path().join("missing")).is_err());
+    }
+}
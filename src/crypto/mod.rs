@@ -1,14 +1,37 @@
 //! Cryptographic primitives for the keystore.
 //!
 //! Provides encryption and key derivation functions.
+pub mod aes256gcm;
 pub mod algorithm;
 pub mod chacha20poly1305;
+pub(crate) mod dpapi;
 pub mod kdf;
+pub(crate) mod key_check;
+pub(crate) mod keyfile;
+pub mod random;
+pub(crate) mod synthetic_nonce;
 
 pub use chacha20poly1305::generate_salt;
-pub use kdf::{KdfParams, derive_key};
+pub use kdf::{
+    Argon2Params, CancelToken, Cancelled, KdfParams, ScryptParams, UnlockKey, derive_key,
+    derive_key_cancellable,
+};
+pub use keyfile::Keyfile;
 
 /// Length of the salt (16 bytes).
 pub const SALT_LEN: usize = 16;
 /// Length of the encryption key (32 bytes / 256 bits).
 pub const KEY_LEN: usize = 32;
+
+/// Error returned when a ciphertext does not authenticate: the key is wrong or the data
+/// was changed. Opening a keystore tells the two apart (see [`crate::WrongPassword`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DecryptionFailed;
+
+impl std::fmt::Display for DecryptionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid password or corrupted data")
+    }
+}
+
+impl std::error::Error for DecryptionFailed {}
@@ -0,0 +1,125 @@
+//! Source of the random bytes used for salts, nonces and keys.
+//!
+//! Every consumer in the crate draws through [`fill`], naming what the bytes are for.
+//! They come from the OS generator unless an [`EntropySource`] is set, either for one
+//! keystore ([`crate::Keynest::set_entropy_source`]) or, with the `test-utils` feature,
+//! for the current thread ([`crate::test_utils::seed_rng`]).
+
+use anyhow::{Result, anyhow};
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+/// What requested random bytes are used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EntropyUse {
+    /// A KDF salt, or the salt of the key index.
+    Salt,
+    /// An AEAD nonce or an SSH certificate nonce.
+    Nonce,
+    /// Key material: the attachment key, the data key of keyslots, an SSH CA key, or
+    /// the hash key of a redacted export.
+    Key,
+    /// A generated password or passphrase.
+    Password,
+}
+
+/// A source of cryptographically secure random bytes.
+///
+/// Implementations see every request together with its [`EntropyUse`], so wrapping
+/// [`OsEntropy`] is enough to audit what a keystore consumes.
+pub trait EntropySource: fmt::Debug + Send + Sync {
+    /// Fills `buf` with random bytes for `purpose`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no random bytes are available.
+    fn fill(&self, purpose: EntropyUse, buf: &mut [u8]) -> Result<()>;
+}
+
+/// The OS random generator.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsEntropy;
+
+impl EntropySource for OsEntropy {
+    fn fill(&self, _purpose: EntropyUse, buf: &mut [u8]) -> Result<()> {
+        getrandom::fill(buf).map_err(|_| anyhow!("OS random generator unavailable"))
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<dyn EntropySource>>> = const { RefCell::new(None) };
+}
+
+/// Fills `buf` with random bytes for `purpose` from the current source.
+///
+/// # Errors
+///
+/// Returns an error if the source fails.
+pub fn fill(purpose: EntropyUse, buf: &mut [u8]) -> Result<()> {
+    match CURRENT.with(|current| current.borrow().clone()) {
+        Some(source) => source.fill(purpose, buf),
+        None => OsEntropy.fill(purpose, buf),
+    }
+}
+
+/// Makes [`fill`] use `source` on this thread until the returned guard is dropped;
+/// with `None` the current source stays in place.
+pub(crate) fn scope(source: Option<&Arc<dyn EntropySource>>) -> Scope {
+    Scope {
+        previous: source
+            .map(|source| CURRENT.with(|current| current.replace(Some(source.clone())))),
+    }
+}
+
+/// Guard returned by [`scope`]; restores the previous source on drop.
+pub(crate) struct Scope {
+    previous: Option<Option<Arc<dyn EntropySource>>>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            CURRENT.with(|current| current.replace(previous));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<(EntropyUse, usize)>>);
+
+    impl EntropySource for Recorder {
+        fn fill(&self, purpose: EntropyUse, buf: &mut [u8]) -> Result<()> {
+            self.0.lock().unwrap().push((purpose, buf.len()));
+            buf.fill(7);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn scoped_source_is_restored() {
+        let recorder = Arc::new(Recorder::default());
+        let source: Arc<dyn EntropySource> = recorder.clone();
+        let mut buf = [0u8; 4];
+        {
+            let _scope = scope(Some(&source));
+            fill(EntropyUse::Nonce, &mut buf).unwrap();
+            let _inner = scope(None);
+            fill(EntropyUse::Key, &mut buf[..1]).unwrap();
+        }
+        assert_eq!(buf, [7; 4]);
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [(EntropyUse::Nonce, 4), (EntropyUse::Key, 1)]
+        );
+
+        fill(EntropyUse::Salt, &mut buf).unwrap();
+        assert_eq!(recorder.0.lock().unwrap().len(), 2);
+    }
+}
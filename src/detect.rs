@@ -0,0 +1,385 @@
+//! Detection of common secret value formats.
+//!
+//! Values are plain strings, but many of them have a recognizable structure (PEM
+//! blocks, JSON documents, JWTs, UUIDs, SSH certificates, base64 blobs). [`detect`]
+//! classifies a value so callers can display it appropriately (`get --pretty`) or check
+//! it, and the decoders here extract what is worth showing without verifying anything
+//! cryptographically.
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use x509_cert::Certificate;
+use x509_cert::der::Decode;
+use zeroize::Zeroizing;
+
+use crate::otp::OtpAuth;
+use crate::ssh::SshCertificateInfo;
+
+/// Shortest value considered a base64 blob rather than a word or short token.
+const MIN_BASE64_LEN: usize = 16;
+
+/// The detected format of a secret value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// One or more PEM blocks (certificates, keys, ...).
+    Pem,
+    /// A JSON Web Token (`header.claims.signature`).
+    Jwt,
+    /// A JSON object or array.
+    Json,
+    /// A UUID (`8-4-4-4-12` hex digits).
+    Uuid,
+    /// An `otpauth://` URI (see [`OtpAuth`]).
+    OtpAuth,
+    /// An OpenSSH certificate (see [`SshCertificateInfo`]).
+    SshCertificate,
+    /// Standard base64 with padding.
+    Base64,
+    /// Anything else.
+    Text,
+}
+
+impl ValueKind {
+    /// Returns the lowercase name of the format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pem => "pem",
+            Self::Jwt => "jwt",
+            Self::Json => "json",
+            Self::Uuid => "uuid",
+            Self::OtpAuth => "otpauth",
+            Self::SshCertificate => "ssh-cert",
+            Self::Base64 => "base64",
+            Self::Text => "text",
+        }
+    }
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Detects the format of `value`.
+///
+/// Formats are tried from the most to the least specific, so a JWT is not reported as
+/// text and a PEM block is not reported as base64.
+pub fn detect(value: &str) -> ValueKind {
+    let trimmed = value.trim();
+    if trimmed.starts_with("-----BEGIN ") && parse_pem(trimmed).is_ok() {
+        ValueKind::Pem
+    } else if decode_jwt(trimmed).is_ok() {
+        ValueKind::Jwt
+    } else if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde::de::IgnoredAny>(trimmed).is_ok()
+    {
+        ValueKind::Json
+    } else if is_uuid(trimmed) {
+        ValueKind::Uuid
+    } else if OtpAuth::is_otp_uri(trimmed) {
+        ValueKind::OtpAuth
+    } else if SshCertificateInfo::is_certificate(trimmed) {
+        ValueKind::SshCertificate
+    } else if is_base64(trimmed) {
+        ValueKind::Base64
+    } else {
+        ValueKind::Text
+    }
+}
+
+/// A decoded PEM block.
+pub struct PemBlock {
+    label: String,
+    der: Zeroizing<Vec<u8>>,
+}
+
+impl PemBlock {
+    /// Returns the label, e.g. `CERTIFICATE` or `PRIVATE KEY`.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the decoded contents.
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// Returns `true` for an X.509 certificate block.
+    pub fn is_certificate(&self) -> bool {
+        self.label == "CERTIFICATE"
+    }
+}
+
+/// Parses all PEM blocks in `value`; text outside the blocks is ignored.
+///
+/// # Errors
+///
+/// Returns an error if there is no block, a block is not terminated, or its body is
+/// not valid base64.
+pub fn parse_pem(value: &str) -> Result<Vec<PemBlock>> {
+    let mut blocks = Vec::new();
+    let mut lines = value.lines().map(str::trim);
+
+    while let Some(line) = lines.next() {
+        let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|l| l.strip_suffix("-----"))
+        else {
+            continue;
+        };
+        let end = format!("-----END {label}-----");
+
+        let mut body = Zeroizing::new(String::new());
+        let mut terminated = false;
+        for line in lines.by_ref() {
+            if line == end {
+                terminated = true;
+                break;
+            }
+            // Skip RFC 1421 headers such as `Proc-Type:` in legacy encrypted keys.
+            if !line.contains(':') {
+                body.push_str(line);
+            }
+        }
+        if !terminated {
+            bail!("PEM block '{label}' is not terminated");
+        }
+
+        let der = STANDARD
+            .decode(body.as_bytes())
+            .with_context(|| format!("PEM block '{label}' is not valid base64"))?;
+        blocks.push(PemBlock {
+            label: label.to_string(),
+            der: Zeroizing::new(der),
+        });
+    }
+
+    if blocks.is_empty() {
+        bail!("no PEM block found");
+    }
+    Ok(blocks)
+}
+
+/// The decoded header and claims of a JWT. The signature is not verified.
+pub struct Jwt {
+    header: serde_json::Value,
+    claims: serde_json::Value,
+}
+
+impl Jwt {
+    /// Returns the JOSE header.
+    pub fn header(&self) -> &serde_json::Value {
+        &self.header
+    }
+
+    /// Returns the claims.
+    pub fn claims(&self) -> &serde_json::Value {
+        &self.claims
+    }
+
+    /// Returns the `alg` header parameter.
+    pub fn algorithm(&self) -> Option<&str> {
+        self.header.get("alg").and_then(|a| a.as_str())
+    }
+
+    /// Returns the time of a numeric date claim such as `exp`, `iat`, or `nbf`.
+    pub fn time_claim(&self, name: &str) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(self.claims.get(name)?.as_i64()?, 0)
+    }
+}
+
+/// Decodes the header and claims of a compact-serialized JWT.
+///
+/// # Errors
+///
+/// Returns an error if `value` does not have three base64url segments, or the header
+/// is not a JSON object with an `alg` or the claims are not JSON.
+pub fn decode_jwt(value: &str) -> Result<Jwt> {
+    let segments: Vec<&str> = value.trim().split('.').collect();
+    let [header, claims, _signature] = segments.as_slice() else {
+        bail!("a JWT has three segments");
+    };
+
+    let decode = |segment: &str| -> Result<serde_json::Value> {
+        let bytes = URL_SAFE_NO_PAD.decode(segment)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    };
+    let header = decode(header).context("invalid JWT header")?;
+    if header.get("alg").and_then(|a| a.as_str()).is_none() {
+        bail!("JWT header has no alg");
+    }
+    let claims = decode(claims).context("invalid JWT claims")?;
+
+    Ok(Jwt { header, claims })
+}
+
+/// The fields of an X.509 certificate that identify it.
+pub struct CertificateSummary {
+    subject: String,
+    issuer: String,
+    serial: String,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+}
+
+impl CertificateSummary {
+    /// Returns the subject distinguished name (RFC 4514).
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Returns the issuer distinguished name (RFC 4514).
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    /// Returns the serial number as colon-separated hex.
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// Returns the start of the validity period.
+    pub fn not_before(&self) -> DateTime<Utc> {
+        self.not_before
+    }
+
+    /// Returns the end of the validity period.
+    pub fn not_after(&self) -> DateTime<Utc> {
+        self.not_after
+    }
+
+    /// Returns `true` if `now` lies outside the validity period.
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        now < self.not_before || now > self.not_after
+    }
+}
+
+/// Summarizes a DER-encoded X.509 certificate.
+///
+/// # Errors
+///
+/// Returns an error if `der` is not a valid certificate.
+pub fn certificate_summary(der: &[u8]) -> Result<CertificateSummary> {
+    let cert = Certificate::from_der(der).context("invalid X.509 certificate")?;
+    let tbs = &cert.tbs_certificate;
+
+    let time = |t: x509_cert::time::Time| {
+        let secs = i64::try_from(t.to_unix_duration().as_secs()).ok();
+        secs.and_then(|s| DateTime::from_timestamp(s, 0))
+            .context("certificate validity is out of range")
+    };
+
+    Ok(CertificateSummary {
+        subject: tbs.subject.to_string(),
+        issuer: tbs.issuer.to_string(),
+        serial: tbs
+            .serial_number
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(":"),
+        not_before: time(tbs.validity.not_before)?,
+        not_after: time(tbs.validity.not_after)?,
+    })
+}
+
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Long, padded-to-length standard base64 that is not just a hex string.
+fn is_base64(value: &str) -> bool {
+    value.len() >= MIN_BASE64_LEN
+        && value.len() % 4 == 0
+        && !value.bytes().all(|b| b.is_ascii_hexdigit())
+        && STANDARD.decode(value).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBnzCCAUWgAwIBAgICEjQwCgYIKoZIzj0EAwIwLjEVMBMGA1UEAwwMZXhhbXBs
+ZS50ZXN0MRUwEwYDVQQKDAxLZXluZXN0IFRlc3QwHhcNMjYxMDE3MjIxODU3WhcN
+MzYxMDE0MjIxODU3WjAuMRUwEwYDVQQDDAxleGFtcGxlLnRlc3QxFTATBgNVBAoM
+DEtleW5lc3QgVGVzdDBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABFznqFgcynVz
+jf2y5gfN+bdN6pWvf6MN1Z61qQzqOoKwF9RRP++N5nJBIImpawRTqveFgtG45APd
+P2cjPV7nG0qjUzBRMB0GA1UdDgQWBBTeGyJ4V/2pMIF85NQf/e1/oWA/bjAfBgNV
+HSMEGDAWgBTeGyJ4V/2pMIF85NQf/e1/oWA/bjAPBgNVHRMBAf8EBTADAQH/MAoG
+CCqGSM49BAMCA0gAMEUCIQC1CUnDGTm1bIrVEa+xxSxUaGKEXQgBvAgp0RgTIl78
+rAIgI/u1WQbhiPJYYAraWUSMbCgpTybtGN+PHtY/7cZr/Ng=
+-----END CERTIFICATE-----
+";
+
+    // {"alg":"HS256","typ":"JWT"}.{"sub":"1234567890","name":"John Doe","iat":1516239022}
+    const JWT: &str = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.\
+        eyJzdWIiOiIxMjM0NTY3ODkwIiwibmFtZSI6IkpvaG4gRG9lIiwiaWF0IjoxNTE2MjM5MDIyfQ.\
+        SflKxwRJSMeKKF2QT4fwpMeJf36POk6yJV_adQssw5c";
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(detect(CERT), ValueKind::Pem);
+        assert_eq!(detect(JWT), ValueKind::Jwt);
+        assert_eq!(detect(r#"{"user": "admin"}"#), ValueKind::Json);
+        assert_eq!(
+            detect("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            ValueKind::Uuid
+        );
+        assert_eq!(
+            detect("otpauth://totp/x?secret=JBSWY3DP"),
+            ValueKind::OtpAuth
+        );
+        assert_eq!(detect("c2VjcmV0IGJpbmFyeSBkYXRh"), ValueKind::Base64);
+        assert_eq!(detect("hunter2"), ValueKind::Text);
+        assert_eq!(detect("deadbeefdeadbeefdeadbeef"), ValueKind::Text);
+        assert_eq!(detect("{not json"), ValueKind::Text);
+        assert_eq!(detect("a.b.c"), ValueKind::Text);
+    }
+
+    #[test]
+    fn decodes_jwt_claims() {
+        let jwt = decode_jwt(JWT).unwrap();
+        assert_eq!(jwt.algorithm(), Some("HS256"));
+        assert_eq!(jwt.claims()["name"], "John Doe");
+        assert_eq!(
+            jwt.time_claim("iat").unwrap().to_rfc3339(),
+            "2018-01-18T01:30:22+00:00"
+        );
+        assert!(jwt.time_claim("exp").is_none());
+    }
+
+    #[test]
+    fn summarizes_certificates() {
+        let blocks = parse_pem(CERT).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].is_certificate());
+
+        let summary = certificate_summary(blocks[0].der()).unwrap();
+        assert_eq!(summary.subject(), "O=Keynest Test,CN=example.test");
+        assert_eq!(summary.issuer(), summary.subject());
+        assert_eq!(summary.serial(), "12:34");
+        assert_eq!(
+            summary.not_after().to_rfc3339(),
+            "2036-10-14T22:18:57+00:00"
+        );
+        assert!(!summary.is_expired_at(summary.not_before()));
+        assert!(summary.is_expired_at(summary.not_after() + chrono::Duration::seconds(1)));
+    }
+
+    #[test]
+    fn rejects_broken_pem() {
+        assert!(parse_pem("-----BEGIN CERTIFICATE-----\nAAAA\n").is_err());
+        assert!(parse_pem("no blocks here").is_err());
+        assert!(parse_pem("-----BEGIN X-----\n!!!\n-----END X-----").is_err());
+    }
+}
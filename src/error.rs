@@ -9,6 +9,28 @@ pub enum StoreError {
     KeyAlreadyExists(String),
     /// No secret with this key was found.
     KeyNotFound(String),
+    /// Following `ref:` values leads back to an entry already on the path.
+    ReferenceCycle(Vec<String>),
+    /// A `ref:` value points at a key that does not exist.
+    BrokenReference { from: String, to: String },
+    /// The secret already has an attachment with this name.
+    AttachmentAlreadyExists { key: String, name: String },
+    /// The secret has no attachment with this name.
+    AttachmentNotFound { key: String, name: String },
+    /// A configured store quota would be exceeded.
+    QuotaExceeded(String),
+    /// The index of a sectioned payload does not match its sections.
+    CorruptedIndex(String),
+    /// The key lies in the `keynest/` namespace reserved for store settings.
+    ReservedKey(String),
+    /// A store setting does not match the type it is read or written as.
+    InvalidSetting { name: String, msg: String },
+    /// The key is not a valid path of `/`-separated names.
+    InvalidKey { key: String, reason: &'static str },
+    /// The tag is empty or contains whitespace, commas or control characters.
+    InvalidTag(String),
+    /// The field name is empty or contains whitespace, `=` or control characters.
+    InvalidField(String),
 }
 
 impl fmt::Display for StoreError {
@@ -16,6 +38,37 @@ impl fmt::Display for StoreError {
         match self {
             StoreError::KeyAlreadyExists(k) => write!(f, "secret '{k}' already exists"),
             StoreError::KeyNotFound(k) => write!(f, "secret '{k}' not found"),
+            StoreError::ReferenceCycle(chain) => {
+                write!(f, "reference cycle detected: {}", chain.join(" -> "))
+            }
+            StoreError::BrokenReference { from, to } => {
+                write!(f, "secret '{from}' references missing secret '{to}'")
+            }
+            StoreError::AttachmentAlreadyExists { key, name } => {
+                write!(f, "secret '{key}' already has an attachment '{name}'")
+            }
+            StoreError::AttachmentNotFound { key, name } => {
+                write!(f, "secret '{key}' has no attachment '{name}'")
+            }
+            StoreError::QuotaExceeded(msg) => write!(f, "quota exceeded: {msg}"),
+            StoreError::CorruptedIndex(msg) => write!(f, "corrupted keystore index: {msg}"),
+            StoreError::ReservedKey(k) => write!(
+                f,
+                "'{k}' is in the reserved '{}' namespace",
+                crate::settings::SETTINGS_PREFIX
+            ),
+            StoreError::InvalidSetting { name, msg } => {
+                write!(f, "invalid setting '{name}': {msg}")
+            }
+            StoreError::InvalidKey { key, reason } => write!(f, "invalid key '{key}': {reason}"),
+            StoreError::InvalidTag(tag) => write!(
+                f,
+                "invalid tag '{tag}': tags must be non-empty and contain no whitespace or commas"
+            ),
+            StoreError::InvalidField(name) => write!(
+                f,
+                "invalid field name '{name}': field names must be non-empty and contain no whitespace or '='"
+            ),
         }
     }
 }
@@ -0,0 +1,235 @@
+//! Plaintext export of secrets for other tools.
+
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use zeroize::Zeroizing;
+
+use crate::crypto::random::{self, EntropyUse};
+use crate::store::reference_target;
+
+/// Output format of [`crate::Keynest::export`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A JSON object of key/value pairs, sorted by key.
+    Json,
+    /// `KEY=value` lines (dotenv), quoting values where needed.
+    Env,
+    /// A `key,value` header followed by one RFC 4180 row per secret.
+    Csv,
+    /// YAML, nesting namespaced keys (`prod/db/password` becomes `prod: db: password:`).
+    Yaml,
+    /// TOML, nesting namespaced keys into tables.
+    Toml,
+}
+
+impl ExportFormat {
+    /// Returns the format conventionally used for files with extension `ext`.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "env" => Some(ExportFormat::Env),
+            "json" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            "yaml" | "yml" => Some(ExportFormat::Yaml),
+            "toml" => Some(ExportFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// How [`crate::Keynest::export_redacted`] replaces values, so the layout of a store can
+/// be shared without its secrets.
+///
+/// `ref:` values are kept as they are: they only name another key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// Replaces each value with `redacted:` and 16 hex digits of a keyed hash. Equal
+    /// values get equal hashes within one export; the key is random per export, so the
+    /// hashes can neither be brute-forced nor compared across exports.
+    Values,
+    /// Keeps the first 3 characters of values of at least 12 characters, followed by
+    /// a fixed `********` mask; shorter values and entries with an access policy are
+    /// masked completely.
+    Partial,
+}
+
+/// Number of leading characters [`Redaction::Partial`] keeps.
+const PARTIAL_PREFIX_LEN: usize = 3;
+
+/// Shortest value [`Redaction::Partial`] keeps a prefix of.
+const PARTIAL_MIN_LEN: usize = 12;
+
+const MASK: &str = "********";
+
+/// Replaces values according to a [`Redaction`].
+pub(crate) struct Redactor {
+    redaction: Redaction,
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl Redactor {
+    /// Creates a redactor with a fresh hash key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no random bytes are available.
+    pub(crate) fn new(redaction: Redaction) -> Result<Self> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        random::fill(EntropyUse::Key, &mut *key)?;
+        Ok(Self { redaction, key })
+    }
+
+    /// Returns the redacted form of `value`; `restricted` entries never keep a prefix.
+    pub(crate) fn redact(&self, value: &str, restricted: bool) -> String {
+        if value.is_empty() || reference_target(value).is_some() {
+            return value.to_string();
+        }
+        match self.redaction {
+            Redaction::Values => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&*self.key)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(value.as_bytes());
+                let digest = mac.finalize().into_bytes();
+                let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+                format!("redacted:{hex}")
+            }
+            Redaction::Partial if !restricted && value.chars().count() >= PARTIAL_MIN_LEN => {
+                let prefix: String = value.chars().take(PARTIAL_PREFIX_LEN).collect();
+                format!("{prefix}{MASK}")
+            }
+            Redaction::Partial => MASK.to_string(),
+        }
+    }
+}
+
+/// Formats `secrets` (sorted by key) as `format`.
+pub(crate) fn format(format: ExportFormat, secrets: &[(&str, &str)]) -> Result<Zeroizing<String>> {
+    let output = match format {
+        ExportFormat::Env => {
+            let mut output = String::new();
+            for (key, value) in secrets {
+                writeln!(&mut output, "{key}={}", escape_env_value(value))?;
+            }
+            output
+        }
+        ExportFormat::Json => {
+            let map: BTreeMap<&str, &str> = secrets.iter().copied().collect();
+            serde_json::to_string_pretty(&map)?
+        }
+        ExportFormat::Csv => {
+            let mut output = String::from("key,value\r\n");
+            for (key, value) in secrets {
+                write!(&mut output, "{},{}\r\n", escape_csv(key), escape_csv(value))?;
+            }
+            output
+        }
+        ExportFormat::Yaml => serde_yaml::to_string(&nest_by_namespace(secrets)?)?,
+        ExportFormat::Toml => toml::to_string(&nest_by_namespace(secrets)?)?,
+    };
+    Ok(Zeroizing::new(output))
+}
+
+fn escape_env_value(value: &str) -> String {
+    if value.contains(' ')
+        || value.contains('"')
+        || value.contains('\'')
+        || value.contains('\n')
+        || value.contains('\\')
+    {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote, or line break.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Builds a nested map from namespaced keys for the YAML and TOML exports, so
+/// `prod/db/password` becomes `{prod: {db: {password: ...}}}`.
+///
+/// Fails if a key is both a secret and a namespace (e.g. `prod` and `prod/db`),
+/// since a nested document cannot represent both.
+fn nest_by_namespace(secrets: &[(&str, &str)]) -> Result<Value> {
+    let mut root = Map::new();
+    for (key, value) in secrets {
+        let mut segments: Vec<&str> = key.split('/').collect();
+        let leaf = segments.pop().unwrap_or_default();
+
+        let mut node = &mut root;
+        for segment in segments {
+            let entry = node
+                .entry(segment.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            node = match entry {
+                Value::Object(map) => map,
+                _ => bail!("cannot nest '{key}': '{segment}' is also a secret"),
+            };
+        }
+
+        if node.contains_key(leaf) {
+            bail!("cannot nest '{key}': it is also used as a namespace");
+        }
+        node.insert(leaf.to_string(), Value::String(value.to_string()));
+    }
+
+    Ok(Value::Object(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        let secrets = [
+            ("plain", "abc"),
+            ("db/url", "a,b"),
+            ("note", "say \"hi\"\nbye"),
+        ];
+        let csv = format(ExportFormat::Csv, &secrets).unwrap();
+        assert_eq!(
+            *csv,
+            "key,value\r\nplain,abc\r\ndb/url,\"a,b\"\r\nnote,\"say \"\"hi\"\"\nbye\"\r\n"
+        );
+    }
+
+    #[test]
+    fn redaction_keeps_structure_but_not_values() {
+        let hashed = Redactor::new(Redaction::Values).unwrap();
+        let a = hashed.redact("hunter2", false);
+        assert!(a.starts_with("redacted:") && a.len() == "redacted:".len() + 16);
+        assert_eq!(hashed.redact("hunter2", true), a);
+        assert_ne!(hashed.redact("hunter3", false), a);
+        assert_eq!(hashed.redact("ref:prod/db", false), "ref:prod/db");
+        assert_eq!(hashed.redact("", false), "");
+        // A new export hashes with a new key.
+        assert_ne!(
+            Redactor::new(Redaction::Values)
+                .unwrap()
+                .redact("hunter2", false),
+            a
+        );
+
+        let partial = Redactor::new(Redaction::Partial).unwrap();
+        assert_eq!(partial.redact("sk_live_0123456789", false), "sk_********");
+        assert_eq!(partial.redact("sk_live_0123456789", true), "********");
+        assert_eq!(partial.redact("short", false), "********");
+    }
+
+    #[test]
+    fn nesting_rejects_keys_that_are_also_namespaces() {
+        let secrets = [("prod", "x"), ("prod/db", "y")];
+        assert!(format(ExportFormat::Yaml, &secrets).is_err());
+        assert!(format(ExportFormat::Json, &secrets).is_ok());
+    }
+}
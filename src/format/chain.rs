@@ -0,0 +1,80 @@
+//! Links of the hash chain over saves of a keystore file.
+
+use anyhow::{Result, bail};
+use sha2::{Digest, Sha256};
+
+use super::v3;
+
+/// Length of a file hash.
+pub const FILE_HASH_LEN: usize = 32;
+/// Length of an encoded link: the generation and the hash of the previous file.
+const LINK_LEN: usize = 8 + FILE_HASH_LEN;
+
+/// The place of a keystore file in the hash chain over its saves, recorded in the
+/// header of v3 files.
+///
+/// Every save counts the generation up and records the SHA-256 hash of the file it
+/// replaces, so a file that was swapped for an older copy (valid, but stale) shows a
+/// generation below one seen before (see [`crate::ChainWitness`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainLink {
+    generation: u64,
+    previous: [u8; FILE_HASH_LEN],
+}
+
+impl ChainLink {
+    /// Creates the link of generation `generation`, following the file hashed as
+    /// `previous` (all zeros for the first save).
+    pub(crate) fn new(generation: u64, previous: [u8; FILE_HASH_LEN]) -> Self {
+        Self {
+            generation,
+            previous,
+        }
+    }
+
+    /// Returns the number of saves since the chain started, 1 for its first file.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the SHA-256 hash of the file this one replaced.
+    pub fn previous(&self) -> &[u8; FILE_HASH_LEN] {
+        &self.previous
+    }
+
+    pub(crate) fn encode(&self) -> [u8; LINK_LEN] {
+        let mut out = [0u8; LINK_LEN];
+        out[..8].copy_from_slice(&self.generation.to_le_bytes());
+        out[8..].copy_from_slice(&self.previous);
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() != LINK_LEN {
+            bail!("invalid chain length");
+        }
+        let (generation, previous) = data.split_at(8);
+        let generation = u64::from_le_bytes(generation.try_into()?);
+        if generation == 0 {
+            bail!("invalid chain generation");
+        }
+        Ok(Self::new(generation, previous.try_into()?))
+    }
+}
+
+/// Returns the SHA-256 hash of the bytes of a keystore file, as recorded by the link of
+/// the file that replaces it.
+///
+/// The value of the Decoy TLV is left out: saving the decoy of a duress password
+/// rewrites it without a new link.
+pub fn file_hash(data: &[u8]) -> [u8; FILE_HASH_LEN] {
+    let mut hasher = Sha256::new();
+    match v3::decoy_range(data) {
+        Some(decoy) => {
+            hasher.update(&data[..decoy.start]);
+            hasher.update(&data[decoy.end..]);
+        }
+        None => hasher.update(data),
+    }
+    hasher.finalize().into()
+}
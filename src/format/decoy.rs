@@ -0,0 +1,105 @@
+//! Decoy: the store a duress password opens instead of the keystore.
+//!
+//! The decoy lives in the header as a Decoy TLV of fixed length (v3 only). Every
+//! keystore with keyslots has one: random bytes until a duress password is set, then
+//! the decoy store encrypted with the key its keyslot wraps, so neither the header nor
+//! the size of the file tells whether there is a duress password. No record
+//! authenticates the TLV, so the decoy is rewritten without the data key, and the file
+//! hash of the chain leaves it out (see [`super::file_hash`]):
+//!
+//! ```text
+//! NONCE | CIPHERTEXT of ( DOCUMENT_LEN (4) | DOCUMENT | ZERO PADDING ), CAPACITY + 4 bytes
+//! ```
+
+use anyhow::{Context, Result, bail};
+use zeroize::Zeroizing;
+
+use crate::EntropyUse;
+use crate::crypto::algorithm::Algorithm;
+use crate::crypto::random;
+
+use super::v2::AEAD_TAG_LEN;
+
+/// Domain separation for the decoy.
+const CONTEXT: &[u8] = b"keynest decoy v1";
+/// Largest encoded decoy store in bytes.
+pub const CAPACITY: usize = 16 * 1024;
+/// Length of the document length prefix.
+const LEN_PREFIX: usize = 4;
+
+/// Returns the length of the Decoy TLV of a keystore encrypted with `algorithm`.
+pub(crate) fn len(algorithm: Algorithm) -> usize {
+    algorithm.nonce_len() + LEN_PREFIX + CAPACITY + AEAD_TAG_LEN
+}
+
+/// Returns random bytes standing in for a decoy until a duress password is set.
+///
+/// # Errors
+///
+/// Returns an error if no random bytes are available.
+pub(crate) fn filler(algorithm: Algorithm) -> Result<Vec<u8>> {
+    let mut filler = vec![0u8; len(algorithm)];
+    random::fill(EntropyUse::Key, &mut filler)?;
+    Ok(filler)
+}
+
+/// Pads the encoded decoy store `document` to [`CAPACITY`] and encrypts it with `key`.
+///
+/// # Errors
+///
+/// Returns an error if the document is larger than [`CAPACITY`] or encryption fails.
+pub(crate) fn seal(algorithm: Algorithm, key: &[u8], document: &[u8]) -> Result<Vec<u8>> {
+    if document.len() > CAPACITY {
+        bail!(
+            "the keystore is too large ({} bytes encoded, limit {} KiB)",
+            document.len(),
+            CAPACITY / 1024
+        );
+    }
+    let mut padded = Zeroizing::new(Vec::with_capacity(LEN_PREFIX + CAPACITY));
+    padded.extend_from_slice(&(document.len() as u32).to_le_bytes());
+    padded.extend_from_slice(document);
+    padded.resize(LEN_PREFIX + CAPACITY, 0);
+
+    let (ciphertext, mut blob) = algorithm.encrypt(key, &padded, CONTEXT)?;
+    blob.extend_from_slice(&ciphertext);
+    Ok(blob)
+}
+
+/// Decrypts the decoy `blob` with `key`, returning the encoded decoy store.
+///
+/// # Errors
+///
+/// Returns an error if `key` does not open the decoy, e.g. because it holds filler.
+pub(crate) fn open(algorithm: Algorithm, key: &[u8], blob: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let (nonce, ciphertext) = blob
+        .split_at_checked(algorithm.nonce_len())
+        .context("truncated decoy")?;
+    let padded = algorithm.decrypt(key, nonce, ciphertext, CONTEXT)?;
+    let Some((len, rest)) = padded.split_first_chunk::<LEN_PREFIX>() else {
+        bail!("invalid decoy padding");
+    };
+    let len = u32::from_le_bytes(*len) as usize;
+    let document = rest.get(..len).context("invalid decoy padding")?;
+    Ok(Zeroizing::new(document.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KEY_LEN;
+
+    #[test]
+    fn decoys_have_the_length_of_filler_and_open_only_with_their_key() {
+        let algorithm = Algorithm::XChaCha20Poly1305;
+        let key = [1u8; KEY_LEN];
+        let blob = seal(algorithm, &key, b"decoy").unwrap();
+
+        assert_eq!(blob.len(), len(algorithm));
+        assert_eq!(filler(algorithm).unwrap().len(), len(algorithm));
+        assert_eq!(&*open(algorithm, &key, &blob).unwrap(), b"decoy");
+        assert!(open(algorithm, &[2u8; KEY_LEN], &blob).is_err());
+        assert!(open(algorithm, &key, &filler(algorithm).unwrap()).is_err());
+        assert!(seal(algorithm, &key, &vec![0; CAPACITY + 1]).is_err());
+    }
+}
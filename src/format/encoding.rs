@@ -0,0 +1,210 @@
+//! Payload encoding recorded in the v3 header.
+//!
+//! The plaintext of every record is a serialized document, optionally compressed and
+//! padded before encryption. The choice is stored in the authenticated header so a
+//! reader knows how to decode the records; the default (JSON, uncompressed, unpadded)
+//! is not written at all, which keeps files from older versions byte-identical.
+
+use anyhow::{Result, bail};
+use std::fmt;
+
+/// Serialization of record plaintexts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Serialization {
+    /// JSON documents.
+    #[default]
+    Json,
+    /// MessagePack documents, with named fields.
+    MessagePack,
+}
+
+/// Compression applied after serialization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// No compression.
+    #[default]
+    None,
+    /// Raw DEFLATE.
+    Deflate,
+    /// Zstandard (zstd) frames, at the default level.
+    Zstd,
+}
+
+/// Padding applied before encryption to hide the exact plaintext size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Padding {
+    /// No padding.
+    #[default]
+    None,
+    /// Pad each record to the next power of two (at least 512 bytes).
+    PowerOfTwo,
+}
+
+/// How the plaintext of each record is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PayloadEncoding {
+    serialization: Serialization,
+    compression: Compression,
+    padding: Padding,
+}
+
+/// Length of the encoding TLV value: serialization, compression, padding.
+pub(crate) const ENCODING_LEN: usize = 3;
+
+impl PayloadEncoding {
+    /// Creates a payload encoding.
+    pub fn new(serialization: Serialization, compression: Compression, padding: Padding) -> Self {
+        Self {
+            serialization,
+            compression,
+            padding,
+        }
+    }
+
+    /// Returns the serialization of record plaintexts.
+    pub fn serialization(&self) -> Serialization {
+        self.serialization
+    }
+
+    /// Returns the compression applied after serialization.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the padding applied before encryption.
+    pub fn padding(&self) -> Padding {
+        self.padding
+    }
+
+    /// Returns the encoding of new keystores: MessagePack, uncompressed and unpadded,
+    /// which is smaller and faster to load and save than JSON.
+    pub fn compact() -> Self {
+        Self::new(Serialization::MessagePack, Compression::None, Padding::None)
+    }
+
+    /// Returns `true` for plain JSON, which older versions of keynest can read.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Encodes the encoding as the value of the header TLV.
+    pub(crate) fn to_bytes(self) -> [u8; ENCODING_LEN] {
+        [
+            match self.serialization {
+                Serialization::Json => 0,
+                Serialization::MessagePack => 1,
+            },
+            match self.compression {
+                Compression::None => 0,
+                Compression::Deflate => 1,
+                Compression::Zstd => 2,
+            },
+            match self.padding {
+                Padding::None => 0,
+                Padding::PowerOfTwo => 1,
+            },
+        ]
+    }
+
+    /// Decodes the value of the header TLV.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value has the wrong length or names an unknown method.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let &[serialization, compression, padding] = bytes else {
+            bail!("invalid payload encoding length");
+        };
+
+        let serialization = match serialization {
+            0 => Serialization::Json,
+            1 => Serialization::MessagePack,
+            x => bail!("unsupported payload serialization: {x}"),
+        };
+        let compression = match compression {
+            0 => Compression::None,
+            1 => Compression::Deflate,
+            2 => Compression::Zstd,
+            x => bail!("unsupported payload compression: {x}"),
+        };
+        let padding = match padding {
+            0 => Padding::None,
+            1 => Padding::PowerOfTwo,
+            x => bail!("unsupported payload padding: {x}"),
+        };
+
+        Ok(Self::new(serialization, compression, padding))
+    }
+}
+
+impl fmt::Display for Serialization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::MessagePack => "msgpack",
+        })
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Deflate => "deflate",
+            Self::Zstd => "zstd",
+        })
+    }
+}
+
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::PowerOfTwo => "power-of-two",
+        })
+    }
+}
+
+impl fmt::Display for PayloadEncoding {
+    /// Formats as `json`, `msgpack+deflate`, `json+padded`, ...
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.serialization)?;
+        if self.compression != Compression::None {
+            write!(f, "+{}", self.compression)?;
+        }
+        if self.padding != Padding::None {
+            f.write_str("+padded")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_roundtrip() {
+        let encoding = PayloadEncoding::new(
+            Serialization::MessagePack,
+            Compression::Deflate,
+            Padding::PowerOfTwo,
+        );
+        assert_eq!(
+            PayloadEncoding::from_bytes(&encoding.to_bytes()).unwrap(),
+            encoding
+        );
+        assert_eq!(encoding.to_string(), "msgpack+deflate+padded");
+        let zstd = PayloadEncoding::new(Serialization::Json, Compression::Zstd, Padding::None);
+        assert_eq!(PayloadEncoding::from_bytes(&zstd.to_bytes()).unwrap(), zstd);
+        assert_eq!(zstd.to_string(), "json+zstd");
+        assert_eq!(PayloadEncoding::default().to_string(), "json");
+    }
+
+    #[test]
+    fn unknown_methods_are_rejected() {
+        assert!(PayloadEncoding::from_bytes(&[2, 0, 0]).is_err());
+        assert!(PayloadEncoding::from_bytes(&[0, 9, 0]).is_err());
+        assert!(PayloadEncoding::from_bytes(&[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Password-free summary of a keystore file.
+
+use anyhow::Result;
+
+use super::{CURRENT_VERSION, KeystoreFile, PayloadEncoding, parse};
+use crate::KdfParams;
+use crate::crypto::algorithm::Algorithm;
+
+/// Summary of the unencrypted structure of a keystore file.
+///
+/// Everything here is read from the header and record framing; nothing is decrypted.
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    version: u8,
+    algorithm: Algorithm,
+    kdf: KdfParams,
+    salt_len: usize,
+    nonce_len: usize,
+    encoding: PayloadEncoding,
+    dpapi_bound: bool,
+    keyfile: bool,
+    keyslots: Vec<String>,
+    key_check: bool,
+    generation: Option<u64>,
+    records: usize,
+    ciphertext_len: usize,
+}
+
+/// Parses and validates the structure of a keystore file held in memory.
+///
+/// Succeeds only if the file would be accepted by [`parse`]: valid magic, a supported
+/// version, all required header fields, and intact record framing. Whether the password
+/// is correct and the ciphertexts authenticate is not checked.
+///
+/// # Errors
+///
+/// Returns an error describing the first structural problem found.
+pub fn inspect(data: &[u8]) -> Result<Inspection> {
+    Ok(parse(data)?.inspect())
+}
+
+impl KeystoreFile {
+    /// Summarizes the unencrypted structure of this file.
+    pub fn inspect(&self) -> Inspection {
+        Inspection {
+            version: self.version(),
+            algorithm: self.algorithm(),
+            kdf: *self.kdf(),
+            salt_len: self.salt().len(),
+            nonce_len: self.nonce().len(),
+            encoding: self.header.encoding(),
+            dpapi_bound: self.header.dpapi_blob().is_some(),
+            keyfile: self.header.requires_keyfile(),
+            keyslots: self
+                .header
+                .keyslots()
+                .iter()
+                .map(|slot| slot.name().to_string())
+                .collect(),
+            key_check: self.header.key_check().is_some(),
+            generation: self.header.chain().map(|link| link.generation()),
+            records: 1 + self.sections().len(),
+            ciphertext_len: self.ciphertext().len()
+                + self
+                    .sections()
+                    .iter()
+                    .map(|r| r.ciphertext().len())
+                    .sum::<usize>(),
+        }
+    }
+}
+
+impl Inspection {
+    /// Returns the file format version.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns `true` if the file uses the format this version of keynest writes.
+    pub fn is_current(&self) -> bool {
+        self.version == CURRENT_VERSION
+    }
+
+    /// Returns the encryption algorithm.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the KDF parameters used for key derivation.
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Returns the salt length in bytes.
+    pub fn salt_len(&self) -> usize {
+        self.salt_len
+    }
+
+    /// Returns the nonce length in bytes.
+    pub fn nonce_len(&self) -> usize {
+        self.nonce_len
+    }
+
+    /// Returns how the record plaintexts are encoded.
+    pub fn encoding(&self) -> PayloadEncoding {
+        self.encoding
+    }
+
+    /// Returns `true` if the key is bound to a Windows account with DPAPI.
+    pub fn is_dpapi_bound(&self) -> bool {
+        self.dpapi_bound
+    }
+
+    /// Returns `true` if opening the file needs a keyfile besides the password.
+    pub fn requires_keyfile(&self) -> bool {
+        self.keyfile
+    }
+
+    /// Returns the names of the keyslots, empty if the key is derived from a single
+    /// password.
+    pub fn keyslots(&self) -> &[String] {
+        &self.keyslots
+    }
+
+    /// Returns `true` if the header has a key-check value, so opening the file tells a
+    /// wrong password apart from damaged data.
+    pub fn has_key_check(&self) -> bool {
+        self.key_check
+    }
+
+    /// Returns the generation recorded in the hash chain over saves, if the header has
+    /// a link (see [`super::ChainLink`]). It is not authenticated until the file is
+    /// decrypted.
+    pub fn chain_generation(&self) -> Option<u64> {
+        self.generation
+    }
+
+    /// Returns the number of encrypted records: 1 for single-ciphertext (v1/v2) files,
+    /// the index plus one per section for sectioned (v3) files.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Returns the total ciphertext size of all records in bytes.
+    pub fn ciphertext_len(&self) -> usize {
+        self.ciphertext_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Header, serialize};
+    use zeroize::Zeroizing;
+
+    fn sectioned_header() -> Header {
+        Header::sectioned(
+            KdfParams::default(),
+            Algorithm::XChaCha20Poly1305,
+            vec![1u8; 16],
+            vec![],
+        )
+    }
+
+    fn sectioned_file() -> Vec<u8> {
+        sectioned_file_with(sectioned_header())
+    }
+
+    fn sectioned_file_with(header: Header) -> Vec<u8> {
+        let file = KeystoreFile::encrypt_sectioned(
+            header,
+            &[7u8; 32],
+            &[
+                Zeroizing::new(b"[]".to_vec()),
+                Zeroizing::new(b"[]".to_vec()),
+            ],
+            &[],
+            false,
+            |_| Ok(Zeroizing::new(b"{}".to_vec())),
+        )
+        .unwrap();
+        serialize(&file).unwrap()
+    }
+
+    #[test]
+    fn inspects_sectioned_file() {
+        let inspection = inspect(&sectioned_file()).unwrap();
+        assert_eq!(inspection.version(), CURRENT_VERSION);
+        assert!(inspection.is_current());
+        assert_eq!(inspection.algorithm(), Algorithm::XChaCha20Poly1305);
+        assert_eq!(inspection.salt_len(), 16);
+        assert_eq!(inspection.nonce_len(), 24);
+        assert!(inspection.encoding().is_default());
+        assert!(!inspection.is_dpapi_bound());
+        assert!(!inspection.requires_keyfile());
+        assert_eq!(inspection.records(), 3);
+        // Three 2-byte plaintexts, each with a 16-byte tag.
+        assert_eq!(inspection.ciphertext_len(), 3 * (2 + 16));
+    }
+
+    #[test]
+    fn reports_dpapi_binding() {
+        let data = sectioned_file_with(sectioned_header().with_dpapi_blob(Some(vec![5u8; 40])));
+        assert!(inspect(&data).unwrap().is_dpapi_bound());
+        let file = parse(&data).unwrap();
+        assert_eq!(file.header.dpapi_blob(), Some(&[5u8; 40][..]));
+        // The blob is authenticated like the rest of the header.
+        assert_eq!(
+            file.decrypt(&[7u8; 32]).unwrap().as_slice(),
+            b"{}".as_slice()
+        );
+    }
+
+    #[test]
+    fn reports_keyfile_requirement() {
+        let data = sectioned_file_with(sectioned_header().with_keyfile(true));
+        assert!(inspect(&data).unwrap().requires_keyfile());
+        assert!(parse(&data).unwrap().header.requires_keyfile());
+        // Dropping the flag changes the AAD, so the file no longer decrypts.
+        let unflagged = sectioned_file_with(sectioned_header());
+        let mut file = parse(&data).unwrap();
+        file.header.keyfile = false;
+        assert!(file.decrypt(&[7u8; 32]).is_err());
+        assert!(parse(&unflagged).unwrap().decrypt(&[7u8; 32]).is_ok());
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let data = sectioned_file();
+        assert!(inspect(&data[..data.len() - 1]).is_err());
+        assert!(inspect(b"KNST").is_err());
+        assert!(inspect(b"NOPE\x03").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! Keyslots: several passwords opening one keystore.
+//!
+//! A keystore with keyslots encrypts its records with a random data key instead of the
+//! key derived from the password. Each keyslot holds that data key wrapped (encrypted)
+//! with a key derived from one password, using the slot's own salt and KDF parameters,
+//! so any of the passwords opens the keystore and changing one only rewraps its slot.
+//! Keyslots are stored in the header as Keyslot TLVs, one per slot (v3 only):
+//!
+//! ```text
+//! NAME_LEN (1) | NAME | KDF_LEN (1) | KDF | SALT (16) | NONCE_LEN (1) | NONCE | WRAPPED KEY
+//! ```
+
+use anyhow::{Result, bail};
+use zeroize::Zeroizing;
+
+use crate::KdfParams;
+use crate::crypto::algorithm::Algorithm;
+use crate::crypto::{KEY_LEN, SALT_LEN};
+
+use super::v2::AEAD_TAG_LEN;
+
+/// Domain separation for the wrapped data key; the slot name follows.
+const CONTEXT: &[u8] = b"keynest keyslot v1";
+/// Longest keyslot name in bytes.
+pub const MAX_NAME_LEN: usize = 64;
+/// Name of the keyslot holding the password of a keystore that gets its first keyslots.
+pub const DEFAULT_KEYSLOT: &str = "default";
+/// Most keyslots a keystore can have; opening with a password runs the KDF of each
+/// slot until one opens.
+pub const MAX_KEYSLOTS: usize = 16;
+
+/// The data key of a keystore, wrapped with the key derived from one password.
+#[derive(Debug, Clone)]
+pub struct Keyslot {
+    name: String,
+    kdf: KdfParams,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    wrapped: Vec<u8>,
+}
+
+impl Keyslot {
+    /// Wraps `data_key` with `key`, the key derived from the slot's password with `salt`
+    /// and `kdf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is invalid (see [`Keyslot::validate_name`]) or
+    /// encryption fails.
+    pub(crate) fn wrap(
+        name: &str,
+        kdf: KdfParams,
+        salt: Vec<u8>,
+        algorithm: Algorithm,
+        key: &[u8; KEY_LEN],
+        data_key: &[u8; KEY_LEN],
+    ) -> Result<Self> {
+        Self::validate_name(name)?;
+        let (wrapped, nonce) = algorithm.encrypt(key, data_key, &aad(name))?;
+        Ok(Self {
+            name: name.to_string(),
+            kdf,
+            salt,
+            nonce,
+            wrapped,
+        })
+    }
+
+    /// Unwraps the data key with `key`, the key derived from the slot's password.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `key` does not open the slot.
+    pub(crate) fn unwrap(
+        &self,
+        algorithm: Algorithm,
+        key: &[u8; KEY_LEN],
+    ) -> Result<[u8; KEY_LEN]> {
+        let data_key = algorithm.decrypt(key, &self.nonce, &self.wrapped, &aad(&self.name))?;
+        let data_key: &[u8] = &data_key;
+        match data_key.try_into() {
+            Ok(data_key) => Ok(data_key),
+            Err(_) => bail!("invalid data key length in keyslot '{}'", self.name),
+        }
+    }
+
+    /// Checks that `name` can name a keyslot: 1 to [`MAX_NAME_LEN`] bytes, without
+    /// whitespace, control characters or commas.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the problem.
+    pub fn validate_name(name: &str) -> Result<()> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            bail!("keyslot names must be 1 to {MAX_NAME_LEN} bytes long");
+        }
+        if name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == ',')
+        {
+            bail!("invalid keyslot name '{name}'");
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the slot, e.g. the person whose password it holds.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the KDF parameters the slot's key is derived with.
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Returns the salt the slot's key is derived with.
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Returns the nonce the data key was wrapped with.
+    pub(crate) fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    /// Encodes the slot as the value of a Keyslot TLV.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.name.len() as u8);
+        out.extend_from_slice(self.name.as_bytes());
+        let mut kdf = Vec::with_capacity(13);
+        self.kdf.encode(&mut kdf);
+        out.push(kdf.len() as u8);
+        out.extend_from_slice(&kdf);
+        out.extend_from_slice(&self.salt);
+        out.push(self.nonce.len() as u8);
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&self.wrapped);
+    }
+
+    /// Decodes the value of a Keyslot TLV.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is truncated or a field is invalid.
+    pub(crate) fn decode(value: &[u8]) -> Result<Self> {
+        let mut rest = value;
+        let mut take = |len: usize| -> Result<&[u8]> {
+            if rest.len() < len {
+                bail!("truncated keyslot");
+            }
+            let (head, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(head)
+        };
+
+        let name_len = take(1)?[0] as usize;
+        let Ok(name) = std::str::from_utf8(take(name_len)?) else {
+            bail!("keyslot name is not UTF-8");
+        };
+        let name = name.to_string();
+        Self::validate_name(&name)?;
+        let kdf_len = take(1)?[0] as usize;
+        let kdf = KdfParams::decode(take(kdf_len)?)?;
+        let salt = take(SALT_LEN)?.to_vec();
+        let nonce_len = take(1)?[0] as usize;
+        let nonce = take(nonce_len)?.to_vec();
+        let wrapped = take(KEY_LEN + AEAD_TAG_LEN)?.to_vec();
+        if !rest.is_empty() {
+            bail!("trailing data in keyslot '{name}'");
+        }
+
+        Ok(Self {
+            name,
+            kdf,
+            salt,
+            nonce,
+            wrapped,
+        })
+    }
+}
+
+fn aad(name: &str) -> Zeroizing<Vec<u8>> {
+    let mut aad = Zeroizing::new(CONTEXT.to_vec());
+    aad.extend_from_slice(name.as_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyslots_roundtrip_and_unwrap_only_with_their_key() {
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let (key, data_key) = ([1u8; KEY_LEN], [2u8; KEY_LEN]);
+        let algorithm = Algorithm::XChaCha20Poly1305;
+        let slot = Keyslot::wrap(
+            "alice",
+            kdf,
+            vec![3u8; SALT_LEN],
+            algorithm,
+            &key,
+            &data_key,
+        )
+        .unwrap();
+
+        let mut encoded = Vec::new();
+        slot.encode(&mut encoded);
+        let decoded = Keyslot::decode(&encoded).unwrap();
+        assert_eq!(decoded.name(), "alice");
+        assert_eq!(decoded.salt(), slot.salt());
+        assert_eq!(decoded.unwrap(algorithm, &key).unwrap(), data_key);
+        assert!(decoded.unwrap(algorithm, &[9u8; KEY_LEN]).is_err());
+
+        assert!(Keyslot::decode(&encoded[..encoded.len() - 1]).is_err());
+        encoded.push(0);
+        assert!(Keyslot::decode(&encoded).is_err());
+        for name in ["", "a b", "a,b", &"x".repeat(MAX_NAME_LEN + 1)] {
+            assert!(Keyslot::validate_name(name).is_err(), "{name}");
+        }
+    }
+}
@@ -1,16 +1,39 @@
 //! File format handling for the keystore.
 //!
 //! Provides version-aware parsing and serialization of the keystore file format.
+//!
+//! The read side of this module is public so external tools can inspect and validate
+//! keystore files without the password: [`parse`] checks the structure of a file and
+//! returns a [`KeystoreFile`] exposing the unencrypted header, and [`inspect`] summarizes
+//! it. Parsing never panics on malformed input, which makes it a suitable fuzzing target.
+//! Creating files is internal; use [`crate::Keynest`] for that.
+//!
+//! The on-disk layout of every version is described in CRYPTO.md.
 
 use anyhow::{Result, bail};
+use std::ops::Range;
+use std::sync::Arc;
 use zeroize::Zeroizing;
 
 use crate::KdfParams;
 use crate::crypto::algorithm::Algorithm;
-
-pub mod tlv;
-pub mod v1;
-pub mod v2;
+use crate::crypto::{KEY_LEN, synthetic_nonce};
+use crate::storage::FileData;
+
+mod chain;
+pub(crate) mod decoy;
+mod encoding;
+mod inspect;
+mod keyslot;
+pub(crate) mod tlv;
+pub(crate) mod v1;
+pub(crate) mod v2;
+pub(crate) mod v3;
+
+pub use chain::{ChainLink, FILE_HASH_LEN, file_hash};
+pub use encoding::{Compression, Padding, PayloadEncoding, Serialization};
+pub use inspect::{Inspection, inspect};
+pub use keyslot::{DEFAULT_KEYSLOT, Keyslot, MAX_KEYSLOTS};
 
 /// Magic bytes identifying a keynest keystore file ("KNST").
 pub const MAGIC: &[u8; 4] = b"KNST";
@@ -19,7 +42,9 @@ pub const MAGIC_LEN: usize = 4;
 /// Length of version field.
 pub const VER_LEN: usize = 1;
 /// Latest format version
-pub const CURRENT_VERSION: u8 = v2::VERSION_V2;
+pub const CURRENT_VERSION: u8 = v3::VERSION_V3;
+/// Domain separation for wrapped and derived entry keys; the record AAD follows.
+const ENTRY_KEY_CONTEXT: &[u8] = b"keynest entry key v1";
 
 /// Authenticated header data used for AAD and file format.
 ///
@@ -31,17 +56,58 @@ pub struct Header {
     pub(crate) algorithm: Algorithm,
     pub(crate) salt: Vec<u8>,
     pub(crate) nonce: Vec<u8>,
+    pub(crate) encoding: PayloadEncoding,
+    pub(crate) dpapi_blob: Option<Vec<u8>>,
+    pub(crate) keyfile: bool,
+    pub(crate) keyslots: Vec<Keyslot>,
+    pub(crate) key_check: Option<Vec<u8>>,
+    pub(crate) chain: Option<ChainLink>,
+    /// The decoy of a keystore with keyslots; written with the header but not
+    /// authenticated by it (see [`decoy`]).
+    pub(crate) decoy: Option<Vec<u8>>,
 }
 
 impl Header {
-    /// Creates a new Header.
-    pub fn new(kdf: KdfParams, algorithm: Algorithm, salt: Vec<u8>, nonce: Vec<u8>) -> Self {
+    /// Creates a new Header for a single-ciphertext (v2) file.
+    pub(crate) fn new(kdf: KdfParams, algorithm: Algorithm, salt: Vec<u8>, nonce: Vec<u8>) -> Self {
+        Self {
+            version: v2::VERSION_V2,
+            kdf,
+            algorithm,
+            salt,
+            nonce,
+            encoding: PayloadEncoding::default(),
+            dpapi_blob: None,
+            keyfile: false,
+            keyslots: Vec::new(),
+            key_check: None,
+            chain: None,
+            decoy: None,
+        }
+    }
+
+    /// Creates a new Header for a sectioned file in the current format.
+    ///
+    /// `nonce` is the nonce of the index record.
+    pub(crate) fn sectioned(
+        kdf: KdfParams,
+        algorithm: Algorithm,
+        salt: Vec<u8>,
+        nonce: Vec<u8>,
+    ) -> Self {
         Self {
             version: CURRENT_VERSION,
             kdf,
             algorithm,
             salt,
             nonce,
+            encoding: PayloadEncoding::default(),
+            dpapi_blob: None,
+            keyfile: false,
+            keyslots: Vec::new(),
+            key_check: None,
+            chain: None,
+            decoy: None,
         }
     }
 
@@ -70,26 +136,208 @@ impl Header {
         &self.nonce
     }
 
+    /// Returns how the record plaintexts are encoded.
+    pub fn encoding(&self) -> PayloadEncoding {
+        self.encoding
+    }
+
+    /// Returns the DPAPI-protected secret the key is bound to, if the keystore is bound
+    /// to a Windows account.
+    pub fn dpapi_blob(&self) -> Option<&[u8]> {
+        self.dpapi_blob.as_deref()
+    }
+
+    /// Returns `true` if the key also depends on a keyfile (see [`crate::Keyfile`]).
+    pub fn requires_keyfile(&self) -> bool {
+        self.keyfile
+    }
+
+    /// Returns the keyslots wrapping the data key, empty if the key is derived from the
+    /// password directly (see [`Keyslot`]).
+    pub fn keyslots(&self) -> &[Keyslot] {
+        &self.keyslots
+    }
+
+    /// Returns the key-check value, which tells a wrong password apart from damaged
+    /// data; absent in files written before it was introduced and in v1/v2 files.
+    pub fn key_check(&self) -> Option<&[u8]> {
+        self.key_check.as_deref()
+    }
+
+    /// Returns the place of the file in the hash chain over its saves; absent in files
+    /// written before the chain was introduced and in v1/v2 files.
+    pub fn chain(&self) -> Option<&ChainLink> {
+        self.chain.as_ref()
+    }
+
+    /// Returns the decoy a duress password opens, or the filler standing in for it;
+    /// present in files with keyslots written since it was introduced (see [`decoy`]).
+    pub(crate) fn decoy(&self) -> Option<&[u8]> {
+        self.decoy.as_deref()
+    }
+
+    /// Sets the payload encoding of a sectioned header.
+    pub(crate) fn with_encoding(mut self, encoding: PayloadEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets the DPAPI-protected secret of a sectioned header.
+    pub(crate) fn with_dpapi_blob(mut self, dpapi_blob: Option<Vec<u8>>) -> Self {
+        self.dpapi_blob = dpapi_blob;
+        self
+    }
+
+    /// Records whether the key of a sectioned header depends on a keyfile.
+    pub(crate) fn with_keyfile(mut self, keyfile: bool) -> Self {
+        self.keyfile = keyfile;
+        self
+    }
+
+    /// Sets the keyslots of a sectioned header.
+    pub(crate) fn with_keyslots(mut self, keyslots: Vec<Keyslot>) -> Self {
+        self.keyslots = keyslots;
+        self
+    }
+
+    /// Sets the key-check value of a sectioned header.
+    pub(crate) fn with_key_check(mut self, key_check: Option<Vec<u8>>) -> Self {
+        self.key_check = key_check;
+        self
+    }
+
+    /// Sets the hash chain link of a sectioned header.
+    pub(crate) fn with_chain(mut self, chain: Option<ChainLink>) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    /// Sets the decoy of a sectioned header.
+    pub(crate) fn with_decoy(mut self, decoy: Option<Vec<u8>>) -> Self {
+        self.decoy = decoy;
+        self
+    }
+
     /// Builds AAD from header data for authenticated encryption.
-    pub fn build_aad(&self) -> Vec<u8> {
+    pub(crate) fn build_aad(&self) -> Vec<u8> {
         build_header_aad(self)
     }
 
     /// Decrypts ciphertext using AAD from header.
+    ///
+    /// For sectioned files this decrypts the index record.
     pub fn decrypt(&self, key: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
-        let aad = self.build_aad();
-        self.algorithm.decrypt(key, self.nonce(), ciphertext, &aad)
+        self.decrypt_record(key, 0, self.nonce(), ciphertext)
+    }
+
+    /// Decrypts record `record` of a sectioned file (0 is the index, sections start at 1).
+    pub fn decrypt_record(
+        &self,
+        key: &[u8],
+        record: u32,
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let aad = self.record_aad(record);
+        self.algorithm.decrypt(key, nonce, ciphertext, &aad)
+    }
+
+    /// Encrypts record `record` of a sectioned file, returning `(ciphertext, nonce)`.
+    ///
+    /// A `deterministic` record gets a synthetic nonce, so the same plaintext encrypts
+    /// to the same bytes (see [`crate::settings::DeterministicRecords`]).
+    pub(crate) fn encrypt_record(
+        &self,
+        key: &[u8],
+        record: u32,
+        plaintext: &[u8],
+        deterministic: bool,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let aad = self.record_aad(record);
+        if deterministic {
+            self.algorithm.encrypt_synthetic(key, plaintext, &aad)
+        } else {
+            self.algorithm.encrypt(key, plaintext, &aad)
+        }
+    }
+
+    /// Wraps `entry_key`, the key of record `record` of a sectioned file, with `key`,
+    /// returning `(wrapped, nonce)`. A `deterministic` wrap gets a synthetic nonce like
+    /// the record itself.
+    pub(crate) fn wrap_entry_key(
+        &self,
+        key: &[u8],
+        record: u32,
+        entry_key: &[u8; KEY_LEN],
+        deterministic: bool,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let aad = self.entry_key_aad(record);
+        if deterministic {
+            self.algorithm.encrypt_synthetic(key, entry_key, &aad)
+        } else {
+            self.algorithm.encrypt(key, entry_key, &aad)
+        }
+    }
+
+    /// Unwraps the key of record `record` of a sectioned file with `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the wrapped key fails to authenticate, e.g. because it was
+    /// moved to another record.
+    pub(crate) fn unwrap_entry_key(
+        &self,
+        key: &[u8],
+        record: u32,
+        nonce: &[u8],
+        wrapped: &[u8],
+    ) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+        let aad = self.entry_key_aad(record);
+        let entry_key = self.algorithm.decrypt(key, nonce, wrapped, &aad)?;
+        let entry_key: &[u8] = &entry_key;
+        match entry_key.try_into() {
+            Ok(entry_key) => Ok(Zeroizing::new(entry_key)),
+            Err(_) => bail!("invalid entry key length for record {record}"),
+        }
+    }
+
+    /// Derives the key of record `record` from `key` and the record's plaintext, for
+    /// deterministic records (see [`crate::settings::DeterministicRecords`]).
+    pub(crate) fn derive_entry_key(
+        &self,
+        key: &[u8],
+        record: u32,
+        plaintext: &[u8],
+    ) -> Zeroizing<[u8; KEY_LEN]> {
+        let derived = synthetic_nonce::derive(key, &self.entry_key_aad(record), plaintext, KEY_LEN);
+        let mut entry_key = Zeroizing::new([0u8; KEY_LEN]);
+        entry_key.copy_from_slice(&derived);
+        entry_key
+    }
+
+    fn entry_key_aad(&self, record: u32) -> Vec<u8> {
+        let mut aad = ENTRY_KEY_CONTEXT.to_vec();
+        aad.extend_from_slice(&self.record_aad(record));
+        aad
     }
 
-    /// Encrypts plaintext and creates a header in one step.
+    fn record_aad(&self, record: u32) -> Vec<u8> {
+        match self.version {
+            v3::VERSION_V3 => v3::build_record_aad(self, record),
+            _ => self.build_aad(),
+        }
+    }
+
+    /// Encrypts plaintext and creates a single-ciphertext (v2) header in one step.
     ///
-    /// This is the preferred way to create encrypted data - it handles
-    /// nonce generation and AAD construction internally.
+    /// It handles nonce generation and AAD construction internally. Keystores are
+    /// written sectioned (see [`KeystoreFile::encrypt_sectioned`]) unless they are
+    /// written in format v2 (see [`crate::settings::WriteFormat`]).
     ///
     /// # Errors
     ///
     /// Returns an error if encryption fails.
-    pub fn encrypt_store(
+    pub(crate) fn encrypt_store(
         kdf: KdfParams,
         algorithm: Algorithm,
         salt: Vec<u8>,
@@ -116,12 +364,131 @@ impl Header {
 pub struct KeystoreFile {
     pub(crate) header: Header,
     pub(crate) ciphertext: Vec<u8>,
+    pub(crate) sections: Vec<Record>,
+}
+
+/// An encrypted section of a sectioned (v3) file.
+#[derive(Debug, Clone)]
+pub struct Record {
+    nonce: Vec<u8>,
+    ciphertext: Bytes,
+}
+
+/// Ciphertext of a record: owned, or a range of the memory-mapped file it was parsed
+/// from (see [`parse_data`]), which stays mapped as long as the record exists.
+#[derive(Clone)]
+pub(crate) enum Bytes {
+    Owned(Vec<u8>),
+    Mapped(Arc<FileData>, Range<usize>),
+}
+
+impl std::ops::Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Owned(data) => data,
+            Self::Mapped(file, range) => &file[range.clone()],
+        }
+    }
+}
+
+impl std::fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes", self.len())
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(data: Vec<u8>) -> Self {
+        Self::Owned(data)
+    }
+}
+
+impl Record {
+    /// Creates a new Record from nonce and ciphertext.
+    pub(crate) fn new(nonce: Vec<u8>, ciphertext: impl Into<Bytes>) -> Self {
+        Self {
+            nonce,
+            ciphertext: ciphertext.into(),
+        }
+    }
+
+    /// Returns the nonce used for encryption.
+    pub fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    /// Returns the encrypted ciphertext.
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
 }
 
 impl KeystoreFile {
     /// Creates a new KeystoreFile from header and ciphertext.
-    pub fn new(header: Header, ciphertext: Vec<u8>) -> Self {
-        Self { header, ciphertext }
+    pub(crate) fn new(header: Header, ciphertext: Vec<u8>) -> Self {
+        Self {
+            header,
+            ciphertext,
+            sections: Vec::new(),
+        }
+    }
+
+    /// Creates a new sectioned KeystoreFile from header, index ciphertext, and sections.
+    pub(crate) fn with_sections(
+        header: Header,
+        ciphertext: Vec<u8>,
+        sections: Vec<Record>,
+    ) -> Self {
+        Self {
+            header,
+            ciphertext,
+            sections,
+        }
+    }
+
+    /// Encrypts a sectioned (v3) payload under a header like `template`, with the
+    /// nonce of the index record filled in.
+    ///
+    /// The sections are encrypted first, each with its entry key from `entry_keys` or,
+    /// if that is empty, with `key`; `build_index` receives the section records and
+    /// returns the index plaintext, which is encrypted as record 0 with `key`. With
+    /// `deterministic`, every record gets a synthetic nonce (see
+    /// [`Header::encrypt_record`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encryption or building the index fails.
+    pub(crate) fn encrypt_sectioned(
+        template: Header,
+        key: &[u8],
+        sections: &[Zeroizing<Vec<u8>>],
+        entry_keys: &[Zeroizing<[u8; KEY_LEN]>],
+        deterministic: bool,
+        build_index: impl FnOnce(&[Record]) -> Result<Zeroizing<Vec<u8>>>,
+    ) -> Result<Self> {
+        let tmp = Header {
+            nonce: vec![],
+            ..template
+        };
+
+        let records = sections
+            .iter()
+            .enumerate()
+            .map(|(i, plaintext)| {
+                let key = entry_keys.get(i).map_or(key, |k| k.as_slice());
+                let (ciphertext, nonce) =
+                    tmp.encrypt_record(key, i as u32 + 1, plaintext, deterministic)?;
+                Ok(Record::new(nonce, ciphertext))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let index = build_index(&records)?;
+        let (ciphertext, nonce) = tmp.encrypt_record(key, 0, &index, deterministic)?;
+
+        let header = Header { nonce, ..tmp };
+        Ok(Self::with_sections(header, ciphertext, records))
     }
 
     /// Returns the file format version.
@@ -154,10 +521,54 @@ impl KeystoreFile {
         &self.ciphertext
     }
 
+    /// Returns the keyslots wrapping the data key (see [`Header::keyslots`]).
+    pub fn keyslots(&self) -> &[Keyslot] {
+        self.header.keyslots()
+    }
+
+    /// Returns the encrypted sections (empty unless the file is sectioned).
+    pub fn sections(&self) -> &[Record] {
+        &self.sections
+    }
+
+    /// Drops the section ciphertexts if they reference a memory-mapped file, so the
+    /// file is unmapped once the caller is done decrypting and can be replaced on
+    /// save (Windows refuses to replace a mapped file). Only the header is left to
+    /// describe the file afterwards.
+    pub(crate) fn release_mapping(&mut self) {
+        if self
+            .sections
+            .iter()
+            .any(|r| matches!(r.ciphertext, Bytes::Mapped(..)))
+        {
+            self.sections.clear();
+        }
+    }
+
+    /// Returns `true` if the payload is split into an index and sections.
+    pub fn is_sectioned(&self) -> bool {
+        self.version() >= v3::VERSION_V3
+    }
+
     /// Decrypts the ciphertext using AAD from header.
+    ///
+    /// For sectioned files this decrypts the index.
     pub fn decrypt(&self, key: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
         self.header.decrypt(key, self.ciphertext())
     }
+
+    /// Decrypts section `section` (0-based) of a sectioned file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the section does not exist or fails to authenticate.
+    pub fn decrypt_section(&self, key: &[u8], section: usize) -> Result<Zeroizing<Vec<u8>>> {
+        let Some(record) = self.sections.get(section) else {
+            bail!("section {section} does not exist");
+        };
+        self.header
+            .decrypt_record(key, section as u32 + 1, record.nonce(), record.ciphertext())
+    }
 }
 
 /// Parses a keystore file and returns a KeystoreFile.
@@ -184,23 +595,41 @@ pub fn parse(data: &[u8]) -> Result<KeystoreFile> {
     match version {
         v1::VERSION_V1 => v1::parse(data),
         v2::VERSION_V2 => v2::parse(data),
+        v3::VERSION_V3 => v3::parse(data),
         _ => bail!("unsupported version"),
     }
 }
 
+/// Parses a keystore file like [`parse`], but references the section ciphertexts of a
+/// memory-mapped v3 file in the mapping instead of copying them.
+///
+/// # Errors
+///
+/// Returns an error if the file is malformed (see [`parse`]).
+pub(crate) fn parse_data(data: &Arc<FileData>) -> Result<KeystoreFile> {
+    if data.is_mapped() && data.get(MAGIC_LEN) == Some(&v3::VERSION_V3) && data.starts_with(MAGIC) {
+        return v3::parse_mapped(data);
+    }
+    parse(data)
+}
+
 /// Serializes a KeystoreFile to bytes.
 ///
 /// # Errors
 ///
 /// Returns an error if the version is unsupported.
 pub fn serialize(file: &KeystoreFile) -> Result<Vec<u8>> {
-    v2::serialize(file)
+    match file.version() {
+        v3::VERSION_V3 => v3::serialize(file),
+        _ => v2::serialize(file),
+    }
 }
 
 /// Builds AAD from header data.
 fn build_header_aad(header: &Header) -> Vec<u8> {
     match header.version() {
         v2::VERSION_V2 => v2::build_header_aad(header),
+        v3::VERSION_V3 => v3::build_record_aad(header, 0),
         _ => unreachable!(),
     }
 }
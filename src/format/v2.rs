@@ -2,20 +2,27 @@
 //!
 //! V2 uses TLV (Type-Length-Value) encoding for extensibility.
 
-use super::tlv;
-use super::{Header, KeystoreFile, MAGIC, MAGIC_LEN, VER_LEN};
+use super::{ChainLink, Header, Keyslot, KeystoreFile, MAGIC, MAGIC_LEN, PayloadEncoding, VER_LEN};
+use super::{keyslot, tlv};
 use crate::{
     KdfParams,
-    crypto::{SALT_LEN, algorithm::Algorithm},
+    crypto::{SALT_LEN, algorithm::Algorithm, key_check::KEY_CHECK_LEN},
 };
 use anyhow::{Result, bail};
 
 /// V2 file format version.
 pub const VERSION_V2: u8 = 2;
 /// Size of the AEAD authentication tag (Poly1305).
-const AEAD_TAG_LEN: usize = 16;
+pub(super) const AEAD_TAG_LEN: usize = 16;
 /// Maximum allowed ciphertext size to prevent memory exhaustion attacks.
-const MAX_CIPHERTEXT: usize = 16 * 1024 * 1024; // 16 MiB max
+pub(crate) const MAX_CIPHERTEXT: usize = 16 * 1024 * 1024; // 16 MiB max
+/// Largest ciphertext a v2 file can hold: the Ciphertext TLV has a 16-bit length.
+pub(crate) const MAX_SERIALIZED_CIPHERTEXT: usize = u16::MAX as usize;
+/// Largest plaintext payload a v2 file can hold.
+pub(crate) const MAX_PAYLOAD: usize = MAX_SERIALIZED_CIPHERTEXT - AEAD_TAG_LEN;
+
+/// Keyfile TLV value: the key is mixed with the SHA-256 digest of the keyfile.
+const KEYFILE_SHA256: u8 = 1;
 
 /// TLV type identifiers for v2 format.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -31,6 +38,23 @@ pub(crate) enum TlvType {
     Nonce,
     /// Encrypted ciphertext
     Ciphertext,
+    /// Payload encoding (v3 only; omitted for plain JSON)
+    Encoding,
+    /// DPAPI-protected secret the key is bound to (v3 only; omitted if unbound)
+    DpapiBlob,
+    /// Marks a key that also depends on a keyfile (v3 only; omitted without a keyfile)
+    Keyfile,
+    /// A keyslot wrapping the data key, one TLV per slot (v3 only; omitted without
+    /// keyslots)
+    Keyslot,
+    /// Key-check value telling a wrong password from damaged data (v3 only)
+    KeyCheck,
+    /// Link of the hash chain over saves (v3 only; authenticated by the index record
+    /// alone)
+    Chain,
+    /// Decoy opened by a duress password, or filler (v3 only; written after the other
+    /// TLVs and authenticated by no record)
+    Decoy,
     /// Unknown type (for forward compatibility)
     Unknown(u8),
 }
@@ -43,6 +67,13 @@ impl From<u8> for TlvType {
             3 => Self::Nonce,
             4 => Self::Ciphertext,
             5 => Self::Algorithm,
+            6 => Self::Encoding,
+            7 => Self::DpapiBlob,
+            8 => Self::Keyfile,
+            9 => Self::Keyslot,
+            10 => Self::KeyCheck,
+            11 => Self::Chain,
+            12 => Self::Decoy,
             x => Self::Unknown(x),
         }
     }
@@ -56,48 +87,55 @@ impl From<TlvType> for u8 {
             TlvType::Nonce => 3,
             TlvType::Ciphertext => 4,
             TlvType::Algorithm => 5,
+            TlvType::Encoding => 6,
+            TlvType::DpapiBlob => 7,
+            TlvType::Keyfile => 8,
+            TlvType::Keyslot => 9,
+            TlvType::KeyCheck => 10,
+            TlvType::Chain => 11,
+            TlvType::Decoy => 12,
             TlvType::Unknown(x) => x,
         }
     }
 }
 
-/// Parses a v2 keystore file.
+/// Fields decoded from a TLV stream, before validation.
+#[derive(Default)]
+pub(super) struct Fields {
+    pub(super) kdf: Option<KdfParams>,
+    pub(super) algorithm: Option<Algorithm>,
+    pub(super) salt: Option<Vec<u8>>,
+    pub(super) nonce: Option<Vec<u8>>,
+    pub(super) ciphertext: Option<Vec<u8>>,
+    pub(super) encoding: Option<PayloadEncoding>,
+    pub(super) dpapi_blob: Option<Vec<u8>>,
+    pub(super) keyfile: bool,
+    pub(super) keyslots: Vec<Keyslot>,
+    pub(super) key_check: Option<Vec<u8>>,
+    pub(super) chain: Option<ChainLink>,
+    pub(super) decoy: Option<Vec<u8>>,
+}
+
+/// Decodes the known TLVs of `data`, rejecting duplicates (other than keyslots of
+/// different names) and ignoring unknown types.
 ///
 /// # Errors
 ///
-/// Returns an error if the file is malformed or required fields are missing.
-pub fn parse(data: &[u8]) -> Result<KeystoreFile> {
-    if data.len() < MAGIC_LEN + VER_LEN {
-        bail!("file too short");
-    }
-
-    let tlv_data = &data[MAGIC_LEN + VER_LEN..];
-    let tlvs = tlv::decode_all(tlv_data)?;
-
-    let mut kdf: Option<KdfParams> = None;
-    let mut algorithm: Option<Algorithm> = None;
-    let mut salt: Option<Vec<u8>> = None;
-    let mut nonce: Option<Vec<u8>> = None;
-    let mut ciphertext: Option<Vec<u8>> = None;
+/// Returns an error if the TLV stream is malformed or a field is duplicated or invalid.
+pub(super) fn decode_fields(data: &[u8]) -> Result<Fields> {
+    let tlvs = tlv::decode_all(data)?;
+    let mut fields = Fields::default();
 
     for t in tlvs {
         match TlvType::from(t.ty()) {
             TlvType::Kdf => {
-                if kdf.is_some() {
+                if fields.kdf.is_some() {
                     bail!("duplicate KDF field");
                 }
-                if t.value().len() != 12 {
-                    bail!("invalid kdf tlv length");
-                }
-
-                let mem = u32::from_le_bytes(t.value()[0..4].try_into()?);
-                let time = u32::from_le_bytes(t.value()[4..8].try_into()?);
-                let par = u32::from_le_bytes(t.value()[8..12].try_into()?);
-
-                kdf = Some(KdfParams::new(mem, time, par)?);
+                fields.kdf = Some(KdfParams::decode(t.value())?);
             }
             TlvType::Algorithm => {
-                if algorithm.is_some() {
+                if fields.algorithm.is_some() {
                     bail!("duplicate algorithm field");
                 }
 
@@ -106,25 +144,80 @@ pub fn parse(data: &[u8]) -> Result<KeystoreFile> {
                 }
 
                 let id = t.value()[0];
-                algorithm = Some(Algorithm::try_from(id)?);
+                fields.algorithm = Some(Algorithm::try_from(id)?);
             }
             TlvType::Salt => {
-                if salt.is_some() {
+                if fields.salt.is_some() {
                     bail!("duplicate salt field");
                 }
-                salt = Some(t.value().to_vec());
+                fields.salt = Some(t.value().to_vec());
             }
             TlvType::Nonce => {
-                if nonce.is_some() {
+                if fields.nonce.is_some() {
                     bail!("duplicate nonce field");
                 }
-                nonce = Some(t.value().to_vec());
+                fields.nonce = Some(t.value().to_vec());
             }
             TlvType::Ciphertext => {
-                if ciphertext.is_some() {
+                if fields.ciphertext.is_some() {
                     bail!("duplicate ciphertext field");
                 }
-                ciphertext = Some(t.value().to_vec());
+                fields.ciphertext = Some(t.value().to_vec());
+            }
+            TlvType::Encoding => {
+                if fields.encoding.is_some() {
+                    bail!("duplicate encoding field");
+                }
+                fields.encoding = Some(PayloadEncoding::from_bytes(t.value())?);
+            }
+            TlvType::DpapiBlob => {
+                if fields.dpapi_blob.is_some() {
+                    bail!("duplicate DPAPI field");
+                }
+                if t.value().is_empty() {
+                    bail!("empty DPAPI field");
+                }
+                fields.dpapi_blob = Some(t.value().to_vec());
+            }
+            TlvType::Keyfile => {
+                if fields.keyfile {
+                    bail!("duplicate keyfile field");
+                }
+                if t.value() != [KEYFILE_SHA256] {
+                    bail!("unsupported keyfile method");
+                }
+                fields.keyfile = true;
+            }
+            TlvType::Keyslot => {
+                let slot = Keyslot::decode(t.value())?;
+                if fields.keyslots.iter().any(|s| s.name() == slot.name()) {
+                    bail!("duplicate keyslot '{}'", slot.name());
+                }
+                if fields.keyslots.len() == keyslot::MAX_KEYSLOTS {
+                    bail!("too many keyslots");
+                }
+                fields.keyslots.push(slot);
+            }
+            TlvType::KeyCheck => {
+                if fields.key_check.is_some() {
+                    bail!("duplicate key check field");
+                }
+                if t.value().len() != KEY_CHECK_LEN {
+                    bail!("invalid key check length");
+                }
+                fields.key_check = Some(t.value().to_vec());
+            }
+            TlvType::Chain => {
+                if fields.chain.is_some() {
+                    bail!("duplicate chain field");
+                }
+                fields.chain = Some(ChainLink::decode(t.value())?);
+            }
+            TlvType::Decoy => {
+                if fields.decoy.is_some() {
+                    bail!("duplicate decoy field");
+                }
+                fields.decoy = Some(t.value().to_vec());
             }
             TlvType::Unknown(_) => {
                 // forward compatibility:
@@ -133,11 +226,56 @@ pub fn parse(data: &[u8]) -> Result<KeystoreFile> {
         }
     }
 
-    let kdf = kdf.ok_or_else(|| anyhow::anyhow!("missing kdf"))?;
-    let algorithm = algorithm.ok_or_else(|| anyhow::anyhow!("missing algorithm"))?;
-    let salt = salt.ok_or_else(|| anyhow::anyhow!("missing salt"))?;
-    let nonce = nonce.ok_or_else(|| anyhow::anyhow!("missing nonce"))?;
-    let ciphertext = ciphertext.ok_or_else(|| anyhow::anyhow!("missing ciphertext"))?;
+    Ok(fields)
+}
+
+/// Parses a v2 keystore file.
+///
+/// # Errors
+///
+/// Returns an error if the file is malformed or required fields are missing.
+pub fn parse(data: &[u8]) -> Result<KeystoreFile> {
+    if data.len() < MAGIC_LEN + VER_LEN {
+        bail!("file too short");
+    }
+
+    let fields = decode_fields(&data[MAGIC_LEN + VER_LEN..])?;
+    if fields.encoding.is_some() {
+        bail!("payload encoding is not supported in format v2");
+    }
+    if fields.dpapi_blob.is_some() {
+        bail!("DPAPI binding is not supported in format v2");
+    }
+    if fields.keyfile {
+        bail!("keyfiles are not supported in format v2");
+    }
+    if !fields.keyslots.is_empty() {
+        bail!("keyslots are not supported in format v2");
+    }
+    if fields.key_check.is_some() {
+        bail!("key checks are not supported in format v2");
+    }
+    if fields.chain.is_some() {
+        bail!("hash chains are not supported in format v2");
+    }
+    if fields.decoy.is_some() {
+        bail!("decoys are not supported in format v2");
+    }
+
+    let kdf = fields.kdf.ok_or_else(|| anyhow::anyhow!("missing kdf"))?;
+    if !matches!(kdf, KdfParams::Argon2id(_)) {
+        bail!("{} is not supported in format v2", kdf.name());
+    }
+    let algorithm = fields
+        .algorithm
+        .ok_or_else(|| anyhow::anyhow!("missing algorithm"))?;
+    let salt = fields.salt.ok_or_else(|| anyhow::anyhow!("missing salt"))?;
+    let nonce = fields
+        .nonce
+        .ok_or_else(|| anyhow::anyhow!("missing nonce"))?;
+    let ciphertext = fields
+        .ciphertext
+        .ok_or_else(|| anyhow::anyhow!("missing ciphertext"))?;
 
     if salt.len() != SALT_LEN {
         bail!("invalid salt length");
@@ -169,17 +307,39 @@ pub fn parse(data: &[u8]) -> Result<KeystoreFile> {
 fn encode_header_prefix(header: &Header, out: &mut Vec<u8>) {
     out.extend_from_slice(MAGIC);
     out.push(VERSION_V2);
+    encode_header_tlvs(header, out);
+}
 
-    let mut kdf_bytes = Vec::with_capacity(12);
-    kdf_bytes.extend_from_slice(&header.kdf().mem_cost_kib().to_le_bytes());
-    kdf_bytes.extend_from_slice(&header.kdf().time_cost().to_le_bytes());
-    kdf_bytes.extend_from_slice(&header.kdf().parallelism().to_le_bytes());
+/// Encodes the KDF / Algorithm / Salt TLVs of `header` into `out`, followed by the
+/// Encoding TLV if the payload is not plain JSON, the DPAPI TLV if the key is bound, the
+/// Keyfile TLV if it depends on a keyfile, a Keyslot TLV per keyslot and the KeyCheck
+/// TLV if the header has one.
+pub(super) fn encode_header_tlvs(header: &Header, out: &mut Vec<u8>) {
+    let mut kdf_bytes = Vec::with_capacity(13);
+    header.kdf().encode(&mut kdf_bytes);
 
     let algo_id: u8 = header.algorithm().into();
 
     tlv::encode(TlvType::Kdf.into(), &kdf_bytes, out);
     tlv::encode(TlvType::Algorithm.into(), &[algo_id], out);
     tlv::encode(TlvType::Salt.into(), header.salt(), out);
+    if !header.encoding().is_default() {
+        tlv::encode(TlvType::Encoding.into(), &header.encoding().to_bytes(), out);
+    }
+    if let Some(blob) = header.dpapi_blob() {
+        tlv::encode(TlvType::DpapiBlob.into(), blob, out);
+    }
+    if header.requires_keyfile() {
+        tlv::encode(TlvType::Keyfile.into(), &[KEYFILE_SHA256], out);
+    }
+    for slot in header.keyslots() {
+        let mut value = Vec::new();
+        slot.encode(&mut value);
+        tlv::encode(TlvType::Keyslot.into(), &value, out);
+    }
+    if let Some(check) = header.key_check() {
+        tlv::encode(TlvType::KeyCheck.into(), check, out);
+    }
 }
 
 /// Serializes a KeystoreFile to v2 format bytes using TLV encoding.
@@ -189,11 +349,14 @@ fn encode_header_prefix(header: &Header, out: &mut Vec<u8>) {
 ///
 /// # Errors
 ///
-/// Returns an error if the version is not v2.
+/// Returns an error if the version is not v2 or the ciphertext does not fit a v2 file.
 pub fn serialize(file: &KeystoreFile) -> Result<Vec<u8>> {
     if file.version() != VERSION_V2 {
         bail!("wrong version for v2 serializer");
     }
+    if file.ciphertext().len() > MAX_SERIALIZED_CIPHERTEXT {
+        bail!("ciphertext too large for format v2");
+    }
 
     let mut buf = Vec::new();
 
@@ -238,9 +401,7 @@ mod tests {
 
         let parsed = parse(&bytes).unwrap();
         assert_eq!(parsed.version(), VERSION_V2);
-        assert_eq!(parsed.kdf().mem_cost_kib(), 65536);
-        assert_eq!(parsed.kdf().time_cost(), 3);
-        assert_eq!(parsed.kdf().parallelism(), 2);
+        assert_eq!(*parsed.kdf(), KdfParams::new(65536, 3, 2).unwrap());
         assert_eq!(parsed.algorithm(), Algorithm::XChaCha20Poly1305);
     }
 
@@ -0,0 +1,441 @@
+//! Sectioned v3 file format for the keystore.
+//!
+//! V3 keeps the v2 header TLVs but splits the encrypted payload into records, so a
+//! reader can decrypt the index on open and fetch single sections on demand:
+//!
+//! ```text
+//! MAGIC (4) | VERSION (1) | HEADER_LEN (4) | HEADER TLVs | RECORD_COUNT (4) | RECORD...
+//! RECORD = NONCE | CIPHERTEXT_LEN (4) | CIPHERTEXT
+//! ```
+//!
+//! Record 0 holds the index, records 1..=N hold the sections. Every record is
+//! authenticated with the header bytes (magic through header TLVs) followed by its
+//! record number as AAD, so records cannot be reordered or moved to another file.
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::v2::{self, AEAD_TAG_LEN, MAX_CIPHERTEXT};
+use super::{Bytes, Header, KeystoreFile, MAGIC, MAGIC_LEN, Record, VER_LEN, tlv};
+use crate::crypto::SALT_LEN;
+use crate::storage::FileData;
+use anyhow::{Context, Result, bail};
+
+/// V3 file format version.
+pub const VERSION_V3: u8 = 3;
+/// Length of the header, record count, and record length fields.
+const LEN_LEN: usize = 4;
+/// Maximum allowed size of the header TLVs.
+const MAX_HEADER_LEN: usize = 64 * 1024;
+/// Maximum allowed number of records, to prevent memory exhaustion attacks.
+const MAX_RECORDS: usize = 1 << 20;
+
+/// Location of a record's ciphertext within a v3 file.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordRef {
+    pub(crate) nonce: Vec<u8>,
+    pub(crate) offset: u64,
+    pub(crate) len: usize,
+}
+
+/// A v3 file with the index ciphertext loaded and the section ciphertexts located
+/// but not read.
+#[derive(Debug)]
+pub(crate) struct Layout {
+    /// Header carrying the nonce of the index record.
+    pub(crate) header: Header,
+    pub(crate) index: Vec<u8>,
+    pub(crate) sections: Vec<RecordRef>,
+}
+
+/// Reads the header and index of a v3 file and locates its section records.
+///
+/// Only the index ciphertext is read into memory; section ciphertexts are skipped and
+/// can be fetched later with [`read_record`].
+///
+/// # Errors
+///
+/// Returns an error if the file is malformed, truncated, or required fields are missing.
+pub(crate) fn read_layout<R: Read + Seek>(reader: &mut R) -> Result<Layout> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut prefix = [0u8; MAGIC_LEN + VER_LEN];
+    reader.read_exact(&mut prefix).context("file too short")?;
+    if &prefix[..MAGIC_LEN] != MAGIC {
+        bail!("invalid magic");
+    }
+    if prefix[MAGIC_LEN] != VERSION_V3 {
+        bail!("wrong version for v3 parser");
+    }
+
+    let header_len = read_len(reader)?;
+    if header_len > MAX_HEADER_LEN {
+        bail!("header too large");
+    }
+    let mut header_tlvs = vec![0u8; header_len];
+    reader
+        .read_exact(&mut header_tlvs)
+        .context("truncated header")?;
+
+    let fields = v2::decode_fields(&header_tlvs)?;
+    if fields.nonce.is_some() || fields.ciphertext.is_some() {
+        bail!("unexpected nonce or ciphertext in v3 header");
+    }
+    let kdf = fields.kdf.ok_or_else(|| anyhow::anyhow!("missing kdf"))?;
+    let algorithm = fields
+        .algorithm
+        .ok_or_else(|| anyhow::anyhow!("missing algorithm"))?;
+    let salt = fields.salt.ok_or_else(|| anyhow::anyhow!("missing salt"))?;
+
+    if salt.len() != SALT_LEN {
+        bail!("invalid salt length");
+    }
+    if fields
+        .keyslots
+        .iter()
+        .any(|slot| slot.nonce().len() != algorithm.nonce_len())
+    {
+        bail!("invalid keyslot nonce length for algorithm");
+    }
+    if fields
+        .decoy
+        .as_ref()
+        .is_some_and(|decoy| decoy.len() != super::decoy::len(algorithm))
+    {
+        bail!("invalid decoy length for algorithm");
+    }
+
+    let record_count = read_len(reader)?;
+    if record_count == 0 {
+        bail!("missing index record");
+    }
+    if record_count > MAX_RECORDS {
+        bail!("too many records");
+    }
+
+    let mut records = Vec::with_capacity(record_count);
+    for _ in 0..record_count {
+        let mut nonce = vec![0u8; algorithm.nonce_len()];
+        reader.read_exact(&mut nonce).context("truncated record")?;
+
+        let len = read_len(reader)?;
+        if len < AEAD_TAG_LEN {
+            bail!("ciphertext too short");
+        }
+        if len > MAX_CIPHERTEXT {
+            bail!("ciphertext too large");
+        }
+
+        let offset = reader.stream_position()?;
+        if offset + len as u64 > file_len {
+            bail!("truncated record");
+        }
+        reader.seek(SeekFrom::Current(len as i64))?;
+
+        records.push(RecordRef { nonce, offset, len });
+    }
+
+    if reader.stream_position()? != file_len {
+        bail!("trailing data after last record");
+    }
+
+    let index_ref = records.remove(0);
+    let index = read_record(reader, &index_ref)?;
+    let header = Header::sectioned(kdf, algorithm, salt, index_ref.nonce)
+        .with_encoding(fields.encoding.unwrap_or_default())
+        .with_dpapi_blob(fields.dpapi_blob)
+        .with_keyfile(fields.keyfile)
+        .with_keyslots(fields.keyslots)
+        .with_key_check(fields.key_check)
+        .with_chain(fields.chain)
+        .with_decoy(fields.decoy);
+
+    Ok(Layout {
+        header,
+        index,
+        sections: records,
+    })
+}
+
+/// Reads the nonce of the index record without parsing the rest of the file.
+///
+/// Every write of the file picks a new nonce, so it identifies the version of the file
+/// a journal extends (see [`crate::journal`]).
+///
+/// # Errors
+///
+/// Returns an error if the file is not a v3 file or is truncated.
+pub(crate) fn read_index_nonce<R: Read + Seek>(
+    reader: &mut R,
+    nonce_len: usize,
+) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut prefix = [0u8; MAGIC_LEN + VER_LEN];
+    reader.read_exact(&mut prefix).context("file too short")?;
+    if &prefix[..MAGIC_LEN] != MAGIC || prefix[MAGIC_LEN] != VERSION_V3 {
+        bail!("not a v3 keystore file");
+    }
+
+    let header_len = read_len(reader)?;
+    if header_len > MAX_HEADER_LEN {
+        bail!("header too large");
+    }
+    reader.seek(SeekFrom::Current(header_len as i64 + LEN_LEN as i64))?;
+    let mut nonce = vec![0u8; nonce_len];
+    reader.read_exact(&mut nonce).context("truncated record")?;
+    Ok(nonce)
+}
+
+/// Reads the ciphertext of a record located by [`read_layout`].
+///
+/// # Errors
+///
+/// Returns an error if the record cannot be read.
+pub(crate) fn read_record<R: Read + Seek>(reader: &mut R, record: &RecordRef) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(record.offset))?;
+    let mut ciphertext = vec![0u8; record.len];
+    reader
+        .read_exact(&mut ciphertext)
+        .context("truncated record")?;
+    Ok(ciphertext)
+}
+
+/// Parses a v3 keystore file held in memory, including all section ciphertexts.
+///
+/// # Errors
+///
+/// Returns an error if the file is malformed or required fields are missing.
+pub fn parse(data: &[u8]) -> Result<KeystoreFile> {
+    let mut cursor = Cursor::new(data);
+    let layout = read_layout(&mut cursor)?;
+
+    let sections = layout
+        .sections
+        .iter()
+        .map(|r| Ok(Record::new(r.nonce.clone(), read_record(&mut cursor, r)?)))
+        .collect::<Result<_>>()?;
+
+    Ok(KeystoreFile::with_sections(
+        layout.header,
+        layout.index,
+        sections,
+    ))
+}
+
+/// Parses a memory-mapped v3 keystore file; the section ciphertexts reference the
+/// mapping rather than being copied out of it.
+///
+/// # Errors
+///
+/// Returns an error if the file is malformed or required fields are missing.
+pub(crate) fn parse_mapped(data: &Arc<FileData>) -> Result<KeystoreFile> {
+    let layout = read_layout(&mut Cursor::new(&***data))?;
+
+    // `read_layout` checked that every record lies within the file.
+    let sections = layout
+        .sections
+        .into_iter()
+        .map(|r| {
+            let start = r.offset as usize;
+            Record::new(r.nonce, Bytes::Mapped(data.clone(), start..start + r.len))
+        })
+        .collect();
+
+    Ok(KeystoreFile::with_sections(
+        layout.header,
+        layout.index,
+        sections,
+    ))
+}
+
+/// Encodes the authenticated header prefix — magic, version, header length, and the
+/// header TLVs (KDF / Algorithm / Salt, plus Encoding if set) — into `out`, followed by
+/// the Chain TLV if `with_chain` is set and the header has one, and the Decoy TLV if
+/// `with_decoy` is set and the header has one.
+///
+/// Shared byte-for-byte between the on-disk file and the AAD of the index record. The
+/// sections leave the Chain TLV out of their AAD, so a deterministic section stays the
+/// same bytes from one save to the next. No record authenticates the Decoy TLV, which
+/// a duress password rewrites without the data key.
+fn encode_header_prefix(header: &Header, with_chain: bool, with_decoy: bool, out: &mut Vec<u8>) {
+    let mut tlvs = Vec::new();
+    v2::encode_header_tlvs(header, &mut tlvs);
+    if let Some(link) = header.chain().filter(|_| with_chain) {
+        tlv::encode(v2::TlvType::Chain.into(), &link.encode(), &mut tlvs);
+    }
+    if let Some(decoy) = header.decoy().filter(|_| with_decoy) {
+        tlv::encode(v2::TlvType::Decoy.into(), decoy, &mut tlvs);
+    }
+
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION_V3);
+    out.extend_from_slice(&(tlvs.len() as u32).to_le_bytes());
+    out.extend_from_slice(&tlvs);
+}
+
+/// Serializes a KeystoreFile to v3 format bytes.
+///
+/// The index (the file's main nonce and ciphertext) is written as record 0, followed by
+/// the sections.
+///
+/// # Errors
+///
+/// Returns an error if the version is not v3 or a record is too large to be read back.
+pub fn serialize(file: &KeystoreFile) -> Result<Vec<u8>> {
+    if file.version() != VERSION_V3 {
+        bail!("wrong version for v3 serializer");
+    }
+    let ciphertexts =
+        std::iter::once(file.ciphertext()).chain(file.sections().iter().map(Record::ciphertext));
+    for ciphertext in ciphertexts {
+        if ciphertext.len() > MAX_CIPHERTEXT {
+            bail!(
+                "record too large ({} bytes, limit {MAX_CIPHERTEXT} bytes)",
+                ciphertext.len()
+            );
+        }
+    }
+
+    let mut buf = Vec::new();
+    encode_header_prefix(&file.header, true, true, &mut buf);
+
+    let record_count = 1 + file.sections().len();
+    buf.extend_from_slice(&(record_count as u32).to_le_bytes());
+
+    encode_record(file.nonce(), file.ciphertext(), &mut buf);
+    for section in file.sections() {
+        encode_record(section.nonce(), section.ciphertext(), &mut buf);
+    }
+
+    Ok(buf)
+}
+
+fn encode_record(nonce: &[u8], ciphertext: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(nonce);
+    out.extend_from_slice(&(ciphertext.len() as u32).to_le_bytes());
+    out.extend_from_slice(ciphertext);
+}
+
+/// Returns where the value of the Decoy TLV lies in `data`, if it is a v3 file with one.
+///
+/// Only the framing is checked; a malformed header gives `None`.
+pub(crate) fn decoy_range(data: &[u8]) -> Option<Range<usize>> {
+    let start = MAGIC_LEN + VER_LEN + LEN_LEN;
+    if data.get(..MAGIC_LEN)? != MAGIC || *data.get(MAGIC_LEN)? != VERSION_V3 {
+        return None;
+    }
+    let header_len = u32::from_le_bytes(data.get(MAGIC_LEN + VER_LEN..start)?.try_into().ok()?);
+    let end = start.checked_add(header_len as usize)?;
+
+    let mut pos = start;
+    while pos < end {
+        let ty = *data.get(pos)?;
+        let len = u16::from_le_bytes(data.get(pos + 1..pos + 3)?.try_into().ok()?) as usize;
+        let value = pos + 3..pos + 3 + len;
+        if value.end > end {
+            return None;
+        }
+        if v2::TlvType::from(ty) == v2::TlvType::Decoy {
+            return Some(value);
+        }
+        pos = value.end;
+    }
+    None
+}
+
+fn read_len<R: Read>(reader: &mut R) -> Result<usize> {
+    let mut bytes = [0u8; LEN_LEN];
+    reader.read_exact(&mut bytes).context("truncated file")?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+/// Builds the AAD of record `record`: the header prefix followed by the record number.
+///
+/// Only the index record (0) authenticates the Chain TLV; as the index lists the nonces
+/// of the sections, it still binds them to this save.
+pub(crate) fn build_record_aad(header: &Header, record: u32) -> Vec<u8> {
+    let mut aad = Vec::new();
+    encode_header_prefix(header, record == 0, false, &mut aad);
+    aad.extend_from_slice(&record.to_le_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{KdfParams, algorithm::Algorithm};
+    use crate::format::{parse, serialize};
+
+    fn sample() -> KeystoreFile {
+        let header = Header::sectioned(
+            KdfParams::new(65536, 3, 1).unwrap(),
+            Algorithm::XChaCha20Poly1305,
+            vec![1u8; 16],
+            vec![2u8; 24],
+        );
+        KeystoreFile::with_sections(
+            header,
+            vec![3u8; 32],
+            vec![
+                Record::new(vec![4u8; 24], vec![5u8; 40]),
+                Record::new(vec![6u8; 24], vec![7u8; 17]),
+            ],
+        )
+    }
+
+    #[test]
+    fn v3_roundtrip() {
+        let bytes = serialize(&sample()).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(bytes[4], VERSION_V3);
+
+        let parsed = parse(&bytes).unwrap();
+        assert_eq!(parsed.version(), VERSION_V3);
+        assert_eq!(parsed.nonce(), &[2u8; 24]);
+        assert_eq!(parsed.ciphertext(), &[3u8; 32]);
+        assert_eq!(parsed.sections().len(), 2);
+        assert_eq!(parsed.sections()[0].nonce(), &[4u8; 24]);
+        assert_eq!(parsed.sections()[1].ciphertext(), &[7u8; 17]);
+    }
+
+    #[test]
+    fn layout_locates_sections_without_reading_them() {
+        let bytes = serialize(&sample()).unwrap();
+        let mut cursor = Cursor::new(&bytes);
+        let layout = read_layout(&mut cursor).unwrap();
+
+        assert_eq!(layout.index, vec![3u8; 32]);
+        assert_eq!(layout.sections.len(), 2);
+        assert_eq!(
+            read_record(&mut cursor, &layout.sections[1]).unwrap(),
+            vec![7u8; 17]
+        );
+    }
+
+    #[test]
+    fn serialized_header_prefix_matches_aad() {
+        let file = sample();
+        let bytes = serialize(&file).unwrap();
+        let aad = build_record_aad(&file.header, 0);
+
+        let prefix = &aad[..aad.len() - LEN_LEN];
+        assert_eq!(&bytes[..prefix.len()], prefix);
+        assert_ne!(aad, build_record_aad(&file.header, 1));
+    }
+
+    #[test]
+    fn truncated_file_fails() {
+        let bytes = serialize(&sample()).unwrap();
+        let err = parse(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+    }
+
+    #[test]
+    fn trailing_data_fails() {
+        let mut bytes = serialize(&sample()).unwrap();
+        bytes.push(0);
+        assert!(parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! Passwords derived from a master secret instead of stored.
+//!
+//! A recipe holds what is needed to recompute a site's password: the site name, a
+//! counter (bumped to rotate the password) and the character classes and length. The
+//! characters are drawn from an HKDF-SHA256 stream keyed with the master secret, with
+//! the same rules as random passwords, so only the master secret has to be copied to
+//! other devices. Recipes are stored as `keynest-derive:<site>?counter=...` values.
+
+use anyhow::{Context, Result, bail};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+use zeroize::Zeroizing;
+
+use super::PasswordOptions;
+
+const SCHEME: &str = "keynest-derive:";
+/// HKDF salt; changing it changes every derived password.
+const SALT: &[u8] = b"keynest derive v1";
+/// Shortest master secret accepted, in bytes.
+const MIN_MASTER_LEN: usize = 16;
+
+/// How to derive the password of one site.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipe {
+    site: String,
+    counter: u32,
+    options: PasswordOptions,
+}
+
+impl Recipe {
+    /// Creates the recipe of `site` with counter 1 and the default password options.
+    /// The site is trimmed and lowercased, so `Example.com` and `example.com` share a
+    /// password.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the site is empty or contains whitespace, control characters,
+    /// or one of `/ ? & # %`.
+    pub fn new(site: &str) -> Result<Self> {
+        let site = site.trim().to_lowercase();
+        if site.is_empty() {
+            bail!("the site name is empty");
+        }
+        if site
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "/?&#%".contains(c))
+        {
+            bail!("invalid site name '{site}' (no spaces or any of / ? & # %)");
+        }
+        Ok(Self {
+            site,
+            counter: 1,
+            options: PasswordOptions::default(),
+        })
+    }
+
+    /// Sets the counter; a new counter gives an unrelated password.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `counter` is 0.
+    pub fn with_counter(mut self, counter: u32) -> Result<Self> {
+        if counter == 0 {
+            bail!("the counter starts at 1");
+        }
+        self.counter = counter;
+        Ok(self)
+    }
+
+    /// Sets the length and character classes of the password.
+    pub fn with_options(mut self, options: PasswordOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the normalized site name.
+    pub fn site(&self) -> &str {
+        &self.site
+    }
+
+    /// Returns the counter.
+    pub fn counter(&self) -> u32 {
+        self.counter
+    }
+
+    /// Returns the length and character classes of the password.
+    pub fn options(&self) -> &PasswordOptions {
+        &self.options
+    }
+
+    /// Returns whether `value` looks like a stored recipe.
+    pub fn is_recipe(value: &str) -> bool {
+        value.trim_start().starts_with(SCHEME)
+    }
+
+    /// Parses a recipe written by [`Recipe::to_uri`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is not a `keynest-derive:` URI, the site is invalid,
+    /// or a parameter is missing, invalid or unknown (a recipe written by a newer version
+    /// is rejected rather than yielding a different password).
+    pub fn parse(value: &str) -> Result<Self> {
+        let Some(rest) = value.trim().strip_prefix(SCHEME) else {
+            bail!("not a keynest-derive: recipe");
+        };
+        let (site, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut recipe = Self::new(site)?;
+
+        let mut counter = None;
+        let mut length = None;
+        let mut classes = None;
+        let mut no_ambiguous = false;
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            match name {
+                "counter" => counter = Some(parse_number(name, value)?),
+                "length" => length = Some(parse_number(name, value)?),
+                "classes" => classes = Some(value),
+                "no-ambiguous" => no_ambiguous = true,
+                _ => bail!("unsupported recipe parameter '{name}'"),
+            }
+        }
+        let (Some(counter), Some(length), Some(classes)) = (counter, length, classes) else {
+            bail!("recipe needs counter, length and classes");
+        };
+        if let Some(c) = classes.chars().find(|c| !"luds".contains(*c)) {
+            bail!("unknown character class '{c}' in recipe (expected l, u, d or s)");
+        }
+
+        recipe = recipe.with_counter(counter)?;
+        recipe.options = PasswordOptions::new(length as usize)
+            .with_lowercase(classes.contains('l'))
+            .with_uppercase(classes.contains('u'))
+            .with_digits(classes.contains('d'))
+            .with_symbols(classes.contains('s'))
+            .with_exclude_ambiguous(no_ambiguous);
+        Ok(recipe)
+    }
+
+    /// Returns the recipe as a `keynest-derive:` URI, with every parameter spelled out
+    /// so the password does not depend on defaults.
+    pub fn to_uri(&self) -> String {
+        let o = &self.options;
+        let classes: String = [
+            (o.lowercase, 'l'),
+            (o.uppercase, 'u'),
+            (o.digits, 'd'),
+            (o.symbols, 's'),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, c)| enabled.then_some(c))
+        .collect();
+        let mut uri = format!(
+            "{SCHEME}{}?counter={}&length={}&classes={classes}",
+            self.site, self.counter, o.length
+        );
+        if o.exclude_ambiguous {
+            uri.push_str("&no-ambiguous");
+        }
+        uri
+    }
+
+    /// Derives the password of the site from `master`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `master` is shorter than 16 bytes or the password options
+    /// cannot be satisfied (no character class, or fewer characters than classes).
+    pub fn derive(&self, master: &str) -> Result<Zeroizing<String>> {
+        if master.len() < MIN_MASTER_LEN {
+            bail!("the master secret must be at least {MIN_MASTER_LEN} bytes long");
+        }
+        let mut stream = Stream::new(master.as_bytes(), &self.site, self.counter);
+        self.options.generate_from(|| stream.next_u32())
+    }
+}
+
+impl fmt::Display for Recipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_uri())
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u32> {
+    value
+        .parse()
+        .with_context(|| format!("recipe {name} is not a number: '{value}'"))
+}
+
+/// The HKDF output of a site and counter, read 32 bits at a time.
+struct Stream {
+    hkdf: Hkdf<Sha256>,
+    /// `site || 0x00 || counter (4, BE)`; the block number is appended per block.
+    info: Vec<u8>,
+    block: u32,
+    bytes: Zeroizing<[u8; 32]>,
+    used: usize,
+}
+
+impl Stream {
+    fn new(master: &[u8], site: &str, counter: u32) -> Self {
+        let mut info = site.as_bytes().to_vec();
+        info.push(0);
+        info.extend_from_slice(&counter.to_be_bytes());
+        Self {
+            hkdf: Hkdf::new(Some(SALT), master),
+            info,
+            block: 0,
+            bytes: Zeroizing::new([0u8; 32]),
+            used: 32,
+        }
+    }
+
+    fn next_u32(&mut self) -> Result<u32> {
+        if self.used == self.bytes.len() {
+            self.hkdf
+                .expand_multi_info(&[&self.info, &self.block.to_be_bytes()], &mut *self.bytes)
+                .map_err(|_| anyhow::anyhow!("HKDF output length is invalid"))?;
+            self.block = self
+                .block
+                .checked_add(1)
+                .context("derivation ran out of output")?;
+            self.used = 0;
+        }
+        let value = u32::from_le_bytes(self.bytes[self.used..self.used + 4].try_into().unwrap());
+        self.used += 4;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "correct horse battery staple";
+
+    #[test]
+    fn derivation_is_stable() {
+        let recipe = Recipe::new("Example.com ").unwrap();
+        assert_eq!(recipe.site(), "example.com");
+        // Changing this value breaks every password derived so far.
+        assert_eq!(
+            recipe.derive(MASTER).unwrap().as_str(),
+            "iEsRrF2b0X1zulsCJj0yTyFN"
+        );
+        assert_eq!(
+            recipe.derive(MASTER).unwrap(),
+            recipe.derive(MASTER).unwrap()
+        );
+
+        let others = [
+            recipe.clone().with_counter(2).unwrap(),
+            Recipe::new("example.org").unwrap(),
+        ];
+        for other in others {
+            assert_ne!(
+                other.derive(MASTER).unwrap(),
+                recipe.derive(MASTER).unwrap()
+            );
+        }
+        assert_ne!(
+            recipe.derive("another master secret").unwrap(),
+            recipe.derive(MASTER).unwrap()
+        );
+        assert!(recipe.derive("short").is_err());
+    }
+
+    #[test]
+    fn derived_passwords_follow_the_options() {
+        let options = PasswordOptions::new(40)
+            .with_symbols(true)
+            .with_exclude_ambiguous(true);
+        let recipe = Recipe::new("example.com").unwrap().with_options(options);
+        let password = recipe.derive(MASTER).unwrap();
+        assert_eq!(password.chars().count(), 40);
+        assert!(password.chars().any(|c| c.is_ascii_punctuation()));
+        assert!(
+            !password
+                .chars()
+                .any(|c| crate::generator::AMBIGUOUS.contains(c))
+        );
+
+        let pin = recipe.with_options(
+            PasswordOptions::new(6)
+                .with_lowercase(false)
+                .with_uppercase(false),
+        );
+        assert!(
+            pin.derive(MASTER)
+                .unwrap()
+                .chars()
+                .all(|c| c.is_ascii_digit())
+        );
+    }
+
+    #[test]
+    fn recipes_round_trip_through_their_uri() {
+        let recipe = Recipe::new("alice@example.com")
+            .unwrap()
+            .with_counter(3)
+            .unwrap()
+            .with_options(
+                PasswordOptions::new(16)
+                    .with_symbols(true)
+                    .with_exclude_ambiguous(true),
+            );
+        let uri = recipe.to_uri();
+        assert_eq!(
+            uri,
+            "keynest-derive:alice@example.com?counter=3&length=16&classes=luds&no-ambiguous"
+        );
+        assert!(Recipe::is_recipe(&uri));
+        assert_eq!(Recipe::parse(&uri).unwrap(), recipe);
+
+        for value in [
+            "example.com",
+            "keynest-derive:?counter=1&length=8&classes=l",
+            "keynest-derive:a/b?counter=1&length=8&classes=l",
+            "keynest-derive:example.com?counter=0&length=8&classes=l",
+            "keynest-derive:example.com?counter=1&classes=l",
+            "keynest-derive:example.com?counter=1&length=8&classes=x",
+            "keynest-derive:example.com?counter=1&length=8&classes=l&pepper=1",
+        ] {
+            assert!(Recipe::parse(value).is_err(), "{value}");
+        }
+    }
+}
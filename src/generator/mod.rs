@@ -0,0 +1,342 @@
+//! Random password and passphrase generation.
+//!
+//! [`PasswordOptions`] draws characters uniformly from the enabled character classes and
+//! guarantees at least one character of each; [`PassphraseOptions`] draws words from a
+//! built-in list of 2048 common English words (11 bits each), diceware-style. Both use
+//! the same OS random generator as the keystore's salts and nonces. A [`Recipe`] draws
+//! the characters of a password from a master secret instead, so the same site always
+//! gets the same password.
+
+use anyhow::{Result, bail};
+use std::sync::LazyLock;
+use zeroize::Zeroizing;
+
+use crate::crypto::random::{self, EntropyUse};
+
+mod derive;
+
+pub use derive::Recipe;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+/// Characters that are easily confused with one another in many fonts.
+const AMBIGUOUS: &str = "0O1lI|`'\"";
+
+static WORDS: LazyLock<Vec<&'static str>> =
+    LazyLock::new(|| include_str!("wordlist.txt").lines().collect());
+
+/// Returns the word list used for passphrases: 2048 lowercase words, sorted.
+pub fn wordlist() -> &'static [&'static str] {
+    &WORDS
+}
+
+/// Character classes and length of a generated password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordOptions {
+    length: usize,
+    lowercase: bool,
+    uppercase: bool,
+    digits: bool,
+    symbols: bool,
+    exclude_ambiguous: bool,
+}
+
+impl Default for PasswordOptions {
+    /// 24 characters of letters and digits.
+    fn default() -> Self {
+        Self::new(24)
+    }
+}
+
+impl PasswordOptions {
+    /// Creates options for `length` characters of lowercase and uppercase letters and
+    /// digits, without symbols.
+    pub fn new(length: usize) -> Self {
+        Self {
+            length,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: false,
+            exclude_ambiguous: false,
+        }
+    }
+
+    /// Sets the number of characters.
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = length;
+        self
+    }
+
+    /// Includes or excludes lowercase letters.
+    pub fn with_lowercase(mut self, enabled: bool) -> Self {
+        self.lowercase = enabled;
+        self
+    }
+
+    /// Includes or excludes uppercase letters.
+    pub fn with_uppercase(mut self, enabled: bool) -> Self {
+        self.uppercase = enabled;
+        self
+    }
+
+    /// Includes or excludes digits.
+    pub fn with_digits(mut self, enabled: bool) -> Self {
+        self.digits = enabled;
+        self
+    }
+
+    /// Includes or excludes ASCII punctuation.
+    pub fn with_symbols(mut self, enabled: bool) -> Self {
+        self.symbols = enabled;
+        self
+    }
+
+    /// Leaves out characters that are easily confused, such as `0`/`O` and `1`/`l`/`I`.
+    pub fn with_exclude_ambiguous(mut self, enabled: bool) -> Self {
+        self.exclude_ambiguous = enabled;
+        self
+    }
+
+    /// Returns the number of characters.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Returns the enabled character classes, each as the characters it contributes.
+    fn classes(&self) -> Vec<Vec<char>> {
+        [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, chars)| {
+            chars
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect()
+        })
+        .collect()
+    }
+
+    /// Returns the entropy of a generated password in bits, ignoring the small loss
+    /// from requiring every class.
+    pub fn entropy_bits(&self) -> f64 {
+        let alphabet: usize = self.classes().iter().map(Vec::len).sum();
+        self.length as f64 * (alphabet as f64).log2()
+    }
+
+    /// Generates a password containing at least one character of every enabled class.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no class is enabled, the length is shorter than the number
+    /// of classes, or the OS random generator fails.
+    pub fn generate(&self) -> Result<Zeroizing<String>> {
+        self.generate_from(random_u32)
+    }
+
+    /// Generates a password from the 32-bit values returned by `next_u32`.
+    fn generate_from(
+        &self,
+        mut next_u32: impl FnMut() -> Result<u32>,
+    ) -> Result<Zeroizing<String>> {
+        let classes = self.classes();
+        if classes.is_empty() {
+            bail!("at least one character class must be enabled");
+        }
+        if self.length < classes.len() {
+            bail!(
+                "a password with {} character classes needs at least {} characters",
+                classes.len(),
+                classes.len()
+            );
+        }
+        let alphabet: Vec<char> = classes.iter().flatten().copied().collect();
+
+        // Redraw until every class is present, so all passwords that satisfy the
+        // requirement stay equally likely.
+        loop {
+            let mut password = Zeroizing::new(String::with_capacity(self.length));
+            for _ in 0..self.length {
+                password.push(alphabet[uniform_below(alphabet.len(), &mut next_u32)?]);
+            }
+            if classes
+                .iter()
+                .all(|class| password.chars().any(|c| class.contains(&c)))
+            {
+                return Ok(password);
+            }
+        }
+    }
+}
+
+/// Word count and formatting of a generated passphrase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassphraseOptions {
+    words: usize,
+    separator: String,
+    capitalize: bool,
+}
+
+impl Default for PassphraseOptions {
+    /// Six words separated by `-` (66 bits).
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+impl PassphraseOptions {
+    /// Creates options for `words` lowercase words separated by `-`.
+    pub fn new(words: usize) -> Self {
+        Self {
+            words,
+            separator: "-".to_string(),
+            capitalize: false,
+        }
+    }
+
+    /// Sets the text between words.
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Capitalizes the first letter of every word.
+    pub fn with_capitalize(mut self, enabled: bool) -> Self {
+        self.capitalize = enabled;
+        self
+    }
+
+    /// Returns the number of words.
+    pub fn words(&self) -> usize {
+        self.words
+    }
+
+    /// Returns the entropy of a generated passphrase in bits.
+    pub fn entropy_bits(&self) -> f64 {
+        self.words as f64 * (wordlist().len() as f64).log2()
+    }
+
+    /// Generates a passphrase.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the word count is 0 or the OS random generator fails.
+    pub fn generate(&self) -> Result<Zeroizing<String>> {
+        if self.words == 0 {
+            bail!("a passphrase needs at least one word");
+        }
+        let words = wordlist();
+        let mut passphrase = Zeroizing::new(String::new());
+        for i in 0..self.words {
+            if i > 0 {
+                passphrase.push_str(&self.separator);
+            }
+            let word = words[random_below(words.len())?];
+            if self.capitalize {
+                let mut chars = word.chars();
+                passphrase.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                passphrase.push_str(chars.as_str());
+            } else {
+                passphrase.push_str(word);
+            }
+        }
+        Ok(passphrase)
+    }
+}
+
+/// Returns a random 32-bit value from the OS random generator.
+fn random_u32() -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    random::fill(EntropyUse::Password, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Returns a uniformly distributed random number in `0..n`.
+fn random_below(n: usize) -> Result<usize> {
+    uniform_below(n, random_u32)
+}
+
+/// Maps the uniformly distributed values of `next_u32` to a uniformly distributed
+/// number in `0..n`.
+fn uniform_below(n: usize, mut next_u32: impl FnMut() -> Result<u32>) -> Result<usize> {
+    let n = u32::try_from(n).expect("alphabets and word lists are small");
+    // Reject values from the incomplete last block of `n`s to avoid modulo bias.
+    let limit = u32::MAX - u32::MAX % n;
+    loop {
+        let value = next_u32()?;
+        if value < limit {
+            return Ok((value % n) as usize);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wordlist_is_sorted_and_unique() {
+        let words = wordlist();
+        assert_eq!(words.len(), 2048);
+        assert!(words.windows(2).all(|w| w[0] < w[1]));
+        assert!(
+            words
+                .iter()
+                .all(|w| (3..=8).contains(&w.len()) && w.chars().all(|c| c.is_ascii_lowercase()))
+        );
+    }
+
+    #[test]
+    fn passwords_use_every_enabled_class() {
+        let options = PasswordOptions::new(32)
+            .with_symbols(true)
+            .with_exclude_ambiguous(true);
+        for _ in 0..50 {
+            let password = options.generate().unwrap();
+            assert_eq!(password.chars().count(), 32);
+            assert!(password.chars().any(|c| c.is_ascii_lowercase()));
+            assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+            assert!(password.chars().any(|c| c.is_ascii_digit()));
+            assert!(password.chars().any(|c| c.is_ascii_punctuation()));
+            assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+        }
+
+        let digits = PasswordOptions::new(6)
+            .with_lowercase(false)
+            .with_uppercase(false);
+        assert!(
+            digits
+                .generate()
+                .unwrap()
+                .chars()
+                .all(|c| c.is_ascii_digit())
+        );
+        assert!((digits.entropy_bits() - 6.0 * 10f64.log2()).abs() < 1e-9);
+
+        assert!(digits.with_digits(false).generate().is_err());
+        assert!(PasswordOptions::new(2).generate().is_err());
+    }
+
+    #[test]
+    fn passphrases_join_listed_words() {
+        let options = PassphraseOptions::new(5)
+            .with_separator(" ")
+            .with_capitalize(true);
+        let passphrase = options.generate().unwrap();
+        let words: Vec<&str> = passphrase.split(' ').collect();
+        assert_eq!(words.len(), 5);
+        for word in words {
+            assert!(word.starts_with(|c: char| c.is_ascii_uppercase()));
+            assert!(wordlist().contains(&word.to_lowercase().as_str()));
+        }
+        assert_eq!(options.entropy_bits(), 55.0);
+        assert!(PassphraseOptions::new(0).generate().is_err());
+    }
+}
@@ -0,0 +1,618 @@
+//! Read-only keystore access that decrypts sections on demand.
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+use crate::error::StoreError;
+use crate::format::v3::{self, RecordRef};
+use crate::format::{Header, MAGIC_LEN, VER_LEN, file_hash, parse_data};
+use crate::journal::Journal;
+use crate::settings::{AuditLog, Leases, Pinned, ReadReceipts, Templates, UsageStats};
+use crate::store::{AccessPolicy, SecretEntry, StoreIndex, reference_target, walk_references};
+use crate::template::Template;
+use crate::{
+    AuditEvent, CancelToken, Keyfile, Keynest, Lease, Setting, Storage, Unlock, UnlockKey, Usage,
+    crypto, default_storage, lease, open_decoy, payload, with_key_check,
+};
+
+/// A read-only view of a keystore that only decrypts what it needs.
+///
+/// Opening decrypts the index (keys and store metadata); the entries themselves are
+/// split into sections that are read from disk and decrypted the first time one of
+/// their keys is accessed. Opening a store with tens of thousands of entries to read a
+/// single secret therefore costs one index and one section instead of the whole store.
+///
+/// Keystores in the single-ciphertext (v1/v2) format, and keystores with a journal (see
+/// [`Keynest::set_journal`]), are decrypted completely on open.
+///
+/// # Example
+///
+/// ```ignore
+/// use keynest::IndexedKeynest;
+/// use zeroize::Zeroizing;
+///
+/// let mut kn = IndexedKeynest::open(Zeroizing::new("password".to_string())).unwrap();
+/// let value = kn.resolve("api_key").unwrap();
+/// ```
+pub struct IndexedKeynest {
+    storage: Storage,
+    file: Option<File>,
+    header: Header,
+    key: Zeroizing<[u8; crypto::KEY_LEN]>,
+    index: StoreIndex,
+    /// Schema the sections were written with (see [`crate::migrations`]).
+    schema: u32,
+    records: Vec<RecordRef>,
+    sections: HashMap<u32, BTreeMap<String, SecretEntry>>,
+}
+
+impl IndexedKeynest {
+    /// Opens an existing keystore with the default storage location.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - No keystore exists at the default location
+    /// - The password is incorrect
+    /// - The keystore is corrupted
+    pub fn open(password: Zeroizing<String>) -> Result<Self> {
+        let storage = default_storage()?;
+        Self::open_with_storage(password, storage)
+    }
+
+    /// Opens an existing keystore from a custom storage location.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - No keystore exists at the given storage path
+    /// - The password is incorrect
+    /// - The keystore is corrupted
+    pub fn open_with_storage(password: Zeroizing<String>, storage: Storage) -> Result<Self> {
+        Self::open_inner(Unlock::Password(password, None, None), storage)
+    }
+
+    /// Opens an existing keystore with a key obtained from [`Keynest::unlock_key`]
+    /// earlier, skipping the key derivation.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::open_with_key`].
+    pub fn open_with_key(key: &UnlockKey, storage: Storage) -> Result<Self> {
+        Self::open_inner(Unlock::Key(key), storage)
+    }
+
+    /// Opens an existing keystore from a custom storage location, deriving the key on a
+    /// worker thread that can be abandoned through `cancel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Cancelled`](crate::Cancelled) error if `cancel` is triggered during key
+    /// derivation, otherwise the same errors as [`IndexedKeynest::open_with_storage`].
+    pub fn open_with_storage_cancellable(
+        password: Zeroizing<String>,
+        storage: Storage,
+        cancel: &CancelToken,
+    ) -> Result<Self> {
+        Self::open_inner(Unlock::Password(password, None, Some(cancel)), storage)
+    }
+
+    /// Opens an existing keystore that requires a keyfile, deriving the key on a worker
+    /// thread that can be abandoned through `cancel`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::open_with_keyfile_cancellable`].
+    pub fn open_with_keyfile_cancellable(
+        password: Zeroizing<String>,
+        keyfile: &Keyfile,
+        storage: Storage,
+        cancel: &CancelToken,
+    ) -> Result<Self> {
+        Self::open_inner(
+            Unlock::Password(password, Some(keyfile), Some(cancel)),
+            storage,
+        )
+    }
+
+    fn open_inner(unlock: Unlock, storage: Storage) -> Result<Self> {
+        if !storage.exists() {
+            bail!(
+                "keystore does not exist: {}\nRun `keynest init` first.",
+                storage.path().display()
+            );
+        }
+
+        let mut file = storage.open_read()?;
+        let mut prefix = [0u8; MAGIC_LEN + VER_LEN];
+        file.read_exact(&mut prefix).context("file too short")?;
+
+        if prefix[MAGIC_LEN] != v3::VERSION_V3 || storage.journal_path().exists() {
+            return Self::open_single_section(unlock, storage);
+        }
+
+        let layout = v3::read_layout(&mut file)?;
+        let key = Zeroizing::new(unlock.key(&layout.header)?.0);
+        if open_decoy(&layout.header, &*key).is_some() {
+            return Self::open_single_section(Unlock::Key(&UnlockKey::from_bytes(*key)), storage);
+        }
+
+        let (index, schema) = with_key_check(&layout.header, &*key, || {
+            let plaintext = layout.header.decrypt(&*key, &layout.index)?;
+            let (index, schema) = payload::parse_index(&plaintext, layout.header.encoding())?;
+            index.verify_sections(layout.sections.iter().map(|r| r.nonce.as_slice()))?;
+            Ok((index, schema))
+        })?;
+
+        Ok(Self {
+            storage,
+            file: Some(file),
+            header: layout.header,
+            key,
+            index,
+            schema,
+            records: layout.sections,
+            sections: HashMap::new(),
+        })
+    }
+
+    /// Opens a v1/v2 keystore or one with a journal, presenting the whole store as a
+    /// single loaded section.
+    fn open_single_section(unlock: Unlock, storage: Storage) -> Result<Self> {
+        let mut kn = Keynest::open_inner(unlock, storage.clone(), None)?;
+        let (index, entries) = std::mem::take(kn.store_mut()?).into_single_section();
+
+        Ok(Self {
+            storage,
+            file: None,
+            header: kn.keystore_file.header.clone(),
+            key: Zeroizing::new(kn.key),
+            index,
+            schema: crate::migrations::CURRENT_SCHEMA,
+            records: Vec::new(),
+            sections: HashMap::from([(0, entries)]),
+        })
+    }
+
+    /// Retrieves a secret entry by key, decrypting its section if necessary.
+    ///
+    /// Returns `None` if the key does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the section cannot be read or authenticated.
+    pub fn entry(&mut self, key: &str) -> Result<Option<&SecretEntry>> {
+        let Some(section) = self.index.section_of(key) else {
+            return Ok(None);
+        };
+        self.load_section(section)?;
+
+        match self.sections[&section].get(key) {
+            Some(entry) => Ok(Some(entry)),
+            None => Err(StoreError::CorruptedIndex(format!(
+                "'{key}' is missing from section {section}"
+            ))
+            .into()),
+        }
+    }
+
+    /// Retrieves a secret by key without following `ref:<key>` references.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the section cannot be read or authenticated.
+    pub fn get(&mut self, key: &str) -> Result<Option<&str>> {
+        Ok(self.entry(key)?.map(|e| e.value()))
+    }
+
+    /// Retrieves a secret by key, transparently following `ref:<key>` references.
+    ///
+    /// Only the sections holding entries on the reference chain are decrypted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a section cannot be read or authenticated, or if the
+    /// reference chain contains a cycle or points at a missing key.
+    pub fn resolve(&mut self, key: &str) -> Result<Option<&str>> {
+        let chain = walk_references(key, |k| {
+            Ok::<_, anyhow::Error>(
+                self.entry(k)?
+                    .map(|e| reference_target(e.value()).map(str::to_string)),
+            )
+        })?;
+
+        match chain.last() {
+            Some(last) => self.get(last),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the access policy that applies when reading `key`, merged over its
+    /// `ref:` chain like [`Keynest::effective_policy`]. Returns `None` if `key` does not
+    /// exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a section cannot be read or authenticated, or if the
+    /// reference chain contains a cycle or points at a missing key.
+    pub fn effective_policy(&mut self, key: &str) -> Result<Option<AccessPolicy>> {
+        let chain = walk_references(key, |k| {
+            Ok::<_, anyhow::Error>(
+                self.entry(k)?
+                    .map(|e| reference_target(e.value()).map(str::to_string)),
+            )
+        })?;
+        if chain.is_empty() {
+            return Ok(None);
+        }
+        let mut policy = AccessPolicy::default();
+        for k in &chain {
+            if let Some(entry) = self.entry(k)? {
+                policy = policy.merge(entry.policy());
+            }
+        }
+        Ok(Some(policy))
+    }
+
+    /// Lists all secret keys. Does not decrypt any section.
+    pub fn list(&self) -> Vec<&String> {
+        self.index.keys().collect()
+    }
+
+    /// Lists the secret keys starting with `prefix`. Does not decrypt any section.
+    pub fn list_prefix(&self, prefix: &str) -> Vec<&String> {
+        self.index.keys_with_prefix(prefix).collect()
+    }
+
+    /// Returns the keys of the secrets whose expiry has passed, sorted. Does not decrypt
+    /// any section, unless the index was written before it recorded expiries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a section has to be read and cannot be decrypted.
+    pub fn expired_keys(&mut self) -> Result<Vec<String>> {
+        let now = Utc::now();
+        if let Some(keys) = self.index.expired_at(now) {
+            return Ok(keys.into_iter().cloned().collect());
+        }
+        let keys: Vec<String> = self.index.keys().cloned().collect();
+        let mut expired = Vec::new();
+        for key in keys {
+            if self.entry(&key)?.is_some_and(|e| e.is_expired_at(now)) {
+                expired.push(key);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Returns the number of secrets stored.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns `true` if the keystore holds no secrets.
+    pub fn is_empty(&self) -> bool {
+        self.index.len() == 0
+    }
+
+    /// Returns the creation date of the store.
+    pub fn creation_date(&self) -> &str {
+        self.index.creation_date()
+    }
+
+    /// Returns the keys of the entries pinned as favorites. Does not decrypt any section.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn pinned(&self) -> Result<BTreeSet<String>> {
+        Ok(self.setting::<Pinned>()?.unwrap_or_default())
+    }
+
+    /// Returns the active leases of all entries, by key (see [`Keynest::lease`]). Does not
+    /// decrypt any section.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn leases(&self) -> Result<BTreeMap<String, Vec<Lease>>> {
+        let mut leases = self.setting::<Leases>()?.unwrap_or_default();
+        lease::retain_active(&mut leases, Utc::now());
+        Ok(leases)
+    }
+
+    /// Returns `true` if reads are recorded (see [`Keynest::records_reads`]), so readers
+    /// need [`IndexedKeynest::into_keynest`] to record them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored settings are malformed.
+    pub fn records_reads(&self) -> Result<bool> {
+        Ok(self.setting::<UsageStats>()?.is_some()
+            || self.setting::<ReadReceipts>()?.is_some()
+            || self.setting::<AuditLog>()?.is_some())
+    }
+
+    /// Returns the events of the audit log, or `None` while it is off (see
+    /// [`Keynest::audit_events`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be read, or was changed or truncated.
+    pub fn audit_events(&self) -> Result<Option<Vec<AuditEvent>>> {
+        let Some(state) = self.setting::<AuditLog>()? else {
+            return Ok(None);
+        };
+        let events = crate::audit::read(&self.storage.audit_path(), &state.key()?, state.frames())?;
+        Ok(Some(events))
+    }
+
+    /// Returns the entry templates stored in the keystore (see [`Keynest::templates`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn templates(&self) -> Result<BTreeMap<String, Template>> {
+        Ok(self.setting::<Templates>()?.unwrap_or_default())
+    }
+
+    /// Returns the value of the store setting `S`, or `None` if it is not set. Does not
+    /// decrypt any section.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored value does not match `S::Value`.
+    pub fn setting<S: Setting>(&self) -> Result<Option<S::Value>> {
+        Ok(self.index.settings().get::<S>()?)
+    }
+
+    /// Turns the view into a writable [`Keynest`] by decrypting the whole store with the
+    /// already derived key, without running the key derivation again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or no longer decrypts with the key
+    /// (e.g. it was rekeyed in the meantime).
+    pub fn into_keynest(self) -> Result<Keynest> {
+        let data = Arc::new(self.storage.load_data()?);
+        let file_hash = file_hash(&data);
+        let mut keystore_file = parse_data(&data)?;
+        drop(data);
+        let (mut store, decoy) = match open_decoy(&keystore_file.header, &*self.key) {
+            Some(store) => (store, true),
+            None => (
+                with_key_check(&keystore_file.header, &*self.key, || {
+                    payload::decrypt(&keystore_file, &*self.key)
+                })?,
+                false,
+            ),
+        };
+        keystore_file.release_mapping();
+        // The journal extends the records, not the decoy.
+        let journal = if decoy {
+            None
+        } else {
+            Journal::replay(&self.storage, &keystore_file, &*self.key, &mut store)?
+        };
+        let mut kn = Keynest {
+            store: store.into(),
+            sealed: None,
+            storage: self.storage.clone(),
+            key: *self.key,
+            keystore_file,
+            entropy: None,
+            locked: false,
+            autosave: false,
+            batch: 0,
+            reader: None,
+            keyfile: None,
+            keyslot: None,
+            journal,
+            audited: None,
+            audit_pending: Vec::new(),
+            file_hash,
+            chain_witness: None,
+            decoy,
+        };
+        kn.track_journal()?;
+        kn.track_audit()?;
+        kn.update_usage(Usage::record_open)?;
+        Ok(kn)
+    }
+
+    fn load_section(&mut self, section: u32) -> Result<()> {
+        if self.sections.contains_key(&section) {
+            return Ok(());
+        }
+
+        let (Some(file), Some(record)) = (self.file.as_mut(), self.records.get(section as usize))
+        else {
+            return Err(
+                StoreError::CorruptedIndex(format!("section {section} does not exist")).into(),
+            );
+        };
+
+        let ciphertext = v3::read_record(file, record)?;
+        let plaintext = with_key_check(&self.header, &*self.key, || {
+            payload::decrypt_section(
+                &self.header,
+                &self.index,
+                &*self.key,
+                section,
+                &record.nonce,
+                &ciphertext,
+            )
+        })?;
+
+        let mut entries = BTreeMap::new();
+        for entry in payload::parse_section(&plaintext, self.schema, self.header.encoding())? {
+            if self.index.section_of(entry.key()) != Some(section) {
+                return Err(StoreError::CorruptedIndex(format!(
+                    "'{}' is not indexed in section {section}",
+                    entry.key()
+                ))
+                .into());
+            }
+            entries.insert(entry.key().to_string(), entry);
+        }
+
+        self.sections.insert(section, entries);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KdfParams;
+    use crate::format::parse;
+
+    fn pw() -> Zeroizing<String> {
+        Zeroizing::new("pw".to_string())
+    }
+
+    #[test]
+    fn get_decrypts_only_the_needed_section() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn =
+            Keynest::init_with_storage_and_kdf(pw(), storage.clone(), KdfParams::default())
+                .unwrap();
+        for i in 0..1000 {
+            kn.set(&format!("key{i:04}"), &format!("value{i}")).unwrap();
+        }
+        kn.set("alias", "ref:key0999").unwrap();
+        kn.save().unwrap();
+
+        let mut indexed = IndexedKeynest::open_with_storage(pw(), storage).unwrap();
+        assert_eq!(indexed.len(), 1001);
+        assert!(indexed.sections.is_empty());
+
+        assert_eq!(indexed.get("key0500").unwrap(), Some("value500"));
+        assert_eq!(indexed.sections.len(), 1);
+
+        assert_eq!(indexed.resolve("alias").unwrap(), Some("value999"));
+        assert_eq!(indexed.sections.len(), 3);
+
+        assert_eq!(indexed.get("missing").unwrap(), None);
+        assert_eq!(indexed.sections.len(), 3);
+    }
+
+    #[test]
+    fn per_entry_records_decrypt_only_the_entry_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let mut kn = Keynest::init_with_storage_and_kdf(pw(), storage.clone(), kdf).unwrap();
+        for i in 0..300 {
+            kn.set(&format!("key{i:03}"), &format!("value{i}")).unwrap();
+        }
+        kn.set_per_entry_records(true).unwrap();
+        kn.save().unwrap();
+        assert_eq!(
+            storage
+                .load()
+                .map(|d| parse(&d).unwrap().sections().len())
+                .unwrap(),
+            300
+        );
+
+        let mut indexed = IndexedKeynest::open_with_storage(pw(), storage.clone()).unwrap();
+        assert_eq!(indexed.get("key150").unwrap(), Some("value150"));
+        assert_eq!(indexed.sections.len(), 1);
+        assert_eq!(indexed.sections.values().next().unwrap().len(), 1);
+
+        let mut kn = indexed.into_keynest().unwrap();
+        assert!(kn.per_entry_records().unwrap());
+        kn.set_per_entry_records(false).unwrap();
+        kn.save().unwrap();
+        assert_eq!(
+            storage
+                .load()
+                .map(|d| parse(&d).unwrap().sections().len())
+                .unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn expired_keys_are_read_from_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn =
+            Keynest::init_with_storage_and_kdf(pw(), storage.clone(), KdfParams::default())
+                .unwrap();
+        let now = Utc::now();
+        for (key, expires) in [
+            ("old", Some(now - chrono::TimeDelta::days(1))),
+            ("fresh", Some(now + chrono::TimeDelta::days(1))),
+            ("plain", None),
+        ] {
+            kn.set(key, "x").unwrap();
+            kn.set_expiry(key, expires).unwrap();
+        }
+        kn.save().unwrap();
+
+        let mut indexed = IndexedKeynest::open_with_storage(pw(), storage).unwrap();
+        assert_eq!(indexed.expired_keys().unwrap(), ["old"]);
+        assert!(indexed.sections.is_empty());
+
+        // Indexes written before expiries were indexed fall back to the entries.
+        let mut index = serde_json::to_value(&indexed.index).unwrap();
+        index.as_object_mut().unwrap().remove("expires").unwrap();
+        indexed.index = serde_json::from_value(index).unwrap();
+        assert_eq!(indexed.expired_keys().unwrap(), ["old"]);
+        assert!(!indexed.sections.is_empty());
+    }
+
+    #[test]
+    fn wrong_password_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        Keynest::init_with_storage_and_kdf(pw(), storage.clone(), KdfParams::default()).unwrap();
+
+        assert!(
+            IndexedKeynest::open_with_storage(Zeroizing::new("wrong".to_string()), storage)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn opens_single_ciphertext_keystores() {
+        use crate::format::{Header, KeystoreFile, serialize};
+        use crate::store::Store;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let kdf = KdfParams::default();
+        let salt = crypto::generate_salt().unwrap();
+        let key = crypto::derive_key("pw", &salt, kdf).unwrap();
+
+        let mut store = Store::new();
+        store.set("a", "b").unwrap();
+        let (header, ciphertext) = Header::encrypt_store(
+            kdf,
+            crate::Algorithm::XChaCha20Poly1305,
+            salt.to_vec(),
+            &key,
+            &serde_json::to_vec(&store).unwrap(),
+        )
+        .unwrap();
+        storage
+            .save(&serialize(&KeystoreFile::new(header, ciphertext)).unwrap())
+            .unwrap();
+
+        let mut indexed = IndexedKeynest::open_with_storage(pw(), storage).unwrap();
+        assert_eq!(indexed.list(), vec!["a"]);
+        assert_eq!(indexed.get("a").unwrap(), Some("b"));
+    }
+}
@@ -0,0 +1,254 @@
+//! Append-only journal of the changes saved since the keystore file was last written.
+//!
+//! Saving a sectioned keystore re-encrypts and rewrites every section, so changing one
+//! entry of a large store costs as much as writing it from scratch. With the
+//! [`JournalEnabled`](crate::settings::JournalEnabled) setting on, [`crate::Keynest::save`] instead
+//! appends the changed entries (and the store metadata, if it changed) to
+//! `<store>.journal`, and opening replays the journal over the file. The file is
+//! rewritten and the journal removed once the journal grows past half the size of the
+//! file, after [`MAX_FRAMES`] saves, or on [`crate::Keynest::compact`].
+//!
+//! File layout:
+//! ```text
+//! MAGIC "KNSJ" (4) | VERSION (1) | BASE_LEN (1) | BASE | FRAME...
+//! FRAME = NONCE | CIPHERTEXT_LEN (4, LE) | CIPHERTEXT
+//! ```
+//!
+//! `BASE` is the nonce of the index record of the keystore file the journal extends.
+//! Every write of the file picks a new one, so a journal that outlived its file (e.g.
+//! after restoring a backup) is ignored. Frames are encrypted with the store key and
+//! authenticated with the AAD of the index record followed by the magic, `BASE` and
+//! the frame number, so they cannot be reordered, skipped or moved to another file.
+//!
+//! Appending is the only write, so a crash can only damage the last frame. A last
+//! frame that is truncated or does not authenticate is ignored, losing only the save
+//! that was interrupted; the next save rewrites the file.
+
+use anyhow::{Context, Result, bail};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use crate::format::v2::MAX_CIPHERTEXT;
+use crate::format::{KeystoreFile, v3};
+use crate::payload;
+use crate::storage::Storage;
+use crate::store::{Store, StoreDigest};
+
+const MAGIC: &[u8; 4] = b"KNSJ";
+const VERSION: u8 = 1;
+const LEN_LEN: usize = 4;
+/// Number of saves after which the file is rewritten, to bound the work on open.
+pub(crate) const MAX_FRAMES: u32 = 1024;
+/// Size up to which the journal may grow regardless of the size of the file.
+const MIN_COMPACT_LEN: u64 = 64 * 1024;
+
+/// State of the journal of an open keystore, as of its last frame.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    /// Nonce of the index record of the file the journal extends.
+    base: Vec<u8>,
+    /// Length of the journal on disk; 0 if there is none yet.
+    len: u64,
+    frames: u32,
+    /// Digest of the store as of the last frame, or as written to the file.
+    digest: StoreDigest,
+}
+
+impl Journal {
+    /// Starts an empty journal for `file`, which holds `store`.
+    pub(crate) fn new(file: &KeystoreFile, store: &Store) -> Self {
+        Self {
+            base: file.nonce().to_vec(),
+            len: 0,
+            frames: 0,
+            digest: store.digest(),
+        }
+    }
+
+    /// Applies the journal next to `storage` to `store`, which was decrypted from
+    /// `file`.
+    ///
+    /// Returns `None` if there is no journal, or it extends another version of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read, is malformed, or a frame other
+    /// than the last does not authenticate.
+    pub(crate) fn replay(
+        storage: &Storage,
+        file: &KeystoreFile,
+        key: &[u8],
+        store: &mut Store,
+    ) -> Result<Option<Self>> {
+        let journal = Storage::new(storage.journal_path());
+        if !file.is_sectioned() || !journal.exists() {
+            return Ok(None);
+        }
+        let data = journal.load()?;
+
+        let Some((base, mut pos)) = parse_header(&data)? else {
+            return Ok(None);
+        };
+        if base != file.nonce() {
+            return Ok(None);
+        }
+
+        let nonce_len = file.algorithm().nonce_len();
+        let mut frames = 0;
+        while let Some(nonce) = data.get(pos..pos + nonce_len) {
+            let Some(len) = data.get(pos + nonce_len..pos + nonce_len + LEN_LEN) else {
+                break;
+            };
+            let start = pos + nonce_len + LEN_LEN;
+            let end = start + u32::from_le_bytes(len.try_into()?) as usize;
+            let Some(ciphertext) = data.get(start..end) else {
+                break;
+            };
+
+            let aad = frame_aad(file, base, frames);
+            let plaintext = match file.algorithm().decrypt(key, nonce, ciphertext, &aad) {
+                Ok(plaintext) => plaintext,
+                Err(_) if end == data.len() => break,
+                Err(_) => bail!(
+                    "journal frame {frames} of {} does not authenticate; possibly corrupted \
+                     data. Move the journal away to open the keystore without the changes \
+                     saved since it was last compacted.",
+                    journal.path().display()
+                ),
+            };
+            store.apply_delta(payload::parse_delta(&plaintext, file.header.encoding())?);
+            frames += 1;
+            pos = end;
+        }
+
+        Ok(Some(Self {
+            base: base.to_vec(),
+            len: pos as u64,
+            frames,
+            digest: store.digest(),
+        }))
+    }
+
+    /// Returns the number of frames, i.e. saves since the file was last written.
+    pub(crate) fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Appends the changes of `store` since the last frame.
+    ///
+    /// Returns `false` without writing if the file should be rewritten instead: the
+    /// journal would grow too large, or the file or the journal changed on disk since
+    /// this process read them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or encryption fails or the journal cannot be
+    /// written.
+    pub(crate) fn append(
+        &mut self,
+        storage: &Storage,
+        file: &KeystoreFile,
+        key: &[u8],
+        store: &Store,
+    ) -> Result<bool> {
+        if self.frames >= MAX_FRAMES || self.base != file.nonce() {
+            return Ok(false);
+        }
+        let path = storage.journal_path();
+        let on_disk = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).context("failed to read the journal"),
+        };
+        let mut current = storage.open_read()?;
+        let file_len = current.metadata()?.len();
+        let nonce_len = file.algorithm().nonce_len();
+        if on_disk != self.len
+            || v3::read_index_nonce(&mut current, nonce_len).ok().as_ref() != Some(&self.base)
+        {
+            return Ok(false);
+        }
+
+        let (delta, digest) = store.delta(&self.digest);
+        if delta.is_empty() {
+            return Ok(true);
+        }
+        let plaintext = payload::encode_delta(file.header.encoding(), &delta)?;
+        let aad = frame_aad(file, &self.base, self.frames);
+        let (ciphertext, nonce) = file.algorithm().encrypt(key, &plaintext, &aad)?;
+        if ciphertext.len() > MAX_CIPHERTEXT {
+            return Ok(false);
+        }
+
+        let mut out = Vec::new();
+        if self.len == 0 {
+            out.extend_from_slice(MAGIC);
+            out.push(VERSION);
+            out.push(self.base.len() as u8);
+            out.extend_from_slice(&self.base);
+        }
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&(ciphertext.len() as u32).to_le_bytes());
+        out.extend_from_slice(&ciphertext);
+        if self.len + out.len() as u64 > (file_len / 2).max(MIN_COMPACT_LEN) {
+            return Ok(false);
+        }
+
+        write_frame(&path, &out, self.len == 0)
+            .with_context(|| format!("failed to append to {}", path.display()))?;
+        self.len += out.len() as u64;
+        self.frames += 1;
+        self.digest = digest;
+        Ok(true)
+    }
+}
+
+/// Parses the journal header, returning `BASE` and the offset of the first frame, or
+/// `None` if a crash left the header incomplete.
+fn parse_header(data: &[u8]) -> Result<Option<(&[u8], usize)>> {
+    let Some(fixed) = data.get(..MAGIC.len() + 2) else {
+        return Ok(None);
+    };
+    if &fixed[..MAGIC.len()] != MAGIC {
+        bail!("invalid journal magic");
+    }
+    if fixed[MAGIC.len()] != VERSION {
+        bail!("unsupported journal version {}", fixed[MAGIC.len()]);
+    }
+    let start = fixed.len();
+    let end = start + usize::from(fixed[MAGIC.len() + 1]);
+    Ok(data.get(start..end).map(|base| (base, end)))
+}
+
+/// Builds the AAD of frame `frame` of the journal extending `file`.
+fn frame_aad(file: &KeystoreFile, base: &[u8], frame: u32) -> Vec<u8> {
+    let mut aad = v3::build_record_aad(&file.header, 0);
+    aad.extend_from_slice(MAGIC);
+    aad.extend_from_slice(base);
+    aad.extend_from_slice(&frame.to_le_bytes());
+    aad
+}
+
+/// Appends `data` to the journal at `path` and syncs it to disk.
+fn write_frame(path: &Path, data: &[u8], create: bool) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.append(true).create(create);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut journal = options.open(path)?;
+    journal.write_all(data)?;
+    journal.sync_all()?;
+
+    #[cfg(not(target_os = "windows"))]
+    if let Some(parent) = path
+        .parent()
+        .filter(|p| create && !p.as_os_str().is_empty())
+    {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
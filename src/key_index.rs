@@ -0,0 +1,224 @@
+//! Opt-in Bloom-filter index of key names, stored next to the keystore.
+//!
+//! The index lets scripts and shell completion ask whether a key exists without the
+//! master password. It is a Bloom filter over salted HMACs of the key names, so it does
+//! not list the names, but anyone who can read it can test guessed names against it:
+//! enabling it leaks the existence of keys. It is therefore off unless the
+//! [`KeyIndexEnabled`](crate::settings::KeyIndexEnabled) setting is set, and is
+//! rewritten on every save while enabled.
+//!
+//! File layout (`<store>.keyindex`):
+//! ```text
+//! MAGIC "KNKI" (4) | VERSION (1) | HASHES (1) | BITS (4, LE) | SALT (16) | FILTER
+//! ```
+
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::path::PathBuf;
+
+use crate::storage::Storage;
+
+const MAGIC: &[u8; 4] = b"KNKI";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = 4 + 1 + 1 + 4 + SALT_LEN;
+
+/// Target false-positive rate when sizing the filter.
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+/// Smallest filter, so tiny stores do not produce a trivially saturated index.
+const MIN_BITS: u32 = 1024;
+/// Largest accepted filter (16 MiB of bits), to bound memory when loading.
+const MAX_BITS: u32 = 1 << 27;
+const MAX_HASHES: u8 = 16;
+
+/// A Bloom filter of key names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyIndex {
+    hashes: u8,
+    bits: u32,
+    salt: [u8; SALT_LEN],
+    filter: Vec<u8>,
+}
+
+impl KeyIndex {
+    /// Builds an index of `keys` with a fresh random salt.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the system random number generator fails.
+    pub fn build<'a>(keys: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let keys: Vec<&str> = keys.into_iter().collect();
+        let n = keys.len().max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-n * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil();
+        let bits = (bits.min(f64::from(MAX_BITS)) as u32)
+            .max(MIN_BITS)
+            .next_multiple_of(8);
+        let hashes = ((f64::from(bits) / n) * ln2)
+            .round()
+            .clamp(1.0, f64::from(MAX_HASHES)) as u8;
+
+        let mut salt = [0u8; SALT_LEN];
+        crate::crypto::random::fill(crate::EntropyUse::Salt, &mut salt)?;
+
+        let mut index = Self {
+            hashes,
+            bits,
+            salt,
+            filter: vec![0; bits as usize / 8],
+        };
+        for key in keys {
+            let positions: Vec<usize> = index.bit_positions(key).collect();
+            for bit in positions {
+                index.filter[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Returns `false` if `key` is definitely not in the index, `true` if it probably is.
+    pub fn might_contain(&self, key: &str) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.filter[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Returns the number of hash functions.
+    pub fn hashes(&self) -> u8 {
+        self.hashes
+    }
+
+    /// Returns the size of the filter in bits.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Returns the fraction of set bits; the false-positive rate is about this value
+    /// raised to the number of hashes.
+    pub fn fill_ratio(&self) -> f64 {
+        let set: u32 = self.filter.iter().map(|b| b.count_ones()).sum();
+        f64::from(set) / f64::from(self.bits)
+    }
+
+    /// Encodes the index in its file format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.filter.len());
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(self.hashes);
+        out.extend_from_slice(&self.bits.to_le_bytes());
+        out.extend_from_slice(&self.salt);
+        out.extend_from_slice(&self.filter);
+        out
+    }
+
+    /// Decodes an index from its file format.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is not a key index or is malformed.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            bail!("not a keynest key index");
+        }
+        if data[4] != VERSION {
+            bail!("unsupported key index version: {}", data[4]);
+        }
+        let hashes = data[5];
+        let bits = u32::from_le_bytes(data[6..10].try_into()?);
+        if !(1..=MAX_HASHES).contains(&hashes)
+            || !(MIN_BITS..=MAX_BITS).contains(&bits)
+            || bits % 8 != 0
+            || data.len() - HEADER_LEN != bits as usize / 8
+        {
+            bail!("malformed key index");
+        }
+        Ok(Self {
+            hashes,
+            bits,
+            salt: data[10..HEADER_LEN].try_into()?,
+            filter: data[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Reads the index belonging to `storage`, or `None` if there is none. Does not
+    /// need the master password.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is malformed.
+    pub fn load(storage: &Storage) -> Result<Option<Self>> {
+        let path = index_path(storage);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(Self::from_bytes(&std::fs::read(&path)?)?))
+    }
+
+    /// Bit positions of `key`, derived from one salted HMAC by double hashing.
+    fn bit_positions(&self, key: &str) -> impl Iterator<Item = usize> + '_ {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.salt)
+            .expect("HMAC accepts keys of any length");
+        mac.update(key.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let h1 = u64::from_le_bytes(digest[..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().expect("8 bytes")) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % u64::from(self.bits)) as usize)
+    }
+}
+
+/// Returns the index file belonging to `storage` (`<store file>.keyindex`).
+pub fn index_path(storage: &Storage) -> PathBuf {
+    let mut name = storage
+        .path()
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(".keyindex");
+    storage.path().with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_all_indexed_keys() {
+        let keys: Vec<String> = (0..500).map(|i| format!("app/{i}/token")).collect();
+        let index = KeyIndex::build(keys.iter().map(String::as_str)).unwrap();
+        assert!(keys.iter().all(|k| index.might_contain(k)));
+
+        let false_positives = (0..10_000)
+            .filter(|i| index.might_contain(&format!("other/{i}")))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let index = KeyIndex::build(["a", "b"]).unwrap();
+        assert_eq!(index.bits(), MIN_BITS);
+        let parsed = KeyIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(parsed, index);
+        assert!(parsed.might_contain("a"));
+        assert!(!parsed.might_contain("c") || parsed.fill_ratio() > 0.0);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let bytes = KeyIndex::build(["a"]).unwrap().to_bytes();
+        assert!(KeyIndex::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(KeyIndex::from_bytes(b"KNCH").is_err());
+        let mut bad = bytes.clone();
+        bad[5] = 0;
+        assert!(KeyIndex::from_bytes(&bad).is_err());
+    }
+
+    #[test]
+    fn salt_differs_between_builds() {
+        let a = KeyIndex::build(["a"]).unwrap();
+        let b = KeyIndex::build(["a"]).unwrap();
+        assert_ne!(a.to_bytes(), b.to_bytes());
+    }
+}
@@ -0,0 +1,153 @@
+//! Leases: advisory checkouts of shared credentials.
+//!
+//! When several people share one store and one credential (an admin account, a test
+//! device login), a lease records who is using an entry and until when, inside the
+//! encrypted payload. Leases never block reading the entry; they make other lease
+//! attempts warn, or fail while an exclusive lease is held. Expired leases are dropped
+//! the next time leases change.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A lease of one entry; see [`crate::Keynest::lease`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    holder: String,
+    acquired: String,
+    expires: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    exclusive: bool,
+}
+
+impl Lease {
+    pub(crate) fn new(
+        holder: &str,
+        acquired: DateTime<Utc>,
+        expires: DateTime<Utc>,
+        exclusive: bool,
+    ) -> Self {
+        Self {
+            holder: holder.to_string(),
+            acquired: crate::store::format_timestamp(acquired),
+            expires: crate::store::format_timestamp(expires),
+            exclusive,
+        }
+    }
+
+    /// Returns who holds the lease, e.g. `alice@laptop`.
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Returns when the lease was taken or last renewed.
+    pub fn acquired(&self) -> Option<DateTime<Utc>> {
+        parse_timestamp(&self.acquired)
+    }
+
+    /// Returns when the lease ends unless it is renewed or released first.
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        parse_timestamp(&self.expires)
+    }
+
+    /// Returns `true` if the lease keeps others from leasing the entry.
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    /// Returns `true` if the lease has not expired at `now`. A lease with an unreadable
+    /// expiry counts as expired.
+    pub(crate) fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires().is_some_and(|expires| expires > now)
+    }
+}
+
+impl fmt::Display for Lease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} until {}", self.holder, self.expires)?;
+        if self.exclusive {
+            f.write_str(" (exclusive)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Drops the leases that expired at `now`, and the keys left without leases.
+pub(crate) fn retain_active(leases: &mut BTreeMap<String, Vec<Lease>>, now: DateTime<Utc>) {
+    for held in leases.values_mut() {
+        held.retain(|lease| lease.is_active(now));
+    }
+    leases.retain(|_, held| !held.is_empty());
+}
+
+fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    Some(DateTime::parse_from_rfc3339(s).ok()?.with_timezone(&Utc))
+}
+
+/// Error returned when an entry cannot be leased because of the leases of others:
+/// one of them is exclusive, or an exclusive lease was requested.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseConflict {
+    key: String,
+    leases: Vec<Lease>,
+}
+
+impl LeaseConflict {
+    pub(crate) fn new(key: &str, leases: Vec<Lease>) -> Self {
+        Self {
+            key: key.to_string(),
+            leases,
+        }
+    }
+
+    /// Returns the leased entry.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the active leases of the other holders.
+    pub fn leases(&self) -> &[Lease] {
+        &self.leases
+    }
+}
+
+impl fmt::Display for LeaseConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is leased by ", self.key)?;
+        for (i, lease) in self.leases.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{lease}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LeaseConflict {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leases_expire_and_display_their_holder() {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let end = start + chrono::TimeDelta::hours(1);
+        let lease = Lease::new("alice@laptop", start, end, true);
+        assert!(lease.is_active(start));
+        assert!(!lease.is_active(end));
+        assert_eq!(lease.acquired(), Some(start));
+        assert_eq!(
+            lease.to_string(),
+            "alice@laptop until 2023-11-14T23:13:20Z (exclusive)"
+        );
+
+        let conflict = LeaseConflict::new("db/admin", vec![lease]);
+        assert_eq!(
+            conflict.to_string(),
+            "'db/admin' is leased by alice@laptop until 2023-11-14T23:13:20Z (exclusive)"
+        );
+    }
+}
@@ -26,31 +26,121 @@
 //! assert_eq!(kn.get("api_key"), Some("secret123"));
 //! ```
 
+mod attachments;
+mod audit;
+pub mod autotype;
+mod breach;
+mod bundle;
+mod chain;
+mod clock;
+pub mod config;
 mod crypto;
+pub mod detect;
 mod error;
-mod format;
+mod export;
+pub mod format;
+pub mod generator;
+mod indexed;
+mod journal;
+pub mod key_index;
+mod lease;
+mod limiter;
+mod matcher;
+mod merge;
+mod metrics;
+mod migrations;
+mod otp;
+mod payload;
+pub mod plan;
+mod quota;
+mod receipts;
+pub mod repair;
+mod sealed;
+pub mod settings;
+mod ssh;
 mod storage;
 mod store;
+mod strength;
+pub mod template;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+mod totp;
+mod usage;
 
-pub use crate::crypto::{KdfParams, algorithm::Algorithm};
-use crate::format::{Header, KeystoreFile, parse, serialize};
-pub use crate::storage::Storage;
-use crate::store::SecretEntry;
+pub use crate::attachments::AttachmentReader;
+use crate::attachments::BlobStore;
+pub use crate::audit::{AuditAction, AuditEvent, AuditLogState};
+use crate::autotype::{Action, Token};
+pub use crate::breach::{BreachList, Breached};
+pub use crate::chain::{ChainStatus, ChainWitness};
+pub use crate::clock::{Clock, FixedClock, OffsetClock, SystemClock};
+pub use crate::crypto::random::{EntropySource, EntropyUse, OsEntropy};
+pub use crate::crypto::{
+    Argon2Params, CancelToken, Cancelled, KdfParams, Keyfile, ScryptParams, UnlockKey,
+    algorithm::Algorithm, derive_key_cancellable,
+};
+pub use crate::error::StoreError;
+pub use crate::export::{ExportFormat, Redaction};
+use crate::format::{
+    ChainLink, DEFAULT_KEYSLOT, FILE_HASH_LEN, Header, Keyslot, KeystoreFile, MAX_KEYSLOTS,
+    PayloadEncoding, Serialization, file_hash, parse, parse_data, serialize,
+};
+use crate::generator::Recipe;
+pub use crate::indexed::IndexedKeynest;
+use crate::journal::Journal;
+use crate::key_index::KeyIndex;
+pub use crate::lease::{Lease, LeaseConflict};
+pub use crate::limiter::{Limiter, RateLimited};
+pub use crate::matcher::{MatchMode, Matcher};
+pub use crate::merge::{Conflict, MergePolicy, MergeSummary, Side};
+pub use crate::metrics::{Metrics, RotationMetrics};
+pub use crate::otp::{OtpAuth, OtpKind};
+use crate::plan::{Plan, StepOutcome};
+pub use crate::quota::{QuotaEnforcement, Quotas};
+pub use crate::receipts::ReadReceipt;
+use crate::settings::{
+    AuditLog, AutotypeSequences, Backups, CompatCopy, DeterministicRecords, DuressSlot,
+    JournalEnabled, KeyIndexEnabled, Leases, PerEntryRecords, Pinned, PinnedEncoding, ReadOnly,
+    ReadReceipts, Templates, UsageStats, WriteFormat,
+};
+pub use crate::settings::{Setting, Settings};
+pub use crate::ssh::{CertKind, CertificateRequest, SshCa, SshCertificateInfo};
+pub use crate::storage::{Backup, FileData, Storage};
+pub use crate::store::{
+    AccessPolicy, EntryKind, ImportPolicy, ImportSummary, RenameSummary, validate_key,
+};
+use crate::store::{Attachment, SecretEntry, StoreDigest, walk_references};
+pub use crate::strength::{Finding, Strength, Weakness, estimate_strength};
+use crate::template::Template;
+pub use crate::totp::{Totp, TotpAlgorithm, TotpCode};
+pub use crate::usage::{EntryUsage, Usage};
 use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
 use directories::ProjectDirs;
 use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
 use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 use store::Store;
 use zeroize::{Zeroize, Zeroizing};
 
+use crate::payload::Opened;
+use crate::sealed::Sealed;
+
 /// A secure keystore for storing secrets locally.
 ///
 /// `Keynest` provides methods to initialize, open, and manage a local encrypted
 /// keystore. All secrets are encrypted at rest using XChaCha20-Poly1305 with a
 /// key derived from your password using Argon2id.
 ///
-/// The struct holds sensitive data (encryption key) which is zeroized on drop
-/// for secure memory handling.
+/// The struct holds sensitive data (encryption key and decrypted secrets) which is
+/// zeroized on drop for secure memory handling, or earlier with [`Keynest::lock`].
+///
+/// A keystore with per-entry records (see [`Keynest::set_per_entry_records`]) opens
+/// with its entries still encrypted: [`Keynest::get`] and the other reads of a single
+/// entry decrypt only that entry, and the rest stays encrypted until something needs
+/// every entry, e.g. a listing or a save.
 ///
 /// # Example
 ///
@@ -65,15 +155,47 @@ use zeroize::{Zeroize, Zeroizing};
 /// kn.save().unwrap();
 /// ```
 pub struct Keynest {
-    store: Store,
+    /// Decrypted store; see [`Keynest::store`].
+    store: OnceLock<Store>,
+    /// Records of a per-entry keystore not decrypted yet (see [`sealed`]).
+    sealed: Option<Box<Sealed>>,
     storage: Storage,
     key: [u8; 32],
     keystore_file: KeystoreFile,
+    entropy: Option<Arc<dyn EntropySource>>,
+    locked: bool,
+    autosave: bool,
+    /// Nesting depth of [`Keynest::mutate`] and [`Keynest::transaction`] calls.
+    batch: u32,
+    /// Name recorded in read receipts; see [`Keynest::set_reader`].
+    reader: Option<String>,
+    /// Keyfile the key depends on, kept for [`Keynest::unlock`] and [`Keynest::rekey`].
+    keyfile: Option<Keyfile>,
+    /// Keyslot the password opened, kept for [`Keynest::rekey`].
+    keyslot: Option<String>,
+    /// Journal that saves append to; see [`Keynest::set_journal`].
+    journal: Option<Journal>,
+    /// Digest of the entries as last logged, while the audit log is on; see
+    /// [`Keynest::set_audit_log`].
+    audited: Option<StoreDigest>,
+    /// Events waiting for the next append to the audit log.
+    audit_pending: Vec<AuditEvent>,
+    /// SHA-256 hash of the file as opened or last written, recorded by the chain link of
+    /// the next save.
+    file_hash: [u8; FILE_HASH_LEN],
+    /// Witness every save advances; see [`Keynest::set_chain_witness`].
+    chain_witness: Option<ChainWitness>,
+    /// Whether the store is the decoy of a duress password, which saves write to the
+    /// header instead of the records; see [`Keynest::set_duress`].
+    decoy: bool,
 }
 
 impl Drop for Keynest {
     fn drop(&mut self) {
         self.key.zeroize();
+        if let Some(store) = self.store.get_mut() {
+            store.wipe();
+        }
     }
 }
 
@@ -131,6 +253,21 @@ impl Keynest {
         password: Zeroizing<String>,
         storage: Storage,
         kdf: KdfParams,
+    ) -> Result<Self> {
+        Self::init_with_options(password, storage, InitOptions::new(kdf))
+    }
+
+    /// Creates a new keystore with custom storage location and the KDF parameters,
+    /// cipher, payload encoding and backup policy in `options`, e.g. taken from a
+    /// profile of the config file (see [`config::Profile::init_options`]).
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::init_with_storage_and_kdf`].
+    pub fn init_with_options(
+        password: Zeroizing<String>,
+        storage: Storage,
+        options: InitOptions,
     ) -> Result<Self> {
         if storage.exists() {
             bail!(
@@ -139,32 +276,77 @@ impl Keynest {
             );
         }
 
-        let store = Store::new();
+        let mut store = options
+            .clock
+            .clone()
+            .map_or_else(Store::new, Store::with_clock);
+        if options.backups > 0 {
+            store.settings_mut().set::<Backups>(&options.backups)?;
+        }
+        Self::create(password, storage, options, store)
+    }
+
+    /// Encrypts `store` under `password` and writes it as a new keystore to `storage`.
+    fn create(
+        password: Zeroizing<String>,
+        storage: Storage,
+        options: InitOptions,
+        store: Store,
+    ) -> Result<Self> {
+        let _entropy = crypto::random::scope(options.entropy.as_ref());
         let salt = crypto::generate_salt()?;
-        let key =
-            crypto::derive_key(&password, &salt, kdf).context("failed to derive encryption key")?;
+        let mut key = crypto::derive_key(&password, &salt, options.kdf)
+            .context("failed to derive encryption key")?;
 
         drop(password);
 
-        let plaintext = Zeroizing::new(serde_json::to_vec(&store)?);
-
-        let (header, ciphertext) = Header::encrypt_store(
-            kdf,
-            Algorithm::XChaCha20Poly1305,
+        if let Some(keyfile) = &options.keyfile {
+            key = keyfile.bind_key(&key);
+        }
+        let dpapi_blob = if options.dpapi {
+            Some(bind_new_key(&mut key)?)
+        } else {
+            None
+        };
+        let binding = payload::KeyBinding {
+            dpapi_blob,
+            keyfile: options.keyfile.is_some(),
+            keyslots: Vec::new(),
+            decoy: None,
+        };
+        let keystore_file = payload::encrypt(
+            &store,
+            options.kdf,
+            options.algorithm,
             salt.to_vec(),
+            options.encoding,
+            binding,
+            Some(ChainLink::new(1, [0; FILE_HASH_LEN])),
             &key,
-            &plaintext,
         )?;
-
-        let keystore_file = KeystoreFile::new(header, ciphertext);
         let file = serialize(&keystore_file)?;
         storage.save(&file)?;
+        let file_hash = file_hash(&file);
 
         Ok(Self {
-            store,
+            store: OnceLock::from(store),
+            sealed: None,
             storage,
             key,
             keystore_file,
+            entropy: options.entropy,
+            locked: false,
+            autosave: false,
+            batch: 0,
+            reader: None,
+            keyfile: options.keyfile,
+            keyslot: None,
+            journal: None,
+            audited: None,
+            audit_pending: Vec::new(),
+            file_hash,
+            chain_witness: None,
+            decoy: false,
         })
     }
 
@@ -190,6 +372,120 @@ impl Keynest {
     /// - The password is incorrect
     /// - The keystore is corrupted
     pub fn open_with_storage(password: Zeroizing<String>, storage: Storage) -> Result<Self> {
+        Self::open_inner(Unlock::Password(password, None, None), storage, None)
+    }
+
+    /// Opens an existing keystore that requires a keyfile (see [`InitOptions::with_keyfile`]).
+    ///
+    /// The keyfile is ignored if the keystore does not require one.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::open_with_storage`]; a wrong keyfile fails like a wrong password.
+    pub fn open_with_keyfile(
+        password: Zeroizing<String>,
+        keyfile: &Keyfile,
+        storage: Storage,
+    ) -> Result<Self> {
+        Self::open_inner(
+            Unlock::Password(password, Some(keyfile), None),
+            storage,
+            None,
+        )
+    }
+
+    /// Opens an existing keystore with a key obtained from [`Keynest::unlock_key`]
+    /// earlier, skipping the key derivation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no keystore exists at the given storage path, the key does
+    /// not decrypt it (e.g. after a rekey), or the keystore is corrupted.
+    pub fn open_with_key(key: &UnlockKey, storage: Storage) -> Result<Self> {
+        Self::open_inner(Unlock::Key(key), storage, None)
+    }
+
+    /// Opens an existing keystore from a custom storage location, deriving the key on a
+    /// worker thread that can be abandoned through `cancel`.
+    ///
+    /// Useful with heavy KDF parameters: a mistyped password can be aborted right away
+    /// instead of waiting for Argon2 to finish.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Cancelled`] error if `cancel` is triggered during key derivation,
+    /// otherwise the same errors as [`Keynest::open_with_storage`].
+    pub fn open_with_storage_cancellable(
+        password: Zeroizing<String>,
+        storage: Storage,
+        cancel: &CancelToken,
+    ) -> Result<Self> {
+        Self::open_inner(
+            Unlock::Password(password, None, Some(cancel)),
+            storage,
+            None,
+        )
+    }
+
+    /// Opens an existing keystore that requires a keyfile like
+    /// [`Keynest::open_with_keyfile`], deriving the key on a worker thread that can be
+    /// abandoned through `cancel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Cancelled`] error if `cancel` is triggered during key derivation,
+    /// otherwise the same errors as [`Keynest::open_with_keyfile`].
+    pub fn open_with_keyfile_cancellable(
+        password: Zeroizing<String>,
+        keyfile: &Keyfile,
+        storage: Storage,
+        cancel: &CancelToken,
+    ) -> Result<Self> {
+        Self::open_inner(
+            Unlock::Password(password, Some(keyfile), Some(cancel)),
+            storage,
+            None,
+        )
+    }
+
+    /// Opens the keystore at the default location, rate limited by `limiter`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::open_with_storage_and_limiter`].
+    pub fn open_with_limiter(password: Zeroizing<String>, limiter: &Limiter) -> Result<Self> {
+        let storage = default_storage()?;
+        Self::open_with_storage_and_limiter(password, storage, limiter)
+    }
+
+    /// Opens an existing keystore from a custom storage location, rate limited by
+    /// `limiter`, for services that unlock on behalf of remote callers.
+    ///
+    /// The limiter is checked before the key derivation runs. A wrong password (or any
+    /// other failure to decrypt the payload) counts as a failed attempt and a successful
+    /// open resets the count; a missing or unparseable file does not count.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RateLimited`] error while a cooldown is active, an error if the
+    /// limiter state cannot be read or written, otherwise the same errors as
+    /// [`Keynest::open_with_storage`].
+    pub fn open_with_storage_and_limiter(
+        password: Zeroizing<String>,
+        storage: Storage,
+        limiter: &Limiter,
+    ) -> Result<Self> {
+        Self::open_inner(
+            Unlock::Password(password, None, None),
+            storage,
+            Some(limiter),
+        )
+    }
+
+    fn open_inner(unlock: Unlock, storage: Storage, limiter: Option<&Limiter>) -> Result<Self> {
+        if let Some(limiter) = limiter {
+            limiter.check()?;
+        }
         if !storage.exists() {
             bail!(
                 "keystore does not exist: {}\nRun `keynest init` first.",
@@ -197,207 +493,3546 @@ impl Keynest {
             );
         }
 
-        let data = storage.load()?;
-        let keystore_file = parse(&data)?;
+        let data = Arc::new(storage.load_data()?);
+        let file_hash = file_hash(&data);
+        let mut keystore_file = parse_data(&data)?;
+        drop(data);
 
-        let key = crypto::derive_key(&password, keystore_file.salt(), *keystore_file.kdf())
-            .context("unable to derive encryption key")?;
-        drop(password);
+        let keyfile = unlock.keyfile(&keystore_file.header).cloned();
+        let (key, keyslot) = match unlock.key(&keystore_file.header) {
+            Err(e) if e.is::<NoMatchingKeyslot>() => {
+                if let Some(limiter) = limiter {
+                    limiter.record_failure()?;
+                }
+                return Err(e);
+            }
+            opened => opened?,
+        };
 
-        let plaintext = keystore_file.decrypt(&key)?;
-        let store = serde_json::from_slice(&plaintext)
-            .context("failed to deserialize keystore; possibly wrong password or corrupted data")?;
+        let decoy = open_decoy(&keystore_file.header, &key);
+        let is_decoy = decoy.is_some();
+        let opened = match decoy {
+            Some(decoy) => Ok(Opened::Store(decoy)),
+            None => with_key_check(&keystore_file.header, &key, || {
+                payload::open(&keystore_file, &key)
+            }),
+        };
+        keystore_file.release_mapping();
+        if let Some(limiter) = limiter {
+            match &opened {
+                Ok(_) => limiter.record_success()?,
+                Err(_) => limiter.record_failure()?,
+            }
+        }
+        let (mut store, sealed) = match opened? {
+            // The journal is replayed into the whole store.
+            Opened::Sealed(sealed) if storage.journal_path().exists() => {
+                (OnceLock::from(sealed.unseal(&key)?), None)
+            }
+            Opened::Sealed(sealed) => (OnceLock::new(), Some(sealed)),
+            Opened::Store(store) => (OnceLock::from(store), None),
+        };
+        // The journal extends the records, not the decoy.
+        let journal = match store.get_mut() {
+            Some(store) if !is_decoy => Journal::replay(&storage, &keystore_file, &key, store)?,
+            _ => None,
+        };
 
-        Ok(Self {
+        let mut kn = Self {
             store,
+            sealed,
             storage,
             key,
             keystore_file,
-        })
+            entropy: None,
+            locked: false,
+            autosave: false,
+            batch: 0,
+            reader: None,
+            keyfile,
+            keyslot,
+            journal,
+            audited: None,
+            audit_pending: Vec::new(),
+            file_hash,
+            chain_witness: None,
+            decoy: is_decoy,
+        };
+        kn.track_journal()?;
+        kn.track_audit()?;
+        kn.update_usage(Usage::record_open)?;
+        Ok(kn)
     }
 
-    /// Stores a secret in the keystore.
+    /// Returns the decrypted store, decrypting every record first if the keystore was
+    /// opened sealed (see [`sealed`]).
     ///
     /// # Errors
     ///
-    /// Returns an error if a secret with the given key already exists.
-    /// Use `update` to change an existing secret.
-    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
-        self.store.set(key, value)?;
-        Ok(())
+    /// Returns an error if a sealed record cannot be decrypted or does not match the
+    /// index.
+    fn store(&self) -> Result<&Store> {
+        if let Some(store) = self.store.get() {
+            return Ok(store);
+        }
+        let store = self.sealed().unseal(&self.key)?;
+        Ok(self.store.get_or_init(|| store))
     }
 
-    /// Retrieves a secret by key.
+    /// Returns the sealed records of a keystore whose store is not decrypted yet.
+    fn sealed(&self) -> &Sealed {
+        self.sealed
+            .as_deref()
+            .expect("the store is sealed until decrypted")
+    }
+
+    /// Returns the decrypted store for a change, decrypting every record first if the
+    /// keystore was opened sealed.
     ///
-    /// Returns `None` if the key does not exist.
-    pub fn get(&self, key: &str) -> Option<&str> {
-        self.store.get(key)
+    /// # Errors
+    ///
+    /// Returns an error if a sealed record cannot be decrypted or does not match the
+    /// index.
+    fn store_mut(&mut self) -> Result<&mut Store> {
+        self.unseal()?;
+        Ok(self.store.get_mut().expect("the store was decrypted above"))
     }
 
-    /// Updates an existing secret's value.
+    /// Decrypts every record of a keystore opened sealed.
     ///
     /// # Errors
     ///
-    /// Returns an error if the key does not exist.
-    /// Use `set` to create a new secret.
-    pub fn update(&mut self, key: &str, value: &str) -> Result<()> {
-        self.store.update(key, value)?;
+    /// Returns an error if a record cannot be decrypted or does not match the index.
+    fn unseal(&mut self) -> Result<()> {
+        if let Some(sealed) = &self.sealed {
+            if self.store.get().is_none() {
+                self.store = OnceLock::from(sealed.unseal(&self.key)?);
+            }
+            self.sealed = None;
+        }
         Ok(())
     }
 
-    /// Removes a secret from the keystore.
+    /// Returns the entry `key`, decrypting only its record while the keystore is sealed.
     ///
     /// # Errors
     ///
-    /// Returns an error if the key does not exist.
-    pub fn remove(&mut self, key: &str) -> Result<()> {
-        self.store.remove(key)?;
-        Ok(())
+    /// Returns an error if its record cannot be decrypted or does not hold the entry.
+    fn entry(&self, key: &str) -> Result<Option<&SecretEntry>> {
+        match self.store.get() {
+            Some(store) => Ok(store.entry(key)),
+            None => self.sealed().entry(&self.key, key),
+        }
     }
 
-    /// Lists all secret keys.
+    /// Stores a secret in the keystore.
     ///
-    /// Returns a vector of references to the key strings.
-    pub fn list(&self) -> Vec<&String> {
-        self.store.keys().collect()
+    /// A value of the form `ref:<key>` stores a reference to another entry instead of
+    /// a literal value (see [`Keynest::resolve`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a secret with the given key already exists, or if the value
+    /// is a reference that would create a cycle.
+    /// Use `update` to change an existing secret.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.set(key, value)?))
     }
 
-    /// Lists all secrets with their metadata.
+    /// Stores a note: free-form (markdown) text such as recovery instructions, kept
+    /// like any other entry but shown as formatted text rather than as a credential.
     ///
-    /// Returns a vector of references to `SecretEntry` containing
-    /// key, value, and update timestamp.
-    pub fn list_all(&self) -> Vec<&SecretEntry> {
-        self.store.entries().collect()
+    /// # Errors
+    ///
+    /// Returns an error if an entry with the given key already exists.
+    /// Use `update` to change an existing note.
+    pub fn set_note(&mut self, key: &str, text: &str) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.set_note(key, text)?))
     }
 
-    /// Persists the keystore to storage.
+    /// Stores a TOTP entry holding the `otpauth://totp/` URI of `otp`, e.g. built from a
+    /// seed with [`OtpAuth::from_totp`]. Codes are generated with [`Keynest::totp`].
     ///
-    /// Must be called after making changes (set, update, remove)
-    /// to save them to disk.
+    /// # Errors
+    ///
+    /// Returns an error if `otp` is a HOTP URI or an entry with the given key exists.
+    pub fn set_totp(&mut self, key: &str, otp: &OtpAuth) -> Result<()> {
+        if otp.totp().is_none() {
+            bail!("cannot store '{key}' as a TOTP entry: the URI is a HOTP URI");
+        }
+        self.mutate(|kn| Ok(kn.store_mut()?.set_totp(key, otp.uri())?))
+    }
+
+    /// Stores a password recipe instead of the password itself; the password is computed
+    /// from a master secret with [`Recipe::derive`].
     ///
     /// # Errors
     ///
-    /// Returns an error if writing to storage fails.
-    pub fn save(&mut self) -> Result<()> {
-        let plaintext = Zeroizing::new(serde_json::to_vec(&self.store)?);
+    /// Returns an error if an entry with the given key already exists.
+    pub fn set_recipe(&mut self, key: &str, recipe: &Recipe) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.set_recipe(key, &recipe.to_uri())?))
+    }
 
-        let (header, ciphertext) = Header::encrypt_store(
-            *self.keystore_file.kdf(),
-            self.keystore_file.algorithm(),
-            self.keystore_file.salt().to_vec(),
-            &self.key,
-            &plaintext,
-        )?;
+    /// Stores a counter entry starting at `start`; [`Keynest::next_counter`] hands out
+    /// the following values, e.g. HOTP counters, certificate serial numbers or one-time
+    /// IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an entry with the given key already exists.
+    pub fn set_counter(&mut self, key: &str, start: u64) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.set_counter(key, start)?))
+    }
 
-        self.keystore_file = KeystoreFile::new(header, ciphertext);
-        let file = serialize(&self.keystore_file)?;
-        self.storage.save(&file)?;
-        Ok(())
+    /// Returns the current value of the counter `key`, or `None` if the key does not
+    /// exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `key` is not a counter entry.
+    pub fn counter(&self, key: &str) -> Result<Option<u64>> {
+        match (self.kind(key)?, self.get(key)?) {
+            (None, _) | (_, None) => Ok(None),
+            (Some(EntryKind::Counter), Some(value)) => counter_value(key, value).map(Some),
+            _ => bail!("'{key}' is not a counter entry"),
+        }
     }
 
-    /// Returns information about the keystore.
+    /// Increments the counter `key`, saves the keystore and returns the new value.
     ///
-    /// Includes file path, size, creation date, secret count,
-    /// and KDF/encryption parameters.
+    /// The value is returned only once it is saved, so a value is never handed out twice,
+    /// even if the process dies right after; a failed save can skip a value instead.
     ///
     /// # Errors
     ///
-    /// Returns an error if the storage metadata cannot be read.
-    pub fn info(&self) -> Result<StoreInfo> {
-        let metadata = std::fs::metadata(self.storage.path())?;
-        Ok(StoreInfo {
-            path: self.storage.path().to_path_buf(),
-            file_size: metadata.len(),
-            creation_date: self.store.creation_date().to_string(),
-            secrets_count: self.store.len(),
-            kdf: *self.keystore_file.kdf(),
-            algorithm: self.keystore_file.algorithm().name(),
-            nonce_len: self.keystore_file.nonce().len(),
-            version: self.keystore_file.version(),
-        })
+    /// Returns an error if `key` does not exist or is not a counter, the counter is at
+    /// `u64::MAX`, or the keystore cannot be saved (e.g. inside a transaction).
+    pub fn next_counter(&mut self, key: &str) -> Result<u64> {
+        self.ensure_writable()?;
+        let current = self
+            .counter(key)?
+            .ok_or_else(|| error::StoreError::KeyNotFound(key.to_string()))?;
+        let Some(next) = current.checked_add(1) else {
+            bail!("counter '{key}' is at its maximum value");
+        };
+        self.store_mut()?.update(key, &next.to_string())?;
+        self.save()?;
+        Ok(next)
     }
 
-    /// Reads keystore metadata from the unencrypted file header, without decrypting.
-    ///
-    /// Unlike [`Keynest::info`], this does not require the master password: it only reads
-    /// the header (format version, algorithm, nonce length, KDF parameters) plus the file
-    /// path/size. Creation date and secret count are encrypted and therefore not included.
+    /// Returns the TOTP code of `key` at the current time of the keystore's clock, or
+    /// `None` if the key does not exist. Works for every entry whose (resolved) value is
+    /// an `otpauth://totp/` URI, not only for those stored with [`Keynest::set_totp`].
     ///
     /// # Errors
     ///
-    /// Returns an error if the file cannot be read or its header cannot be parsed.
-    pub fn inspect_header(storage: &Storage) -> Result<HeaderInfo> {
-        let data = storage.load()?;
-        let keystore_file = parse(&data)?;
-        let metadata = std::fs::metadata(storage.path())?;
+    /// Returns an error if the value is not a valid TOTP URI.
+    pub fn totp(&self, key: &str) -> Result<Option<TotpCode>> {
+        let Some(value) = self.resolve(key)? else {
+            return Ok(None);
+        };
+        let code = totp_code(key, value, self.clock().now())?;
+        Ok(Some(code))
+    }
 
-        Ok(HeaderInfo {
-            path: storage.path().to_path_buf(),
-            file_size: metadata.len(),
-            version: keystore_file.version(),
-            algorithm: keystore_file.algorithm().name(),
-            nonce_len: keystore_file.nonce().len(),
-            kdf: *keystore_file.kdf(),
-        })
+    /// Tags the entry `key` with `tag`, e.g. a project or environment. Returns `false`
+    /// if it already had the tag. Persisted on the next [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry does not exist, or if `tag` is empty or contains
+    /// whitespace or commas.
+    pub fn add_tag(&mut self, key: &str, tag: &str) -> Result<bool> {
+        self.mutate(|kn| Ok(kn.store_mut()?.add_tag(key, tag)?))
     }
 
-    /// Changes the password and/or KDF parameters.
+    /// Removes `tag` from the entry `key`. Returns `false` if it did not have the tag.
     ///
-    /// Re-encrypts the keystore with a new key derived from the new password
-    /// and optional new KDF parameters. The existing secrets are preserved.
+    /// # Errors
     ///
-    /// # Arguments
+    /// Returns an error if the entry does not exist.
+    pub fn remove_tag(&mut self, key: &str, tag: &str) -> Result<bool> {
+        self.mutate(|kn| Ok(kn.store_mut()?.remove_tag(key, tag)?))
+    }
+
+    /// Returns the tags of `key`, sorted, or `None` if it does not exist.
     ///
-    /// * `new_password` - The new password to derive the encryption key from
-    /// * `new_kdf` - The new KDF parameters (can be different from current)
+    /// # Errors
+    ///
+    /// Returns an error if the record holding the entry cannot be decrypted (see
+    /// [`Keynest::set_per_entry_records`]).
+    pub fn tags(&self, key: &str) -> Result<Option<&[String]>> {
+        Ok(self.entry(key)?.map(|e| e.tags()))
+    }
+
+    /// Sets field `name` of the entry `key`, e.g. the `user` or `url` of a login,
+    /// replacing an existing field of that name. Persisted on the next
+    /// [`Keynest::save`].
     ///
     /// # Errors
     ///
-    /// Returns an error if:
-    /// - Key derivation fails
-    /// - Encryption fails
-    /// - Writing to storage fails
-    pub fn rekey(&mut self, new_password: Zeroizing<String>, new_kdf: KdfParams) -> Result<()> {
-        let current_algorithm = self.keystore_file.algorithm();
+    /// Returns an error if the entry does not exist, the name is invalid, or the value
+    /// exceeds the value quota.
+    pub fn set_field(&mut self, key: &str, name: &str, value: &str) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.set_field(key, name, value)?))
+    }
 
-        self.rekey_with_algorithm(new_password, new_kdf, current_algorithm)
+    /// Removes field `name` from the entry `key`. Returns `false` if it had no such
+    /// field.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry does not exist.
+    pub fn remove_field(&mut self, key: &str, name: &str) -> Result<bool> {
+        self.mutate(|kn| Ok(kn.store_mut()?.remove_field(key, name)?))
     }
 
-    fn rekey_with_algorithm(
-        &mut self,
-        new_password: Zeroizing<String>,
-        new_kdf: KdfParams,
-        new_algorithm: Algorithm,
-    ) -> Result<()> {
-        let new_salt = crypto::generate_salt()?;
+    /// Returns the fields of `key` by name, or `None` if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record holding the entry cannot be decrypted (see
+    /// [`Keynest::set_per_entry_records`]).
+    pub fn fields(&self, key: &str) -> Result<Option<&BTreeMap<String, String>>> {
+        Ok(self.entry(key)?.map(|e| e.fields()))
+    }
 
-        let new_key = crypto::derive_key(&new_password, &new_salt, new_kdf)
-            .context("failed to derive new encryption key")?;
+    /// Sets when the secret `key` expires, or with `None` clears its expiry. Expired
+    /// secrets are still readable; see [`Keynest::expired`]. Persisted on the next
+    /// [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry does not exist.
+    pub fn set_expiry(&mut self, key: &str, expires: Option<DateTime<Utc>>) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.set_expiry(key, expires)?))
+    }
 
-        drop(new_password);
+    /// Returns when the secret `key` expires, or `None` if it has no expiry or does not
+    /// exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record holding the entry cannot be decrypted (see
+    /// [`Keynest::set_per_entry_records`]).
+    pub fn expires(&self, key: &str) -> Result<Option<DateTime<Utc>>> {
+        Ok(self.entry(key)?.and_then(SecretEntry::expires))
+    }
 
-        let plaintext = Zeroizing::new(serde_json::to_vec(&self.store)?);
+    /// Replaces the access policy of `key`. Persisted on the next [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry does not exist.
+    pub fn set_policy(&mut self, key: &str, policy: AccessPolicy) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.set_policy(key, policy)?))
+    }
 
-        let (header, ciphertext) = Header::encrypt_store(
-            new_kdf,
-            new_algorithm,
-            new_salt.to_vec(),
-            &new_key,
-            &plaintext,
-        )?;
+    /// Returns the access policy set on `key`, or `None` if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record holding the entry cannot be decrypted (see
+    /// [`Keynest::set_per_entry_records`]).
+    pub fn policy(&self, key: &str) -> Result<Option<AccessPolicy>> {
+        Ok(self.entry(key)?.map(|e| e.policy()))
+    }
 
-        self.keystore_file = KeystoreFile::new(header, ciphertext);
-        let file = serialize(&self.keystore_file)?;
-        self.storage.save(&file)?;
+    /// Returns the access policy that applies when reading `key`: its own merged with
+    /// that of every entry its `ref:` chain passes through. Returns `None` if `key`
+    /// does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reference chain contains a cycle or points at a missing
+    /// key, or a record on it cannot be decrypted.
+    pub fn effective_policy(&self, key: &str) -> Result<Option<AccessPolicy>> {
+        let chain = self.references(key)?;
+        if chain.is_empty() {
+            return Ok(None);
+        }
+        let mut policy = AccessPolicy::default();
+        for k in &chain {
+            if let Some(entry) = self.entry(k)? {
+                policy = policy.merge(entry.policy());
+            }
+        }
+        Ok(Some(policy))
+    }
 
-        self.key.zeroize();
-        self.key = new_key;
+    /// Returns the entries whose expiry has passed, sorted by key, e.g. to find the
+    /// secrets that are due for rotation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn expired(&self) -> Result<Vec<&SecretEntry>> {
+        let now = self.clock().now();
+        Ok(self
+            .store()?
+            .entries()
+            .filter(|e| e.is_expired_at(now))
+            .collect())
+    }
 
-        Ok(())
+    /// Returns the secrets whose value last changed more than `max_age` ago (see
+    /// [`SecretEntry::rotated`]), sorted by key, e.g. to enforce a rotation policy.
+    /// Notes, TOTP seeds, recipes, counters and references are skipped; entries with an
+    /// unreadable timestamp count as stale.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn stale(&self, max_age: chrono::TimeDelta) -> Result<Vec<&SecretEntry>> {
+        let now = self.clock().now();
+        Ok(strength::passwords(self.store()?.entries())
+            .filter(|e| e.rotated_at().is_none_or(|rotated| now - rotated > max_age))
+            .collect())
     }
-}
 
-/// Returns the default storage location for the keystore.
+    /// Scores the value of every secret for how hard it is to guess and finds values
+    /// shared by several secrets, sorted by key; see [`estimate_strength`]. Notes, TOTP
+    /// seeds, recipes, counters and references are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Locked`] error while the keystore is locked.
+    pub fn audit(&self) -> Result<Vec<Finding>> {
+        self.ensure_unlocked()?;
+        Ok(strength::audit(self.store()?.entries()))
+    }
+
+    /// Looks up the value of every secret in `list` and returns the secrets found, sorted
+    /// by key. Notes, TOTP seeds, recipes, counters and references are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Locked`] error while the keystore is locked, or an error if the list
+    /// cannot be read.
+    pub fn audit_breach(&self, list: &BreachList) -> Result<Vec<Breached>> {
+        self.ensure_unlocked()?;
+        let mut breached = Vec::new();
+        for entry in strength::passwords(self.store()?.entries()) {
+            if let Some(count) = list.lookup(entry.value())? {
+                breached.push(Breached::new(entry.key(), count));
+            }
+        }
+        Ok(breached)
+    }
+
+    /// Returns the clock the keystore reads the time from.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        match self.store.get() {
+            Some(store) => store.clock(),
+            None => self.sealed().clock(),
+        }
+    }
+
+    /// Makes the keystore read the time from `clock` instead of the system clock: the
+    /// `updated` timestamps of changed entries, usage timestamps and
+    /// [`Keynest::expired`] all use it, e.g. to pin the time in tests or to simulate a
+    /// skewed clock with [`OffsetClock`].
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        match self.store.get_mut() {
+            Some(store) => store.set_clock(clock),
+            None => self
+                .sealed
+                .as_deref_mut()
+                .expect("the store is sealed until decrypted")
+                .set_clock(clock),
+        }
+    }
+
+    /// Makes the keystore draw its salts, nonces and keys from `source` instead of the
+    /// OS generator, e.g. to make tests reproducible or to audit every request.
+    ///
+    /// The source applies while this keystore saves, rekeys, converts or attaches; code
+    /// that uses [`SshCa`] or [`generator`] directly keeps the OS generator.
+    pub fn set_entropy_source(&mut self, source: Arc<dyn EntropySource>) {
+        self.entropy = Some(source);
+    }
+
+    fn now_timestamp(&self) -> String {
+        store::format_timestamp(self.clock().now())
+    }
+
+    /// Returns the keys of all entries tagged with `tag`, sorted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn find_by_tag(&self, tag: &str) -> Result<Vec<&String>> {
+        Ok(self.store()?.keys_with_tag(tag).collect())
+    }
+
+    /// Returns the keys of the entries pinned as favorites.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn pinned(&self) -> Result<BTreeSet<String>> {
+        Ok(self.setting::<Pinned>()?.unwrap_or_default())
+    }
+
+    /// Pins entry `key` as a favorite, so listings show it first. Returns `false` if it
+    /// was already pinned. Persisted on the next save; removing the entry unpins it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry does not exist.
+    pub fn pin(&mut self, key: &str) -> Result<bool> {
+        if self.store()?.get(key).is_none() {
+            return Err(error::StoreError::KeyNotFound(key.to_string()).into());
+        }
+        let mut pinned = self.pinned()?;
+        if !pinned.insert(key.to_string()) {
+            return Ok(false);
+        }
+        self.set_setting::<Pinned>(&pinned)?;
+        Ok(true)
+    }
+
+    /// Unpins entry `key`. Returns `false` if it was not pinned. Persisted on the next
+    /// save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn unpin(&mut self, key: &str) -> Result<bool> {
+        let mut pinned = self.pinned()?;
+        if !pinned.remove(key) {
+            return Ok(false);
+        }
+        if pinned.is_empty() {
+            self.mutate(|kn| {
+                kn.remove_setting::<Pinned>()?;
+                Ok(())
+            })?;
+        } else {
+            self.set_setting::<Pinned>(&pinned)?;
+        }
+        Ok(true)
+    }
+
+    /// Returns the active leases of all entries, by key, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn leases(&self) -> Result<BTreeMap<String, Vec<Lease>>> {
+        let mut leases = self.setting::<Leases>()?.unwrap_or_default();
+        lease::retain_active(&mut leases, self.clock().now());
+        Ok(leases)
+    }
+
+    /// Leases entry `key` to `holder` for `ttl`, renewing the lease `holder` already has.
+    /// Returns the active leases of other holders, which callers should warn about.
+    /// Leases are advisory: they do not keep anyone from reading the entry. Persisted on
+    /// the next save; renaming the entry moves its leases and removing it drops them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry does not exist or `ttl` is not positive, and a
+    /// [`LeaseConflict`] if another holder's lease is exclusive, or if `exclusive` is
+    /// requested while others hold leases.
+    pub fn lease(
+        &mut self,
+        key: &str,
+        holder: &str,
+        ttl: chrono::TimeDelta,
+        exclusive: bool,
+    ) -> Result<Vec<Lease>> {
+        if self.store()?.get(key).is_none() {
+            return Err(error::StoreError::KeyNotFound(key.to_string()).into());
+        }
+        if ttl <= chrono::TimeDelta::zero() {
+            bail!("the lease duration must be positive");
+        }
+        let now = self.clock().now();
+        let expires = now
+            .checked_add_signed(ttl)
+            .context("the lease duration is too long")?;
+
+        let mut leases = self.leases()?;
+        let held = leases.entry(key.to_string()).or_default();
+        held.retain(|lease| lease.holder() != holder);
+        if held.iter().any(Lease::is_exclusive) || (exclusive && !held.is_empty()) {
+            return Err(LeaseConflict::new(key, held.clone()).into());
+        }
+        let others = held.clone();
+        held.push(Lease::new(holder, now, expires, exclusive));
+        self.set_setting::<Leases>(&leases)?;
+        Ok(others)
+    }
+
+    /// Releases the lease of `holder` on `key`, or with `None` the leases of every holder.
+    /// Returns the number of active leases released. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn release_lease(&mut self, key: &str, holder: Option<&str>) -> Result<usize> {
+        let mut leases = self.leases()?;
+        let Some(held) = leases.get_mut(key) else {
+            return Ok(0);
+        };
+        let before = held.len();
+        held.retain(|lease| holder.is_some_and(|holder| lease.holder() != holder));
+        let released = before - held.len();
+        if released == 0 {
+            return Ok(0);
+        }
+        if held.is_empty() {
+            leases.remove(key);
+        }
+        if leases.is_empty() {
+            self.mutate(|kn| {
+                kn.remove_setting::<Leases>()?;
+                Ok(())
+            })?;
+        } else {
+            self.set_setting::<Leases>(&leases)?;
+        }
+        Ok(released)
+    }
+
+    /// Returns the autotype sequences configured per login, by login.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn autotype_sequences(&self) -> Result<BTreeMap<String, String>> {
+        Ok(self.setting::<AutotypeSequences>()?.unwrap_or_default())
+    }
+
+    /// Sets the autotype sequence of `login` (a namespace such as `bank`), or with
+    /// `None` reverts it to [`autotype::DEFAULT_SEQUENCE`]. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sequence` is not a valid sequence (see [`autotype`]).
+    pub fn set_autotype_sequence(&mut self, login: &str, sequence: Option<&str>) -> Result<()> {
+        let mut sequences = self.autotype_sequences()?;
+        match sequence {
+            Some(sequence) => {
+                autotype::parse(sequence)?;
+                sequences.insert(login.to_string(), sequence.to_string());
+            }
+            None => {
+                sequences.remove(login);
+            }
+        }
+        self.mutate(|kn| {
+            if sequences.is_empty() {
+                kn.remove_setting::<AutotypeSequences>()?;
+                Ok(())
+            } else {
+                kn.set_setting::<AutotypeSequences>(&sequences)
+            }
+        })
+    }
+
+    /// Returns the entry templates stored in the keystore, by name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn templates(&self) -> Result<BTreeMap<String, Template>> {
+        Ok(self.setting::<Templates>()?.unwrap_or_default())
+    }
+
+    /// Stores `template` in the keystore under `name`, replacing a template of that
+    /// name, or with `None` removes it. Returns `false` when removing a template that
+    /// did not exist. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is empty or the template is invalid (see
+    /// [`Template::validate`]).
+    pub fn set_template(&mut self, name: &str, template: Option<&Template>) -> Result<bool> {
+        let mut templates = self.templates()?;
+        let changed = match template {
+            Some(template) => {
+                if name.trim().is_empty() {
+                    bail!("template name cannot be empty");
+                }
+                template.validate()?;
+                templates.insert(name.to_string(), template.clone());
+                true
+            }
+            None => templates.remove(name).is_some(),
+        };
+        self.mutate(|kn| {
+            if templates.is_empty() {
+                kn.remove_setting::<Templates>()?;
+                Ok(())
+            } else {
+                kn.set_setting::<Templates>(&templates)
+            }
+        })?;
+        Ok(changed)
+    }
+
+    /// Applies a provisioning plan: creates the entries of its steps that do not exist
+    /// yet, each after the entries it uses (see [`Plan::order`]), in one transaction.
+    /// Returns the key and outcome of each step, in the order they ran.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a step uses an entry that neither exists nor is created by
+    /// the plan, or if a step or the save fails; the store is then left unchanged.
+    pub fn apply_plan(&mut self, plan: &Plan) -> Result<Vec<(String, StepOutcome)>> {
+        let order = plan.order()?;
+        for step in &order {
+            for dependency in step.dependencies() {
+                if self.kind(dependency)?.is_none()
+                    && !plan.steps().iter().any(|s| s.key() == dependency)
+                {
+                    bail!(
+                        "step '{}' uses '{dependency}', which neither exists nor is created by the plan",
+                        step.key()
+                    );
+                }
+            }
+        }
+        let now = self.clock().now().timestamp();
+        self.transaction(|kn| {
+            let mut outcomes = Vec::with_capacity(order.len());
+            for step in order {
+                let key = step.key();
+                if kn.kind(key)?.is_some() {
+                    outcomes.push((key.to_string(), StepOutcome::Kept));
+                    continue;
+                }
+                let value_of = |dependency: &str| {
+                    Ok(kn
+                        .resolve(dependency)?
+                        .map(|value| Zeroizing::new(value.to_string())))
+                };
+                let produced = step
+                    .produce(value_of, now)
+                    .with_context(|| format!("step '{key}' failed"))?;
+                kn.set(key, &produced.value)?;
+                for tag in step.tags() {
+                    kn.add_tag(key, tag)?;
+                }
+                for (name, value) in step.fields() {
+                    kn.set_field(key, name, value)?;
+                }
+                for (name, value) in &produced.fields {
+                    kn.set_field(key, name, value)?;
+                }
+                outcomes.push((key.to_string(), StepOutcome::Created));
+            }
+            Ok(outcomes)
+        })
+    }
+
+    /// Resolves the autotype sequence of `login` into the keystrokes to type.
+    ///
+    /// Uses `sequence` if given, else the sequence configured for `login`, else
+    /// [`autotype::DEFAULT_SEQUENCE`]. A field placeholder `{NAME}` takes field `name`
+    /// of the entry `login` (`{USERNAME}` also its `user` field), else the value of
+    /// `<login>/name`; `{PASSWORD}` falls back to `login` itself if that is an entry.
+    /// References are followed. Returns the actions and the keys whose values they
+    /// contain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence is invalid or a field does not exist.
+    pub fn autotype(
+        &self,
+        login: &str,
+        sequence: Option<&str>,
+    ) -> Result<(Vec<Action>, Vec<String>)> {
+        let configured = self.autotype_sequences()?.remove(login);
+        let sequence = sequence
+            .or(configured.as_deref())
+            .unwrap_or(autotype::DEFAULT_SEQUENCE);
+
+        let mut actions = Vec::new();
+        let mut keys = Vec::new();
+        for token in autotype::parse(sequence)? {
+            actions.push(match token {
+                Token::Text(text) => Action::Text(Zeroizing::new(text)),
+                Token::Key(key) => Action::Key(key),
+                Token::Delay(delay) => Action::Delay(delay),
+                Token::Field(name) => {
+                    let field = self.entry(login)?.and_then(|entry| match name.as_str() {
+                        "username" => entry.field("username").or_else(|| entry.field("user")),
+                        _ => entry.field(&name),
+                    });
+                    if let Some(value) = field {
+                        if !keys.iter().any(|k| k == login) {
+                            keys.push(login.to_string());
+                        }
+                        Action::Text(Zeroizing::new(value.to_string()))
+                    } else {
+                        let mut key = format!("{login}/{name}");
+                        if self.get(&key)?.is_none()
+                            && name == "password"
+                            && self.get(login)?.is_some()
+                        {
+                            key = login.to_string();
+                        }
+                        let value = self.resolve(&key)?.ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "login '{login}' has no field '{name}' (no entry '{key}')"
+                            )
+                        })?;
+                        keys.push(key);
+                        Action::Text(Zeroizing::new(value.to_string()))
+                    }
+                }
+            });
+        }
+        Ok((actions, keys))
+    }
+
+    /// Returns whether `key` holds a secret, a note or a TOTP seed, or `None` if it does
+    /// not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record holding the entry cannot be decrypted (see
+    /// [`Keynest::set_per_entry_records`]).
+    pub fn kind(&self, key: &str) -> Result<Option<EntryKind>> {
+        Ok(self.entry(key)?.map(|e| e.kind()))
+    }
+
+    /// Retrieves a secret by key.
+    ///
+    /// Returns the stored value as-is; `ref:<key>` references are not followed
+    /// (use [`Keynest::resolve`] for that). Returns `None` if the key does not exist.
+    ///
+    /// A keystore opened with its entries encrypted (see
+    /// [`Keynest::set_per_entry_records`]) decrypts only the record of `key`.
+    /// # Errors
+    ///
+    /// Returns an error if the record holding the entry cannot be decrypted (see
+    /// [`Keynest::set_per_entry_records`]).
+    pub fn get(&self, key: &str) -> Result<Option<&str>> {
+        Ok(self.entry(key)?.map(|e| e.value()))
+    }
+
+    /// Retrieves a secret by key, transparently following `ref:<key>` references.
+    ///
+    /// A value of the form `ref:prod/db/password` is resolved to the value stored under
+    /// `prod/db/password` (recursively), so shared values only need to be stored once.
+    /// Returns `None` if the key does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reference chain contains a cycle or points at a missing
+    /// key, or a record on it cannot be decrypted.
+    pub fn resolve(&self, key: &str) -> Result<Option<&str>> {
+        match self.references(key)?.last() {
+            Some(last) => self.get(last),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the chain of keys visited when resolving `key`.
+    ///
+    /// The chain starts with `key` and ends with the entry holding the actual value;
+    /// it is empty if `key` does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reference chain contains a cycle or points at a missing
+    /// key, or a record on it cannot be decrypted.
+    pub fn references(&self, key: &str) -> Result<Vec<String>> {
+        walk_references(key, |k| {
+            Ok(self
+                .get(k)?
+                .map(|v| store::reference_target(v).map(str::to_string)))
+        })
+    }
+
+    /// Returns the keys whose value directly references `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn dependents(&self, key: &str) -> Result<Vec<&str>> {
+        Ok(self.store()?.dependents(key).collect())
+    }
+
+    /// Updates an existing secret's value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key does not exist.
+    /// Use `set` to create a new secret.
+    pub fn update(&mut self, key: &str, value: &str) -> Result<()> {
+        match self.kind(key)? {
+            Some(EntryKind::Totp) => {
+                totp_code(key, value, self.clock().now())?;
+            }
+            Some(EntryKind::Recipe) => {
+                Recipe::parse(value).with_context(|| format!("invalid recipe '{key}'"))?;
+            }
+            Some(EntryKind::Counter) => {
+                let current = self.counter(key)?.unwrap_or_default();
+                if counter_value(key, value)? < current {
+                    bail!("counter '{key}' cannot go backwards (it is at {current})");
+                }
+            }
+            _ => {}
+        }
+        self.mutate(|kn| Ok(kn.store_mut()?.update(key, value)?))
+    }
+
+    /// Stores many secrets at once, e.g. from an imported file: either all of them are
+    /// applied or, if one is rejected, none. Existing keys are skipped or overwritten
+    /// according to `policy`. Call [`Keynest::save`] once afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error [`Keynest::set`] or [`Keynest::update`] would return
+    /// (reserved key, reference cycle, quota); the keystore is then unchanged.
+    pub fn import_entries<K: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        entries: impl IntoIterator<Item = (K, V)>,
+        policy: ImportPolicy,
+    ) -> Result<ImportSummary> {
+        self.mutate(|kn| Ok(kn.store_mut()?.import(entries, policy)?))
+    }
+
+    /// Renames entry `from` to `to`, keeping its fields, tags and timestamps. References
+    /// to it, its pin and its usage counters follow. With `overwrite`, an entry `to` is
+    /// replaced. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `from` does not exist, or `to` is invalid or (without
+    /// `overwrite`) taken.
+    pub fn rename(&mut self, from: &str, to: &str, overwrite: bool) -> Result<RenameSummary> {
+        self.mutate(|kn| {
+            let summary = kn.store_mut()?.rename(from, to, overwrite)?;
+            kn.rename_metadata(&summary)?;
+            Ok(summary)
+        })
+    }
+
+    /// Renames every entry under the prefix `from` to the prefix `to` (e.g. `old-app/`
+    /// to `new-app/`) in one step: either every key is renamed or none is. References
+    /// to the renamed entries are rewritten, and pins, usage counters and the autotype
+    /// sequences of the namespaces follow. With `overwrite`, entries in the way are
+    /// replaced. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a new key is invalid, reserved, or (without `overwrite`)
+    /// taken by an entry that is not renamed itself; the keystore is then unchanged.
+    pub fn rename_prefix(
+        &mut self,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+    ) -> Result<RenameSummary> {
+        self.mutate(|kn| kn.rename_prefix_inner(from, to, overwrite))
+    }
+
+    fn rename_prefix_inner(
+        &mut self,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+    ) -> Result<RenameSummary> {
+        let summary = self.store_mut()?.rename_prefix(from, to, overwrite)?;
+        self.rename_metadata(&summary)?;
+
+        // A login is a namespace: `bank` moves with the prefix `bank/` (or `ba`).
+        let sequences = self.autotype_sequences()?;
+        if sequences
+            .keys()
+            .any(|login| format!("{login}/").starts_with(from))
+        {
+            let sequences: BTreeMap<String, String> = sequences
+                .into_iter()
+                .map(
+                    |(login, sequence)| match format!("{login}/").strip_prefix(from) {
+                        Some(rest) => (
+                            format!("{to}{rest}").trim_end_matches('/').to_string(),
+                            sequence,
+                        ),
+                        None => (login, sequence),
+                    },
+                )
+                .collect();
+            self.set_setting::<AutotypeSequences>(&sequences)?;
+        }
+        Ok(summary)
+    }
+
+    /// Moves the pins, leases, read receipts and usage counters of the renamed keys; those of replaced
+    /// entries are dropped.
+    fn rename_metadata(&mut self, summary: &RenameSummary) -> Result<()> {
+        let renamed: BTreeMap<&String, &String> = summary
+            .renamed()
+            .iter()
+            .map(|(old, new)| (old, new))
+            .collect();
+        let pinned = self.pinned()?;
+        let moved: BTreeSet<String> = pinned
+            .iter()
+            .filter(|key| !summary.replaced().contains(key))
+            .map(|key| renamed.get(key).map_or(key, |new| *new).clone())
+            .collect();
+        if moved != pinned {
+            if moved.is_empty() {
+                self.remove_setting::<Pinned>()?;
+            } else {
+                self.set_setting::<Pinned>(&moved)?;
+            }
+        }
+        let leases = self.leases()?;
+        let moved: BTreeMap<String, Vec<Lease>> = leases
+            .iter()
+            .filter(|(key, _)| !summary.replaced().contains(key))
+            .map(|(key, held)| {
+                (
+                    renamed.get(key).map_or(key, |new| *new).clone(),
+                    held.clone(),
+                )
+            })
+            .collect();
+        if moved != leases {
+            self.set_setting::<Leases>(&moved)?;
+        }
+        if let Some(receipts) = self.read_receipts()? {
+            let moved: BTreeMap<String, ReadReceipt> = receipts
+                .iter()
+                .filter(|(key, _)| !summary.replaced().contains(key))
+                .map(|(key, receipt)| {
+                    (
+                        renamed.get(key).map_or(key, |new| *new).clone(),
+                        receipt.clone(),
+                    )
+                })
+                .collect();
+            if moved != receipts {
+                self.set_setting::<ReadReceipts>(&moved)?;
+            }
+        }
+        self.update_usage(|usage| usage.rename(summary.renamed(), summary.replaced()))
+    }
+
+    /// Copies entry `from` to `to` with its kind, fields, tags, expiry and attachments;
+    /// the copy is timestamped now. With `overwrite`, an entry `to` is replaced.
+    /// Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `from` does not exist, `to` is invalid or (without
+    /// `overwrite`) taken, or a quota or reference cycle forbids the copy.
+    pub fn copy(&mut self, from: &str, to: &str, overwrite: bool) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.copy(from, to, overwrite)?))
+    }
+
+    /// Copies the entries `keys`, given relative to namespace `from`, into namespace
+    /// `to` (e.g. from `staging/` to `prod/`) like [`Keynest::copy`], replacing the
+    /// entries there. Copied `ref:` values pointing into `from` are rewritten to point
+    /// into `to`, and every copy is logged as a `promote` event if the audit log is on.
+    /// Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Keynest::copy`] and [`Keynest::update`]; the entries
+    /// promoted before the failing one are kept.
+    pub fn promote(&mut self, from: &str, to: &str, keys: &[String]) -> Result<()> {
+        self.mutate(|kn| {
+            for key in keys {
+                kn.store_mut()?
+                    .copy(&format!("{from}{key}"), &format!("{to}{key}"), true)?;
+            }
+            // Rewritten once every entry is copied, so references between promoted
+            // entries find their target.
+            for key in keys {
+                let promoted = format!("{to}{key}");
+                let rewritten = kn
+                    .store()?
+                    .get(&promoted)
+                    .and_then(store::reference_target)
+                    .and_then(|target| target.strip_prefix(from))
+                    .map(|rest| format!("{}{to}{rest}", store::REFERENCE_PREFIX));
+                if let Some(value) = rewritten {
+                    kn.store_mut()?.update(&promoted, &value)?;
+                }
+                kn.log_promotion(&format!("{from}{key}"), &promoted)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Removes a secret from the keystore.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key does not exist.
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.remove(key)?))
+    }
+
+    /// Attaches the content of `reader` to secret `key` under `name`.
+    ///
+    /// The content is split into chunks that are encrypted and stored next to the
+    /// keystore (`<store>.blobs/`); chunks with identical content are stored only once,
+    /// no matter how many entries attach them. The chunks are written immediately, the
+    /// attachment itself becomes part of the keystore on the next [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret does not exist, already has an attachment
+    /// `name`, or a chunk cannot be written.
+    pub fn attach(&mut self, key: &str, name: &str, reader: impl Read) -> Result<()> {
+        self.ensure_not_decoy("store attachments")?;
+        if self.store()?.get(key).is_none() {
+            return Err(error::StoreError::KeyNotFound(key.to_string()).into());
+        }
+        if self.store()?.attachment(key, name).is_some() {
+            return Err(error::StoreError::AttachmentAlreadyExists {
+                key: key.to_string(),
+                name: name.to_string(),
+            }
+            .into());
+        }
+
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        if self.store()?.attachment_key().is_none() {
+            let mut attachment_key = Zeroizing::new([0u8; crypto::KEY_LEN]);
+            crypto::random::fill(EntropyUse::Key, &mut *attachment_key)?;
+            self.store_mut()?
+                .set_attachment_key(attachments::to_hex(&*attachment_key));
+        }
+
+        let attachment = self.blob_store()?.write(reader)?;
+        self.mutate(|kn| Ok(kn.store_mut()?.attach(key, name, attachment)?))
+    }
+
+    /// Reads the attachment `name` of secret `key` into memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the attachment does not exist or a chunk cannot be
+    /// read or authenticated.
+    pub fn read_attachment(&self, key: &str, name: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let attachment = self.attachment(key, name)?;
+        self.blob_store()?.read(attachment)
+    }
+
+    /// Returns a reader that streams the attachment `name` of secret `key`.
+    ///
+    /// Chunks are decrypted and authenticated one at a time as the reader advances, so
+    /// attachments of hundreds of megabytes can be piped to other tools (e.g. with
+    /// [`std::io::copy`]) without buffering the whole plaintext in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the attachment does not exist. Chunks that are missing or fail
+    /// authentication are reported as I/O errors while reading.
+    pub fn attachment_reader(&self, key: &str, name: &str) -> Result<AttachmentReader> {
+        let attachment = self.attachment(key, name)?;
+        Ok(self.blob_store()?.reader(attachment))
+    }
+
+    /// Removes the attachment `name` from secret `key`.
+    ///
+    /// Chunks no longer referenced by any attachment are deleted from disk by
+    /// [`Keynest::purge_attachments`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the attachment does not exist.
+    pub fn detach(&mut self, key: &str, name: &str) -> Result<()> {
+        self.mutate(|kn| {
+            kn.store_mut()?.detach(key, name)?;
+            Ok(())
+        })
+    }
+
+    /// Returns the attachments of secret `key`, keyed by name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record holding the entry cannot be decrypted (see
+    /// [`Keynest::set_per_entry_records`]).
+    pub fn attachments(&self, key: &str) -> Result<Option<&BTreeMap<String, Attachment>>> {
+        Ok(self.entry(key)?.map(|e| e.attachments()))
+    }
+
+    /// Deletes attachment chunks on disk that no attachment references anymore.
+    ///
+    /// Returns the number of chunk files removed. Call this after [`Keynest::save`]
+    /// so chunks released by `detach`/`remove` are only deleted once the keystore no
+    /// longer references them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the chunk directory cannot be read or cleaned up.
+    pub fn purge_attachments(&self) -> Result<usize> {
+        let store = self.store()?;
+        if store.attachment_key().is_none() {
+            return Ok(0);
+        }
+        self.blob_store()?.purge(|id| store.is_chunk_referenced(id))
+    }
+
+    fn attachment(&self, key: &str, name: &str) -> Result<&Attachment> {
+        self.store()?.attachment(key, name).ok_or_else(|| {
+            error::StoreError::AttachmentNotFound {
+                key: key.to_string(),
+                name: name.to_string(),
+            }
+            .into()
+        })
+    }
+
+    fn blob_store(&self) -> Result<BlobStore> {
+        self.ensure_unlocked()?;
+        let hex = self
+            .store()?
+            .attachment_key()
+            .context("keystore has no attachment key")?;
+        let attachment_key = Zeroizing::new(attachments::from_hex(hex)?);
+        Ok(BlobStore::new(
+            &self.storage,
+            &attachment_key,
+            self.keystore_file.algorithm(),
+        ))
+    }
+
+    /// Returns the store quotas (maximum entries, value length, and store size).
+    pub fn quotas(&self) -> &Quotas {
+        match self.store.get() {
+            Some(store) => store.quotas(),
+            None => self.sealed().index().quotas(),
+        }
+    }
+
+    /// Replaces the store quotas. Persisted on the next [`Keynest::save`].
+    ///
+    /// Quotas are checked on `set`, `update`, and `save`; depending on
+    /// [`QuotaEnforcement`] exceeding one prints a warning or fails the operation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn set_quotas(&mut self, quotas: Quotas) -> Result<()> {
+        self.store_mut()?.set_quotas(quotas);
+        Ok(())
+    }
+
+    /// Returns the value of the store setting `S`, or `None` if it is not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored value does not match `S::Value`.
+    pub fn setting<S: Setting>(&self) -> Result<Option<S::Value>> {
+        Ok(self.settings().get::<S>()?)
+    }
+
+    /// Sets the store setting `S`. Persisted on the next [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be serialized.
+    pub fn set_setting<S: Setting>(&mut self, value: &S::Value) -> Result<()> {
+        self.mutate(|kn| Ok(kn.store_mut()?.settings_mut().set::<S>(value)?))
+    }
+
+    /// Removes the store setting `S`. Returns `true` if it was set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn remove_setting<S: Setting>(&mut self) -> Result<bool> {
+        Ok(self.store_mut()?.settings_mut().remove::<S>())
+    }
+
+    /// Returns all store settings.
+    pub fn settings(&self) -> &Settings {
+        match self.store.get() {
+            Some(store) => store.settings(),
+            None => self.sealed().settings(),
+        }
+    }
+
+    /// Returns the file format version written on [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn write_format(&self) -> Result<u8> {
+        Ok(self
+            .settings()
+            .get::<WriteFormat>()?
+            .unwrap_or(format::CURRENT_VERSION))
+    }
+
+    /// Selects the file format version written on [`Keynest::save`]; `None` selects the
+    /// current format. Only [`Keynest::migrate_to`] selects version 2, which rewrites the
+    /// store itself as a single-ciphertext file; stores shared with older keynest
+    /// versions keep a copy instead (see [`Keynest::set_compat_copy`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `version` is neither 2 nor the current format version.
+    pub(crate) fn set_write_format(&mut self, version: Option<u8>) -> Result<()> {
+        match version {
+            None | Some(format::CURRENT_VERSION) => self.mutate(|kn| {
+                kn.store_mut()?.settings_mut().remove::<WriteFormat>();
+                Ok(())
+            }),
+            Some(version @ format::v2::VERSION_V2) => self.set_setting::<WriteFormat>(&version),
+            Some(version) => bail!(
+                "cannot write format version {version}; supported: 2 (compatibility) and {}",
+                format::CURRENT_VERSION
+            ),
+        }
+    }
+
+    /// Returns the format version of the copy [`Keynest::save`] writes beside the store,
+    /// or `None` if it writes none (see [`Keynest::set_compat_copy`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn compat_copy(&self) -> Result<Option<u8>> {
+        Ok(self.settings().get::<CompatCopy>()?)
+    }
+
+    /// Makes [`Keynest::save`] also write the store in format `version` to
+    /// `<store>.v<version>` (see [`Storage::compat_path`]), or stop and delete the copy
+    /// with `None`.
+    ///
+    /// The store itself keeps the current format. The copy is for keynest versions that
+    /// cannot read it: it opens with the same password, but is replaced on the next save,
+    /// so changes made to it are lost. Only version 2 is supported; saves fail with a
+    /// clear error once the store outgrows v2's 64 KiB payload. Saves rewrite the file
+    /// while a copy is written, even with the journal on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `version` is not 2, if the store is itself written in format
+    /// v2, or if it uses a key factor or algorithm v2 cannot hold (a keyfile, keyslots,
+    /// DPAPI, scrypt or AES-256-GCM).
+    pub fn set_compat_copy(&mut self, version: Option<u8>) -> Result<()> {
+        match version {
+            None => self.mutate(|kn| {
+                kn.store_mut()?.settings_mut().remove::<CompatCopy>();
+                Ok(())
+            }),
+            Some(version @ format::v2::VERSION_V2) => {
+                self.ensure_not_decoy("write a copy for older versions")?;
+                if self.write_format()? == version {
+                    bail!("the keystore is itself written in format v{version}");
+                }
+                payload::check_v2(
+                    *self.keystore_file.kdf(),
+                    self.keystore_file.algorithm(),
+                    &payload::KeyBinding::of(&self.keystore_file.header),
+                )?;
+                self.set_setting::<CompatCopy>(&version)
+            }
+            Some(version) => bail!("cannot write format version {version} as a copy; supported: 2"),
+        }
+    }
+
+    /// Returns the usage counters, or `None` while usage tracking is off.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored counters are malformed.
+    pub fn usage(&self) -> Result<Option<Usage>> {
+        Ok(self.settings().get::<UsageStats>()?)
+    }
+
+    /// Turns local usage tracking on or off. While on, the payload counts unlocks,
+    /// saves, and reads of each entry ([`Keynest::record_get`]); turning it off deletes
+    /// the counters. Turning it on again, or while already on, keeps existing counters.
+    /// Persisted on the next [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn set_usage_tracking(&mut self, enabled: bool) -> Result<()> {
+        self.mutate(|kn| {
+            if !enabled {
+                kn.store_mut()?.settings_mut().remove::<UsageStats>();
+            } else if kn.usage()?.is_none() {
+                let usage = Usage::new(kn.now_timestamp());
+                kn.store_mut()?.settings_mut().set::<UsageStats>(&usage)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Resets the usage counters, if tracking is on. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn reset_usage(&mut self) -> Result<()> {
+        if self.usage()?.is_some() {
+            let usage = Usage::new(self.now_timestamp());
+            self.set_setting::<UsageStats>(&usage)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the latest read of each entry, by key, or `None` while read receipts are
+    /// off.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored receipts are malformed.
+    pub fn read_receipts(&self) -> Result<Option<BTreeMap<String, ReadReceipt>>> {
+        Ok(self.settings().get::<ReadReceipts>()?)
+    }
+
+    /// Turns read receipts on or off. While on, [`Keynest::record_get`] also records who
+    /// read the entry (see [`Keynest::set_reader`]) and when; turning them off deletes
+    /// the receipts. Persisted on the next [`Keynest::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn set_read_receipts(&mut self, enabled: bool) -> Result<()> {
+        self.mutate(|kn| {
+            if !enabled {
+                kn.store_mut()?.settings_mut().remove::<ReadReceipts>();
+            } else if kn.read_receipts()?.is_none() {
+                kn.store_mut()?
+                    .settings_mut()
+                    .set::<ReadReceipts>(&BTreeMap::new())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets the name recorded in read receipts for reads through this handle, e.g.
+    /// `alice@laptop`. Without one, reads are recorded as `unknown`.
+    pub fn set_reader(&mut self, reader: &str) {
+        self.reader = Some(reader.to_string());
+    }
+
+    /// Returns `true` if reads are recorded by [`Keynest::record_get`]: usage tracking,
+    /// read receipts or the audit log are on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored settings are malformed.
+    pub fn records_reads(&self) -> Result<bool> {
+        Ok(self.usage()?.is_some()
+            || self.read_receipts()?.is_some()
+            || self.audit_log_enabled()?)
+    }
+
+    /// Counts a read of `key` if usage tracking is on, records its reader if read
+    /// receipts are on, and logs it if the audit log is on. Persisted by
+    /// [`Keynest::save_usage`] or the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored counters or receipts are malformed.
+    pub fn record_get(&mut self, key: &str) -> Result<()> {
+        self.log_event(AuditAction::Get, Some(key))?;
+        if let Some(mut receipts) = self.read_receipts()? {
+            let reader = self.reader.as_deref().unwrap_or("unknown");
+            let receipt = ReadReceipt::new(reader, self.clock().now());
+            receipts.insert(key.to_string(), receipt);
+            self.store_mut()?
+                .settings_mut()
+                .set::<ReadReceipts>(&receipts)?;
+        }
+        let now = self.now_timestamp();
+        self.update_usage(|usage| usage.record_get(key, now))
+    }
+
+    fn update_usage(&mut self, f: impl FnOnce(&mut Usage)) -> Result<()> {
+        if let Some(mut usage) = self.usage()? {
+            f(&mut usage);
+            self.store_mut()?.settings_mut().set::<UsageStats>(&usage)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the password-free key index is maintained on save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn key_index_enabled(&self) -> Result<bool> {
+        Ok(self.settings().get::<KeyIndexEnabled>()?.unwrap_or(false))
+    }
+
+    /// Enables or disables the key index, a Bloom filter of key names written next to
+    /// the keystore (`<store>.keyindex`) that answers "does this key exist?" without the
+    /// master password. It leaks the existence of keys to anyone who can read the file
+    /// and guess names, so it is off by default.
+    ///
+    /// Takes effect on the next [`Keynest::save`], which writes the index while enabled
+    /// and removes it while disabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn set_key_index(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.ensure_not_decoy("keep a key index")?;
+            self.set_setting::<KeyIndexEnabled>(&true)
+        } else {
+            self.mutate(|kn| {
+                kn.store_mut()?.settings_mut().remove::<KeyIndexEnabled>();
+                Ok(())
+            })
+        }
+    }
+
+    /// Rebuilds the key index from the current keys and writes it next to the keystore.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index cannot be written.
+    pub fn rebuild_key_index(&self) -> Result<()> {
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let index = KeyIndex::build(self.store()?.keys().map(String::as_str))?;
+        Storage::new(key_index::index_path(&self.storage)).save(&index.to_bytes())
+    }
+
+    /// Checks the key index on disk against the keystore and returns the keys it is
+    /// missing (empty if the index is current).
+    ///
+    /// Keys that were removed may still test positive until the next rebuild; that only
+    /// adds false positives, which a Bloom filter allows anyway.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no key index or it is malformed.
+    pub fn verify_key_index(&self) -> Result<Vec<String>> {
+        let Some(index) = KeyIndex::load(&self.storage)? else {
+            bail!(
+                "no key index at {}",
+                key_index::index_path(&self.storage).display()
+            );
+        };
+        Ok(self
+            .store()?
+            .keys()
+            .filter(|key| !index.might_contain(key))
+            .cloned()
+            .collect())
+    }
+
+    /// Returns how the record plaintexts of the keystore file are encoded.
+    pub fn payload_encoding(&self) -> PayloadEncoding {
+        self.keystore_file.header.encoding()
+    }
+
+    /// Re-encrypts the keystore with a different payload encoding and writes it.
+    ///
+    /// The new file is parsed and decrypted again before it replaces the old one, and
+    /// must yield exactly the store that was encoded; otherwise nothing is written.
+    /// Unsaved changes are included. Encodings other than plain JSON need the current
+    /// file format, so they cannot be combined with a migration to format v2.
+    ///
+    /// The encoding is kept from then on (see the [`PinnedEncoding`] setting); until
+    /// then saves move JSON payloads to MessagePack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or encryption fails, if the re-encrypted file does
+    /// not decrypt to the same store, or if writing to storage fails.
+    pub fn convert(&mut self, encoding: PayloadEncoding) -> Result<()> {
+        self.ensure_unlocked()?;
+        self.ensure_outside_transaction()?;
+        self.ensure_not_decoy("convert the keystore")?;
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let pinned = self.settings().get::<PinnedEncoding>()?;
+        self.store_mut()?
+            .settings_mut()
+            .set::<PinnedEncoding>(&true)?;
+        let result = self.write_converted(encoding);
+        if result.is_err() && pinned.is_none() {
+            self.store_mut()?.settings_mut().remove::<PinnedEncoding>();
+        }
+        result
+    }
+
+    fn write_converted(&mut self, encoding: PayloadEncoding) -> Result<()> {
+        self.unseal()?;
+        let keystore_file = payload::encrypt(
+            self.store()?,
+            *self.keystore_file.kdf(),
+            self.keystore_file.algorithm(),
+            self.keystore_file.salt().to_vec(),
+            encoding,
+            payload::KeyBinding::of(&self.keystore_file.header),
+            Some(self.next_link()),
+            &self.key,
+        )?;
+        let file = serialize(&keystore_file)?;
+
+        let decrypted = payload::decrypt(&parse(&file)?, &self.key)
+            .context("verification failed: the converted keystore cannot be decrypted")?;
+        if serde_json::to_value(&decrypted)? != serde_json::to_value(self.store()?)? {
+            bail!("verification failed: the converted keystore does not match the original");
+        }
+
+        self.storage.save(&file)?;
+        self.keystore_file = keystore_file;
+        self.chain_saved(&file)
+    }
+
+    /// Lists all secret keys.
+    ///
+    /// Returns a vector of references to the key strings.
+    pub fn list(&self) -> Vec<&String> {
+        match self.store.get() {
+            Some(store) => store.keys().collect(),
+            None => self.sealed().index().keys().collect(),
+        }
+    }
+
+    /// Lists the secret keys starting with `prefix`, e.g. `prod/` for everything in the
+    /// `prod` namespace.
+    pub fn list_prefix(&self, prefix: &str) -> Vec<&String> {
+        match self.store.get() {
+            Some(store) => store.keys_with_prefix(prefix).collect(),
+            None => self.sealed().index().keys_with_prefix(prefix).collect(),
+        }
+    }
+
+    /// Exports the secrets as plaintext in `format`, sorted by key, optionally only the
+    /// keys starting with `prefix`. Reference values are exported as stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secrets cannot be represented in `format` (YAML and TOML
+    /// fail when a key is both a secret and a namespace, e.g. `prod` and `prod/db`).
+    pub fn export(&self, format: ExportFormat, prefix: Option<&str>) -> Result<Zeroizing<String>> {
+        let mut secrets: Vec<(&str, &str)> = Vec::new();
+        for key in self.list_prefix(prefix.unwrap_or_default()) {
+            if let Some(value) = self.get(key)? {
+                secrets.push((key.as_str(), value));
+            }
+        }
+        export::format(format, &secrets)
+    }
+
+    /// Exports like [`Keynest::export`], but with every value replaced according to
+    /// `redaction`, so the layout of the store can be shared for debugging.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no random bytes are available for the hash key, or the
+    /// errors of [`Keynest::export`].
+    pub fn export_redacted(
+        &self,
+        format: ExportFormat,
+        prefix: Option<&str>,
+        redaction: Redaction,
+    ) -> Result<Zeroizing<String>> {
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let redactor = export::Redactor::new(redaction)?;
+        let redacted: Vec<(&str, String)> = self
+            .store()?
+            .entries()
+            .filter(|e| e.key().starts_with(prefix.unwrap_or_default()))
+            .map(|e| {
+                let restricted = !e.policy().is_unrestricted();
+                (e.key(), redactor.redact(e.value(), restricted))
+            })
+            .collect();
+        let secrets: Vec<(&str, &str)> = redacted.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        export::format(format, &secrets)
+    }
+
+    /// Exports the entries starting with `prefix` (all without one) as an encrypted
+    /// bundle: a standalone file under `passphrase`, with its own salt and KDF
+    /// parameters, for moving secrets to another store over untrusted channels. Entries
+    /// keep their kind, fields, tags, expiry and access policy; attachments are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the KDF parameters are invalid or encryption fails.
+    pub fn export_bundle(
+        &self,
+        prefix: Option<&str>,
+        passphrase: Zeroizing<String>,
+        kdf: KdfParams,
+    ) -> Result<Vec<u8>> {
+        self.ensure_unlocked()?;
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let entries = self
+            .store()?
+            .entries()
+            .filter(|e| e.key().starts_with(prefix.unwrap_or_default()))
+            .map(SecretEntry::without_attachments)
+            .collect();
+        bundle::seal(entries, &passphrase, kdf)
+    }
+
+    /// Imports the entries of a bundle written by [`Keynest::export_bundle`]: all of
+    /// them, or none if one is rejected. Existing entries are skipped or replaced as
+    /// `policy` says; a replaced entry keeps its attachments. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bundle` is not a bundle or does not decrypt under
+    /// `passphrase`, or the errors of [`Keynest::import_entries`].
+    pub fn import_bundle(
+        &mut self,
+        bundle: &[u8],
+        passphrase: Zeroizing<String>,
+        policy: ImportPolicy,
+    ) -> Result<ImportSummary> {
+        self.ensure_unlocked()?;
+        let entries = bundle::open(bundle, &passphrase)?;
+        drop(passphrase);
+        self.mutate(|kn| Ok(kn.store_mut()?.import_full(entries, policy)?))
+    }
+
+    /// Merges `other`, a copy of this store changed elsewhere, into this one: entries
+    /// only `other` holds are added, and entries that differ are decided by `policy`.
+    /// See [`Keynest::merge_with`].
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::merge_with`].
+    pub fn merge(&mut self, other: &Keynest, policy: MergePolicy) -> Result<MergeSummary> {
+        self.merge_with(other, |conflict| Ok(policy.choose(conflict)))
+    }
+
+    /// Merges `other` into this store, asking `choose` which side to keep for each entry
+    /// that both hold with a different value, kind, fields, tags, expiry or policy.
+    /// Entries taken from `other` keep their `updated` timestamp, so later merges compare
+    /// them correctly; attachments they have that this store's entry lacks are copied.
+    /// Entries removed from one store are added back from the other. Settings are not
+    /// merged. Persisted on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of `choose`, or an error if an entry of `other` is
+    /// rejected (quotas, reference cycles); no entry is merged then. Returns an error if
+    /// an attachment cannot be copied; the entries are merged then, with the attachments
+    /// copied before the error.
+    pub fn merge_with(
+        &mut self,
+        other: &Keynest,
+        mut choose: impl FnMut(&Conflict) -> Result<Side>,
+    ) -> Result<MergeSummary> {
+        self.ensure_unlocked()?;
+        other.ensure_unlocked()?;
+        let mut summary = MergeSummary::default();
+        let mut taken = Vec::new();
+        for theirs in other.store()?.entries() {
+            let Some(ours) = self.store()?.entry(theirs.key()) else {
+                summary.added.push(theirs.key().to_string());
+                taken.push(theirs);
+                continue;
+            };
+            let conflict = Conflict { ours, theirs };
+            if conflict.differences().is_empty() {
+                summary.unchanged += 1;
+                continue;
+            }
+            match choose(&conflict)? {
+                Side::Ours => summary.kept.push(theirs.key().to_string()),
+                Side::Theirs => {
+                    summary.taken.push(theirs.key().to_string());
+                    taken.push(theirs);
+                }
+            }
+        }
+
+        self.mutate(|kn| {
+            let entries = taken.iter().map(|e| e.without_attachments()).collect();
+            kn.store_mut()?
+                .import_full(entries, ImportPolicy::Overwrite)?;
+            for theirs in &taken {
+                for name in theirs.attachments().keys() {
+                    if kn.store()?.attachment(theirs.key(), name).is_none() {
+                        kn.attach(
+                            theirs.key(),
+                            name,
+                            other.attachment_reader(theirs.key(), name)?,
+                        )?;
+                    }
+                }
+            }
+            Ok(summary)
+        })
+    }
+
+    /// Lists all secrets with their metadata.
+    ///
+    /// Returns a vector of references to `SecretEntry` containing
+    /// key, value, and update timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn list_all(&self) -> Result<Vec<&SecretEntry>> {
+        Ok(self.store()?.entries().collect())
+    }
+
+    /// Returns the entries matching `matcher` by key (or, if it searches values, by a
+    /// line of their value), in key order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn find<'a>(
+        &'a self,
+        matcher: &'a Matcher,
+    ) -> Result<impl Iterator<Item = &'a SecretEntry>> {
+        Ok(self
+            .store()?
+            .entries()
+            .filter(|e| matcher.matches_entry(e.key(), e.value())))
+    }
+
+    /// Returns the entries whose libsecret-style attributes have all the given values,
+    /// in key order, so credentials can be found the way Secret Service clients look
+    /// them up (`service`, `account`, `host`, ...). See [`SecretEntry::attribute`] for
+    /// how attributes map to keys and fields.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of a keystore opened with its entries encrypted
+    /// cannot be decrypted (see [`Keynest::set_per_entry_records`]).
+    pub fn lookup<'a>(
+        &'a self,
+        attributes: &'a [(&'a str, &'a str)],
+    ) -> Result<impl Iterator<Item = &'a SecretEntry>> {
+        Ok(self
+            .store()?
+            .entries()
+            .filter(|e| e.matches_attributes(attributes)))
+    }
+
+    /// Persists the keystore to storage.
+    ///
+    /// Must be called after making changes (set, update, remove)
+    /// to save them to disk. Writes the sectioned (v3) format, so older keystores are
+    /// upgraded on their first save, unless the store was migrated to format v2 with
+    /// [`Keynest::migrate_to`]. Also writes the copy selected with
+    /// [`Keynest::set_compat_copy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to storage fails, if the serialized store
+    /// exceeds the configured size quota in [`QuotaEnforcement::Error`] mode, or if
+    /// it is too large for format v2 while written in it or with a v2 copy.
+    pub fn save(&mut self) -> Result<()> {
+        self.update_usage(Usage::record_save)?;
+        self.write()
+    }
+
+    /// Turns autosave on or off (off by default). While on, every method that changes
+    /// entries or settings saves the keystore when it succeeds, so changes cannot be lost
+    /// by forgetting [`Keynest::save`]; a [`Keynest::transaction`] saves once at its
+    /// end. [`Keynest::set_quotas`], [`Keynest::remove_setting`] and the usage counters
+    /// of [`Keynest::record_get`] still wait for the next save.
+    ///
+    /// Each save rewrites the whole file unless the journal is on (see
+    /// [`Keynest::set_journal`]), so batch many changes in a transaction.
+    pub fn set_autosave(&mut self, enabled: bool) {
+        self.autosave = enabled;
+    }
+
+    /// Returns `true` if autosave is on (see [`Keynest::set_autosave`]).
+    pub fn autosave(&self) -> bool {
+        self.autosave
+    }
+
+    /// Applies the changes made by `f` atomically and saves once at the end: if `f`
+    /// returns an error, or the save fails, the in-memory store is rolled back to its
+    /// state before the transaction and nothing is written.
+    ///
+    /// Chunks written by [`Keynest::attach`] stay on disk after a rollback until
+    /// [`Keynest::purge_attachments`] removes them.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `f` or of the save. Fails without calling `f` if the
+    /// keystore is locked or read-only, or when called inside another transaction;
+    /// calling [`Keynest::save`], [`Keynest::rekey`] or [`Keynest::convert`] inside
+    /// `f` fails too.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// kn.transaction(|tx| {
+    ///     tx.update("db/password", &new_password)?;
+    ///     tx.remove("db/old_password")?;
+    ///     Ok(())
+    /// })?;
+    /// ```
+    pub fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.batch > 0 {
+            bail!("transactions cannot be nested");
+        }
+        self.ensure_writable()?;
+        self.unseal()?;
+        let mut before = self.store()?.clone();
+
+        self.batch += 1;
+        let result = f(self);
+        self.batch -= 1;
+        let result = result.and_then(|value| {
+            self.save()?;
+            Ok(value)
+        });
+
+        if result.is_err() {
+            std::mem::swap(self.store_mut()?, &mut before);
+        }
+        before.wipe();
+        result
+    }
+
+    /// Runs a change to the store and, in autosave mode, saves afterwards unless the
+    /// change is part of a larger one (a transaction, or another method built on it).
+    fn mutate<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.unseal()?;
+        self.batch += 1;
+        let result = f(self);
+        self.batch -= 1;
+        let value = result?;
+        if self.autosave && self.batch == 0 {
+            self.save()?;
+        }
+        Ok(value)
+    }
+
+    /// Persists the usage counters and read receipts recorded since the keystore was
+    /// opened (see [`Keynest::record_get`]) without counting a save. Does nothing while
+    /// neither is on.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::save`].
+    pub fn save_usage(&mut self) -> Result<()> {
+        if self.usage()?.is_some() || self.read_receipts()?.is_some() {
+            return self.write();
+        }
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        self.flush_audit()
+    }
+
+    fn write(&mut self) -> Result<()> {
+        self.ensure_writable()?;
+        self.unseal()?;
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let store = self.store()?;
+        if let Some(mut usage) = store.settings().get::<UsageStats>()? {
+            usage.retain(|key| store.get(key).is_some());
+            self.store_mut()?.settings_mut().set::<UsageStats>(&usage)?;
+        }
+        let store = self.store()?;
+        if let Some(mut receipts) = store.settings().get::<ReadReceipts>()? {
+            receipts.retain(|key, _| store.get(key).is_some());
+            self.store_mut()?
+                .settings_mut()
+                .set::<ReadReceipts>(&receipts)?;
+        }
+        let store = self.store()?;
+        if let Some(mut pinned) = store.settings().get::<Pinned>()? {
+            pinned.retain(|key| store.get(key).is_some());
+            if pinned.is_empty() {
+                self.store_mut()?.settings_mut().remove::<Pinned>();
+            } else {
+                self.store_mut()?.settings_mut().set::<Pinned>(&pinned)?;
+            }
+        }
+        let store = self.store()?;
+        if let Some(mut leases) = store.settings().get::<Leases>()? {
+            leases.retain(|key, _| store.get(key).is_some());
+            lease::retain_active(&mut leases, store.clock().now());
+            if leases.is_empty() {
+                self.store_mut()?.settings_mut().remove::<Leases>();
+            } else {
+                self.store_mut()?.settings_mut().set::<Leases>(&leases)?;
+            }
+        }
+
+        if self.decoy {
+            return self.write_decoy();
+        }
+
+        self.flush_audit()?;
+        let compat_copy = self.compat_copy()?;
+        if !self.append_journal()? {
+            // The copy is encrypted first, so a store that no longer fits it is not saved.
+            let copy = match compat_copy {
+                Some(_) => {
+                    let mut store = self.store()?.clone();
+                    store.settings_mut().remove::<CompatCopy>();
+                    Some(payload::encrypt_v2(
+                        &store,
+                        *self.keystore_file.kdf(),
+                        self.keystore_file.algorithm(),
+                        self.keystore_file.salt().to_vec(),
+                        &payload::KeyBinding::of(&self.keystore_file.header),
+                        &self.key,
+                    )?)
+                }
+                None => None,
+            };
+            self.keystore_file = payload::encrypt(
+                self.store()?,
+                *self.keystore_file.kdf(),
+                self.keystore_file.algorithm(),
+                self.keystore_file.salt().to_vec(),
+                self.write_encoding()?,
+                payload::KeyBinding::of(&self.keystore_file.header),
+                Some(self.next_link()),
+                &self.key,
+            )?;
+            let file = serialize(&self.keystore_file)?;
+            self.storage
+                .rotate_backups(self.backups()?, self.clock().now())?;
+            self.storage.save(&file)?;
+            self.chain_saved(&file)?;
+            self.journal = None;
+            self.track_journal()?;
+            if let (Some(version), Some(copy)) = (compat_copy, copy) {
+                Storage::new(self.storage.compat_path(version)).save(&serialize(&copy)?)?;
+            }
+        }
+        if compat_copy.is_none() {
+            let path = self.storage.compat_path(format::v2::VERSION_V2);
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+        }
+
+        let audit_log = self.storage.audit_path();
+        if !self.audit_log_enabled()? && audit_log.exists() {
+            std::fs::remove_file(&audit_log)
+                .with_context(|| format!("failed to remove {}", audit_log.display()))?;
+        }
+
+        if self.key_index_enabled()? {
+            self.rebuild_key_index()?;
+        } else {
+            let path = key_index::index_path(&self.storage);
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the decoy of a duress password to the header of the file as it is now on
+    /// disk, leaving its records, its chain link and the files next to it as they are.
+    fn write_decoy(&mut self) -> Result<()> {
+        let decoy =
+            payload::encrypt_decoy(self.store()?, self.keystore_file.algorithm(), &self.key)?;
+        let data = Arc::new(self.storage.load_data()?);
+        let mut keystore_file = parse_data(&data)?;
+        drop(data);
+        keystore_file.header.decoy = Some(decoy);
+        let file = serialize(&keystore_file)?;
+        keystore_file.release_mapping();
+
+        self.storage
+            .rotate_backups(self.backups()?, self.clock().now())?;
+        self.storage.save(&file)?;
+        self.keystore_file = keystore_file;
+        self.file_hash = file_hash(&file);
+        Ok(())
+    }
+
+    /// Returns the payload encoding of the next rewrite of the file: the current one if
+    /// it was chosen with [`Keynest::convert`]; otherwise MessagePack for the current
+    /// format and JSON for stores migrated to format v2, which cannot hold anything else.
+    fn write_encoding(&self) -> Result<PayloadEncoding> {
+        let current = self.keystore_file.header.encoding();
+        let settings = self.settings();
+        if settings.get::<PinnedEncoding>()?.unwrap_or(false) {
+            return Ok(current);
+        }
+        Ok(match settings.get::<WriteFormat>()? {
+            None | Some(format::CURRENT_VERSION)
+                if current.serialization() == Serialization::Json =>
+            {
+                PayloadEncoding::new(
+                    Serialization::MessagePack,
+                    current.compression(),
+                    current.padding(),
+                )
+            }
+            Some(format::v2::VERSION_V2) if current == PayloadEncoding::compact() => {
+                PayloadEncoding::default()
+            }
+            _ => current,
+        })
+    }
+
+    /// Appends the changes since the last save to the journal, if journaling is on.
+    /// Returns `false` if the file has to be rewritten instead.
+    fn append_journal(&mut self) -> Result<bool> {
+        if !self.journal_enabled()?
+            // The quota applies to the whole store, which only a rewrite measures.
+            || self.store()?.quotas().max_store_size().is_some()
+            || !matches!(
+                self.settings().get::<WriteFormat>()?,
+                None | Some(format::CURRENT_VERSION)
+            )
+            // The copy for older versions is written with the file.
+            || self.compat_copy()?.is_some()
+            // Frames use the encoding of the file, so migrating it takes a rewrite.
+            || self.write_encoding()? != self.keystore_file.header.encoding()
+        {
+            return Ok(false);
+        }
+        // A journal is only started for a decrypted store.
+        let (Some(journal), Some(store)) = (self.journal.as_mut(), self.store.get()) else {
+            return Ok(false);
+        };
+        journal.append(&self.storage, &self.keystore_file, &self.key, store)
+    }
+
+    /// Starts an empty journal for the file as read or written, unless a journal was
+    /// replayed over it or journaling is off.
+    fn track_journal(&mut self) -> Result<()> {
+        if self.journal.is_none() && self.journal_enabled()? && self.keystore_file.is_sectioned() {
+            self.unseal()?;
+            self.journal = Some(Journal::new(&self.keystore_file, self.store()?));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if saves append to the journal (see [`Keynest::set_journal`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn journal_enabled(&self) -> Result<bool> {
+        Ok(self.settings().get::<JournalEnabled>()?.unwrap_or(false))
+    }
+
+    /// Enables or disables the journal (off by default). While enabled, [`Keynest::save`]
+    /// appends the entries changed since the previous save, encrypted, to
+    /// `<store>.journal` instead of rewriting the whole file, and opening the keystore
+    /// replays them. This keeps saves of large keystores cheap.
+    ///
+    /// The file is still rewritten, and the journal folded into it, once the journal
+    /// grows past half the size of the file, after 1024 saves, or on
+    /// [`Keynest::compact`]. Backups (see [`Keynest::set_backups`]) are only made when
+    /// the file is rewritten, and saves with a store size quota always rewrite it.
+    /// Takes effect after the next save; disabling it folds the journal into the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn set_journal(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.ensure_not_decoy("keep a journal")?;
+            self.set_setting::<JournalEnabled>(&true)
+        } else {
+            self.mutate(|kn| {
+                kn.store_mut()?.settings_mut().remove::<JournalEnabled>();
+                Ok(())
+            })
+        }
+    }
+
+    /// Returns `true` if every entry is encrypted as a record of its own (see
+    /// [`Keynest::set_per_entry_records`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn per_entry_records(&self) -> Result<bool> {
+        Ok(self.settings().get::<PerEntryRecords>()?.unwrap_or(false))
+    }
+
+    /// Encrypts every entry as a record of its own instead of in sections of up to 256
+    /// entries (off by default).
+    ///
+    /// [`IndexedKeynest`], which `keynest get` and the other read-only commands use,
+    /// decrypts only the index and the records of the entries it reads, so with this
+    /// on reading one secret never decrypts, or holds in memory, any other value. Each
+    /// record is encrypted with an entry key of its own, which the index holds wrapped
+    /// with the store key, and costs about 200 bytes of nonce, length, tag, wrapped key
+    /// and digest. A `Keynest` opened on such a store decrypts an entry the first time
+    /// it is read, and every entry once it lists, searches or saves.
+    ///
+    /// Takes effect the next time the file is rewritten, e.g. by [`Keynest::convert`]
+    /// or [`Keynest::compact`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn set_per_entry_records(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.set_setting::<PerEntryRecords>(&true)
+        } else {
+            self.mutate(|kn| {
+                kn.store_mut()?.settings_mut().remove::<PerEntryRecords>();
+                Ok(())
+            })
+        }
+    }
+
+    /// Returns `true` if records are written deterministically (see
+    /// [`Keynest::set_deterministic_records`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn deterministic_records(&self) -> Result<bool> {
+        Ok(self
+            .settings()
+            .get::<DeterministicRecords>()?
+            .unwrap_or(false))
+    }
+
+    /// Encrypts records under nonces derived from the key and their contents instead of
+    /// random ones (off by default), so a record whose entries did not change is written
+    /// as the same bytes on every save.
+    ///
+    /// Meant for stores synced with git or Syncthing: a save only changes the records of
+    /// the changed entries (combine with [`Keynest::set_per_entry_records`] to narrow
+    /// that to the entries themselves) plus the index. The price is that someone who
+    /// sees two versions of the file learns which records stayed the same. Journal
+    /// frames still get random nonces, so leave the journal off.
+    ///
+    /// Takes effect the next time the file is rewritten.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn set_deterministic_records(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.set_setting::<DeterministicRecords>(&true)
+        } else {
+            self.mutate(|kn| {
+                kn.store_mut()?
+                    .settings_mut()
+                    .remove::<DeterministicRecords>();
+                Ok(())
+            })
+        }
+    }
+
+    /// Returns `true` if the audit log is on (see [`Keynest::set_audit_log`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn audit_log_enabled(&self) -> Result<bool> {
+        Ok(self.settings().get::<AuditLog>()?.is_some())
+    }
+
+    /// Turns the encrypted, append-only audit log `<store>.audit` on or off (off by
+    /// default). While it is on, every save logs the entries added, changed and removed
+    /// since the previous save, [`Keynest::record_get`] logs reads and rekeys are
+    /// logged, each with its time and reader name (see [`Keynest::set_reader`]); see
+    /// [`Keynest::audit_events`]. Changes are logged from the moment the log is turned
+    /// on; turning it off deletes the log on the next save.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no random bytes are available for the log key, or the
+    /// setting cannot be stored.
+    pub fn set_audit_log(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.audit_log_enabled()? {
+            return Ok(());
+        }
+        if !enabled {
+            self.audited = None;
+            self.audit_pending.clear();
+            return self.mutate(|kn| {
+                kn.store_mut()?.settings_mut().remove::<AuditLog>();
+                Ok(())
+            });
+        }
+        self.ensure_not_decoy("keep an audit log")?;
+
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let mut key = Zeroizing::new([0u8; crypto::KEY_LEN]);
+        crypto::random::fill(EntropyUse::Key, &mut *key)?;
+        self.audited = Some(self.store()?.digest());
+        self.set_setting::<AuditLog>(&AuditLogState::new(&*key))
+    }
+
+    /// Returns the events of the audit log, oldest first, or `None` while it is off.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be read, or was changed or truncated.
+    pub fn audit_events(&self) -> Result<Option<Vec<AuditEvent>>> {
+        let Some(state) = self.settings().get::<AuditLog>()? else {
+            return Ok(None);
+        };
+        let events = audit::read(&self.storage.audit_path(), &state.key()?, state.frames())?;
+        Ok(Some(events))
+    }
+
+    /// Starts logging changes from the store as opened, if the audit log is on.
+    fn track_audit(&mut self) -> Result<()> {
+        if self.audit_log_enabled()? {
+            self.unseal()?;
+            self.audited = Some(self.store()?.digest());
+        }
+        Ok(())
+    }
+
+    /// Queues an event for the audit log, if it is on.
+    fn log_event(&mut self, action: AuditAction, key: Option<&str>) -> Result<()> {
+        if self.audit_log_enabled()? {
+            let actor = self.reader.as_deref().unwrap_or("unknown");
+            let event = AuditEvent::new(self.clock().now(), action, key, actor);
+            self.audit_pending.push(event);
+        }
+        Ok(())
+    }
+
+    /// Queues a `promote` event of entry `to`, copied from `from`, if the audit log is on.
+    fn log_promotion(&mut self, from: &str, to: &str) -> Result<()> {
+        if self.audit_log_enabled()? {
+            let actor = self.reader.as_deref().unwrap_or("unknown");
+            let now = self.clock().now();
+            let event = AuditEvent::new(now, AuditAction::Promote, Some(to), actor);
+            self.audit_pending.push(event.with_from(from));
+        }
+        Ok(())
+    }
+
+    /// Appends the queued events and the entries changed since the last append to the
+    /// audit log, and records its length in the store.
+    fn flush_audit(&mut self) -> Result<()> {
+        let Some(mut state) = self.settings().get::<AuditLog>()? else {
+            return Ok(());
+        };
+        let digest = self.store()?.digest();
+        let mut events = std::mem::take(&mut self.audit_pending);
+        if let Some(since) = &self.audited {
+            let now = self.clock().now();
+            let actor = self.reader.as_deref().unwrap_or("unknown");
+            for (action, key) in since.changes(&digest) {
+                events.push(AuditEvent::new(now, action, Some(key), actor));
+            }
+        }
+        self.audited = Some(digest);
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let frames = audit::append(
+            &self.storage.audit_path(),
+            &state.key()?,
+            self.keystore_file.algorithm(),
+            &events,
+        )?;
+        state.set_frames(frames);
+        self.store_mut()?.settings_mut().set::<AuditLog>(&state)?;
+        Ok(())
+    }
+
+    /// Returns the place of the file in the hash chain over its saves, or `None` if it
+    /// has not been saved with a link yet: files written before the chain was
+    /// introduced get one on their next save, files written in format v2 never.
+    pub fn chain(&self) -> Option<&ChainLink> {
+        self.keystore_file.header.chain()
+    }
+
+    /// Sets the witness that every save of this handle advances, or removes it with
+    /// `None` (see [`ChainWitness`]).
+    pub fn set_chain_witness(&mut self, witness: Option<ChainWitness>) {
+        self.chain_witness = witness;
+    }
+
+    /// Compares the file, as opened or last saved by this handle, to the newest save
+    /// recorded in `witness`, and records the file there unless it was rolled back or
+    /// forked. A store more than one save ahead is followed back through its backups
+    /// to the recorded save, as far as they go.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file has no chain link yet (see [`Keynest::chain`]), or
+    /// the witness or a backup cannot be read or written.
+    pub fn verify_chain(&self, witness: &ChainWitness) -> Result<ChainStatus> {
+        let Some(link) = self.chain() else {
+            bail!(
+                "the keystore has no hash chain yet; it starts with the next save in the current format"
+            );
+        };
+        let mut earlier = BTreeMap::new();
+        for backup in self.storage.backups()? {
+            let data = std::fs::read(backup.path())?;
+            if let Some(link) = parse(&data)
+                .ok()
+                .and_then(|file| file.header.chain().copied())
+            {
+                earlier.insert(file_hash(&data), link);
+            }
+        }
+        let status = chain::verify(link, self.file_hash, witness, |hash| {
+            earlier.get(hash).copied()
+        })?;
+        if status.is_ok() {
+            self.accept_chain(witness)?;
+        }
+        Ok(status)
+    }
+
+    /// Records the file in `witness` as the newest save seen, e.g. after putting back a
+    /// backup on purpose.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file has no chain link yet or the witness cannot be
+    /// written.
+    pub fn accept_chain(&self, witness: &ChainWitness) -> Result<()> {
+        let Some(link) = self.chain() else {
+            bail!(
+                "the keystore has no hash chain yet; it starts with the next save in the current format"
+            );
+        };
+        witness.record(chain::ChainHead {
+            generation: link.generation(),
+            hash: self.file_hash,
+        })
+    }
+
+    /// Returns the chain link of the next save.
+    fn next_link(&self) -> ChainLink {
+        let generation = self.chain().map_or(0, ChainLink::generation);
+        ChainLink::new(generation + 1, self.file_hash)
+    }
+
+    /// Remembers the hash of the file just written and advances the chain witness.
+    fn chain_saved(&mut self, file: &[u8]) -> Result<()> {
+        self.file_hash = file_hash(file);
+        match (&self.chain_witness, self.keystore_file.header.chain()) {
+            (Some(witness), Some(link)) => witness.advance(link, self.file_hash),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rewrites the keystore file with every change saved to the journal, plus any
+    /// unsaved changes, and removes the journal. Returns the number of saves that were
+    /// folded in.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::save`].
+    pub fn compact(&mut self) -> Result<u32> {
+        self.ensure_writable()?;
+        let frames = self.journal.take().map_or(0, |journal| journal.frames());
+        self.write()?;
+        Ok(frames)
+    }
+
+    /// Rewrites the keystore file in format `version` (2 or
+    /// [`format::CURRENT_VERSION`]) and keeps writing it on later saves (see
+    /// [`Keynest::write_format`]). Rewriting a file of an older version into the
+    /// current one also moves it to the current header fields (key check, payload
+    /// encoding) while keeping its cipher, KDF, keyslots and other key factors.
+    ///
+    /// The file is copied to `<store>.bak.<time>` first; saves still in the journal are
+    /// folded into the file before, so the copy holds them. Returns the copy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `version` is not supported, if the store does not fit it
+    /// (format v2 holds 64 KiB and no keyfile, keyslots, DPAPI, scrypt or AES-256-GCM),
+    /// or if the copy or the file cannot be written. The store is left in its previous
+    /// format then.
+    pub fn migrate_to(&mut self, version: u8) -> Result<Option<Backup>> {
+        self.ensure_writable()?;
+        self.ensure_not_decoy("migrate the keystore")?;
+        if self
+            .journal
+            .as_ref()
+            .is_some_and(|journal| journal.frames() > 0)
+        {
+            self.compact()?;
+        }
+        let previous = self.settings().get::<WriteFormat>()?;
+        self.set_write_format(Some(version))?;
+        let result = self.storage.backup(self.clock().now()).and_then(|backup| {
+            self.journal = None;
+            self.write()?;
+            Ok(backup)
+        });
+        if result.is_err() {
+            self.set_write_format(previous)?;
+        }
+        result
+    }
+
+    /// Returns how many previous versions of the keystore file are kept on save, as
+    /// `<store>.bak.1` (most recent) to `<store>.bak.<n>`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn backups(&self) -> Result<u32> {
+        Ok(self.settings().get::<Backups>()?.unwrap_or(0))
+    }
+
+    /// Sets how many previous versions of the keystore file [`Keynest::save`] keeps; 0
+    /// keeps none. Existing backups beyond the new count are left in place. Persisted
+    /// on the next [`Keynest::save`]. Changing the password with [`Keynest::rekey`] does
+    /// not create a backup, so older backups stay encrypted under the old password.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting cannot be stored.
+    pub fn set_backups(&mut self, count: u32) -> Result<()> {
+        if count == 0 {
+            self.mutate(|kn| {
+                kn.store_mut()?.settings_mut().remove::<Backups>();
+                Ok(())
+            })
+        } else {
+            self.set_setting::<Backups>(&count)
+        }
+    }
+
+    /// Returns `true` if this keystore is a read-only snapshot (see
+    /// [`Keynest::snapshot`]), which [`Keynest::save`] refuses to write.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn is_read_only(&self) -> Result<bool> {
+        Ok(self.settings().get::<ReadOnly>()?.unwrap_or(false))
+    }
+
+    fn ensure_unlocked(&self) -> Result<()> {
+        if self.locked {
+            return Err(Locked.into());
+        }
+        Ok(())
+    }
+
+    fn ensure_outside_transaction(&self) -> Result<()> {
+        if self.batch > 0 {
+            bail!(
+                "cannot write the keystore inside a transaction; it is saved when the transaction ends"
+            );
+        }
+        Ok(())
+    }
+
+    /// Fails for the decoy of a duress password, whose saves only rewrite the decoy in
+    /// the header: `action` would change the rest of the header or files kept next to
+    /// the keystore.
+    fn ensure_not_decoy(&self, action: &str) -> Result<()> {
+        if self.decoy {
+            bail!("cannot {action} with this password");
+        }
+        Ok(())
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        self.ensure_unlocked()?;
+        self.ensure_outside_transaction()?;
+        if self.is_read_only()? {
+            bail!(
+                "keystore is a read-only snapshot: {}\nCreate a new snapshot from the source store instead.",
+                self.storage.path().display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes a read-only copy of selected entries to a new keystore at `storage`,
+    /// encrypted under its own `password`, e.g. to give a CI pipeline exactly the
+    /// secrets it needs.
+    ///
+    /// Each selector is a key or, ending in `/`, a namespace (`prod/` selects everything
+    /// under `prod`). References are resolved, so the copy does not depend on entries
+    /// left out; tags and expiry dates are kept, attachments and store settings are not
+    /// copied. [`Keynest::save`] and
+    /// [`Keynest::rekey`] refuse to modify the copy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a keystore already exists at `storage`, a selector matches no
+    /// entry (`StoreError::KeyNotFound`), a selected reference is broken, or the copy
+    /// cannot be encrypted or written.
+    pub fn snapshot<S: AsRef<str>>(
+        &self,
+        selectors: &[S],
+        password: Zeroizing<String>,
+        storage: Storage,
+        kdf: KdfParams,
+    ) -> Result<Keynest> {
+        self.ensure_unlocked()?;
+        if storage.exists() {
+            bail!("keystore already exists: {}", storage.path().display());
+        }
+
+        let mut store = Store::with_clock(self.clock().clone());
+        for selector in selectors {
+            let selector = selector.as_ref();
+            let keys: Vec<&str> = if selector.ends_with('/') {
+                self.list_prefix(selector)
+                    .into_iter()
+                    .map(String::as_str)
+                    .collect()
+            } else {
+                self.get(selector).map(|_| selector).into_iter().collect()
+            };
+            if keys.is_empty() {
+                return Err(error::StoreError::KeyNotFound(selector.to_string()).into());
+            }
+
+            for key in keys {
+                if store.get(key).is_some() {
+                    continue;
+                }
+                let value = self
+                    .resolve(key)?
+                    .ok_or_else(|| error::StoreError::KeyNotFound(key.to_string()))?;
+                match self.kind(key)? {
+                    Some(EntryKind::Note) => store.set_note(key, value)?,
+                    Some(EntryKind::Totp) => store.set_totp(key, value)?,
+                    Some(EntryKind::Recipe) => store.set_recipe(key, value)?,
+                    Some(EntryKind::Counter) => {
+                        store.set_counter(key, counter_value(key, value)?)?
+                    }
+                    _ => store.set(key, value)?,
+                }
+                for tag in self.tags(key)?.unwrap_or_default() {
+                    store.add_tag(key, tag)?;
+                }
+                if let Some(entry) = self.store()?.entries().find(|e| e.key() == key) {
+                    store.set_expiry(key, entry.expires())?;
+                    for (name, value) in entry.fields() {
+                        store.set_field(key, name, value)?;
+                    }
+                }
+            }
+        }
+        store.settings_mut().set::<ReadOnly>(&true)?;
+
+        let mut options = InitOptions::new(kdf).with_clock(self.clock().clone());
+        options.entropy = self.entropy.clone();
+        Self::create(password, storage, options, store)
+    }
+
+    /// Returns information about the keystore.
+    ///
+    /// Includes file path, size, creation date, secret count,
+    /// and KDF/encryption parameters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage metadata cannot be read.
+    pub fn info(&self) -> Result<StoreInfo> {
+        let metadata = std::fs::metadata(self.storage.path())?;
+        Ok(StoreInfo {
+            path: self.storage.path().to_path_buf(),
+            file_size: metadata.len(),
+            creation_date: self.store()?.creation_date().to_string(),
+            secrets_count: self.store()?.len(),
+            kdf: *self.keystore_file.kdf(),
+            algorithm: self.keystore_file.algorithm().name(),
+            nonce_len: self.keystore_file.nonce().len(),
+            dpapi_bound: self.keystore_file.header.dpapi_blob().is_some(),
+            requires_keyfile: self.keystore_file.header.requires_keyfile(),
+            keyslots: self
+                .keyslots()
+                .iter()
+                .map(|slot| slot.name().to_string())
+                .collect(),
+            version: self.keystore_file.version(),
+            payload_encoding: self.payload_encoding().to_string(),
+            read_only: self.is_read_only()?,
+            backups: self.backups()?,
+        })
+    }
+
+    /// Returns statistics about the keystore for dashboards and status bars: entry
+    /// counts, sizes, when it was last saved, the KDF cost, rotation compliance (see
+    /// [`Keynest::expired`]) and whether it is locked. While locked, the counts are zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage metadata or a store setting cannot be read.
+    pub fn metrics(&self) -> Result<Metrics> {
+        let file = std::fs::metadata(self.storage.path())?;
+        let journal = std::fs::metadata(self.storage.journal_path()).ok();
+        let last_saved = [Some(&file), journal.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|metadata| metadata.modified().ok())
+            .max()
+            .map(|time| store::format_timestamp(time.into()));
+
+        let mut kinds = BTreeMap::new();
+        let mut tags = BTreeSet::new();
+        let (mut attachments, mut value_bytes, mut attachment_bytes) = (0, 0, 0);
+        for entry in self.store()?.entries() {
+            *kinds.entry(entry.kind().to_string()).or_insert(0) += 1;
+            tags.extend(entry.tags());
+            attachments += entry.attachments().len();
+            value_bytes += entry.value().len() as u64;
+            attachment_bytes += entry
+                .attachments()
+                .values()
+                .map(Attachment::size)
+                .sum::<u64>();
+        }
+
+        let kdf = *self.keystore_file.kdf();
+        Ok(Metrics {
+            entries: self.store()?.len(),
+            kinds,
+            tags: tags.len(),
+            pinned: self.pinned()?.len(),
+            attachments,
+            value_bytes,
+            attachment_bytes,
+            file_size: file.len(),
+            journal_size: journal.map_or(0, |metadata| metadata.len()),
+            last_saved,
+            kdf,
+            kdf_memory_kib: kdf.memory_kib(),
+            rotation: RotationMetrics::new(self.store()?.entries(), self.clock().now()),
+            locked: self.locked,
+            read_only: self.is_read_only()?,
+        })
+    }
+
+    /// Reads keystore metadata from the unencrypted file header, without decrypting.
+    ///
+    /// Unlike [`Keynest::info`], this does not require the master password: it only reads
+    /// the header (format version, algorithm, nonce length, KDF parameters) plus the file
+    /// path/size. Creation date and secret count are encrypted and therefore not included.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or its header cannot be parsed.
+    pub fn inspect_header(storage: &Storage) -> Result<HeaderInfo> {
+        let data = storage.load()?;
+        let inspection = format::inspect(&data)?;
+
+        Ok(HeaderInfo {
+            path: storage.path().to_path_buf(),
+            file_size: data.len() as u64,
+            version: inspection.version(),
+            algorithm: inspection.algorithm().name(),
+            nonce_len: inspection.nonce_len(),
+            dpapi_bound: inspection.is_dpapi_bound(),
+            requires_keyfile: inspection.requires_keyfile(),
+            keyslots: inspection.keyslots().to_vec(),
+            kdf: *inspection.kdf(),
+            payload_encoding: inspection.encoding().to_string(),
+        })
+    }
+
+    /// Returns the key the keystore is unlocked with, for [`Keynest::open_with_key`].
+    ///
+    /// Anyone holding the key can decrypt the keystore until it is rekeyed; keep it in
+    /// memory only.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Locked`] error while the keystore is locked.
+    pub fn unlock_key(&self) -> Result<UnlockKey> {
+        self.ensure_unlocked()?;
+        Ok(UnlockKey::from_bytes(self.key))
+    }
+
+    /// Zeroizes the key and the decrypted secrets, so a long-running application can
+    /// keep the `Keynest` around without holding plaintext between uses.
+    ///
+    /// Unsaved changes are discarded. While locked the keystore reads as empty, and
+    /// saving, rekeying, converting, snapshots and attachments fail with a [`Locked`]
+    /// error until [`Keynest::unlock`]. Locking twice does nothing.
+    pub fn lock(&mut self) {
+        self.key.zeroize();
+        if let Some(sealed) = self.sealed.take() {
+            let mut store = Store::default();
+            store.set_clock(sealed.clock().clone());
+            self.store = OnceLock::from(store);
+        }
+        if let Some(store) = self.store.get_mut() {
+            store.wipe();
+        }
+        self.locked = true;
+    }
+
+    /// Returns `true` between [`Keynest::lock`] and a successful [`Keynest::unlock`].
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Derives the key from `password` again and reloads the keystore from storage, so
+    /// changes saved by other processes while locked are picked up. The clock and
+    /// entropy source set on this instance are kept.
+    ///
+    /// Also works on an unlocked keystore, discarding unsaved changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore no longer exists, the password is incorrect, or
+    /// the keystore is corrupted; the keystore then stays locked.
+    pub fn unlock(&mut self, password: Zeroizing<String>) -> Result<()> {
+        self.lock();
+        let storage = self.storage.clone();
+        let unlock = Unlock::Password(password, self.keyfile.as_ref(), None);
+        let mut kn = Self::open_inner(unlock, storage, None)?;
+        kn.set_clock(self.clock().clone());
+
+        std::mem::swap(&mut self.key, &mut kn.key);
+        std::mem::swap(&mut self.store, &mut kn.store);
+        std::mem::swap(&mut self.sealed, &mut kn.sealed);
+        std::mem::swap(&mut self.keystore_file, &mut kn.keystore_file);
+        std::mem::swap(&mut self.keyslot, &mut kn.keyslot);
+        std::mem::swap(&mut self.journal, &mut kn.journal);
+        self.decoy = kn.decoy;
+        self.locked = false;
+        Ok(())
+    }
+
+    /// Changes the password and/or KDF parameters.
+    ///
+    /// Re-encrypts the keystore with a new key derived from the new password
+    /// and optional new KDF parameters. The existing secrets are preserved.
+    ///
+    /// A keystore with keyslots keeps its data key: only the keyslot the keystore was
+    /// opened with is rewrapped for the new password, so the other passwords keep
+    /// working (see [`Keynest::add_keyslot`]).
+    ///
+    /// # Arguments
+    ///
+    /// * `new_password` - The new password to derive the encryption key from
+    /// * `new_kdf` - The new KDF parameters (can be different from current)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - Key derivation fails
+    /// - Encryption fails
+    /// - Writing to storage fails
+    pub fn rekey(&mut self, new_password: Zeroizing<String>, new_kdf: KdfParams) -> Result<()> {
+        self.ensure_not_decoy("change the password")?;
+        if self.keystore_file.header.requires_keyfile() && self.keyfile.is_none() {
+            bail!("the keystore requires a keyfile; open it with the keyfile to rekey it");
+        }
+        if !self.keystore_file.header.keyslots().is_empty() {
+            let Some(name) = self.keyslot.clone() else {
+                bail!("open the keystore with the password of a keyslot to rekey it");
+            };
+            return self.rewrap_keyslot(&name, new_password, new_kdf);
+        }
+        let keyfile = self.keyfile.clone();
+        self.rekey_with_keyfile(new_password, new_kdf, keyfile)
+    }
+
+    /// Changes the password and/or KDF parameters like [`Keynest::rekey`], and makes the
+    /// keystore require `keyfile` from now on, or no keyfile if `None`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::rekey`]; also fails for a keystore with keyslots, whose other
+    /// keyslots would stop working with a different keyfile.
+    pub fn rekey_with_keyfile(
+        &mut self,
+        new_password: Zeroizing<String>,
+        new_kdf: KdfParams,
+        keyfile: Option<Keyfile>,
+    ) -> Result<()> {
+        if !self.keystore_file.header.keyslots().is_empty() {
+            bail!("the keyfile of a keystore with keyslots cannot be changed");
+        }
+        let current_algorithm = self.keystore_file.algorithm();
+
+        self.rekey_with_algorithm(new_password, new_kdf, current_algorithm, keyfile)
+    }
+
+    fn rekey_with_algorithm(
+        &mut self,
+        new_password: Zeroizing<String>,
+        new_kdf: KdfParams,
+        new_algorithm: Algorithm,
+        keyfile: Option<Keyfile>,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+        self.unseal()?;
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let new_salt = crypto::generate_salt()?;
+
+        let mut new_key = crypto::derive_key(&new_password, &new_salt, new_kdf)
+            .context("failed to derive new encryption key")?;
+
+        drop(new_password);
+
+        if let Some(keyfile) = &keyfile {
+            new_key = keyfile.bind_key(&new_key);
+        }
+
+        // A bound keystore gets a new DPAPI secret along with the new salt.
+        let dpapi_blob = match self.keystore_file.header.dpapi_blob() {
+            Some(_) => Some(bind_new_key(&mut new_key)?),
+            None => None,
+        };
+
+        let binding = payload::KeyBinding {
+            dpapi_blob,
+            keyfile: keyfile.is_some(),
+            keyslots: Vec::new(),
+            decoy: None,
+        };
+        self.keystore_file = payload::encrypt(
+            self.store()?,
+            new_kdf,
+            new_algorithm,
+            new_salt.to_vec(),
+            self.keystore_file.header.encoding(),
+            binding,
+            Some(self.next_link()),
+            &new_key,
+        )?;
+        let file = serialize(&self.keystore_file)?;
+        self.storage.save(&file)?;
+        self.chain_saved(&file)?;
+
+        self.key.zeroize();
+        self.key = new_key;
+        self.keyfile = keyfile;
+
+        self.log_event(AuditAction::Rekey, None)?;
+        self.flush_audit()
+    }
+
+    /// Returns the keyslots of the keystore, empty if its key is derived from a single
+    /// password.
+    pub fn keyslots(&self) -> &[Keyslot] {
+        self.keystore_file.header.keyslots()
+    }
+
+    /// Returns the name of the keyslot the keystore was opened with, if it has keyslots
+    /// and was opened with a password.
+    pub fn opened_keyslot(&self) -> Option<&str> {
+        self.keyslot.as_deref()
+    }
+
+    /// Adds a keyslot named `name`, so `password` opens the keystore too, and saves it.
+    ///
+    /// A keystore without keyslots first gets a random data key, wrapped in a keyslot
+    /// named [`format::DEFAULT_KEYSLOT`] for the current password, and is re-encrypted with it.
+    /// From then on every keyslot wraps the same data key with a key derived from its own
+    /// password, salt and `kdf`; a keyfile or DPAPI binding applies to all of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is invalid or taken, the keystore already has
+    /// [`format::MAX_KEYSLOTS`] keyslots, it is locked, read-only or inside a transaction, or
+    /// key derivation, encryption or writing to storage fails.
+    pub fn add_keyslot(
+        &mut self,
+        name: &str,
+        password: Zeroizing<String>,
+        kdf: KdfParams,
+    ) -> Result<()> {
+        self.check_new_keyslot(name)?;
+        self.push_keyslot(name, password, kdf, None)
+    }
+
+    /// Checks that keyslot `name` can be added.
+    fn check_new_keyslot(&self, name: &str) -> Result<()> {
+        self.ensure_writable()?;
+        self.ensure_not_decoy("change the keyslots")?;
+        Keyslot::validate_name(name)?;
+        let header = &self.keystore_file.header;
+        if header.keyslots().iter().any(|slot| slot.name() == name) {
+            bail!("keyslot '{name}' already exists");
+        }
+        if header.keyslots().len() >= MAX_KEYSLOTS {
+            bail!("the keystore already has {MAX_KEYSLOTS} keyslots");
+        }
+        Ok(())
+    }
+
+    /// Adds keyslot `name` wrapping `wrapped` (the data key if `None`) for `password`,
+    /// converting the keystore to a data key first if it has no keyslots yet.
+    fn push_keyslot(
+        &mut self,
+        name: &str,
+        password: Zeroizing<String>,
+        kdf: KdfParams,
+        wrapped: Option<&[u8; crypto::KEY_LEN]>,
+    ) -> Result<()> {
+        let header = &self.keystore_file.header;
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let algorithm = header.algorithm();
+
+        let mut keyslots = header.keyslots().to_vec();
+        let mut data_key = Zeroizing::new(self.key);
+        let converting = keyslots.is_empty();
+        if converting {
+            // The current key is the one derived with the header salt and KDF, so it
+            // wraps the data key for the current password as is.
+            crypto::random::fill(EntropyUse::Key, &mut *data_key)?;
+            keyslots.push(Keyslot::wrap(
+                DEFAULT_KEYSLOT,
+                *header.kdf(),
+                header.salt().to_vec(),
+                algorithm,
+                &self.key,
+                &data_key,
+            )?);
+        }
+        let salt = crypto::generate_salt()?;
+        let key = Zeroizing::new(self.derive_keyslot_key(&password, &salt, kdf)?);
+        drop(password);
+        keyslots.push(Keyslot::wrap(
+            name,
+            kdf,
+            salt.to_vec(),
+            algorithm,
+            &key,
+            wrapped.unwrap_or(&data_key),
+        )?);
+
+        self.save_keyslots(keyslots, &data_key)?;
+        if converting {
+            self.key = *data_key;
+            self.keyslot = Some(DEFAULT_KEYSLOT.to_string());
+        }
+        Ok(())
+    }
+
+    /// Removes the keyslot named `name` and saves the keystore; returns `false` if there
+    /// is none.
+    ///
+    /// The data key stays the same, so removing a keyslot does not lock out someone who
+    /// kept a copy of the file or the key from before: re-create the keystore for that.
+    /// Removing the keyslot of the duress password also replaces its decoy with filler
+    /// (see [`Keynest::set_duress`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the last keyslot, or if the keystore is locked, read-only or
+    /// inside a transaction, or writing to storage fails.
+    pub fn remove_keyslot(&mut self, name: &str) -> Result<bool> {
+        self.ensure_writable()?;
+        self.ensure_not_decoy("change the keyslots")?;
+        let mut keyslots = self.keystore_file.header.keyslots().to_vec();
+        let Some(index) = keyslots.iter().position(|slot| slot.name() == name) else {
+            return Ok(false);
+        };
+        if keyslots.len() == 1 {
+            bail!("cannot remove the last keyslot");
+        }
+        keyslots.remove(index);
+        // The decoy of the duress keyslot goes with it, replaced by filler.
+        let duress = self.duress_slot()?.as_deref() == Some(name);
+        let decoy = if duress {
+            self.store_mut()?.settings_mut().remove::<DuressSlot>();
+            self.keystore_file.header.decoy.take()
+        } else {
+            None
+        };
+        let key = self.key;
+        let saved = self.save_keyslots(keyslots, &key);
+        if saved.is_err() && duress {
+            self.keystore_file.header.decoy = decoy;
+            self.store_mut()?
+                .settings_mut()
+                .set::<DuressSlot>(&name.to_string())?;
+        }
+        saved?;
+        if self.keyslot.as_deref() == Some(name) {
+            self.keyslot = None;
+        }
+        Ok(true)
+    }
+
+    /// Sets up a duress password: adds keyslot `name` wrapping the key of a new, empty
+    /// decoy store for `password`. Opening this keystore with `password` then opens the
+    /// decoy instead, with every open function, and changes are saved to the decoy.
+    ///
+    /// The decoy is kept in the header in place of the random filler every keystore
+    /// with keyslots carries, and the keyslot looks like any other, so give it an
+    /// innocuous name: the file does not tell whether it has a duress password. Opened
+    /// with the duress password, the keystore reports the path, header and size of the
+    /// file as usual, but cannot change its password, keyslots, format or encoding, nor
+    /// keep a journal, an audit log, a key index or attachments, and the decoy holds at
+    /// most 16 KiB of compressed entries. [`Keynest::destroy`] destroys the whole
+    /// keystore either way. Keystores without keyslots are converted first, as by
+    /// [`Keynest::add_keyslot`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore already has a duress password, the keyslot
+    /// cannot be added (see [`Keynest::add_keyslot`]), or encrypting the decoy fails.
+    pub fn set_duress(
+        &mut self,
+        name: &str,
+        password: Zeroizing<String>,
+        kdf: KdfParams,
+    ) -> Result<()> {
+        self.check_new_keyslot(name)?;
+        if let Some(slot) = self.duress_slot()? {
+            bail!("the keystore already has a duress password, in keyslot '{slot}'");
+        }
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        // It shares the creation date, which `info` shows, with the store.
+        let mut decoy = self.store()?.empty_like();
+        // Backups are kept next to the file, so the decoy keeps as many.
+        if let Some(backups) = self.settings().get::<Backups>()? {
+            decoy.settings_mut().set::<Backups>(&backups)?;
+        }
+        let mut decoy_key = Zeroizing::new([0u8; crypto::KEY_LEN]);
+        crypto::random::fill(EntropyUse::Key, &mut *decoy_key)?;
+        let sealed = payload::encrypt_decoy(&decoy, self.keystore_file.algorithm(), &*decoy_key)?;
+
+        let previous = self.keystore_file.header.decoy.replace(sealed);
+        self.store_mut()?
+            .settings_mut()
+            .set::<DuressSlot>(&name.to_string())?;
+        let added = self.push_keyslot(name, password, kdf, Some(&decoy_key));
+        if added.is_err() {
+            self.keystore_file.header.decoy = previous;
+            self.store_mut()?.settings_mut().remove::<DuressSlot>();
+        }
+        added
+    }
+
+    /// Returns the name of the keyslot holding the duress password (see
+    /// [`Keynest::set_duress`]), or `None` if there is none. Opened with the duress
+    /// password, the keystore has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored setting is malformed.
+    pub fn duress_slot(&self) -> Result<Option<String>> {
+        Ok(self.settings().get::<DuressSlot>()?)
+    }
+
+    /// Removes the duress password (see [`Keynest::set_duress`]) with its keyslot and
+    /// decoy, like [`Keynest::remove_keyslot`] does for its keyslot; returns `false` if
+    /// there is none.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Keynest::remove_keyslot`].
+    pub fn remove_duress(&mut self) -> Result<bool> {
+        match self.duress_slot()? {
+            Some(slot) => self.remove_keyslot(&slot),
+            None => Ok(false),
+        }
+    }
+
+    /// Destroys the keystore: overwrites its file with random data, flushes it to disk
+    /// and deletes it, and does the same with everything kept next to it (backups,
+    /// temporary files of interrupted saves, the journal, the audit log, the key index,
+    /// attachment chunks and conflict copies of sync tools). Returns the files destroyed,
+    /// the keystore file last.
+    ///
+    /// Copy-on-write file systems, snapshots and the wear leveling of SSDs can still
+    /// keep old blocks of the files; only full-disk encryption rules that out. Copies
+    /// elsewhere, such as exports, are not touched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore is locked or inside a transaction, or a file
+    /// cannot be overwritten or deleted; the keystore file is only destroyed once
+    /// everything else is.
+    pub fn destroy(self) -> Result<Vec<PathBuf>> {
+        self.ensure_unlocked()?;
+        self.ensure_outside_transaction()?;
+        let files = owned_files(&self.storage)?;
+        for file in &files {
+            crate::storage::shred(file)?;
+        }
+        let blobs = attachments::blob_dir(&self.storage);
+        if blobs.exists() {
+            std::fs::remove_dir(&blobs)
+                .with_context(|| format!("failed to remove {}", blobs.display()))?;
+        }
+        Ok(files)
+    }
+
+    /// Wraps the data key in keyslot `name` again for `new_password`, with a new salt.
+    fn rewrap_keyslot(
+        &mut self,
+        name: &str,
+        new_password: Zeroizing<String>,
+        new_kdf: KdfParams,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+        let _entropy = crypto::random::scope(self.entropy.as_ref());
+        let salt = crypto::generate_salt()?;
+        let key = Zeroizing::new(self.derive_keyslot_key(&new_password, &salt, new_kdf)?);
+        drop(new_password);
+
+        let header = &self.keystore_file.header;
+        let algorithm = header.algorithm();
+        let mut keyslots = header.keyslots().to_vec();
+        let Some(slot) = keyslots.iter_mut().find(|slot| slot.name() == name) else {
+            bail!("keyslot '{name}' no longer exists");
+        };
+        *slot = Keyslot::wrap(name, new_kdf, salt.to_vec(), algorithm, &key, &self.key)?;
+        let key = self.key;
+        self.save_keyslots(keyslots, &key)?;
+        self.log_event(AuditAction::Rekey, None)?;
+        self.flush_audit()
+    }
+
+    /// Derives the key of a keyslot from `password`, bound like the keystore's key.
+    fn derive_keyslot_key(
+        &self,
+        password: &str,
+        salt: &[u8],
+        kdf: KdfParams,
+    ) -> Result<[u8; crypto::KEY_LEN]> {
+        let header = &self.keystore_file.header;
+        if header.requires_keyfile() && self.keyfile.is_none() {
+            bail!("the keystore requires a keyfile; open it with the keyfile to change keyslots");
+        }
+        let secret = header
+            .dpapi_blob()
+            .map(crypto::dpapi::unprotect)
+            .transpose()?;
+        derive_bound_key(
+            password,
+            salt,
+            kdf,
+            self.keyfile.as_ref(),
+            secret.as_deref().map(|s| s.as_slice()),
+            None,
+        )
+    }
+
+    /// Encrypts the keystore with `key` and `keyslots` in its header and saves it.
+    fn save_keyslots(&mut self, keyslots: Vec<Keyslot>, key: &[u8; crypto::KEY_LEN]) -> Result<()> {
+        self.unseal()?;
+        let binding = payload::KeyBinding {
+            keyslots,
+            ..payload::KeyBinding::of(&self.keystore_file.header)
+        };
+        let keystore_file = payload::encrypt(
+            self.store()?,
+            *self.keystore_file.kdf(),
+            self.keystore_file.algorithm(),
+            self.keystore_file.salt().to_vec(),
+            self.keystore_file.header.encoding(),
+            binding,
+            Some(self.next_link()),
+            key,
+        )?;
+        let file = serialize(&keystore_file)?;
+        self.storage.save(&file)?;
+        self.keystore_file = keystore_file;
+        self.chain_saved(&file)
+    }
+}
+
+/// Error returned by operations that need the key or the secrets while the keystore is
+/// locked (see [`Keynest::lock`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locked;
+
+impl std::fmt::Display for Locked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "keystore is locked; unlock it first")
+    }
+}
+
+impl std::error::Error for Locked {}
+
+/// Error returned when opening a keystore fails because the password (or keyfile) does
+/// not match the key-check value in its header.
+///
+/// Keystores written before key checks were introduced cannot tell a wrong password
+/// from damaged data and fail with a generic error until they are saved again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongPassword;
+
+impl std::fmt::Display for WrongPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid password")
+    }
+}
+
+impl std::error::Error for WrongPassword {}
+
+/// Error returned when the password matches the key-check value of a keystore but its
+/// data does not decrypt: the file is damaged or was tampered with (see
+/// [`repair`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Corrupted;
+
+impl std::fmt::Display for Corrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "keystore is corrupted: the password is correct, but the data does not decrypt"
+        )
+    }
+}
+
+impl std::error::Error for Corrupted {}
+
+/// Error returned when opening a keystore that requires a keyfile without one (see
+/// [`Keynest::open_with_keyfile`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingKeyfile;
+
+impl std::fmt::Display for MissingKeyfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "keystore requires a keyfile besides the password")
+    }
+}
+
+impl std::error::Error for MissingKeyfile {}
+
+/// Error returned when the password opens none of the keyslots of a keystore (see
+/// [`Keynest::add_keyslot`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoMatchingKeyslot;
+
+impl std::fmt::Display for NoMatchingKeyslot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid password: it opens none of the keyslots")
+    }
+}
+
+impl std::error::Error for NoMatchingKeyslot {}
+
+/// What unlocks a keystore: its master password and keyfile, or the key derived from
+/// them earlier.
+pub(crate) enum Unlock<'a> {
+    Password(
+        Zeroizing<String>,
+        Option<&'a Keyfile>,
+        Option<&'a CancelToken>,
+    ),
+    Key(&'a UnlockKey),
+}
+
+impl<'a> Unlock<'a> {
+    /// Returns the keyfile to keep for the keystore with `header`, if it requires one.
+    pub(crate) fn keyfile(&self, header: &Header) -> Option<&'a Keyfile> {
+        match self {
+            Unlock::Password(_, keyfile, _) if header.requires_keyfile() => *keyfile,
+            _ => None,
+        }
+    }
+
+    /// Returns the key for the keystore with `header`, deriving it if needed, and the
+    /// name of the keyslot the password opened.
+    pub(crate) fn key(self, header: &Header) -> Result<([u8; crypto::KEY_LEN], Option<String>)> {
+        match self {
+            Unlock::Password(password, keyfile, cancel) => {
+                open_keyslots(&password, keyfile, header, cancel)
+            }
+            Unlock::Key(key) => Ok((*key.as_bytes(), None)),
+        }
+    }
+}
+
+/// Runs `decrypt` after checking `key` against the key-check value of `header`, so a
+/// wrong key fails with [`WrongPassword`] and a decryption failure with the right key
+/// with [`Corrupted`]. Headers without a key check leave the errors of `decrypt` as is.
+pub(crate) fn with_key_check<T>(
+    header: &Header,
+    key: &[u8],
+    decrypt: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let Some(check) = header.key_check() else {
+        return decrypt();
+    };
+    if !crypto::key_check::verify(key, check) {
+        return Err(WrongPassword.into());
+    }
+    decrypt().map_err(|e| {
+        if e.is::<crypto::DecryptionFailed>() {
+            Corrupted.into()
+        } else if matches!(
+            e.downcast_ref::<StoreError>(),
+            Some(StoreError::CorruptedIndex(_))
+        ) {
+            e.context(Corrupted)
+        } else {
+            e
+        }
+    })
+}
+
+/// Decrypts the decoy in `header` if `key`, opened from a keyslot of `header`, fails its
+/// key check but opens the decoy: the key of a duress keyslot (see
+/// [`Keynest::set_duress`]).
+pub(crate) fn open_decoy(header: &Header, key: &[u8]) -> Option<Store> {
+    let check = header.key_check()?;
+    if header.decoy().is_none() || crypto::key_check::verify(key, check) {
+        return None;
+    }
+    payload::decrypt_decoy(header, key).ok()
+}
+
+/// Lists the files of the keystore at `storage` that [`Keynest::destroy`] overwrites,
+/// the keystore file last.
+fn owned_files(storage: &Storage) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    files.extend(
+        storage
+            .backups()?
+            .into_iter()
+            .map(|b| b.path().to_path_buf()),
+    );
+    files.extend(storage.temp_files()?);
+    files.extend(storage.conflict_copies()?);
+    files.extend([
+        storage.journal_path(),
+        storage.audit_path(),
+        key_index::index_path(storage),
+    ]);
+    let blobs = attachments::blob_dir(storage);
+    if blobs.is_dir() {
+        for entry in std::fs::read_dir(&blobs)? {
+            files.push(entry?.path());
+        }
+    }
+    files.push(storage.path().clone());
+    files.retain(|file| file.is_file());
+    Ok(files)
+}
+
+/// Derives the key that unlocks the keystore with `header`, on a worker thread if the
+/// caller wants to be able to cancel.
+///
+/// A keystore bound with DPAPI or requiring a keyfile is checked first, so opening it on
+/// another account or without the keyfile fails before the KDF runs. `keyfile` is
+/// ignored if the keystore does not require one.
+fn derive_unlock_key(
+    password: &str,
+    keyfile: Option<&Keyfile>,
+    header: &Header,
+    cancel: Option<&CancelToken>,
+) -> Result<[u8; crypto::KEY_LEN]> {
+    open_keyslots(password, keyfile, header, cancel).map(|(key, _)| key)
+}
+
+/// Like [`derive_unlock_key`], also returning the name of the keyslot the password
+/// opens if the keystore has keyslots. The KDF of each keyslot runs in turn until one
+/// unwraps the data key.
+fn open_keyslots(
+    password: &str,
+    keyfile: Option<&Keyfile>,
+    header: &Header,
+    cancel: Option<&CancelToken>,
+) -> Result<([u8; crypto::KEY_LEN], Option<String>)> {
+    let keyfile = match (header.requires_keyfile(), keyfile) {
+        (true, None) => bail!(MissingKeyfile),
+        (true, keyfile) => keyfile,
+        (false, _) => None,
+    };
+    let secret = header
+        .dpapi_blob()
+        .map(crypto::dpapi::unprotect)
+        .transpose()?;
+    let secret = secret.as_deref().map(|s| s.as_slice());
+    if header.keyslots().is_empty() {
+        let key = derive_bound_key(
+            password,
+            header.salt(),
+            *header.kdf(),
+            keyfile,
+            secret,
+            cancel,
+        )?;
+        return Ok((key, None));
+    }
+    for slot in header.keyslots() {
+        let key = Zeroizing::new(derive_bound_key(
+            password,
+            slot.salt(),
+            *slot.kdf(),
+            keyfile,
+            secret,
+            cancel,
+        )?);
+        if let Ok(data_key) = slot.unwrap(header.algorithm(), &key) {
+            return Ok((data_key, Some(slot.name().to_string())));
+        }
+    }
+    bail!(NoMatchingKeyslot)
+}
+
+/// Derives a key from `password` with `salt` and `kdf`, then binds it to `keyfile` and
+/// the DPAPI `secret`, if any.
+fn derive_bound_key(
+    password: &str,
+    salt: &[u8],
+    kdf: KdfParams,
+    keyfile: Option<&Keyfile>,
+    secret: Option<&[u8]>,
+    cancel: Option<&CancelToken>,
+) -> Result<[u8; crypto::KEY_LEN]> {
+    let key = match cancel {
+        Some(cancel) => crypto::derive_key_cancellable(password, salt, kdf, cancel),
+        None => crypto::derive_key(password, salt, kdf),
+    };
+    // Keep `Cancelled` as the outermost error so callers can detect it with `is`.
+    let mut key = match key {
+        Err(e) if e.is::<Cancelled>() => return Err(e),
+        key => key.context("unable to derive encryption key")?,
+    };
+    if let Some(keyfile) = keyfile {
+        key = keyfile.bind_key(&key);
+    }
+    if let Some(secret) = secret {
+        key = crypto::dpapi::bind_key(&key, secret);
+    }
+    Ok(key)
+}
+
+/// Parses the value of the counter entry `key`.
+fn counter_value(key: &str, value: &str) -> Result<u64> {
+    value
+        .parse()
+        .with_context(|| format!("counter '{key}' does not hold a number: '{value}'"))
+}
+
+/// Returns the TOTP code at `now` of the entry `key` with `value`.
+fn totp_code(key: &str, value: &str, now: DateTime<Utc>) -> Result<TotpCode> {
+    if !OtpAuth::is_otp_uri(value) {
+        bail!("'{key}' is not a TOTP entry (its value is not an otpauth:// URI)");
+    }
+    let otp = OtpAuth::parse(value).with_context(|| format!("invalid OTP entry '{key}'"))?;
+    match otp.totp() {
+        Some(totp) => Ok(totp.code_at(now)),
+        None => bail!("'{key}' is a HOTP entry; only TOTP codes can be generated"),
+    }
+}
+
+/// Binds `key` to the current Windows account with a new DPAPI secret and returns the
+/// protected secret for the header.
+fn bind_new_key(key: &mut [u8; crypto::KEY_LEN]) -> Result<Vec<u8>> {
+    let (secret, blob) = crypto::dpapi::new_binding()?;
+    *key = crypto::dpapi::bind_key(key, &secret);
+    Ok(blob)
+}
+
+/// Returns the default storage location for the keystore.
 ///
 /// The default location is platform-specific:
 /// - Linux: `~/.local/share/keynest/.keynest.db`
@@ -411,470 +4046,2859 @@ pub fn default_storage() -> Result<Storage> {
     let project_dirs =
         ProjectDirs::from("", "", "keynest").context("could not determine platform directories")?;
 
-    let path = project_dirs.data_dir().join(".keynest.db");
+    let path = project_dirs.data_dir().join(".keynest.db");
+
+    Ok(Storage::new(path))
+}
+
+/// Parameters of a new keystore.
+///
+/// Passed to [`Keynest::init_with_options`]; [`config::Profile::init_options`] builds
+/// them from a profile of the config file.
+#[derive(Debug, Clone)]
+pub struct InitOptions {
+    kdf: KdfParams,
+    algorithm: Algorithm,
+    encoding: PayloadEncoding,
+    backups: u32,
+    dpapi: bool,
+    keyfile: Option<Keyfile>,
+    entropy: Option<Arc<dyn EntropySource>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl InitOptions {
+    /// Creates options with the given KDF parameters, XChaCha20-Poly1305, the
+    /// MessagePack payload encoding (see [`PayloadEncoding::compact`]) and no backups.
+    pub fn new(kdf: KdfParams) -> Self {
+        Self {
+            kdf,
+            algorithm: Algorithm::XChaCha20Poly1305,
+            encoding: PayloadEncoding::compact(),
+            backups: 0,
+            dpapi: false,
+            keyfile: None,
+            entropy: None,
+            clock: None,
+        }
+    }
+
+    /// Sets the KDF parameters.
+    pub fn with_kdf(mut self, kdf: KdfParams) -> Self {
+        self.kdf = kdf;
+        self
+    }
+
+    /// Sets the encryption algorithm.
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the payload encoding.
+    pub fn with_encoding(mut self, encoding: PayloadEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets how many previous versions of the file are kept on save.
+    pub fn with_backups(mut self, backups: u32) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    /// Binds the key to the current Windows user account with DPAPI, so the file can
+    /// only be opened by that account on that machine, and only with the password;
+    /// creating the keystore fails on other platforms.
+    pub fn with_dpapi(mut self, dpapi: bool) -> Self {
+        self.dpapi = dpapi;
+        self
+    }
+
+    /// Mixes `keyfile` into the key, so the file can only be opened with the password
+    /// *and* the keyfile (see [`Keynest::open_with_keyfile`]).
+    pub fn with_keyfile(mut self, keyfile: Option<Keyfile>) -> Self {
+        self.keyfile = keyfile;
+        self
+    }
+
+    /// Sets where the salt, nonces and keys of the keystore come from (see
+    /// [`Keynest::set_entropy_source`]).
+    pub fn with_entropy_source(mut self, source: Arc<dyn EntropySource>) -> Self {
+        self.entropy = Some(source);
+        self
+    }
+
+    /// Sets the clock the keystore reads its timestamps from (see [`Keynest::set_clock`]).
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Returns the KDF parameters.
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Returns the encryption algorithm.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the payload encoding.
+    pub fn encoding(&self) -> PayloadEncoding {
+        self.encoding
+    }
+
+    /// Returns how many previous versions of the file are kept on save.
+    pub fn backups(&self) -> u32 {
+        self.backups
+    }
+
+    /// Returns `true` if the key is bound to the Windows user account with DPAPI.
+    pub fn dpapi(&self) -> bool {
+        self.dpapi
+    }
+
+    /// Returns the keyfile mixed into the key, if any.
+    pub fn keyfile(&self) -> Option<&Keyfile> {
+        self.keyfile.as_ref()
+    }
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        Self::new(KdfParams::default())
+    }
+}
+
+/// Information about a keystore.
+///
+/// Returned by [`Keynest::info`].
+#[derive(Serialize)]
+pub struct StoreInfo {
+    path: PathBuf,
+    file_size: u64,
+    creation_date: String,
+    secrets_count: usize,
+    kdf: KdfParams,
+    algorithm: &'static str,
+    nonce_len: usize,
+    dpapi_bound: bool,
+    requires_keyfile: bool,
+    keyslots: Vec<String>,
+    version: u8,
+    payload_encoding: String,
+    read_only: bool,
+    backups: u32,
+}
+
+impl StoreInfo {
+    /// Returns the keystore creation date.
+    pub fn creation_date(&self) -> &str {
+        &self.creation_date
+    }
+
+    /// Returns the number of secrets stored.
+    pub fn secrets_count(&self) -> usize {
+        self.secrets_count
+    }
+
+    /// Returns `true` if the keystore is a read-only snapshot.
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns how many previous versions of the file are kept on save.
+    pub fn backups(&self) -> u32 {
+        self.backups
+    }
+
+    /// Returns the KDF parameters used for key derivation.
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Returns the encryption algorithm.
+    pub fn algorithm(&self) -> &'static str {
+        self.algorithm
+    }
+
+    /// Returns the nonce length in bytes.
+    pub fn nonce_len(&self) -> usize {
+        self.nonce_len
+    }
+
+    /// Returns `true` if the key is bound to a Windows account with DPAPI.
+    pub fn dpapi_bound(&self) -> bool {
+        self.dpapi_bound
+    }
+
+    /// Returns `true` if opening the keystore needs a keyfile besides the password.
+    pub fn requires_keyfile(&self) -> bool {
+        self.requires_keyfile
+    }
+
+    /// Returns the names of the keyslots, empty if the key is derived from a single
+    /// password.
+    pub fn keyslots(&self) -> &[String] {
+        &self.keyslots
+    }
+
+    /// Returns the format version.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the payload encoding, e.g. `json` or `msgpack+deflate`.
+    pub fn payload_encoding(&self) -> &str {
+        &self.payload_encoding
+    }
+
+    /// Returns the file size in bytes.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Returns the file path.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+
+    if bytes >= GB {
+        format!("{:.1} GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.1} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
+/// Fields shown by both the [`StoreInfo`] and [`HeaderInfo`] displays.
+struct HeaderFields<'a> {
+    path: &'a std::path::Path,
+    file_size: u64,
+    version: u8,
+    payload_encoding: &'a str,
+    algorithm: &'a str,
+    nonce_len: usize,
+    dpapi_bound: bool,
+    requires_keyfile: bool,
+    keyslots: &'a [String],
+    kdf: &'a KdfParams,
+}
+
+/// Writes the Location, Encryption, and Key Derivation sections shared by the
+/// [`StoreInfo`] and [`HeaderInfo`] displays.
+fn write_header_sections(
+    f: &mut std::fmt::Formatter<'_>,
+    HeaderFields {
+        path,
+        file_size,
+        version,
+        payload_encoding,
+        algorithm,
+        nonce_len,
+        dpapi_bound,
+        requires_keyfile,
+        keyslots,
+        kdf,
+    }: HeaderFields<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "Location")?;
+    writeln!(f, "  Path:              {}", path.display())?;
+    writeln!(f, "  Size:              {}", format_size(file_size))?;
+    writeln!(f, "  Format version:    {}", version)?;
+    writeln!(f, "  Payload encoding:  {}", payload_encoding)?;
+    writeln!(f)?;
+
+    writeln!(f, "Encryption")?;
+    writeln!(f, "  Algorithm:         {}", algorithm)?;
+    writeln!(f, "  Nonce length:      {} bytes", nonce_len)?;
+    if dpapi_bound {
+        writeln!(f, "  Bound to:          Windows account (DPAPI)")?;
+    }
+    if requires_keyfile {
+        writeln!(f, "  Keyfile:           required")?;
+    }
+    if !keyslots.is_empty() {
+        writeln!(f, "  Keyslots:          {}", keyslots.join(", "))?;
+    }
+    writeln!(f)?;
+
+    writeln!(f, "Key Derivation")?;
+    match kdf {
+        KdfParams::Argon2id(kdf) => {
+            writeln!(f, "  Function:          Argon2id")?;
+            writeln!(f, "  Memory:            {} KiB", kdf.mem_cost_kib())?;
+            writeln!(f, "  Time cost:         {}", kdf.time_cost())?;
+            writeln!(f, "  Parallelism:       {}", kdf.parallelism())
+        }
+        KdfParams::Scrypt(kdf) => {
+            writeln!(f, "  Function:          scrypt")?;
+            writeln!(f, "  Cost (N):          {} (2^{})", kdf.n(), kdf.log_n())?;
+            writeln!(f, "  Block size (r):    {}", kdf.r())?;
+            writeln!(f, "  Parallelism (p):   {}", kdf.p())
+        }
+    }
+}
+
+impl std::fmt::Display for StoreInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Keynest Store Information")?;
+        writeln!(f, "────────────────────────────────────────")?;
+        writeln!(f)?;
+
+        write_header_sections(
+            f,
+            HeaderFields {
+                path: &self.path,
+                file_size: self.file_size,
+                version: self.version,
+                payload_encoding: &self.payload_encoding,
+                algorithm: self.algorithm,
+                nonce_len: self.nonce_len,
+                dpapi_bound: self.dpapi_bound,
+                requires_keyfile: self.requires_keyfile,
+                keyslots: &self.keyslots,
+                kdf: &self.kdf,
+            },
+        )?;
+        writeln!(f)?;
+
+        writeln!(f, "Metadata")?;
+        writeln!(f, "  Created:           {}", self.creation_date)?;
+        writeln!(f, "  Secrets stored:    {}", self.secrets_count)?;
+        if self.backups > 0 {
+            writeln!(f, "  Backups kept:      {}", self.backups)?;
+        }
+        if self.read_only {
+            writeln!(f, "  Read-only:         yes (snapshot)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Keystore metadata read from the unencrypted file header (no password required).
+///
+/// Returned by [`Keynest::inspect_header`]. Contains everything [`StoreInfo`] does except
+/// the encrypted `creation_date` and `secrets_count`.
+#[derive(Serialize)]
+pub struct HeaderInfo {
+    path: PathBuf,
+    file_size: u64,
+    version: u8,
+    algorithm: &'static str,
+    nonce_len: usize,
+    dpapi_bound: bool,
+    requires_keyfile: bool,
+    keyslots: Vec<String>,
+    kdf: KdfParams,
+    payload_encoding: String,
+}
+
+impl HeaderInfo {
+    /// Returns the file path.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Returns the file size in bytes.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Returns the format version.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the encryption algorithm.
+    pub fn algorithm(&self) -> &'static str {
+        self.algorithm
+    }
+
+    /// Returns the nonce length in bytes.
+    pub fn nonce_len(&self) -> usize {
+        self.nonce_len
+    }
+
+    /// Returns `true` if the key is bound to a Windows account with DPAPI.
+    pub fn dpapi_bound(&self) -> bool {
+        self.dpapi_bound
+    }
+
+    /// Returns `true` if opening the keystore needs a keyfile besides the password.
+    pub fn requires_keyfile(&self) -> bool {
+        self.requires_keyfile
+    }
+
+    /// Returns the names of the keyslots, empty if the key is derived from a single
+    /// password.
+    pub fn keyslots(&self) -> &[String] {
+        &self.keyslots
+    }
+
+    /// Returns the KDF parameters used for key derivation.
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Returns the payload encoding, e.g. `json` or `msgpack+deflate`.
+    pub fn payload_encoding(&self) -> &str {
+        &self.payload_encoding
+    }
+}
+
+impl std::fmt::Display for HeaderInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Keynest Store Information (header only)")?;
+        writeln!(f, "────────────────────────────────────────")?;
+        writeln!(f)?;
+
+        write_header_sections(
+            f,
+            HeaderFields {
+                path: &self.path,
+                file_size: self.file_size,
+                version: self.version,
+                payload_encoding: &self.payload_encoding,
+                algorithm: self.algorithm,
+                nonce_len: self.nonce_len,
+                dpapi_bound: self.dpapi_bound,
+                requires_keyfile: self.requires_keyfile,
+                keyslots: &self.keyslots,
+                kdf: &self.kdf,
+            },
+        )?;
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            "Note: run `keynest info` (with the password) to see creation date and secret count."
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt_roundtrip() {
+        use crate::crypto::*;
+        use crate::format::{Header, KeystoreFile, parse, serialize};
+
+        let kdf = KdfParams::default();
+        let salt = generate_salt().unwrap();
+        let key = derive_key("pw", &salt, kdf).unwrap();
+
+        let data = b"secret data".to_vec();
+
+        let (header, ciphertext) = Header::encrypt_store(
+            kdf,
+            Algorithm::XChaCha20Poly1305,
+            salt.to_vec(),
+            &key,
+            &data,
+        )
+        .unwrap();
+
+        let keystore_file = KeystoreFile::new(header, ciphertext);
+        let file = serialize(&keystore_file).unwrap();
+
+        let keystore_file2 = parse(&file).unwrap();
+        let key2 = derive_key("pw", keystore_file2.salt(), *keystore_file2.kdf()).unwrap();
+        let plaintext = keystore_file2.decrypt(&key2).unwrap();
+
+        assert_eq!(*plaintext, data);
+    }
+
+    #[test]
+    fn init_and_open_with_zeroize_wrappers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keynest.db");
+        let storage = Storage::new(path);
+        let password = Zeroizing::new(String::from("pw"));
+        let mut kn =
+            Keynest::init_with_storage_and_kdf(password, storage.clone(), KdfParams::default())
+                .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.save().unwrap();
+
+        let password = Zeroizing::new(String::from("pw"));
+        let kn2 = Keynest::open_with_storage(password, storage).unwrap();
+        assert_eq!(kn2.get("A").unwrap(), Some("B"));
+    }
+
+    #[test]
+    fn settings_persist_in_the_encrypted_payload() {
+        struct Retention;
+
+        impl Setting for Retention {
+            const NAME: &'static str = "retention";
+            type Value = u32;
+        }
+
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let password = Zeroizing::new(String::from("pw"));
+        let mut kn =
+            Keynest::init_with_storage_and_kdf(password, storage.clone(), KdfParams::default())
+                .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.set_setting::<Retention>(&30).unwrap();
+        kn.save().unwrap();
+
+        let kn2 = Keynest::open_with_storage(Zeroizing::new(String::from("pw")), storage.clone())
+            .unwrap();
+        assert_eq!(kn2.setting::<Retention>().unwrap(), Some(30));
+        assert_eq!(kn2.list(), vec!["A"]);
+
+        let indexed =
+            IndexedKeynest::open_with_storage(Zeroizing::new(String::from("pw")), storage).unwrap();
+        assert_eq!(indexed.setting::<Retention>().unwrap(), Some(30));
+        assert_eq!(indexed.list(), vec!["A"]);
+    }
+
+    #[test]
+    fn compat_copy_keeps_a_v2_file_beside_the_store() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let copy = Storage::new(storage.compat_path(2));
+        let pw = || Zeroizing::new(String::from("pw"));
+        let mut kn =
+            Keynest::init_with_storage_and_kdf(pw(), storage.clone(), KdfParams::default())
+                .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.set_compat_copy(Some(2)).unwrap();
+        kn.save().unwrap();
+        assert_eq!(storage.load().unwrap()[4], format::CURRENT_VERSION);
+        assert_eq!(copy.load().unwrap()[4], 2);
+
+        let mut kn = Keynest::open_with_storage(pw(), storage.clone()).unwrap();
+        assert_eq!(kn.write_format().unwrap(), format::CURRENT_VERSION);
+        assert_eq!(kn.compat_copy().unwrap(), Some(2));
+        let old = Keynest::open_with_storage(pw(), copy.clone()).unwrap();
+        assert_eq!(old.get("A").unwrap(), Some("B"));
+        assert_eq!(old.compat_copy().unwrap(), None);
+
+        // Outgrowing v2 fails the save before either file is written.
+        let saved = (storage.load().unwrap(), copy.load().unwrap());
+        kn.set("big", &"x".repeat(70_000)).unwrap();
+        let err = kn.save().unwrap_err();
+        assert!(err.to_string().contains("too large for format v2"));
+        assert_eq!((storage.load().unwrap(), copy.load().unwrap()), saved);
+        kn.remove("big").unwrap();
+
+        assert!(kn.set_compat_copy(Some(1)).is_err());
+        kn.set_compat_copy(None).unwrap();
+        kn.save().unwrap();
+        assert!(!copy.exists());
+        assert_eq!(storage.load().unwrap()[4], format::CURRENT_VERSION);
+    }
+
+    #[test]
+    fn migrate_to_rewrites_the_file_and_keeps_the_original() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let pw = || Zeroizing::new(String::from("pw"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let mut kn = Keynest::init_with_storage_and_kdf(pw(), storage.clone(), kdf).unwrap();
+        kn.set("A", "B").unwrap();
+        kn.set_write_format(Some(2)).unwrap();
+        kn.save().unwrap();
+        let original = storage.load().unwrap();
+
+        let backup = kn.migrate_to(format::CURRENT_VERSION).unwrap().unwrap();
+        assert_eq!(std::fs::read(backup.path()).unwrap(), original);
+        assert_eq!(storage.load().unwrap()[4], format::CURRENT_VERSION);
+        assert_eq!(kn.write_format().unwrap(), format::CURRENT_VERSION);
+
+        let mut kn = Keynest::open_with_storage(pw(), storage.clone()).unwrap();
+        assert_eq!(kn.get("A").unwrap(), Some("B"));
+        assert_eq!(kn.info().unwrap().version(), format::CURRENT_VERSION);
+        let old =
+            Keynest::open_with_storage(pw(), Storage::new(backup.path().to_path_buf())).unwrap();
+        assert_eq!(old.info().unwrap().version(), 2);
+
+        // A store that does not fit the target keeps its format.
+        kn.set("big", &"x".repeat(70_000)).unwrap();
+        kn.save().unwrap();
+        assert!(kn.migrate_to(2).is_err());
+        assert!(kn.migrate_to(1).is_err());
+        assert_eq!(kn.write_format().unwrap(), format::CURRENT_VERSION);
+        assert_eq!(storage.load().unwrap()[4], format::CURRENT_VERSION);
+    }
+
+    #[test]
+    fn convert_changes_the_payload_encoding_in_place() {
+        use crate::format::{Compression, Padding, Serialization};
+
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let pw = || Zeroizing::new(String::from("pw"));
+        let mut kn =
+            Keynest::init_with_storage_and_kdf(pw(), storage.clone(), KdfParams::default())
+                .unwrap();
+        for i in 0..300 {
+            kn.set(&format!("key{i:03}"), "the same value again")
+                .unwrap();
+        }
+        kn.save().unwrap();
+        let json_size = storage.load().unwrap().len();
+
+        let encoding = PayloadEncoding::new(
+            Serialization::MessagePack,
+            Compression::Deflate,
+            Padding::None,
+        );
+        kn.convert(encoding).unwrap();
+        assert!(storage.load().unwrap().len() < json_size);
+        assert_eq!(
+            Keynest::inspect_header(&storage)
+                .unwrap()
+                .payload_encoding(),
+            "msgpack+deflate"
+        );
+
+        // Saves and indexed reads keep the encoding.
+        let mut kn = Keynest::open_with_storage(pw(), storage.clone()).unwrap();
+        assert_eq!(kn.payload_encoding(), encoding);
+        kn.set("new", "value").unwrap();
+        kn.save().unwrap();
+        let mut indexed = IndexedKeynest::open_with_storage(pw(), storage.clone()).unwrap();
+        assert_eq!(indexed.get("key299").unwrap(), Some("the same value again"));
+        assert_eq!(indexed.get("new").unwrap(), Some("value"));
+
+        // The v2 compatibility mode only holds plain JSON.
+        kn.set_write_format(Some(2)).unwrap();
+        assert!(kn.save().is_err());
+        kn.convert(PayloadEncoding::default()).unwrap();
+        assert_eq!(storage.load().unwrap()[4], 2);
+    }
+
+    #[test]
+    fn key_index_follows_the_setting_on_save() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let index_path = key_index::index_path(&storage);
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("github/token", "x").unwrap();
+        kn.save().unwrap();
+        assert!(!index_path.exists());
+        assert!(kn.verify_key_index().is_err());
+
+        kn.set_key_index(true).unwrap();
+        kn.save().unwrap();
+        let index = KeyIndex::load(&storage).unwrap().unwrap();
+        assert!(index.might_contain("github/token"));
+        assert!(kn.verify_key_index().unwrap().is_empty());
+
+        kn.set_key_index(false).unwrap();
+        kn.save().unwrap();
+        assert!(!index_path.exists());
+        assert!(!kn.key_index_enabled().unwrap());
+    }
+
+    #[test]
+    fn limiter_locks_out_repeated_wrong_passwords() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        let limiter = Limiter::new(dir.path().join("limiter.json")).with_max_attempts(2);
+
+        let open = |password: &str| {
+            Keynest::open_with_storage_and_limiter(
+                Zeroizing::new(password.to_string()),
+                storage.clone(),
+                &limiter,
+            )
+        };
+        assert!(open("pw").is_ok());
+        assert!(!open("wrong").err().unwrap().is::<RateLimited>());
+        assert!(!open("wrong").err().unwrap().is::<RateLimited>());
+        assert!(open("pw").err().unwrap().is::<RateLimited>());
+        assert_eq!(limiter.failures().unwrap(), 2);
+    }
+
+    #[test]
+    fn init_fails_if_store_exists() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keynest.db");
+        let storage = Storage::new(path);
+
+        let password = Zeroizing::new(String::from("pw"));
+        Keynest::init_with_storage_and_kdf(password, storage.clone(), KdfParams::default())
+            .unwrap();
+        let password = Zeroizing::new(String::from("pw"));
+        assert!(
+            Keynest::init_with_storage_and_kdf(password, storage, KdfParams::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn wrong_password_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("correct".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        assert!(Keynest::open_with_storage(Zeroizing::new("wrong".to_string()), storage).is_err());
+    }
+
+    #[test]
+    fn set_existing_key_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        assert!(kn.set("A", "C").is_err());
+    }
+
+    #[test]
+    fn update_key_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.update("A", "C").unwrap();
+        assert_eq!(kn.get("A").unwrap().unwrap(), "C");
+    }
+
+    #[test]
+    fn update_not_existing_key_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        assert!(kn.update("Z", "C").is_err());
+    }
+
+    #[test]
+    fn removing_key_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+
+        assert_eq!(kn.get("A").unwrap().unwrap(), "B");
+        kn.remove("A").unwrap();
+        assert_eq!(kn.get("A").unwrap(), None);
+    }
+
+    #[test]
+    fn removing_not_existing_key_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        assert!(kn.remove("A").is_err());
+    }
+
+    #[test]
+    fn snapshot_copies_selected_entries_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("deploy/token", "t").unwrap();
+        kn.set("deploy/db", "ref:shared/db").unwrap();
+        kn.set("shared/db", "url").unwrap();
+        kn.set("other", "o").unwrap();
+
+        let snapshot_storage = Storage::new(dir.path().join("ci.db"));
+        assert!(
+            kn.snapshot(
+                &["deploy/", "missing"],
+                Zeroizing::new("ci".to_string()),
+                snapshot_storage.clone(),
+                KdfParams::default(),
+            )
+            .is_err()
+        );
+        assert!(!snapshot_storage.exists());
+
+        kn.snapshot(
+            &["deploy/"],
+            Zeroizing::new("ci".to_string()),
+            snapshot_storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+
+        let mut snapshot =
+            Keynest::open_with_storage(Zeroizing::new("ci".to_string()), snapshot_storage).unwrap();
+        assert_eq!(snapshot.list(), ["deploy/db", "deploy/token"]);
+        assert_eq!(snapshot.get("deploy/db").unwrap(), Some("url"));
+        assert!(snapshot.is_read_only().unwrap());
+        snapshot.set("x", "y").unwrap();
+        assert!(snapshot.save().is_err());
+        assert!(!kn.is_read_only().unwrap());
+    }
+
+    #[test]
+    fn tags_are_saved_and_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("a", "1").unwrap();
+        kn.set("b", "2").unwrap();
+        kn.add_tag("a", "prod").unwrap();
+        kn.add_tag("b", "prod").unwrap();
+        kn.add_tag("b", "billing").unwrap();
+        kn.save().unwrap();
+
+        let mut kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert_eq!(kn.find_by_tag("prod").unwrap(), ["a", "b"]);
+        assert_eq!(kn.tags("b").unwrap().unwrap(), ["billing", "prod"]);
+        assert!(kn.remove_tag("b", "prod").unwrap());
+        assert_eq!(kn.find_by_tag("prod").unwrap(), ["a"]);
+        assert!(kn.find_by_tag("none").unwrap().is_empty());
+    }
+
+    #[test]
+    fn init_options_are_applied_and_backups_rotated() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8192, 1, 1).unwrap();
+        let padded = PayloadEncoding::new(
+            format::Serialization::Json,
+            format::Compression::None,
+            format::Padding::PowerOfTwo,
+        );
+
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            InitOptions::new(kdf).with_encoding(padded).with_backups(2),
+        )
+        .unwrap();
+        let info = kn.info().unwrap();
+        assert_eq!(*info.kdf(), kdf);
+        assert_eq!(info.payload_encoding(), "json+padded");
+        assert_eq!(info.backups(), 2);
+
+        for key in ["a", "b", "c"] {
+            kn.set(key, "1").unwrap();
+            kn.save().unwrap();
+        }
+        let backups = storage.backups().unwrap();
+        assert_eq!(backups.len(), 2);
+        let backup = Keynest::open_with_storage(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(backups[0].path().to_path_buf()),
+        )
+        .unwrap();
+        assert_eq!(backup.list(), ["a", "b"]);
+        let oldest = Keynest::open_with_storage(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(backups[1].path().to_path_buf()),
+        )
+        .unwrap();
+        assert_eq!(oldest.list(), ["a"]);
+    }
+
+    #[test]
+    fn expired_returns_entries_past_their_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("old", "1").unwrap();
+        kn.set("new", "2").unwrap();
+        kn.set("none", "3").unwrap();
+        kn.set_expiry("old", Some(Utc::now() - chrono::TimeDelta::days(1)))
+            .unwrap();
+        kn.set_expiry("new", Some(Utc::now() + chrono::TimeDelta::days(1)))
+            .unwrap();
+        assert!(kn.set_expiry("missing", None).is_err());
+        kn.save().unwrap();
+
+        let mut kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        let expired: Vec<_> = kn.expired().unwrap().iter().map(|e| e.key()).collect();
+        assert_eq!(expired, ["old"]);
+        kn.set_expiry("old", None).unwrap();
+        assert!(kn.expired().unwrap().is_empty());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn dpapi_binding_needs_windows() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let err = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            InitOptions::new(kdf).with_dpapi(true),
+        )
+        .err()
+        .unwrap();
+        assert!(
+            err.to_string().contains("only available on Windows"),
+            "{err}"
+        );
+        assert!(!storage.exists());
+
+        // A keystore bound elsewhere is refused before the KDF runs.
+        let key = crypto::derive_key("pw", &[1u8; 16], kdf).unwrap();
+        let file = payload::encrypt(
+            &Store::new(),
+            kdf,
+            Algorithm::XChaCha20Poly1305,
+            vec![1u8; 16],
+            PayloadEncoding::default(),
+            payload::KeyBinding {
+                dpapi_blob: Some(vec![9u8; 40]),
+                keyfile: false,
+                keyslots: Vec::new(),
+                decoy: None,
+            },
+            None,
+            &key,
+        )
+        .unwrap();
+        storage.save(&serialize(&file).unwrap()).unwrap();
+        let info = Keynest::inspect_header(&storage).unwrap();
+        assert!(info.dpapi_bound());
+        assert!(info.to_string().contains("Windows account (DPAPI)"));
+        let err = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage)
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("bound to a Windows account"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn totp_entries_generate_codes() {
+        let dir = tempdir().unwrap();
+        let clock = Arc::new(FixedClock::new(DateTime::from_timestamp(59, 0).unwrap()));
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(dir.path().join("keynest.db")),
+            InitOptions::new(KdfParams::new(8, 1, 1).unwrap()).with_clock(clock.clone()),
+        )
+        .unwrap();
+
+        // The RFC 6238 SHA-1 seed, "12345678901234567890".
+        let totp = Totp::new("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        kn.set_totp("github", &OtpAuth::from_totp("GitHub", None, &totp))
+            .unwrap();
+        assert_eq!(kn.kind("github").unwrap(), Some(EntryKind::Totp));
+
+        let code = kn.totp("github").unwrap().unwrap();
+        assert_eq!((code.code(), code.remaining_secs()), ("287082", 1));
+        clock.set(DateTime::from_timestamp(1111111109, 0).unwrap());
+        assert_eq!(kn.totp("github").unwrap().unwrap().code(), "081804");
+        assert!(kn.totp("missing").unwrap().is_none());
+
+        assert!(kn.update("github", "hunter2").is_err());
+        let hotp = OtpAuth::parse("otpauth://hotp/x?secret=JBSWY3DP&counter=0").unwrap();
+        assert!(kn.set_totp("hotp", &hotp).is_err());
+    }
+
+    #[test]
+    fn leases_warn_block_and_expire() {
+        let dir = tempdir().unwrap();
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let clock = Arc::new(FixedClock::new(start));
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(dir.path().join("keynest.db")),
+            InitOptions::new(KdfParams::new(8, 1, 1).unwrap()).with_clock(clock.clone()),
+        )
+        .unwrap();
+        kn.set("db/admin", "hunter2").unwrap();
+        let hour = chrono::TimeDelta::hours(1);
+
+        assert!(kn.lease("missing", "alice", hour, false).is_err());
+        assert!(
+            kn.lease("db/admin", "alice", hour, false)
+                .unwrap()
+                .is_empty()
+        );
+        // A shared lease is granted with the other holders to warn about.
+        let others = kn.lease("db/admin", "bob", hour * 2, false).unwrap();
+        assert_eq!(others.len(), 1);
+        assert_eq!(others[0].holder(), "alice");
+        let err = kn.lease("db/admin", "carol", hour, true).unwrap_err();
+        let conflict = err.downcast_ref::<LeaseConflict>().unwrap();
+        assert_eq!(conflict.leases().len(), 2);
+
+        // Alice's lease expires; Bob renews his own lease exclusively.
+        clock.set(start + hour);
+        assert_eq!(kn.leases().unwrap()["db/admin"].len(), 1);
+        assert!(kn.lease("db/admin", "bob", hour, true).unwrap().is_empty());
+        assert!(
+            kn.lease("db/admin", "alice", hour, false)
+                .unwrap_err()
+                .is::<LeaseConflict>()
+        );
+
+        assert_eq!(kn.release_lease("db/admin", Some("alice")).unwrap(), 0);
+        assert_eq!(kn.release_lease("db/admin", Some("bob")).unwrap(), 1);
+        assert!(kn.leases().unwrap().is_empty());
+        assert!(kn.setting::<Leases>().unwrap().is_none());
+
+        kn.lease("db/admin", "alice", hour, false).unwrap();
+        kn.lease("db/admin", "bob", hour, false).unwrap();
+        assert_eq!(kn.release_lease("db/admin", None).unwrap(), 2);
+
+        // Leases follow renames and are dropped with their entry on save.
+        kn.lease("db/admin", "alice", hour, false).unwrap();
+        kn.rename("db/admin", "db/root", false).unwrap();
+        assert!(kn.leases().unwrap().contains_key("db/root"));
+        kn.remove("db/root").unwrap();
+        kn.save().unwrap();
+        assert!(kn.setting::<Leases>().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_receipts_record_the_latest_reader() {
+        let dir = tempdir().unwrap();
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let clock = Arc::new(FixedClock::new(start));
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(dir.path().join("keynest.db")),
+            InitOptions::new(KdfParams::new(8, 1, 1).unwrap()).with_clock(clock.clone()),
+        )
+        .unwrap();
+        kn.set("db/admin", "hunter2").unwrap();
+        kn.set("db/replica", "hunter3").unwrap();
+
+        // Off by default: nothing is recorded.
+        kn.record_get("db/admin").unwrap();
+        assert!(!kn.records_reads().unwrap());
+        assert!(kn.read_receipts().unwrap().is_none());
+
+        kn.set_read_receipts(true).unwrap();
+        assert!(kn.records_reads().unwrap());
+        kn.record_get("db/admin").unwrap();
+        kn.set_reader("alice@laptop");
+        kn.record_get("db/replica").unwrap();
+        clock.set(start + chrono::TimeDelta::minutes(5));
+        kn.set_reader("bob@desktop");
+        kn.record_get("db/admin").unwrap();
+
+        let receipts = kn.read_receipts().unwrap().unwrap();
+        assert_eq!(receipts["db/admin"].reader(), "bob@desktop");
+        assert_eq!(receipts["db/admin"].read(), "2023-11-14T22:18:20Z");
+        assert_eq!(receipts["db/replica"].reader(), "alice@laptop");
+
+        // Receipts follow renames and are dropped with their entry on save.
+        kn.rename("db/admin", "db/root", false).unwrap();
+        kn.remove("db/replica").unwrap();
+        kn.save().unwrap();
+        let receipts = kn.read_receipts().unwrap().unwrap();
+        assert_eq!(receipts.keys().collect::<Vec<_>>(), ["db/root"]);
+
+        kn.set_read_receipts(false).unwrap();
+        assert!(kn.read_receipts().unwrap().is_none());
+    }
+
+    #[test]
+    fn recipe_entries_store_the_recipe_not_the_password() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            InitOptions::new(KdfParams::new(8, 1, 1).unwrap()),
+        )
+        .unwrap();
+
+        let recipe = Recipe::new("example.com").unwrap().with_counter(2).unwrap();
+        kn.set_recipe("derive/example.com", &recipe).unwrap();
+        kn.save().unwrap();
+        let kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert_eq!(
+            kn.kind("derive/example.com").unwrap(),
+            Some(EntryKind::Recipe)
+        );
+        let stored = Recipe::parse(kn.get("derive/example.com").unwrap().unwrap()).unwrap();
+        assert_eq!(stored, recipe);
+
+        let mut kn = kn;
+        assert!(kn.update("derive/example.com", "hunter2").is_err());
+        kn.update(
+            "derive/example.com",
+            &recipe.with_counter(3).unwrap().to_uri(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn injected_clock_and_entropy_are_used() {
+        #[derive(Debug, Default)]
+        struct Audit(std::sync::Mutex<Vec<EntropyUse>>);
+
+        impl EntropySource for Audit {
+            fn fill(&self, purpose: EntropyUse, buf: &mut [u8]) -> Result<()> {
+                self.0.lock().unwrap().push(purpose);
+                OsEntropy.fill(purpose, buf)
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let start = DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let clock = Arc::new(FixedClock::new(start));
+        let audit = Arc::new(Audit::default());
+
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            InitOptions::new(KdfParams::new(8, 1, 1).unwrap())
+                .with_clock(clock.clone())
+                .with_entropy_source(audit.clone()),
+        )
+        .unwrap();
+        assert_eq!(
+            *audit.0.lock().unwrap(),
+            [EntropyUse::Salt, EntropyUse::Nonce]
+        );
+        assert_eq!(kn.info().unwrap().creation_date(), "2030-01-01T00:00:00Z");
+
+        kn.set("a", "1").unwrap();
+        kn.set_expiry("a", Some(start + chrono::TimeDelta::hours(1)))
+            .unwrap();
+        assert!(kn.expired().unwrap().is_empty());
+        clock.advance(chrono::TimeDelta::hours(2));
+        assert_eq!(kn.expired().unwrap().len(), 1);
+        assert_eq!(
+            kn.store().unwrap().entries().next().unwrap().updated(),
+            "2030-01-01T00:00:00Z"
+        );
+        kn.save().unwrap();
+        let uses = audit.0.lock().unwrap();
+        assert!(uses.len() > 2 && uses[2..].iter().all(|u| *u == EntropyUse::Nonce));
+        drop(uses);
+
+        // A reopened keystore uses the system clock again, for which 2030 is ahead.
+        let kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert!(kn.expired().unwrap().is_empty());
+    }
+
+    #[test]
+    fn autotype_resolves_login_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage,
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("bank/username", "alice").unwrap();
+        kn.set("bank/password", "ref:shared/pw").unwrap();
+        kn.set("shared/pw", "s3").unwrap();
+        kn.set("token", "t0k").unwrap();
+
+        let texts = |actions: &[Action]| -> Vec<String> {
+            actions
+                .iter()
+                .map(|a| match a {
+                    Action::Text(text) => text.to_string(),
+                    Action::Key(key) => format!("{key:?}"),
+                    Action::Delay(_) => "delay".to_string(),
+                })
+                .collect()
+        };
+
+        let (actions, keys) = kn.autotype("bank", None).unwrap();
+        assert_eq!(texts(&actions), ["alice", "Tab", "s3", "Enter"]);
+        assert_eq!(keys, ["bank/username", "bank/password"]);
+
+        kn.set_autotype_sequence("bank", Some("{PASSWORD}!"))
+            .unwrap();
+        let (actions, _) = kn.autotype("bank", None).unwrap();
+        assert_eq!(texts(&actions), ["s3", "!"]);
+
+        let (actions, _) = kn.autotype("token", Some("{PASSWORD}")).unwrap();
+        assert_eq!(texts(&actions), ["t0k"]);
+        assert!(kn.autotype("token", None).is_err());
+        assert!(kn.set_autotype_sequence("bank", Some("{TAB")).is_err());
+
+        kn.set("github", "x").unwrap();
+        kn.set_field("github", "user", "bob").unwrap();
+        kn.set_field("github", "password", "hunter2").unwrap();
+        let (actions, keys) = kn.autotype("github", None).unwrap();
+        assert_eq!(texts(&actions), ["bob", "Tab", "hunter2", "Enter"]);
+        assert_eq!(keys, ["github"]);
+    }
+
+    #[test]
+    fn pins_persist_and_follow_removals() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("a", "1").unwrap();
+        kn.set("b", "2").unwrap();
+        assert!(kn.pin("missing").is_err());
+        assert!(kn.pin("b").unwrap());
+        assert!(kn.pin("a").unwrap());
+        assert!(!kn.pin("a").unwrap());
+        kn.save().unwrap();
+
+        let indexed =
+            IndexedKeynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone())
+                .unwrap();
+        assert_eq!(
+            indexed.pinned().unwrap(),
+            BTreeSet::from(["a".into(), "b".into()])
+        );
+
+        let mut kn =
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone()).unwrap();
+        assert!(kn.unpin("b").unwrap());
+        assert!(!kn.unpin("b").unwrap());
+        kn.remove("a").unwrap();
+        kn.save().unwrap();
+
+        let kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert!(kn.pinned().unwrap().is_empty());
+        assert!(kn.setting::<Pinned>().unwrap().is_none());
+    }
+
+    #[test]
+    fn rename_prefix_moves_pins_usage_and_sequences() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("bank/username", "alice").unwrap();
+        kn.set("bank/password", "s3").unwrap();
+        kn.set("card", "ref:bank/password").unwrap();
+        kn.pin("bank/password").unwrap();
+        kn.set_usage_tracking(true).unwrap();
+        kn.record_get("bank/username").unwrap();
+        kn.set_autotype_sequence("bank", Some("{PASSWORD}"))
+            .unwrap();
+
+        let summary = kn.rename_prefix("bank/", "finance/bank/", false).unwrap();
+        assert_eq!(summary.renamed().len(), 2);
+        kn.save().unwrap();
+
+        let kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert_eq!(kn.get("card").unwrap(), Some("ref:finance/bank/password"));
+        assert_eq!(
+            kn.pinned().unwrap(),
+            BTreeSet::from(["finance/bank/password".into()])
+        );
+        let usage = kn.usage().unwrap().unwrap();
+        assert_eq!(usage.entry("finance/bank/username").unwrap().gets(), 1);
+        assert_eq!(
+            kn.autotype_sequences().unwrap(),
+            BTreeMap::from([("finance/bank".into(), "{PASSWORD}".into())])
+        );
+    }
 
-    Ok(Storage::new(path))
-}
+    #[test]
+    fn find_matches_keys_and_optionally_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
 
-/// Information about a keystore.
-///
-/// Returned by [`Keynest::info`].
-#[derive(Serialize)]
-pub struct StoreInfo {
-    path: PathBuf,
-    file_size: u64,
-    creation_date: String,
-    secrets_count: usize,
-    kdf: KdfParams,
-    algorithm: &'static str,
-    nonce_len: usize,
-    version: u8,
-}
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage,
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("prod/app/db_password", "s3").unwrap();
+        kn.set("prod/app/api_key", "db_token").unwrap();
+        kn.set("dev/app/db_password", "x").unwrap();
 
-impl StoreInfo {
-    /// Returns the keystore creation date.
-    pub fn creation_date(&self) -> &str {
-        &self.creation_date
+        let keys = |matcher: &Matcher| -> Vec<String> {
+            kn.find(matcher)
+                .unwrap()
+                .map(|e| e.key().to_string())
+                .collect()
+        };
+        let glob = Matcher::auto("prod/*/db_*", false).unwrap();
+        assert_eq!(keys(&glob), ["prod/app/db_password"]);
+        let regex = Matcher::new("db_", MatchMode::Regex, false).unwrap();
+        assert_eq!(
+            keys(&regex),
+            ["dev/app/db_password", "prod/app/db_password"]
+        );
+        assert_eq!(
+            keys(&regex.with_values(true)),
+            [
+                "dev/app/db_password",
+                "prod/app/api_key",
+                "prod/app/db_password"
+            ]
+        );
     }
 
-    /// Returns the number of secrets stored.
-    pub fn secrets_count(&self) -> usize {
-        self.secrets_count
+    #[test]
+    fn lookup_finds_entries_by_attributes() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage,
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("mail/alice", "a").unwrap();
+        kn.set("mail/bob", "b").unwrap();
+        kn.set("imap", "c").unwrap();
+        kn.set_field("imap", "url", "imaps://mail.example.org")
+            .unwrap();
+
+        let keys = |attributes: &[(&str, &str)]| -> Vec<String> {
+            kn.lookup(attributes)
+                .unwrap()
+                .map(|e| e.key().to_string())
+                .collect()
+        };
+        assert_eq!(keys(&[("service", "mail")]), ["mail/alice", "mail/bob"]);
+        assert_eq!(
+            keys(&[("service", "mail"), ("account", "bob")]),
+            ["mail/bob"]
+        );
+        assert_eq!(keys(&[("host", "MAIL.example.org")]), ["imap"]);
+        assert!(keys(&[("service", "mail"), ("protocol", "imap")]).is_empty());
     }
 
-    /// Returns the KDF parameters used for key derivation.
-    pub fn kdf(&self) -> &KdfParams {
-        &self.kdf
+    #[test]
+    fn list_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+
+        assert!(kn.list().contains(&&"A".to_string()));
+        assert!(!kn.list().contains(&&"B".to_string()));
     }
 
-    /// Returns the encryption algorithm.
-    pub fn algorithm(&self) -> &'static str {
-        self.algorithm
+    #[test]
+    fn list_all_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        for sec_entry in kn.list_all().unwrap() {
+            assert_eq!(sec_entry.key(), "A");
+            assert_eq!(sec_entry.value(), "B");
+            assert_ne!(sec_entry.updated(), "");
+        }
+    }
+
+    #[test]
+    fn open_with_key_reuses_the_derived_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.save().unwrap();
+        let key = kn.unlock_key().unwrap();
+
+        let kn = Keynest::open_with_key(&key, storage.clone()).unwrap();
+        assert_eq!(kn.get("A").unwrap(), Some("B"));
+        let mut indexed = IndexedKeynest::open_with_key(&key, storage.clone()).unwrap();
+        assert_eq!(indexed.get("A").unwrap(), Some("B"));
+
+        let mut kn = Keynest::open_with_key(&key, storage.clone()).unwrap();
+        kn.rekey(Zeroizing::new("new".to_string()), KdfParams::default())
+            .unwrap();
+        assert!(Keynest::open_with_key(&key, storage).is_err());
+    }
+
+    #[test]
+    fn lock_wipes_the_secrets_until_unlock() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.save().unwrap();
+
+        kn.lock();
+        assert!(kn.is_locked());
+        assert_eq!(kn.get("A").unwrap(), None);
+        assert!(kn.save().unwrap_err().is::<Locked>());
+        assert!(kn.unlock_key().is_err_and(|e| e.is::<Locked>()));
+
+        // Changes saved elsewhere while locked are picked up.
+        let mut other =
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        other.set("C", "D").unwrap();
+        other.save().unwrap();
+
+        assert!(kn.unlock(Zeroizing::new("wrong".to_string())).is_err());
+        assert!(kn.is_locked());
+        kn.unlock(Zeroizing::new("pw".to_string())).unwrap();
+        assert!(!kn.is_locked());
+        assert_eq!(kn.get("A").unwrap(), Some("B"));
+        assert_eq!(kn.get("C").unwrap(), Some("D"));
+        kn.set("E", "F").unwrap();
+        kn.save().unwrap();
+    }
+
+    #[test]
+    fn transaction_saves_once_or_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let open = || Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone());
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.transaction(|tx| {
+            tx.set("A", "1")?;
+            tx.set("B", "2")?;
+            tx.set_field("A", "user", "alice")
+        })
+        .unwrap();
+        assert_eq!(open().unwrap().get("B").unwrap(), Some("2"));
+
+        let err = kn
+            .transaction(|tx| {
+                tx.remove("A")?;
+                tx.update("B", "3")?;
+                tx.set("B", "4")
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err}");
+        assert_eq!(kn.get("A").unwrap(), Some("1"));
+        assert_eq!(kn.get("B").unwrap(), Some("2"));
+
+        assert!(kn.transaction(|tx| tx.save()).is_err());
+        assert!(kn.transaction(|tx| tx.transaction(|_| Ok(()))).is_err());
+        let reopened = open().unwrap();
+        assert_eq!(reopened.get("A").unwrap(), Some("1"));
+        assert_eq!(reopened.fields("A").unwrap().unwrap()["user"], "alice");
+    }
+
+    #[test]
+    fn autosave_saves_each_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let open = || Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone());
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "1").unwrap();
+        assert_eq!(open().unwrap().get("A").unwrap(), None);
+
+        kn.set_autosave(true);
+        kn.set("B", "2").unwrap();
+        assert_eq!(open().unwrap().get("A").unwrap(), Some("1"));
+        kn.rename_prefix("B", "C", false).unwrap();
+        kn.pin("C").unwrap();
+        let reopened = open().unwrap();
+        assert_eq!(reopened.get("C").unwrap(), Some("2"));
+        assert!(reopened.pinned().unwrap().contains("C"));
+
+        // A failed change saves nothing.
+        assert!(kn.set("C", "3").is_err());
+        assert_eq!(open().unwrap().get("C").unwrap(), Some("2"));
+    }
+
+    #[test]
+    fn rekey_changes_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("old".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.save().unwrap();
+
+        //reopen
+        let mut kn =
+            Keynest::open_with_storage(Zeroizing::new("old".to_string()), storage.clone()).unwrap();
+
+        //rekey
+        kn.rekey(Zeroizing::new("new".to_string()), KdfParams::default())
+            .unwrap();
+
+        assert!(
+            Keynest::open_with_storage(Zeroizing::new("old".to_string()), storage.clone()).is_err()
+        );
+
+        let kn2 = Keynest::open_with_storage(Zeroizing::new("new".to_string()), storage).unwrap();
+
+        assert_eq!(kn2.get("A").unwrap(), Some("B"));
+    }
+
+    #[test]
+    fn keyfile_is_required_until_rekeyed_away() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let pw = || Zeroizing::new("pw".to_string());
+        let keyfile = Keyfile::from_bytes(b"keyfile contents");
+
+        let mut kn = Keynest::init_with_options(
+            pw(),
+            storage.clone(),
+            InitOptions::new(kdf).with_keyfile(Some(keyfile.clone())),
+        )
+        .unwrap();
+        kn.set("A", "B").unwrap();
+        kn.save().unwrap();
+        assert!(
+            Keynest::inspect_header(&storage)
+                .unwrap()
+                .requires_keyfile()
+        );
+
+        let err = Keynest::open_with_storage(pw(), storage.clone())
+            .err()
+            .unwrap();
+        assert!(err.is::<MissingKeyfile>(), "{err}");
+        let wrong = Keyfile::from_bytes(b"other contents");
+        assert!(Keynest::open_with_keyfile(pw(), &wrong, storage.clone()).is_err());
+
+        // Locking and rekeying keep the keyfile.
+        let mut kn = Keynest::open_with_keyfile(pw(), &keyfile, storage.clone()).unwrap();
+        assert!(kn.info().unwrap().requires_keyfile());
+        kn.lock();
+        kn.unlock(pw()).unwrap();
+        kn.rekey(Zeroizing::new("new".to_string()), kdf).unwrap();
+        assert!(
+            Keynest::open_with_storage(Zeroizing::new("new".to_string()), storage.clone()).is_err()
+        );
+        let mut kn = Keynest::open_with_keyfile(
+            Zeroizing::new("new".to_string()),
+            &keyfile,
+            storage.clone(),
+        )
+        .unwrap();
+
+        kn.rekey_with_keyfile(pw(), kdf, None).unwrap();
+        let kn = Keynest::open_with_storage(pw(), storage.clone()).unwrap();
+        assert_eq!(kn.get("A").unwrap(), Some("B"));
+        assert!(!kn.info().unwrap().requires_keyfile());
+
+        // A keyfile given for a store without one is ignored.
+        assert!(Keynest::open_with_keyfile(pw(), &keyfile, storage).is_ok());
+    }
+
+    #[test]
+    fn keyslots_let_several_passwords_open_the_store() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let pw = |s: &str| Zeroizing::new(s.to_string());
+        let open = |s: &str| Keynest::open_with_storage(pw(s), storage.clone());
+
+        let mut kn = Keynest::init_with_storage_and_kdf(pw("owner"), storage.clone(), kdf).unwrap();
+        kn.set("A", "B").unwrap();
+        kn.save().unwrap();
+        assert!(kn.keyslots().is_empty());
+
+        // The first keyslot moves the current password to the default one.
+        kn.add_keyslot("alice", pw("alice-pw"), kdf).unwrap();
+        assert_eq!(kn.opened_keyslot(), Some(format::DEFAULT_KEYSLOT));
+        assert!(kn.add_keyslot("alice", pw("x"), kdf).is_err());
+        assert!(kn.add_keyslot("bad name", pw("x"), kdf).is_err());
+        let info = Keynest::inspect_header(&storage).unwrap();
+        assert_eq!(info.keyslots(), ["default", "alice"]);
+        assert!(
+            info.to_string()
+                .contains("Keyslots:          default, alice")
+        );
+
+        let mut kn = open("alice-pw").unwrap();
+        assert_eq!(kn.get("A").unwrap(), Some("B"));
+        assert_eq!(kn.opened_keyslot(), Some("alice"));
+        let err = open("wrong").err().unwrap();
+        assert!(err.is::<NoMatchingKeyslot>(), "{err}");
+
+        // Rekeying rewraps only the slot that was opened; the data key stays.
+        let key = kn.unlock_key().unwrap();
+        kn.rekey(pw("alice-new"), kdf).unwrap();
+        assert!(open("alice-pw").is_err());
+        assert_eq!(open("alice-new").unwrap().get("A").unwrap(), Some("B"));
+        assert_eq!(open("owner").unwrap().get("A").unwrap(), Some("B"));
+        assert!(Keynest::open_with_key(&key, storage.clone()).is_ok());
+        assert!(kn.rekey_with_keyfile(pw("x"), kdf, None).is_err());
+
+        // Removing a slot keeps the others; the last one cannot be removed.
+        assert!(kn.remove_keyslot("default").unwrap());
+        assert!(!kn.remove_keyslot("default").unwrap());
+        assert!(open("owner").is_err());
+        assert!(kn.remove_keyslot("alice").is_err());
+        let kn = open("alice-new").unwrap();
+        assert_eq!(kn.keyslots().len(), 1);
+        assert_eq!(kn.get("A").unwrap(), Some("B"));
     }
 
-    /// Returns the nonce length in bytes.
-    pub fn nonce_len(&self) -> usize {
-        self.nonce_len
+    #[test]
+    fn rekey_changes_kdf_parameters() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+
+        let original_kdf = KdfParams::default();
+
+        // init
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            original_kdf,
+        )
+        .unwrap();
+
+        kn.save().unwrap();
+
+        // reopen
+        let mut kn =
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone()).unwrap();
+
+        // neue Parameter
+        let KdfParams::Argon2id(original) = original_kdf else {
+            panic!("the default KDF is Argon2id");
+        };
+        let new_kdf = KdfParams::new(
+            original.mem_cost_kib() * 2,
+            original.time_cost() + 1,
+            original.parallelism(),
+        )
+        .unwrap();
+
+        kn.rekey(Zeroizing::new("pw".to_string()), new_kdf).unwrap();
+
+        // reopen mit neuem password
+        let kn2 = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+
+        assert_eq!(*kn2.keystore_file.kdf(), new_kdf);
     }
 
-    /// Returns the format version.
-    pub fn version(&self) -> u8 {
-        self.version
-    }
+    #[test]
+    fn attachments_survive_rekey() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
 
-    /// Returns the file size in bytes.
-    pub fn file_size(&self) -> u64 {
-        self.file_size
-    }
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("old".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        kn.set("tls", "x").unwrap();
+        kn.attach("tls", "ca.pem", &b"certificate"[..]).unwrap();
+        kn.save().unwrap();
 
-    /// Returns the file path.
-    pub fn path(&self) -> &PathBuf {
-        &self.path
-    }
-}
+        kn.rekey(Zeroizing::new("new".to_string()), KdfParams::default())
+            .unwrap();
 
-fn format_size(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
+        let kn = Keynest::open_with_storage(Zeroizing::new("new".to_string()), storage).unwrap();
+        assert_eq!(
+            *kn.read_attachment("tls", "ca.pem").unwrap(),
+            b"certificate"
+        );
+        assert_eq!(kn.attachments("tls").unwrap().unwrap()["ca.pem"].size(), 11);
 
-    if bytes >= GB {
-        format!("{:.1} GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.1} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.1} KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{} bytes", bytes)
+        let mut streamed = Vec::new();
+        kn.attachment_reader("tls", "ca.pem")
+            .unwrap()
+            .read_to_end(&mut streamed)
+            .unwrap();
+        assert_eq!(streamed, b"certificate");
     }
-}
 
-/// Writes the Location, Encryption, and Key Derivation sections shared by the
-/// [`StoreInfo`] and [`HeaderInfo`] displays.
-fn write_header_sections(
-    f: &mut std::fmt::Formatter<'_>,
-    path: &std::path::Path,
-    file_size: u64,
-    version: u8,
-    algorithm: &str,
-    nonce_len: usize,
-    kdf: &KdfParams,
-) -> std::fmt::Result {
-    writeln!(f, "Location")?;
-    writeln!(f, "  Path:              {}", path.display())?;
-    writeln!(f, "  Size:              {}", format_size(file_size))?;
-    writeln!(f, "  Format version:    {}", version)?;
-    writeln!(f)?;
+    #[test]
+    fn plans_create_missing_entries_or_nothing() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let mut kn =
+            Keynest::init_with_storage_and_kdf(Zeroizing::new("pw".to_string()), storage, kdf)
+                .unwrap();
+        kn.set("existing", "not a key").unwrap();
 
-    writeln!(f, "Encryption")?;
-    writeln!(f, "  Algorithm:         {}", algorithm)?;
-    writeln!(f, "  Nonce length:      {} bytes", nonce_len)?;
-    writeln!(f)?;
+        let plan = Plan::from_yaml(
+            "
+            steps:
+              - {key: web/cert, ssh-cert: {ca: ca, subject: web/key, identity: web, principals: [web]}}
+              - {key: ca, ssh-ca: {}}
+              - {key: web/key, ssh-key: {comment: web}}
+              - {key: alias, ref: existing}
+              - {key: existing, value: replaced?}
+            ",
+        )
+        .unwrap();
+        let outcomes = kn.apply_plan(&plan).unwrap();
+        assert_eq!(outcomes.len(), 5);
+        assert!(outcomes.contains(&("existing".to_string(), StepOutcome::Kept)));
+        assert_eq!(kn.get("existing").unwrap(), Some("not a key"));
+        assert_eq!(kn.resolve("alias").unwrap(), Some("not a key"));
+        assert!(SshCertificateInfo::is_certificate(
+            kn.get("web/cert").unwrap().unwrap()
+        ));
+        assert!(
+            kn.fields("ca").unwrap().unwrap()[plan::PUBLIC_KEY_FIELD].starts_with("ssh-ed25519 ")
+        );
 
-    writeln!(f, "Key Derivation")?;
-    writeln!(f, "  Memory:            {} KiB", kdf.mem_cost_kib())?;
-    writeln!(f, "  Time cost:         {}", kdf.time_cost())?;
-    writeln!(f, "  Parallelism:       {}", kdf.parallelism())
-}
+        // A failing step undoes the steps before it.
+        let failing = Plan::from_yaml(
+            "
+            steps:
+              - {key: new, value: x}
+              - {key: bad/cert, ssh-cert: {ca: ca, subject: existing, identity: x, principals: [x]}}
+            ",
+        )
+        .unwrap();
+        let err = kn.apply_plan(&failing).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("step 'bad/cert' failed"),
+            "{err:#}"
+        );
+        assert_eq!(kn.get("new").unwrap(), None);
 
-impl std::fmt::Display for StoreInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Keynest Store Information")?;
-        writeln!(f, "────────────────────────────────────────")?;
-        writeln!(f)?;
+        let missing = Plan::from_yaml("steps:\n  - {key: b, ref: nowhere}\n").unwrap();
+        assert!(kn.apply_plan(&missing).is_err());
+    }
 
-        write_header_sections(
-            f,
-            &self.path,
-            self.file_size,
-            self.version,
-            self.algorithm,
-            self.nonce_len,
-            &self.kdf,
-        )?;
-        writeln!(f)?;
+    #[test]
+    fn counters_only_count_up() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keynest.db");
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(path.clone()),
+            kdf,
+        )
+        .unwrap();
+        kn.set_counter("ca/serial", 41).unwrap();
+        kn.set("plain", "7").unwrap();
 
-        writeln!(f, "Metadata")?;
-        writeln!(f, "  Created:           {}", self.creation_date)?;
-        writeln!(f, "  Secrets stored:    {}", self.secrets_count)
-    }
-}
+        assert_eq!(kn.next_counter("ca/serial").unwrap(), 42);
+        assert_eq!(kn.next_counter("ca/serial").unwrap(), 43);
+        assert!(kn.next_counter("plain").is_err());
+        assert!(kn.next_counter("missing").is_err());
+        assert!(kn.update("ca/serial", "10").is_err());
+        kn.update("ca/serial", "100").unwrap();
+        assert_eq!(kn.counter("ca/serial").unwrap(), Some(100));
 
-/// Keystore metadata read from the unencrypted file header (no password required).
-///
-/// Returned by [`Keynest::inspect_header`]. Contains everything [`StoreInfo`] does except
-/// the encrypted `creation_date` and `secrets_count`.
-#[derive(Serialize)]
-pub struct HeaderInfo {
-    path: PathBuf,
-    file_size: u64,
-    version: u8,
-    algorithm: &'static str,
-    nonce_len: usize,
-    kdf: KdfParams,
-}
+        // Each value is saved before it is returned.
+        kn.next_counter("ca/serial").unwrap();
+        let reopened =
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), Storage::new(path))
+                .unwrap();
+        assert_eq!(reopened.counter("ca/serial").unwrap(), Some(101));
+        assert_eq!(
+            reopened.kind("ca/serial").unwrap(),
+            Some(EntryKind::Counter)
+        );
 
-impl HeaderInfo {
-    /// Returns the file path.
-    pub fn path(&self) -> &PathBuf {
-        &self.path
+        kn.set_counter("max", u64::MAX).unwrap();
+        assert!(kn.next_counter("max").is_err());
     }
 
-    /// Returns the file size in bytes.
-    pub fn file_size(&self) -> u64 {
-        self.file_size
-    }
+    #[test]
+    fn aes_gcm_stores_stay_aes_across_saves_and_rekeys() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            InitOptions::new(kdf).with_algorithm(Algorithm::Aes256Gcm),
+        )
+        .unwrap();
+        kn.set("db", "s3cret").unwrap();
+        kn.attach("db", "ca.pem", &b"certificate"[..]).unwrap();
+        kn.save().unwrap();
+        kn.rekey(Zeroizing::new("new".to_string()), kdf).unwrap();
 
-    /// Returns the format version.
-    pub fn version(&self) -> u8 {
-        self.version
+        let reopened =
+            Keynest::open_with_storage(Zeroizing::new("new".to_string()), storage.clone()).unwrap();
+        assert_eq!(reopened.info().unwrap().algorithm(), "AES-256-GCM");
+        assert_eq!(reopened.get("db").unwrap(), Some("s3cret"));
+        assert_eq!(
+            &*reopened.read_attachment("db", "ca.pem").unwrap(),
+            b"certificate"
+        );
+        assert_eq!(
+            format::inspect(&storage.load().unwrap())
+                .unwrap()
+                .nonce_len(),
+            12
+        );
+        assert_eq!(
+            Algorithm::from_name("aes256-gcm"),
+            Some(Algorithm::Aes256Gcm)
+        );
+        assert_eq!(
+            Algorithm::from_name("XChaCha20-Poly1305"),
+            Some(Algorithm::XChaCha20Poly1305)
+        );
     }
 
-    /// Returns the encryption algorithm.
-    pub fn algorithm(&self) -> &'static str {
-        self.algorithm
-    }
+    #[test]
+    fn access_policies_follow_references_and_persist() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keynest.db");
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(path.clone()),
+            kdf,
+        )
+        .unwrap();
+        kn.set("prod/db", "hunter2").unwrap();
+        kn.set("app/db", "ref:prod/db").unwrap();
+        kn.set_policy("prod/db", AccessPolicy::default().with_no_print(true))
+            .unwrap();
+        kn.set_policy("app/db", AccessPolicy::default().with_confirm(true))
+            .unwrap();
+        assert!(kn.set_policy("missing", AccessPolicy::default()).is_err());
 
-    /// Returns the nonce length in bytes.
-    pub fn nonce_len(&self) -> usize {
-        self.nonce_len
-    }
+        // Reading through the reference picks up the restrictions of both entries.
+        let effective = kn.effective_policy("app/db").unwrap().unwrap();
+        assert!(effective.no_print() && effective.confirm());
+        assert!(!kn.policy("app/db").unwrap().unwrap().no_print());
+        assert_eq!(kn.effective_policy("missing").unwrap(), None);
+        kn.save().unwrap();
 
-    /// Returns the KDF parameters used for key derivation.
-    pub fn kdf(&self) -> &KdfParams {
-        &self.kdf
+        let mut indexed =
+            IndexedKeynest::open_with_storage(Zeroizing::new("pw".to_string()), Storage::new(path))
+                .unwrap();
+        assert_eq!(indexed.effective_policy("app/db").unwrap(), Some(effective));
+        let own = indexed.effective_policy("prod/db").unwrap().unwrap();
+        assert!(own.no_print() && !own.confirm());
     }
-}
 
-impl std::fmt::Display for HeaderInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Keynest Store Information (header only)")?;
-        writeln!(f, "────────────────────────────────────────")?;
-        writeln!(f)?;
+    #[test]
+    fn tampered_headers_fail_authentication() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keynest.db");
+        let kdf = KdfParams::new(16, 1, 1).unwrap();
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            Storage::new(path.clone()),
+            kdf,
+        )
+        .unwrap();
+        kn.set("db/password", "hunter2").unwrap();
+        kn.save().unwrap();
+        let original = std::fs::read(&path).unwrap();
+        let open = || {
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), Storage::new(path.clone()))
+        };
+        assert!(open().is_ok());
 
-        write_header_sections(
-            f,
-            &self.path,
-            self.file_size,
-            self.version,
-            self.algorithm,
-            self.nonce_len,
-            &self.kdf,
-        )?;
-        writeln!(f)?;
+        // magic (4) | version (1) | header length (4) | KDF TLV: type (1), length (2),
+        // then the Argon2id memory cost as the first u32.
+        assert_eq!(&original[12..16], &16u32.to_le_bytes());
+        let mut downgraded = original.clone();
+        downgraded[12..16].copy_from_slice(&8u32.to_le_bytes());
+        std::fs::write(&path, &downgraded).unwrap();
+        assert!(
+            open().is_err(),
+            "a lowered Argon2 memory cost must not open"
+        );
 
-        writeln!(
-            f,
-            "Note: run `keynest info` (with the password) to see creation date and secret count."
-        )
+        let mut reversioned = original.clone();
+        reversioned[4] = 2;
+        std::fs::write(&path, &reversioned).unwrap();
+        assert!(open().is_err(), "a changed version byte must not open");
+
+        std::fs::write(&path, &original).unwrap();
+        assert_eq!(open().unwrap().get("db/password").unwrap(), Some("hunter2"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use tempfile::tempdir;
+    #[test]
+    fn journal_appends_changes_until_compacted() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let journal = storage.journal_path();
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let open = || {
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone()).unwrap()
+        };
 
-    use super::*;
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            kdf,
+        )
+        .unwrap();
+        for i in 0..600 {
+            kn.set(&format!("app/key{i:03}"), &"v".repeat(100)).unwrap();
+        }
+        kn.set_journal(true).unwrap();
+        kn.save().unwrap();
+        let file = storage.load().unwrap();
+        assert!(!journal.exists());
 
-    #[test]
-    fn encrypt_decrypt_roundtrip() {
-        use crate::crypto::*;
-        use crate::format::{Header, KeystoreFile, parse, serialize};
+        kn.update("app/key001", "changed").unwrap();
+        kn.save().unwrap();
+        kn.remove("app/key002").unwrap();
+        kn.set("app/new", "added").unwrap();
+        kn.save().unwrap();
+        assert_eq!(
+            storage.load().unwrap(),
+            file,
+            "saves must not rewrite the file"
+        );
+        assert!(journal.exists());
 
-        let kdf = KdfParams::default();
-        let salt = generate_salt().unwrap();
-        let key = derive_key("pw", &salt, kdf).unwrap();
+        let mut kn = open();
+        assert_eq!(kn.get("app/key001").unwrap(), Some("changed"));
+        assert_eq!(kn.get("app/key002").unwrap(), None);
+        assert_eq!(kn.get("app/new").unwrap(), Some("added"));
+        assert_eq!(kn.list().len(), 600);
 
-        let data = b"secret data".to_vec();
+        let mut indexed =
+            IndexedKeynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone())
+                .unwrap();
+        assert_eq!(indexed.get("app/new").unwrap(), Some("added"));
 
-        let (header, ciphertext) = Header::encrypt_store(
-            kdf,
-            Algorithm::XChaCha20Poly1305,
-            salt.to_vec(),
-            &key,
-            &data,
+        kn.update("app/key003", "also changed").unwrap();
+        kn.save().unwrap();
+        assert_eq!(kn.compact().unwrap(), 3);
+        assert!(!journal.exists());
+        assert_ne!(storage.load().unwrap(), file);
+        let kn = open();
+        assert_eq!(kn.get("app/key001").unwrap(), Some("changed"));
+        assert_eq!(kn.get("app/key003").unwrap(), Some("also changed"));
+        assert_eq!(kn.get("app/key002").unwrap(), None);
+    }
+
+    #[test]
+    fn journal_ignores_a_torn_last_frame_and_stale_journals() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let journal = storage.journal_path();
+        let open = || {
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone()).unwrap()
+        };
+
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
+        kn.set("a", "1").unwrap();
+        kn.set_journal(true).unwrap();
+        kn.save().unwrap();
+        kn.set("b", "2").unwrap();
+        kn.save().unwrap();
+        let one_frame = std::fs::read(&journal).unwrap();
+        kn.set("c", "3").unwrap();
+        kn.save().unwrap();
 
-        let keystore_file = KeystoreFile::new(header, ciphertext);
-        let file = serialize(&keystore_file).unwrap();
+        // A damaged frame before the last is an error, not an interrupted append.
+        let two_frames = std::fs::read(&journal).unwrap();
+        let mut damaged = two_frames.clone();
+        damaged[one_frame.len() - 3] ^= 1;
+        std::fs::write(&journal, &damaged).unwrap();
+        let err = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("does not authenticate"), "{err:#}");
 
-        let keystore_file2 = parse(&file).unwrap();
-        let key2 = derive_key("pw", keystore_file2.salt(), *keystore_file2.kdf()).unwrap();
-        let plaintext = keystore_file2.decrypt(&key2).unwrap();
+        // A crash in the middle of the second append.
+        std::fs::write(&journal, &two_frames[..two_frames.len() - 5]).unwrap();
+        let mut kn = open();
+        assert_eq!(kn.get("b").unwrap(), Some("2"));
+        assert_eq!(kn.get("c").unwrap(), None);
 
-        assert_eq!(*plaintext, data);
+        // The next save cannot append after the damaged frame, so it rewrites the file.
+        kn.set("d", "4").unwrap();
+        kn.save().unwrap();
+        assert!(!journal.exists());
+        assert_eq!(open().get("d").unwrap(), Some("4"));
+
+        // A journal of an earlier version of the file is ignored.
+        std::fs::write(&journal, &one_frame).unwrap();
+        let mut kn = open();
+        assert_eq!(kn.get("d").unwrap(), Some("4"));
+        kn.set("e", "5").unwrap();
+        kn.save().unwrap();
+        let kn = open();
+        assert_eq!(kn.get("e").unwrap(), Some("5"));
+        assert_eq!(kn.list().len(), 4);
     }
 
     #[test]
-    fn init_and_open_with_zeroize_wrappers() {
+    fn key_check_tells_wrong_passwords_from_corrupted_data() {
         let dir = tempdir().unwrap();
         let path = dir.path().join("keynest.db");
-        let storage = Storage::new(path);
-        let password = Zeroizing::new(String::from("pw"));
-        let mut kn =
-            Keynest::init_with_storage_and_kdf(password, storage.clone(), KdfParams::default())
-                .unwrap();
-        kn.set("A", "B").unwrap();
+        let storage = Storage::new(path.clone());
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::new(8, 1, 1).unwrap(),
+        )
+        .unwrap();
+        kn.set("db/password", "hunter2").unwrap();
         kn.save().unwrap();
+        assert!(kn.keystore_file.header.key_check().is_some());
 
-        let password = Zeroizing::new(String::from("pw"));
-        let kn2 = Keynest::open_with_storage(password, storage).unwrap();
-        assert_eq!(kn2.get("A"), Some("B"));
+        let err = Keynest::open_with_storage(Zeroizing::new("wrong".to_string()), storage.clone())
+            .err()
+            .unwrap();
+        assert!(err.is::<WrongPassword>(), "{err:#}");
+        let err =
+            IndexedKeynest::open_with_storage(Zeroizing::new("wrong".to_string()), storage.clone())
+                .err()
+                .unwrap();
+        assert!(err.is::<WrongPassword>(), "{err:#}");
+
+        // The last byte belongs to the tag of the last section.
+        let mut data = std::fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &data).unwrap();
+        let err = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone())
+            .err()
+            .unwrap();
+        assert!(err.is::<Corrupted>(), "{err:#}");
+        let mut indexed =
+            IndexedKeynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        let err = indexed.get("db/password").unwrap_err();
+        assert!(err.is::<Corrupted>(), "{err:#}");
     }
 
     #[test]
-    fn init_fails_if_store_exists() {
+    fn memory_mapped_stores_open_and_save() {
         let dir = tempdir().unwrap();
-        let path = dir.path().join("keynest.db");
-        let storage = Storage::new(path);
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::new(8, 1, 1).unwrap(),
+        )
+        .unwrap();
+        for i in 0..300 {
+            kn.set(&format!("app/key{i:03}"), &"v".repeat(4096))
+                .unwrap();
+        }
+        kn.save().unwrap();
+        assert!(
+            storage
+                .clone()
+                .with_mmap(true)
+                .load_data()
+                .unwrap()
+                .is_mapped()
+        );
 
-        let password = Zeroizing::new(String::from("pw"));
-        Keynest::init_with_storage_and_kdf(password, storage.clone(), KdfParams::default())
-            .unwrap();
-        let password = Zeroizing::new(String::from("pw"));
+        let mapped = storage.with_mmap(true);
+        let mut kn =
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), mapped.clone()).unwrap();
+        assert_eq!(kn.list().len(), 300);
+        assert_eq!(
+            kn.get("app/key123").unwrap(),
+            Some("v".repeat(4096).as_str())
+        );
         assert!(
-            Keynest::init_with_storage_and_kdf(password, storage, KdfParams::default()).is_err()
+            kn.keystore_file.sections().is_empty(),
+            "the mapping must not outlive open"
+        );
+
+        kn.update("app/key123", "changed").unwrap();
+        kn.save().unwrap();
+        let mut indexed =
+            IndexedKeynest::open_with_storage(Zeroizing::new("pw".to_string()), mapped).unwrap();
+        assert_eq!(indexed.get("app/key123").unwrap(), Some("changed"));
+        let kn = indexed.into_keynest().unwrap();
+        assert_eq!(
+            kn.get("app/key000").unwrap(),
+            Some("v".repeat(4096).as_str())
         );
     }
 
     #[test]
-    fn wrong_password_fails() {
-        let dir = tempfile::tempdir().unwrap();
+    fn json_payloads_move_to_messagepack_unless_pinned() {
+        let dir = tempdir().unwrap();
         let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let pw = || Zeroizing::new("pw".to_string());
 
-        Keynest::init_with_storage_and_kdf(
-            Zeroizing::new("correct".to_string()),
+        let kn = Keynest::init_with_storage_and_kdf(pw(), storage.clone(), kdf).unwrap();
+        assert_eq!(kn.payload_encoding(), PayloadEncoding::compact());
+
+        // Stores written before MessagePack became the default migrate on save.
+        std::fs::remove_file(storage.path()).unwrap();
+        let mut kn = Keynest::init_with_options(
+            pw(),
             storage.clone(),
-            KdfParams::default(),
+            InitOptions::new(kdf).with_encoding(PayloadEncoding::default()),
         )
         .unwrap();
-        assert!(Keynest::open_with_storage(Zeroizing::new("wrong".to_string()), storage).is_err());
+        kn.set("db/password", "hunter2").unwrap();
+        assert_eq!(kn.payload_encoding(), PayloadEncoding::default());
+        kn.save().unwrap();
+        let kn = Keynest::open_with_storage(pw(), storage.clone()).unwrap();
+        assert_eq!(kn.payload_encoding(), PayloadEncoding::compact());
+        assert_eq!(kn.get("db/password").unwrap(), Some("hunter2"));
+
+        // The v2 compatibility mode writes JSON without an explicit conversion.
+        let mut kn = kn;
+        kn.set_write_format(Some(2)).unwrap();
+        kn.save().unwrap();
+        assert_eq!(storage.load().unwrap()[4], 2);
+        kn.set_write_format(None).unwrap();
+        kn.save().unwrap();
+        assert_eq!(kn.payload_encoding(), PayloadEncoding::compact());
+
+        // Converting pins the encoding, JSON included.
+        kn.convert(PayloadEncoding::default()).unwrap();
+        kn.set("db/user", "admin").unwrap();
+        kn.save().unwrap();
+        let kn = Keynest::open_with_storage(pw(), storage).unwrap();
+        assert_eq!(kn.payload_encoding(), PayloadEncoding::default());
     }
 
     #[test]
-    fn set_existing_key_fails() {
-        let dir = tempfile::tempdir().unwrap();
+    fn metrics_summarize_the_store() {
+        let dir = tempdir().unwrap();
         let storage = Storage::new(dir.path().join("keynest.db"));
-
         let mut kn = Keynest::init_with_storage_and_kdf(
             Zeroizing::new("pw".to_string()),
-            storage.clone(),
-            KdfParams::default(),
+            storage,
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
-        kn.set("A", "B").unwrap();
-        assert!(kn.set("A", "C").is_err());
+        kn.set("db/password", "hunter2").unwrap();
+        kn.set("api/token", "abc").unwrap();
+        kn.set_note("recovery", "call Bob").unwrap();
+        kn.add_tag("db/password", "prod").unwrap();
+        kn.add_tag("api/token", "prod").unwrap();
+        kn.pin("api/token").unwrap();
+        kn.attach("recovery", "codes.txt", &b"123456"[..]).unwrap();
+        kn.set_expiry("db/password", Some(Utc::now() - chrono::TimeDelta::days(1)))
+            .unwrap();
+        kn.set_expiry("api/token", Some(Utc::now() + chrono::TimeDelta::days(7)))
+            .unwrap();
+        kn.save().unwrap();
+
+        let metrics = kn.metrics().unwrap();
+        assert_eq!(metrics.entries(), 3);
+        assert_eq!(metrics.kinds().get("secret"), Some(&2));
+        assert_eq!(metrics.kinds().get("note"), Some(&1));
+        assert_eq!((metrics.tags(), metrics.pinned()), (1, 1));
+        assert_eq!((metrics.attachments(), metrics.attachment_bytes()), (1, 6));
+        assert_eq!(metrics.value_bytes(), 18);
+        assert!(metrics.file_size() > 0);
+        assert!(metrics.last_saved().is_some());
+        assert_eq!(metrics.kdf_memory_kib(), 8);
+        assert_eq!(metrics.rotation().with_expiry(), 2);
+        assert_eq!(metrics.rotation().expired(), 1);
+        assert_eq!(metrics.rotation().expiring_soon(), 1);
+        assert_eq!(metrics.rotation().compliance(), Some(0.5));
+        assert!(!metrics.locked());
+
+        kn.lock();
+        let metrics = kn.metrics().unwrap();
+        assert!(metrics.locked());
+        assert_eq!(metrics.entries(), 0);
+        assert_eq!(metrics.rotation().compliance(), None);
     }
 
     #[test]
-    fn update_key_works() {
-        let dir = tempfile::tempdir().unwrap();
-        let storage = Storage::new(dir.path().join("keynest.db"));
+    fn bundles_move_entries_between_stores() {
+        let dir = tempdir().unwrap();
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let pw = || Zeroizing::new("pw".to_string());
+        let mut source = Keynest::init_with_storage_and_kdf(
+            pw(),
+            Storage::new(dir.path().join("source.db")),
+            kdf,
+        )
+        .unwrap();
+        source.set("work/db", "hunter2").unwrap();
+        source.set_field("work/db", "user", "admin").unwrap();
+        source.add_tag("work/db", "prod").unwrap();
+        source.set_note("work/notes", "call Bob").unwrap();
+        source.set("home/wifi", "secret").unwrap();
+        let bundle = source
+            .export_bundle(Some("work/"), Zeroizing::new("transfer".to_string()), kdf)
+            .unwrap();
+        assert!(!bundle.windows(7).any(|w| w == b"hunter2"));
 
-        let mut kn = Keynest::init_with_storage_and_kdf(
-            Zeroizing::new("pw".to_string()),
-            storage.clone(),
-            KdfParams::default(),
+        let mut target = Keynest::init_with_storage_and_kdf(
+            pw(),
+            Storage::new(dir.path().join("target.db")),
+            kdf,
         )
         .unwrap();
-        kn.set("A", "B").unwrap();
-        kn.update("A", "C").unwrap();
-        assert_eq!(kn.get("A").unwrap(), "C");
+        target.set("work/db", "old").unwrap();
+        let err = target
+            .import_bundle(
+                &bundle,
+                Zeroizing::new("wrong".to_string()),
+                ImportPolicy::Overwrite,
+            )
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("wrong passphrase"));
+        let mut tampered = bundle.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(
+            target
+                .import_bundle(
+                    &tampered,
+                    Zeroizing::new("transfer".to_string()),
+                    ImportPolicy::Overwrite
+                )
+                .is_err()
+        );
+
+        let summary = target
+            .import_bundle(
+                &bundle,
+                Zeroizing::new("transfer".to_string()),
+                ImportPolicy::SkipExisting,
+            )
+            .unwrap();
+        assert_eq!((summary.created(), summary.skipped()), (1, 1));
+        assert_eq!(target.get("work/db").unwrap(), Some("old"));
+        assert_eq!(target.kind("work/notes").unwrap(), Some(EntryKind::Note));
+        assert_eq!(target.get("home/wifi").unwrap(), None);
+
+        let summary = target
+            .import_bundle(
+                &bundle,
+                Zeroizing::new("transfer".to_string()),
+                ImportPolicy::Overwrite,
+            )
+            .unwrap();
+        assert_eq!(summary.updated(), 2);
+        assert_eq!(target.get("work/db").unwrap(), Some("hunter2"));
+        assert_eq!(target.fields("work/db").unwrap().unwrap()["user"], "admin");
+        assert_eq!(target.tags("work/db").unwrap().unwrap(), ["prod"]);
     }
 
     #[test]
-    fn update_not_existing_key_fails() {
-        let dir = tempfile::tempdir().unwrap();
-        let storage = Storage::new(dir.path().join("keynest.db"));
+    fn merge_reconciles_two_copies_of_a_store() {
+        let dir = tempdir().unwrap();
+        let pw = || Zeroizing::new("pw".to_string());
+        let at = |secs| Arc::new(FixedClock::new(DateTime::from_timestamp(secs, 0).unwrap()));
+        let ours_storage = Storage::new(dir.path().join("ours.db"));
+        let mut ours = Keynest::init_with_storage_and_kdf(
+            pw(),
+            ours_storage.clone(),
+            KdfParams::new(8, 1, 1).unwrap(),
+        )
+        .unwrap();
+        ours.set_clock(at(1_000));
+        for key in ["same", "a", "b"] {
+            ours.set(key, "v1").unwrap();
+        }
+        ours.save().unwrap();
+        let theirs_storage = Storage::new(dir.path().join("theirs.db"));
+        std::fs::copy(ours_storage.path(), theirs_storage.path()).unwrap();
+        let mut theirs = Keynest::open_with_storage(pw(), theirs_storage).unwrap();
+
+        // "a" changed on our side first, "b" on theirs later, "c" only exists there.
+        ours.set_clock(at(2_000));
+        ours.update("a", "ours").unwrap();
+        ours.update("b", "ours").unwrap();
+        theirs.set_clock(at(1_500));
+        theirs.update("a", "theirs").unwrap();
+        theirs.set_clock(at(3_000));
+        theirs.update("b", "theirs").unwrap();
+        theirs.set_field("b", "user", "bob").unwrap();
+        theirs.set_note("c", "new").unwrap();
+
+        let mut conflicts = Vec::new();
+        let summary = ours
+            .merge_with(&theirs, |conflict| {
+                conflicts.push((conflict.key().to_string(), conflict.differences()));
+                Ok(MergePolicy::Newer.choose(conflict))
+            })
+            .unwrap();
+        assert_eq!(
+            conflicts,
+            [
+                ("a".to_string(), vec!["value"]),
+                ("b".to_string(), vec!["value", "fields"])
+            ]
+        );
+        assert_eq!(summary.added(), ["c"]);
+        assert_eq!(summary.taken(), ["b"]);
+        assert_eq!(summary.kept(), ["a"]);
+        assert_eq!(summary.unchanged(), 1);
+        assert_eq!(ours.get("a").unwrap(), Some("ours"));
+        assert_eq!(ours.get("b").unwrap(), Some("theirs"));
+        assert_eq!(ours.fields("b").unwrap().unwrap()["user"], "bob");
+        assert_eq!(ours.kind("c").unwrap(), Some(EntryKind::Note));
+
+        let summary = ours.merge(&theirs, MergePolicy::Theirs).unwrap();
+        assert_eq!(summary.taken(), ["a"]);
+        assert_eq!(ours.get("a").unwrap(), Some("theirs"));
+        assert!(
+            ours.merge_with(&theirs, |_| bail!("no")).is_ok(),
+            "identical stores have no conflicts"
+        );
+    }
 
+    #[test]
+    fn deterministic_records_rewrite_only_changed_entries() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
         let mut kn = Keynest::init_with_storage_and_kdf(
             Zeroizing::new("pw".to_string()),
             storage.clone(),
-            KdfParams::default(),
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
-        kn.set("A", "B").unwrap();
-        assert!(kn.update("Z", "C").is_err());
+        assert!(!kn.deterministic_records().unwrap());
+        kn.set_deterministic_records(true).unwrap();
+        kn.set_per_entry_records(true).unwrap();
+        kn.set("a", "1").unwrap();
+        kn.set("b", "2").unwrap();
+        kn.save().unwrap();
+        let first = std::fs::read(storage.path()).unwrap();
+
+        // Only the header and the index record the new link of the hash chain.
+        kn.compact().unwrap();
+        let rewritten = format::parse(&std::fs::read(storage.path()).unwrap()).unwrap();
+        let parsed = format::parse(&first).unwrap();
+        assert_eq!(
+            rewritten.sections()[0].ciphertext(),
+            parsed.sections()[0].ciphertext()
+        );
+        assert_eq!(
+            rewritten.sections()[1].ciphertext(),
+            parsed.sections()[1].ciphertext()
+        );
+        assert_eq!(
+            rewritten.header.chain().unwrap().generation(),
+            parsed.header.chain().unwrap().generation() + 1
+        );
+
+        kn.update("b", "3").unwrap();
+        kn.save().unwrap();
+        let second = std::fs::read(storage.path()).unwrap();
+        let (before, after) = (
+            format::parse(&first).unwrap(),
+            format::parse(&second).unwrap(),
+        );
+        assert_eq!(before.sections()[0].nonce(), after.sections()[0].nonce());
+        assert_ne!(before.sections()[1].nonce(), after.sections()[1].nonce());
+
+        let kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert!(kn.deterministic_records().unwrap());
+        assert_eq!(kn.get("b").unwrap(), Some("3"));
     }
 
     #[test]
-    fn removing_key_works() {
-        let dir = tempfile::tempdir().unwrap();
-        let storage = Storage::new(dir.path().join("keynest.db"));
-
+    fn per_entry_records_open_sealed_and_decrypt_only_the_entries_read() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
         let mut kn = Keynest::init_with_storage_and_kdf(
             Zeroizing::new("pw".to_string()),
             storage.clone(),
-            KdfParams::default(),
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
-        kn.set("A", "B").unwrap();
+        kn.set_per_entry_records(true).unwrap();
+        kn.set("a", "1").unwrap();
+        kn.set_field("a", "user", "alice").unwrap();
+        kn.set("b", "ref:a").unwrap();
+        kn.save().unwrap();
 
-        assert_eq!(kn.get("A").unwrap(), "B");
-        kn.remove("A").unwrap();
-        assert_eq!(kn.get("A"), None);
+        // Each record is encrypted with its own key, not with the store key.
+        let file = format::parse(&std::fs::read(storage.path()).unwrap()).unwrap();
+        assert_eq!(file.sections().len(), 2);
+        assert!(file.decrypt_section(&kn.key, 0).is_err());
+
+        let mut kn =
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone()).unwrap();
+        assert_eq!(kn.get("a").unwrap(), Some("1"));
+        assert_eq!(kn.resolve("b").unwrap(), Some("1"));
+        assert_eq!(kn.fields("a").unwrap().unwrap()["user"], "alice");
+        assert_eq!(kn.get("missing").unwrap(), None);
+        assert!(
+            kn.store.get().is_none(),
+            "only the records read are decrypted"
+        );
+
+        kn.lock();
+        assert_eq!(kn.get("a").unwrap(), None);
+        kn.unlock(Zeroizing::new("pw".to_string())).unwrap();
+        assert!(kn.store.get().is_none());
+        assert_eq!(kn.list(), ["a", "b"]);
+        assert!(kn.store.get().is_none(), "the index lists the keys");
+        assert_eq!(kn.list_all().unwrap().len(), 2);
+        assert!(
+            kn.store.get().is_some(),
+            "listing entries decrypts every record"
+        );
+
+        kn.set("c", "3").unwrap();
+        kn.save().unwrap();
+        let kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert_eq!(kn.get("c").unwrap(), Some("3"));
+        assert_eq!(kn.list_all().unwrap().len(), 3);
     }
 
     #[test]
-    fn removing_not_existing_key_fails() {
-        let dir = tempfile::tempdir().unwrap();
-        let storage = Storage::new(dir.path().join("keynest.db"));
-
+    fn audit_log_records_changes_reads_and_rekeys() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
         let mut kn = Keynest::init_with_storage_and_kdf(
             Zeroizing::new("pw".to_string()),
             storage.clone(),
-            KdfParams::default(),
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
-        assert!(kn.remove("A").is_err());
+        kn.set("before", "x").unwrap();
+        assert!(kn.audit_events().unwrap().is_none());
+        kn.set_audit_log(true).unwrap();
+        kn.set_reader("alice@laptop");
+        kn.set("a", "1").unwrap();
+        kn.set("b", "2").unwrap();
+        kn.save().unwrap();
+        kn.update("a", "3").unwrap();
+        kn.remove("b").unwrap();
+        kn.save().unwrap();
+        kn.record_get("a").unwrap();
+        kn.save_usage().unwrap();
+        kn.rekey(
+            Zeroizing::new("new".to_string()),
+            KdfParams::new(8, 1, 1).unwrap(),
+        )
+        .unwrap();
+
+        let kn =
+            Keynest::open_with_storage(Zeroizing::new("new".to_string()), storage.clone()).unwrap();
+        let events = kn.audit_events().unwrap().unwrap();
+        let actions: Vec<_> = events
+            .iter()
+            .map(|e| (e.action().as_str(), e.key().unwrap_or("-")))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("set", "a"),
+                ("set", "b"),
+                ("update", "a"),
+                ("remove", "b"),
+                ("get", "a"),
+                ("rekey", "-")
+            ]
+        );
+        assert!(events.iter().all(|e| e.actor() == "alice@laptop"));
+
+        // Frames cut off the end are noticed.
+        let log = std::fs::read(storage.audit_path()).unwrap();
+        std::fs::write(storage.audit_path(), &log[..log.len() / 2]).unwrap();
+        assert!(kn.audit_events().is_err());
+
+        let mut kn = kn;
+        kn.set_audit_log(false).unwrap();
+        kn.save().unwrap();
+        assert!(!storage.audit_path().exists());
     }
 
     #[test]
-    fn list_works() {
-        let dir = tempfile::tempdir().unwrap();
-        let storage = Storage::new(dir.path().join("keynest.db"));
-
+    fn promote_rewrites_references_into_the_source_and_logs_the_copies() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
         let mut kn = Keynest::init_with_storage_and_kdf(
             Zeroizing::new("pw".to_string()),
             storage.clone(),
-            KdfParams::default(),
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
-        kn.set("A", "B").unwrap();
+        kn.set("shared/ca", "pem").unwrap();
+        kn.set("staging/db", "s3cret").unwrap();
+        kn.set("staging/app", "ref:staging/db").unwrap();
+        kn.set("staging/ca", "ref:shared/ca").unwrap();
+        kn.set("prod/db", "old").unwrap();
+        kn.set_audit_log(true).unwrap();
+        kn.set_reader("alice@laptop");
+        kn.save().unwrap();
 
-        assert!(kn.list().contains(&&"A".to_string()));
-        assert!(!kn.list().contains(&&"B".to_string()));
+        let keys = ["app", "ca", "db"].map(String::from);
+        kn.promote("staging/", "prod/", &keys).unwrap();
+        kn.save().unwrap();
+
+        assert_eq!(kn.get("prod/app").unwrap(), Some("ref:prod/db"));
+        assert_eq!(kn.get("prod/ca").unwrap(), Some("ref:shared/ca"));
+        assert_eq!(kn.get("prod/db").unwrap(), Some("s3cret"));
+        assert_eq!(kn.get("staging/app").unwrap(), Some("ref:staging/db"));
+
+        let events = kn.audit_events().unwrap().unwrap();
+        let promoted: Vec<_> = events
+            .iter()
+            .filter(|e| e.action() == AuditAction::Promote)
+            .map(|e| (e.from().unwrap(), e.key().unwrap(), e.actor()))
+            .collect();
+        assert_eq!(
+            promoted,
+            [
+                ("staging/app", "prod/app", "alice@laptop"),
+                ("staging/ca", "prod/ca", "alice@laptop"),
+                ("staging/db", "prod/db", "alice@laptop"),
+            ]
+        );
     }
 
     #[test]
-    fn list_all_works() {
-        let dir = tempfile::tempdir().unwrap();
-        let storage = Storage::new(dir.path().join("keynest.db"));
-
+    fn chain_detects_a_store_put_back_to_an_older_copy() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
+        let open = || {
+            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone()).unwrap()
+        };
         let mut kn = Keynest::init_with_storage_and_kdf(
             Zeroizing::new("pw".to_string()),
             storage.clone(),
-            KdfParams::default(),
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
-        kn.set("A", "B").unwrap();
-        for sec_entry in kn.list_all() {
-            assert_eq!(sec_entry.key(), "A");
-            assert_eq!(sec_entry.value(), "B");
-            assert_ne!(sec_entry.updated(), "");
-        }
+        assert_eq!(kn.chain().unwrap().generation(), 1);
+        let witness = ChainWitness::new(dir.path().join("witness"));
+        assert_eq!(
+            kn.verify_chain(&witness).unwrap(),
+            ChainStatus::Untracked { generation: 1 }
+        );
+
+        let first = storage.load().unwrap();
+        kn.set_chain_witness(Some(witness.clone()));
+        kn.set_backups(5).unwrap();
+        kn.set("a", "1").unwrap();
+        kn.save().unwrap();
+        let link = *kn.chain().unwrap();
+        assert_eq!(link.generation(), 2);
+        assert_eq!(link.previous(), &file_hash(&first));
+        assert_eq!(
+            kn.verify_chain(&witness).unwrap(),
+            ChainStatus::Current { generation: 2 }
+        );
+
+        // Saves without the witness are followed back through the backups.
+        let stale = storage.load().unwrap();
+        let mut other = open();
+        other.set("b", "2").unwrap();
+        other.save().unwrap();
+        other.set("c", "3").unwrap();
+        other.save().unwrap();
+        assert_eq!(
+            open().verify_chain(&witness).unwrap(),
+            ChainStatus::Ahead {
+                generation: 4,
+                saves: 2,
+                linked: true
+            }
+        );
+
+        storage.save(&stale).unwrap();
+        let mut old = open();
+        assert_eq!(
+            old.verify_chain(&witness).unwrap(),
+            ChainStatus::RolledBack {
+                generation: 2,
+                seen: 4
+            }
+        );
+        old.set_chain_witness(Some(witness.clone()));
+        old.set("x", "1").unwrap();
+        old.save().unwrap();
+        assert_eq!(
+            old.verify_chain(&witness).unwrap(),
+            ChainStatus::RolledBack {
+                generation: 3,
+                seen: 4
+            }
+        );
+        old.accept_chain(&witness).unwrap();
+        assert_eq!(
+            old.verify_chain(&witness).unwrap(),
+            ChainStatus::Current { generation: 3 }
+        );
     }
 
     #[test]
-    fn rekey_changes_password() {
-        let dir = tempfile::tempdir().unwrap();
+    fn audit_scores_secrets_and_finds_reused_values() {
+        let dir = tempdir().unwrap();
         let storage = Storage::new(dir.path().join("keynest.db"));
-
         let mut kn = Keynest::init_with_storage_and_kdf(
-            Zeroizing::new("old".to_string()),
-            storage.clone(),
-            KdfParams::default(),
+            Zeroizing::new("pw".to_string()),
+            storage,
+            KdfParams::new(8, 1, 1).unwrap(),
         )
         .unwrap();
-        kn.set("A", "B").unwrap();
-        kn.save().unwrap();
-
-        //reopen
-        let mut kn =
-            Keynest::open_with_storage(Zeroizing::new("old".to_string()), storage.clone()).unwrap();
-
-        //rekey
-        kn.rekey(Zeroizing::new("new".to_string()), KdfParams::default())
-            .unwrap();
+        kn.set("mail", "P@ssw0rd2024!").unwrap();
+        kn.set("bank", "T7mq2VxR9pLw4KzH8bNc").unwrap();
+        kn.set("bank-old", "T7mq2VxR9pLw4KzH8bNc").unwrap();
+        kn.set("alias", "ref:mail").unwrap();
+        kn.set_note("diary", "password").unwrap();
 
+        let findings = kn.audit().unwrap();
+        let keys: Vec<_> = findings.iter().map(Finding::key).collect();
+        assert_eq!(keys, ["bank", "bank-old", "mail"]);
+        assert_eq!(findings[0].strength().score(), 4);
+        assert_eq!(findings[0].reused_by(), ["bank-old"]);
+        assert!(findings[0].needs_rotation(3));
+        assert_eq!(findings[2].strength().score(), 0);
         assert!(
-            Keynest::open_with_storage(Zeroizing::new("old".to_string()), storage.clone()).is_err()
+            findings[2]
+                .strength()
+                .weaknesses()
+                .contains(&Weakness::Common)
         );
+        assert!(findings[2].reused_by().is_empty());
+        assert!(!findings[2].needs_rotation(0));
 
-        let kn2 = Keynest::open_with_storage(Zeroizing::new("new".to_string()), storage).unwrap();
-
-        assert_eq!(kn2.get("A"), Some("B"));
+        kn.lock();
+        assert!(kn.audit().is_err());
     }
 
     #[test]
-    fn rekey_changes_kdf_parameters() {
-        let dir = tempfile::tempdir().unwrap();
+    fn stale_lists_secrets_whose_value_is_older_than_the_max_age() {
+        let dir = tempdir().unwrap();
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let clock = Arc::new(FixedClock::new(start));
         let storage = Storage::new(dir.path().join("keynest.db"));
-
-        let original_kdf = KdfParams::default();
-
-        // init
-        let mut kn = Keynest::init_with_storage_and_kdf(
+        let mut kn = Keynest::init_with_options(
             Zeroizing::new("pw".to_string()),
             storage.clone(),
-            original_kdf,
+            InitOptions::new(KdfParams::new(8, 1, 1).unwrap()).with_clock(clock.clone()),
         )
         .unwrap();
+        kn.set("api", "old-token").unwrap();
+        kn.set("db", "old-password").unwrap();
+        kn.set("same", "unchanged").unwrap();
+        kn.set_note("runbook", "rotate quarterly").unwrap();
 
+        let max_age = chrono::TimeDelta::days(90);
+        clock.set(start + chrono::TimeDelta::days(100));
+        kn.update("db", "new-password").unwrap();
+        kn.update("same", "unchanged").unwrap();
+        kn.add_tag("api", "prod").unwrap();
+        kn.set_field("api", "user", "ci").unwrap();
         kn.save().unwrap();
 
-        // reopen
-        let mut kn =
-            Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage.clone()).unwrap();
+        let mut kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        kn.set_clock(clock);
+        let stale: Vec<_> = kn.stale(max_age).unwrap().iter().map(|e| e.key()).collect();
+        assert_eq!(stale, ["api", "same"]);
+        let api = kn.stale(max_age).unwrap()[0];
+        assert_eq!(api.rotated_at(), Some(start));
+        assert!(api.updated() > api.rotated());
+        assert!(kn.stale(chrono::TimeDelta::days(200)).unwrap().is_empty());
+    }
 
-        // neue Parameter
-        let new_kdf = KdfParams::new(
-            original_kdf.mem_cost_kib() * 2,
-            original_kdf.time_cost() + 1,
-            original_kdf.parallelism(),
-        )
-        .unwrap();
+    #[test]
+    fn duress_password_opens_the_decoy_store() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let pw = |s: &str| Zeroizing::new(s.to_string());
+        let open = |s: &str| Keynest::open_with_storage(pw(s), storage.clone());
 
-        kn.rekey(Zeroizing::new("pw".to_string()), new_kdf).unwrap();
+        let mut kn = Keynest::init_with_storage_and_kdf(pw("owner"), storage.clone(), kdf).unwrap();
+        kn.set("real", "1").unwrap();
+        kn.add_keyslot("alice", pw("alice"), kdf).unwrap();
+        let filler = kn.keystore_file.header.decoy().unwrap().to_vec();
+        kn.set_duress("recovery", pw("duress"), kdf).unwrap();
+        assert!(kn.set_duress("other", pw("x"), kdf).is_err());
+        assert_eq!(kn.duress_slot().unwrap().as_deref(), Some("recovery"));
+        assert_eq!(kn.keyslots().len(), 3);
+        // The decoy takes the place of the filler, at the same length.
+        let sealed = kn.keystore_file.header.decoy().unwrap();
+        assert_eq!(sealed.len(), filler.len());
+        assert_ne!(sealed, filler);
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(files.len(), 1);
 
-        // reopen mit neuem password
-        let kn2 = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        // The duress password opens the decoy, and changes are saved to it.
+        let mut decoy = open("duress").unwrap();
+        assert!(decoy.list().is_empty());
+        assert_eq!(decoy.duress_slot().unwrap(), None);
+        decoy.set("fake", "2").unwrap();
+        decoy.save().unwrap();
+        let mut decoy = open("duress").unwrap();
+        assert_eq!(decoy.get("fake").unwrap(), Some("2"));
+        assert_eq!(decoy.get("real").unwrap(), None);
+        // It reports the keystore file and its header, which it cannot change.
+        let (info, real) = (decoy.info().unwrap(), kn.info().unwrap());
+        assert_eq!(info.path, real.path);
+        assert_eq!(info.keyslots, ["default", "alice", "recovery"]);
+        assert_eq!(info.payload_encoding, real.payload_encoding);
+        assert_eq!(info.creation_date, real.creation_date);
+        assert_eq!(decoy.chain(), kn.chain());
+        assert!(decoy.rekey(pw("new"), kdf).is_err());
+        assert!(decoy.add_keyslot("bob", pw("bob"), kdf).is_err());
+        assert!(decoy.set_journal(true).is_err());
+        let mut indexed = IndexedKeynest::open_with_storage(pw("duress"), storage.clone()).unwrap();
+        assert_eq!(indexed.get("fake").unwrap(), Some("2"));
 
-        assert_eq!(
-            kn2.keystore_file.kdf().mem_cost_kib(),
-            new_kdf.mem_cost_kib()
-        );
-        assert_eq!(kn2.keystore_file.kdf().time_cost(), new_kdf.time_cost());
+        let kn = open("owner").unwrap();
+        assert_eq!(kn.get("real").unwrap(), Some("1"));
+        assert_eq!(kn.get("fake").unwrap(), None);
+        let mut indexed = IndexedKeynest::open_with_storage(pw("owner"), storage.clone()).unwrap();
+        assert_eq!(indexed.get("real").unwrap(), Some("1"));
+
+        // Removing the duress password puts filler back.
+        let mut kn = open("owner").unwrap();
+        assert!(kn.remove_duress().unwrap());
+        assert!(!kn.remove_duress().unwrap());
+        assert_eq!(kn.keyslots().len(), 2);
+        assert!(open("duress").is_err());
+        assert_eq!(open("owner").unwrap().get("real").unwrap(), Some("1"));
+    }
+
+    #[test]
+    fn destroy_wipes_the_keystore_and_its_files() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        let kdf = KdfParams::new(8, 1, 1).unwrap();
+        let pw = |s: &str| Zeroizing::new(s.to_string());
+
+        let mut kn = Keynest::init_with_storage_and_kdf(pw("owner"), storage.clone(), kdf).unwrap();
+        kn.set_backups(2).unwrap();
+        kn.set_audit_log(true).unwrap();
+        kn.set("A", "B").unwrap();
+        kn.attach("A", "notes.txt", &b"attached"[..]).unwrap();
+        kn.save().unwrap();
+        kn.set("C", "D").unwrap();
+        kn.save().unwrap();
+        kn.set_duress("recovery", pw("duress"), kdf).unwrap();
+        let mut decoy = Keynest::open_with_storage(pw("duress"), storage.clone()).unwrap();
+        decoy.set_backups(1).unwrap();
+        decoy.save().unwrap();
+        decoy.save().unwrap();
+        std::fs::write(dir.path().join("keynest.db.tmp.0011223344556677"), b"left").unwrap();
+        std::fs::write(storage.journal_path(), b"journal").unwrap();
+        std::fs::write(dir.path().join("other.db"), b"unrelated").unwrap();
+        assert!(storage.audit_path().exists());
+        assert!(attachments::blob_dir(&storage).is_dir());
+        assert!(!storage.backups().unwrap().is_empty());
+
+        let files = kn.destroy().unwrap();
+        assert_eq!(files.last(), Some(storage.path()));
+        let left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["other.db"]);
+        assert!(Keynest::open_with_storage(pw("owner"), storage).is_err());
     }
 }
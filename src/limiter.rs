@@ -0,0 +1,257 @@
+//! Rate limiting of unlock attempts for services that embed keynest.
+//!
+//! A [`Limiter`] counts consecutive failed unlocks in a small state file. After
+//! `max_attempts` failures every further failure locks unlocking for a cooldown that
+//! doubles each time (up to `max_cooldown`); a successful unlock resets the count.
+//! While locked, [`crate::Keynest::open_with_limiter`] fails with [`RateLimited`]
+//! before running the key derivation, so a locked service spends no CPU on guesses.
+//!
+//! The state survives restarts. It is guarded by a mutex within one process; processes
+//! sharing a state file may race and lose a count, which only loosens the limit by the
+//! number of concurrent attempts.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::storage::Storage;
+
+/// Failures allowed before the first cooldown.
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+/// First cooldown; doubled on each further failure.
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+/// Longest cooldown.
+const DEFAULT_MAX_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+/// Unlocking is refused until the cooldown after too many failed attempts has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Returns how long to wait before the next attempt is accepted.
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "too many failed unlock attempts; retry in {}s",
+            self.retry_after.as_secs_f64().ceil()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Persisted limiter state.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct State {
+    /// Consecutive failed attempts.
+    failures: u32,
+    /// Unix time in milliseconds before which attempts are refused.
+    locked_until: u64,
+}
+
+/// Counts failed unlock attempts and enforces cooldowns, persisted in a state file.
+///
+/// Use one limiter (and state file) per keystore.
+pub struct Limiter {
+    storage: Storage,
+    max_attempts: u32,
+    cooldown: Duration,
+    max_cooldown: Duration,
+    lock: Mutex<()>,
+}
+
+impl Limiter {
+    /// Creates a limiter keeping its state in `path`, with the defaults of 5 attempts,
+    /// a 30 second first cooldown and a one hour maximum cooldown.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            storage: Storage::new(path),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            cooldown: DEFAULT_COOLDOWN,
+            max_cooldown: DEFAULT_MAX_COOLDOWN,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Sets the number of failed attempts allowed before the first cooldown.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the first cooldown, which doubles on each further failure.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Sets the longest cooldown.
+    pub fn with_max_cooldown(mut self, max_cooldown: Duration) -> Self {
+        self.max_cooldown = max_cooldown;
+        self
+    }
+
+    /// Returns the path of the state file.
+    pub fn path(&self) -> &Path {
+        self.storage.path()
+    }
+
+    /// Returns the number of consecutive failed attempts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file cannot be read.
+    pub fn failures(&self) -> Result<u32> {
+        let _guard = self.guard();
+        Ok(self.load()?.failures)
+    }
+
+    /// Fails with [`RateLimited`] while a cooldown is active.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RateLimited`] error during a cooldown, or an error if the state file
+    /// cannot be read. An unreadable state file refuses the attempt rather than
+    /// silently dropping the limit.
+    pub fn check(&self) -> Result<()> {
+        let _guard = self.guard();
+        let state = self.load()?;
+        let now = now_millis()?;
+        if state.locked_until > now {
+            return Err(RateLimited {
+                retry_after: Duration::from_millis(state.locked_until - now),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Records a failed attempt, starting or extending the cooldown once
+    /// `max_attempts` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file cannot be read or written.
+    pub fn record_failure(&self) -> Result<()> {
+        let _guard = self.guard();
+        let mut state = self.load()?;
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.max_attempts {
+            let doublings = (state.failures - self.max_attempts).min(31);
+            let cooldown = self
+                .cooldown
+                .saturating_mul(1 << doublings)
+                .min(self.max_cooldown);
+            let cooldown = u64::try_from(cooldown.as_millis()).unwrap_or(u64::MAX);
+            state.locked_until = now_millis()?.saturating_add(cooldown);
+        }
+        self.store(state)
+    }
+
+    /// Records a successful attempt, clearing the failure count and any cooldown.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file cannot be written.
+    pub fn record_success(&self) -> Result<()> {
+        let _guard = self.guard();
+        if self.load()? != State::default() {
+            self.store(State::default())?;
+        }
+        Ok(())
+    }
+
+    fn guard(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn load(&self) -> Result<State> {
+        if !self.storage.exists() {
+            return Ok(State::default());
+        }
+        let data = self.storage.load()?;
+        serde_json::from_slice(&data).with_context(|| {
+            format!(
+                "corrupted unlock limiter state: {}",
+                self.storage.path().display()
+            )
+        })
+    }
+
+    fn store(&self, state: State) -> Result<()> {
+        self.storage.save(&serde_json::to_vec(&state)?)
+    }
+}
+
+fn now_millis() -> Result<u64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system clock is before 1970")?;
+    Ok(u64::try_from(now.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn locks_after_max_attempts_with_doubling_cooldown() {
+        let dir = tempdir().unwrap();
+        let limiter = Limiter::new(dir.path().join("limiter.json"))
+            .with_max_attempts(2)
+            .with_cooldown(Duration::from_secs(60))
+            .with_max_cooldown(Duration::from_secs(100));
+
+        limiter.record_failure().unwrap();
+        limiter.check().unwrap();
+        limiter.record_failure().unwrap();
+        let err = limiter.check().unwrap_err();
+        let limited = err.downcast_ref::<RateLimited>().unwrap();
+        assert!(limited.retry_after() > Duration::from_secs(50));
+        assert!(limited.retry_after() <= Duration::from_secs(60));
+
+        limiter.record_failure().unwrap();
+        let err = limiter.check().unwrap_err();
+        let limited = err.downcast_ref::<RateLimited>().unwrap();
+        assert!(limited.retry_after() > Duration::from_secs(90));
+        assert!(limited.retry_after() <= Duration::from_secs(100));
+
+        limiter.record_success().unwrap();
+        limiter.check().unwrap();
+        assert_eq!(limiter.failures().unwrap(), 0);
+    }
+
+    #[test]
+    fn state_persists_across_limiters() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("limiter.json");
+        let limiter = Limiter::new(path.clone()).with_max_attempts(1);
+        limiter.record_failure().unwrap();
+
+        let limiter = Limiter::new(path);
+        assert_eq!(limiter.failures().unwrap(), 1);
+        assert!(limiter.check().unwrap_err().is::<RateLimited>());
+    }
+
+    #[test]
+    fn corrupted_state_refuses_attempts() {
+        let dir = tempdir().unwrap();
+        let limiter = Limiter::new(dir.path().join("limiter.json"));
+        limiter.storage.save(b"not json").unwrap();
+        let err = limiter.check().unwrap_err();
+        assert!(!err.is::<RateLimited>());
+        assert!(err.to_string().contains("corrupted unlock limiter state"));
+    }
+}
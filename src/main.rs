@@ -11,5 +11,34 @@ use commands::Command;
 
 fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
-    cli.command.run(cli.store)
+    if let Some(fd) = cli.password_fd {
+        auth::set_password_fd(fd)?;
+    }
+    if let Some(path) = cli.keyfile {
+        auth::set_keyfile(path);
+    }
+    if cli.use_keychain {
+        auth::set_use_keychain();
+    }
+    commands::profile::select(cli.profile.as_deref())?;
+    if let Some(score) = commands::profile::min_password_score() {
+        auth::set_min_password_score(score);
+    }
+    commands::agent::select(cli.use_agent)?;
+    let store = cli.store.or_else(commands::profile::store);
+    match cli.command.run(store) {
+        Err(e) if e.is::<keynest::Cancelled>() => {
+            eprintln!("Cancelled");
+            Ok(ExitCode::from(130))
+        }
+        Err(e) if e.is::<keynest::WrongPassword>() || e.is::<keynest::NoMatchingKeyslot>() => {
+            eprintln!("Error: {e:?}");
+            Ok(ExitCode::from(3))
+        }
+        Err(e) if e.is::<keynest::Corrupted>() => {
+            eprintln!("Error: {e:?}");
+            Ok(ExitCode::from(4))
+        }
+        result => result,
+    }
 }
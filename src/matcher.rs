@@ -0,0 +1,194 @@
+//! Patterns for finding entries by key and value.
+//!
+//! A [`Matcher`] is built from plain text, a glob, or a regular expression, and all three
+//! are compiled to a regex. Globs match whole keys (or whole lines of a value) and treat
+//! `/` as the namespace separator: `*` and `?` stay within one name, `**` crosses them.
+
+use anyhow::{Context, Result, bail};
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// Compiled size limit of a pattern, well above any sensible key pattern.
+const SIZE_LIMIT: usize = 1 << 20;
+
+/// How the text of a [`Matcher`] is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Matches anywhere the text occurs.
+    Substring,
+    /// Matches the whole key or line against a glob such as `prod/*/db_*`.
+    Glob,
+    /// Matches anywhere the regular expression matches.
+    Regex,
+}
+
+/// A pattern to find entries with [`crate::Keynest::find`].
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    regex: Regex,
+    values: bool,
+}
+
+impl Matcher {
+    /// Compiles `pattern` in `mode`, case-insensitively if `ignore_case` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pattern is empty, or not a valid glob or regex.
+    pub fn new(pattern: &str, mode: MatchMode, ignore_case: bool) -> Result<Self> {
+        if pattern.is_empty() {
+            bail!("search pattern cannot be empty");
+        }
+        let source = match mode {
+            MatchMode::Substring => regex::escape(pattern),
+            MatchMode::Glob => glob_to_regex(pattern)?,
+            MatchMode::Regex => pattern.to_string(),
+        };
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(ignore_case)
+            .size_limit(SIZE_LIMIT)
+            .build()
+            .with_context(|| format!("invalid pattern '{pattern}'"))?;
+        Ok(Self {
+            regex,
+            values: false,
+        })
+    }
+
+    /// Compiles `pattern` as a glob if it contains `*`, `?` or `[`, as plain text
+    /// otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Matcher::new`].
+    pub fn auto(pattern: &str, ignore_case: bool) -> Result<Self> {
+        let mode = if pattern.contains(['*', '?', '[']) {
+            MatchMode::Glob
+        } else {
+            MatchMode::Substring
+        };
+        Self::new(pattern, mode, ignore_case)
+    }
+
+    /// Also matches entries by the lines of their value, not only by key.
+    pub fn with_values(mut self, values: bool) -> Self {
+        self.values = values;
+        self
+    }
+
+    /// Returns `true` if values are searched too.
+    pub fn searches_values(&self) -> bool {
+        self.values
+    }
+
+    /// Returns `true` if `text` matches.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    /// Returns the byte range of the first match in `text`.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        self.regex.find(text).map(|m| m.range())
+    }
+
+    /// Returns `true` if the entry `key` with `value` matches: its key, or with
+    /// [`Matcher::with_values`] a line of its value.
+    pub(crate) fn matches_entry(&self, key: &str, value: &str) -> bool {
+        self.is_match(key) || (self.values && value.lines().any(|line| self.is_match(line)))
+    }
+}
+
+/// Translates a glob into an anchored regex.
+fn glob_to_regex(glob: &str) -> Result<String> {
+    let mut out = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                out.push_str(".*");
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                out.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    out.push('^');
+                }
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    if matches!(c, '\\' | '[' | '&' | '~') {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                }
+                if !closed {
+                    bail!("unclosed '[' in glob '{glob}'");
+                }
+                out.push(']');
+            }
+            c => out.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    out.push('$');
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_whole_keys_within_namespaces() {
+        let glob = Matcher::new("prod/*/db_*", MatchMode::Glob, false).unwrap();
+        assert!(glob.is_match("prod/app/db_password"));
+        assert!(!glob.is_match("prod/app/sub/db_password"));
+        assert!(!glob.is_match("xprod/app/db_password"));
+
+        let deep = Matcher::new("prod/**/db_?", MatchMode::Glob, false).unwrap();
+        assert!(deep.is_match("prod/a/b/db_1"));
+        assert!(!deep.is_match("prod/a/b/db_12"));
+
+        let class = Matcher::new("key[!0-9].txt", MatchMode::Glob, false).unwrap();
+        assert!(class.is_match("keya.txt"));
+        assert!(!class.is_match("key1.txt"));
+        assert!(Matcher::new("key[", MatchMode::Glob, false).is_err());
+    }
+
+    #[test]
+    fn substrings_and_regexes_match_anywhere() {
+        let text = Matcher::new("a.b", MatchMode::Substring, false).unwrap();
+        assert_eq!(text.find("xxa.b"), Some(2..5));
+        assert!(!text.is_match("axb"));
+
+        let regex = Matcher::new("^db_(user|pass)$", MatchMode::Regex, true).unwrap();
+        assert!(regex.is_match("DB_PASS"));
+        assert!(Matcher::new("(", MatchMode::Regex, false).is_err());
+        assert!(Matcher::new("", MatchMode::Substring, false).is_err());
+    }
+
+    #[test]
+    fn auto_picks_glob_only_for_wildcards() {
+        assert!(
+            Matcher::auto("github", false)
+                .unwrap()
+                .is_match("my/github/token")
+        );
+        assert!(
+            !Matcher::auto("github*", false)
+                .unwrap()
+                .is_match("my/github")
+        );
+    }
+
+    #[test]
+    fn values_are_only_searched_on_request() {
+        let matcher = Matcher::auto("hunter2", false).unwrap();
+        assert!(!matcher.matches_entry("k", "x\nhunter2"));
+        assert!(matcher.with_values(true).matches_entry("k", "x\nhunter2"));
+    }
+}
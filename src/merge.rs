@@ -0,0 +1,124 @@
+//! Merging two copies of a store.
+//!
+//! [`crate::Keynest::merge`] reconciles a store with a copy that was changed elsewhere,
+//! e.g. on another laptop. Entries only in the other copy are added, and entries that
+//! differ are conflicts, decided per entry by a [`MergePolicy`] or a callback. There is
+//! no history, so an entry removed from one copy is added back from the other.
+
+use chrono::{DateTime, Utc};
+
+use crate::store::SecretEntry;
+
+/// Which side of a conflict [`crate::Keynest::merge`] keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Keep the entry of this store.
+    Ours,
+    /// Take the entry of the other store.
+    Theirs,
+    /// Keep the entry updated last; this store's on a tie.
+    Newer,
+}
+
+/// A side of a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// This store.
+    Ours,
+    /// The other store.
+    Theirs,
+}
+
+/// An entry that both stores hold with different contents.
+#[derive(Debug, Clone, Copy)]
+pub struct Conflict<'a> {
+    pub(crate) ours: &'a SecretEntry,
+    pub(crate) theirs: &'a SecretEntry,
+}
+
+impl<'a> Conflict<'a> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &'a str {
+        self.ours.key()
+    }
+
+    /// Returns the entry of this store.
+    pub fn ours(&self) -> &'a SecretEntry {
+        self.ours
+    }
+
+    /// Returns the entry of the other store.
+    pub fn theirs(&self) -> &'a SecretEntry {
+        self.theirs
+    }
+
+    /// Returns what differs between the two entries: `value`, `kind`, `fields`, `tags`,
+    /// `expires` and `policy`, in that order.
+    pub fn differences(&self) -> Vec<&'static str> {
+        let (ours, theirs) = (self.ours, self.theirs);
+        [
+            ("value", ours.value() != theirs.value()),
+            ("kind", ours.kind() != theirs.kind()),
+            ("fields", ours.fields() != theirs.fields()),
+            ("tags", ours.tags() != theirs.tags()),
+            ("expires", ours.expires() != theirs.expires()),
+            ("policy", ours.policy() != theirs.policy()),
+        ]
+        .into_iter()
+        .filter_map(|(name, differs)| differs.then_some(name))
+        .collect()
+    }
+}
+
+impl MergePolicy {
+    /// Returns the side this policy keeps in `conflict`.
+    pub fn choose(self, conflict: &Conflict) -> Side {
+        match self {
+            Self::Ours => Side::Ours,
+            Self::Theirs => Side::Theirs,
+            Self::Newer => {
+                let updated = |entry: &SecretEntry| {
+                    DateTime::parse_from_rfc3339(entry.updated())
+                        .ok()
+                        .map(|time| time.with_timezone(&Utc))
+                };
+                if updated(conflict.theirs) > updated(conflict.ours) {
+                    Side::Theirs
+                } else {
+                    Side::Ours
+                }
+            }
+        }
+    }
+}
+
+/// What a merge changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub(crate) added: Vec<String>,
+    pub(crate) taken: Vec<String>,
+    pub(crate) kept: Vec<String>,
+    pub(crate) unchanged: usize,
+}
+
+impl MergeSummary {
+    /// Returns the keys of the entries that only the other store held.
+    pub fn added(&self) -> &[String] {
+        &self.added
+    }
+
+    /// Returns the keys of the conflicts decided for the other store.
+    pub fn taken(&self) -> &[String] {
+        &self.taken
+    }
+
+    /// Returns the keys of the conflicts decided for this store.
+    pub fn kept(&self) -> &[String] {
+        &self.kept
+    }
+
+    /// Returns the number of entries that were the same in both stores.
+    pub fn unchanged(&self) -> usize {
+        self.unchanged
+    }
+}
@@ -0,0 +1,166 @@
+//! Vault statistics for dashboards.
+//!
+//! [`crate::Keynest::metrics`] gathers in one pass what a status bar or a GUI shows
+//! about an open keystore: entry counts, sizes, when it was last saved, the cost of its
+//! KDF, how many entries are due for rotation, and whether it is locked. Nothing here is
+//! secret; values are only measured, never returned.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::crypto::KdfParams;
+use crate::store::SecretEntry;
+
+/// How far ahead [`RotationMetrics::expiring_soon`] looks.
+pub const EXPIRING_SOON: Duration = Duration::days(30);
+
+/// Statistics about an open keystore.
+///
+/// Returned by [`crate::Keynest::metrics`].
+#[derive(Serialize, Debug, Clone)]
+pub struct Metrics {
+    pub(crate) entries: usize,
+    pub(crate) kinds: BTreeMap<String, usize>,
+    pub(crate) tags: usize,
+    pub(crate) pinned: usize,
+    pub(crate) attachments: usize,
+    pub(crate) value_bytes: u64,
+    pub(crate) attachment_bytes: u64,
+    pub(crate) file_size: u64,
+    pub(crate) journal_size: u64,
+    pub(crate) last_saved: Option<String>,
+    pub(crate) kdf: KdfParams,
+    pub(crate) kdf_memory_kib: u64,
+    pub(crate) rotation: RotationMetrics,
+    pub(crate) locked: bool,
+    pub(crate) read_only: bool,
+}
+
+/// Rotation compliance: how many entries have an expiry, and how many of them are due.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RotationMetrics {
+    with_expiry: usize,
+    expired: usize,
+    expiring_soon: usize,
+}
+
+impl RotationMetrics {
+    /// Counts the expiries of `entries` as of `now`.
+    pub(crate) fn new<'a>(
+        entries: impl Iterator<Item = &'a SecretEntry>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let mut rotation = Self::default();
+        for expires in entries.filter_map(SecretEntry::expires) {
+            rotation.with_expiry += 1;
+            if expires <= now {
+                rotation.expired += 1;
+            } else if expires <= now + EXPIRING_SOON {
+                rotation.expiring_soon += 1;
+            }
+        }
+        rotation
+    }
+
+    /// Returns the number of entries with an expiry.
+    pub fn with_expiry(&self) -> usize {
+        self.with_expiry
+    }
+
+    /// Returns the number of entries whose expiry has passed.
+    pub fn expired(&self) -> usize {
+        self.expired
+    }
+
+    /// Returns the number of entries that expire within [`EXPIRING_SOON`].
+    pub fn expiring_soon(&self) -> usize {
+        self.expiring_soon
+    }
+
+    /// Returns the share of entries with an expiry that have not expired, from 0 to 1,
+    /// or `None` if no entry has one.
+    pub fn compliance(&self) -> Option<f64> {
+        (self.with_expiry > 0)
+            .then(|| (self.with_expiry - self.expired) as f64 / self.with_expiry as f64)
+    }
+}
+
+impl Metrics {
+    /// Returns the number of entries.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Returns the number of entries of each kind (`secret`, `note`, ...), omitting kinds
+    /// without entries.
+    pub fn kinds(&self) -> &BTreeMap<String, usize> {
+        &self.kinds
+    }
+
+    /// Returns the number of distinct tags.
+    pub fn tags(&self) -> usize {
+        self.tags
+    }
+
+    /// Returns the number of pinned entries.
+    pub fn pinned(&self) -> usize {
+        self.pinned
+    }
+
+    /// Returns the number of attached files.
+    pub fn attachments(&self) -> usize {
+        self.attachments
+    }
+
+    /// Returns the total length of the values in bytes.
+    pub fn value_bytes(&self) -> u64 {
+        self.value_bytes
+    }
+
+    /// Returns the total plaintext size of the attached files in bytes.
+    pub fn attachment_bytes(&self) -> u64 {
+        self.attachment_bytes
+    }
+
+    /// Returns the size of the keystore file in bytes.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Returns the size of the save journal in bytes; 0 if there is none.
+    pub fn journal_size(&self) -> u64 {
+        self.journal_size
+    }
+
+    /// Returns when the keystore file or its journal was last written (RFC 3339, UTC),
+    /// if the file system records it.
+    pub fn last_saved(&self) -> Option<&str> {
+        self.last_saved.as_deref()
+    }
+
+    /// Returns the KDF parameters the password is derived with.
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Returns the memory one derivation of the key needs, in KiB.
+    pub fn kdf_memory_kib(&self) -> u64 {
+        self.kdf_memory_kib
+    }
+
+    /// Returns the rotation compliance of the entries.
+    pub fn rotation(&self) -> &RotationMetrics {
+        &self.rotation
+    }
+
+    /// Returns `true` if the keystore is locked; the counts are then all zero.
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Returns `true` if the keystore is a read-only snapshot.
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+}
@@ -0,0 +1,187 @@
+//! Schema versioning of the decrypted store.
+//!
+//! The JSON store carries a `schema` number (payloads written before it was introduced
+//! are schema 1). On open, the decrypted JSON is passed through every registered
+//! migration between its schema and [`CURRENT_SCHEMA`] before it is deserialized, so
+//! adding or reshaping fields only needs a new migration step here.
+//!
+//! Migrations work on the store metadata (the top-level payload without `secrets`, or the
+//! index of a sectioned payload) and on single entries separately, so sections of a
+//! sectioned payload can still be decrypted and migrated on demand.
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+
+/// Schema version written by this version of keynest.
+pub(crate) const CURRENT_SCHEMA: u32 = 2;
+
+/// Schema of payloads without a `schema` field.
+const INITIAL_SCHEMA: u32 = 1;
+
+/// A single migration step from schema `to - 1` to schema `to`.
+struct Migration {
+    to: u32,
+    /// Transforms the store metadata.
+    meta: fn(&mut Map<String, Value>),
+    /// Transforms a single secret entry.
+    entry: fn(&mut Map<String, Value>),
+}
+
+/// All migrations, ordered by the schema they produce.
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: 2,
+    meta: v2_meta,
+    entry: v2_entry,
+}];
+
+/// Migrates store metadata to [`CURRENT_SCHEMA`] and returns the schema it was written
+/// with, which is needed to migrate its entries.
+///
+/// # Errors
+///
+/// Returns an error if the metadata is not a JSON object or was written with a newer
+/// schema than this version of keynest understands.
+pub(crate) fn migrate_meta(meta: &mut Value) -> Result<u32> {
+    let Some(meta) = meta.as_object_mut() else {
+        bail!("keystore payload is not a JSON object");
+    };
+
+    let schema = match meta.get("schema") {
+        None => INITIAL_SCHEMA,
+        Some(v) => match v.as_u64().and_then(|v| u32::try_from(v).ok()) {
+            Some(schema) => schema,
+            None => bail!("invalid keystore schema version"),
+        },
+    };
+    if schema > CURRENT_SCHEMA {
+        bail!(
+            "keystore uses schema version {schema}, but this version of keynest only supports \
+             up to {CURRENT_SCHEMA}; please upgrade keynest"
+        );
+    }
+
+    for migration in steps(schema) {
+        (migration.meta)(meta);
+    }
+    meta.insert("schema".to_string(), Value::from(CURRENT_SCHEMA));
+    Ok(schema)
+}
+
+/// Migrates a single entry written with schema `from` to [`CURRENT_SCHEMA`].
+pub(crate) fn migrate_entry(from: u32, entry: &mut Value) {
+    if let Some(entry) = entry.as_object_mut() {
+        for migration in steps(from) {
+            (migration.entry)(entry);
+        }
+    }
+}
+
+/// Migrates a whole single-ciphertext payload (metadata plus the `secrets` map).
+///
+/// # Errors
+///
+/// Returns the errors of [`migrate_meta`].
+pub(crate) fn migrate_store(store: &mut Value) -> Result<()> {
+    let secrets = store.as_object_mut().and_then(|s| s.remove("secrets"));
+    let from = migrate_meta(store)?;
+
+    if let Some(mut secrets) = secrets {
+        if let Some(secrets) = secrets.as_object_mut() {
+            for entry in secrets.values_mut() {
+                migrate_entry(from, entry);
+            }
+        }
+        if let Some(store) = store.as_object_mut() {
+            store.insert("secrets".to_string(), secrets);
+        }
+    }
+    Ok(())
+}
+
+fn steps(from: u32) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS.iter().filter(move |m| m.to > from)
+}
+
+/// Schema 2: timestamps are UTC RFC 3339.
+///
+/// Schema 1 stores wrote `Local::now().to_string()` (`2025-01-05 12:34:56.123 +01:00`).
+/// Values that are already RFC 3339 or cannot be parsed are kept as they are.
+fn v2_meta(meta: &mut Map<String, Value>) {
+    normalize_timestamp(meta, "creation_date");
+}
+
+fn v2_entry(entry: &mut Map<String, Value>) {
+    normalize_timestamp(entry, "updated");
+}
+
+fn normalize_timestamp(object: &mut Map<String, Value>, field: &str) {
+    let Some(Value::String(value)) = object.get_mut(field) else {
+        return;
+    };
+    if DateTime::parse_from_rfc3339(value).is_ok() {
+        return;
+    }
+    if let Ok(parsed) = DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f %:z") {
+        *value = parsed
+            .with_timezone(&Utc)
+            .to_rfc3339_opts(SecondsFormat::Secs, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn registry_is_contiguous_and_ends_at_current() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.to, INITIAL_SCHEMA + 1 + i as u32);
+        }
+        assert_eq!(
+            MIGRATIONS.last().map_or(INITIAL_SCHEMA, |m| m.to),
+            CURRENT_SCHEMA
+        );
+    }
+
+    #[test]
+    fn v1_to_v2_normalizes_local_timestamps() {
+        let mut store = json!({
+            "creation_date": "2025-01-05 12:34:56.123456789 +01:00",
+            "secrets": {
+                "a": {"key": "a", "value": "1", "updated": "2025-01-06 00:30:00 +02:00"},
+                "b": {"key": "b", "value": "2", "updated": "2025-01-07T10:00:00Z"},
+                "c": {"key": "c", "value": "3", "updated": "not a date"}
+            }
+        });
+        migrate_store(&mut store).unwrap();
+
+        assert_eq!(store["schema"], 2);
+        assert_eq!(store["creation_date"], "2025-01-05T11:34:56Z");
+        assert_eq!(store["secrets"]["a"]["updated"], "2025-01-05T22:30:00Z");
+        assert_eq!(store["secrets"]["b"]["updated"], "2025-01-07T10:00:00Z");
+        assert_eq!(store["secrets"]["c"]["updated"], "not a date");
+    }
+
+    #[test]
+    fn current_schema_is_left_unchanged() {
+        let mut meta =
+            json!({"schema": CURRENT_SCHEMA, "creation_date": "2025-01-05 12:34:56 +01:00"});
+        assert_eq!(migrate_meta(&mut meta).unwrap(), CURRENT_SCHEMA);
+        assert_eq!(meta["creation_date"], "2025-01-05 12:34:56 +01:00");
+
+        let mut entry = json!({"updated": "2025-01-05 12:34:56 +01:00"});
+        migrate_entry(CURRENT_SCHEMA, &mut entry);
+        assert_eq!(entry["updated"], "2025-01-05 12:34:56 +01:00");
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut meta = json!({"schema": CURRENT_SCHEMA + 1});
+        let err = migrate_meta(&mut meta).unwrap_err();
+        assert!(err.to_string().contains("upgrade keynest"));
+
+        assert!(migrate_meta(&mut json!({"schema": "two"})).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! OTP entries: secrets whose value is an `otpauth://` URI.
+//!
+//! Authenticator apps enroll from a `otpauth://totp/<label>?secret=<base32>&...` URI
+//! (usually shown as a QR code). Storing that URI as the value keeps everything needed
+//! to enroll another device; this module validates it and extracts what is shown to the
+//! user. TOTP entries also yield the seed for generating codes (see [`crate::Totp`]).
+
+use anyhow::{Result, bail};
+use std::fmt;
+use zeroize::Zeroizing;
+
+use crate::totp::{DEFAULT_DIGITS, DEFAULT_PERIOD, Totp, TotpAlgorithm};
+
+const SCHEME: &str = "otpauth://";
+
+/// The kind of one-time password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpKind {
+    /// Time-based (RFC 6238).
+    Totp,
+    /// Counter-based (RFC 4226).
+    Hotp,
+}
+
+impl fmt::Display for OtpKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Totp => "totp",
+            Self::Hotp => "hotp",
+        })
+    }
+}
+
+/// A validated `otpauth://` URI.
+#[derive(Debug, Clone)]
+pub struct OtpAuth {
+    kind: OtpKind,
+    label: String,
+    issuer: Option<String>,
+    uri: Zeroizing<String>,
+    totp: Option<Totp>,
+}
+
+impl OtpAuth {
+    /// Parses and validates an `otpauth://` URI.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is not an `otpauth://totp/` or `otpauth://hotp/` URI
+    /// with a label and a base32 `secret`, or if `digits`, `period`, `counter`, or
+    /// `algorithm` are invalid.
+    pub fn parse(uri: &str) -> Result<Self> {
+        let Some(rest) = strip_prefix_ignore_case(uri.trim(), SCHEME) else {
+            bail!("not an otpauth:// URI");
+        };
+        let Some((kind, rest)) = rest.split_once('/') else {
+            bail!("otpauth URI is missing a label");
+        };
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "totp" => OtpKind::Totp,
+            "hotp" => OtpKind::Hotp,
+            other => bail!("unsupported OTP type '{other}' (expected totp or hotp)"),
+        };
+
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let label = percent_decode(label)?;
+        if label.is_empty() {
+            bail!("otpauth URI is missing a label");
+        }
+
+        let mut secret = None;
+        let mut issuer = None;
+        let mut counter = None;
+        let mut digits = None;
+        let mut period = None;
+        let mut algorithm = None;
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            match name.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(Zeroizing::new(percent_decode(value)?)),
+                "issuer" => issuer = Some(percent_decode(value)?),
+                "counter" => counter = Some(parse_number(name, value)?),
+                "digits" => match parse_number(name, value)? {
+                    n @ 6..=8 => digits = Some(n as u32),
+                    _ => bail!("otpauth digits must be between 6 and 8"),
+                },
+                "period" => match parse_number(name, value)? {
+                    0 => bail!("otpauth period must be positive"),
+                    n => period = Some(n),
+                },
+                "algorithm" => match TotpAlgorithm::from_name(value) {
+                    Some(a) => algorithm = Some(a),
+                    None => bail!("unsupported otpauth algorithm '{value}'"),
+                },
+                _ => {}
+            }
+        }
+
+        let secret = match secret {
+            None => bail!("otpauth URI has no secret"),
+            Some(secret) if !is_base32(&secret) => bail!("otpauth secret is not valid base32"),
+            Some(secret) => secret,
+        };
+        if kind == OtpKind::Hotp && counter.is_none() {
+            bail!("hotp URI has no counter");
+        }
+        let totp = match kind {
+            OtpKind::Totp => Some(
+                Totp::new(&secret)?
+                    .with_algorithm(algorithm.unwrap_or_default())
+                    .with_digits(digits.unwrap_or(DEFAULT_DIGITS))?
+                    .with_period(period.unwrap_or(DEFAULT_PERIOD))?,
+            ),
+            OtpKind::Hotp => None,
+        };
+
+        Ok(Self {
+            kind,
+            label,
+            issuer,
+            uri: Zeroizing::new(uri.trim().to_string()),
+            totp,
+        })
+    }
+
+    /// Builds the `otpauth://totp/` URI of `totp`, e.g. for a seed entered by hand.
+    /// Parameters are only included if they differ from the defaults.
+    pub fn from_totp(label: &str, issuer: Option<&str>, totp: &Totp) -> Self {
+        let mut uri = Zeroizing::new(format!(
+            "{SCHEME}totp/{}?secret={}",
+            percent_encode(label),
+            *totp.seed()
+        ));
+        if let Some(issuer) = issuer {
+            uri.push_str(&format!("&issuer={}", percent_encode(issuer)));
+        }
+        if totp.algorithm() != TotpAlgorithm::default() {
+            uri.push_str(&format!("&algorithm={}", totp.algorithm()));
+        }
+        if totp.digits() != DEFAULT_DIGITS {
+            uri.push_str(&format!("&digits={}", totp.digits()));
+        }
+        if totp.period() != DEFAULT_PERIOD {
+            uri.push_str(&format!("&period={}", totp.period()));
+        }
+
+        Self {
+            kind: OtpKind::Totp,
+            label: label.to_string(),
+            issuer: issuer.map(str::to_string),
+            uri,
+            totp: Some(totp.clone()),
+        }
+    }
+
+    /// Returns `true` if `value` looks like an `otpauth://` URI (without validating it).
+    pub fn is_otp_uri(value: &str) -> bool {
+        strip_prefix_ignore_case(value.trim_start(), SCHEME).is_some()
+    }
+
+    /// Returns the kind of one-time password.
+    pub fn kind(&self) -> OtpKind {
+        self.kind
+    }
+
+    /// Returns the decoded label, usually `Issuer:account`.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the decoded `issuer` parameter, if present.
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// Returns the URI, including the secret.
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Returns the seed and parameters for generating codes, or `None` for HOTP.
+    pub fn totp(&self) -> Option<&Totp> {
+        self.totp.as_ref()
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &s[prefix.len()..])
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("otpauth {name} is not a number: '{value}'"))
+}
+
+/// Base32 (RFC 4648) as used by authenticators: case-insensitive, padding optional.
+fn is_base32(secret: &str) -> bool {
+    let data = secret.trim_end_matches('=');
+    !data.is_empty()
+        && data
+            .bytes()
+            .all(|b| matches!(b.to_ascii_uppercase(), b'A'..=b'Z' | b'2'..=b'7'))
+}
+
+/// Percent-encodes everything but unreserved characters and the `:` of `Issuer:account`.
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = s
+                    .get(i + 1..i + 3)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                let Some(byte) = hex else {
+                    bail!("invalid percent-encoding in otpauth URI");
+                };
+                out.push(byte);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| anyhow::anyhow!("otpauth URI is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_totp_uri() {
+        let otp = OtpAuth::parse(
+            "otpauth://totp/GitHub:alice%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=GitHub&digits=6&period=30",
+        )
+        .unwrap();
+        assert_eq!(otp.kind(), OtpKind::Totp);
+        assert_eq!(otp.label(), "GitHub:alice@example.com");
+        assert_eq!(otp.issuer(), Some("GitHub"));
+        assert!(otp.uri().contains("JBSWY3DPEHPK3PXP"));
+        assert_eq!(otp.totp().unwrap().period(), 30);
+    }
+
+    #[test]
+    fn builds_uri_from_seed() {
+        let totp = Totp::new("jbsw y3dp ehpk 3pxp")
+            .unwrap()
+            .with_period(60)
+            .unwrap();
+        let otp = OtpAuth::from_totp("GitHub:alice@example.com", Some("Git Hub"), &totp);
+        assert_eq!(
+            otp.uri(),
+            "otpauth://totp/GitHub:alice%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=Git%20Hub&period=60"
+        );
+
+        let parsed = OtpAuth::parse(otp.uri()).unwrap();
+        assert_eq!(parsed.label(), "GitHub:alice@example.com");
+        assert_eq!(parsed.issuer(), Some("Git Hub"));
+        assert_eq!(parsed.totp().unwrap().period(), 60);
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        for uri in [
+            "JBSWY3DPEHPK3PXP",
+            "otpauth://totp/?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://sms/x?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/x",
+            "otpauth://totp/x?secret=not-base32!",
+            "otpauth://totp/x?secret=JBSWY3DP&digits=4",
+            "otpauth://totp/x?secret=JBSWY3DP&algorithm=MD5",
+            "otpauth://hotp/x?secret=JBSWY3DP",
+        ] {
+            assert!(OtpAuth::parse(uri).is_err(), "{uri}");
+        }
+        assert!(OtpAuth::parse("otpauth://hotp/x?secret=JBSWY3DP&counter=0").is_ok());
+    }
+
+    #[test]
+    fn detects_otp_uris() {
+        assert!(OtpAuth::is_otp_uri("OTPAUTH://totp/x"));
+        assert!(!OtpAuth::is_otp_uri("hunter2"));
+    }
+}
@@ -0,0 +1,858 @@
+//! Encoding of the store into the encrypted payload.
+//!
+//! Single-ciphertext files (v1/v2) hold the whole store as one JSON document. Sectioned
+//! files (v3) hold an index plus sections of entries, each encrypted separately, so a
+//! reader only has to decrypt what it needs (see [`crate::IndexedKeynest`]).
+//!
+//! Sectioned files may encode their records differently (see [`PayloadEncoding`]): each
+//! document is serialized, then optionally compressed, then optionally padded before it
+//! is encrypted. Decoding always goes through a JSON value so migrations apply to every
+//! encoding alike.
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io::{Read, Write};
+use zeroize::Zeroizing;
+
+use crate::EntropyUse;
+use crate::crypto::{self, KEY_LEN, KdfParams, algorithm::Algorithm};
+use crate::format::{
+    CURRENT_VERSION, ChainLink, Compression, Header, Keyslot, KeystoreFile, Padding,
+    PayloadEncoding, Record, Serialization, decoy, v2,
+};
+use crate::migrations;
+use crate::sealed::Sealed;
+use crate::settings::{DeterministicRecords, PerEntryRecords, WriteFormat};
+use crate::store::{EntryKey, ParsedDelta, SecretEntry, Store, StoreDelta, StoreIndex};
+
+/// Smallest size of a padded record.
+const MIN_PADDED_LEN: usize = 512;
+/// Length of the plaintext length prefix of a padded record.
+const PADDING_PREFIX_LEN: usize = 4;
+/// Largest decompressed record, to guard against decompression bombs.
+const MAX_DECOMPRESSED_LEN: u64 = 256 * 1024 * 1024;
+
+/// What the key depends on besides the password, as recorded in the header.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KeyBinding {
+    /// DPAPI-protected secret if the key is bound to a Windows account (see
+    /// [`crate::crypto::dpapi`]).
+    pub(crate) dpapi_blob: Option<Vec<u8>>,
+    /// Whether the key is mixed with a keyfile (see [`crate::Keyfile`]).
+    pub(crate) keyfile: bool,
+    /// Keyslots wrapping the key, if it is a random data key (see [`Keyslot`]).
+    pub(crate) keyslots: Vec<Keyslot>,
+    /// Decoy of a duress keyslot, or its filler; new filler is made for keyslots
+    /// without one (see [`decoy`]).
+    pub(crate) decoy: Option<Vec<u8>>,
+}
+
+impl KeyBinding {
+    /// Returns the binding recorded in `header`.
+    pub(crate) fn of(header: &Header) -> Self {
+        Self {
+            dpapi_blob: header.dpapi_blob.clone(),
+            keyfile: header.requires_keyfile(),
+            keyslots: header.keyslots().to_vec(),
+            decoy: header.decoy().map(<[u8]>::to_vec),
+        }
+    }
+}
+
+/// Encrypts `store` into a keystore file in the format selected by its
+/// [`WriteFormat`] setting (sectioned unless the store was migrated to format v2).
+///
+/// `binding` is recorded in the header, so opening the file asks for the same factors,
+/// and so is `chain` unless the file is written in format v2.
+///
+/// # Errors
+///
+/// Returns an error if serialization or encryption fails, if the payload exceeds the
+/// store size quota in error mode, or if it does not fit the selected format.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encrypt(
+    store: &Store,
+    kdf: KdfParams,
+    algorithm: Algorithm,
+    salt: Vec<u8>,
+    encoding: PayloadEncoding,
+    binding: KeyBinding,
+    chain: Option<ChainLink>,
+    key: &[u8],
+) -> Result<KeystoreFile> {
+    match store.settings().get::<WriteFormat>()? {
+        None | Some(CURRENT_VERSION) => {
+            // Every header with keyslots carries a decoy or filler of the same length.
+            let decoy = match (binding.keyslots.is_empty(), binding.decoy) {
+                (true, _) => None,
+                (false, Some(decoy)) => Some(decoy),
+                (false, None) => Some(decoy::filler(algorithm)?),
+            };
+            let template = Header::sectioned(kdf, algorithm, salt, vec![])
+                .with_encoding(encoding)
+                .with_dpapi_blob(binding.dpapi_blob)
+                .with_keyfile(binding.keyfile)
+                .with_keyslots(binding.keyslots)
+                .with_key_check(Some(crypto::key_check::compute(key)))
+                .with_chain(chain)
+                .with_decoy(decoy);
+            encrypt_sectioned(store, template, key)
+        }
+        Some(v2::VERSION_V2) if !encoding.is_default() => bail!(
+            "format v2 cannot hold a {encoding} payload; convert the keystore back to json \
+             before migrating it to format v2"
+        ),
+        Some(v2::VERSION_V2) => encrypt_v2(store, kdf, algorithm, salt, &binding, key),
+        Some(version) => bail!("unsupported write format version {version}"),
+    }
+}
+
+/// Checks that a keystore derived with `kdf`, encrypted with `algorithm` and bound to
+/// `binding` can be written in format v2.
+///
+/// # Errors
+///
+/// Returns an error naming the key factor or algorithm that v2 cannot hold.
+pub(crate) fn check_v2(kdf: KdfParams, algorithm: Algorithm, binding: &KeyBinding) -> Result<()> {
+    if binding.dpapi_blob.is_some() {
+        bail!("format v2 cannot hold a keystore bound with DPAPI");
+    }
+    if binding.keyfile {
+        bail!("format v2 cannot hold a keystore that requires a keyfile");
+    }
+    if !binding.keyslots.is_empty() {
+        bail!("format v2 cannot hold a keystore with keyslots");
+    }
+    if !matches!(kdf, KdfParams::Argon2id(_)) {
+        bail!(
+            "format v2 cannot hold a keystore derived with {}",
+            kdf.name()
+        );
+    }
+    if algorithm != Algorithm::XChaCha20Poly1305 {
+        bail!(
+            "format v2 cannot hold a keystore encrypted with {}",
+            algorithm.name()
+        );
+    }
+    Ok(())
+}
+
+/// Encrypts `store` as one v2 ciphertext, for keynest versions that predate sections.
+/// The payload is always JSON, the only encoding v2 knows.
+///
+/// # Errors
+///
+/// Returns an error if the keystore uses a key factor v2 cannot hold (see
+/// [`check_v2`]), if the payload exceeds the store size quota in error mode, or if it
+/// is larger than v2's payload limit.
+pub(crate) fn encrypt_v2(
+    store: &Store,
+    kdf: KdfParams,
+    algorithm: Algorithm,
+    salt: Vec<u8>,
+    binding: &KeyBinding,
+    key: &[u8],
+) -> Result<KeystoreFile> {
+    check_v2(kdf, algorithm, binding)?;
+    let plaintext = Zeroizing::new(serde_json::to_vec(store)?);
+    store.quotas().check_store_size(plaintext.len())?;
+    if plaintext.len() > v2::MAX_PAYLOAD {
+        bail!(
+            "keystore is too large for format v2 ({} bytes, limit {} bytes)",
+            plaintext.len(),
+            v2::MAX_PAYLOAD
+        );
+    }
+
+    let (header, ciphertext) = Header::encrypt_store(kdf, algorithm, salt, key, &plaintext)?;
+    Ok(KeystoreFile::new(header, ciphertext))
+}
+
+/// Encrypts `store` into a sectioned keystore file with a header like `template`.
+fn encrypt_sectioned(store: &Store, template: Header, key: &[u8]) -> Result<KeystoreFile> {
+    let encoding = template.encoding();
+    let sections = store.sections();
+    let serialized = sections
+        .iter()
+        .map(|section| serialize(encoding, section))
+        .collect::<Result<Vec<_>>>()?;
+
+    // The quota applies to the serialized documents, before compression and padding.
+    let mut size: usize = serialized.iter().map(|p| p.len()).sum();
+    let plaintexts = serialized
+        .iter()
+        .map(|p| pack(encoding, p))
+        .collect::<Result<Vec<_>>>()?;
+
+    let deterministic = store
+        .settings()
+        .get::<DeterministicRecords>()?
+        .unwrap_or(false);
+    let entry_keys = if store.settings().get::<PerEntryRecords>()?.unwrap_or(false) {
+        plaintexts
+            .iter()
+            .enumerate()
+            .map(|(i, p)| entry_key(&template, key, i as u32 + 1, p, deterministic))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
+    let wrapped = entry_keys
+        .iter()
+        .enumerate()
+        .map(|(i, k)| template.wrap_entry_key(key, i as u32 + 1, k, deterministic))
+        .collect::<Result<Vec<_>>>()?;
+
+    let file = KeystoreFile::encrypt_sectioned(
+        template,
+        key,
+        &plaintexts,
+        &entry_keys,
+        deterministic,
+        |records| {
+            let nonces: Vec<&[u8]> = records.iter().map(Record::nonce).collect();
+            let entry_keys = wrapped
+                .iter()
+                .zip(records)
+                .map(|((wrapped, nonce), record)| {
+                    EntryKey::new(nonce, wrapped, record.ciphertext())
+                })
+                .collect();
+            let index = serialize(encoding, &store.index(&sections, &nonces, entry_keys))?;
+            size += index.len();
+            pack(encoding, &index)
+        },
+    )?;
+
+    store.quotas().check_store_size(size)?;
+    Ok(file)
+}
+
+/// Draws the key of section record `record` of a per-entry store. Deterministic records
+/// derive it from the plaintext instead, so an unchanged record still encrypts to the
+/// same bytes.
+fn entry_key(
+    header: &Header,
+    key: &[u8],
+    record: u32,
+    plaintext: &[u8],
+    deterministic: bool,
+) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    if deterministic {
+        return Ok(header.derive_entry_key(key, record, plaintext));
+    }
+    let mut entry_key = Zeroizing::new([0u8; KEY_LEN]);
+    crypto::random::fill(EntropyUse::Key, &mut *entry_key)?;
+    Ok(entry_key)
+}
+
+/// Serializes a record document with the serialization of `encoding`.
+fn serialize<T: Serialize>(encoding: PayloadEncoding, value: &T) -> Result<Zeroizing<Vec<u8>>> {
+    let bytes = match encoding.serialization() {
+        Serialization::Json => serde_json::to_vec(value)?,
+        Serialization::MessagePack => rmp_serde::to_vec_named(value)?,
+    };
+    Ok(Zeroizing::new(bytes))
+}
+
+/// Compresses and pads a serialized document as selected by `encoding`.
+fn pack(encoding: PayloadEncoding, serialized: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let compressed = match encoding.compression() {
+        Compression::None => Zeroizing::new(serialized.to_vec()),
+        Compression::Deflate => {
+            let mut out = Zeroizing::new(Vec::new());
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(&mut *out, flate2::Compression::default());
+            encoder.write_all(serialized)?;
+            encoder.finish()?;
+            out
+        }
+        Compression::Zstd => Zeroizing::new(zstd::bulk::compress(
+            serialized,
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )?),
+    };
+
+    match encoding.padding() {
+        Padding::None => Ok(compressed),
+        Padding::PowerOfTwo => {
+            let len = u32::try_from(compressed.len()).context("record too large to pad")?;
+            let padded_len = (PADDING_PREFIX_LEN + compressed.len())
+                .next_power_of_two()
+                .max(MIN_PADDED_LEN);
+
+            let mut padded = Zeroizing::new(Vec::with_capacity(padded_len));
+            padded.extend_from_slice(&len.to_le_bytes());
+            padded.extend_from_slice(&compressed);
+            padded.resize(padded_len, 0);
+            Ok(padded)
+        }
+    }
+}
+
+/// Reverses [`pack`] and deserializes the document into a JSON value.
+fn decode(encoding: PayloadEncoding, plaintext: &[u8]) -> Result<serde_json::Value> {
+    let unpadded = match encoding.padding() {
+        Padding::None => plaintext,
+        Padding::PowerOfTwo => {
+            let Some((len, rest)) = plaintext.split_first_chunk::<PADDING_PREFIX_LEN>() else {
+                bail!("invalid record padding");
+            };
+            let len = u32::from_le_bytes(*len) as usize;
+            rest.get(..len).context("invalid record padding")?
+        }
+    };
+
+    let decompressed;
+    let serialized = match encoding.compression() {
+        Compression::None => unpadded,
+        Compression::Deflate => {
+            decompressed = decompress(flate2::read::DeflateDecoder::new(unpadded))?;
+            decompressed.as_slice()
+        }
+        Compression::Zstd => {
+            let decoder = zstd::stream::read::Decoder::with_buffer(unpadded)
+                .context("failed to decompress record")?;
+            decompressed = decompress(decoder)?;
+            decompressed.as_slice()
+        }
+    };
+
+    deserialize(encoding, serialized)
+}
+
+/// Reads a decompressing `reader` to the end, refusing records that decompress to more
+/// than [`MAX_DECOMPRESSED_LEN`].
+fn decompress(reader: impl Read) -> Result<Zeroizing<Vec<u8>>> {
+    let mut out = Zeroizing::new(Vec::new());
+    reader
+        .take(MAX_DECOMPRESSED_LEN + 1)
+        .read_to_end(&mut out)
+        .context("failed to decompress record")?;
+    if out.len() as u64 > MAX_DECOMPRESSED_LEN {
+        bail!("decompressed record too large");
+    }
+    Ok(out)
+}
+
+fn deserialize<T: DeserializeOwned>(encoding: PayloadEncoding, serialized: &[u8]) -> Result<T> {
+    Ok(match encoding.serialization() {
+        Serialization::Json => serde_json::from_slice(serialized)?,
+        Serialization::MessagePack => rmp_serde::from_slice(serialized)?,
+    })
+}
+
+/// Encoding of the decoy store, compressed to make the most of its fixed size.
+fn decoy_encoding() -> PayloadEncoding {
+    PayloadEncoding::new(Serialization::MessagePack, Compression::Zstd, Padding::None)
+}
+
+/// Encrypts `store` with `key` as the decoy of a duress keyslot (see [`decoy`]).
+///
+/// # Errors
+///
+/// Returns an error if the encoded store does not fit the decoy or encryption fails.
+pub(crate) fn encrypt_decoy(store: &Store, algorithm: Algorithm, key: &[u8]) -> Result<Vec<u8>> {
+    let encoding = decoy_encoding();
+    let document = pack(encoding, &serialize(encoding, store)?)?;
+    decoy::seal(algorithm, key, &document)
+}
+
+/// Decrypts the decoy in `header` with `key`.
+///
+/// # Errors
+///
+/// Returns an error if the header has no decoy, `key` does not open it, or the store in
+/// it is malformed.
+pub(crate) fn decrypt_decoy(header: &Header, key: &[u8]) -> Result<Store> {
+    let blob = header.decoy().context("the keystore has no decoy")?;
+    let document = decoy::open(header.algorithm(), key, blob)?;
+    let mut store = decode(decoy_encoding(), &document)?;
+    migrations::migrate_store(&mut store)?;
+    Ok(serde_json::from_value(store)?)
+}
+
+/// Decrypts the whole store from `file`, whatever its layout.
+///
+/// # Errors
+///
+/// Returns an error if decryption fails (wrong password or tampered data) or the
+/// payload is malformed.
+pub(crate) fn decrypt(file: &KeystoreFile, key: &[u8]) -> Result<Store> {
+    match open(file, key)? {
+        Opened::Store(store) => Ok(store),
+        Opened::Sealed(sealed) => sealed.unseal(key),
+    }
+}
+
+/// A decrypted keystore file.
+pub(crate) enum Opened {
+    /// The whole store.
+    Store(Store),
+    /// A per-entry store whose records are left sealed.
+    Sealed(Box<Sealed>),
+}
+
+/// Decrypts `file` like [`decrypt`], except that the records of a per-entry store are
+/// only checked against the index and left sealed (see [`crate::sealed`]).
+///
+/// # Errors
+///
+/// Returns an error if decryption fails (wrong password or tampered data) or the
+/// payload is malformed.
+pub(crate) fn open(file: &KeystoreFile, key: &[u8]) -> Result<Opened> {
+    let plaintext = file.decrypt(key)?;
+    let encoding = file.header.encoding();
+    if !file.is_sectioned() {
+        let mut store = decode(encoding, &plaintext)
+            .context("failed to deserialize keystore; possibly wrong password or corrupted data")?;
+        migrations::migrate_store(&mut store)?;
+        let store = serde_json::from_value(store)
+            .context("failed to deserialize keystore; possibly wrong password or corrupted data")?;
+        return Ok(Opened::Store(store));
+    }
+
+    let (index, schema) = parse_index(&plaintext, encoding)?;
+    index.verify_sections(file.sections().iter().map(|r| r.nonce()))?;
+    if index.has_entry_keys() {
+        let sealed = Sealed::new(file.header.clone(), index, schema, file.sections())?;
+        return Ok(Opened::Sealed(Box::new(sealed)));
+    }
+
+    let sections = decrypt_sections(&file.header, file.sections(), &index, key, schema)?;
+    Ok(Opened::Store(Store::from_sections(index, sections)?))
+}
+
+/// Decrypts and parses the section `records` of a file with `header` on the rayon
+/// thread pool.
+///
+/// Sections are independent, so opening a large store for `export` or `list --all`
+/// scales with the number of cores; results keep the section order.
+#[cfg(feature = "parallel")]
+pub(crate) fn decrypt_sections(
+    header: &Header,
+    records: &[Record],
+    index: &StoreIndex,
+    key: &[u8],
+    schema: u32,
+) -> Result<Vec<Vec<SecretEntry>>> {
+    use rayon::prelude::*;
+
+    records
+        .par_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let plaintext = decrypt_section(
+                header,
+                index,
+                key,
+                i as u32,
+                record.nonce(),
+                record.ciphertext(),
+            )?;
+            parse_section(&plaintext, schema, header.encoding())
+        })
+        .collect()
+}
+
+/// Decrypts and parses the section `records` of a file with `header`.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn decrypt_sections(
+    header: &Header,
+    records: &[Record],
+    index: &StoreIndex,
+    key: &[u8],
+    schema: u32,
+) -> Result<Vec<Vec<SecretEntry>>> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let plaintext = decrypt_section(
+                header,
+                index,
+                key,
+                i as u32,
+                record.nonce(),
+                record.ciphertext(),
+            )?;
+            parse_section(&plaintext, schema, header.encoding())
+        })
+        .collect()
+}
+
+/// Decrypts section `section` (0-based) of a sectioned file with `header` and `index`:
+/// with its entry key if the index has entry keys, with `key` otherwise.
+///
+/// # Errors
+///
+/// Returns an error if the entry key or the section fails to authenticate.
+pub(crate) fn decrypt_section(
+    header: &Header,
+    index: &StoreIndex,
+    key: &[u8],
+    section: u32,
+    nonce: &[u8],
+    ciphertext: &[u8],
+) -> Result<Zeroizing<Vec<u8>>> {
+    let record = section + 1;
+    let Some(entry_key) = index.entry_key(section) else {
+        return header.decrypt_record(key, record, nonce, ciphertext);
+    };
+    let (key_nonce, wrapped) = entry_key.wrapped()?;
+    let entry_key = header.unwrap_entry_key(key, record, &key_nonce, &wrapped)?;
+    header.decrypt_record(&*entry_key, record, nonce, ciphertext)
+}
+
+/// Parses and migrates a decrypted index. Also returns the schema it was written with,
+/// which [`parse_section`] needs to migrate the entries.
+pub(crate) fn parse_index(
+    plaintext: &[u8],
+    encoding: PayloadEncoding,
+) -> Result<(StoreIndex, u32)> {
+    let mut index = decode(encoding, plaintext)
+        .context("failed to deserialize keystore index; possibly corrupted data")?;
+    let schema = migrations::migrate_meta(&mut index)?;
+    let index = serde_json::from_value(index)
+        .context("failed to deserialize keystore index; possibly corrupted data")?;
+    Ok((index, schema))
+}
+
+/// Parses a decrypted section written with `schema`, migrating its entries.
+pub(crate) fn parse_section(
+    plaintext: &[u8],
+    schema: u32,
+    encoding: PayloadEncoding,
+) -> Result<Vec<SecretEntry>> {
+    let mut entries: Vec<serde_json::Value> = serde_json::from_value(decode(encoding, plaintext)?)
+        .context("failed to deserialize keystore section; possibly corrupted data")?;
+    for entry in &mut entries {
+        migrations::migrate_entry(schema, entry);
+    }
+    entries
+        .into_iter()
+        .map(serde_json::from_value)
+        .collect::<Result<_, _>>()
+        .context("failed to deserialize keystore section; possibly corrupted data")
+}
+
+/// Encodes the changes of one save for the journal (see [`crate::journal`]).
+///
+/// # Errors
+///
+/// Returns an error if serialization or compression fails.
+pub(crate) fn encode_delta(
+    encoding: PayloadEncoding,
+    delta: &StoreDelta,
+) -> Result<Zeroizing<Vec<u8>>> {
+    pack(encoding, &serialize(encoding, delta)?)
+}
+
+/// Parses a decrypted journal frame, migrating its metadata and entries.
+pub(crate) fn parse_delta(plaintext: &[u8], encoding: PayloadEncoding) -> Result<ParsedDelta> {
+    let mut delta = decode(encoding, plaintext)
+        .context("failed to deserialize journal frame; possibly corrupted data")?;
+    let Some(fields) = delta.as_object_mut() else {
+        bail!("failed to deserialize journal frame; possibly corrupted data");
+    };
+    let schema = fields
+        .get("schema")
+        .and_then(serde_json::Value::as_u64)
+        .and_then(|v| u32::try_from(v).ok())
+        .context("invalid journal schema version")?;
+    if schema > migrations::CURRENT_SCHEMA {
+        bail!(
+            "journal uses schema version {schema}, but this version of keynest only supports \
+             up to {}; please upgrade keynest",
+            migrations::CURRENT_SCHEMA
+        );
+    }
+    if let Some(meta) = fields.get_mut("meta") {
+        migrations::migrate_meta(meta)?;
+    }
+    if let Some(secrets) = fields.get_mut("secrets").and_then(|s| s.as_object_mut()) {
+        for entry in secrets.values_mut() {
+            migrations::migrate_entry(schema, entry);
+        }
+    }
+    serde_json::from_value(delta)
+        .context("failed to deserialize journal frame; possibly corrupted data")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{parse, serialize};
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    fn encrypt_store(store: &Store) -> KeystoreFile {
+        encrypt(
+            store,
+            KdfParams::default(),
+            Algorithm::XChaCha20Poly1305,
+            vec![1u8; 16],
+            PayloadEncoding::default(),
+            KeyBinding::default(),
+            None,
+            &KEY,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn sectioned_roundtrip() {
+        let mut store = Store::new();
+        for i in 0..600 {
+            store
+                .set(&format!("key{i:04}"), &format!("value{i}"))
+                .unwrap();
+        }
+
+        let file = encrypt_store(&store);
+        assert_eq!(file.sections().len(), 3);
+
+        let parsed = parse(&serialize(&file).unwrap()).unwrap();
+        let decrypted = decrypt(&parsed, &KEY).unwrap();
+        assert_eq!(decrypted.len(), 600);
+        assert_eq!(decrypted.get("key0599"), Some("value599"));
+        assert_eq!(decrypted.creation_date(), store.creation_date());
+    }
+
+    #[test]
+    fn every_encoding_roundtrips() {
+        let mut store = Store::new();
+        for i in 0..600 {
+            store
+                .set(&format!("key{i:04}"), &format!("value{i}"))
+                .unwrap();
+        }
+
+        for serialization in [Serialization::Json, Serialization::MessagePack] {
+            for compression in [Compression::None, Compression::Deflate, Compression::Zstd] {
+                for padding in [Padding::None, Padding::PowerOfTwo] {
+                    let encoding = PayloadEncoding::new(serialization, compression, padding);
+                    let file = encrypt(
+                        &store,
+                        KdfParams::default(),
+                        Algorithm::XChaCha20Poly1305,
+                        vec![1u8; 16],
+                        encoding,
+                        KeyBinding::default(),
+                        None,
+                        &KEY,
+                    )
+                    .unwrap();
+
+                    let parsed = parse(&serialize(&file).unwrap()).unwrap();
+                    assert_eq!(parsed.header.encoding(), encoding);
+                    let decrypted = decrypt(&parsed, &KEY).unwrap();
+                    assert_eq!(decrypted.len(), 600, "{encoding}");
+                    assert_eq!(decrypted.get("key0599"), Some("value599"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn padding_hides_the_record_size() {
+        let encoding =
+            PayloadEncoding::new(Serialization::Json, Compression::None, Padding::PowerOfTwo);
+        let short = pack(encoding, b"[]").unwrap();
+        let long = pack(encoding, &[b' '; 300]).unwrap();
+        assert_eq!(short.len(), MIN_PADDED_LEN);
+        assert_eq!(long.len(), MIN_PADDED_LEN);
+        assert_eq!(pack(encoding, &[b' '; 600]).unwrap().len(), 1024);
+
+        let mut truncated = short.clone();
+        truncated[0] = 0xff;
+        assert!(decode(encoding, &truncated).is_err());
+    }
+
+    #[test]
+    fn empty_store_has_no_sections() {
+        let file = encrypt_store(&Store::new());
+        assert!(file.sections().is_empty());
+        assert_eq!(decrypt(&file, &KEY).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn single_ciphertext_payload_still_decrypts() {
+        let mut store = Store::new();
+        store.set("a", "b").unwrap();
+        let plaintext = serde_json::to_vec(&store).unwrap();
+
+        let (header, ciphertext) = Header::encrypt_store(
+            KdfParams::default(),
+            Algorithm::XChaCha20Poly1305,
+            vec![1u8; 16],
+            &KEY,
+            &plaintext,
+        )
+        .unwrap();
+        let file = KeystoreFile::new(header, ciphertext);
+
+        assert_eq!(decrypt(&file, &KEY).unwrap().get("a"), Some("b"));
+    }
+
+    #[test]
+    fn schema_1_payload_is_migrated_on_open() {
+        let plaintext = serde_json::to_vec(&serde_json::json!({
+            "creation_date": "2025-01-05 12:34:56.5 +01:00",
+            "secrets": {"a": {"key": "a", "value": "b", "updated": "2025-01-06 08:00:00 +00:00"}}
+        }))
+        .unwrap();
+        let (header, ciphertext) = Header::encrypt_store(
+            KdfParams::default(),
+            Algorithm::XChaCha20Poly1305,
+            vec![1u8; 16],
+            &KEY,
+            &plaintext,
+        )
+        .unwrap();
+
+        let store = decrypt(&KeystoreFile::new(header, ciphertext), &KEY).unwrap();
+        assert_eq!(store.creation_date(), "2025-01-05T11:34:56Z");
+        assert_eq!(
+            store.entries().next().unwrap().updated(),
+            "2025-01-06T08:00:00Z"
+        );
+
+        // Saving writes the current schema, so the store reopens without migrating.
+        let reopened = decrypt(&encrypt_store(&store), &KEY).unwrap();
+        assert_eq!(reopened.creation_date(), "2025-01-05T11:34:56Z");
+    }
+
+    #[test]
+    fn sections_cannot_be_swapped() {
+        let mut store = Store::new();
+        for i in 0..300 {
+            store.set(&format!("key{i:04}"), "v").unwrap();
+        }
+
+        let mut file = encrypt_store(&store);
+        file.sections.swap(0, 1);
+        assert!(decrypt(&file, &KEY).is_err());
+    }
+
+    #[test]
+    fn sections_from_another_save_are_rejected() {
+        let mut store = Store::new();
+        store.set("a", "b").unwrap();
+
+        let mut file = encrypt_store(&store);
+        let other = encrypt_store(&store);
+        file.sections[0] = other.sections[0].clone();
+
+        let err = decrypt(&file, &KEY).unwrap_err();
+        assert!(err.to_string().contains("does not belong"));
+    }
+
+    #[test]
+    fn per_entry_records_are_encrypted_with_their_own_keys() {
+        let mut store = Store::new();
+        store.settings_mut().set::<PerEntryRecords>(&true).unwrap();
+        store.set("a", "1").unwrap();
+        store.set("b", "2").unwrap();
+
+        let mut file = encrypt_store(&store);
+        assert_eq!(file.sections().len(), 2);
+        assert!(file.decrypt_section(&KEY, 0).is_err());
+        assert_eq!(decrypt(&file, &KEY).unwrap().get("b"), Some("2"));
+
+        // Records are checked against the index before they are decrypted.
+        let record = &file.sections[1];
+        let mut ciphertext = record.ciphertext().to_vec();
+        ciphertext[0] ^= 1;
+        file.sections[1] = Record::new(record.nonce().to_vec(), ciphertext);
+        let Err(err) = open(&file, &KEY) else {
+            panic!("a tampered record was left sealed");
+        };
+        assert!(
+            err.to_string().contains("does not match the index"),
+            "{err}"
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_decryption_matches_the_sequential_path() {
+        // Grouped sections, then one record per entry, each with its own entry key.
+        for (per_entry, count, sections) in [(false, 1000, 4), (true, 300, 300)] {
+            let mut store = Store::new();
+            store
+                .settings_mut()
+                .set::<PerEntryRecords>(&per_entry)
+                .unwrap();
+            for i in 0..count {
+                store
+                    .set(&format!("key{i:04}"), &format!("value{i}"))
+                    .unwrap();
+            }
+            let mut file = encrypt_store(&store);
+            assert_eq!(file.sections().len(), sections);
+            let encoding = file.header.encoding();
+            let (index, schema) = parse_index(&file.decrypt(&KEY).unwrap(), encoding).unwrap();
+            assert_eq!(index.has_entry_keys(), per_entry);
+
+            let sequential = |file: &KeystoreFile| -> Result<Vec<Vec<SecretEntry>>> {
+                file.sections()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, record)| {
+                        let plaintext = decrypt_section(
+                            &file.header,
+                            &index,
+                            &KEY,
+                            i as u32,
+                            record.nonce(),
+                            record.ciphertext(),
+                        )?;
+                        parse_section(&plaintext, schema, encoding)
+                    })
+                    .collect()
+            };
+            let keys = |sections: Vec<Vec<SecretEntry>>| -> Vec<Vec<String>> {
+                sections
+                    .into_iter()
+                    .map(|section| section.iter().map(|e| e.key().to_string()).collect())
+                    .collect()
+            };
+            // More threads than cores, so the sections are decrypted concurrently even on
+            // a single-core machine.
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(4)
+                .build()
+                .unwrap();
+            let parallel = |file: &KeystoreFile| {
+                pool.install(|| {
+                    decrypt_sections(&file.header, file.sections(), &index, &KEY, schema)
+                })
+            };
+
+            let expected = keys(sequential(&file).unwrap());
+            assert_eq!(keys(parallel(&file).unwrap()), expected);
+            let all: Vec<String> = store.entries().map(|e| e.key().to_string()).collect();
+            assert_eq!(expected.concat(), all);
+            if per_entry {
+                // Unsealing a keystore opened sealed takes the same parallel path.
+                let Ok(Opened::Sealed(sealed)) = open(&file, &KEY) else {
+                    panic!("a per-entry keystore opens sealed");
+                };
+                let unsealed = pool.install(|| sealed.unseal(&KEY)).unwrap();
+                assert_eq!(unsealed.keys().cloned().collect::<Vec<_>>(), all);
+            }
+
+            let record = &file.sections[2];
+            let mut ciphertext = record.ciphertext().to_vec();
+            ciphertext[0] ^= 1;
+            file.sections[2] = Record::new(record.nonce().to_vec(), ciphertext);
+            assert_eq!(
+                parallel(&file).unwrap_err().to_string(),
+                sequential(&file).unwrap_err().to_string()
+            );
+        }
+    }
+}
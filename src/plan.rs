@@ -0,0 +1,374 @@
+//! Provisioning plans: several interdependent secrets created in one run.
+//!
+//! A plan lists steps, each creating one entry: a fixed or generated value, a `ref:`
+//! reference to another entry, an SSH CA key, an SSH key pair, or an SSH certificate
+//! signed by a CA for a key pair. Steps that use another entry run after the step
+//! creating it, whatever their order in the file (see [`Plan::order`]), and
+//! [`crate::Keynest::apply_plan`] applies them in one transaction, so a failing step
+//! leaves the store unchanged:
+//!
+//! ```yaml
+//! steps:
+//!   - key: ssh/web1/cert
+//!     ssh-cert: { ca: ssh/ca, subject: ssh/web1/key, identity: web1, principals: [web1.lan], host: true }
+//!   - key: ssh/ca
+//!     ssh-ca: { comment: prod-ca }
+//!   - key: ssh/web1/key
+//!     ssh-key: { comment: web1 }
+//!   - key: db/password
+//!     generate: { length: 32, symbols: true }
+//!     tags: [db]
+//!     fields: { user: app }
+//!   - key: app/db-password
+//!     ref: db/password
+//! ```
+//!
+//! Entries that already exist are kept, so applying a plan again only creates what is
+//! missing.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use zeroize::Zeroizing;
+
+use crate::ssh::{CertKind, CertificateRequest, SshCa};
+use crate::store::{REFERENCE_PREFIX, validate_key};
+use crate::template::Rule;
+
+/// Certificates are backdated by this many seconds to tolerate clock skew.
+const BACKDATE_SECS: i64 = 5 * 60;
+
+/// Field holding the public key of entries created by `ssh-ca` and `ssh-key` steps.
+pub const PUBLIC_KEY_FIELD: &str = "public-key";
+
+/// A provisioning plan; see the [module documentation](self).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    steps: Vec<Step>,
+}
+
+/// One entry to create.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Step {
+    key: String,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default, rename = "ref")]
+    reference: Option<String>,
+    #[serde(default)]
+    generate: Option<Rule>,
+    #[serde(default)]
+    ssh_ca: Option<SshKeySpec>,
+    #[serde(default)]
+    ssh_key: Option<SshKeySpec>,
+    #[serde(default)]
+    ssh_cert: Option<SshCertSpec>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    fields: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct SshKeySpec {
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct SshCertSpec {
+    ca: String,
+    subject: String,
+    identity: String,
+    principals: Vec<String>,
+    #[serde(default)]
+    host: bool,
+    #[serde(default = "default_days")]
+    days: u32,
+    #[serde(default)]
+    serial: u64,
+}
+
+fn default_days() -> u32 {
+    365
+}
+
+/// The value of an entry created by a step, and the fields that come with it.
+pub(crate) struct Produced {
+    pub(crate) value: Zeroizing<String>,
+    pub(crate) fields: Vec<(String, String)>,
+}
+
+impl Produced {
+    fn value(value: Zeroizing<String>) -> Self {
+        Self {
+            value,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// What applying a step did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The entry was created.
+    Created,
+    /// The entry already existed and was kept.
+    Kept,
+}
+
+impl Plan {
+    /// Parses a plan from YAML (or JSON) and checks its steps.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for invalid YAML, unknown keys, or invalid steps (see
+    /// [`Plan::validate`]).
+    pub fn from_yaml(text: &str) -> Result<Self> {
+        let plan: Self = serde_yaml::from_str(text)?;
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    /// Checks that every step has a valid key and exactly one way to create its value,
+    /// that no key is created twice, and that the steps can be ordered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first problem found.
+    pub fn validate(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for step in &self.steps {
+            validate_key(&step.key).with_context(|| format!("invalid step key '{}'", step.key))?;
+            step.check()
+                .with_context(|| format!("step '{}'", step.key))?;
+            if !seen.insert(step.key.as_str()) {
+                bail!("'{}' is created by more than one step", step.key);
+            }
+        }
+        self.order().map(|_| ())
+    }
+
+    /// Returns the steps in the order they run: every step after the steps creating the
+    /// entries it uses, otherwise in file order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if steps depend on each other in a cycle.
+    pub fn order(&self) -> Result<Vec<&Step>> {
+        let index: HashMap<&str, usize> = self
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| (step.key.as_str(), i))
+            .collect();
+        let mut done = vec![false; self.steps.len()];
+        let mut order = Vec::with_capacity(self.steps.len());
+        while order.len() < self.steps.len() {
+            let ready = self.steps.iter().enumerate().position(|(i, step)| {
+                !done[i]
+                    && step
+                        .dependencies()
+                        .iter()
+                        .all(|dep| index.get(dep).is_none_or(|&j| done[j]))
+            });
+            let Some(i) = ready else {
+                let stuck: Vec<_> = self
+                    .steps
+                    .iter()
+                    .zip(&done)
+                    .filter(|(_, done)| !**done)
+                    .map(|(step, _)| step.key.as_str())
+                    .collect();
+                bail!(
+                    "steps depend on each other in a cycle: {}",
+                    stuck.join(", ")
+                );
+            };
+            done[i] = true;
+            order.push(&self.steps[i]);
+        }
+        Ok(order)
+    }
+
+    /// Returns the steps in file order.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+}
+
+impl Step {
+    /// Returns the key of the entry the step creates.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns a short description of how the value is created, e.g. `generate` or
+    /// `ref db/password`.
+    pub fn describe(&self) -> String {
+        if let Some(target) = &self.reference {
+            format!("ref {target}")
+        } else if self.generate.is_some() {
+            "generate".to_string()
+        } else if self.ssh_ca.is_some() {
+            "ssh-ca".to_string()
+        } else if self.ssh_key.is_some() {
+            "ssh-key".to_string()
+        } else if let Some(cert) = &self.ssh_cert {
+            format!("ssh-cert {} signed by {}", cert.subject, cert.ca)
+        } else {
+            "value".to_string()
+        }
+    }
+
+    /// Returns the keys of the entries the step uses.
+    pub fn dependencies(&self) -> Vec<&str> {
+        match (&self.reference, &self.ssh_cert) {
+            (Some(target), _) => vec![target.as_str()],
+            (_, Some(cert)) => vec![cert.ca.as_str(), cert.subject.as_str()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the tags of the entry.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Returns the fields of the entry, by name.
+    pub fn fields(&self) -> &BTreeMap<String, String> {
+        &self.fields
+    }
+
+    fn check(&self) -> Result<()> {
+        let sources = [
+            self.value.is_some(),
+            self.reference.is_some(),
+            self.generate.is_some(),
+            self.ssh_ca.is_some(),
+            self.ssh_key.is_some(),
+            self.ssh_cert.is_some(),
+        ];
+        if sources.iter().filter(|set| **set).count() != 1 {
+            bail!("set exactly one of value, ref, generate, ssh-ca, ssh-key and ssh-cert");
+        }
+        if let Some(rule) = &self.generate {
+            if !rule.generates() {
+                bail!("generate needs length or words");
+            }
+            rule.validate("generate")?;
+        }
+        if let Some(cert) = &self.ssh_cert {
+            if cert.principals.is_empty() {
+                bail!("ssh-cert needs at least one principal");
+            }
+            if cert.days == 0 {
+                bail!("ssh-cert needs a validity of at least one day");
+            }
+        }
+        if let Some(target) = &self.reference {
+            validate_key(target).with_context(|| format!("invalid ref '{target}'"))?;
+        }
+        Ok(())
+    }
+
+    /// Creates the value of the entry and the fields that come with it. `value_of`
+    /// returns the value of an entry the step uses, with references resolved; `now` is
+    /// the current Unix time.
+    pub(crate) fn produce(
+        &self,
+        mut value_of: impl FnMut(&str) -> Result<Option<Zeroizing<String>>>,
+        now: i64,
+    ) -> Result<Produced> {
+        let mut value_of =
+            |key: &str| value_of(key)?.with_context(|| format!("'{key}' does not exist"));
+        if let Some(value) = &self.value {
+            return Ok(Produced::value(Zeroizing::new(value.clone())));
+        }
+        if let Some(target) = &self.reference {
+            let reference = format!("{REFERENCE_PREFIX}{target}");
+            return Ok(Produced::value(Zeroizing::new(reference)));
+        }
+        if let Some(rule) = &self.generate {
+            let value = rule.generate()?.context("generate needs length or words")?;
+            return Ok(Produced::value(value));
+        }
+        if let Some(spec) = self.ssh_ca.as_ref().or(self.ssh_key.as_ref()) {
+            let comment = spec.comment.as_deref().unwrap_or(&self.key);
+            let key = SshCa::generate(comment)?;
+            return Ok(Produced {
+                value: key.to_openssh()?,
+                fields: vec![(PUBLIC_KEY_FIELD.to_string(), key.public_key())],
+            });
+        }
+        let cert = self.ssh_cert.as_ref().context("step creates no value")?;
+        let ca = SshCa::from_openssh(&value_of(&cert.ca)?)
+            .with_context(|| format!("invalid SSH CA entry '{}'", cert.ca))?;
+        let subject = SshCa::from_openssh(&value_of(&cert.subject)?)
+            .with_context(|| format!("invalid SSH key entry '{}'", cert.subject))?;
+        let kind = if cert.host {
+            CertKind::Host
+        } else {
+            CertKind::User
+        };
+        let valid_after = u64::try_from(now - BACKDATE_SECS).unwrap_or(0);
+        let valid_before = u64::try_from(now)
+            .unwrap_or(0)
+            .saturating_add(u64::from(cert.days) * 24 * 60 * 60);
+        let request = CertificateRequest::new(
+            kind,
+            &cert.identity,
+            cert.principals.clone(),
+            valid_after,
+            valid_before,
+        )
+        .with_serial(cert.serial);
+        let certificate = ca.sign(&subject.public_key(), &request)?;
+        Ok(Produced::value(Zeroizing::new(certificate)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_run_after_their_dependencies() {
+        let plan = Plan::from_yaml(
+            "
+            steps:
+              - key: cert
+                ssh-cert: { ca: ca, subject: host, identity: h, principals: [h] }
+              - key: alias
+                ref: db
+              - key: ca
+                ssh-ca: {}
+              - key: host
+                ssh-key: {}
+              - key: db
+                generate: { length: 20 }
+            ",
+        )
+        .unwrap();
+        let order: Vec<_> = plan.order().unwrap().iter().map(|s| s.key()).collect();
+        assert_eq!(order, ["ca", "host", "cert", "db", "alias"]);
+
+        let cycle = "steps:\n  - {key: a, ref: b}\n  - {key: b, ref: a}\n";
+        let err = Plan::from_yaml(cycle).unwrap_err().to_string();
+        assert!(err.contains("cycle"), "{err}");
+
+        for invalid in [
+            "steps:\n  - {key: a}\n",
+            "steps:\n  - {key: a, value: x, ref: b}\n",
+            "steps:\n  - {key: a, value: x}\n  - {key: a, value: y}\n",
+            "steps:\n  - {key: a, generate: {default: x}}\n",
+            "steps:\n  - {key: a, value: x, colour: red}\n",
+        ] {
+            assert!(Plan::from_yaml(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Store-level quotas.
+//!
+//! Quotas cap the number of entries, the length of a single value, and the size of the
+//! serialized store, so accidental misuse (e.g. dumping a huge file into a value) is
+//! caught before it degrades every open and save.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::StoreError;
+
+/// What happens when a quota is exceeded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaEnforcement {
+    /// Print a warning to stderr and continue.
+    #[default]
+    Warn,
+    /// Reject the operation.
+    Error,
+}
+
+/// Store-level limits, persisted inside the encrypted payload.
+///
+/// A limit of `None` means unlimited. The default has no limits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quotas {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_entries: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_value_len: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_store_size: Option<u64>,
+    #[serde(default)]
+    enforcement: QuotaEnforcement,
+}
+
+impl Quotas {
+    /// Creates a new set of quotas.
+    ///
+    /// * `max_entries` - Maximum number of secrets
+    /// * `max_value_len` - Maximum length of a single value in bytes
+    /// * `max_store_size` - Maximum size of the serialized store in bytes
+    pub fn new(
+        max_entries: Option<u64>,
+        max_value_len: Option<u64>,
+        max_store_size: Option<u64>,
+        enforcement: QuotaEnforcement,
+    ) -> Self {
+        Self {
+            max_entries,
+            max_value_len,
+            max_store_size,
+            enforcement,
+        }
+    }
+
+    /// Returns the maximum number of secrets.
+    pub fn max_entries(&self) -> Option<u64> {
+        self.max_entries
+    }
+
+    /// Returns the maximum length of a single value in bytes.
+    pub fn max_value_len(&self) -> Option<u64> {
+        self.max_value_len
+    }
+
+    /// Returns the maximum size of the serialized store in bytes.
+    pub fn max_store_size(&self) -> Option<u64> {
+        self.max_store_size
+    }
+
+    /// Returns what happens when a quota is exceeded.
+    pub fn enforcement(&self) -> QuotaEnforcement {
+        self.enforcement
+    }
+
+    /// Returns `true` if no limit is configured.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_entries.is_none() && self.max_value_len.is_none() && self.max_store_size.is_none()
+    }
+
+    /// Checks the length of a value about to be stored under `key`.
+    pub(crate) fn check_value(&self, key: &str, len: usize) -> Result<(), StoreError> {
+        match self.max_value_len {
+            Some(max) if len as u64 > max => self.exceeded(format!(
+                "value of '{key}' is {len} bytes, limit is {max} bytes"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the number of entries after an insertion.
+    pub(crate) fn check_entries(&self, count: usize) -> Result<(), StoreError> {
+        match self.max_entries {
+            Some(max) if count as u64 > max => {
+                self.exceeded(format!("store would hold {count} secrets, limit is {max}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the size of the serialized store.
+    pub(crate) fn check_store_size(&self, size: usize) -> Result<(), StoreError> {
+        match self.max_store_size {
+            Some(max) if size as u64 > max => {
+                self.exceeded(format!("store is {size} bytes, limit is {max} bytes"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn exceeded(&self, message: String) -> Result<(), StoreError> {
+        match self.enforcement {
+            QuotaEnforcement::Warn => {
+                eprintln!("Warning: quota exceeded: {message}");
+                Ok(())
+            }
+            QuotaEnforcement::Error => Err(StoreError::QuotaExceeded(message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_unlimited() {
+        let quotas = Quotas::default();
+        assert!(quotas.is_unlimited());
+        assert!(quotas.check_value("k", usize::MAX).is_ok());
+        assert!(quotas.check_entries(usize::MAX).is_ok());
+        assert!(quotas.check_store_size(usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn error_mode_rejects() {
+        let quotas = Quotas::new(Some(2), Some(4), Some(100), QuotaEnforcement::Error);
+
+        assert!(quotas.check_value("k", 4).is_ok());
+        assert!(matches!(
+            quotas.check_value("k", 5),
+            Err(StoreError::QuotaExceeded(_))
+        ));
+        assert!(quotas.check_entries(2).is_ok());
+        assert!(quotas.check_entries(3).is_err());
+        assert!(quotas.check_store_size(101).is_err());
+    }
+
+    #[test]
+    fn warn_mode_allows() {
+        let quotas = Quotas::new(Some(1), Some(1), Some(1), QuotaEnforcement::Warn);
+        assert!(quotas.check_value("k", 10).is_ok());
+        assert!(quotas.check_entries(10).is_ok());
+        assert!(quotas.check_store_size(10).is_ok());
+    }
+}
@@ -0,0 +1,36 @@
+//! Read receipts: who last read each entry of a shared store, and when.
+//!
+//! Receipts are optional and off by default. While they are on, every read that is
+//! counted as usage (`get`, `exec`/`run`, `lookup`, ...) records the name of the reader,
+//! e.g. `alice@laptop`, inside the encrypted payload, so a team sharing one store can
+//! see who last used a credential without running a server. Only the latest read of
+//! each entry is kept.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The latest read of one entry; see [`crate::Keynest::set_read_receipts`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReadReceipt {
+    reader: String,
+    read: String,
+}
+
+impl ReadReceipt {
+    pub(crate) fn new(reader: &str, read: DateTime<Utc>) -> Self {
+        Self {
+            reader: reader.to_string(),
+            read: crate::store::format_timestamp(read),
+        }
+    }
+
+    /// Returns who read the entry, as set with [`crate::Keynest::set_reader`].
+    pub fn reader(&self) -> &str {
+        &self.reader
+    }
+
+    /// Returns when the entry was read (RFC 3339, UTC).
+    pub fn read(&self) -> &str {
+        &self.read
+    }
+}
@@ -0,0 +1,381 @@
+//! Recovery of keystores whose main file was damaged or lost.
+//!
+//! A crash or a full disk can leave the main file truncated or missing while an intact
+//! copy survives elsewhere: a leftover `<store>.tmp.<hex>` from an interrupted save, a
+//! `<store>.bak*` backup next to it, or a mirror the caller knows about. [`plan`]
+//! checks the main file and every candidate with the master password, and [`promote`]
+//! replaces the main file with the newest candidate that decrypts.
+//!
+//! A candidate only counts as valid if it fully decrypts, which also proves the password
+//! is right. A main file that parses but does not decrypt is therefore only replaced
+//! when a candidate decrypts with the same password.
+
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::format::parse;
+use crate::storage::Storage;
+use crate::{Keyfile, derive_unlock_key, payload};
+
+/// State of the main keystore file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    /// The file opens with the password.
+    Healthy,
+    /// The file does not exist.
+    Missing,
+    /// The file is not a readable keystore (e.g. truncated).
+    Unreadable(String),
+    /// The file parses but does not decrypt: a wrong password or damaged ciphertext.
+    Undecryptable,
+}
+
+impl Health {
+    /// Returns `true` unless the file is healthy.
+    pub fn needs_repair(&self) -> bool {
+        *self != Health::Healthy
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Healthy => write!(f, "healthy"),
+            Health::Missing => write!(f, "missing"),
+            Health::Unreadable(e) => write!(f, "unreadable ({e})"),
+            Health::Undecryptable => {
+                write!(f, "does not decrypt (wrong password or damaged data)")
+            }
+        }
+    }
+}
+
+/// Where a recovery candidate was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateSource {
+    /// A temporary file left behind by an interrupted save.
+    Temporary,
+    /// A `<store>.bak*` file next to the keystore.
+    Backup,
+    /// A path given by the caller, e.g. a mirror on another disk.
+    Extra,
+}
+
+impl fmt::Display for CandidateSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CandidateSource::Temporary => write!(f, "temporary"),
+            CandidateSource::Backup => write!(f, "backup"),
+            CandidateSource::Extra => write!(f, "extra"),
+        }
+    }
+}
+
+/// A file that might replace the main keystore file.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    path: PathBuf,
+    source: CandidateSource,
+    modified: Option<SystemTime>,
+    result: Result<usize, String>,
+}
+
+impl Candidate {
+    /// Returns the path of the candidate.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns where the candidate was found.
+    pub fn source(&self) -> CandidateSource {
+        self.source
+    }
+
+    /// Returns the modification time of the candidate, if known.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Returns `true` if the candidate decrypts with the password.
+    pub fn is_valid(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Returns the number of entries of a valid candidate.
+    pub fn entries(&self) -> Option<usize> {
+        self.result.as_ref().ok().copied()
+    }
+
+    /// Returns why the candidate was rejected.
+    pub fn error(&self) -> Option<&str> {
+        self.result.as_ref().err().map(String::as_str)
+    }
+}
+
+/// The result of checking a keystore and its recovery candidates.
+#[derive(Debug, Clone)]
+pub struct RepairPlan {
+    health: Health,
+    candidates: Vec<Candidate>,
+}
+
+impl RepairPlan {
+    /// Returns the state of the main file.
+    pub fn health(&self) -> &Health {
+        &self.health
+    }
+
+    /// Returns all candidates, newest first.
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /// Returns the candidate [`promote`] should use: the newest valid one, if the main
+    /// file needs repair.
+    pub fn selected(&self) -> Option<&Candidate> {
+        if !self.health.needs_repair() {
+            return None;
+        }
+        self.candidates.iter().find(|c| c.is_valid())
+    }
+}
+
+/// Checks the keystore at `storage` and its recovery candidates (temporary files and
+/// backups next to it, plus `extra`) with `password`.
+///
+/// # Errors
+///
+/// Returns an error if the keystore directory cannot be listed. Problems with
+/// individual files are reported in the plan instead.
+pub fn plan(storage: &Storage, password: &str, extra: &[PathBuf]) -> Result<RepairPlan> {
+    plan_with_keyfile(storage, password, None, extra)
+}
+
+/// Like [`plan`], for keystores that require `keyfile` besides the password.
+///
+/// # Errors
+///
+/// Same as [`plan`].
+pub fn plan_with_keyfile(
+    storage: &Storage,
+    password: &str,
+    keyfile: Option<&Keyfile>,
+    extra: &[PathBuf],
+) -> Result<RepairPlan> {
+    let health = if !storage.exists() {
+        Health::Missing
+    } else {
+        match verify(storage.path(), password, keyfile) {
+            Ok(_) => Health::Healthy,
+            Err(VerifyError::Parse(e)) => Health::Unreadable(e),
+            Err(VerifyError::Decrypt) => Health::Undecryptable,
+        }
+    };
+
+    let mut paths: Vec<(PathBuf, CandidateSource)> = find_siblings(storage)?;
+    paths.extend(extra.iter().map(|p| (p.clone(), CandidateSource::Extra)));
+
+    let mut candidates: Vec<Candidate> = paths
+        .into_iter()
+        .map(|(path, source)| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            let result = verify(&path, password, keyfile).map_err(|e| e.to_string());
+            Candidate {
+                path,
+                source,
+                modified,
+                result,
+            }
+        })
+        .collect();
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.modified));
+
+    Ok(RepairPlan { health, candidates })
+}
+
+/// Replaces the main keystore file with `candidate`.
+///
+/// The damaged main file, if any, is kept as `<store>.corrupt.<unix time>`; a promoted
+/// temporary file is removed. Returns the path the damaged file was moved to.
+///
+/// # Errors
+///
+/// Returns an error if the candidate is not valid or the files cannot be moved or
+/// written.
+pub fn promote(storage: &Storage, candidate: &Candidate) -> Result<Option<PathBuf>> {
+    if !candidate.is_valid() {
+        bail!("{} is not a valid keystore", candidate.path.display());
+    }
+    let data = Storage::new(candidate.path.clone()).load()?;
+
+    let moved = if storage.exists() {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut name = storage.path().as_os_str().to_os_string();
+        name.push(format!(".corrupt.{secs}"));
+        let corrupt = PathBuf::from(name);
+        fs::rename(storage.path(), &corrupt)
+            .with_context(|| format!("failed to move {}", storage.path().display()))?;
+        Some(corrupt)
+    } else {
+        None
+    };
+
+    storage.save(&data)?;
+    if candidate.source == CandidateSource::Temporary {
+        fs::remove_file(&candidate.path)
+            .with_context(|| format!("failed to remove {}", candidate.path.display()))?;
+    }
+    Ok(moved)
+}
+
+enum VerifyError {
+    Parse(String),
+    Decrypt,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Parse(e) => write!(f, "unreadable: {e}"),
+            VerifyError::Decrypt => write!(f, "does not decrypt with this password"),
+        }
+    }
+}
+
+/// Fully decrypts the keystore at `path`, returning its number of entries.
+fn verify(path: &Path, password: &str, keyfile: Option<&Keyfile>) -> Result<usize, VerifyError> {
+    let data = Storage::new(path.to_path_buf())
+        .load()
+        .map_err(|e| VerifyError::Parse(format!("{e:#}")))?;
+    let file = parse(&data).map_err(|e| VerifyError::Parse(format!("{e:#}")))?;
+    let key = derive_unlock_key(password, keyfile, &file.header, None)
+        .map_err(|e| VerifyError::Parse(format!("{e:#}")))?;
+    let store = payload::decrypt(&file, &key).map_err(|_| VerifyError::Decrypt)?;
+    Ok(store.len())
+}
+
+/// Returns the temporary files and backups next to the keystore, without checking
+/// them. Needs no password, so callers can suggest a repair cheaply.
+///
+/// # Errors
+///
+/// Returns an error if the keystore directory cannot be listed.
+pub fn find_candidates(storage: &Storage) -> Result<Vec<PathBuf>> {
+    Ok(find_siblings(storage)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect())
+}
+
+/// Lists `<store>.tmp.*` and `<store>.bak*` files next to the keystore.
+fn find_siblings(storage: &Storage) -> Result<Vec<(PathBuf, CandidateSource)>> {
+    let path = storage.path();
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to list {}", dir.display())),
+    };
+
+    let tmp_prefix = format!("{name}.tmp.");
+    let bak_prefix = format!("{name}.bak");
+    let mut found = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Some(file_name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let source = if file_name.starts_with(&tmp_prefix) {
+            CandidateSource::Temporary
+        } else if file_name.starts_with(&bak_prefix) {
+            CandidateSource::Backup
+        } else {
+            continue;
+        };
+        if entry.file_type()?.is_file() {
+            found.push((entry.path(), source));
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use zeroize::Zeroizing;
+
+    use super::*;
+    use crate::{KdfParams, Keynest};
+
+    fn store_with(dir: &Path, keys: &[&str]) -> Storage {
+        let storage = Storage::new(dir.join("keynest.db"));
+        let mut kn = Keynest::init_with_storage_and_kdf(
+            Zeroizing::new("pw".to_string()),
+            storage.clone(),
+            KdfParams::default(),
+        )
+        .unwrap();
+        for key in keys {
+            kn.set(key, "v").unwrap();
+        }
+        kn.save().unwrap();
+        storage
+    }
+
+    #[test]
+    fn healthy_store_selects_nothing() {
+        let dir = tempdir().unwrap();
+        let storage = store_with(dir.path(), &["a"]);
+        let plan = plan(&storage, "pw", &[]).unwrap();
+        assert_eq!(*plan.health(), Health::Healthy);
+        assert!(plan.selected().is_none());
+    }
+
+    #[test]
+    fn truncated_store_is_replaced_by_leftover_temp_file() {
+        let dir = tempdir().unwrap();
+        let storage = store_with(dir.path(), &["a", "b"]);
+        let good = fs::read(storage.path()).unwrap();
+        let tmp = dir.path().join("keynest.db.tmp.0011223344556677");
+        Storage::new(tmp.clone()).save(&good).unwrap();
+        fs::write(storage.path(), &good[..good.len() / 2]).unwrap();
+
+        let plan = plan(&storage, "pw", &[]).unwrap();
+        assert!(matches!(plan.health(), Health::Unreadable(_)));
+        let selected = plan.selected().unwrap();
+        assert_eq!(selected.path(), tmp);
+        assert_eq!(selected.entries(), Some(2));
+
+        let moved = promote(&storage, selected).unwrap().unwrap();
+        assert!(moved.exists());
+        assert!(!tmp.exists());
+        let kn = Keynest::open_with_storage(Zeroizing::new("pw".to_string()), storage).unwrap();
+        assert_eq!(kn.get("b").unwrap(), Some("v"));
+    }
+
+    #[test]
+    fn wrong_password_rejects_every_candidate() {
+        let dir = tempdir().unwrap();
+        let storage = store_with(dir.path(), &["a"]);
+        let backup = dir.path().join("keynest.db.bak");
+        fs::copy(storage.path(), &backup).unwrap();
+        fs::write(storage.path(), b"garbage").unwrap();
+
+        let plan = plan(&storage, "wrong", &[]).unwrap();
+        assert_eq!(plan.candidates().len(), 1);
+        assert_eq!(plan.candidates()[0].source(), CandidateSource::Backup);
+        assert!(plan.selected().is_none());
+        assert!(plan.candidates()[0].error().unwrap().contains("password"));
+    }
+}
@@ -0,0 +1,179 @@
+//! Per-entry records left encrypted until they are read.
+//!
+//! A keystore with per-entry records (see [`crate::Keynest::set_per_entry_records`])
+//! encrypts every entry with its own key, wrapped with the store key in the index.
+//! [`crate::Keynest`] opens such a keystore by decrypting the index only: the records
+//! are checked against the digests the index holds for them and kept sealed, an entry is
+//! decrypted the first time it is read, and the whole store once something needs every
+//! entry (listing, searching, saving).
+//!
+//! Since the index authenticates every record on open, decrypting a record later can
+//! only fail if the keystore was written with the right key but malformed content; the
+//! reads that decrypt it return that as an error.
+
+use anyhow::Result;
+use std::sync::{Arc, OnceLock};
+
+use crate::clock::{Clock, SharedClock};
+use crate::error::StoreError;
+use crate::format::{Header, Record};
+use crate::payload;
+use crate::settings::Settings;
+use crate::store::{SecretEntry, Store, StoreIndex};
+
+/// The sealed records of a per-entry keystore, with the entries decrypted so far.
+pub(crate) struct Sealed {
+    header: Header,
+    index: StoreIndex,
+    /// Schema the records were written with (see [`crate::migrations`]).
+    schema: u32,
+    records: Vec<Record>,
+    /// Entries of each record, decrypted on first access.
+    entries: Vec<OnceLock<Vec<SecretEntry>>>,
+    /// Clock handed to the store once it is unsealed.
+    clock: SharedClock,
+}
+
+impl Sealed {
+    /// Keeps `records`, the section records of a file with `header` and `index`, sealed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::CorruptedIndex` if a record does not match the digest the
+    /// index holds for it.
+    pub(crate) fn new(
+        header: Header,
+        index: StoreIndex,
+        schema: u32,
+        records: &[Record],
+    ) -> Result<Self, StoreError> {
+        for (i, record) in records.iter().enumerate() {
+            if !index
+                .entry_key(i as u32)
+                .is_some_and(|k| k.matches(record.ciphertext()))
+            {
+                return Err(StoreError::CorruptedIndex(format!(
+                    "section {i} does not match the index"
+                )));
+            }
+        }
+
+        Ok(Self {
+            header,
+            index,
+            schema,
+            records: records
+                .iter()
+                .map(|r| Record::new(r.nonce().to_vec(), r.ciphertext().to_vec()))
+                .collect(),
+            entries: records.iter().map(|_| OnceLock::new()).collect(),
+            clock: SharedClock::default(),
+        })
+    }
+
+    /// Returns the index, which holds the keys and the store metadata.
+    pub(crate) fn index(&self) -> &StoreIndex {
+        &self.index
+    }
+
+    /// Returns the store settings, which the index holds.
+    pub(crate) fn settings(&self) -> &Settings {
+        self.index.settings()
+    }
+
+    /// Returns the clock the store will read the time from.
+    pub(crate) fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock.0
+    }
+
+    /// Makes the store read the time from `clock` once it is unsealed.
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = SharedClock(clock);
+    }
+
+    /// Returns the entry `key`, decrypting only its record with `store_key`. Returns
+    /// `None` if the key does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be decrypted or does not hold the entry.
+    pub(crate) fn entry(&self, store_key: &[u8], key: &str) -> Result<Option<&SecretEntry>> {
+        let Some(section) = self.index.section_of(key) else {
+            return Ok(None);
+        };
+        match self
+            .section(store_key, section)?
+            .iter()
+            .find(|e| e.key() == key)
+        {
+            Some(entry) => Ok(Some(entry)),
+            None => Err(StoreError::CorruptedIndex(format!(
+                "'{key}' is missing from section {section}"
+            ))
+            .into()),
+        }
+    }
+
+    /// Decrypts every record and assembles the store, on the rayon thread pool like
+    /// opening a keystore without entry keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record cannot be decrypted or does not match the index.
+    pub(crate) fn unseal(&self, store_key: &[u8]) -> Result<Store> {
+        let sections = payload::decrypt_sections(
+            &self.header,
+            &self.records,
+            &self.index,
+            store_key,
+            self.schema,
+        )?;
+        let mut store = Store::from_sections(self.index.clone(), sections)?;
+        store.set_clock(self.clock.0.clone());
+        Ok(store)
+    }
+
+    /// Returns the entries of section `section`, decrypting it on first access.
+    fn section(&self, store_key: &[u8], section: u32) -> Result<&[SecretEntry]> {
+        let (Some(record), Some(cell)) = (
+            self.records.get(section as usize),
+            self.entries.get(section as usize),
+        ) else {
+            return Err(
+                StoreError::CorruptedIndex(format!("section {section} does not exist")).into(),
+            );
+        };
+        if let Some(entries) = cell.get() {
+            return Ok(entries);
+        }
+
+        let plaintext = payload::decrypt_section(
+            &self.header,
+            &self.index,
+            store_key,
+            section,
+            record.nonce(),
+            record.ciphertext(),
+        )?;
+        let entries = payload::parse_section(&plaintext, self.schema, self.header.encoding())?;
+        if let Some(entry) = entries
+            .iter()
+            .find(|e| self.index.section_of(e.key()) != Some(section))
+        {
+            return Err(StoreError::CorruptedIndex(format!(
+                "'{}' is not indexed in section {section}",
+                entry.key()
+            ))
+            .into());
+        }
+        Ok(cell.get_or_init(|| entries))
+    }
+}
+
+impl Drop for Sealed {
+    fn drop(&mut self) {
+        for entries in self.entries.iter_mut().filter_map(OnceLock::get_mut) {
+            entries.iter_mut().for_each(SecretEntry::wipe);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Store-scoped settings in the reserved `keynest/` namespace.
+//!
+//! Features that need to persist configuration alongside the secrets (padding policy,
+//! retention, hooks, aliases, ...) store it here instead of adding a new top-level field
+//! to the payload. Settings live inside the encrypted payload and are addressed as
+//! `keynest/<name>`; secret keys under that prefix are rejected.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::error::StoreError;
+
+/// Key prefix reserved for store settings.
+pub const SETTINGS_PREFIX: &str = "keynest/";
+
+/// Returns `true` if `key` lies in the reserved `keynest/` namespace.
+pub fn is_reserved_key(key: &str) -> bool {
+    key.starts_with(SETTINGS_PREFIX)
+}
+
+/// A typed store setting.
+///
+/// # Example
+///
+/// ```ignore
+/// struct Retention;
+///
+/// impl Setting for Retention {
+///     const NAME: &'static str = "retention";
+///     type Value = u32;
+/// }
+///
+/// kn.set_setting::<Retention>(&30)?;
+/// assert_eq!(kn.setting::<Retention>()?, Some(30));
+/// ```
+pub trait Setting {
+    /// Name of the setting below `keynest/`.
+    const NAME: &'static str;
+    /// Type of the stored value.
+    type Value: Serialize + DeserializeOwned;
+}
+
+/// File format version written on save.
+///
+/// Unset means the current format. Set to 2 by [`crate::Keynest::migrate_to`] to keep
+/// writing the store itself as a single-ciphertext v2 file.
+pub struct WriteFormat;
+
+impl Setting for WriteFormat {
+    const NAME: &'static str = "write-format";
+    type Value = u8;
+}
+
+/// Format version of a copy of the store written beside it on every save, for keynest
+/// versions that cannot open the format of the store itself.
+///
+/// Unset means no copy; see [`crate::Keynest::set_compat_copy`].
+pub struct CompatCopy;
+
+impl Setting for CompatCopy {
+    const NAME: &'static str = "compat-copy";
+    type Value = u8;
+}
+
+/// Whether the password-free key index (`<store>.keyindex`) is maintained on save.
+///
+/// Unset means disabled; see [`crate::Keynest::set_key_index`].
+pub struct KeyIndexEnabled;
+
+impl Setting for KeyIndexEnabled {
+    const NAME: &'static str = "key-index";
+    type Value = bool;
+}
+
+/// Whether saves append the changes to `<store>.journal` instead of rewriting the file.
+///
+/// Unset means disabled; see [`crate::Keynest::set_journal`].
+pub struct JournalEnabled;
+
+impl Setting for JournalEnabled {
+    const NAME: &'static str = "journal";
+    type Value = bool;
+}
+
+/// Whether the payload encoding was chosen with [`crate::Keynest::convert`] and is kept
+/// as is on save.
+///
+/// Unset means saves pick the encoding: JSON payloads move to MessagePack, and stores
+/// written in format v2 stay JSON.
+pub struct PinnedEncoding;
+
+impl Setting for PinnedEncoding {
+    const NAME: &'static str = "pinned-encoding";
+    type Value = bool;
+}
+
+/// Whether every entry is encrypted as a record of its own instead of in sections of
+/// up to 256 entries.
+///
+/// Unset means grouped; see [`crate::Keynest::set_per_entry_records`].
+pub struct PerEntryRecords;
+
+impl Setting for PerEntryRecords {
+    const NAME: &'static str = "per-entry-records";
+    type Value = bool;
+}
+
+/// Key and length of the audit log, present while it is on.
+///
+/// Unset means off; see [`crate::Keynest::set_audit_log`].
+pub struct AuditLog;
+
+impl Setting for AuditLog {
+    const NAME: &'static str = "audit-log";
+    type Value = crate::audit::AuditLogState;
+}
+
+/// Whether records are encrypted under synthetic instead of random nonces, so an
+/// unchanged record is written as the same bytes on every save.
+///
+/// Unset means random nonces; see [`crate::Keynest::set_deterministic_records`].
+pub struct DeterministicRecords;
+
+impl Setting for DeterministicRecords {
+    const NAME: &'static str = "deterministic-records";
+    type Value = bool;
+}
+
+/// Usage counters, present while usage tracking is enabled.
+///
+/// Unset means tracking is off; see [`crate::Keynest::set_usage_tracking`].
+pub struct UsageStats;
+
+impl Setting for UsageStats {
+    const NAME: &'static str = "usage";
+    type Value = crate::usage::Usage;
+}
+
+/// Autotype sequences by login namespace.
+///
+/// Logins without an entry use [`crate::autotype::DEFAULT_SEQUENCE`]; see
+/// [`crate::Keynest::set_autotype_sequence`].
+pub struct AutotypeSequences;
+
+impl Setting for AutotypeSequences {
+    const NAME: &'static str = "autotype";
+    type Value = std::collections::BTreeMap<String, String>;
+}
+
+/// Keys of the entries pinned as favorites, which listings show first.
+///
+/// Unset means none; see [`crate::Keynest::pin`].
+pub struct Pinned;
+
+impl Setting for Pinned {
+    const NAME: &'static str = "pinned";
+    type Value = std::collections::BTreeSet<String>;
+}
+
+/// Active leases of shared entries, by key.
+///
+/// Unset means none; see [`crate::Keynest::lease`].
+pub struct Leases;
+
+impl Setting for Leases {
+    const NAME: &'static str = "leases";
+    type Value = std::collections::BTreeMap<String, Vec<crate::lease::Lease>>;
+}
+
+/// Latest read of each entry, present while read receipts are enabled.
+///
+/// Unset means receipts are off; see [`crate::Keynest::set_read_receipts`].
+pub struct ReadReceipts;
+
+impl Setting for ReadReceipts {
+    const NAME: &'static str = "read_receipts";
+    type Value = std::collections::BTreeMap<String, crate::receipts::ReadReceipt>;
+}
+
+/// Entry templates shared by everyone using the store, by name.
+///
+/// Unset means none; see [`crate::Keynest::set_template`].
+pub struct Templates;
+
+impl Setting for Templates {
+    const NAME: &'static str = "templates";
+    type Value = std::collections::BTreeMap<String, crate::template::Template>;
+}
+
+/// Marks a store as a read-only snapshot.
+///
+/// Unset means writable; see [`crate::Keynest::snapshot`].
+pub struct ReadOnly;
+
+impl Setting for ReadOnly {
+    const NAME: &'static str = "read-only";
+    type Value = bool;
+}
+
+/// Number of previous versions of the keystore file kept on save.
+///
+/// Unset means none; see [`crate::Keynest::set_backups`].
+pub struct Backups;
+
+impl Setting for Backups {
+    const NAME: &'static str = "backups";
+    type Value = u32;
+}
+
+/// Name of the keyslot holding the duress password, kept in the real store only, as
+/// the header does not tell it from the other keyslots.
+///
+/// Unset means no duress password; see [`crate::Keynest::set_duress`].
+pub struct DuressSlot;
+
+impl Setting for DuressSlot {
+    const NAME: &'static str = "duress-slot";
+    type Value = String;
+}
+
+/// The settings of a store, keyed by name (without the `keynest/` prefix).
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct Settings(BTreeMap<String, serde_json::Value>);
+
+impl Settings {
+    /// Returns the value of setting `S`, or `None` if it is not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::InvalidSetting` if the stored value does not match `S::Value`.
+    pub fn get<S: Setting>(&self) -> Result<Option<S::Value>, StoreError> {
+        self.0
+            .get(S::NAME)
+            .map(|value| serde_json::from_value(value.clone()).map_err(|e| invalid::<S>(e)))
+            .transpose()
+    }
+
+    /// Sets setting `S`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::InvalidSetting` if the value cannot be serialized.
+    pub fn set<S: Setting>(&mut self, value: &S::Value) -> Result<(), StoreError> {
+        let value = serde_json::to_value(value).map_err(|e| invalid::<S>(e))?;
+        self.0.insert(S::NAME.to_string(), value);
+        Ok(())
+    }
+
+    /// Removes setting `S`. Returns `true` if it was set.
+    pub fn remove<S: Setting>(&mut self) -> bool {
+        self.0.remove(S::NAME).is_some()
+    }
+
+    /// Returns an iterator over the full keys (`keynest/<name>`) of all settings.
+    pub fn keys(&self) -> impl Iterator<Item = String> + '_ {
+        self.0.keys().map(|name| format!("{SETTINGS_PREFIX}{name}"))
+    }
+
+    /// Returns `true` if no setting is stored.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+fn invalid<S: Setting>(e: serde_json::Error) -> StoreError {
+    StoreError::InvalidSetting {
+        name: format!("{SETTINGS_PREFIX}{}", S::NAME),
+        msg: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Retention;
+
+    impl Setting for Retention {
+        const NAME: &'static str = "retention";
+        type Value = u32;
+    }
+
+    struct Aliases;
+
+    impl Setting for Aliases {
+        const NAME: &'static str = "aliases";
+        type Value = BTreeMap<String, String>;
+    }
+
+    #[test]
+    fn typed_roundtrip() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.get::<Retention>().unwrap(), None);
+
+        settings.set::<Retention>(&30).unwrap();
+        let aliases = BTreeMap::from([("db".to_string(), "prod/db/password".to_string())]);
+        settings.set::<Aliases>(&aliases).unwrap();
+
+        let json = serde_json::to_string(&settings).unwrap();
+        let parsed: Settings = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.get::<Retention>().unwrap(), Some(30));
+        assert_eq!(parsed.get::<Aliases>().unwrap(), Some(aliases));
+        assert_eq!(
+            parsed.keys().collect::<Vec<_>>(),
+            ["keynest/aliases", "keynest/retention"]
+        );
+    }
+
+    #[test]
+    fn mismatched_type_is_reported() {
+        let settings: Settings = serde_json::from_str(r#"{"retention": "forever"}"#).unwrap();
+        let err = settings.get::<Retention>().unwrap_err();
+        assert!(err.to_string().contains("keynest/retention"));
+    }
+
+    #[test]
+    fn remove_reports_presence() {
+        let mut settings = Settings::default();
+        settings.set::<Retention>(&1).unwrap();
+        assert!(settings.remove::<Retention>());
+        assert!(!settings.remove::<Retention>());
+        assert!(settings.is_empty());
+    }
+}
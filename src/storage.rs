@@ -1,9 +1,11 @@
 //! Storage backend for keystore files.
 
 use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
 use getrandom::fill;
 use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::io::{Read, Write};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
 
 #[cfg(not(target_os = "windows"))]
@@ -12,6 +14,89 @@ use std::fs::File;
 #[cfg(unix)]
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
 
+/// Size from which [`Storage::load_data`] maps the file instead of reading it, if
+/// mapping is enabled; smaller files are cheaper to read.
+const MMAP_MIN_LEN: u64 = 1024 * 1024;
+
+/// Size of the random blocks [`shred`] overwrites files with.
+const SHRED_BLOCK_LEN: usize = 64 * 1024;
+
+/// Format of the time in the names of backups.
+const BACKUP_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+fn backup_id(time: DateTime<Utc>) -> String {
+    time.format(BACKUP_TIME_FORMAT).to_string()
+}
+
+/// A previous version of a keystore file; see [`Storage::backups`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    path: PathBuf,
+    id: String,
+    created: Option<DateTime<Utc>>,
+}
+
+impl Backup {
+    /// Returns the path of the backup file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns what follows `.bak.` in the file name: the time the backup was taken,
+    /// like `20260718T093012.345Z`, or the number of a backup of an earlier version.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns when the backup was taken; for numbered backups, when the file was last
+    /// modified.
+    pub fn created(&self) -> Option<DateTime<Utc>> {
+        self.created
+    }
+}
+
+/// Contents of a storage file returned by [`Storage::load_data`]: read into memory,
+/// or memory-mapped.
+pub struct FileData(Contents);
+
+enum Contents {
+    Read(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl FileData {
+    /// Returns `true` if the file is memory-mapped rather than read into memory.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.0, Contents::Mapped(_))
+    }
+}
+
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Contents::Read(data) => data,
+            Contents::Mapped(map) => map,
+        }
+    }
+}
+
+impl AsRef<[u8]> for FileData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl std::fmt::Debug for FileData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileData")
+            .field("len", &self.len())
+            .field("mapped", &self.is_mapped())
+            .finish()
+    }
+}
+
 /// A storage backend for persisting keystore data.
 ///
 /// `Storage` handles reading and writing encrypted keystore files
@@ -19,12 +104,26 @@ use std::os::unix::fs::{MetadataExt, PermissionsExt};
 #[derive(Clone)]
 pub struct Storage {
     path: PathBuf,
+    mmap: bool,
 }
 
 impl Storage {
     /// Creates a new Storage instance with the given path.
     pub fn new(path: PathBuf) -> Self {
-        Self { path }
+        Self { path, mmap: false }
+    }
+
+    /// Memory-maps files of 1 MiB and more when opening a keystore, instead of reading
+    /// them into memory (see [`Storage::load_data`]).
+    ///
+    /// Opening then decrypts the section ciphertexts straight from the mapping, so a
+    /// large store is never copied to the heap as a whole. The mapping is released
+    /// before opening returns. Off by default: if another program truncates the file
+    /// while it is mapped, reading it crashes the process (keynest itself only ever
+    /// replaces the file, which is safe).
+    pub fn with_mmap(mut self, enabled: bool) -> Self {
+        self.mmap = enabled;
+        self
     }
 
     /// Returns `true` if the storage file exists.
@@ -44,6 +143,44 @@ impl Storage {
         Ok(fs::read(&self.path)?)
     }
 
+    /// Loads the storage file like [`Storage::load`], but memory-maps it instead if
+    /// enabled with [`Storage::with_mmap`] and the file is at least 1 MiB.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or mapped.
+    pub fn load_data(&self) -> Result<FileData> {
+        if !self.mmap {
+            return Ok(FileData(Contents::Read(self.load()?)));
+        }
+        let mut file = self.open_read()?;
+        if file.metadata()?.len() < MMAP_MIN_LEN {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            return Ok(FileData(Contents::Read(data)));
+        }
+        // SAFETY: keynest never writes to a keystore file in place; saves replace it
+        // with a new file, which leaves the mapped one intact. Changes by other
+        // programs are outside what can be guarded against (see `with_mmap`).
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .with_context(|| format!("failed to map {}", self.path.display()))?;
+        Ok(FileData(Contents::Mapped(map)))
+    }
+
+    /// Opens the storage file for reading without loading it into memory.
+    ///
+    /// Used to read individual records of large keystores on demand.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn open_read(&self) -> Result<fs::File> {
+        #[cfg(unix)]
+        self.security_check()?;
+
+        Ok(fs::File::open(&self.path)?)
+    }
+
     /// Saves data to the storage file using atomic write.
     ///
     /// This method ensures crash-safety by:
@@ -55,6 +192,9 @@ impl Storage {
     /// If a crash occurs during save, either the old or new file will be present,
     /// never a corrupted partial write.
     ///
+    /// The journal of the file (see [`Storage::journal_path`]) is removed afterwards,
+    /// since it extends the replaced version.
+    ///
     /// Creates parent directories if they don't exist.
     ///
     /// # Errors
@@ -102,9 +242,225 @@ impl Storage {
             dir.sync_all()?;
         }
 
+        match fs::remove_file(self.journal_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).context("failed to remove the journal of the replaced file")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the path of the journal that holds the changes saved since the file
+    /// was last written, `<file>.journal` (see [`crate::Keynest::set_journal`]).
+    pub fn journal_path(&self) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(".journal");
+        PathBuf::from(name)
+    }
+
+    /// Returns the path of the audit log of the file, `<file>.audit` (see
+    /// [`crate::Keynest::set_audit_log`]).
+    pub fn audit_path(&self) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(".audit");
+        PathBuf::from(name)
+    }
+
+    /// Returns the path of the copy of the file written in format `version` for older
+    /// keynest versions, `<file>.v<version>` (see [`crate::Keynest::set_compat_copy`]).
+    pub fn compat_path(&self, version: u8) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".v{version}"));
+        PathBuf::from(name)
+    }
+
+    /// Keeps the current file as `<file>.bak.<time>` before it is replaced and deletes
+    /// all but the `count` most recent backups.
+    ///
+    /// Does nothing if `count` is 0 or the file does not exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backup cannot be written or an old one removed.
+    pub fn rotate_backups(&self, count: u32, now: DateTime<Utc>) -> Result<()> {
+        if count == 0 || self.backup(now)?.is_none() {
+            return Ok(());
+        }
+        for old in self.backups()?.into_iter().skip(count as usize) {
+            fs::remove_file(old.path())
+                .with_context(|| format!("failed to remove {}", old.path().display()))?;
+        }
         Ok(())
     }
 
+    /// Copies the current file to `<file>.bak.<time>`, e.g. before it is replaced by a
+    /// restored backup. Returns `None` if the file does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or the backup written.
+    pub fn backup(&self, now: DateTime<Utc>) -> Result<Option<Backup>> {
+        if !self.exists() {
+            return Ok(None);
+        }
+        // Saves within the same millisecond get the next free one.
+        let mut time = now;
+        while self.backup_path(time).exists() {
+            time += TimeDelta::milliseconds(1);
+        }
+
+        let data = self.load()?;
+        let backup = Storage::new(self.backup_path(time));
+        backup
+            .save(&data)
+            .with_context(|| format!("failed to write {}", backup.path().display()))?;
+        Ok(Some(Backup {
+            path: backup.path,
+            id: backup_id(time),
+            created: Some(time),
+        }))
+    }
+
+    /// Returns the path of the backup taken at `time`, `<file>.bak.<time>` with the time
+    /// in UTC, e.g. `keynest.db.bak.20260718T093012.345Z`.
+    pub fn backup_path(&self, time: DateTime<Utc>) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".bak.{}", backup_id(time)));
+        self.path.with_file_name(name)
+    }
+
+    /// Returns the backups of the file, most recent first: those taken on save, then
+    /// the numbered `<file>.bak.<n>` backups of earlier keynest versions. Other files
+    /// named like backups (e.g. a copy made by hand as `<file>.bak`) are not included.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be listed.
+    pub fn backups(&self) -> Result<Vec<Backup>> {
+        let Some(name) = self.path.file_name().and_then(|n| n.to_str()) else {
+            return Ok(Vec::new());
+        };
+        let Some(entries) = self.read_dir()? else {
+            return Ok(Vec::new());
+        };
+
+        let prefix = format!("{name}.bak.");
+        let mut backups = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(id) = file_name.to_str().and_then(|n| n.strip_prefix(&prefix)) else {
+                continue;
+            };
+            let created = match NaiveDateTime::parse_from_str(id, BACKUP_TIME_FORMAT) {
+                Ok(time) => Some(time.and_utc()),
+                Err(_) if id.parse::<u32>().is_ok() => {
+                    entry.metadata()?.modified().ok().map(DateTime::<Utc>::from)
+                }
+                Err(_) => continue,
+            };
+            if entry.file_type()?.is_file() {
+                backups.push(Backup {
+                    path: entry.path(),
+                    id: id.to_string(),
+                    created,
+                });
+            }
+        }
+        // Numbered backups predate the timestamped ones and count from the newest.
+        backups.sort_by_key(|b| match b.id.parse::<u32>() {
+            Ok(n) => (1, n, None),
+            Err(_) => (0, 0, Some(std::cmp::Reverse(b.created))),
+        });
+        Ok(backups)
+    }
+
+    /// Returns the conflict copies that sync tools left next to the file, sorted by
+    /// name: Syncthing's `<stem>.sync-conflict-<...>.<ext>` and the
+    /// `<stem> (... conflicted copy ...).<ext>` of Dropbox and Nextcloud.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be listed.
+    pub fn conflict_copies(&self) -> Result<Vec<PathBuf>> {
+        let (Some(stem), Some(entries)) = (
+            self.path.file_stem().and_then(|n| n.to_str()),
+            self.read_dir()?,
+        ) else {
+            return Ok(Vec::new());
+        };
+        let extension = match self.path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => format!(".{extension}"),
+            None => String::new(),
+        };
+
+        let mut copies = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(middle) = file_name
+                .to_str()
+                .and_then(|n| n.strip_prefix(stem))
+                .and_then(|n| n.strip_suffix(&extension))
+            else {
+                continue;
+            };
+            let syncthing = middle
+                .strip_prefix(".sync-conflict-")
+                .is_some_and(|id| !id.contains('.'));
+            let copy = middle.starts_with(" (")
+                && middle.ends_with(')')
+                && middle.contains("conflicted copy");
+            if (syncthing || copy) && entry.file_type()?.is_file() {
+                copies.push(entry.path());
+            }
+        }
+        copies.sort();
+        Ok(copies)
+    }
+
+    /// Returns the temporary files `<file>.tmp.<hex>` that interrupted saves left next to
+    /// the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be listed.
+    pub(crate) fn temp_files(&self) -> Result<Vec<PathBuf>> {
+        let (Some(name), Some(entries)) = (
+            self.path.file_name().and_then(|n| n.to_str()),
+            self.read_dir()?,
+        ) else {
+            return Ok(Vec::new());
+        };
+        let prefix = format!("{name}.tmp.");
+        let mut found = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let is_temp = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|n| n.starts_with(&prefix));
+            if is_temp && entry.file_type()?.is_file() {
+                found.push(entry.path());
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Lists the directory of the file; `None` if it does not exist.
+    fn read_dir(&self) -> Result<Option<fs::ReadDir>> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        match fs::read_dir(dir) {
+            Ok(entries) => Ok(Some(entries)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to list {}", dir.display())),
+        }
+    }
+
     /// Returns the path to the storage file.
     pub fn path(&self) -> &PathBuf {
         &self.path
@@ -265,6 +621,34 @@ impl Storage {
     }
 }
 
+/// Overwrites the file at `path` with random bytes, flushes them to disk and deletes
+/// the file.
+///
+/// This only destroys the data where the file system writes in place: copy-on-write
+/// file systems, snapshots and the wear leveling of SSDs can keep the old blocks.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written or deleted.
+pub(crate) fn shred(path: &Path) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut remaining = file.metadata()?.len();
+    let mut block = vec![0u8; SHRED_BLOCK_LEN];
+    while remaining > 0 {
+        let len = remaining.min(SHRED_BLOCK_LEN as u64) as usize;
+        fill(&mut block[..len])?;
+        file.write_all(&block[..len])
+            .with_context(|| format!("failed to overwrite {}", path.display()))?;
+        remaining -= len as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -298,6 +682,29 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn load_data_maps_only_large_files_when_enabled() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
+        let large = vec![7u8; MMAP_MIN_LEN as usize];
+
+        storage.save(b"hello world").unwrap();
+        let data = storage.clone().with_mmap(true).load_data().unwrap();
+        assert!(!data.is_mapped());
+        assert_eq!(&*data, b"hello world");
+
+        storage.save(&large).unwrap();
+        assert!(!storage.load_data().unwrap().is_mapped());
+        let data = storage.clone().with_mmap(true).load_data().unwrap();
+        assert!(data.is_mapped());
+        assert_eq!(&*data, &large[..]);
+
+        // Saving replaces the file, so the mapping keeps the old contents.
+        storage.save(b"replaced").unwrap();
+        assert_eq!(&*data, &large[..]);
+        assert_eq!(storage.load().unwrap(), b"replaced");
+    }
+
     // --------------------------------------------------
     // EXISTS TESTS
     // --------------------------------------------------
@@ -441,4 +848,110 @@ mod tests {
             assert_eq!(mode, 0o600, "file should be 0600");
         }
     }
+
+    // --------------------------------------------------
+    // BACKUPS
+    // --------------------------------------------------
+
+    #[test]
+    fn backups_are_timestamped_and_pruned() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("store.db");
+        let storage = Storage::new(path.clone());
+
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        storage.rotate_backups(2, now).unwrap();
+        assert!(storage.backups().unwrap().is_empty());
+
+        // A numbered backup of an earlier version and a copy made by hand.
+        fs::write(dir.path().join("store.db.bak.1"), b"legacy").unwrap();
+        fs::write(dir.path().join("store.db.bak"), b"manual").unwrap();
+
+        storage.save(b"first").unwrap();
+        storage.rotate_backups(2, now).unwrap();
+        storage.save(b"second").unwrap();
+        storage.rotate_backups(2, now).unwrap();
+
+        let backups = storage.backups().unwrap();
+        let ids: Vec<_> = backups.iter().map(Backup::id).collect();
+        assert_eq!(ids, ["20231114T221320.001Z", "20231114T221320.000Z"]);
+        assert_eq!(fs::read(backups[0].path()).unwrap(), b"second");
+        assert_eq!(backups[1].created(), Some(now));
+        assert!(!dir.path().join("store.db.bak.1").exists());
+        assert!(dir.path().join("store.db.bak").exists());
+    }
+
+    #[test]
+    fn conflict_copies_are_found_next_to_the_file() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
+        assert!(storage.conflict_copies().unwrap().is_empty());
+
+        for name in [
+            "store.db",
+            "store.sync-conflict-20260101-120000-ABCDEFG.db",
+            "store (Alice's conflicted copy 2026-01-02).db",
+            "store (conflicted copy 2026-01-03 120000).db",
+            "store.sync-conflict-20260101-120000-ABCDEFG.db.journal",
+            "store.db.bak.1",
+            "other.sync-conflict-20260101-120000-ABCDEFG.db",
+            "store (copy).db",
+        ] {
+            fs::write(dir.path().join(name), b"data").unwrap();
+        }
+        let names: Vec<_> = storage
+            .conflict_copies()
+            .unwrap()
+            .into_iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "store (Alice's conflicted copy 2026-01-02).db",
+                "store (conflicted copy 2026-01-03 120000).db",
+                "store.sync-conflict-20260101-120000-ABCDEFG.db",
+            ]
+        );
+    }
+
+    // --------------------------------------------------
+    // SHRED
+    // --------------------------------------------------
+
+    #[cfg(unix)]
+    #[test]
+    fn shred_overwrites_the_data_before_deleting() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("store.db");
+        let data = vec![b'x'; SHRED_BLOCK_LEN + 10];
+        fs::write(&path, &data).unwrap();
+        // A second link keeps the overwritten blocks reachable after the delete.
+        let link = dir.path().join("link");
+        fs::hard_link(&path, &link).unwrap();
+
+        shred(&path).unwrap();
+        assert!(!path.exists());
+        let left = fs::read(&link).unwrap();
+        assert_eq!(left.len(), data.len());
+        assert!(left.iter().filter(|b| **b == b'x').count() < 1000);
+        assert!(shred(&path).is_err());
+    }
+
+    #[test]
+    fn temp_files_are_found_next_to_the_file() {
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path().join("store.db"));
+        for name in [
+            "store.db",
+            "store.db.tmp.0011223344556677",
+            "other.db.tmp.00",
+        ] {
+            fs::write(dir.path().join(name), b"data").unwrap();
+        }
+        assert_eq!(
+            storage.temp_files().unwrap(),
+            [dir.path().join("store.db.tmp.0011223344556677")]
+        );
+    }
 }
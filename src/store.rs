@@ -1,107 +1,1446 @@
 //! In-memory secret storage.
 
+use crate::audit::AuditAction;
+use crate::clock::{Clock, SharedClock};
 use crate::error::StoreError;
-use chrono::{SecondsFormat, Utc};
+use crate::migrations::CURRENT_SCHEMA;
+use crate::quota::Quotas;
+use crate::settings::{PerEntryRecords, Settings, is_reserved_key};
+use chrono::{DateTime, SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use zeroize::{Zeroize, Zeroizing};
 
-/// Returns the current time as a UTC RFC 3339 timestamp (e.g. `2026-07-22T12:34:56Z`).
+/// Formats `time` as a UTC RFC 3339 timestamp (e.g. `2026-07-22T12:34:56Z`).
 ///
 /// Used for `creation_date` and `updated`: UTC + RFC 3339 is locale-independent and
 /// lexically sortable, unlike the previous `Local::now().to_string()`.
-fn now_timestamp() -> String {
-    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+pub(crate) fn format_timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Returns the host of `url`, e.g. `github.com` for `https://alice@github.com:443/login`.
+/// A URL without a scheme is read as starting with the host.
+fn url_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split_once(']')?.0,
+        None => host.split(':').next()?,
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+/// Prefix marking a secret value as a reference to another entry (`ref:prod/db/password`).
+pub const REFERENCE_PREFIX: &str = "ref:";
+
+/// Returns the referenced key if `value` is a `ref:<key>` reference.
+pub fn reference_target(value: &str) -> Option<&str> {
+    value
+        .strip_prefix(REFERENCE_PREFIX)
+        .filter(|target| !target.is_empty())
+}
+
+/// Maximum number of entries in one section of the sectioned (v3) payload.
+pub(crate) const SECTION_MAX_ENTRIES: usize = 256;
+/// Approximate maximum plaintext size of one section; larger entries start a new one.
+pub(crate) const SECTION_MAX_BYTES: usize = 1024 * 1024;
+
+/// Walks the `ref:` chain starting at `key` and returns the keys visited, ending with
+/// the entry holding the actual value (empty if `key` does not exist).
+///
+/// `target_of` looks up a key: `None` if it does not exist, `Some(None)` for a literal
+/// value, and `Some(Some(target))` for a reference to `target`.
+pub(crate) fn walk_references<E: From<StoreError>>(
+    key: &str,
+    mut target_of: impl FnMut(&str) -> Result<Option<Option<String>>, E>,
+) -> Result<Vec<String>, E> {
+    let Some(mut next) = target_of(key)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut chain = vec![key.to_string()];
+    while let Some(target) = next {
+        if chain.contains(&target) {
+            chain.push(target);
+            return Err(StoreError::ReferenceCycle(chain).into());
+        }
+
+        next = target_of(&target)?.ok_or_else(|| StoreError::BrokenReference {
+            from: chain.last().cloned().unwrap_or_default(),
+            to: target.clone(),
+        })?;
+        chain.push(target);
+    }
+
+    Ok(chain)
+}
+
+/// Returns the SHA-256 digest of the JSON serialization of `value`.
+fn digest_of<T: Serialize>(value: &T) -> [u8; 32] {
+    // Entries and metadata hold no maps with non-string keys, so serializing cannot fail.
+    let json = Zeroizing::new(serde_json::to_vec(value).unwrap_or_default());
+    Sha256::digest(&*json).into()
 }
 
 /// In-memory secret store.
 ///
 /// Holds all secrets in a `BTreeMap` keyed by secret name, so keys and entries
 /// are always iterated in sorted order (deterministic `list`/`export`/`exec` output).
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Store {
     secrets: BTreeMap<String, SecretEntry>,
+    #[serde(flatten)]
+    meta: StoreMeta,
+    #[serde(skip)]
+    clock: SharedClock,
+}
+
+/// Store-wide metadata, i.e. everything in the payload except the secrets.
+///
+/// Flattened into [`Store`] so the single-ciphertext payload keeps its JSON layout; the
+/// sectioned payload stores it in the index.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub(crate) struct StoreMeta {
+    /// Schema version of the payload (see [`crate::migrations`]).
+    #[serde(default)]
+    schema: u32,
     creation_date: String,
+    /// Reference counts of the attachment chunks, keyed by chunk id.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    chunks: BTreeMap<String, u32>,
+    /// Hex-encoded random key that encrypts and addresses attachment chunks.
+    ///
+    /// Kept inside the encrypted payload (instead of using the password-derived key)
+    /// so `rekey` does not have to re-encrypt every chunk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Quotas::is_unlimited")]
+    quotas: Quotas,
+    /// Store settings, addressed as `keynest/<name>` (see [`crate::settings`]).
+    #[serde(
+        rename = "keynest",
+        default,
+        skip_serializing_if = "Settings::is_empty"
+    )]
+    settings: Settings,
+}
+
+/// Decrypted index of a sectioned payload.
+///
+/// Lists every key with the section holding its entry, so a reader can decrypt a
+/// single section on demand instead of the whole store.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct StoreIndex {
+    #[serde(flatten)]
+    meta: StoreMeta,
+    /// Section number of each key.
+    keys: BTreeMap<String, u32>,
+    /// Hex-encoded nonces of the section records, in order.
+    ///
+    /// Binds the sections to this index, so sections from another save of the same
+    /// store cannot be swapped in.
+    sections: Vec<String>,
+    /// Keys of the section records, in order, if every record is encrypted with its own
+    /// key (see [`crate::Keynest::set_per_entry_records`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    entry_keys: Vec<EntryKey>,
+    /// Expiry of each key that has one, so expired keys can be listed without
+    /// decrypting a section. `None` in indexes written before expiries were indexed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<BTreeMap<String, String>>,
+}
+
+/// Key of one section record, wrapped with the store key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct EntryKey {
+    /// Hex-encoded nonce the key is wrapped with.
+    nonce: String,
+    /// Hex-encoded wrapped key.
+    wrapped: String,
+    /// Hex-encoded SHA-256 digest of the record ciphertext, so the record can be checked
+    /// against the index before it is decrypted.
+    digest: String,
+}
+
+impl EntryKey {
+    /// Records the key of a section record, wrapped with `nonce` as `wrapped`, and
+    /// the digest of its `ciphertext`.
+    pub(crate) fn new(nonce: &[u8], wrapped: &[u8], ciphertext: &[u8]) -> Self {
+        Self {
+            nonce: crate::attachments::to_hex(nonce),
+            wrapped: crate::attachments::to_hex(wrapped),
+            digest: crate::attachments::to_hex(&Sha256::digest(ciphertext)),
+        }
+    }
+
+    /// Returns the nonce and the wrapped key.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::CorruptedIndex` if either is not valid hex.
+    pub(crate) fn wrapped(&self) -> Result<(Vec<u8>, Vec<u8>), StoreError> {
+        let decode = |hex: &str| {
+            crate::attachments::from_hex(hex)
+                .map_err(|_| StoreError::CorruptedIndex("invalid entry key".to_string()))
+        };
+        Ok((decode(&self.nonce)?, decode(&self.wrapped)?))
+    }
+
+    /// Returns `true` if `ciphertext` is the record this key was recorded for.
+    pub(crate) fn matches(&self, ciphertext: &[u8]) -> bool {
+        crate::attachments::to_hex(&Sha256::digest(ciphertext)) == self.digest
+    }
+}
+
+impl StoreIndex {
+    /// Returns the section holding `key`.
+    pub(crate) fn section_of(&self, key: &str) -> Option<u32> {
+        self.keys.get(key).copied()
+    }
+
+    /// Returns an iterator over all keys.
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &String> {
+        self.keys.keys()
+    }
+
+    /// Returns an iterator over the keys starting with `prefix`, in order.
+    pub(crate) fn keys_with_prefix<'a>(&'a self, prefix: &str) -> impl Iterator<Item = &'a String> {
+        keys_with_prefix(&self.keys, prefix)
+    }
+
+    /// Returns the number of keys.
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns the creation date of the store.
+    pub(crate) fn creation_date(&self) -> &str {
+        &self.meta.creation_date
+    }
+
+    /// Returns the store settings.
+    pub(crate) fn settings(&self) -> &Settings {
+        &self.meta.settings
+    }
+
+    pub(crate) fn quotas(&self) -> &Quotas {
+        &self.meta.quotas
+    }
+
+    /// Returns the keys whose expiry is at or before `now`, in order, or `None` if the
+    /// index predates indexed expiries.
+    pub(crate) fn expired_at(&self, now: DateTime<Utc>) -> Option<Vec<&String>> {
+        let expires = self.expires.as_ref()?;
+        Some(
+            expires
+                .iter()
+                .filter(|(_, expires)| {
+                    DateTime::parse_from_rfc3339(expires).is_ok_and(|expires| expires <= now)
+                })
+                .map(|(key, _)| key)
+                .collect(),
+        )
+    }
+
+    /// Returns the key of section `section`, or `None` if the sections are encrypted
+    /// with the store key.
+    pub(crate) fn entry_key(&self, section: u32) -> Option<&EntryKey> {
+        self.entry_keys.get(section as usize)
+    }
+
+    /// Returns `true` if every section is encrypted with its own key.
+    pub(crate) fn has_entry_keys(&self) -> bool {
+        !self.entry_keys.is_empty()
+    }
+
+    /// Checks that the section records match the nonces recorded in the index.
+    pub(crate) fn verify_sections<'a>(
+        &self,
+        nonces: impl ExactSizeIterator<Item = &'a [u8]>,
+    ) -> Result<(), StoreError> {
+        if nonces.len() != self.sections.len() {
+            return Err(StoreError::CorruptedIndex(
+                "section count does not match the index".to_string(),
+            ));
+        }
+        if self.has_entry_keys() && self.entry_keys.len() != self.sections.len() {
+            return Err(StoreError::CorruptedIndex(
+                "entry key count does not match the index".to_string(),
+            ));
+        }
+        for (i, (nonce, expected)) in nonces.zip(&self.sections).enumerate() {
+            if crate::attachments::to_hex(nonce) != *expected {
+                return Err(StoreError::CorruptedIndex(format!(
+                    "section {i} does not belong to this index"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SHA-256 digests of the metadata and of every entry of a store, to find what changed
+/// since (see [`Store::delta`]).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StoreDigest {
+    meta: [u8; 32],
+    entries: BTreeMap<String, [u8; 32]>,
+}
+
+impl StoreDigest {
+    /// Returns the entries added, changed and removed between this digest and `later`,
+    /// by key.
+    pub(crate) fn changes<'a>(&'a self, later: &'a StoreDigest) -> Vec<(AuditAction, &'a str)> {
+        let mut changes: Vec<_> = later
+            .entries
+            .iter()
+            .filter_map(|(key, digest)| match self.entries.get(key) {
+                None => Some((AuditAction::Set, key.as_str())),
+                Some(before) if before != digest => Some((AuditAction::Update, key.as_str())),
+                Some(_) => None,
+            })
+            .chain(
+                self.entries
+                    .keys()
+                    .filter(|key| !later.entries.contains_key(*key))
+                    .map(|key| (AuditAction::Remove, key.as_str())),
+            )
+            .collect();
+        changes.sort_by_key(|(_, key)| *key);
+        changes
+    }
+}
+
+/// The changes of a store since a [`StoreDigest`], as appended to the journal (see
+/// [`crate::journal`]).
+#[derive(Serialize, Debug)]
+pub(crate) struct StoreDelta<'a> {
+    /// Schema of the metadata and entries (see [`crate::migrations`]).
+    schema: u32,
+    /// The metadata, if it changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<&'a StoreMeta>,
+    /// Entries that were added or changed.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    secrets: BTreeMap<&'a str, &'a SecretEntry>,
+    /// Keys of removed entries.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<&'a str>,
+}
+
+impl StoreDelta<'_> {
+    /// Returns `true` if nothing changed.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.meta.is_none() && self.secrets.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A [`StoreDelta`] read back from the journal, already migrated.
+#[derive(Deserialize, Debug)]
+pub(crate) struct ParsedDelta {
+    #[serde(default)]
+    meta: Option<StoreMeta>,
+    #[serde(default)]
+    secrets: BTreeMap<String, SecretEntry>,
+    #[serde(default)]
+    removed: Vec<String>,
+}
+
+/// What an entry holds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A credential (password, token, key, ...).
+    #[default]
+    Secret,
+    /// Free-form markdown text, e.g. recovery instructions.
+    Note,
+    /// An `otpauth://totp/` URI holding a TOTP seed.
+    Totp,
+    /// A `keynest-derive:` recipe from which a site's password is derived.
+    Recipe,
+    /// A monotonic counter, stored as a decimal number.
+    Counter,
+}
+
+impl EntryKind {
+    fn is_secret(&self) -> bool {
+        *self == Self::Secret
+    }
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Secret => "secret",
+            Self::Note => "note",
+            Self::Totp => "totp",
+            Self::Recipe => "recipe",
+            Self::Counter => "counter",
+        })
+    }
+}
+
+/// Checks that `key` is a path of `/`-separated names, like `prod/db/password`.
+///
+/// Names must be non-empty and must not be `.` or `..`, so keys map cleanly onto
+/// namespaces (and onto files for tools that export them as such). Control characters
+/// are rejected anywhere in the key.
+///
+/// # Errors
+///
+/// Returns `StoreError::InvalidKey` describing the first problem found.
+pub fn validate_key(key: &str) -> Result<(), StoreError> {
+    let reason = if key.is_empty() {
+        Some("key is empty")
+    } else if key.starts_with('/') || key.ends_with('/') {
+        Some("key must not start or end with '/'")
+    } else if key.chars().any(char::is_control) {
+        Some("key must not contain control characters")
+    } else if key.split('/').any(str::is_empty) {
+        Some("key must not contain empty names ('//')")
+    } else if key.split('/').any(|name| name == "." || name == "..") {
+        Some("key must not contain '.' or '..' names")
+    } else {
+        None
+    };
+    match reason {
+        Some(reason) => Err(StoreError::InvalidKey {
+            key: key.to_string(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the keys of `map` starting with `prefix`, using the map's ordering to skip
+/// straight to them.
+fn keys_with_prefix<'a, V>(
+    map: &'a BTreeMap<String, V>,
+    prefix: &str,
+) -> impl Iterator<Item = &'a String> {
+    map.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        .map(|(key, _)| key)
+        .take_while(move |key| key.starts_with(prefix))
+}
+
+/// How [`crate::Keynest::import_entries`] treats keys that already exist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportPolicy {
+    /// Keep the existing value.
+    #[default]
+    SkipExisting,
+    /// Replace the existing value.
+    Overwrite,
+}
+
+/// What an import changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    created: usize,
+    updated: usize,
+    skipped: usize,
+}
+
+impl ImportSummary {
+    /// Returns the number of new entries.
+    pub fn created(&self) -> usize {
+        self.created
+    }
+
+    /// Returns the number of existing entries that were overwritten.
+    pub fn updated(&self) -> usize {
+        self.updated
+    }
+
+    /// Returns the number of existing entries that were left unchanged.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+/// What a rename changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenameSummary {
+    renamed: Vec<(String, String)>,
+    replaced: Vec<String>,
+    rewritten: Vec<String>,
+}
+
+impl RenameSummary {
+    /// Returns the renamed keys as `(old, new)` pairs, in order of the old keys.
+    pub fn renamed(&self) -> &[(String, String)] {
+        &self.renamed
+    }
+
+    /// Returns the keys of the entries that were in the way and got replaced.
+    pub fn replaced(&self) -> &[String] {
+        &self.replaced
+    }
+
+    /// Returns the (new) keys of the entries whose `ref:` value was rewritten to follow
+    /// a renamed key.
+    pub fn rewritten(&self) -> &[String] {
+        &self.rewritten
+    }
 }
 
 /// A single secret entry with key, value, and timestamp.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SecretEntry {
     key: String,
     value: String,
     updated: String,
+    /// RFC 3339 timestamp of the last change of the value; `None` in entries written
+    /// before it was tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotated: Option<String>,
+    #[serde(default, skip_serializing_if = "EntryKind::is_secret")]
+    kind: EntryKind,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    attachments: BTreeMap<String, Attachment>,
+    /// Sorted, without duplicates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// RFC 3339 timestamp after which the secret should be rotated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<String>,
+    /// Named values next to the main one, e.g. `user` and `url` of a login.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "AccessPolicy::is_unrestricted")]
+    policy: AccessPolicy,
+}
+
+/// Restrictions on how the value of an entry may be handed out.
+///
+/// The library only stores the policy; the `keynest` CLI checks it on every command
+/// that reveals a value (see [`AccessPolicy::merge`] for entries reached through
+/// `ref:` references).
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AccessPolicy {
+    /// Never write the value to stdout; the clipboard, a file descriptor, a file or
+    /// the environment of a command are fine.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    no_print: bool,
+    /// Ask on the terminal before each retrieval.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    confirm: bool,
+}
+
+impl AccessPolicy {
+    /// Returns the policy with "never print to stdout" set to `no_print`.
+    pub fn with_no_print(mut self, no_print: bool) -> Self {
+        self.no_print = no_print;
+        self
+    }
+
+    /// Returns the policy with "require confirmation on access" set to `confirm`.
+    pub fn with_confirm(mut self, confirm: bool) -> Self {
+        self.confirm = confirm;
+        self
+    }
+
+    /// Returns `true` if the value must never be written to stdout.
+    pub fn no_print(&self) -> bool {
+        self.no_print
+    }
+
+    /// Returns `true` if every retrieval must be confirmed.
+    pub fn confirm(&self) -> bool {
+        self.confirm
+    }
+
+    /// Returns `true` if neither restriction is set.
+    pub fn is_unrestricted(&self) -> bool {
+        !self.no_print && !self.confirm
+    }
+
+    /// Combines two policies so that every restriction of either applies, e.g. those of
+    /// a `ref:` entry and of the entry it points at.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            no_print: self.no_print || other.no_print,
+            confirm: self.confirm || other.confirm,
+        }
+    }
+}
+
+/// A file attached to a secret entry.
+///
+/// Only metadata lives in the payload: the content is stored as encrypted,
+/// content-addressed chunks next to the keystore, so identical content shared by
+/// several entries is stored once.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    size: u64,
+    chunks: Vec<String>,
+}
+
+impl Attachment {
+    pub(crate) fn new(size: u64, chunks: Vec<String>) -> Self {
+        Self { size, chunks }
+    }
+
+    /// Returns the plaintext size in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the ids of the chunks making up the content, in order.
+    pub fn chunks(&self) -> &[String] {
+        &self.chunks
+    }
 }
 
-impl SecretEntry {
-    pub(crate) fn new(key: String, value: String) -> Self {
-        Self {
-            key,
-            value,
-            updated: now_timestamp(),
+impl SecretEntry {
+    /// Overwrites the value and fields with zeros.
+    pub(crate) fn wipe(&mut self) {
+        self.value.zeroize();
+        self.fields.values_mut().for_each(Zeroize::zeroize);
+    }
+
+    pub(crate) fn new(key: String, value: String, kind: EntryKind, updated: String) -> Self {
+        Self {
+            key,
+            value,
+            rotated: Some(updated.clone()),
+            updated,
+            kind,
+            attachments: BTreeMap::new(),
+            tags: Vec::new(),
+            expires: None,
+            fields: BTreeMap::new(),
+            policy: AccessPolicy::default(),
+        }
+    }
+
+    /// Returns a copy of the entry without its attachments, whose chunks only exist next
+    /// to this store.
+    pub(crate) fn without_attachments(&self) -> Self {
+        Self {
+            attachments: BTreeMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the secret key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the secret value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the last update timestamp.
+    pub fn updated(&self) -> &str {
+        &self.updated
+    }
+
+    /// Returns the timestamp of the last change of the value. Entries from before
+    /// keynest tracked it return their last update timestamp, which is no earlier.
+    pub fn rotated(&self) -> &str {
+        self.rotated.as_deref().unwrap_or(&self.updated)
+    }
+
+    /// Returns [`SecretEntry::rotated`] parsed, or `None` if it is not a valid RFC 3339
+    /// timestamp.
+    pub fn rotated_at(&self) -> Option<DateTime<Utc>> {
+        let rotated = DateTime::parse_from_rfc3339(self.rotated()).ok()?;
+        Some(rotated.with_timezone(&Utc))
+    }
+
+    /// Returns whether the entry is a secret or a note.
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    /// Returns the attachments of this entry, keyed by name.
+    pub fn attachments(&self) -> &BTreeMap<String, Attachment> {
+        &self.attachments
+    }
+
+    /// Returns the tags of this entry, sorted.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Returns when the secret expires, if an expiry is set.
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        let expires = DateTime::parse_from_rfc3339(self.expires.as_deref()?).ok()?;
+        Some(expires.with_timezone(&Utc))
+    }
+
+    /// Returns the fields of this entry, keyed by name.
+    pub fn fields(&self) -> &BTreeMap<String, String> {
+        &self.fields
+    }
+
+    /// Returns the access restrictions of this entry.
+    pub fn policy(&self) -> AccessPolicy {
+        self.policy
+    }
+
+    /// Returns the value of field `name`, if the entry has one.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    /// Returns the value of the libsecret-style lookup attribute `name`.
+    ///
+    /// A field of the same name always wins. Otherwise the attributes applications
+    /// usually look up are derived from the entry:
+    /// - `service`: the namespace of the key (`wifi` for `wifi/home`), or the whole key
+    /// - `account` (or `user`, `username`): the `user` or `username` field, else the last
+    ///   name of a key with a namespace
+    /// - `host` (or `server`): the host of the `url` field
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        if let Some(value) = self.field(name) {
+            return Some(value);
+        }
+        match name {
+            "service" => Some(self.key.rsplit_once('/').map_or(&self.key, |(ns, _)| ns)),
+            "account" | "user" | "username" => self
+                .field("user")
+                .or_else(|| self.field("username"))
+                .or_else(|| self.key.rsplit_once('/').map(|(_, name)| name)),
+            "host" | "server" => self.field("url").and_then(url_host),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if every `(name, value)` pair matches [`SecretEntry::attribute`];
+    /// host names are compared ignoring ASCII case.
+    pub(crate) fn matches_attributes(&self, attributes: &[(&str, &str)]) -> bool {
+        attributes
+            .iter()
+            .all(|&(name, value)| match (name, self.attribute(name)) {
+                ("host" | "server", Some(host)) => host.eq_ignore_ascii_case(value),
+                (_, found) => found == Some(value),
+            })
+    }
+
+    /// Returns `true` if the secret has an expiry at or before `now`.
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires().is_some_and(|expires| expires <= now)
+    }
+
+    /// Returns `true` if the entry is tagged with `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.binary_search_by(|t| t.as_str().cmp(tag)).is_ok()
+    }
+
+    pub(crate) fn update_value(&mut self, new_value: String, updated: String) {
+        if new_value != self.value {
+            self.rotated = Some(updated.clone());
+        }
+        self.value = new_value;
+        self.updated = updated;
+    }
+}
+
+impl Store {
+    /// Creates a new empty store.
+    pub fn new() -> Self {
+        Self::with_clock(SharedClock::default().0)
+    }
+
+    /// Creates a new empty store whose timestamps come from `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Store {
+            secrets: BTreeMap::new(),
+            meta: StoreMeta {
+                schema: CURRENT_SCHEMA,
+                creation_date: format_timestamp(clock.now()),
+                ..StoreMeta::default()
+            },
+            clock: SharedClock(clock),
+        }
+    }
+
+    /// Creates a new empty store with the clock and creation date of this one.
+    pub(crate) fn empty_like(&self) -> Self {
+        let mut store = Self::with_clock(self.clock().clone());
+        store
+            .meta
+            .creation_date
+            .clone_from(&self.meta.creation_date);
+        store
+    }
+
+    /// Returns the clock of the store.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock.0
+    }
+
+    /// Makes the store read the time from `clock` from now on.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = SharedClock(clock);
+    }
+
+    /// Overwrites the values, fields and attachment key with zeros and empties the
+    /// store; the clock is kept.
+    pub fn wipe(&mut self) {
+        self.secrets.values_mut().for_each(SecretEntry::wipe);
+        self.meta.attachment_key.zeroize();
+        self.secrets.clear();
+        self.meta = StoreMeta::default();
+    }
+
+    /// Splits the entries into sections for the sectioned payload.
+    ///
+    /// Entries stay in key order; a section is closed once it holds
+    /// [`SECTION_MAX_ENTRIES`] entries or about [`SECTION_MAX_BYTES`] of keys and values,
+    /// or after every entry with [`PerEntryRecords`] set.
+    pub(crate) fn sections(&self) -> Vec<Vec<&SecretEntry>> {
+        let max_entries = match self.settings().get::<PerEntryRecords>() {
+            Ok(Some(true)) => 1,
+            _ => SECTION_MAX_ENTRIES,
+        };
+        let mut sections = Vec::new();
+        let mut current: Vec<&SecretEntry> = Vec::new();
+        let mut bytes = 0;
+
+        for entry in self.secrets.values() {
+            let size = entry.key.len()
+                + entry.value.len()
+                + entry
+                    .fields
+                    .iter()
+                    .map(|(name, value)| name.len() + value.len())
+                    .sum::<usize>();
+            if !current.is_empty()
+                && (current.len() == max_entries || bytes + size > SECTION_MAX_BYTES)
+            {
+                sections.push(std::mem::take(&mut current));
+                bytes = 0;
+            }
+            current.push(entry);
+            bytes += size;
+        }
+        if !current.is_empty() {
+            sections.push(current);
+        }
+        sections
+    }
+
+    /// Builds the index for `sections` (as returned by [`Store::sections`]), whose
+    /// records were encrypted with `nonces` and, if not empty, `entry_keys`.
+    pub(crate) fn index(
+        &self,
+        sections: &[Vec<&SecretEntry>],
+        nonces: &[&[u8]],
+        entry_keys: Vec<EntryKey>,
+    ) -> StoreIndex {
+        let keys = sections
+            .iter()
+            .enumerate()
+            .flat_map(|(i, section)| section.iter().map(move |e| (e.key.clone(), i as u32)))
+            .collect();
+
+        StoreIndex {
+            meta: self.meta.clone(),
+            keys,
+            sections: nonces
+                .iter()
+                .map(|n| crate::attachments::to_hex(n))
+                .collect(),
+            entry_keys,
+            expires: Some(self.expiries()),
+        }
+    }
+
+    /// Reassembles a store from its index and decrypted sections.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::CorruptedIndex` if an entry is not listed in the index under
+    /// its section, or the index lists keys missing from the sections.
+    pub(crate) fn from_sections(
+        index: StoreIndex,
+        sections: Vec<Vec<SecretEntry>>,
+    ) -> Result<Self, StoreError> {
+        let mut secrets = BTreeMap::new();
+        for (i, section) in sections.into_iter().enumerate() {
+            for entry in section {
+                if index.section_of(&entry.key) != Some(i as u32) {
+                    return Err(StoreError::CorruptedIndex(format!(
+                        "'{}' is not indexed in section {i}",
+                        entry.key
+                    )));
+                }
+                secrets.insert(entry.key.clone(), entry);
+            }
+        }
+        if secrets.len() != index.len() {
+            return Err(StoreError::CorruptedIndex(
+                "index lists keys missing from the sections".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            secrets,
+            meta: index.meta,
+            clock: SharedClock::default(),
+        })
+    }
+
+    /// Converts the store into an index with all entries in section 0.
+    ///
+    /// Used to present single-ciphertext payloads through the same interface as
+    /// sectioned ones.
+    pub(crate) fn into_single_section(self) -> (StoreIndex, BTreeMap<String, SecretEntry>) {
+        let index = StoreIndex {
+            expires: Some(self.expiries()),
+            meta: self.meta,
+            keys: self.secrets.keys().map(|k| (k.clone(), 0)).collect(),
+            sections: Vec::new(),
+            entry_keys: Vec::new(),
+        };
+        (index, self.secrets)
+    }
+
+    /// Returns the expiry of each entry that has one, as stored.
+    fn expiries(&self) -> BTreeMap<String, String> {
+        self.secrets
+            .iter()
+            .filter_map(|(key, entry)| Some((key.clone(), entry.expires.clone()?)))
+            .collect()
+    }
+
+    /// Computes the digests [`Store::delta`] compares against.
+    pub(crate) fn digest(&self) -> StoreDigest {
+        StoreDigest {
+            meta: digest_of(&self.meta),
+            entries: self
+                .secrets
+                .iter()
+                .map(|(key, entry)| (key.clone(), digest_of(entry)))
+                .collect(),
+        }
+    }
+
+    /// Returns the changes since `since`, along with the digests of the current store.
+    pub(crate) fn delta<'a>(&'a self, since: &'a StoreDigest) -> (StoreDelta<'a>, StoreDigest) {
+        let digest = self.digest();
+        let secrets = self
+            .secrets
+            .iter()
+            .filter(|(key, _)| since.entries.get(*key) != digest.entries.get(*key))
+            .map(|(key, entry)| (key.as_str(), entry))
+            .collect();
+        let removed = since
+            .entries
+            .keys()
+            .filter(|key| !self.secrets.contains_key(*key))
+            .map(String::as_str)
+            .collect();
+
+        let delta = StoreDelta {
+            schema: CURRENT_SCHEMA,
+            meta: (since.meta != digest.meta).then_some(&self.meta),
+            secrets,
+            removed,
+        };
+        (delta, digest)
+    }
+
+    /// Applies changes read back from the journal.
+    pub(crate) fn apply_delta(&mut self, delta: ParsedDelta) {
+        for key in &delta.removed {
+            if let Some(mut entry) = self.secrets.remove(key) {
+                entry.value.zeroize();
+                entry.fields.values_mut().for_each(Zeroize::zeroize);
+            }
+        }
+        for (key, entry) in delta.secrets {
+            if let Some(mut old) = self.secrets.insert(key, entry) {
+                old.value.zeroize();
+                old.fields.values_mut().for_each(Zeroize::zeroize);
+            }
+        }
+        if let Some(meta) = delta.meta {
+            self.meta.attachment_key.zeroize();
+            self.meta = meta;
+        }
+    }
+
+    /// Stores a secret.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyAlreadyExists` if key already exists, or
+    /// `StoreError::ReservedKey` if it lies in the `keynest/` settings namespace.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), StoreError> {
+        self.insert(key, value, EntryKind::Secret)
+    }
+
+    /// Stores a note.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Store::set`].
+    pub fn set_note(&mut self, key: &str, text: &str) -> Result<(), StoreError> {
+        self.insert(key, text, EntryKind::Note)
+    }
+
+    /// Stores a TOTP entry; `uri` is its `otpauth://totp/` URI.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Store::set`].
+    pub fn set_totp(&mut self, key: &str, uri: &str) -> Result<(), StoreError> {
+        self.insert(key, uri, EntryKind::Totp)
+    }
+
+    /// Stores a password recipe; `uri` is its `keynest-derive:` URI.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Store::set`].
+    pub fn set_recipe(&mut self, key: &str, uri: &str) -> Result<(), StoreError> {
+        self.insert(key, uri, EntryKind::Recipe)
+    }
+
+    /// Stores a counter starting at `start`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Store::set`].
+    pub fn set_counter(&mut self, key: &str, start: u64) -> Result<(), StoreError> {
+        self.insert(key, &start.to_string(), EntryKind::Counter)
+    }
+
+    fn insert(&mut self, key: &str, value: &str, kind: EntryKind) -> Result<(), StoreError> {
+        validate_key(key)?;
+        if is_reserved_key(key) {
+            Err(StoreError::ReservedKey(key.to_string()))
+        } else if self.secrets.contains_key(key) {
+            Err(StoreError::KeyAlreadyExists(key.to_string()))
+        } else {
+            self.meta.quotas.check_value(key, value.len())?;
+            self.meta.quotas.check_entries(self.secrets.len() + 1)?;
+            self.check_reference(key, value)?;
+            self.secrets.insert(
+                key.to_string(),
+                SecretEntry::new(
+                    key.to_string(),
+                    value.to_string(),
+                    kind,
+                    format_timestamp(self.clock.0.now()),
+                ),
+            );
+            Ok(())
+        }
+    }
+
+    /// Retrieves a secret by key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.secrets.get(key).map(|e| e.value())
+    }
+
+    /// Returns the entry `key` with its metadata.
+    pub fn entry(&self, key: &str) -> Option<&SecretEntry> {
+        self.secrets.get(key)
+    }
+
+    /// Removes a secret.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if key doesn't exist.
+    pub fn remove(&mut self, key: &str) -> Result<(), StoreError> {
+        match self.secrets.remove(key) {
+            Some(entry) => {
+                for attachment in entry.attachments.values() {
+                    self.release_chunks(attachment);
+                }
+                Ok(())
+            }
+            None => Err(StoreError::KeyNotFound(key.to_string())),
+        }
+    }
+
+    /// Renames entry `from` to `to`, rewriting the references to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if `from` doesn't exist, or the errors of
+    /// [`Store::rename_prefix`].
+    pub fn rename(
+        &mut self,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+    ) -> Result<RenameSummary, StoreError> {
+        if !self.secrets.contains_key(from) {
+            return Err(StoreError::KeyNotFound(from.to_string()));
+        }
+        self.rename_keys(vec![(from.to_string(), to.to_string())], overwrite)
+    }
+
+    /// Renames every entry whose key starts with `from` by replacing that prefix with
+    /// `to` (e.g. `old-app/` to `new-app/`), all of them or none.
+    ///
+    /// The entries keep their kind, fields, tags, attachments and timestamps, and `ref:`
+    /// values pointing at a renamed key are rewritten to its new key. With `overwrite`,
+    /// entries in the way are replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::InvalidKey` or `StoreError::ReservedKey` if a new key is not
+    /// allowed, or, without `overwrite`, `StoreError::KeyAlreadyExists` if it is taken by
+    /// an entry that is not renamed itself; the store is then left as it was.
+    pub fn rename_prefix(
+        &mut self,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+    ) -> Result<RenameSummary, StoreError> {
+        let renames = self
+            .keys_with_prefix(from)
+            .map(|key| (key.clone(), format!("{to}{}", &key[from.len()..])))
+            .collect();
+        self.rename_keys(renames, overwrite)
+    }
+
+    fn rename_keys(
+        &mut self,
+        renames: Vec<(String, String)>,
+        overwrite: bool,
+    ) -> Result<RenameSummary, StoreError> {
+        let moved: BTreeMap<&str, &str> = renames
+            .iter()
+            .map(|(old, new)| (old.as_str(), new.as_str()))
+            .collect();
+        for new in moved.values() {
+            validate_key(new)?;
+            if is_reserved_key(new) {
+                return Err(StoreError::ReservedKey(new.to_string()));
+            }
+            if !overwrite && self.secrets.contains_key(*new) && !moved.contains_key(*new) {
+                return Err(StoreError::KeyAlreadyExists(new.to_string()));
+            }
+        }
+
+        let mut replaced = Vec::new();
+
+        let entries: Vec<SecretEntry> = moved
+            .keys()
+            .filter_map(|old| self.secrets.remove(*old))
+            .collect();
+        for mut entry in entries {
+            entry.key = moved[entry.key.as_str()].to_string();
+            if let Some(previous) = self.secrets.insert(entry.key.clone(), entry) {
+                for attachment in previous.attachments.values() {
+                    self.release_chunks(attachment);
+                }
+                replaced.push(previous.key);
+            }
+        }
+
+        let mut rewritten = Vec::new();
+        for entry in self.secrets.values_mut() {
+            if let Some(new) = reference_target(&entry.value).and_then(|t| moved.get(t)) {
+                entry.value = format!("{REFERENCE_PREFIX}{new}");
+                rewritten.push(entry.key.clone());
+            }
+        }
+
+        Ok(RenameSummary {
+            renamed: renames,
+            replaced,
+            rewritten,
+        })
+    }
+
+    /// Copies entry `from` to `to`, with its kind, fields, tags, expiry and attachments.
+    /// With `overwrite`, an entry `to` is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if `from` doesn't exist, or the errors of
+    /// [`Store::set`] (`StoreError::KeyAlreadyExists` only without `overwrite`).
+    pub fn copy(&mut self, from: &str, to: &str, overwrite: bool) -> Result<(), StoreError> {
+        let entry = self
+            .secrets
+            .get(from)
+            .ok_or_else(|| StoreError::KeyNotFound(from.to_string()))?;
+        validate_key(to)?;
+        if is_reserved_key(to) {
+            return Err(StoreError::ReservedKey(to.to_string()));
+        }
+        let exists = self.secrets.contains_key(to);
+        if exists && !overwrite {
+            return Err(StoreError::KeyAlreadyExists(to.to_string()));
+        }
+        if !exists {
+            self.meta.quotas.check_entries(self.secrets.len() + 1)?;
+        }
+        self.check_reference(to, &entry.value)?;
+
+        let mut copy = entry.clone();
+        copy.key = to.to_string();
+        copy.updated = format_timestamp(self.clock.0.now());
+        for attachment in copy.attachments.values() {
+            for id in &attachment.chunks {
+                *self.meta.chunks.entry(id.clone()).or_insert(0) += 1;
+            }
+        }
+        if let Some(previous) = self.secrets.insert(to.to_string(), copy) {
+            for attachment in previous.attachments.values() {
+                self.release_chunks(attachment);
+            }
         }
+        Ok(())
     }
 
-    /// Returns the secret key.
-    pub fn key(&self) -> &str {
-        &self.key
+    /// Attaches a file (already written as chunks) to an existing secret and takes a
+    /// reference on each of its chunks.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if the secret doesn't exist, or
+    /// `StoreError::AttachmentAlreadyExists` if it already has an attachment `name`.
+    pub fn attach(
+        &mut self,
+        key: &str,
+        name: &str,
+        attachment: Attachment,
+    ) -> Result<(), StoreError> {
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+
+        if entry.attachments.contains_key(name) {
+            return Err(StoreError::AttachmentAlreadyExists {
+                key: key.to_string(),
+                name: name.to_string(),
+            });
+        }
+
+        for id in &attachment.chunks {
+            *self.meta.chunks.entry(id.clone()).or_insert(0) += 1;
+        }
+        entry.attachments.insert(name.to_string(), attachment);
+        entry.updated = format_timestamp(self.clock.0.now());
+        Ok(())
     }
 
-    /// Returns the secret value.
-    pub fn value(&self) -> &str {
-        &self.value
+    /// Detaches a file from a secret and releases its chunk references.
+    ///
+    /// Chunks whose reference count drops to zero are forgotten; their files are
+    /// deleted by the next purge.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` or `StoreError::AttachmentNotFound`.
+    pub fn detach(&mut self, key: &str, name: &str) -> Result<Attachment, StoreError> {
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+
+        let attachment =
+            entry
+                .attachments
+                .remove(name)
+                .ok_or_else(|| StoreError::AttachmentNotFound {
+                    key: key.to_string(),
+                    name: name.to_string(),
+                })?;
+        entry.updated = format_timestamp(self.clock.0.now());
+
+        self.release_chunks(&attachment);
+        Ok(attachment)
     }
 
-    /// Returns the last update timestamp.
-    pub fn updated(&self) -> &str {
-        &self.updated
+    /// Tags secret `key` with `tag`. Returns `false` if it already had the tag.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if the secret doesn't exist, or
+    /// `StoreError::InvalidTag` if `tag` is empty or contains whitespace or commas.
+    pub fn add_tag(&mut self, key: &str, tag: &str) -> Result<bool, StoreError> {
+        if tag.is_empty()
+            || tag
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || c == ',')
+        {
+            return Err(StoreError::InvalidTag(tag.to_string()));
+        }
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+
+        match entry.tags.binary_search_by(|t| t.as_str().cmp(tag)) {
+            Ok(_) => Ok(false),
+            Err(pos) => {
+                entry.tags.insert(pos, tag.to_string());
+                entry.updated = format_timestamp(self.clock.0.now());
+                Ok(true)
+            }
+        }
     }
 
-    pub(crate) fn update_value(&mut self, new_value: String) {
-        self.value = new_value;
-        self.updated = now_timestamp();
+    /// Sets or, with `None`, clears the expiry of secret `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if the secret doesn't exist.
+    pub fn set_expiry(
+        &mut self,
+        key: &str,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+        entry.expires = expires.map(format_timestamp);
+        entry.updated = format_timestamp(self.clock.0.now());
+        Ok(())
     }
-}
 
-impl Store {
-    /// Creates a new empty store.
-    pub fn new() -> Self {
-        Store {
-            secrets: BTreeMap::new(),
-            creation_date: now_timestamp(),
-        }
+    /// Replaces the access policy of secret `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if the secret doesn't exist.
+    pub fn set_policy(&mut self, key: &str, policy: AccessPolicy) -> Result<(), StoreError> {
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+        entry.policy = policy;
+        entry.updated = format_timestamp(self.clock.0.now());
+        Ok(())
     }
 
-    /// Stores a secret.
+    /// Sets field `name` of secret `key` to `value`, replacing an existing one.
     ///
     /// # Errors
     ///
-    /// Returns `StoreError::KeyAlreadyExists` if key already exists.
-    pub fn set(&mut self, key: &str, value: &str) -> Result<(), StoreError> {
-        if self.secrets.contains_key(key) {
-            Err(StoreError::KeyAlreadyExists(key.to_string()))
-        } else {
-            self.secrets.insert(
-                key.to_string(),
-                SecretEntry::new(key.to_string(), value.to_string()),
-            );
-            Ok(())
+    /// Returns `StoreError::KeyNotFound` if the secret doesn't exist,
+    /// `StoreError::InvalidField` if `name` is empty or contains whitespace, `=` or
+    /// control characters, or `StoreError::QuotaExceeded` if `value` is too long.
+    pub fn set_field(&mut self, key: &str, name: &str, value: &str) -> Result<(), StoreError> {
+        if name.is_empty()
+            || name
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || c == '=')
+        {
+            return Err(StoreError::InvalidField(name.to_string()));
         }
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+        self.meta.quotas.check_value(key, value.len())?;
+
+        entry.fields.insert(name.to_string(), value.to_string());
+        entry.updated = format_timestamp(self.clock.0.now());
+        Ok(())
     }
 
-    /// Retrieves a secret by key.
-    pub fn get(&self, key: &str) -> Option<&str> {
-        self.secrets.get(key).map(|e| e.value())
+    /// Removes field `name` from secret `key`. Returns `false` if it had no such field.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StoreError::KeyNotFound` if the secret doesn't exist.
+    pub fn remove_field(&mut self, key: &str, name: &str) -> Result<bool, StoreError> {
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+
+        if entry.fields.remove(name).is_none() {
+            return Ok(false);
+        }
+        entry.updated = format_timestamp(self.clock.0.now());
+        Ok(true)
     }
 
-    /// Removes a secret.
+    /// Removes `tag` from secret `key`. Returns `false` if it did not have the tag.
     ///
     /// # Errors
     ///
-    /// Returns `StoreError::KeyNotFound` if key doesn't exist.
-    pub fn remove(&mut self, key: &str) -> Result<(), StoreError> {
-        if self.secrets.remove(key).is_some() {
-            Ok(())
-        } else {
-            Err(StoreError::KeyNotFound(key.to_string()))
+    /// Returns `StoreError::KeyNotFound` if the secret doesn't exist.
+    pub fn remove_tag(&mut self, key: &str, tag: &str) -> Result<bool, StoreError> {
+        let entry = self
+            .secrets
+            .get_mut(key)
+            .ok_or_else(|| StoreError::KeyNotFound(key.to_string()))?;
+
+        match entry.tags.binary_search_by(|t| t.as_str().cmp(tag)) {
+            Ok(pos) => {
+                entry.tags.remove(pos);
+                entry.updated = format_timestamp(self.clock.0.now());
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Returns the attachment `name` of secret `key`.
+    pub fn attachment(&self, key: &str, name: &str) -> Option<&Attachment> {
+        self.secrets.get(key)?.attachments.get(name)
+    }
+
+    /// Returns `true` if any attachment still references chunk `id`.
+    pub fn is_chunk_referenced(&self, id: &str) -> bool {
+        self.meta.chunks.contains_key(id)
+    }
+
+    /// Returns the configured quotas.
+    pub fn quotas(&self) -> &Quotas {
+        &self.meta.quotas
+    }
+
+    /// Replaces the configured quotas.
+    pub fn set_quotas(&mut self, quotas: Quotas) {
+        self.meta.quotas = quotas;
+    }
+
+    /// Returns the store settings.
+    pub fn settings(&self) -> &Settings {
+        &self.meta.settings
+    }
+
+    /// Returns the store settings for modification.
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.meta.settings
+    }
+
+    /// Returns the hex-encoded attachment key, if one has been generated.
+    pub fn attachment_key(&self) -> Option<&str> {
+        self.meta.attachment_key.as_deref()
+    }
+
+    /// Sets the hex-encoded attachment key.
+    pub fn set_attachment_key(&mut self, key: String) {
+        self.meta.attachment_key = Some(key);
+    }
+
+    fn release_chunks(&mut self, attachment: &Attachment) {
+        for id in &attachment.chunks {
+            if let Some(count) = self.meta.chunks.get_mut(id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.meta.chunks.remove(id);
+                }
+            }
         }
     }
 
@@ -111,20 +1450,186 @@ impl Store {
     ///
     /// Returns `StoreError::KeyNotFound` if key doesn't exist.
     pub fn update(&mut self, key: &str, value: &str) -> Result<(), StoreError> {
+        if self.secrets.contains_key(key) {
+            self.meta.quotas.check_value(key, value.len())?;
+            self.check_reference(key, value)?;
+        }
         match self.secrets.get_mut(key) {
             Some(secret) => {
-                secret.update_value(value.to_string());
+                secret.update_value(value.to_string(), format_timestamp(self.clock.0.now()));
                 Ok(())
             }
             None => Err(StoreError::KeyNotFound(key.to_string())),
         }
     }
 
+    /// Stores many secrets at once: all of them, or none if one is rejected.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of [`Store::set`] or [`Store::update`]; the store is then
+    /// left as it was.
+    pub fn import<K: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        entries: impl IntoIterator<Item = (K, V)>,
+        policy: ImportPolicy,
+    ) -> Result<ImportSummary, StoreError> {
+        // Previous entry of each changed key (`None` if it was created).
+        let mut undo: Vec<(String, Option<SecretEntry>)> = Vec::new();
+        let mut summary = ImportSummary::default();
+
+        for (key, value) in entries {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            let result = match self.secrets.get(key) {
+                Some(_) if policy == ImportPolicy::SkipExisting => {
+                    summary.skipped += 1;
+                    continue;
+                }
+                Some(entry) => {
+                    let previous = entry.clone();
+                    self.update(key, value).map(|()| {
+                        undo.push((key.to_string(), Some(previous)));
+                        summary.updated += 1;
+                    })
+                }
+                None => self.set(key, value).map(|()| {
+                    undo.push((key.to_string(), None));
+                    summary.created += 1;
+                }),
+            };
+
+            if let Err(e) = result {
+                for (key, previous) in undo.into_iter().rev() {
+                    match previous {
+                        Some(previous) => {
+                            self.secrets.insert(key, previous);
+                        }
+                        None => {
+                            self.secrets.remove(&key);
+                        }
+                    }
+                }
+                return Err(e);
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Stores entries taken from another store (see [`crate::Keynest::import_bundle`])
+    /// with their kind, fields, tags, expiry, policy and timestamp: all of them, or none
+    /// if one is rejected. An overwritten entry keeps its attachments.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first invalid or reserved key, exceeded quota or reference cycle; the
+    /// store is then left as it was.
+    pub(crate) fn import_full(
+        &mut self,
+        entries: Vec<SecretEntry>,
+        policy: ImportPolicy,
+    ) -> Result<ImportSummary, StoreError> {
+        let previous = self.secrets.clone();
+        let result = self.put_all(entries, policy);
+        if result.is_err() {
+            self.secrets = previous;
+        }
+        result
+    }
+
+    fn put_all(
+        &mut self,
+        entries: Vec<SecretEntry>,
+        policy: ImportPolicy,
+    ) -> Result<ImportSummary, StoreError> {
+        let mut summary = ImportSummary::default();
+        let mut imported = Vec::new();
+        for mut entry in entries {
+            validate_key(&entry.key)?;
+            if is_reserved_key(&entry.key) {
+                return Err(StoreError::ReservedKey(entry.key));
+            }
+            self.meta
+                .quotas
+                .check_value(&entry.key, entry.value.len())?;
+            for value in entry.fields.values() {
+                self.meta.quotas.check_value(&entry.key, value.len())?;
+            }
+            match self.secrets.get(&entry.key) {
+                Some(_) if policy == ImportPolicy::SkipExisting => {
+                    summary.skipped += 1;
+                    continue;
+                }
+                Some(existing) => {
+                    entry.attachments = existing.attachments.clone();
+                    summary.updated += 1;
+                }
+                None => {
+                    entry.attachments.clear();
+                    summary.created += 1;
+                }
+            }
+            imported.push(entry.key.clone());
+            self.secrets.insert(entry.key.clone(), entry);
+        }
+        self.meta.quotas.check_entries(self.secrets.len())?;
+        for key in &imported {
+            self.check_reference(key, &self.secrets[key].value)?;
+        }
+        Ok(summary)
+    }
+
+    /// Returns the keys whose value directly references `key`.
+    pub fn dependents(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.secrets
+            .values()
+            .filter(move |e| reference_target(e.value()) == Some(key))
+            .map(|e| e.key())
+    }
+
+    /// Rejects `value` if storing it under `key` would close a reference cycle.
+    ///
+    /// Dangling references are allowed here (the target may be created later) and are
+    /// only reported when resolving.
+    fn check_reference(&self, key: &str, value: &str) -> Result<(), StoreError> {
+        let Some(mut target) = reference_target(value) else {
+            return Ok(());
+        };
+
+        let mut chain = vec![key.to_string()];
+        loop {
+            chain.push(target.to_string());
+            if target == key {
+                return Err(StoreError::ReferenceCycle(chain));
+            }
+            match self.get(target).and_then(reference_target) {
+                // A pre-existing cycle that doesn't involve `key` is reported on resolve.
+                Some(next) if chain.iter().any(|k| k == next) && next != key => return Ok(()),
+                Some(next) => target = next,
+                None => return Ok(()),
+            }
+        }
+    }
+
     /// Returns an iterator over all keys.
     pub fn keys(&self) -> impl Iterator<Item = &String> {
         self.secrets.keys()
     }
 
+    /// Returns an iterator over the keys starting with `prefix`, in order.
+    ///
+    /// With a namespace prefix such as `prod/`, this lists everything under `prod`.
+    pub fn keys_with_prefix<'a>(&'a self, prefix: &str) -> impl Iterator<Item = &'a String> {
+        keys_with_prefix(&self.secrets, prefix)
+    }
+
+    /// Returns an iterator over the keys of entries tagged with `tag`, in order.
+    pub fn keys_with_tag<'a>(&'a self, tag: &str) -> impl Iterator<Item = &'a String> {
+        self.secrets
+            .iter()
+            .filter(move |(_, entry)| entry.has_tag(tag))
+            .map(|(key, _)| key)
+    }
+
     /// Returns an iterator over all secret entries.
     pub fn entries(&self) -> impl Iterator<Item = &SecretEntry> {
         self.secrets.values()
@@ -132,7 +1637,7 @@ impl Store {
 
     /// Returns the creation date of the store.
     pub fn creation_date(&self) -> &str {
-        &self.creation_date
+        &self.meta.creation_date
     }
 
     /// Returns the number of secrets stored.
@@ -145,11 +1650,37 @@ impl Store {
 mod tests {
     use super::*;
 
+    /// Returns the keys visited when resolving `key`, like [`crate::Keynest::references`].
+    fn reference_chain(store: &Store, key: &str) -> Result<Vec<String>, StoreError> {
+        walk_references(key, |k| {
+            Ok(store
+                .get(k)
+                .map(|v| reference_target(v).map(str::to_string)))
+        })
+    }
+
+    /// Follows `ref:` values like [`crate::Keynest::resolve`].
+    fn resolve<'a>(store: &'a Store, key: &str) -> Result<Option<&'a str>, StoreError> {
+        let chain = reference_chain(store, key)?;
+        Ok(chain.last().and_then(|last| store.get(last)))
+    }
+
     #[test]
     fn create_new_store_works() {
         let store = Store::new();
         assert_eq!(store.secrets.keys().count(), 0);
-        assert_ne!(store.creation_date, "");
+        assert_ne!(store.meta.creation_date, "");
+    }
+
+    #[test]
+    fn settings_namespace_is_reserved() {
+        let mut store = Store::new();
+        assert!(matches!(
+            store.set("keynest/padding", "x"),
+            Err(StoreError::ReservedKey(_))
+        ));
+        store.set("keynest", "x").unwrap();
+        store.set("app/keynest/x", "x").unwrap();
     }
 
     #[test]
@@ -203,6 +1734,145 @@ mod tests {
         }
     }
 
+    #[test]
+    fn keys_are_validated_as_paths() {
+        for key in ["api_key", "prod/db/password", "a.b/c..d", "with space"] {
+            assert!(validate_key(key).is_ok(), "{key}");
+        }
+        for key in [
+            "",
+            "/abs",
+            "trailing/",
+            "a//b",
+            "a/./b",
+            "../up",
+            "tab\there",
+        ] {
+            assert!(
+                matches!(validate_key(key), Err(StoreError::InvalidKey { .. })),
+                "{key}"
+            );
+        }
+
+        let mut store = Store::new();
+        assert!(store.set("prod//db", "x").is_err());
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn keys_with_prefix_lists_a_namespace() {
+        let mut store = Store::new();
+        for key in [
+            "prod/db/password",
+            "prod/api",
+            "production",
+            "staging/api",
+            "a",
+        ] {
+            store.set(key, "x").unwrap();
+        }
+        let keys: Vec<_> = store.keys_with_prefix("prod/").collect();
+        assert_eq!(keys, ["prod/api", "prod/db/password"]);
+        assert_eq!(store.keys_with_prefix("prod").count(), 3);
+        assert_eq!(store.keys_with_prefix("").count(), 5);
+        assert_eq!(store.keys_with_prefix("zzz").count(), 0);
+    }
+
+    #[test]
+    fn tags_stay_sorted_and_unique() {
+        let mut store = Store::new();
+        store.set("a", "x").unwrap();
+
+        assert!(store.add_tag("a", "prod").unwrap());
+        assert!(store.add_tag("a", "billing").unwrap());
+        assert!(!store.add_tag("a", "prod").unwrap());
+        assert!(matches!(
+            store.add_tag("a", "two words"),
+            Err(StoreError::InvalidTag(_))
+        ));
+        assert!(store.add_tag("missing", "prod").is_err());
+
+        let entry = store.entries().next().unwrap();
+        assert_eq!(entry.tags(), ["billing", "prod"]);
+        assert!(entry.has_tag("prod"));
+
+        assert!(store.remove_tag("a", "prod").unwrap());
+        assert!(!store.remove_tag("a", "prod").unwrap());
+        assert!(!store.entries().next().unwrap().has_tag("prod"));
+    }
+
+    #[test]
+    fn fields_are_set_and_removed() {
+        let mut store = Store::new();
+        store.set("github", "").unwrap();
+
+        store.set_field("github", "user", "alice").unwrap();
+        store.set_field("github", "password", "hunter2").unwrap();
+        store.set_field("github", "user", "bob").unwrap();
+        for name in ["", "two words", "a=b"] {
+            assert!(matches!(
+                store.set_field("github", name, "x"),
+                Err(StoreError::InvalidField(_))
+            ));
+        }
+        assert!(store.set_field("missing", "user", "x").is_err());
+
+        let entry = store.entries().next().unwrap();
+        assert_eq!(
+            entry.fields().keys().collect::<Vec<_>>(),
+            ["password", "user"]
+        );
+        assert_eq!(entry.field("user"), Some("bob"));
+
+        assert!(store.remove_field("github", "user").unwrap());
+        assert!(!store.remove_field("github", "user").unwrap());
+        assert_eq!(store.entries().next().unwrap().field("user"), None);
+    }
+
+    #[test]
+    fn import_applies_all_entries_or_none() {
+        let mut store = Store::new();
+        store.set("a", "old").unwrap();
+
+        let summary = store
+            .import([("a", "new"), ("b", "1")], ImportPolicy::SkipExisting)
+            .unwrap();
+        assert_eq!((summary.created(), summary.skipped()), (1, 1));
+        assert_eq!(store.get("a"), Some("old"));
+
+        let err = store
+            .import(
+                [("a", "new"), ("c", "2"), ("keynest/x", "3")],
+                ImportPolicy::Overwrite,
+            )
+            .unwrap_err();
+        assert!(matches!(err, StoreError::ReservedKey(_)));
+        assert_eq!(store.get("a"), Some("old"));
+        assert_eq!(store.get("c"), None);
+
+        let summary = store
+            .import([("a", "new"), ("c", "2")], ImportPolicy::Overwrite)
+            .unwrap();
+        assert_eq!((summary.created(), summary.updated()), (1, 1));
+        assert_eq!(store.get("a"), Some("new"));
+    }
+
+    #[test]
+    fn notes_keep_their_kind() {
+        let mut store = Store::new();
+        store.set("A", "B").unwrap();
+        store.set_note("N", "# Recovery").unwrap();
+        store.update("N", "# Recovery\n\nCall Bob").unwrap();
+
+        let kinds: Vec<_> = store.entries().map(|e| e.kind()).collect();
+        assert_eq!(kinds, [EntryKind::Secret, EntryKind::Note]);
+
+        // Secrets serialize without a kind, so older versions read them unchanged.
+        let json = serde_json::to_value(store.entries().collect::<Vec<_>>()).unwrap();
+        assert!(json[0].get("kind").is_none());
+        assert_eq!(json[1]["kind"], "note");
+    }
+
     #[test]
     fn get_key_works() {
         let mut store = Store::new();
@@ -216,6 +1886,224 @@ mod tests {
         assert_eq!(store.get("A"), None);
     }
 
+    #[test]
+    fn resolve_follows_references() {
+        let mut store = Store::new();
+        store.set("prod/db/password", "hunter2").unwrap();
+        store
+            .set("staging/db/password", "ref:prod/db/password")
+            .unwrap();
+        store
+            .set("dev/db/password", "ref:staging/db/password")
+            .unwrap();
+
+        assert_eq!(resolve(&store, "dev/db/password").unwrap(), Some("hunter2"));
+        assert_eq!(
+            reference_chain(&store, "dev/db/password").unwrap(),
+            vec!["dev/db/password", "staging/db/password", "prod/db/password"]
+        );
+        assert_eq!(resolve(&store, "missing").unwrap(), None);
+    }
+
+    #[test]
+    fn resolve_reports_broken_reference() {
+        let mut store = Store::new();
+        store.set("A", "ref:B").unwrap();
+        match resolve(&store, "A") {
+            Err(StoreError::BrokenReference { from, to }) => {
+                assert_eq!(from, "A");
+                assert_eq!(to, "B");
+            }
+            other => panic!("expected BrokenReference, got: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reference_cycles_are_rejected() {
+        let mut store = Store::new();
+        assert!(matches!(
+            store.set("A", "ref:A"),
+            Err(StoreError::ReferenceCycle(_))
+        ));
+
+        store.set("A", "ref:B").unwrap();
+        store.set("B", "ref:C").unwrap();
+        match store.set("C", "ref:A") {
+            Err(StoreError::ReferenceCycle(chain)) => assert_eq!(chain, ["C", "A", "B", "C"]),
+            other => panic!("expected ReferenceCycle, got: {other:?}"),
+        }
+
+        store.set("C", "value").unwrap();
+        assert!(matches!(
+            store.update("C", "ref:B"),
+            Err(StoreError::ReferenceCycle(_))
+        ));
+        assert_eq!(resolve(&store, "A").unwrap(), Some("value"));
+    }
+
+    #[test]
+    fn dependents_lists_direct_references() {
+        let mut store = Store::new();
+        store.set("target", "v").unwrap();
+        store.set("a", "ref:target").unwrap();
+        store.set("b", "ref:a").unwrap();
+
+        let deps: Vec<&str> = store.dependents("target").collect();
+        assert_eq!(deps, ["a"]);
+    }
+
+    #[test]
+    fn rename_prefix_moves_entries_and_references() {
+        let mut store = Store::new();
+        store.set("old/db", "pw").unwrap();
+        store.set("old/api", "ref:old/db").unwrap();
+        store.set("old/db/user", "admin").unwrap();
+        store.set("other", "ref:old/api").unwrap();
+        store.add_tag("old/db", "prod").unwrap();
+
+        let summary = store.rename_prefix("old/", "new/", false).unwrap();
+        assert_eq!(
+            summary.renamed(),
+            [
+                ("old/api".to_string(), "new/api".to_string()),
+                ("old/db".to_string(), "new/db".to_string()),
+                ("old/db/user".to_string(), "new/db/user".to_string()),
+            ]
+        );
+        assert_eq!(summary.rewritten(), ["new/api", "other"]);
+        assert_eq!(
+            store.keys().collect::<Vec<_>>(),
+            ["new/api", "new/db", "new/db/user", "other"]
+        );
+        assert_eq!(resolve(&store, "other").unwrap(), Some("pw"));
+        assert!(
+            store
+                .entries()
+                .any(|e| e.key() == "new/db" && e.has_tag("prod"))
+        );
+    }
+
+    #[test]
+    fn rename_is_all_or_nothing() {
+        let mut store = Store::new();
+        store.set("a/x", "1").unwrap();
+        store.set("a/y", "2").unwrap();
+        store.set("b/y", "3").unwrap();
+
+        assert!(matches!(
+            store.rename_prefix("a/", "b/", false),
+            Err(StoreError::KeyAlreadyExists(k)) if k == "b/y"
+        ));
+        assert!(matches!(
+            store.rename_prefix("a/", "keynest/", false),
+            Err(StoreError::ReservedKey(_))
+        ));
+        assert!(matches!(
+            store.rename("c", "d", false),
+            Err(StoreError::KeyNotFound(_))
+        ));
+        assert_eq!(store.keys().collect::<Vec<_>>(), ["a/x", "a/y", "b/y"]);
+
+        // Keys may move onto keys that are renamed themselves.
+        store.rename_prefix("", "a/", false).unwrap();
+        assert_eq!(
+            store.keys().collect::<Vec<_>>(),
+            ["a/a/x", "a/a/y", "a/b/y"]
+        );
+    }
+
+    #[test]
+    fn copy_and_overwrite_keep_chunks_counted() {
+        let mut store = Store::new();
+        store.set("a", "1").unwrap();
+        store.set("b", "2").unwrap();
+        store.add_tag("a", "prod").unwrap();
+        store
+            .attach("a", "ca.pem", Attachment::new(1, vec!["c1".into()]))
+            .unwrap();
+
+        assert!(matches!(
+            store.copy("a", "b", false),
+            Err(StoreError::KeyAlreadyExists(_))
+        ));
+        store.copy("a", "c", false).unwrap();
+        assert_eq!(store.get("c"), Some("1"));
+        assert!(store.entries().any(|e| e.key() == "c" && e.has_tag("prod")));
+        assert_eq!(store.meta.chunks["c1"], 2);
+
+        assert!(matches!(
+            store.rename("c", "a", false),
+            Err(StoreError::KeyAlreadyExists(_))
+        ));
+        let summary = store.rename("c", "a", true).unwrap();
+        assert_eq!(summary.replaced(), ["a"]);
+        assert_eq!(store.meta.chunks["c1"], 1);
+        assert_eq!(store.keys().collect::<Vec<_>>(), ["a", "b"]);
+    }
+
+    #[test]
+    fn shared_chunks_are_reference_counted() {
+        let mut store = Store::new();
+        store.set("A", "a").unwrap();
+        store.set("B", "b").unwrap();
+
+        let shared = Attachment::new(10, vec!["c1".into(), "c2".into()]);
+        store.attach("A", "ca.pem", shared.clone()).unwrap();
+        store.attach("B", "ca.pem", shared).unwrap();
+        assert_eq!(store.meta.chunks["c1"], 2);
+
+        store.detach("A", "ca.pem").unwrap();
+        assert!(store.is_chunk_referenced("c1"));
+
+        store.remove("B").unwrap();
+        assert!(!store.is_chunk_referenced("c1"));
+        assert!(!store.is_chunk_referenced("c2"));
+    }
+
+    #[test]
+    fn attach_errors() {
+        let mut store = Store::new();
+        let attachment = Attachment::new(0, vec![]);
+        assert!(matches!(
+            store.attach("A", "f", attachment.clone()),
+            Err(StoreError::KeyNotFound(_))
+        ));
+
+        store.set("A", "a").unwrap();
+        store.attach("A", "f", attachment.clone()).unwrap();
+        assert!(matches!(
+            store.attach("A", "f", attachment),
+            Err(StoreError::AttachmentAlreadyExists { .. })
+        ));
+        assert!(matches!(
+            store.detach("A", "g"),
+            Err(StoreError::AttachmentNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn quotas_are_enforced_on_set_and_update() {
+        use crate::quota::QuotaEnforcement;
+
+        let mut store = Store::new();
+        store.set_quotas(Quotas::new(Some(1), Some(3), None, QuotaEnforcement::Error));
+
+        assert!(matches!(
+            store.set("A", "toolong"),
+            Err(StoreError::QuotaExceeded(_))
+        ));
+        store.set("A", "abc").unwrap();
+        assert!(matches!(
+            store.set("B", "b"),
+            Err(StoreError::QuotaExceeded(_))
+        ));
+        assert!(matches!(
+            store.update("A", "abcd"),
+            Err(StoreError::QuotaExceeded(_))
+        ));
+        assert_eq!(store.get("A"), Some("abc"));
+    }
+
     #[test]
     fn timestamps_are_rfc3339() {
         let mut store = Store::new();
@@ -226,4 +2114,33 @@ mod tests {
         let entry = store.entries().next().unwrap();
         assert!(chrono::DateTime::parse_from_rfc3339(entry.updated()).is_ok());
     }
+
+    #[test]
+    fn attributes_map_to_key_and_fields() {
+        let mut store = Store::new();
+        store.set("wifi/home", "pw").unwrap();
+        store.set("github", "token").unwrap();
+        store.set_field("github", "user", "alice").unwrap();
+        store
+            .set_field("github", "url", "https://alice@GitHub.com:443/login")
+            .unwrap();
+        store.set_field("github", "service", "gh-cli").unwrap();
+        let entry = |key: &str| store.entries().find(|e| e.key() == key).unwrap();
+
+        let wifi = entry("wifi/home");
+        assert_eq!(wifi.attribute("service"), Some("wifi"));
+        assert_eq!(wifi.attribute("account"), Some("home"));
+        assert_eq!(wifi.attribute("host"), None);
+
+        let github = entry("github");
+        assert_eq!(github.attribute("service"), Some("gh-cli"));
+        assert_eq!(github.attribute("username"), Some("alice"));
+        assert_eq!(github.attribute("server"), Some("GitHub.com"));
+        assert!(github.matches_attributes(&[("host", "github.com"), ("account", "alice")]));
+        assert!(!github.matches_attributes(&[("host", "github.com"), ("account", "bob")]));
+
+        assert_eq!(url_host("[::1]:8080/x"), Some("::1"));
+        assert_eq!(url_host("example.org"), Some("example.org"));
+        assert_eq!(url_host("https:///path"), None);
+    }
 }
@@ -0,0 +1,410 @@
+//! Strength estimates of passwords, and a report of the weak and reused secrets of a
+//! store.
+//!
+//! [`estimate_strength`] estimates how hard a password is to guess for an attacker who
+//! tries common passwords and patterns first, in the spirit of zxcvbn but much simpler:
+//! the password is split greedily into dictionary words (the passphrase word list and
+//! a list of common passwords), sequences (`abc`, `987`), keyboard runs (`qwerty`),
+//! repeated characters and single characters, and the bits of the parts are added up.
+//! A common password that is capitalized, spelled with leetspeak substitutions or
+//! followed by digits and symbols scores barely above the common password itself.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::LazyLock;
+
+use crate::generator::wordlist;
+use crate::store::{EntryKind, SecretEntry, reference_target};
+
+/// Passwords shorter than this are reported as [`Weakness::Short`].
+const MIN_LENGTH: usize = 12;
+/// Bits needed for the scores 1 to 4.
+const SCORE_BITS: [f64; 4] = [28.0, 40.0, 60.0, 80.0];
+/// Bits of a word of the passphrase word list (2048 words).
+const WORD_BITS: f64 = 11.0;
+/// Longest dictionary word looked for.
+const MAX_WORD_LEN: usize = 12;
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+static COMMON: LazyLock<Vec<&'static str>> =
+    LazyLock::new(|| include_str!("common.txt").lines().collect());
+
+/// A pattern that makes a password easier to guess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Weakness {
+    /// Shorter than 12 characters.
+    Short,
+    /// A common password, possibly capitalized, in leetspeak or with digits appended.
+    Common,
+    /// Contains dictionary words.
+    Word,
+    /// Contains a sequence like `abc` or `987`.
+    Sequence,
+    /// Contains a run of neighbouring keys like `qwerty`.
+    Keyboard,
+    /// Contains a character repeated three or more times.
+    Repeat,
+}
+
+impl Weakness {
+    /// Returns the name of the weakness, e.g. `keyboard`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Short => "short",
+            Self::Common => "common",
+            Self::Word => "word",
+            Self::Sequence => "sequence",
+            Self::Keyboard => "keyboard",
+            Self::Repeat => "repeat",
+        }
+    }
+}
+
+impl fmt::Display for Weakness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Short => "shorter than 12 characters",
+            Self::Common => "a common password",
+            Self::Word => "dictionary words",
+            Self::Sequence => "a sequence like abc or 987",
+            Self::Keyboard => "a keyboard run like qwerty",
+            Self::Repeat => "repeated characters",
+        })
+    }
+}
+
+/// Estimated strength of a password; see [`estimate_strength`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strength {
+    bits: f64,
+    weaknesses: Vec<Weakness>,
+}
+
+impl Strength {
+    /// Returns the estimated entropy in bits: the base-2 logarithm of the number of
+    /// guesses needed.
+    pub fn bits(&self) -> f64 {
+        self.bits
+    }
+
+    /// Returns a score from 0 (guessed at once) to 4 (out of reach), for 28, 40, 60 and
+    /// 80 bits or more.
+    pub fn score(&self) -> u8 {
+        SCORE_BITS.iter().filter(|&&bits| self.bits >= bits).count() as u8
+    }
+
+    /// Returns the patterns found, in the order of [`Weakness`].
+    pub fn weaknesses(&self) -> &[Weakness] {
+        &self.weaknesses
+    }
+}
+
+/// Estimates how hard `password` is to guess for an attacker who tries common passwords,
+/// dictionary words and patterns first.
+pub fn estimate_strength(password: &str) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let mut weaknesses = BTreeSet::new();
+    if chars.len() < MIN_LENGTH {
+        weaknesses.insert(Weakness::Short);
+    }
+    let bits = match common_bits(password) {
+        Some(bits) => {
+            weaknesses.insert(Weakness::Common);
+            bits
+        }
+        None => pattern_bits(&chars, &mut weaknesses),
+    };
+    Strength {
+        bits,
+        weaknesses: weaknesses.into_iter().collect(),
+    }
+}
+
+/// The value of a secret that needs attention, found by [`crate::Keynest::audit`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    key: String,
+    strength: Strength,
+    reused_by: Vec<String>,
+}
+
+impl Finding {
+    /// Returns the key of the secret.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the estimated strength of the value.
+    pub fn strength(&self) -> &Strength {
+        &self.strength
+    }
+
+    /// Returns the keys of the other secrets holding the same value, sorted.
+    pub fn reused_by(&self) -> &[String] {
+        &self.reused_by
+    }
+
+    /// Returns `true` if the value scores below `min_score` or is reused.
+    pub fn needs_rotation(&self, min_score: u8) -> bool {
+        self.strength.score() < min_score || !self.reused_by.is_empty()
+    }
+}
+
+/// Returns the entries among `entries` whose value is a password to audit: secrets,
+/// skipping notes, TOTP seeds, recipes, counters, references and empty values.
+pub(crate) fn passwords<'a>(
+    entries: impl IntoIterator<Item = &'a SecretEntry>,
+) -> impl Iterator<Item = &'a SecretEntry> {
+    entries.into_iter().filter(|e| {
+        e.kind() == EntryKind::Secret
+            && !e.value().is_empty()
+            && reference_target(e.value()).is_none()
+    })
+}
+
+/// Scores the value of every secret among `entries` (see [`passwords`]) and finds the
+/// values several of them share.
+pub(crate) fn audit<'a>(entries: impl IntoIterator<Item = &'a SecretEntry>) -> Vec<Finding> {
+    let secrets: Vec<&SecretEntry> = passwords(entries).collect();
+    let mut by_value: HashMap<&str, Vec<&str>> = HashMap::new();
+    for entry in &secrets {
+        by_value.entry(entry.value()).or_default().push(entry.key());
+    }
+    secrets
+        .iter()
+        .map(|entry| Finding {
+            key: entry.key().to_string(),
+            strength: estimate_strength(entry.value()),
+            reused_by: by_value[entry.value()]
+                .iter()
+                .filter(|key| **key != entry.key())
+                .map(|key| key.to_string())
+                .collect(),
+        })
+        .collect()
+}
+
+/// Returns the bits of `password` if it is a common password, possibly capitalized,
+/// with leetspeak substitutions, or followed by digits and symbols.
+fn common_bits(password: &str) -> Option<f64> {
+    let lower = password.to_lowercase();
+    let capitalized = if lower == password { 0.0 } else { 1.0 };
+    if let Some(rank) = common_rank(&lower) {
+        return Some(rank_bits(rank) + capitalized);
+    }
+
+    let stem = lower.trim_end_matches(|c: char| c.is_ascii_digit() || c.is_ascii_punctuation());
+    // Digits and symbols appended are guessed per class: a year is four digits, not four
+    // characters out of digits and symbols.
+    let suffix_bits: f64 = lower[stem.len()..]
+        .chars()
+        .map(|c| if c.is_ascii_digit() { 10f64 } else { 33f64 }.log2())
+        .sum();
+    for (candidate, leet) in [(stem.to_string(), 0.0), (unleet(stem), 1.0)] {
+        if candidate.len() < 4 {
+            continue;
+        }
+        if let Some(rank) = common_rank(&candidate) {
+            return Some(rank_bits(rank) + capitalized + leet + suffix_bits);
+        }
+    }
+    None
+}
+
+/// Adds up the bits of the patterns and single characters `chars` splits into, recording
+/// the patterns found in `weaknesses`.
+fn pattern_bits(chars: &[char], weaknesses: &mut BTreeSet<Weakness>) -> f64 {
+    let lower: Vec<char> = chars.iter().map(char::to_ascii_lowercase).collect();
+    let per_char = (alphabet_size(chars) as f64).log2();
+    let mut bits = 0.0;
+    let mut i = 0;
+    while i < lower.len() {
+        // The longest part wins; on a tie, the later candidate (`1234` is a common password, not a sequence).
+        let candidates = [
+            repeat_len(&lower[i..])
+                .map(|len| (len, per_char + (len as f64).log2(), Weakness::Repeat)),
+            keyboard_len(&lower[i..])
+                .map(|len| (len, 6.0 + (len as f64).log2(), Weakness::Keyboard)),
+            sequence_len(&lower[i..])
+                .map(|len| (len, 5.0 + (len as f64).log2(), Weakness::Sequence)),
+            word(&lower[i..]).map(|(len, word_bits)| {
+                let capitalized = chars[i..i + len].iter().any(|c| c.is_uppercase());
+                (
+                    len,
+                    word_bits + f64::from(u8::from(capitalized)),
+                    Weakness::Word,
+                )
+            }),
+        ];
+        match candidates
+            .into_iter()
+            .flatten()
+            .max_by_key(|(len, ..)| *len)
+        {
+            Some((len, part_bits, weakness)) => {
+                weaknesses.insert(weakness);
+                bits += part_bits;
+                i += len;
+            }
+            None => {
+                bits += per_char;
+                i += 1;
+            }
+        }
+    }
+    bits
+}
+
+/// Returns the size of the alphabet the characters of `chars` are drawn from: the
+/// character classes they use.
+fn alphabet_size(chars: &[char]) -> usize {
+    let uses = |class: fn(&char) -> bool| chars.iter().any(class);
+    let mut size = 0;
+    if uses(char::is_ascii_lowercase) {
+        size += 26;
+    }
+    if uses(char::is_ascii_uppercase) {
+        size += 26;
+    }
+    if uses(char::is_ascii_digit) {
+        size += 10;
+    }
+    if uses(|c| c.is_ascii_punctuation() || *c == ' ') {
+        size += 33;
+    }
+    if uses(|c| !c.is_ascii()) {
+        size += 100;
+    }
+    size.max(1)
+}
+
+/// Returns the length of the run of the first character of `chars`, if it repeats at
+/// least three times.
+fn repeat_len(chars: &[char]) -> Option<usize> {
+    let len = chars.iter().take_while(|c| **c == chars[0]).count();
+    (len >= 3).then_some(len)
+}
+
+/// Returns the length of the ascending or descending sequence of letters or digits
+/// `chars` starts with, if it is at least three long.
+fn sequence_len(chars: &[char]) -> Option<usize> {
+    if !chars.first()?.is_ascii_alphanumeric() || chars.len() < 3 {
+        return None;
+    }
+    let step = chars[1] as i32 - chars[0] as i32;
+    if step.abs() != 1 {
+        return None;
+    }
+    let len = 1 + chars
+        .windows(2)
+        .take_while(|pair| {
+            pair[1].is_ascii_alphanumeric() && pair[1] as i32 - pair[0] as i32 == step
+        })
+        .count();
+    (len >= 3).then_some(len)
+}
+
+/// Returns the length of the run of neighbouring keys of a keyboard row, forwards or
+/// backwards, `chars` starts with, if it is at least four long.
+fn keyboard_len(chars: &[char]) -> Option<usize> {
+    KEYBOARD_ROWS
+        .iter()
+        .flat_map(|row| {
+            let forward: Vec<char> = row.chars().collect();
+            let backward: Vec<char> = row.chars().rev().collect();
+            [forward, backward]
+        })
+        .filter_map(|row| {
+            let start = row.iter().position(|c| *c == chars[0])?;
+            let len = row[start..]
+                .iter()
+                .zip(chars)
+                .take_while(|(key, c)| key == c)
+                .count();
+            (len >= 4).then_some(len)
+        })
+        .max()
+}
+
+/// Returns the length and bits of the longest dictionary word or common password of at
+/// least four characters `chars` starts with.
+fn word(chars: &[char]) -> Option<(usize, f64)> {
+    (4..=chars.len().min(MAX_WORD_LEN)).rev().find_map(|len| {
+        let candidate: String = chars[..len].iter().collect();
+        if let Some(rank) = common_rank(&candidate) {
+            return Some((len, rank_bits(rank)));
+        }
+        wordlist()
+            .binary_search(&candidate.as_str())
+            .is_ok()
+            .then_some((len, WORD_BITS))
+    })
+}
+
+fn common_rank(candidate: &str) -> Option<usize> {
+    COMMON.iter().position(|common| *common == candidate)
+}
+
+fn rank_bits(rank: usize) -> f64 {
+    ((rank + 2) as f64).log2()
+}
+
+/// Undoes common leetspeak substitutions, e.g. `p@ssw0rd` to `password`.
+fn unleet(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '@' | '4' => 'a',
+            '3' => 'e',
+            '1' | '!' => 'i',
+            '0' => 'o',
+            '$' | '5' => 's',
+            '7' => 't',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_passwords_and_patterns_score_low() {
+        for weak in [
+            "pw",
+            "password",
+            "P@ssw0rd123!",
+            "hunter2",
+            "qwertyuiop",
+            "aaaaaaaaaaaa",
+        ] {
+            let strength = estimate_strength(weak);
+            assert!(strength.score() <= 1, "{weak}: {strength:?}");
+        }
+        assert!(
+            estimate_strength("P@ssw0rd123!")
+                .weaknesses()
+                .contains(&Weakness::Common)
+        );
+        assert_eq!(
+            estimate_strength("abcdefg9876").weaknesses(),
+            [Weakness::Short, Weakness::Sequence]
+        );
+        assert!(
+            estimate_strength("xkcd qwertyui")
+                .weaknesses()
+                .contains(&Weakness::Keyboard)
+        );
+    }
+
+    #[test]
+    fn random_values_and_passphrases_score_high() {
+        assert_eq!(estimate_strength("T7mq2VxR9pLw4KzH8bNc").score(), 4);
+        assert!(estimate_strength("bubble upper file busy").score() >= 3);
+        assert!(
+            estimate_strength("bubble upper file busy")
+                .weaknesses()
+                .contains(&Weakness::Word)
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Entry templates: the shape of a kind of credential, defined once.
+//!
+//! A template lists the fields an entry gets and how each one is filled — a fixed
+//! default, a value asked for when the entry is created, or a generated password or
+//! passphrase — plus the tags and expiry of new entries. Templates are defined in the
+//! config file (see [`crate::config`]) or stored in the keystore, so everyone sharing it
+//! creates entries the same way (see [`crate::Keynest::set_template`]):
+//!
+//! ```toml
+//! [templates.postgres]
+//! description = "PostgreSQL login"
+//! value = { length = 32, symbols = true }
+//! tags = ["db"]
+//! expires = "90d"
+//! fields.host = { ask = true }
+//! fields.port = { default = "5432" }
+//! fields.user = { ask = true, default = "postgres" }
+//! ```
+//!
+//! Without a `value` rule, the value is a generated 24-character password.
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
+
+use crate::generator::{PassphraseOptions, PasswordOptions};
+
+/// Length of the password generated for templates without a `value` rule.
+const DEFAULT_LENGTH: usize = 24;
+
+/// The shape of a kind of entry; see the [module documentation](self).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Template {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<Rule>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<String, Rule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<String>,
+}
+
+/// How one value of a new entry is filled.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Rule {
+    /// Fixed value, or the value offered when asking.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    /// Ask for the value when the entry is created.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    ask: bool,
+    /// Do not echo the value when asking for it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    secret: bool,
+    /// Generate a password of this many characters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    length: Option<usize>,
+    /// Generate a passphrase of this many words.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    words: Option<usize>,
+    /// Include symbols in a generated password.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    symbols: bool,
+    /// Leave easily confused characters out of a generated password.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    no_ambiguous: bool,
+}
+
+impl Template {
+    /// Parses a template from TOML, e.g. the contents of a `[templates.NAME]` table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for invalid TOML, unknown keys, or an invalid template (see
+    /// [`Template::validate`]).
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let template: Self = toml::from_str(text)?;
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Returns the template as TOML, in the form [`Template::from_toml`] reads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization fails.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Checks the field names, tags and rules.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first problem found.
+    pub fn validate(&self) -> Result<()> {
+        self.value().validate("the value")?;
+        for (name, rule) in &self.fields {
+            if name.is_empty()
+                || name
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || c == '=')
+            {
+                bail!("invalid field name '{name}'");
+            }
+            rule.validate(&format!("field '{name}'"))?;
+        }
+        for tag in &self.tags {
+            if tag.is_empty()
+                || tag
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || c == ',')
+            {
+                bail!("invalid tag '{tag}'");
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the one-line description, if any.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Returns how the value of new entries is filled.
+    pub fn value(&self) -> Rule {
+        self.value
+            .clone()
+            .unwrap_or_else(|| Rule::generated(DEFAULT_LENGTH))
+    }
+
+    /// Returns the fields of new entries and how each is filled, by name.
+    pub fn fields(&self) -> &BTreeMap<String, Rule> {
+        &self.fields
+    }
+
+    /// Returns the tags of new entries.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Returns when new entries expire, as accepted by `keynest set --expires` (e.g.
+    /// `90d`), if they do.
+    pub fn expires(&self) -> Option<&str> {
+        self.expires.as_deref()
+    }
+}
+
+impl Rule {
+    /// Creates a rule that generates a password of `length` letters and digits.
+    pub fn generated(length: usize) -> Self {
+        Self {
+            length: Some(length),
+            ..Self::default()
+        }
+    }
+
+    /// Creates a rule that fills in `value`.
+    pub fn fixed(value: &str) -> Self {
+        Self {
+            default: Some(value.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the fixed value, or the value offered when asking.
+    pub fn default_value(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    /// Returns `true` if the value is asked for when an entry is created.
+    pub fn asks(&self) -> bool {
+        self.ask
+    }
+
+    /// Returns `true` if the value must not be echoed when asking for it.
+    pub fn is_secret(&self) -> bool {
+        self.secret
+    }
+
+    /// Returns `true` if the value is generated.
+    pub fn generates(&self) -> bool {
+        self.length.is_some() || self.words.is_some()
+    }
+
+    /// Generates a value if the rule is a generation rule.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the generator fails.
+    pub fn generate(&self) -> Result<Option<Zeroizing<String>>> {
+        if let Some(words) = self.words {
+            return PassphraseOptions::new(words).generate().map(Some);
+        }
+        let Some(length) = self.length else {
+            return Ok(None);
+        };
+        PasswordOptions::new(length)
+            .with_symbols(self.symbols)
+            .with_exclude_ambiguous(self.no_ambiguous)
+            .generate()
+            .map(Some)
+    }
+
+    /// Checks that the rule fills in a value in one way; `what` names it in errors.
+    pub(crate) fn validate(&self, what: &str) -> Result<()> {
+        if self.length.is_some() && self.words.is_some() {
+            bail!("{what}: use either length or words");
+        }
+        if self.generates() && (self.ask || self.default.is_some()) {
+            bail!("{what}: a generated value cannot also be asked for or have a default");
+        }
+        if (self.symbols || self.no_ambiguous) && self.length.is_none() {
+            bail!("{what}: symbols and no-ambiguous only apply to a generated password (length)");
+        }
+        if !self.generates() && !self.ask && self.default.is_none() {
+            bail!("{what}: set a default, ask = true, or length/words to generate it");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_parse_and_validate() {
+        let template = Template::from_toml(
+            r#"
+            description = "PostgreSQL login"
+            value = { length = 32, symbols = true }
+            tags = ["db"]
+            expires = "90d"
+            fields.host = { ask = true }
+            fields.port = { default = "5432" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(template.description(), Some("PostgreSQL login"));
+        assert!(template.value().generates());
+        assert_eq!(template.value().generate().unwrap().unwrap().len(), 32);
+        assert!(template.fields()["host"].asks());
+        assert_eq!(template.fields()["port"].default_value(), Some("5432"));
+        assert_eq!(template.tags(), ["db"]);
+        assert_eq!(template.expires(), Some("90d"));
+        assert_eq!(
+            Template::from_toml(&template.to_toml().unwrap()).unwrap(),
+            template
+        );
+
+        // Without a value rule, a password is generated.
+        assert_eq!(Template::default().value(), Rule::generated(DEFAULT_LENGTH));
+
+        for invalid in [
+            "fields.host = {}",
+            "fields.host = { length = 8, default = \"x\" }",
+            "value = { words = 4, symbols = true }",
+            "fields.\"a b\" = { default = \"x\" }",
+            "tags = [\"a,b\"]",
+            "colour = \"red\"",
+        ] {
+            assert!(Template::from_toml(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Helpers for fast, deterministic tests of applications that embed keynest.
+//!
+//! Enabled by the `test-utils` feature, typically as a dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! keynest = { version = "0.5", features = ["test-utils"] }
+//! ```
+//!
+//! Real keystores derive their key with 64 MiB of Argon2 and draw salts and nonces from
+//! the OS; [`fast_kdf`] and [`seed_rng`] replace both for tests ([`SeededEntropy`] does
+//! the same for a single keystore, see [`Keynest::set_entropy_source`]). Never enable
+//! the feature in production builds: a seeded generator makes every salt and nonce
+//! predictable.
+//!
+//! There is no in-memory keystore: [`Storage`] always reads and writes files, along with
+//! side files such as the journal and backups. [`TempDirStore`] is a fixture that puts
+//! the keystore in a temp directory on disk and deletes it afterwards.
+//!
+//! ```ignore
+//! use keynest::test_utils::{TempDirStore, seed_rng};
+//!
+//! let _rng = seed_rng(42);
+//! let store = TempDirStore::new()?;
+//! let mut kn = store.init("pw")?;
+//! kn.set("api_key", "secret")?;
+//! kn.save()?;
+//! assert_eq!(store.open("pw")?.get("api_key")?, Some("secret"));
+//! ```
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use zeroize::Zeroizing;
+
+use crate::crypto::random;
+use crate::{EntropySource, EntropyUse, KdfParams, Keynest, Storage};
+
+/// Returns the cheapest valid KDF parameters: 8 KiB, one iteration, one lane.
+///
+/// Unlocking with them takes microseconds instead of the default's fraction of a second.
+pub fn fast_kdf() -> KdfParams {
+    KdfParams::new(8, 1, 1).expect("minimal KDF parameters are valid")
+}
+
+/// Makes salts, nonces and keys generated on the current thread come from a
+/// deterministic stream derived from `seed`, until the returned guard is dropped.
+///
+/// Two runs with the same seed and the same operations produce the same bytes. The
+/// stream is per thread, so tests running in parallel do not affect each other.
+///
+/// A keystore with its own source ([`Keynest::set_entropy_source`]) keeps using that.
+#[must_use = "the seeded generator is only active while the guard is alive"]
+pub fn seed_rng(seed: u64) -> SeededRng {
+    let source: Arc<dyn EntropySource> = Arc::new(SeededEntropy::new(seed));
+    SeededRng {
+        _scope: random::scope(Some(&source)),
+    }
+}
+
+/// Guard returned by [`seed_rng`]; restores the previous generator on drop.
+pub struct SeededRng {
+    _scope: random::Scope,
+}
+
+/// A deterministic [`EntropySource`]: SHA-256 in counter mode over a seed.
+///
+/// Reproducible, not secure.
+#[derive(Debug)]
+pub struct SeededEntropy {
+    seed: u64,
+    state: Mutex<Stream>,
+}
+
+#[derive(Debug)]
+struct Stream {
+    counter: u64,
+    block: [u8; 32],
+    used: usize,
+}
+
+impl SeededEntropy {
+    /// Creates the stream for `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: Mutex::new(Stream {
+                counter: 0,
+                block: [0; 32],
+                used: 32,
+            }),
+        }
+    }
+}
+
+impl EntropySource for SeededEntropy {
+    fn fill(&self, _purpose: EntropyUse, buf: &mut [u8]) -> Result<()> {
+        let mut stream = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        for byte in buf {
+            if stream.used == stream.block.len() {
+                let mut hasher = Sha256::new();
+                hasher.update(self.seed.to_le_bytes());
+                hasher.update(stream.counter.to_le_bytes());
+                stream.block = hasher.finalize().into();
+                stream.counter += 1;
+                stream.used = 0;
+            }
+            *byte = stream.block[stream.used];
+            stream.used += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Test fixture: a keystore location in a new directory under the system temp directory,
+/// deleted with everything in it on drop.
+///
+/// This is not an in-memory store. The keystore is a real file on disk, read and written
+/// through [`Storage`] like any other, so tests pay for file I/O but exercise the same
+/// code paths as production.
+pub struct TempDirStore {
+    dir: TempDir,
+    storage: Storage,
+}
+
+impl TempDirStore {
+    /// Creates an empty location; no keystore exists there until [`TempDirStore::init`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn new() -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("keynest-test-")
+            .tempdir()
+            .context("failed to create a test store directory")?;
+        let storage = Storage::new(dir.path().join("keynest.db"));
+        Ok(Self { dir, storage })
+    }
+
+    /// Creates a keystore protected by `password` with [`fast_kdf`] parameters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore already exists or cannot be written.
+    pub fn init(&self, password: &str) -> Result<Keynest> {
+        Keynest::init_with_storage_and_kdf(
+            Zeroizing::new(password.to_string()),
+            self.storage(),
+            fast_kdf(),
+        )
+    }
+
+    /// Opens the keystore with `password`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore does not exist or the password is wrong.
+    pub fn open(&self, password: &str) -> Result<Keynest> {
+        Keynest::open_with_storage(Zeroizing::new(password.to_string()), self.storage())
+    }
+
+    /// Returns the storage of the keystore, e.g. for [`crate::IndexedKeynest`].
+    pub fn storage(&self) -> Storage {
+        self.storage.clone()
+    }
+
+    /// Returns the path of the keystore file.
+    pub fn path(&self) -> &Path {
+        self.storage.path()
+    }
+
+    /// Returns the directory holding the keystore and its side files.
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
@@ -0,0 +1,303 @@
+//! Time-based one-time passwords (RFC 6238).
+//!
+//! A TOTP code is the HOTP value (RFC 4226) of the number of periods elapsed since the
+//! Unix epoch: an HMAC of that counter under the shared seed, truncated to a few decimal
+//! digits. The seed is the base32 `secret` of an `otpauth://totp/` URI (see
+//! [`crate::OtpAuth::totp`]); authenticators default to HMAC-SHA1, 6 digits and a
+//! 30-second period.
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Number of digits of a code unless the URI says otherwise.
+pub const DEFAULT_DIGITS: u32 = 6;
+/// Seconds a code is valid for unless the URI says otherwise.
+pub const DEFAULT_PERIOD: u64 = 30;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The HMAC hash function of a TOTP seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TotpAlgorithm {
+    /// HMAC-SHA1, what nearly every service uses.
+    #[default]
+    Sha1,
+    /// HMAC-SHA256.
+    Sha256,
+    /// HMAC-SHA512.
+    Sha512,
+}
+
+impl TotpAlgorithm {
+    /// Parses the `algorithm` parameter of an `otpauth://` URI (case-insensitive).
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Sha1, Self::Sha256, Self::Sha512]
+            .into_iter()
+            .find(|a| a.to_string().eq_ignore_ascii_case(name))
+    }
+
+    fn mac(self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => mac::<Hmac<Sha1>>(key, message),
+            Self::Sha256 => mac::<Hmac<Sha256>>(key, message),
+            Self::Sha512 => mac::<Hmac<Sha512>>(key, message),
+        }
+    }
+}
+
+impl fmt::Display for TotpAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Sha512 => "SHA512",
+        })
+    }
+}
+
+fn mac<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// A TOTP seed with its parameters.
+#[derive(Clone)]
+pub struct Totp {
+    secret: Zeroizing<Vec<u8>>,
+    algorithm: TotpAlgorithm,
+    digits: u32,
+    period: u64,
+}
+
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Totp {
+    /// Creates a TOTP from a base32 seed with the default parameters.
+    ///
+    /// The seed is case-insensitive; spaces (as in `JBSW Y3DP EHPK 3PXP`) and trailing
+    /// `=` padding are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the seed is empty or not valid base32.
+    pub fn new(seed: &str) -> Result<Self> {
+        let Some(secret) = base32_decode(seed) else {
+            bail!("TOTP seed is not valid base32");
+        };
+        if secret.is_empty() {
+            bail!("TOTP seed is empty");
+        }
+        Ok(Self {
+            secret,
+            algorithm: TotpAlgorithm::default(),
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+        })
+    }
+
+    /// Sets the HMAC hash function.
+    pub fn with_algorithm(mut self, algorithm: TotpAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the number of digits of a code.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error unless `digits` is between 6 and 8.
+    pub fn with_digits(mut self, digits: u32) -> Result<Self> {
+        if !(6..=8).contains(&digits) {
+            bail!("TOTP digits must be between 6 and 8");
+        }
+        self.digits = digits;
+        Ok(self)
+    }
+
+    /// Sets how many seconds a code is valid for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `period` is zero.
+    pub fn with_period(mut self, period: u64) -> Result<Self> {
+        if period == 0 {
+            bail!("TOTP period must be positive");
+        }
+        self.period = period;
+        Ok(self)
+    }
+
+    /// Returns the HMAC hash function.
+    pub fn algorithm(&self) -> TotpAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the number of digits of a code.
+    pub fn digits(&self) -> u32 {
+        self.digits
+    }
+
+    /// Returns how many seconds a code is valid for.
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Returns the seed in base32, without padding.
+    pub fn seed(&self) -> Zeroizing<String> {
+        base32_encode(&self.secret)
+    }
+
+    /// Returns the code valid at `time`.
+    pub fn code_at(&self, time: DateTime<Utc>) -> TotpCode {
+        let seconds = u64::try_from(time.timestamp()).unwrap_or(0);
+        let counter = seconds / self.period;
+
+        let hash = Zeroizing::new(self.algorithm.mac(&self.secret, &counter.to_be_bytes()));
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        let code = binary % 10u32.pow(self.digits);
+
+        TotpCode {
+            code: Zeroizing::new(format!("{code:0width$}", width = self.digits as usize)),
+            remaining: self.period - seconds % self.period,
+        }
+    }
+}
+
+/// A TOTP code and how long it stays valid.
+#[derive(Debug, Clone)]
+pub struct TotpCode {
+    code: Zeroizing<String>,
+    remaining: u64,
+}
+
+impl TotpCode {
+    /// Returns the code, zero-padded to the number of digits.
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Returns the seconds until the next code.
+    pub fn remaining_secs(&self) -> u64 {
+        self.remaining
+    }
+}
+
+/// Decodes RFC 4648 base32, case-insensitively and ignoring spaces and padding.
+fn base32_decode(input: &str) -> Option<Zeroizing<Vec<u8>>> {
+    let mut out = Zeroizing::new(Vec::with_capacity(input.len() * 5 / 8));
+    let (mut buffer, mut bits) = (0u64, 0u32);
+    for c in input.trim_end_matches('=').bytes() {
+        if c == b' ' {
+            continue;
+        }
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// Encodes RFC 4648 base32 without padding, as authenticators expect in URIs.
+fn base32_encode(data: &[u8]) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(String::with_capacity(data.len().div_ceil(5) * 8));
+    let (mut buffer, mut bits) = (0u64, 0u32);
+    for &byte in data {
+        buffer = (buffer << 8) | u64::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        // The RFC seeds are ASCII digits, repeated to the hash length.
+        let sha1 = Totp::new(&base32_encode(b"12345678901234567890")).unwrap();
+        let sha256 = Totp::new(&base32_encode(b"12345678901234567890123456789012"))
+            .unwrap()
+            .with_algorithm(TotpAlgorithm::Sha256);
+        let sha512 = Totp::new(&base32_encode(
+            b"1234567890123456789012345678901234567890123456789012345678901234",
+        ))
+        .unwrap()
+        .with_algorithm(TotpAlgorithm::Sha512);
+
+        for (time, expected) in [
+            (59, ["94287082", "46119246", "90693936"]),
+            (1111111109, ["07081804", "68084774", "25091201"]),
+            (2000000000, ["69279037", "90698825", "38618901"]),
+        ] {
+            for (totp, expected) in [&sha1, &sha256, &sha512].into_iter().zip(expected) {
+                let totp = totp.clone().with_digits(8).unwrap();
+                assert_eq!(
+                    totp.code_at(at(time)).code(),
+                    expected,
+                    "{totp:?} at {time}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn codes_have_default_parameters_and_remaining_time() {
+        let totp = Totp::new("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(totp.seed().as_str(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+
+        let code = totp.code_at(at(59));
+        assert_eq!(code.code(), "287082");
+        assert_eq!(code.remaining_secs(), 1);
+        assert_eq!(totp.code_at(at(60)).remaining_secs(), 30);
+    }
+
+    #[test]
+    fn rejects_invalid_seeds_and_parameters() {
+        assert!(Totp::new("not base32!").is_err());
+        assert!(Totp::new("").is_err());
+        let totp = Totp::new("JBSWY3DPEHPK3PXP").unwrap();
+        assert!(totp.clone().with_digits(5).is_err());
+        assert!(totp.with_period(0).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Local usage counters, kept inside the encrypted payload.
+//!
+//! Tracking is optional and off by default. While it is on, the store counts how often
+//! it was unlocked and saved and how often each entry was read, so unused credentials
+//! can be found before they are deleted. Nothing leaves the store.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Usage counters of a store; see [`crate::Keynest::set_usage_tracking`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    since: String,
+    #[serde(default)]
+    opens: u64,
+    #[serde(default)]
+    saves: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    entries: BTreeMap<String, EntryUsage>,
+}
+
+/// Usage counters of one entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EntryUsage {
+    gets: u64,
+    last_used: String,
+}
+
+impl Usage {
+    /// Starts counting at `since`.
+    pub(crate) fn new(since: String) -> Self {
+        Self {
+            since,
+            opens: 0,
+            saves: 0,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Returns when tracking was enabled (RFC 3339, UTC).
+    pub fn since(&self) -> &str {
+        &self.since
+    }
+
+    /// Returns how often the store was unlocked by commands that record usage.
+    pub fn opens(&self) -> u64 {
+        self.opens
+    }
+
+    /// Returns how often changes to the store were saved.
+    pub fn saves(&self) -> u64 {
+        self.saves
+    }
+
+    /// Returns the counters of `key`, or `None` if it was never read.
+    pub fn entry(&self, key: &str) -> Option<&EntryUsage> {
+        self.entries.get(key)
+    }
+
+    /// Returns the counters of all entries that were read, by key.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &EntryUsage)> {
+        self.entries.iter()
+    }
+
+    pub(crate) fn record_open(&mut self) {
+        self.opens = self.opens.saturating_add(1);
+    }
+
+    pub(crate) fn record_save(&mut self) {
+        self.saves = self.saves.saturating_add(1);
+    }
+
+    pub(crate) fn record_get(&mut self, key: &str, now: String) {
+        self.entries
+            .entry(key.to_string())
+            .and_modify(|e| {
+                e.gets = e.gets.saturating_add(1);
+                e.last_used.clone_from(&now);
+            })
+            .or_insert(EntryUsage {
+                gets: 1,
+                last_used: now,
+            });
+    }
+
+    /// Moves the counters of renamed entries, given as `(old, new)` key pairs, and drops
+    /// those of the `replaced` entries.
+    pub(crate) fn rename(&mut self, renamed: &[(String, String)], replaced: &[String]) {
+        for key in replaced {
+            self.entries.remove(key);
+        }
+        let moved: Vec<_> = renamed
+            .iter()
+            .filter_map(|(old, new)| Some((new.clone(), self.entries.remove(old)?)))
+            .collect();
+        self.entries.extend(moved);
+    }
+
+    /// Drops the counters of entries for which `exists` returns `false`.
+    pub(crate) fn retain(&mut self, exists: impl Fn(&str) -> bool) {
+        self.entries.retain(|key, _| exists(key));
+    }
+}
+
+impl EntryUsage {
+    /// Returns how often the entry was read.
+    pub fn gets(&self) -> u64 {
+        self.gets
+    }
+
+    /// Returns when the entry was last read (RFC 3339, UTC).
+    pub fn last_used(&self) -> &str {
+        &self.last_used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_gets_per_entry() {
+        let mut usage = Usage::new("2026-01-01T00:00:00Z".to_string());
+        usage.record_get("a", "2026-01-02T00:00:00Z".to_string());
+        usage.record_get("a", "2026-01-03T00:00:00Z".to_string());
+        usage.record_get("b", "2026-01-02T00:00:00Z".to_string());
+        usage.record_open();
+        assert_eq!(usage.entry("a").unwrap().gets(), 2);
+        assert_eq!(
+            usage.entry("a").unwrap().last_used(),
+            "2026-01-03T00:00:00Z"
+        );
+        assert_eq!(usage.opens(), 1);
+
+        usage.retain(|key| key == "a");
+        assert!(usage.entry("b").is_none());
+        assert_eq!(usage.entries().count(), 1);
+    }
+}
@@ -0,0 +1,65 @@
+use keynest::test_utils::{TempDirStore, fast_kdf, seed_rng};
+
+#[test]
+fn temp_dir_store_roundtrips_with_fast_kdf() {
+    let store = TempDirStore::new().unwrap();
+    let mut kn = store.init("pw").unwrap();
+    kn.set("api_key", "secret").unwrap();
+    kn.save().unwrap();
+
+    let kn = store.open("pw").unwrap();
+    assert_eq!(kn.get("api_key").unwrap(), Some("secret"));
+    assert_eq!(*kn.info().unwrap().kdf(), fast_kdf());
+    assert!(store.open("wrong").is_err());
+
+    let dir = store.dir().to_path_buf();
+    drop(store);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn seeded_rng_makes_salts_and_nonces_reproducible() {
+    let header = |seed: Option<u64>| {
+        let _rng = seed.map(seed_rng);
+        let store = TempDirStore::new().unwrap();
+        store.init("pw").unwrap();
+        let file = keynest::format::parse(&std::fs::read(store.path()).unwrap()).unwrap();
+        (file.salt().to_vec(), file.nonce().to_vec())
+    };
+
+    assert_eq!(header(Some(7)), header(Some(7)));
+    assert_ne!(header(Some(7)), header(Some(8)));
+    assert_ne!(header(None), header(None));
+}
+
+#[test]
+fn seeded_entropy_and_fixed_clock_make_keystores_identical() {
+    use chrono::{DateTime, TimeDelta};
+    use keynest::test_utils::SeededEntropy;
+    use keynest::{FixedClock, InitOptions, Keynest};
+    use std::sync::Arc;
+    use zeroize::Zeroizing;
+
+    let build = || {
+        let store = TempDirStore::new().unwrap();
+        let clock = Arc::new(FixedClock::new(
+            DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z")
+                .unwrap()
+                .to_utc(),
+        ));
+        let mut kn = Keynest::init_with_options(
+            Zeroizing::new("pw".to_string()),
+            store.storage(),
+            InitOptions::new(fast_kdf())
+                .with_clock(clock.clone())
+                .with_entropy_source(Arc::new(SeededEntropy::new(1))),
+        )
+        .unwrap();
+        clock.advance(TimeDelta::days(1));
+        kn.set("api_key", "secret").unwrap();
+        kn.save().unwrap();
+        std::fs::read(store.path()).unwrap()
+    };
+
+    assert_eq!(build(), build());
+}